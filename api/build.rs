@@ -41,10 +41,15 @@ async fn main() -> Result<()> {
     compile("api", false).await?;
     compile("crud", false).await?;
     compile("datastore", false).await?;
+    compile("encoding", false).await?;
     compile("endpoint", false).await?;
     compile("event", false).await?;
+    compile("files", false).await?;
+    compile("login", false).await?;
     compile("request", false).await?;
+    compile("testing", false).await?;
     compile("utils", false).await?;
+    compile("websocket", false).await?;
     compile("worker", true).await?;
 
     Ok(())
@@ -12,18 +12,64 @@ export type {
     CRUDCreateResponses,
     CRUDMethods,
     CRUDMethodSignature,
+    CRUDPagination,
 } from "./crud.ts";
 export {
+    archive,
     AuthUser,
+    autoCrud,
+    cache,
     ChiselCursor,
     ChiselEntity,
     chiselIterator,
+    column,
+    connector,
+    counted,
+    enqueue,
+    FieldRef,
+    FilterPredicate,
+    index,
     labels,
     loggedInUser,
+    outbox,
+    partition,
     requestContext,
+    searchable,
+    table,
     unique,
 } from "./datastore.ts";
+export type {
+    AggregateOptions,
+    AggregateResult,
+    AutoCrudOptions,
+    EntityHooks,
+    FilterBuilder,
+    FilterFields,
+    Page,
+    PageOptions,
+    PartitionOptions,
+} from "./datastore.ts";
 export type { ChiselEvent } from "./event.ts";
+export { deleteFile, readFile, storeFile } from "./files.ts";
+export type { ChiselFile, UploadedFile } from "./files.ts";
+export { loginRoutes } from "./login.ts";
+export {
+    encodeCbor,
+    encodeMessagePack,
+    negotiateEncoding,
+} from "./encoding.ts";
+export type { ResponseEncoding } from "./encoding.ts";
 export { ChiselRequest, Query } from "./request.ts";
-export { getSecret, responseFromJson } from "./utils.ts";
-export type { JSONValue } from "./utils.ts";
+export type { Authenticator, Principal } from "./request.ts";
+export { mockFetch, test, testClock, testRequest } from "./testing.ts";
+export type { TestRequestInit } from "./testing.ts";
+export {
+    getSecret,
+    responseFromEvents,
+    responseFromJson,
+    responseFromStream,
+    responseFromValue,
+} from "./utils.ts";
+export type { JSONValue, ServerSentEvent } from "./utils.ts";
+export { ChiselSocket } from "./websocket.ts";
+export type { WebSocketHandler } from "./websocket.ts";
@@ -1,7 +1,15 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
-import { mergeDeep, opAsync, responseFromJson } from "./utils.ts";
-import { ChiselCursor, ChiselEntity, requestContext } from "./datastore.ts";
+import { mergeDeep, opAsync, responseFromValue } from "./utils.ts";
+import {
+    ChiselCursor,
+    ChiselEntity,
+    deleteHooks,
+    ensureNotInMemory,
+    hasHooks,
+    requestContext,
+    runHook,
+} from "./datastore.ts";
 
 // TODO: BEGIN: when module import is fixed:
 //     import { parse as regExParamParse } from "regexparam";
@@ -167,59 +175,144 @@ export type CRUDCreateResponses<
     [K in keyof CRUDMethods<T, E, P>]: CRUDCreateResponse;
 };
 
+/**
+ * How the GET of a CRUD endpoint lists entities:
+ *  - `"pages"`: `{ results, next_page, prev_page }`, with the URLs of the
+ *    pages around the page.
+ *  - `"envelope"`: `{ items, next_cursor, total }`, with the `cursor`
+ *    parameter of the next page if there may be one, and the number of
+ *    entities if it is cheap to know.
+ *  - `"array"`: the entities as a bare array, with the next page in a
+ *    `Link` header and the total in an `X-Total-Count` header, like the
+ *    `"envelope"` fields.
+ */
+export type CRUDPagination = "pages" | "envelope" | "array";
+
+type Envelope = {
+    items: unknown[];
+    next_cursor?: string;
+    total?: number;
+};
+
 /**
  * Fetches crud data based on crud `url`.
  */
 async function fetchEntitiesCrud<T extends ChiselEntity>(
     type: { new (): T },
     url: string,
+    pagination: CRUDPagination = "pages",
 ): Promise<T[]> {
+    ensureNotInMemory("CRUD queries");
     const results = await opAsync(
         "op_chisel_crud_query",
         {
             typeName: type.name,
             url,
+            pagination: pagination == "pages" ? "pages" : "envelope",
         },
         requestContext,
     );
     return results as T[];
 }
 
+/** Responds with the entities of `envelope` as an array. */
+async function arrayResponse(
+    envelope: Envelope,
+    url: URL,
+    createResponse: CRUDCreateResponse,
+): Promise<Response> {
+    const response = await createResponse(envelope.items, 200);
+    if (envelope.next_cursor !== undefined) {
+        const next = new URL(url);
+        next.searchParams.delete("offset");
+        next.searchParams.set("cursor", envelope.next_cursor);
+        response.headers.set("link", `<${next.href}>; rel="next"`);
+    }
+    if (envelope.total !== undefined) {
+        response.headers.set("x-total-count", String(envelope.total));
+    }
+    return response;
+}
+
+/** The GET of a CRUD endpoint that lists entities with `pagination`. */
+function crudGet(
+    pagination: CRUDPagination,
+): CRUDMethodSignature<ChiselEntity, GenericChiselEntityClass> {
+    // Returns a specific entity matching params.id (if present) or all entities matching the filter in the `filter` URL parameter.
+    return async (
+        entity: GenericChiselEntityClass,
+        _req: Request,
+        params: CRUDBaseParams,
+        url: URL,
+        createResponse: CRUDCreateResponse,
+    ) => {
+        const { id } = params;
+        if (id) {
+            const u = await entity.findOne({ id });
+            return createResponse(u ?? "Not found", u ? 200 : 404);
+        }
+        const page = await fetchEntitiesCrud(entity, url.href, pagination);
+        if (pagination == "array") {
+            return arrayResponse(
+                page as unknown as Envelope,
+                url,
+                createResponse,
+            );
+        }
+        return createResponse(page, 200);
+    };
+}
+
 async function deleteEntitiesCrud<T extends ChiselEntity>(
     type: { new (): T },
     url: string,
-): Promise<void> {
-    await opAsync(
+): Promise<number> {
+    ensureNotInMemory("CRUD queries");
+    // The delete hooks are passed the entities, so they are fetched first,
+    // page by page, in the transaction that deletes them. Deletes ignore
+    // offsets, so their pages must too.
+    const olds: T[] = [];
+    if (hasHooks(type, deleteHooks)) {
+        const firstPage = new URL(url);
+        firstPage.searchParams.delete("offset");
+        let pageUrl: string | undefined = firstPage.href;
+        while (pageUrl !== undefined) {
+            const page = await opAsync(
+                "op_chisel_crud_query",
+                {
+                    typeName: type.name,
+                    url: pageUrl,
+                },
+                requestContext,
+            ) as { results: Record<string, unknown>[]; next_page?: string };
+            for (const result of page.results) {
+                const old = new type();
+                mergeDeep(old as Record<string, unknown>, result);
+                olds.push(old);
+            }
+            pageUrl = page.results.length === 0 ? undefined : page.next_page;
+        }
+    }
+    for (const old of olds) {
+        await runHook(type, "beforeDelete", old);
+    }
+    const deleted = await opAsync(
         "op_chisel_crud_delete",
         {
             typeName: type.name,
             url,
         },
         requestContext,
-    );
+    ) as number;
+    for (const old of olds) {
+        await runHook(type, "afterDelete", old);
+    }
+    return deleted;
 }
 
 const defaultCrudMethods: CRUDMethods<ChiselEntity, GenericChiselEntityClass> =
     {
-        // Returns a specific entity matching params.id (if present) or all entities matching the filter in the `filter` URL parameter.
-        GET: async (
-            entity: GenericChiselEntityClass,
-            _req: Request,
-            params: CRUDBaseParams,
-            url: URL,
-            createResponse: CRUDCreateResponse,
-        ) => {
-            const { id } = params;
-            if (id) {
-                const u = await entity.findOne({ id });
-                return createResponse(u ?? "Not found", u ? 200 : 404);
-            } else {
-                return createResponse(
-                    await fetchEntitiesCrud(entity, url.href),
-                    200,
-                );
-            }
-        },
+        GET: crudGet("pages"),
         // Creates and returns a new entity from the `req` payload. Ignores the payload's id property and assigns a fresh one.
         POST: async (
             entity: GenericChiselEntityClass,
@@ -370,9 +463,13 @@ export const standardCRUDMethods = {
  *     `:id` pattern.
  *  - `createResponses`: if present, a dictionary of method-specific Response creators.
  *  - `defaultCreateResponse`: default function to create all responses if `createResponses` entry is not provided.
- *     Defaults to `responseFromJson()`.
+ *     Defaults to `responseFromValue()`, which encodes the response as JSON, MessagePack or CBOR as requested by
+ *     the `Accept` header.
  *  - `parsePath`: parses the URL path instead of https://deno.land/x/regexparam. The parsing result is passed to
  *     CRUD methods as the `params` argument.
+ *  - `cacheControl`: `Cache-Control` header of successful GET responses that don't set one, e.g.
+ *     `"public, s-maxage=60"` to let chiseld serve them from its response cache for a minute.
+ *  - `pagination`: how GET lists entities, `"pages"` by default. See `CRUDPagination`.
  * @returns A request-handling function suitable as a default export in an endpoint.
  */
 export function crud<
@@ -389,6 +486,8 @@ export function crud<
         >;
         defaultCreateResponse?: CRUDCreateResponse;
         parsePath?: (url: URL) => P;
+        cacheControl?: string;
+        pagination?: CRUDPagination;
     },
 ): (req: Request) => Promise<Response> {
     const pathTemplateRaw = "/:chiselVersion" + requestContext.path + "/" +
@@ -398,18 +497,21 @@ export function crud<
 
     const pathTemplate = pathTemplateRaw.replace(/\/+/g, "/"); // in case we end up with foo///bar somehow.
 
-    const defaultCreateResponse = config?.defaultCreateResponse ||
-        responseFromJson;
     const parsePath = config?.parsePath ||
         createURLPathParser(pathTemplate);
-    const localDefaultCrudMethods =
-        defaultCrudMethods as unknown as CRUDMethods<T, E, P>;
+    const localDefaultCrudMethods = {
+        ...defaultCrudMethods,
+        GET: crudGet(config?.pagination ?? "pages"),
+    } as unknown as CRUDMethods<T, E, P>;
     const methods = config?.customMethods
         ? { ...localDefaultCrudMethods, ...config?.customMethods }
         : localDefaultCrudMethods;
 
     return (req: Request): Promise<Response> => {
         const methodName = req.method as keyof typeof methods; // assume valid, will be handled gracefully
+        const defaultCreateResponse = config?.defaultCreateResponse ||
+            ((body: unknown, status: number) =>
+                responseFromValue(body, status, req.headers.get("accept")));
         const createResponse = config?.createResponses?.[methodName] ||
             defaultCreateResponse;
         const method = methods[methodName];
@@ -421,6 +523,16 @@ export function crud<
 
         const url = new URL(req.url);
         const params = parsePath(url);
-        return method(entity, req, params, url, createResponse);
+        const response = method(entity, req, params, url, createResponse);
+        const cacheControl = config?.cacheControl;
+        if (cacheControl === undefined || methodName !== "GET") {
+            return response;
+        }
+        return response.then((res) => {
+            if (res.status == 200 && !res.headers.has("cache-control")) {
+                res.headers.set("cache-control", cacheControl);
+            }
+            return res;
+        });
     };
 }
@@ -1,6 +1,7 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
 import { crud } from "./crud.ts";
+import type { CRUDPagination } from "./crud.ts";
 import { mergeDeep, opAsync, opSync } from "./utils.ts";
 
 /**
@@ -51,15 +52,17 @@ abstract class Operator<Input, Output> {
                 const rid = getRid();
                 try {
                     while (true) {
-                        const properties = await opAsync(
-                            "op_chisel_query_next",
+                        const batch = await opAsync(
+                            "op_chisel_query_next_batch",
                             rid,
-                        );
+                        ) as unknown[];
 
-                        if (properties === null) {
+                        if (batch.length === 0) {
                             break;
                         }
-                        yield recordToOutput(properties);
+                        for (const properties of batch) {
+                            yield recordToOutput(properties);
+                        }
                     }
                 } finally {
                     Deno.core.tryClose(rid);
@@ -69,6 +72,56 @@ abstract class Operator<Input, Output> {
     }
 }
 
+/**
+ * Rows of each entity by id, kept in memory instead of the database while
+ * unit tests run.
+ */
+export type MemoryStore = Map<string, Map<string, Record<string, unknown>>>;
+
+let memoryStore: MemoryStore | undefined;
+
+/**
+ * Makes entities be stored in `store`, or in the database again if `store`
+ * is undefined.
+ */
+export function useMemoryStore(store: MemoryStore | undefined) {
+    memoryStore = store;
+}
+
+/** Throws if entities are kept in memory, where `what` isn't supported. */
+export function ensureNotInMemory(what: string) {
+    if (memoryStore !== undefined) {
+        throw new Error(`${what} can't be used in unit tests`);
+    }
+}
+
+function memoryRows(store: MemoryStore, name: string) {
+    let rows = store.get(name);
+    if (rows === undefined) {
+        rows = new Map();
+        store.set(name, rows);
+    }
+    return rows;
+}
+
+/** Stores `entity`, and the entities nested in it, in `store`. */
+function saveInMemory(store: MemoryStore, entity: ChiselEntity) {
+    for (const value of Object.values(entity)) {
+        if (value instanceof ChiselEntity) {
+            saveInMemory(store, value);
+        } else if (Array.isArray(value)) {
+            for (const element of value) {
+                if (element instanceof ChiselEntity) {
+                    saveInMemory(store, element);
+                }
+            }
+        }
+    }
+    entity.id ??= crypto.randomUUID();
+    const row = JSON.parse(JSON.stringify(entity));
+    memoryRows(store, entity.constructor.name).set(entity.id, row);
+}
+
 /**
  * Specifies Entity whose elements are to be fetched.
  */
@@ -93,8 +146,19 @@ class BaseEntity<T> extends Operator<never, T> {
         return result;
     }
 
-    public eval(): undefined {
-        return undefined;
+    public eval(): AsyncIterable<T> | undefined {
+        if (memoryStore === undefined) {
+            return undefined;
+        }
+        const rows = [...memoryRows(memoryStore, this.name).values()];
+        const recordToOutput = (row: unknown) => this.recordToOutput(row);
+        return {
+            [Symbol.asyncIterator]: async function* () {
+                for (const row of rows) {
+                    yield recordToOutput(JSON.parse(JSON.stringify(row)));
+                }
+            },
+        };
     }
 }
 
@@ -167,6 +231,146 @@ class Skip<T> extends Operator<T, T> {
     }
 }
 
+/**
+ * TextSearch operator keeps the elements whose `@searchable` fields contain
+ * all the words of `terms`. Only the database can evaluate it.
+ */
+class TextSearch<T> extends Operator<T, T> {
+    constructor(
+        inner: Operator<unknown, T>,
+        public readonly terms: string,
+    ) {
+        super(inner);
+    }
+
+    apply(
+        _iter: AsyncIterable<T>,
+    ): AsyncIterable<T> {
+        throw new Error(
+            "full-text search can only be evaluated by the database",
+        );
+    }
+
+    recordToOutput(rawRecord: unknown): T {
+        return this.inner!.recordToOutput(rawRecord);
+    }
+}
+
+/**
+ * WithArchived operator also reads the rows moved to the archive table of an
+ * `@archive` entity. Rows are only ever archived in the database.
+ */
+class WithArchived<T> extends Operator<T, T> {
+    constructor(inner: Operator<unknown, T>) {
+        super(inner);
+    }
+
+    apply(
+        iter: AsyncIterable<T>,
+    ): AsyncIterable<T> {
+        return iter;
+    }
+
+    recordToOutput(rawRecord: unknown): T {
+        return this.inner!.recordToOutput(rawRecord);
+    }
+}
+
+/**
+ * Children operator replaces the elements of an entity that refers to itself
+ * with the elements whose `field` refers to them, i.e. their children in a
+ * tree.
+ */
+class Children<T> extends Operator<T, T> {
+    constructor(
+        inner: Operator<unknown, T>,
+        public readonly field: string,
+        readonly base: BaseEntity<T>,
+    ) {
+        super(inner);
+    }
+
+    apply(
+        iter: AsyncIterable<T>,
+    ): AsyncIterable<T> {
+        const field = this.field;
+        const base = this.base;
+        return {
+            [Symbol.asyncIterator]: async function* () {
+                const ids = new Set<string>();
+                for await (const e of iter) {
+                    ids.add((e as unknown as { id: string }).id);
+                }
+                const all = base.eval() ?? base.runChiselQuery();
+                for await (const e of all) {
+                    const parent = (e as Record<string, unknown>)[field] as
+                        | { id?: string }
+                        | undefined;
+                    if (parent?.id !== undefined && ids.has(parent.id)) {
+                        yield e;
+                    }
+                }
+            },
+        };
+    }
+
+    recordToOutput(rawRecord: unknown): T {
+        return this.base.recordToOutput(rawRecord);
+    }
+}
+
+/**
+ * Related operator replaces the elements with the elements of `entityName`
+ * that they relate to in their many-to-many relation `field`.
+ */
+class Related<Input, T> extends Operator<Input, T> {
+    constructor(
+        inner: Operator<unknown, Input>,
+        public readonly field: string,
+        public readonly entityName: string,
+        readonly base: BaseEntity<T>,
+    ) {
+        super(inner);
+    }
+
+    apply(
+        iter: AsyncIterable<Input>,
+    ): AsyncIterable<T> {
+        const field = this.field;
+        const base = this.base;
+        return {
+            [Symbol.asyncIterator]: async function* () {
+                const ids = new Set<string>();
+                for await (const e of iter) {
+                    const related = (e as Record<string, unknown>)[field] as
+                        | { id?: string }[]
+                        | undefined;
+                    for (const r of related ?? []) {
+                        if (r.id !== undefined) {
+                            ids.add(r.id);
+                        }
+                    }
+                }
+                const all = base.eval() ?? base.runChiselQuery();
+                for await (const e of all) {
+                    if (ids.has((e as unknown as { id: string }).id)) {
+                        yield e;
+                    }
+                }
+            },
+        };
+    }
+
+    recordToOutput(rawRecord: unknown): T {
+        return this.base.recordToOutput(rawRecord);
+    }
+}
+
+/** The keys of `T` whose values are of type `V`. */
+type KeysOfType<T, V> = {
+    [K in keyof T]-?: T[K] extends V ? K : never;
+}[keyof T];
+
 /**
  * Map operator applies a function to each element of this collection
  */
@@ -275,6 +479,7 @@ class PredicateFilter<T> extends Operator<T, T> {
     public eval(): AsyncIterable<T> {
         let iter = this.inner!.eval();
         if (iter === undefined) {
+            warnFilterFallback(this.inner!, this.predicate);
             iter = this.inner!.runChiselQuery();
         }
         return this.apply(iter);
@@ -285,6 +490,37 @@ class PredicateFilter<T> extends Operator<T, T> {
     }
 }
 
+// Predicates already reported by `warnFilterFallback`, keyed by endpoint path.
+const reportedFallbacks = new Set<string>();
+
+/**
+ * Logs a warning (once per endpoint and predicate) that `predicate` is
+ * evaluated in the isolate over all the elements fetched by `inner`, because it
+ * couldn't be translated into a database query.
+ */
+function warnFilterFallback<T>(
+    inner: Operator<unknown, T>,
+    predicate: (arg: T) => boolean,
+) {
+    let base: Operator<unknown, unknown> = inner;
+    while (base.inner !== undefined) {
+        base = base.inner;
+    }
+    if (!(base instanceof BaseEntity)) {
+        return;
+    }
+    const expression = predicate.toString();
+    const key = `${requestContext.path}\0${expression}`;
+    if (reportedFallbacks.has(key)) {
+        return;
+    }
+    reportedFallbacks.add(key);
+    opSync("op_chisel_filter_fallback", {
+        typeName: base.name,
+        expression,
+    }, requestContext);
+}
+
 /**
  * ExpressionFilter operator is intended only to be used by Chisel compiler.
  * It applies `predicate` on each element and keeps only those for which
@@ -464,6 +700,112 @@ class MaxBy<Input, K extends keyof Input>
 }
 
 /** ChiselCursor is a lazy iterator that will be used by ChiselStrike to construct an optimized query. */
+/** Selects a page of results, see `ChiselCursor.page()`. */
+export type PageOptions = {
+    /** The `nextCursor` of the previous page. Omitted for the first page. */
+    cursor?: string;
+    /** The maximum number of results in the page. */
+    limit: number;
+};
+
+/** A page of results, see `ChiselCursor.page()`. */
+export type Page<T> = {
+    results: T[];
+    /** Opaque token to get the next page with. Undefined on the last page. */
+    nextCursor?: string;
+};
+
+/** What `ChiselCursor.aggregate()` computes over the elements. */
+export type AggregateOptions<T> = {
+    /** Counts the elements. */
+    count?: boolean;
+    /** A number field to sum up. */
+    sum?: keyof T;
+    /** A number field to average. */
+    avg?: keyof T;
+    /** A field by whose values the elements are grouped. */
+    groupBy?: keyof T;
+};
+
+/**
+ * The `count`, `sum` and `avg` asked for by `AggregateOptions`. When the
+ * elements are grouped, it also has the value of the grouping field.
+ */
+export type AggregateResult = {
+    count?: number;
+    sum?: number;
+    /** Null when no element has a value for the averaged field. */
+    avg?: number | null;
+    [field: string]: unknown;
+};
+
+type GroupKey = string | number | boolean | null;
+
+/**
+ * Computes `options` over the elements of `iter` in the isolate, the way
+ * the database does for cursors it can evaluate.
+ */
+async function aggregateInIsolate<T>(
+    iter: AsyncIterable<T>,
+    options: AggregateOptions<T>,
+): Promise<AggregateResult | AggregateResult[]> {
+    if (
+        !options.count && options.sum === undefined &&
+        options.avg === undefined
+    ) {
+        throw new Error(
+            "an aggregation needs at least one of count, sum or avg",
+        );
+    }
+    const groupBy = options.groupBy;
+    const groups = new Map<GroupKey, T[]>();
+    for await (const e of iter) {
+        const key = groupBy === undefined
+            ? null
+            : (e[groupBy] ?? null) as unknown as GroupKey;
+        let group = groups.get(key);
+        if (group === undefined) {
+            group = [];
+            groups.set(key, group);
+        }
+        group.push(e);
+    }
+    const aggregate = (elements: T[]) => {
+        const result: AggregateResult = {};
+        const numbers = (field: keyof T) => {
+            const values = elements.map((e) => e[field] as unknown);
+            return values.filter((v) => typeof v == "number") as number[];
+        };
+        if (options.count) {
+            result.count = elements.length;
+        }
+        if (options.sum !== undefined) {
+            result.sum = numbers(options.sum).reduce((a, b) => a + b, 0);
+        }
+        if (options.avg !== undefined) {
+            const values = numbers(options.avg);
+            result.avg = values.length == 0
+                ? null
+                : values.reduce((a, b) => a + b, 0) / values.length;
+        }
+        return result;
+    };
+    if (groupBy === undefined) {
+        return aggregate(groups.get(null) ?? []);
+    }
+    // Sorted like the database does, with the missing values last.
+    const keys = [...groups.keys()].sort((l, r) => {
+        if (l === null || r === null) {
+            return l === r ? 0 : l === null ? 1 : -1;
+        }
+        return l < r ? -1 : l > r ? 1 : 0;
+    });
+    return keys.map((key) => ({
+        [groupBy]: key,
+        ...aggregate(groups.get(key)!),
+    }));
+}
+
 export class ChiselCursor<T> {
     constructor(private inner: Operator<unknown, T>) {}
 
@@ -497,6 +839,66 @@ export class ChiselCursor<T> {
         );
     }
 
+    /**
+     * Restricts this cursor to the elements whose `@searchable` fields
+     * contain all the words of `terms`.
+     */
+    search(terms: string): ChiselCursor<T> {
+        return new ChiselCursor(
+            new TextSearch(this.inner, terms),
+        );
+    }
+
+    /**
+     * Makes this cursor also contain the elements that `@archive` moved to
+     * the archive table of their entity, which it otherwise leaves out.
+     */
+    withArchived(): ChiselCursor<T> {
+        return new ChiselCursor(
+            new WithArchived(this.inner),
+        );
+    }
+
+    /**
+     * Makes a cursor of the children of the elements of this cursor: the
+     * objects of the same entity whose `field` refers to one of them.
+     *
+     * @example
+     * ```typescript
+     * const subcategories = Category.cursor().filter({ name: "books" })
+     *     .children("parent");
+     * ```
+     */
+    children(field: KeysOfType<T, T | undefined>): ChiselCursor<T> {
+        return new ChiselCursor(
+            new Children(this.inner, field as string, baseEntity(this.inner)),
+        );
+    }
+
+    /**
+     * Makes a cursor of the objects of class `entity` that the elements of
+     * this cursor relate to in their many-to-many relation `field`.
+     *
+     * @example
+     * ```typescript
+     * const tags = Post.cursor().filter({ author: "Ann" })
+     *     .related("tags", Tag);
+     * ```
+     */
+    related<E extends ChiselEntity>(
+        field: KeysOfType<T, E[] | undefined>,
+        entity: { new (): E },
+    ): ChiselCursor<E> {
+        return new ChiselCursor(
+            new Related<T, E>(
+                this.inner,
+                field as string,
+                entity.name,
+                new BaseEntity(entity.name, entity),
+            ),
+        );
+    }
+
     /**
      * Restricts this cursor to contain only elements that match the given @predicate.
      */
@@ -525,27 +927,39 @@ export class ChiselCursor<T> {
                 // If it's an empty restriction, no need to create an empty filter.
                 return this;
             }
-            const predicate = (arg: T) => {
-                for (const key in restrictions) {
-                    if (restrictions[key] === undefined) {
-                        continue;
-                    }
-                    if (arg[key] != restrictions[key]) {
-                        return false;
-                    }
-                }
-                return true;
-            };
             return new ChiselCursor(
                 new ExpressionFilter(
                     this.inner,
-                    predicate,
+                    (arg: T) => matchesRestrictions(arg, restrictions),
                     expr,
                 ),
             );
         }
     }
 
+    /**
+     * Restricts this cursor to contain only elements that match the predicate
+     * produced by `builder`.
+     *
+     * @example
+     * ```typescript
+     * const adults = Person.cursor().where((p) => p.age.gte(18));
+     * ```
+     *
+     * Unlike `filter` with a lambda, the predicate is always evaluated by the
+     * database.
+     */
+    where(builder: FilterBuilder<T>): ChiselCursor<T> {
+        const filter = builder(filterFields<T>());
+        return new ChiselCursor(
+            new ExpressionFilter(
+                this.inner,
+                filter.predicate,
+                filter.expression,
+            ),
+        );
+    }
+
     // Filtering function used by Chisel Compiler. Not intended for direct usage.
     __filter(
         exprPredicate: (arg: T) => boolean,
@@ -571,13 +985,33 @@ export class ChiselCursor<T> {
      *
      * Note: the sort is not guaranteed to be stable.
      */
-    sortBy(key: keyof T, ascending = true): ChiselCursor<T> {
-        return new ChiselCursor(
-            new SortBy(
-                this.inner,
-                [new SortKey<T>(key, ascending)],
-            ),
-        );
+    sortBy(key: keyof T, ascending?: boolean): ChiselCursor<T>;
+    /**
+     * Sorts cursor elements lexicographically by multiple `keys`: elements
+     * equal in the first key are sorted by the second one and so on.
+     *
+     * @param keys the attributes of `T` to sort by, each ascending unless
+     * `ascending` is false.
+     *
+     * @example
+     * ```typescript
+     * Person.cursor().sortBy([{ key: "age", ascending: false }, { key: "name" }]);
+     * ```
+     */
+    sortBy(
+        keys: { key: keyof T; ascending?: boolean }[],
+    ): ChiselCursor<T>;
+    sortBy(
+        keyOrKeys: keyof T | { key: keyof T; ascending?: boolean }[],
+        ascending = true,
+    ): ChiselCursor<T> {
+        const keys = Array.isArray(keyOrKeys)
+            ? keyOrKeys.map((k) => new SortKey<T>(k.key, k.ascending ?? true))
+            : [new SortKey<T>(keyOrKeys, ascending)];
+        if (keys.length == 0) {
+            throw new Error("sortBy needs at least one key");
+        }
+        return new ChiselCursor(new SortBy(this.inner, keys));
     }
 
     /**
@@ -614,6 +1048,48 @@ export class ChiselCursor<T> {
         }
     }
 
+    /**
+     * Computes the count, sum or average asked for by `options` over the
+     * elements of this cursor, or over each group of elements that have the
+     * same value of the `groupBy` field, ordered by that value.
+     *
+     * When the database can evaluate this cursor, it computes the
+     * aggregation as well, and the elements aren't fetched at all.
+     *
+     * @example
+     * ```typescript
+     * const { count } = await Product.cursor().filter({ inStock: true })
+     *     .aggregate({ count: true });
+     * const categories = await Product.cursor()
+     *     .aggregate({ count: true, avg: "price", groupBy: "category" });
+     * ```
+     */
+    async aggregate(
+        options: AggregateOptions<T> & { groupBy: keyof T },
+    ): Promise<AggregateResult[]>;
+    async aggregate(options: AggregateOptions<T>): Promise<AggregateResult>;
+    async aggregate(
+        options: AggregateOptions<T>,
+    ): Promise<AggregateResult | AggregateResult[]> {
+        const iter = this.inner.eval();
+        if (iter !== undefined) {
+            return await aggregateInIsolate(iter, options);
+        }
+        return await opAsync("op_chisel_relational_aggregate", {
+            opChain: this.inner,
+            aggregation: options,
+        }, requestContext) as AggregateResult | AggregateResult[];
+    }
+
+    /**
+     * Counts the elements of this cursor. It's the same as
+     * `aggregate({ count: true })`, so the database counts them when it can.
+     */
+    async count(): Promise<number> {
+        const { count } = await this.aggregate({ count: true });
+        return count!;
+    }
+
     /** Executes the function `func` for each element of this cursor. */
     async forEach(func: (arg: T) => void): Promise<void> {
         for await (const t of this) {
@@ -621,6 +1097,63 @@ export class ChiselCursor<T> {
         }
     }
 
+    /**
+     * Returns a page of at most `limit` elements, ordered by id, that follow
+     * the page `cursor` was returned with.
+     *
+     * @example
+     * ```typescript
+     * let page = await Person.cursor().page({ limit: 100 });
+     * while (page.nextCursor !== undefined) {
+     *     page = await Person.cursor().page({ cursor: page.nextCursor, limit: 100 });
+     * }
+     * ```
+     *
+     * The cursor is evaluated by the database, so the cursor can't be
+     * sorted, skipped, limited or filtered by a predicate that isn't
+     * translated to a database query.
+     */
+    async page(options: PageOptions): Promise<Page<T>> {
+        let op: Operator<unknown, unknown> | undefined = this.inner;
+        for (; op !== undefined; op = op.inner) {
+            if (
+                !(op instanceof BaseEntity || op instanceof ExpressionFilter ||
+                    op instanceof ColumnsSelect || op instanceof Children ||
+                    op instanceof Related)
+            ) {
+                throw new Error(
+                    "only cursors filtered by restrictions or expressions can be paginated",
+                );
+            }
+        }
+        if (memoryStore !== undefined) {
+            return await this.memoryPage(options);
+        }
+        const page = await opAsync("op_chisel_relational_query_paginated", {
+            opChain: this.inner,
+            cursor: options.cursor,
+            limit: options.limit,
+        }, requestContext) as { results: unknown[]; nextCursor?: string };
+        return {
+            results: page.results.map((r) => this.inner.recordToOutput(r)),
+            nextCursor: page.nextCursor,
+        };
+    }
+
+    // Page of the in-memory store, whose cursor is the id of the last element.
+    private async memoryPage(options: PageOptions): Promise<Page<T>> {
+        const id = (e: T) => (e as unknown as { id: string }).id;
+        const elements = (await this.toArray())
+            .filter((e) => options.cursor === undefined || id(e) > options.cursor)
+            .sort((l, r) => id(l) < id(r) ? -1 : 1);
+        const results = elements.slice(0, options.limit);
+        const more = elements.length > options.limit;
+        return {
+            results,
+            nextCursor: more ? id(results[results.length - 1]) : undefined,
+        };
+    }
+
     /** Converts this cursor to an Array.
      *
      * Use this with caution as the result set can be very big.
@@ -654,13 +1187,167 @@ export class ChiselCursor<T> {
     }
 }
 
-export function chiselIterator<T extends ChiselEntity>(
+/** The `BaseEntity` whose elements the chain of operators `op` yields. */
+function baseEntity<T>(op: Operator<unknown, T>): BaseEntity<T> {
+    let base: Operator<unknown, unknown> = op;
+    while (base.inner !== undefined) {
+        if (base instanceof Children || base instanceof Related) {
+            return base.base as BaseEntity<T>;
+        }
+        base = base.inner;
+    }
+    if (!(base instanceof BaseEntity)) {
+        throw new Error("cursor doesn't start from an entity");
+    }
+    return base as BaseEntity<T>;
+}
+
+export function chiselIterator<T extends ChiselEntity>(
     type: { new (): T },
 ) {
     const b = new BaseEntity<T>(type.name, type);
     return new ChiselCursor(b);
 }
 
+/**
+ * Hooks that a model class can define as static methods, called around the
+ * writes of its entities in the transaction of the request, so that throwing
+ * from one rolls the write back. `before` hooks can change the entity that
+ * is about to be written. Updates and deletes pass the stored values as
+ * `old`.
+ *
+ * Hooks run for the entities written through `ChiselEntity`, including
+ * those of `crud()` routes, but not for the entities nested in them. The
+ * routes of `@autoCrud` don't see the model class, so it can't be used on
+ * classes with hooks: export `crud()` of the class from a route instead.
+ *
+ * @example
+ * ```typescript
+ * export class Post extends ChiselEntity {
+ *   title: string;
+ *   slug: string;
+ *
+ *   static beforeCreate(post: Post) {
+ *     post.slug = post.title.toLowerCase().replaceAll(" ", "-");
+ *   }
+ *
+ *   static async afterDelete(old: Post) {
+ *     await Comment.delete({ postId: old.id });
+ *   }
+ * }
+ * ```
+ */
+export type EntityHooks<T> = {
+    beforeCreate?(entity: T): void | Promise<void>;
+    afterCreate?(entity: T): void | Promise<void>;
+    beforeUpdate?(entity: T, old: T): void | Promise<void>;
+    afterUpdate?(entity: T, old: T): void | Promise<void>;
+    beforeDelete?(old: T): void | Promise<void>;
+    afterDelete?(old: T): void | Promise<void>;
+};
+
+type HookName = keyof EntityHooks<unknown>;
+
+const updateHooks: HookName[] = ["beforeUpdate", "afterUpdate"];
+const writeHooks: HookName[] = ["beforeCreate", "afterCreate", ...updateHooks];
+export const deleteHooks: HookName[] = ["beforeDelete", "afterDelete"];
+
+/** Whether the model class `type` defines any of the hooks `names`. */
+export function hasHooks(type: unknown, names: HookName[]): boolean {
+    const hooks = type as Record<string, unknown>;
+    return names.some((name) => typeof hooks[name] === "function");
+}
+
+/** Calls hook `name` of the model class `type`, if it defines it. */
+export async function runHook(
+    type: unknown,
+    name: HookName,
+    ...args: unknown[]
+) {
+    const hook = (type as Record<string, unknown>)[name];
+    if (typeof hook === "function") {
+        await hook.apply(type, args);
+    }
+}
+
+/** Writes `entity`, which is new if it has no `id`, without running hooks. */
+/**
+ * Gets the graphs of the objects nested in `entities` ready to be saved in
+ * one call, which saves the objects an object refers to before it. A new
+ * object nested in several places is given its id here, so that it is saved
+ * once instead of once per place. An object can't be saved before itself, so
+ * one that refers back to an object it is nested in is refused.
+ */
+function prepareGraphs(entities: ChiselEntity[]) {
+    const seen = new Set<ChiselEntity>();
+    const ancestors: ChiselEntity[] = [];
+    function visit(entity: ChiselEntity, path: string) {
+        if (ancestors.includes(entity)) {
+            throw new Error(
+                `Cannot save ${path}: it refers back to an object it is nested in`,
+            );
+        }
+        if (seen.has(entity)) {
+            entity.id ??= crypto.randomUUID();
+            return;
+        }
+        seen.add(entity);
+        ancestors.push(entity);
+        for (const [field, value] of Object.entries(entity)) {
+            if (value instanceof ChiselEntity) {
+                visit(value, `${path}.${field}`);
+            } else if (Array.isArray(value)) {
+                value.forEach((element, i) => {
+                    if (element instanceof ChiselEntity) {
+                        visit(element, `${path}.${field}[${i}]`);
+                    }
+                });
+            }
+        }
+        ancestors.pop();
+    }
+    for (const entity of entities) {
+        visit(entity, entity.constructor.name);
+    }
+}
+
+async function storeEntity(entity: ChiselEntity) {
+    prepareGraphs([entity]);
+    if (memoryStore !== undefined) {
+        saveInMemory(memoryStore, entity);
+        return;
+    }
+    const jsonIds = await opAsync("op_chisel_store", {
+        name: entity.constructor.name,
+        value: storedValue(entity),
+    }, requestContext) as IdsJson;
+    backfillIds(entity, jsonIds);
+}
+
+/**
+ * The value of `object` to store, whose nested entities say which entity
+ * they are of in `__type`, so that the objects of polymorphic relations, like
+ * `target: Post | Photo`, are stored as the right entity.
+ */
+function storedValue(object: object): Record<string, unknown> {
+    const value: Record<string, unknown> = {};
+    for (const [field, fieldValue] of Object.entries(object)) {
+        if (fieldValue instanceof ChiselEntity) {
+            value[field] = {
+                ...storedValue(fieldValue),
+                __type: fieldValue.constructor.name,
+            };
+        } else if (Array.isArray(fieldValue)) {
+            value[field] = fieldValue.map((element) =>
+                element instanceof ChiselEntity ? storedValue(element) : element
+            );
+        } else {
+            value[field] = fieldValue;
+        }
+    }
+    return value;
+}
+
 export type UpsertArgs<T> = {
     restrictions: Partial<T>;
     create: Partial<T>;
@@ -717,21 +1404,19 @@ export class ChiselEntity {
     /** saves the current object into the backend */
     async save() {
         ensureNotGet();
-        type IdsJson = { id: string; children: Record<string, IdsJson> };
-        const jsonIds = await opAsync("op_chisel_store", {
-            name: this.constructor.name,
-            value: this,
-        }, requestContext) as IdsJson;
-        function backfillIds(this_: ChiselEntity, jsonIds: IdsJson) {
-            this_.id = jsonIds.id;
-            for (const [fieldName, value] of Object.entries(jsonIds.children)) {
-                const child = (this_ as unknown as Record<string, unknown>)[
-                    fieldName
-                ];
-                backfillIds(child as ChiselEntity, value);
-            }
+        const type = this.constructor as typeof ChiselEntity;
+        const old = this.id !== undefined && hasHooks(type, writeHooks)
+            ? await type.findOne({ id: this.id })
+            : undefined;
+        if (old === undefined) {
+            await runHook(type, "beforeCreate", this);
+            await storeEntity(this);
+            await runHook(type, "afterCreate", this);
+        } else {
+            await runHook(type, "beforeUpdate", this, old);
+            await storeEntity(this);
+            await runHook(type, "afterUpdate", this, old);
         }
-        backfillIds(this, jsonIds);
     }
 
     /** Returns a `ChiselCursor` containing all elements of type T known to ChiselStrike.
@@ -743,6 +1428,65 @@ export class ChiselEntity {
         return chiselIterator<T>(this);
     }
 
+    /**
+     * Returns a `ChiselCursor` of the entities whose `@searchable` fields
+     * contain all the words of `terms`.
+     *
+     * @example
+     * ```typescript
+     * @searchable("title", "body")
+     * export class Post extends ChiselEntity {
+     *     title: string;
+     *     body: string;
+     * }
+     * const posts = await Post.search("rust database").take(10).toArray();
+     * ```
+     */
+    static search<T extends ChiselEntity>(
+        this: { new (): T },
+        terms: string,
+    ): ChiselCursor<T> {
+        return chiselIterator<T>(this).search(terms);
+    }
+
+    /**
+     * Computes the count, sum or average asked for by `options` over all
+     * the entities of type T, see `ChiselCursor.aggregate()`.
+     *
+     * @example
+     * ```typescript
+     * const categories = await Product.aggregate({
+     *     count: true,
+     *     avg: "price",
+     *     groupBy: "category",
+     * });
+     * ```
+     */
+    static async aggregate<T extends ChiselEntity>(
+        this: { new (): T },
+        options: AggregateOptions<T> & { groupBy: keyof T },
+    ): Promise<AggregateResult[]>;
+    static async aggregate<T extends ChiselEntity>(
+        this: { new (): T },
+        options: AggregateOptions<T>,
+    ): Promise<AggregateResult>;
+    static async aggregate<T extends ChiselEntity>(
+        this: { new (): T },
+        options: AggregateOptions<T>,
+    ): Promise<AggregateResult | AggregateResult[]> {
+        return await chiselIterator<T>(this).aggregate(options);
+    }
+
+    /**
+     * Counts all the entities of type T. Entities declared `@counted` keep
+     * the count, instead of counting them every time.
+     */
+    static async count<T extends ChiselEntity>(
+        this: { new (): T },
+    ): Promise<number> {
+        return await chiselIterator<T>(this).count();
+    }
+
     /**
      * Return all entities of type T.
      */
@@ -757,6 +1501,26 @@ export class ChiselEntity {
         return await it.toArray();
     }
 
+    /**
+     * Returns a `ChiselCursor` containing the elements of type T that match the
+     * predicate produced by `builder`.
+     *
+     * @example
+     * ```typescript
+     * const seniors = await Person.filter((p) => p.age.gt(65).and(p.name.like("A%")))
+     *     .toArray();
+     * ```
+     *
+     * The field names and the types of the compared values are checked at
+     * compile time, and the predicate is always evaluated by the database.
+     */
+    static filter<T extends ChiselEntity>(
+        this: { new (): T },
+        builder: FilterBuilder<T>,
+    ): ChiselCursor<T> {
+        return chiselIterator<T>(this).where(builder);
+    }
+
     /**
      * Returns all entities of type T for which the given `predicate` returns true.
      * You can optionaly specify `take` parameter that will limit the number of
@@ -779,17 +1543,39 @@ export class ChiselEntity {
         take?: number,
     ): Promise<T[]>;
 
+    /**
+     * Returns a page of the entities of type T matching given `restrictions`.
+     * Pass the `nextCursor` of a page as `cursor` to get the page after it.
+     *
+     * @example
+     * ```typescript
+     * const first = await Person.findMany({ country: "Brazil" }, { limit: 10 });
+     * const second = await Person.findMany({ country: "Brazil" }, {
+     *     cursor: first.nextCursor,
+     *     limit: 10,
+     * });
+     * ```
+     */
+    static async findMany<T extends ChiselEntity>(
+        this: { new (): T },
+        restrictions: Partial<T>,
+        page: PageOptions,
+    ): Promise<Page<T>>;
+
     static async findMany<T extends ChiselEntity>(
         this: { new (): T },
         arg1: ((arg: T) => boolean) | Partial<T>,
-        take?: number,
-    ): Promise<T[]> {
+        take?: number | PageOptions,
+    ): Promise<T[] | Page<T>> {
         let it = undefined;
         if (typeof arg1 == "function") {
             it = chiselIterator<T>(this).filter(arg1);
         } else {
             it = chiselIterator<T>(this).filter(arg1);
         }
+        if (typeof take == "object") {
+            return await it.page(take);
+        }
         if (take !== undefined) {
             it = it.take(take);
         }
@@ -881,16 +1667,39 @@ export class ChiselEntity {
      *
      * await User.delete({ email: "alice@example.com"})
      * ```
+     *
+     * @returns The number of deleted objects.
      */
     static async delete<T extends ChiselEntity>(
         this: { new (): T },
         restrictions: Partial<T>,
-    ): Promise<void> {
+    ): Promise<number> {
         ensureNotGet();
-        await opAsync("op_chisel_entity_delete", {
-            typeName: this.name,
-            filterExpr: restrictionsToFilterExpr(restrictions),
-        }, requestContext);
+        const olds = hasHooks(this, deleteHooks)
+            ? await chiselIterator<T>(this).filter(restrictions).toArray()
+            : [];
+        for (const old of olds) {
+            await runHook(this, "beforeDelete", old);
+        }
+        let deleted = 0;
+        if (memoryStore !== undefined) {
+            const rows = memoryRows(memoryStore, this.name);
+            for (const [id, row] of rows) {
+                if (matchesRestrictions(row as Partial<T>, restrictions)) {
+                    rows.delete(id);
+                    deleted++;
+                }
+            }
+        } else {
+            deleted = await opAsync("op_chisel_entity_delete", {
+                typeName: this.name,
+                filterExpr: restrictionsToFilterExpr(restrictions),
+            }, requestContext) as number;
+        }
+        for (const old of olds) {
+            await runHook(this, "afterDelete", old);
+        }
+        return deleted;
     }
 
     /**
@@ -957,6 +1766,118 @@ export class ChiselEntity {
         return result;
     }
 
+    /**
+     * Creates new objects and persists them all at once, which is much faster
+     * than saving them one by one.
+     *
+     * @example
+     * ```typescript
+     * export class User extends ChiselEntity {
+     *   username: string,
+     *   email: string,
+     * }
+     * const users = await User.createMany([
+     *     { username: "alice", email: "alice@example.com" },
+     *     { username: "bob", email: "bob@example.com" },
+     * ]);
+     * ```
+     *
+     * @returns The persisted entities, with their `id` property set.
+     */
+    static async createMany<T extends ChiselEntity>(
+        this: { new (): T },
+        values: Partial<T>[],
+    ): Promise<T[]> {
+        ensureNotGet();
+        const results = values.map((properties) => {
+            const result = new this();
+            mergeDeep(
+                result as Record<string, unknown>,
+                properties as Record<string, unknown>,
+            );
+            return result;
+        });
+        for (const result of results) {
+            await runHook(this, "beforeCreate", result);
+        }
+        prepareGraphs(results);
+        if (memoryStore !== undefined) {
+            for (const result of results) {
+                saveInMemory(memoryStore, result);
+            }
+        } else if (results.length !== 0) {
+            const jsonIds = await opAsync("op_chisel_store_many", {
+                name: this.name,
+                values: results.map(storedValue),
+            }, requestContext) as IdsJson[];
+            results.forEach((result, i) => backfillIds(result, jsonIds[i]));
+        }
+        for (const result of results) {
+            await runHook(this, "afterCreate", result);
+        }
+        return results;
+    }
+
+    /**
+     * Sets the given properties of the stored object with id `id`, leaving
+     * the others as they are. Unlike `save()`, the object doesn't need to be
+     * loaded first. Entity properties are set to refer to the given entity,
+     * which must already be saved.
+     *
+     * @example
+     * ```typescript
+     * export class User extends ChiselEntity {
+     *   username: string,
+     *   email: string,
+     * }
+     * await User.update(id, { email: "alice@chiselstrike.com" });
+     * ```
+     *
+     * If the class has update hooks, the object is loaded to pass it to
+     * them, and saved whole.
+     *
+     * @returns Whether there is an object with id `id`.
+     */
+    static async update<T extends ChiselEntity>(
+        this: { new (): T },
+        id: string,
+        changes: Partial<T>,
+    ): Promise<boolean> {
+        ensureNotGet();
+        if (hasHooks(this, updateHooks)) {
+            const [old] = await chiselIterator<T>(this)
+                .filter({ id } as Partial<T>)
+                .take(1)
+                .toArray();
+            if (old === undefined) {
+                return false;
+            }
+            const entity = new this();
+            mergeDeep(
+                entity as Record<string, unknown>,
+                old as Record<string, unknown>,
+                changes as Record<string, unknown>,
+            );
+            await runHook(this, "beforeUpdate", entity, old);
+            await storeEntity(entity);
+            await runHook(this, "afterUpdate", entity, old);
+            return true;
+        }
+        if (memoryStore !== undefined) {
+            const row = memoryRows(memoryStore, this.name).get(id);
+            if (row === undefined) {
+                return false;
+            }
+            Object.assign(row, JSON.parse(JSON.stringify(changes)));
+            return true;
+        }
+        return await opAsync("op_chisel_update", {
+            name: this.name,
+            id,
+            changes: storedValue(changes),
+        }, requestContext) as boolean;
+    }
+
     /**
      * Update an object or create it if it doesn't exist.
      *
@@ -975,12 +1896,36 @@ export class ChiselEntity {
      *
      * Please note that upsert only updates a single row it matches on.
      *
+     * If `restrictions` only has a `@unique` property, or the id, with the
+     * same value as in `create`, the object is created or updated by a single
+     * `INSERT ... ON CONFLICT` statement, so concurrent upserts can't create
+     * it twice. That is not the case if the class has hooks, which need
+     * the object to be loaded first.
+     *
      * @version experimental
      */
     static async upsert<T extends ChiselEntity>(
         this: { new (): T },
         args: UpsertArgs<T>,
     ): Promise<T> {
+        ensureNotGet();
+        if (memoryStore === undefined && !hasHooks(this, writeHooks)) {
+            const create = new this();
+            mergeDeep(
+                create as Record<string, unknown>,
+                args.create as Record<string, unknown>,
+            );
+            const id = await opAsync("op_chisel_upsert", {
+                name: this.name,
+                restrictions: args.restrictions,
+                create: storedValue(create),
+                update: storedValue(args.update),
+            }, requestContext) as string | null;
+            if (id !== null) {
+                const record = await this.findOne({ id } as Partial<T>);
+                return record!;
+            }
+        }
         const it = chiselIterator<T>(this).filter(args.restrictions);
         let record = null;
         for await (const value of it) {
@@ -1006,6 +1951,36 @@ export class ChiselEntity {
     }
 }
 
+type IdsJson = {
+    id: string;
+    children: Record<string, IdsJson>;
+    lists?: Record<string, IdsJson[]>;
+};
+
+function backfillIds(this_: ChiselEntity, jsonIds: IdsJson) {
+    this_.id = jsonIds.id;
+    const fields = this_ as unknown as Record<string, unknown>;
+    for (const [fieldName, value] of Object.entries(jsonIds.children)) {
+        backfillIds(fields[fieldName] as ChiselEntity, value);
+    }
+    for (const [fieldName, values] of Object.entries(jsonIds.lists ?? {})) {
+        const elements = fields[fieldName] as ChiselEntity[];
+        values.forEach((value, i) => backfillIds(elements[i], value));
+    }
+}
+
+function matchesRestrictions<T>(arg: T, restrictions: Partial<T>): boolean {
+    for (const key in restrictions) {
+        if (restrictions[key] === undefined) {
+            continue;
+        }
+        if (arg[key] != restrictions[key]) {
+            return false;
+        }
+    }
+    return true;
+}
+
 function restrictionsToFilterExpr<T extends ChiselEntity>(
     restrictions: Partial<T>,
 ): Record<string, unknown> | undefined {
@@ -1041,6 +2016,203 @@ function restrictionsToFilterExpr<T extends ChiselEntity>(
     return expr;
 }
 
+/**
+ * A predicate over entities of type `T` built with the typed filter DSL.
+ *
+ * Unlike an arbitrary lambda, a `FilterPredicate` always carries an
+ * expression that the Rust backend can translate into a database predicate,
+ * so filtering with it never falls back to a full scan in the isolate.
+ */
+export class FilterPredicate<T> {
+    constructor(
+        public readonly expression: Record<string, unknown>,
+        public readonly predicate: (arg: T) => boolean,
+    ) {}
+
+    /** Returns a predicate that holds when both `this` and `other` hold. */
+    and(other: FilterPredicate<T>): FilterPredicate<T> {
+        return this.combine(other, "And", (l, r) => l && r);
+    }
+
+    /** Returns a predicate that holds when either `this` or `other` holds. */
+    or(other: FilterPredicate<T>): FilterPredicate<T> {
+        return this.combine(other, "Or", (l, r) => l || r);
+    }
+
+    private combine(
+        other: FilterPredicate<T>,
+        op: string,
+        combinator: (lhs: boolean, rhs: boolean) => boolean,
+    ): FilterPredicate<T> {
+        const [lhs, rhs] = [this.predicate, other.predicate];
+        return new FilterPredicate(
+            {
+                exprType: "Binary",
+                left: this.expression,
+                op,
+                right: other.expression,
+            },
+            (arg: T) => combinator(lhs(arg), rhs(arg)),
+        );
+    }
+}
+
+/**
+ * Reference to a (possibly nested) field of type `V` of entity `T`, used to
+ * build `FilterPredicate`s. Values passed to the comparison methods are
+ * checked against the field type at compile time.
+ */
+export class FieldRef<T, V> {
+    // Private names so that they can't shadow entity fields in `filterFields`.
+    readonly #path: string[];
+
+    constructor(path: string[]) {
+        this.#path = path;
+    }
+
+    /** Field is equal to `value`. */
+    eq(value: V): FilterPredicate<T> {
+        return this.#compare("Eq", value, (l, r) => l == r);
+    }
+
+    /** Field is not equal to `value`. */
+    ne(value: V): FilterPredicate<T> {
+        return this.#compare("NotEq", value, (l, r) => l != r);
+    }
+
+    /** Field is strictly less than `value`. */
+    lt(value: V): FilterPredicate<T> {
+        return this.#compare("Lt", value, (l, r) => l < r);
+    }
+
+    /** Field is less than or equal to `value`. */
+    lte(value: V): FilterPredicate<T> {
+        return this.#compare("LtEq", value, (l, r) => l <= r);
+    }
+
+    /** Field is strictly greater than `value`. */
+    gt(value: V): FilterPredicate<T> {
+        return this.#compare("Gt", value, (l, r) => l > r);
+    }
+
+    /** Field is greater than or equal to `value`. */
+    gte(value: V): FilterPredicate<T> {
+        return this.#compare("GtEq", value, (l, r) => l >= r);
+    }
+
+    /**
+     * Field matches the SQL LIKE `pattern`, where `%` matches any sequence of
+     * characters and `_` matches a single character.
+     */
+    like(this: FieldRef<T, string>, pattern: string): FilterPredicate<T> {
+        const re = likePatternToRegExp(pattern);
+        return this.#compare("Like", pattern, (l) => re.test(String(l)));
+    }
+
+    /** Field doesn't match the SQL LIKE `pattern`. See `like`. */
+    notLike(this: FieldRef<T, string>, pattern: string): FilterPredicate<T> {
+        const re = likePatternToRegExp(pattern);
+        return this.#compare("NotLike", pattern, (l) => !re.test(String(l)));
+    }
+
+    #compare<U>(
+        op: string,
+        value: U,
+        cmp: (lhs: U, rhs: U) => boolean,
+    ): FilterPredicate<T> {
+        let property: Record<string, unknown> = {
+            exprType: "Parameter",
+            position: 0,
+        };
+        for (const name of this.#path) {
+            property = {
+                exprType: "Property",
+                object: property,
+                property: name,
+            };
+        }
+        const path = this.#path;
+        return new FilterPredicate(
+            {
+                exprType: "Binary",
+                left: property,
+                op,
+                right: { exprType: "Value", value },
+            },
+            (arg: T) => {
+                let v: unknown = arg;
+                for (const name of path) {
+                    v = (v as Record<string, unknown> | undefined)?.[name];
+                }
+                return cmp(v as U, value);
+            },
+        );
+    }
+}
+
+/**
+ * Typed view of the fields of entity `T` handed to filter builders. Fields
+ * that are themselves entities expose their own fields, so nested
+ * predicates like `p.biography.title.eq("...")` are checked as well.
+ */
+export type FilterFields<T, E = T> = {
+    [K in keyof E as E[K] extends (...args: never[]) => unknown ? never : K]-?:
+        & FieldRef<T, NonNullable<E[K]>>
+        & (NonNullable<E[K]> extends ChiselEntity
+            ? FilterFields<T, NonNullable<E[K]>>
+            : unknown);
+};
+
+/** Builds a `FilterPredicate` from the typed fields of `T`. */
+export type FilterBuilder<T> = (fields: FilterFields<T>) => FilterPredicate<T>;
+
+/** Names of the comparison methods of `FieldRef`. */
+const fieldRefMethods = new Set(
+    Object.getOwnPropertyNames(FieldRef.prototype).filter((name) =>
+        name != "constructor"
+    ),
+);
+
+function filterFields<T>(path: string[] = []): FilterFields<T> {
+    const target = new FieldRef<T, unknown>(path);
+    return new Proxy(target, {
+        get(target, prop) {
+            if (typeof prop !== "string") {
+                return Reflect.get(target, prop);
+            }
+            const field = filterFields<T>([...path, prop]);
+            if (!fieldRefMethods.has(prop)) {
+                return field;
+            }
+            // An entity can have a field named like a comparison method, so
+            // `prop` is both: calling it compares this field, and reading its
+            // properties goes on to the nested field. Methods access private
+            // fields, so they must run on the target.
+            const method = Reflect.get(target, prop) as (
+                ...args: unknown[]
+            ) => unknown;
+            return new Proxy(
+                (...args: unknown[]) => method.apply(target, args),
+                { get: (_, nested) => Reflect.get(field, nested) },
+            );
+        },
+    }) as unknown as FilterFields<T>;
+}
+
+function likePatternToRegExp(pattern: string): RegExp {
+    let re = "";
+    for (const c of pattern) {
+        if (c == "%") {
+            re += ".*";
+        } else if (c == "_") {
+            re += ".";
+        } else {
+            re += c.replace(/[.*+?^${}()|[\]\\]/g, "\\$&");
+        }
+    }
+    return new RegExp(`^${re}$`, "s");
+}
+
 export function labels(..._val: string[]) {
     return <T>(_target: T, _propertyName: string) => {
         // chisel-decorator, no content
@@ -1051,12 +2223,196 @@ export function unique(_target: unknown, _name: string): void {
     // chisel-decorator, no content
 }
 
+/**
+ * Stores a field in the column `name` of the table of its entity, when applied
+ * to it as `@column("created_at")`, rather than in one named after it.
+ */
+export function column(_name: string) {
+    return <T>(_target: T, _propertyName: string) => {
+        // chisel-decorator, no content
+    };
+}
+
+/**
+ * Stores the rows of an entity class in the table `name`, when applied to it
+ * as `@table("blog_posts")`, rather than in a table of its own. The table is
+ * created if it doesn't exist, and an existing one is used as it is, so the
+ * entity can sit on top of a table that other applications also use.
+ */
+export function table(_name: string) {
+    return <T>(_target: T) => {
+        // chisel-decorator, no content
+    };
+}
+
+/**
+ * Indexes a field, when applied to it, or the given fields together, when
+ * applied to an entity class as `@index("field1", "field2")`.
+ */
+export function index(_target: unknown, _name: string): void;
+export function index(...fields: string[]): <T>(target: T) => void;
+export function index(...args: unknown[]) {
+    if (typeof args[0] === "string") {
+        return <T>(_target: T) => {
+            // chisel-decorator, no content
+        };
+    }
+    // chisel-decorator, no content
+}
+
+/**
+ * Makes the given string fields of an entity class searchable with
+ * `search()`, when applied to it as `@searchable("field1", "field2")`.
+ */
+export function searchable(..._fields: string[]) {
+    return <T>(_target: T) => {
+        // chisel-decorator, no content
+    };
+}
+
+/**
+ * Keeps a count of the rows of an entity class, when applied to it as
+ * `@counted()`, or of its rows with each value of the given fields, as
+ * `@counted("field1", "field2")`. The counts are updated along with the rows,
+ * so counting all the rows, or grouping their count by one of the fields,
+ * doesn't scan the table.
+ */
+export function counted(..._fields: string[]) {
+    return <T>(_target: T) => {
+        // chisel-decorator, no content
+    };
+}
+
+/**
+ * Lets responses of endpoints that read only entities with this decorator be
+ * cached for `ttl` seconds, and then served stale for up to
+ * `staleWhileRevalidate` more seconds while a fresh one is computed.
+ */
+export function cache(
+    _hint: { ttl: number; staleWhileRevalidate?: number },
+) {
+    return <T>(_target: T) => {
+        // chisel-decorator, no content
+    };
+}
+
+/**
+ * How the table of an entity is partitioned, by `@partition`. Tables are only
+ * partitioned on Postgres.
+ */
+export type PartitionOptions =
+    | {
+        by: "time";
+        /** A `Date` field. */
+        field: string;
+        /** How much time each partition holds. */
+        interval: "day" | "month" | "year";
+        /**
+         * How many intervals before the current one are kept. Older rows
+         * are dropped. All of them are kept if unset.
+         */
+        retention?: number;
+    }
+    | {
+        by: "hash";
+        field: string;
+        /** How many partitions the rows are spread over. */
+        partitions: number;
+    };
+
+/**
+ * Partitions the table of an entity class by time or by the hash of a field,
+ * when applied to it as `@partition({ by: "time", field: "createdAt",
+ * interval: "month" })`. Partitions by time are created ahead of time and
+ * dropped once past retention by the server.
+ */
+export function partition(_options: PartitionOptions) {
+    return <T>(_target: T) => {
+        // chisel-decorator, no content
+    };
+}
+
+/**
+ * Moves the rows of an entity class to an archive table once they are
+ * `afterDays` days older than their `Date` field `field`, when applied to it
+ * as `@archive({ field: "createdAt", afterDays: 90 })`. Queries only read
+ * archived rows after `withArchived()`, but deletes apply to them too.
+ */
+export function archive(_options: { field: string; afterDays: number }) {
+    return <T>(_target: T) => {
+        // chisel-decorator, no content
+    };
+}
+
+/**
+ * Where the rows of an entity with `@connector` are fetched from. The rows
+ * are fetched again when they are read after `ttl` seconds, at most
+ * `rateLimit` times a minute.
+ */
+export type ConnectorOptions = {
+    /** An HTTP URL that responds to GET with JSON. */
+    url: string;
+    /**
+     * The dotted path of the array of rows in the response, if it isn't
+     * the response itself.
+     */
+    items?: string;
+    /**
+     * The dotted path in each item of the value of a field, by field name.
+     * Fields not in it are read from the item property of the same name.
+     */
+    fields?: Record<string, string>;
+    /** How many seconds the fetched rows are used for. 60 if unset. */
+    ttl?: number;
+    /** How many times a minute rows can be fetched. 60 if unset. */
+    rateLimit?: number;
+};
+
+/**
+ * Fetches the rows of an entity class from an external API, when applied to
+ * it as `@connector({ url: "https://example.com/items.json" })`. The rows
+ * are cached in its table and can be queried like any other, but not
+ * written.
+ */
+export function connector(_options: ConnectorOptions) {
+    return <T>(_target: T) => {
+        // chisel-decorator, no content
+    };
+}
+
+export type AutoCrudOptions = {
+    /** How GET lists entities, see `CRUDPagination`. */
+    pagination?: CRUDPagination;
+};
+
+/**
+ * Serves the REST methods of `ChiselEntity.crud()` for an entity class at
+ * `/<version>/<path>`, when applied to it as `@autoCrud("books")`, without a
+ * route file. The path is the lowercase class name if not given. Options,
+ * like `@autoCrud("books", { pagination: "envelope" })`, are passed to
+ * `crud()`.
+ */
+export function autoCrud(options?: AutoCrudOptions): <T>(target: T) => void;
+export function autoCrud(
+    path: string,
+    options?: AutoCrudOptions,
+): <T>(target: T) => void;
+export function autoCrud(..._args: unknown[]) {
+    return <T>(_target: T) => {
+        // chisel-decorator, no content
+    };
+}
+
 export const requestContext: {
     path: string;
     method: string;
     headers: Record<string, string>;
     apiVersion: string;
     userId?: string;
+    claims?: Record<string, unknown>;
+    tenant?: string;
+    /** Name of the API key the request was made with. */
+    apiKey?: string;
 } = {
     path: "",
     method: "",
@@ -1064,12 +2420,110 @@ export const requestContext: {
     apiVersion: "",
 };
 
-function ensureNotGet() {
+export function ensureNotGet() {
     if (requestContext.method === "GET") {
         throw new Error("Mutating the backend is not allowed during GET");
     }
 }
 
+/**
+ * Runs `fn` in a transaction nested in the one of the current request, for
+ * `ChiselRequest.transaction()`.
+ */
+export async function runInTransaction<T>(fn: () => Promise<T>): Promise<T> {
+    if (memoryStore !== undefined) {
+        const store = memoryStore;
+        const saved = [...store].map(([name, rows]) =>
+            [name, JSON.parse(JSON.stringify([...rows]))] as const
+        );
+        try {
+            return await fn();
+        } catch (e) {
+            store.clear();
+            for (const [name, rows] of saved) {
+                store.set(name, new Map(rows));
+            }
+            throw e;
+        }
+    }
+    await opAsync("op_chisel_begin_nested_transaction");
+    let result;
+    try {
+        result = await fn();
+    } catch (e) {
+        await opAsync("op_chisel_rollback_nested_transaction");
+        throw e;
+    }
+    await opAsync("op_chisel_commit_nested_transaction");
+    return result;
+}
+
+/**
+ * Transactional outbox for side effects that must happen if, and only if, the
+ * current request's writes are committed.
+ */
+export const outbox = {
+    /**
+     * Enqueues `payload`, serialized as JSON, for delivery to `target` after
+     * the current transaction commits. If the transaction is rolled back, the
+     * message is discarded along with the writes.
+     *
+     * `target` is either the URL of a webhook, which receives the payload in
+     * a POST request, or `kafka:<topic>` to produce the payload to a topic of
+     * the Kafka broker chiseld is configured with. Failed deliveries are
+     * retried, so the same message can be delivered more than once; the
+     * `ChiselStrike-Outbox-Message-Id` header identifies the message.
+     *
+     * @example
+     * ```typescript
+     * await order.save();
+     * await Chisel.outbox.enqueue("https://example.com/hooks/orders", { id: order.id });
+     * ```
+     */
+    async enqueue(target: string, payload: unknown): Promise<void> {
+        ensureNotGet();
+        if (memoryStore !== undefined) {
+            // Unit tests don't commit, so nothing would be delivered.
+            return;
+        }
+        await opAsync("op_chisel_outbox_enqueue", {
+            target,
+            payload: JSON.stringify(payload),
+        }, requestContext);
+    },
+};
+
+/**
+ * Enqueues a run of the task `taskName`, the module of that path in the
+ * `tasks` directory, with `payload` serialized as JSON. The task runs in the
+ * background once the current transaction commits, so the request doesn't
+ * wait for it. If the transaction is rolled back, the task is discarded
+ * along with the writes.
+ *
+ * A failed task is retried later, waiting longer after each failure, so
+ * the same task may run more than once.
+ *
+ * @example
+ * ```typescript
+ * await user.save();
+ * await Chisel.enqueue("send-welcome-email", { email: user.email });
+ * ```
+ */
+export async function enqueue(
+    taskName: string,
+    payload: unknown,
+): Promise<void> {
+    ensureNotGet();
+    if (memoryStore !== undefined) {
+        // Unit tests don't commit, so nothing would run.
+        return;
+    }
+    await opAsync("op_chisel_task_enqueue", {
+        name: taskName,
+        payload: JSON.stringify(payload ?? null),
+    }, requestContext);
+}
+
 export class AuthUser extends ChiselEntity {
     emailVerified?: string;
     name?: string;
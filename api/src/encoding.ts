@@ -0,0 +1,324 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+/**
+ * Binary response encodings: MessagePack (https://msgpack.org/) and
+ * CBOR (RFC 8949). Values are encoded with the same semantics as
+ * `JSON.stringify`: `toJSON()` is honored and `undefined` object properties
+ * are skipped, so a client gets the same data whatever format it asks for.
+ */
+
+export type ResponseEncoding = "json" | "msgpack" | "cbor";
+
+const mediaTypes: Record<string, ResponseEncoding> = {
+    "application/json": "json",
+    "application/msgpack": "msgpack",
+    "application/x-msgpack": "msgpack",
+    "application/vnd.msgpack": "msgpack",
+    "application/cbor": "cbor",
+};
+
+export const contentTypes: Record<ResponseEncoding, string> = {
+    json: "application/json",
+    msgpack: "application/msgpack",
+    cbor: "application/cbor",
+};
+
+/**
+ * Picks the response encoding preferred by an `Accept` header, falling back
+ * to JSON when the header is missing or names no supported format.
+ */
+export function negotiateEncoding(
+    accept: string | null | undefined,
+): ResponseEncoding {
+    let best: ResponseEncoding = "json";
+    let bestQuality = 0;
+    for (const range of (accept ?? "").split(",")) {
+        const [mediaType, ...params] = range.split(";").map((s) => s.trim());
+        const encoding = mediaTypes[mediaType.toLowerCase()];
+        if (encoding === undefined) {
+            continue;
+        }
+        let quality = 1;
+        for (const param of params) {
+            const [key, value] = param.split("=").map((s) => s.trim());
+            if (key === "q") {
+                quality = Number(value);
+            }
+        }
+        if (quality > bestQuality) {
+            best = encoding;
+            bestQuality = quality;
+        }
+    }
+    return best;
+}
+
+/** Growable byte buffer the encoders write into. */
+class Writer {
+    #buf = new Uint8Array(256);
+    #view = new DataView(this.#buf.buffer);
+    #len = 0;
+
+    #reserve(n: number) {
+        if (this.#len + n <= this.#buf.length) {
+            return;
+        }
+        let size = this.#buf.length * 2;
+        while (size < this.#len + n) {
+            size *= 2;
+        }
+        const buf = new Uint8Array(size);
+        buf.set(this.#buf.subarray(0, this.#len));
+        this.#buf = buf;
+        this.#view = new DataView(buf.buffer);
+    }
+
+    u8(v: number) {
+        this.#reserve(1);
+        this.#view.setUint8(this.#len, v);
+        this.#len += 1;
+    }
+
+    u16(v: number) {
+        this.#reserve(2);
+        this.#view.setUint16(this.#len, v);
+        this.#len += 2;
+    }
+
+    u32(v: number) {
+        this.#reserve(4);
+        this.#view.setUint32(this.#len, v);
+        this.#len += 4;
+    }
+
+    u64(v: number) {
+        this.#reserve(8);
+        this.#view.setBigUint64(this.#len, BigInt(v));
+        this.#len += 8;
+    }
+
+    i64(v: number) {
+        this.#reserve(8);
+        this.#view.setBigInt64(this.#len, BigInt(v));
+        this.#len += 8;
+    }
+
+    f64(v: number) {
+        this.#reserve(8);
+        this.#view.setFloat64(this.#len, v);
+        this.#len += 8;
+    }
+
+    bytes(v: Uint8Array) {
+        this.#reserve(v.length);
+        this.#buf.set(v, this.#len);
+        this.#len += v.length;
+    }
+
+    finish(): Uint8Array {
+        return this.#buf.slice(0, this.#len);
+    }
+}
+
+const textEncoder = new TextEncoder();
+
+/** Normalizes `value` the way `JSON.stringify` would before encoding it. */
+function toPlain(value: unknown): unknown {
+    if (
+        value !== null && typeof value === "object" &&
+        typeof (value as { toJSON?: unknown }).toJSON === "function"
+    ) {
+        return (value as { toJSON: () => unknown }).toJSON();
+    }
+    return value;
+}
+
+function objectEntries(value: object): [string, unknown][] {
+    return Object.entries(value).filter(([_, v]) =>
+        v !== undefined && typeof v !== "function"
+    );
+}
+
+function isInteger(v: number): boolean {
+    return Number.isSafeInteger(v) && !Object.is(v, -0);
+}
+
+function msgpackValue(w: Writer, value: unknown) {
+    value = toPlain(value);
+    if (value === null || value === undefined) {
+        w.u8(0xc0);
+    } else if (typeof value === "boolean") {
+        w.u8(value ? 0xc3 : 0xc2);
+    } else if (typeof value === "number") {
+        if (!isInteger(value)) {
+            w.u8(0xcb);
+            w.f64(value);
+        } else if (value >= 0) {
+            if (value < 0x80) {
+                w.u8(value);
+            } else if (value <= 0xff) {
+                w.u8(0xcc);
+                w.u8(value);
+            } else if (value <= 0xffff) {
+                w.u8(0xcd);
+                w.u16(value);
+            } else if (value <= 0xffffffff) {
+                w.u8(0xce);
+                w.u32(value);
+            } else {
+                w.u8(0xcf);
+                w.u64(value);
+            }
+        } else if (value >= -32) {
+            w.u8(value & 0xff);
+        } else if (value >= -0x80) {
+            w.u8(0xd0);
+            w.u8(value & 0xff);
+        } else if (value >= -0x8000) {
+            w.u8(0xd1);
+            w.u16(value & 0xffff);
+        } else if (value >= -0x80000000) {
+            w.u8(0xd2);
+            w.u32(value >>> 0);
+        } else {
+            w.u8(0xd3);
+            w.i64(value);
+        }
+    } else if (typeof value === "string") {
+        const bytes = textEncoder.encode(value);
+        msgpackHead(w, bytes.length, 0xa0, 32, [0xd9, 0xda, 0xdb]);
+        w.bytes(bytes);
+    } else if (value instanceof Uint8Array) {
+        msgpackHead(w, value.length, undefined, 0, [0xc4, 0xc5, 0xc6]);
+        w.bytes(value);
+    } else if (Array.isArray(value)) {
+        msgpackHead(w, value.length, 0x90, 16, [undefined, 0xdc, 0xdd]);
+        for (const element of value) {
+            msgpackValue(w, element);
+        }
+    } else if (typeof value === "object") {
+        const entries = objectEntries(value);
+        msgpackHead(w, entries.length, 0x80, 16, [undefined, 0xde, 0xdf]);
+        for (const [k, v] of entries) {
+            msgpackValue(w, k);
+            msgpackValue(w, v);
+        }
+    } else {
+        throw new Error(`cannot encode ${typeof value} as MessagePack`);
+    }
+}
+
+/**
+ * Writes the type and length of a MessagePack string, binary, array or map:
+ * the `fixed` form if `length` is below `fixedLimit`, otherwise the smallest
+ * of the 8, 16 and 32 bit `sized` forms that exists for the type.
+ */
+function msgpackHead(
+    w: Writer,
+    length: number,
+    fixed: number | undefined,
+    fixedLimit: number,
+    sized: (number | undefined)[],
+) {
+    const [tag8, tag16, tag32] = sized;
+    if (fixed !== undefined && length < fixedLimit) {
+        w.u8(fixed | length);
+    } else if (tag8 !== undefined && length <= 0xff) {
+        w.u8(tag8);
+        w.u8(length);
+    } else if (length <= 0xffff) {
+        w.u8(tag16!);
+        w.u16(length);
+    } else {
+        w.u8(tag32!);
+        w.u32(length);
+    }
+}
+
+/** Writes a CBOR data item head of `major` type with argument `arg`. */
+function cborHead(w: Writer, major: number, arg: number) {
+    const m = major << 5;
+    if (arg < 24) {
+        w.u8(m | arg);
+    } else if (arg <= 0xff) {
+        w.u8(m | 24);
+        w.u8(arg);
+    } else if (arg <= 0xffff) {
+        w.u8(m | 25);
+        w.u16(arg);
+    } else if (arg <= 0xffffffff) {
+        w.u8(m | 26);
+        w.u32(arg);
+    } else {
+        w.u8(m | 27);
+        w.u64(arg);
+    }
+}
+
+function cborValue(w: Writer, value: unknown) {
+    value = toPlain(value);
+    if (value === null || value === undefined) {
+        w.u8(0xf6);
+    } else if (typeof value === "boolean") {
+        w.u8(value ? 0xf5 : 0xf4);
+    } else if (typeof value === "number") {
+        if (!isInteger(value)) {
+            w.u8(0xfb);
+            w.f64(value);
+        } else if (value >= 0) {
+            cborHead(w, 0, value);
+        } else {
+            cborHead(w, 1, -1 - value);
+        }
+    } else if (typeof value === "string") {
+        const bytes = textEncoder.encode(value);
+        cborHead(w, 3, bytes.length);
+        w.bytes(bytes);
+    } else if (value instanceof Uint8Array) {
+        cborHead(w, 2, value.length);
+        w.bytes(value);
+    } else if (Array.isArray(value)) {
+        cborHead(w, 4, value.length);
+        for (const element of value) {
+            cborValue(w, element);
+        }
+    } else if (typeof value === "object") {
+        const entries = objectEntries(value);
+        cborHead(w, 5, entries.length);
+        for (const [k, v] of entries) {
+            cborValue(w, k);
+            cborValue(w, v);
+        }
+    } else {
+        throw new Error(`cannot encode ${typeof value} as CBOR`);
+    }
+}
+
+/** Encodes `value` as MessagePack. */
+export function encodeMessagePack(value: unknown): Uint8Array {
+    const w = new Writer();
+    msgpackValue(w, value);
+    return w.finish();
+}
+
+/** Encodes `value` as CBOR. */
+export function encodeCbor(value: unknown): Uint8Array {
+    const w = new Writer();
+    cborValue(w, value);
+    return w.finish();
+}
+
+/** Encodes `value` in the given encoding. */
+export function encodeBody(
+    value: unknown,
+    encoding: ResponseEncoding,
+): string | Uint8Array {
+    switch (encoding) {
+        case "json":
+            return JSON.stringify(value, null, 2);
+        case "msgpack":
+            return encodeMessagePack(value);
+        case "cbor":
+            return encodeCbor(value);
+    }
+}
@@ -100,15 +100,23 @@ type Endpoint = { path: string; apiVersion: string; version: number };
 
 type EventHandler = { path: string; apiVersion: string; version: number };
 
+type Job = { path: string; apiVersion: string };
+
+type Task = { path: string; apiVersion: string };
+
 // FIXME: rename to importModules()
 export async function importEndpoints(
     endpoints: [Endpoint],
     eventHandlers: [EventHandler],
+    jobs: [Job],
+    tasks: [Task],
 ) {
-    await toWorker({
+    return await toWorker({
         cmd: "importEndpoints",
         endpoints,
         eventHandlers,
+        jobs,
+        tasks,
     });
 }
 
@@ -126,6 +134,33 @@ export async function activateEventHandler(path: string) {
     });
 }
 
+export async function activateJob(path: string) {
+    return await toWorker({
+        cmd: "activateJob",
+        path,
+    });
+}
+
+export async function activateTask(path: string) {
+    await toWorker({
+        cmd: "activateTask",
+        path,
+    });
+}
+
+export async function runUnitTests(
+    apiVersion: string,
+    tests: string[],
+    coverage: boolean,
+) {
+    return await toWorker({
+        cmd: "runUnitTests",
+        apiVersion,
+        tests,
+        coverage,
+    });
+}
+
 export function endOfRequest(id: number) {
     endpointWorker.postMessage({ cmd: "endOfRequest", id });
     delete bodyParts[id];
@@ -214,3 +249,59 @@ export async function callEventHandler(
     }
     clear();
 }
+
+export async function callJobHandler(path: string, apiVersion: string) {
+    try {
+        await sendMsg({
+            cmd: "callJobHandler",
+            path,
+            apiVersion,
+        });
+    } catch (e) {
+        clear();
+        throw e;
+    }
+    clear();
+}
+
+export async function callTaskHandler(
+    path: string,
+    apiVersion: string,
+    payload: string,
+) {
+    try {
+        await sendMsg({
+            cmd: "callTaskHandler",
+            path,
+            apiVersion,
+            payload,
+        });
+    } catch (e) {
+        clear();
+        throw e;
+    }
+    clear();
+}
+
+export async function callSocketHandler(
+    path: string,
+    apiVersion: string,
+    socketId: string,
+    event: string,
+    data: string | ArrayBuffer | null,
+) {
+    try {
+        await sendMsg({
+            cmd: "callSocketHandler",
+            path,
+            apiVersion,
+            socketId,
+            event,
+            data,
+        });
+    } catch (e) {
+        clear();
+        throw e;
+    }
+    clear();
+}
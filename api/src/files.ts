@@ -0,0 +1,76 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+import { ensureNotGet, ensureNotInMemory } from "./datastore.ts";
+import { opAsync } from "./utils.ts";
+
+/**
+ * A file in the blob store of the server, which chiseld is configured with
+ * `--blob-store`. Entities hold files in fields of this type: the metadata
+ * is stored with the entity, and the content in the blob store, to be read
+ * with `readFile()`.
+ *
+ * @example
+ * ```typescript
+ * export class Profile extends ChiselEntity {
+ *     name: string;
+ *     avatar?: ChiselFile;
+ * }
+ * ```
+ */
+export type ChiselFile = {
+    /** Where the content is in the blob store. */
+    key: string;
+    name: string;
+    /** The media type of the content, like `image/png`. */
+    type: string;
+    /** The size of the content, in bytes. */
+    size: number;
+};
+
+/** A file uploaded in a multipart request, from `ChiselRequest.files()`. */
+export type UploadedFile = ChiselFile & {
+    /** The name of the form field holding the file. */
+    field: string;
+};
+
+/**
+ * Stores `content` in the blob store, as a file named `name` of media type
+ * `type`.
+ *
+ * Content is stored right away, even if the current transaction is rolled
+ * back later, and is kept until `deleteFile()` deletes it.
+ */
+export async function storeFile(
+    content: Uint8Array | string | Blob,
+    name: string,
+    type = "application/octet-stream",
+): Promise<ChiselFile> {
+    ensureNotGet();
+    ensureNotInMemory("Files");
+    if (content instanceof Blob) {
+        content = new Uint8Array(await content.arrayBuffer());
+    } else if (typeof content === "string") {
+        content = new TextEncoder().encode(content);
+    }
+    return await opAsync("op_chisel_file_store", {
+        name,
+        type,
+        content,
+    }) as ChiselFile;
+}
+
+/** Reads the content of `file` from the blob store. */
+export async function readFile(file: ChiselFile): Promise<Uint8Array> {
+    ensureNotInMemory("Files");
+    return await opAsync("op_chisel_file_read", file.key) as Uint8Array;
+}
+
+/**
+ * Deletes the content of `file` from the blob store. Deleting an entity, or
+ * changing its file fields, doesn't delete the content of its files.
+ */
+export async function deleteFile(file: ChiselFile): Promise<void> {
+    ensureNotGet();
+    ensureNotInMemory("Files");
+    await opAsync("op_chisel_file_delete", file.key);
+}
@@ -26,10 +26,15 @@ lazy_static! {
         source_js!("api"),
         source_js!("crud"),
         source_js!("datastore"),
+        source_js!("encoding"),
         source_js!("endpoint"),
         source_js!("event"),
+        source_js!("files"),
+        source_js!("login"),
         source_js!("request"),
+        source_js!("testing"),
         source_js!("utils"),
+        source_js!("websocket"),
         source_js!("worker"),
     ]
     .into_iter()
@@ -38,10 +43,15 @@ lazy_static! {
         source_d_ts!("api"),
         source_d_ts!("crud"),
         source_d_ts!("datastore"),
+        source_d_ts!("encoding"),
         source_d_ts!("endpoint"),
         source_d_ts!("event"),
+        source_d_ts!("files"),
+        source_d_ts!("login"),
         source_d_ts!("request"),
+        source_d_ts!("testing"),
         source_d_ts!("utils"),
+        source_d_ts!("websocket"),
         source_d_ts!("worker"),
     ]
     .into_iter()
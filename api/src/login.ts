@@ -0,0 +1,413 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+import { AuthUser, ChiselEntity, requestContext } from "./datastore.ts";
+import type { ChiselRequest } from "./request.ts";
+import { getSecret, responseFromJson } from "./utils.ts";
+
+class AuthAccount extends ChiselEntity {
+    providerAccountId = "";
+    userId = "";
+    provider = "";
+    type = "";
+    access_token?: string;
+    token_type?: string;
+    id_token?: string;
+    refresh_token?: string;
+    scope?: string;
+    expires_at?: number;
+}
+
+class AuthSession extends ChiselEntity {
+    sessionToken = "";
+    userId = "";
+    expires = "";
+}
+
+/**
+ * How to log in with a provider, from the `CHISELD_OIDC_PROVIDERS` secret.
+ * The endpoints of an OpenID Connect provider are discovered from its
+ * `issuer`, unless they are given.
+ */
+type ProviderConfig = {
+    clientId?: string;
+    clientSecret?: string;
+    issuer?: string;
+    authorizationUrl?: string;
+    tokenUrl?: string;
+    userinfoUrl?: string;
+    scope?: string;
+};
+
+type Provider = {
+    clientId: string;
+    clientSecret: string;
+    authorizationUrl: string;
+    tokenUrl: string;
+    userinfoUrl: string;
+    scope: string;
+};
+
+const PROVIDERS_SECRET = "CHISELD_OIDC_PROVIDERS";
+
+// Providers that only need a client ID and secret.
+const PRESETS: Record<string, ProviderConfig> = {
+    github: {
+        authorizationUrl: "https://github.com/login/oauth/authorize",
+        tokenUrl: "https://github.com/login/oauth/access_token",
+        userinfoUrl: "https://api.github.com/user",
+        scope: "read:user user:email",
+    },
+    google: { issuer: "https://accounts.google.com" },
+};
+
+// Read by the `session` auth provider of the server, like the cookie of a
+// NextAuth session.
+const SESSION_COOKIE = "next-auth.session-token";
+const SESSION_DAYS = 30;
+// State of a login in progress: the `state` parameter, the PKCE verifier
+// and where to go once logged in, separated by dots.
+const STATE_COOKIE = "chisel-auth-state";
+const STATE_SECONDS = 600;
+
+/** A login that failed, answered with `status`. */
+class LoginError extends Error {
+    constructor(public status: number, message: string) {
+        super(message);
+    }
+}
+
+async function provider(name: string): Promise<Provider> {
+    const secret = getSecret(PROVIDERS_SECRET) as
+        | Record<string, ProviderConfig>
+        | undefined;
+    const config = { ...PRESETS[name], ...secret?.[name] };
+    if (config.clientId === undefined || config.clientSecret === undefined) {
+        throw new LoginError(
+            404,
+            `login provider ${name} has no clientId and clientSecret in ${PROVIDERS_SECRET}`,
+        );
+    }
+    if (
+        config.issuer !== undefined &&
+        (config.authorizationUrl === undefined ||
+            config.tokenUrl === undefined ||
+            config.userinfoUrl === undefined)
+    ) {
+        const issuer = config.issuer.replace(/\/$/, "");
+        const res = await fetch(`${issuer}/.well-known/openid-configuration`);
+        if (!res.ok) {
+            throw new LoginError(
+                502,
+                `could not discover login provider ${name}: ${res.status}`,
+            );
+        }
+        const discovery = await res.json();
+        config.authorizationUrl ??= discovery.authorization_endpoint;
+        config.tokenUrl ??= discovery.token_endpoint;
+        config.userinfoUrl ??= discovery.userinfo_endpoint;
+    }
+    const { authorizationUrl, tokenUrl, userinfoUrl } = config;
+    if (
+        authorizationUrl === undefined || tokenUrl === undefined ||
+        userinfoUrl === undefined
+    ) {
+        throw new LoginError(
+            404,
+            `login provider ${name} needs an issuer, or an authorizationUrl, tokenUrl and userinfoUrl`,
+        );
+    }
+    return {
+        clientId: config.clientId,
+        clientSecret: config.clientSecret,
+        authorizationUrl,
+        tokenUrl,
+        userinfoUrl,
+        scope: config.scope ?? "openid email profile",
+    };
+}
+
+function base64url(bytes: Uint8Array): string {
+    return btoa(String.fromCharCode(...bytes))
+        .replace(/\+/g, "-")
+        .replace(/\//g, "_")
+        .replace(/=+$/, "");
+}
+
+function randomToken(): string {
+    return base64url(crypto.getRandomValues(new Uint8Array(32)));
+}
+
+function cookie(req: Request, name: string): string | undefined {
+    for (const part of (req.headers.get("cookie") ?? "").split(";")) {
+        const [key, ...value] = part.trim().split("=");
+        if (key === name) {
+            return value.join("=");
+        }
+    }
+    return undefined;
+}
+
+function setCookie(req: Request, name: string, value: string, attrs: string) {
+    const secure = new URL(req.url).protocol === "https:" ? "; Secure" : "";
+    return `${name}=${value}; ${attrs}; HttpOnly; SameSite=Lax${secure}`;
+}
+
+function redirectTo(location: string, cookie: string): Response {
+    return new Response(null, {
+        status: 302,
+        headers: { location, "set-cookie": cookie },
+    });
+}
+
+function loginPath(req: ChiselRequest): string {
+    return `/${req.version}${req.endpoint}`;
+}
+
+function callbackUrl(req: ChiselRequest, name: string): string {
+    return `${new URL(req.url).origin}${loginPath(req)}/callback/${name}`;
+}
+
+// Only paths of this server are redirected to, so that a link to the
+// login can't send the user elsewhere.
+function checkRedirect(redirect: string | undefined): string {
+    if (redirect === undefined) {
+        return "";
+    }
+    if (!redirect.startsWith("/") || redirect.startsWith("//")) {
+        throw new LoginError(400, "redirect must be a path of this server");
+    }
+    return redirect;
+}
+
+async function login(req: ChiselRequest, name: string): Promise<Response> {
+    const { authorizationUrl, clientId, scope } = await provider(name);
+    const redirect = checkRedirect(req.query.get("redirect"));
+    const state = randomToken();
+    const verifier = randomToken();
+    const challenge = await crypto.subtle.digest(
+        "SHA-256",
+        new TextEncoder().encode(verifier),
+    );
+    const url = new URL(authorizationUrl);
+    url.searchParams.set("response_type", "code");
+    url.searchParams.set("client_id", clientId);
+    url.searchParams.set("redirect_uri", callbackUrl(req, name));
+    url.searchParams.set("scope", scope);
+    url.searchParams.set("state", state);
+    url.searchParams.set(
+        "code_challenge",
+        base64url(new Uint8Array(challenge)),
+    );
+    url.searchParams.set("code_challenge_method", "S256");
+    const value = `${state}.${verifier}.${encodeURIComponent(redirect)}`;
+    return redirectTo(
+        url.toString(),
+        setCookie(
+            req,
+            STATE_COOKIE,
+            value,
+            `Path=${loginPath(req)}; Max-Age=${STATE_SECONDS}`,
+        ),
+    );
+}
+
+function stringOf(value: unknown): string | undefined {
+    return typeof value === "string" && value !== "" ? value : undefined;
+}
+
+/**
+ * The user that logged in as `profile` with `provider`: the user of its
+ * account if they logged in before, or else the user with the same verified
+ * email, or else a new user. The account keeps the latest `tokens`.
+ */
+async function linkUser(
+    provider: string,
+    profile: Record<string, unknown>,
+    tokens: Record<string, unknown>,
+): Promise<AuthUser> {
+    const providerAccountId = String(profile.sub ?? profile.id ?? "");
+    if (providerAccountId === "") {
+        throw new LoginError(502, `${provider} didn't identify the user`);
+    }
+    let account = await AuthAccount.findOne({ provider, providerAccountId });
+    let user = account === undefined
+        ? undefined
+        : await AuthUser.findOne({ id: account.userId });
+    const email = stringOf(profile.email);
+    // Linking by an unverified email would let anyone claim the user.
+    const isVerified = email !== undefined && profile.email_verified === true;
+    if (user === undefined && isVerified) {
+        user = await AuthUser.findOne({ email });
+    }
+    user ??= AuthUser.build({});
+    user.email ??= email;
+    user.name ??= stringOf(profile.name) ?? stringOf(profile.login);
+    user.image ??= stringOf(profile.picture) ?? stringOf(profile.avatar_url);
+    if (isVerified && user.email === email) {
+        user.emailVerified ??= new Date().toISOString();
+    }
+    await user.save();
+
+    account ??= AuthAccount.build({
+        provider,
+        providerAccountId,
+        type: "oauth",
+    });
+    account.userId = user.id!;
+    account.access_token = stringOf(tokens.access_token);
+    account.token_type = stringOf(tokens.token_type);
+    account.id_token = stringOf(tokens.id_token);
+    account.refresh_token = stringOf(tokens.refresh_token);
+    account.scope = stringOf(tokens.scope);
+    account.expires_at = typeof tokens.expires_in === "number"
+        ? Math.floor(Date.now() / 1000) + tokens.expires_in
+        : undefined;
+    await account.save();
+    return user;
+}
+
+async function callback(req: ChiselRequest, name: string): Promise<Response> {
+    const { tokenUrl, userinfoUrl, clientId, clientSecret } = await provider(
+        name,
+    );
+    const error = req.query.get("error");
+    if (error !== undefined) {
+        throw new LoginError(401, `${name} refused the login: ${error}`);
+    }
+    const [state, verifier, redirect] = (cookie(req, STATE_COOKIE) ?? "")
+        .split(".");
+    const code = req.query.get("code");
+    if (
+        code === undefined || verifier === undefined ||
+        req.query.get("state") !== state
+    ) {
+        throw new LoginError(400, "invalid login state, please log in again");
+    }
+
+    const tokenRes = await fetch(tokenUrl, {
+        method: "POST",
+        headers: {
+            "content-type": "application/x-www-form-urlencoded",
+            accept: "application/json",
+        },
+        body: new URLSearchParams({
+            grant_type: "authorization_code",
+            code,
+            redirect_uri: callbackUrl(req, name),
+            client_id: clientId,
+            client_secret: clientSecret,
+            code_verifier: verifier,
+        }),
+    });
+    const tokens = await tokenRes.json();
+    if (!tokenRes.ok || typeof tokens.access_token !== "string") {
+        throw new LoginError(401, `${name} didn't issue an access token`);
+    }
+    const profileRes = await fetch(userinfoUrl, {
+        headers: {
+            authorization: `Bearer ${tokens.access_token}`,
+            accept: "application/json",
+        },
+    });
+    if (!profileRes.ok) {
+        throw new LoginError(502, `${name} didn't return the user's profile`);
+    }
+    // The provider redirects back with a GET, which logs the user in all
+    // the same.
+    requestContext.method = "POST";
+    const user = await linkUser(name, await profileRes.json(), tokens);
+
+    const sessionToken = randomToken();
+    const expires = new Date(Date.now() + SESSION_DAYS * 24 * 3600 * 1000);
+    await AuthSession.create({
+        sessionToken,
+        userId: user.id,
+        expires: expires.toISOString(),
+    });
+    const session = setCookie(
+        req,
+        SESSION_COOKIE,
+        sessionToken,
+        `Path=/; Expires=${expires.toUTCString()}`,
+    );
+    if (redirect !== undefined && redirect !== "") {
+        return redirectTo(decodeURIComponent(redirect), session);
+    }
+    const res = responseFromJson({ user });
+    res.headers.set("set-cookie", session);
+    return res;
+}
+
+async function logout(req: ChiselRequest): Promise<Response> {
+    const redirect = checkRedirect(req.query.get("redirect"));
+    const sessionToken = cookie(req, SESSION_COOKIE);
+    if (sessionToken !== undefined) {
+        await AuthSession.delete({ sessionToken });
+    }
+    const cleared = setCookie(req, SESSION_COOKIE, "", "Path=/; Max-Age=0");
+    if (redirect !== "") {
+        return redirectTo(redirect, cleared);
+    }
+    return new Response(null, {
+        status: 204,
+        headers: { "set-cookie": cleared },
+    });
+}
+
+const METHODS: Record<string, string> = {
+    providers: "GET",
+    session: "GET",
+    logout: "POST",
+    login: "GET",
+    callback: "GET",
+};
+
+/**
+ * Handles the login route that the server generates at
+ * `/<version>/__auth` when the `login` key of Chisel.toml lists
+ * `providers`. For each of them, `GET login/<provider>` redirects to the
+ * provider, which redirects back to `GET callback/<provider>`. That creates
+ * or links the `AuthUser`, and starts a session, whose cookie the `session`
+ * auth provider accepts. `POST logout` ends the session, `GET session`
+ * returns the user logged in, and `GET providers` the providers.
+ */
+export function loginRoutes(providers: string[]) {
+    return async function (req: ChiselRequest): Promise<Response> {
+        const [action = "providers", name, ...rest] = req.pathComponents();
+        const method = METHODS[action];
+        const isPerProvider = action === "login" || action === "callback";
+        if (
+            method === undefined || isPerProvider !== (name !== undefined) ||
+            rest.length > 0
+        ) {
+            return new Response("Not found", { status: 404 });
+        }
+        if (req.method !== method) {
+            return new Response(`Method ${req.method} not allowed`, {
+                status: 405,
+                headers: { allow: method },
+            });
+        }
+        try {
+            switch (action) {
+                case "providers":
+                    return responseFromJson({ providers });
+                case "session":
+                    return responseFromJson({ user: req.user ?? null });
+                case "logout":
+                    return await logout(req);
+            }
+            if (!providers.includes(name!)) {
+                throw new LoginError(404, `unknown login provider ${name}`);
+            }
+            return action === "login"
+                ? await login(req, name!)
+                : await callback(req, name!);
+        } catch (e) {
+            if (e instanceof LoginError) {
+                return new Response(e.message, { status: e.status });
+            }
+            throw e;
+        }
+    };
+}
@@ -1,6 +1,9 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
+import { runInTransaction } from "./datastore.ts";
 import type { AuthUser } from "./datastore.ts";
+import { storeFile } from "./files.ts";
+import type { UploadedFile } from "./files.ts";
 
 /** Extends the Request class adding ChiselStrike-specific helpers
  *
@@ -8,6 +11,7 @@ import type { AuthUser } from "./datastore.ts";
  * @property {string} endpoint - The current endpoint being called.
  * @property {string} pathParams - This is essentially the URL's path, but with everything before the endpoint name removed.
  * @property {AuthUser} user - The currently logged in user. `undefined` if there isn't one.
+ * @property {Record<string, unknown>} claims - Claims about the user made by the credentials of the request, such as the payload of a JWT.
  * @property {Query} query - Helper structure containing parsed query string from the URL.
  */
 export class ChiselRequest extends Request {
@@ -20,6 +24,7 @@ export class ChiselRequest extends Request {
         public endpoint: string,
         public pathParams: string,
         public user?: AuthUser | undefined,
+        public claims: Record<string, unknown> = {},
     ) {
         super(input, init);
         this.query = new Query(new URL(this.url).searchParams);
@@ -38,8 +43,79 @@ export class ChiselRequest extends Request {
     pathComponents(): string[] {
         return this.pathParams.split("/").filter((n) => n.length != 0);
     }
+
+    /**
+     * Runs `fn` so that the writes it makes are kept, or rolled back, all
+     * together. If `fn` throws, its writes are rolled back and the error is
+     * rethrown, while the other writes of the request are kept unless the
+     * request fails too. Calls can be nested.
+     *
+     * @example
+     * ```typescript
+     * export default async function (req: ChiselRequest) {
+     *     try {
+     *         await req.transaction(async () => {
+     *             await Account.update(from.id, { balance: from.balance - 10 });
+     *             await Account.update(to.id, { balance: to.balance + 10 });
+     *         });
+     *     } catch (e) {
+     *         return new Response("transfer failed", { status: 409 });
+     *     }
+     *     return "transferred";
+     * }
+     * ```
+     *
+     * @returns What `fn` returns.
+     */
+    async transaction<T>(fn: () => Promise<T>): Promise<T> {
+        return await runInTransaction(fn);
+    }
+
+    /**
+     * Stores the files uploaded in the `multipart/form-data` body of the
+     * request in the blob store, and returns them in the order of the form.
+     * Like `formData()`, this reads the body of the request, so it can only
+     * be called once, and the other fields of the form are not returned.
+     *
+     * @example
+     * ```typescript
+     * export default async function (req: ChiselRequest) {
+     *     const avatar = (await req.files()).find((f) => f.field == "avatar");
+     *     const profile = await Profile.create({ name: "Al", avatar });
+     *     return profile;
+     * }
+     * ```
+     */
+    async files(): Promise<UploadedFile[]> {
+        const form = await this.formData();
+        const files = [];
+        for (const [field, value] of form.entries()) {
+            if (typeof value === "string") {
+                continue;
+            }
+            const type = value.type || undefined;
+            const file = await storeFile(value, value.name, type);
+            files.push({ ...file, field });
+        }
+        return files;
+    }
 }
 
+/** The user identified by an `authenticate` function. */
+export type Principal = {
+    userId: string;
+    claims?: Record<string, unknown>;
+};
+
+/**
+ * Identifies the user of a request, or returns `undefined` for anonymous
+ * requests. Route modules export it as `authenticate`, to be used with the
+ * `function:<route>` auth provider.
+ */
+export type Authenticator = (
+    req: Request,
+) => Principal | undefined | Promise<Principal | undefined>;
+
 /** Query is a helper class used to access query parameters parsed from the URL.
  */
 export class Query {
@@ -0,0 +1,414 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+// Runtime of `chisel test --unit`, which runs the tests of an application
+// without a database: entities are kept in an in-memory store that starts
+// empty for every test, and fetch() is answered by a mock or replayed from a
+// recording.
+
+import { AuthUser, requestContext, useMemoryStore } from "./datastore.ts";
+import { ChiselRequest } from "./request.ts";
+import { opAsync } from "./utils.ts";
+
+type TestFunction = () => unknown | Promise<unknown>;
+
+// Tests registered by the module being imported.
+let registered: { name: string; fn: TestFunction }[] = [];
+
+/**
+ * Registers a unit test, to be run by `chisel test --unit`.
+ *
+ * @example
+ * ```typescript
+ * import { test, testRequest } from "@chiselstrike/api";
+ * import handler from "../routes/greet.ts";
+ *
+ * test("greets by name", async () => {
+ *     const res = await handler(testRequest("/greet?name=Al"));
+ *     if (res !== "Hello, Al") {
+ *         throw new Error(`unexpected greeting ${res}`);
+ *     }
+ * });
+ * ```
+ */
+export function test(name: string, fn: TestFunction) {
+    registered.push({ name, fn });
+}
+
+type FetchHandler = (req: Request) => Response | Promise<Response>;
+
+let fetchHandler: FetchHandler | undefined;
+
+/**
+ * Answers the fetch() calls of the current unit test with `handler`.
+ * Without one, fetch() replays the calls recorded by
+ * `chisel test --unit --record`, and throws for those that weren't.
+ */
+export function mockFetch(handler: FetchHandler) {
+    fetchHandler = handler;
+}
+
+/** A fetch() call recorded by `chisel test --unit --record`. */
+type Interaction = {
+    request: { method: string; url: string; body: string };
+    response: { status: number; headers: [string, string][]; body: string };
+};
+
+/** The fetch() calls of the tests of a module, by test name. */
+type Cassette = Record<string, Interaction[]>;
+
+let realFetch: typeof fetch = globalThis.fetch;
+// The calls made by the current test, when recording.
+let recording: Interaction[] | undefined;
+// The recorded calls of the current test that haven't been replayed yet.
+let replaying: Interaction[] = [];
+
+function recordedResponse({ response }: Interaction): Response {
+    // Responses like 204 can't have a body, not even an empty one.
+    return new Response(response.body === "" ? null : response.body, {
+        status: response.status,
+        headers: response.headers,
+    });
+}
+
+async function recordFetch(req: Request): Promise<Response> {
+    const body = await req.clone().text();
+    const res = await realFetch(req);
+    const interaction = {
+        request: { method: req.method, url: req.url, body },
+        response: {
+            status: res.status,
+            headers: [...res.headers],
+            body: await res.text(),
+        },
+    };
+    recording!.push(interaction);
+    return recordedResponse(interaction);
+}
+
+// Replays the first recorded call with the same method, URL and body.
+async function replayFetch(req: Request): Promise<Response> {
+    const body = await req.text();
+    const i = replaying.findIndex(({ request }) =>
+        request.method === req.method && request.url === req.url &&
+        request.body === body
+    );
+    if (i === -1) {
+        throw new Error(
+            `fetch(${req.url}) in a unit test; answer it with mockFetch() or record it with \`chisel test --unit --record\``,
+        );
+    }
+    const [interaction] = replaying.splice(i, 1);
+    return recordedResponse(interaction);
+}
+
+async function mockedFetch(
+    input: string | URL | Request,
+    init?: RequestInit,
+): Promise<Response> {
+    const req = new Request(input, init);
+    if (fetchHandler !== undefined) {
+        return await fetchHandler(req);
+    }
+    if (recording !== undefined) {
+        return await recordFetch(req);
+    }
+    return await replayFetch(req);
+}
+
+// The time unit tests start at, so that they don't depend on when they run.
+const START_TIME = Date.UTC(2022, 0, 1);
+
+const RealDate = Date;
+const realTimers = {
+    setTimeout: globalThis.setTimeout,
+    clearTimeout: globalThis.clearTimeout,
+    setInterval: globalThis.setInterval,
+    clearInterval: globalThis.clearInterval,
+};
+
+type Timer = {
+    id: number;
+    due: number;
+    interval?: number;
+    callback: () => void;
+};
+
+let now = START_TIME;
+let timers: Timer[] = [];
+let nextTimerId = 1;
+
+function addTimer(delay: number, callback: () => void, repeat: boolean) {
+    const id = nextTimerId++;
+    delay = Math.max(0, delay || 0);
+    // Like the real ones, intervals fire at most once per millisecond.
+    const interval = repeat ? Math.max(1, delay) : undefined;
+    timers.push({ id, due: now + delay, interval, callback });
+    return id;
+}
+
+function clearTimer(id?: number) {
+    timers = timers.filter((timer) => timer.id !== id);
+}
+
+// The first timer due by `end`. Ties go to the timer created first.
+function nextDueTimer(end: number): Timer | undefined {
+    let next;
+    for (const timer of timers) {
+        if (
+            timer.due <= end &&
+            (next === undefined || timer.due < next.due)
+        ) {
+            next = timer;
+        }
+    }
+    return next;
+}
+
+/**
+ * Clock of unit tests. While a test runs, `Date` and the timer functions
+ * (`setTimeout()`, `setInterval()`...) follow this clock instead of the real
+ * one: it only moves when the test moves it, and timers fire as it does.
+ * Every test starts at 2022-01-01T00:00:00Z with no pending timers.
+ *
+ * @example
+ * ```typescript
+ * import { test, testClock } from "@chiselstrike/api";
+ * import { Session } from "../models/session.ts";
+ *
+ * const DAY = 24 * 60 * 60 * 1000;
+ *
+ * test("sessions expire after a day", async () => {
+ *     const session = await Session.create({ expiresAt: Date.now() + DAY });
+ *     await testClock.advance(DAY);
+ *     if (!session.isExpired()) {
+ *         throw new Error("the session didn't expire");
+ *     }
+ * });
+ * ```
+ */
+export const testClock = {
+    /** Current time of the clock, in milliseconds since the epoch. */
+    now(): number {
+        return now;
+    },
+
+    /**
+     * Moves the clock `ms` milliseconds forward, firing the timers that are
+     * due in the meantime, in order. Promise continuations run after each
+     * timer fires.
+     */
+    async advance(ms: number) {
+        if (ms < 0) {
+            throw new Error("the test clock can't go back in time");
+        }
+        const end = now + ms;
+        for (;;) {
+            const timer = nextDueTimer(end);
+            if (timer === undefined) {
+                break;
+            }
+            now = timer.due;
+            if (timer.interval === undefined) {
+                clearTimer(timer.id);
+            } else {
+                timer.due += timer.interval;
+            }
+            timer.callback();
+            await new Promise((resolve) => realTimers.setTimeout(resolve, 0));
+        }
+        now = end;
+    },
+
+    /** Moves the clock forward to `time`, like `advance()`. */
+    async set(time: Date | number) {
+        await testClock.advance(new RealDate(time).getTime() - now);
+    },
+};
+
+function resetTestClock() {
+    now = START_TIME;
+    timers = [];
+}
+
+class TestDate extends RealDate {
+    constructor(...args: unknown[]) {
+        if (args.length === 0) {
+            super(now);
+        } else {
+            super(...(args as [string]));
+        }
+    }
+
+    static now(): number {
+        return now;
+    }
+}
+
+type TimerHandler = (...args: unknown[]) => void;
+
+function useTestClock() {
+    globalThis.Date = TestDate as DateConstructor;
+    Object.assign(globalThis, {
+        setTimeout: (fn: TimerHandler, delay?: number, ...args: unknown[]) =>
+            addTimer(delay ?? 0, () => fn(...args), false),
+        setInterval: (fn: TimerHandler, delay?: number, ...args: unknown[]) =>
+            addTimer(delay ?? 0, () => fn(...args), true),
+        clearTimeout: clearTimer,
+        clearInterval: clearTimer,
+    });
+}
+
+function useRealClock() {
+    globalThis.Date = RealDate;
+    Object.assign(globalThis, realTimers);
+}
+
+// How long a test can take, in real time, before it fails. A test waiting for
+// a timer that the test clock never reaches would otherwise never end.
+const TEST_TIMEOUT_MS = 5000;
+
+async function runWithTimeout(fn: TestFunction) {
+    let timer;
+    const timeout = new Promise((_, reject) => {
+        timer = realTimers.setTimeout(() => {
+            reject(
+                new Error(
+                    `test timed out after ${TEST_TIMEOUT_MS}ms; move testClock forward if it waits for a timer`,
+                ),
+            );
+        }, TEST_TIMEOUT_MS);
+    });
+    try {
+        await Promise.race([fn(), timeout]);
+    } finally {
+        realTimers.clearTimeout(timer);
+    }
+}
+
+/** Options of `testRequest()`, besides those of the Request constructor. */
+export type TestRequestInit = RequestInit & {
+    pathParams?: string;
+    user?: AuthUser;
+    claims?: Record<string, unknown>;
+};
+
+/** Builds a request to `url` to pass to a route handler in a unit test. */
+export function testRequest(
+    url: string,
+    init: TestRequestInit = {},
+): ChiselRequest {
+    const { pathParams, user, claims, ...requestInit } = init;
+    return new ChiselRequest(
+        new URL(url, "http://localhost").href,
+        requestInit,
+        requestContext.apiVersion,
+        requestContext.path,
+        pathParams ?? "",
+        user,
+        claims ?? {},
+    );
+}
+
+export type UnitTestResult = {
+    file: string;
+    name: string;
+    error?: string;
+    durationMs: number;
+};
+
+/** V8 coverage of a module the tests loaded, by path in the pseudo version. */
+export type ScriptCoverage = {
+    path: string;
+    functions: unknown[];
+};
+
+export type UnitTestRun = {
+    results: UnitTestResult[];
+    coverage?: ScriptCoverage[];
+    cassettes: Record<string, string>;
+};
+
+/**
+ * Imports each test module in `files`, which are relative to the pseudo
+ * version `apiVersion` they were loaded in, and runs the tests they register.
+ * With `coverage`, also returns the code coverage of the modules they loaded.
+ *
+ * The fetch() calls that no mock answers are replayed from `cassettes`, the
+ * JSON of the calls recorded by each module's tests. With `record`, they are
+ * made for real instead, and the returned cassettes have them.
+ */
+export async function runUnitTests(
+    apiVersion: string,
+    files: string[],
+    coverage: boolean,
+    cassettes: Record<string, string>,
+    record: boolean,
+): Promise<UnitTestRun> {
+    if (coverage) {
+        await opAsync("op_chisel_coverage_start");
+    }
+    const savedContext = { ...requestContext };
+    const savedFetch = globalThis.fetch;
+    realFetch = savedFetch;
+    globalThis.fetch = mockedFetch;
+    useTestClock();
+    const results = [];
+    const recorded: Record<string, string> = {};
+    let scripts;
+    try {
+        for (const file of files) {
+            registered = [];
+            await import(`file:///${apiVersion}/${file}`);
+            const cassette: Cassette = JSON.parse(cassettes[file] ?? "{}");
+            const fileRecorded: Cassette = {};
+            for (const { name, fn } of registered) {
+                Object.assign(requestContext, {
+                    path: "",
+                    method: "",
+                    headers: {},
+                    apiVersion,
+                    userId: undefined,
+                    claims: undefined,
+                    tenant: undefined,
+                });
+                useMemoryStore(new Map());
+                fetchHandler = undefined;
+                recording = record ? [] : undefined;
+                replaying = [...(cassette[name] ?? [])];
+                resetTestClock();
+                const start = performance.now();
+                let error;
+                try {
+                    await runWithTimeout(fn);
+                } catch (e) {
+                    error = e instanceof Error ? e.stack ?? e.message : String(e);
+                }
+                const durationMs = performance.now() - start;
+                results.push({ file, name, error, durationMs });
+                if (recording !== undefined && recording.length > 0) {
+                    fileRecorded[name] = recording;
+                }
+            }
+            if (Object.keys(fileRecorded).length > 0) {
+                recorded[file] = JSON.stringify(fileRecorded, null, 4) + "\n";
+            }
+        }
+    } finally {
+        if (coverage) {
+            scripts = await opAsync(
+                "op_chisel_coverage_take",
+                `file:///${apiVersion}/`,
+            ) as ScriptCoverage[];
+        }
+        registered = [];
+        fetchHandler = undefined;
+        recording = undefined;
+        replaying = [];
+        useMemoryStore(undefined);
+        resetTestClock();
+        useRealClock();
+        globalThis.fetch = savedFetch;
+        Object.assign(requestContext, savedContext);
+    }
+    return { results, coverage: scripts, cassettes: recorded };
+}
@@ -1,5 +1,7 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
+import { contentTypes, encodeBody, negotiateEncoding } from "./encoding.ts";
+
 export function opSync(opName: string, a?: unknown, b?: unknown): unknown {
     return Deno.core.opSync(opName, a, b);
 }
@@ -31,7 +33,18 @@ export function mergeDeep(
 
     if (isObject(target) && isObject(source)) {
         for (const key in source) {
-            if (isObject(source[key])) {
+            const value = source[key];
+            if (
+                value instanceof Date ||
+                (target[key] instanceof Date &&
+                    (typeof value === "string" || typeof value === "number"))
+            ) {
+                // Dates are stored as ISO-8601 strings, and are also built
+                // from those or from milliseconds since the epoch.
+                Object.assign(target, {
+                    [key]: new Date(value as string | number | Date),
+                });
+            } else if (isObject(source[key])) {
                 if (!target[key]) {
                     Object.assign(target, { [key]: {} });
                 }
@@ -66,12 +79,12 @@ export function getSecret(key: string): JSONValue | undefined {
     return opSync("op_chisel_get_secret", key) as JSONValue | undefined;
 }
 
-export function responseFromJson(body: unknown, status = 200) {
-    // https://fetch.spec.whatwg.org/#null-body-status
-    const isNullBody = (status: number): boolean => {
-        return status == 101 || status == 204 || status == 205 || status == 304;
-    };
+// https://fetch.spec.whatwg.org/#null-body-status
+function isNullBody(status: number): boolean {
+    return status == 101 || status == 204 || status == 205 || status == 304;
+}
 
+export function responseFromJson(body: unknown, status = 200) {
     const json = isNullBody(status) ? null : JSON.stringify(body, null, 2);
     return new Response(json, {
         status: status,
@@ -80,3 +93,126 @@ export function responseFromJson(body: unknown, status = 200) {
         ],
     });
 }
+
+/**
+ * Like `responseFromJson()`, but encodes `body` as JSON, MessagePack or CBOR,
+ * whichever the `accept` header value prefers.
+ */
+export function responseFromValue(
+    body: unknown,
+    status = 200,
+    accept?: string | null,
+) {
+    const encoding = negotiateEncoding(accept);
+    const encoded = isNullBody(status) ? null : encodeBody(body, encoding);
+    return new Response(encoded, {
+        status: status,
+        headers: [
+            ["content-type", contentTypes[encoding]],
+        ],
+    });
+}
+
+/** An event of a `text/event-stream` response. */
+export type ServerSentEvent = {
+    data: string;
+    event?: string;
+    id?: string;
+    /** Milliseconds for the client to wait before reconnecting. */
+    retry?: number;
+};
+
+function formatEvent(event: ServerSentEvent | string): string {
+    if (typeof event === "string") {
+        event = { data: event };
+    }
+    let text = "";
+    if (event.event !== undefined) {
+        text += `event: ${event.event}\n`;
+    }
+    if (event.id !== undefined) {
+        text += `id: ${event.id}\n`;
+    }
+    if (event.retry !== undefined) {
+        text += `retry: ${event.retry}\n`;
+    }
+    for (const line of event.data.split(/\r\n|\r|\n/)) {
+        text += `data: ${line}\n`;
+    }
+    return text + "\n";
+}
+
+/**
+ * Makes a readable stream out of `chunks`, which only asks for the next
+ * chunk once the previous one is sent.
+ */
+function streamOf(
+    chunks: AsyncIterable<string>,
+): ReadableStream<Uint8Array> {
+    const iterator = chunks[Symbol.asyncIterator]();
+    const encoder = new TextEncoder();
+    return new ReadableStream<Uint8Array>({
+        async pull(controller) {
+            const { value, done } = await iterator.next();
+            if (done) {
+                controller.close();
+            } else {
+                controller.enqueue(encoder.encode(value));
+            }
+        },
+        async cancel() {
+            await iterator.return?.();
+        },
+    });
+}
+
+/**
+ * Makes a `text/event-stream` response that sends each of `events` to the
+ * client as soon as it is produced. Strings are sent as the data of
+ * unnamed events.
+ *
+ * Like any response, it is sent in the transaction of its request, and the
+ * executor serving it doesn't serve other requests until it ends.
+ */
+export function responseFromEvents(
+    events: AsyncIterable<ServerSentEvent | string>,
+    status = 200,
+) {
+    async function* formatted() {
+        for await (const event of events) {
+            yield formatEvent(event);
+        }
+    }
+    return new Response(streamOf(formatted()), {
+        status,
+        headers: [
+            ["content-type", "text/event-stream"],
+            ["cache-control", "no-cache"],
+        ],
+    });
+}
+
+/**
+ * Makes a response with a JSON array of `values`, such as the entities of a
+ * `ChiselCursor`, that is sent to the client as the values are produced
+ * instead of being materialized first.
+ */
+export function responseFromStream(
+    values: AsyncIterable<unknown>,
+    status = 200,
+) {
+    async function* json() {
+        let separator = "[";
+        for await (const value of values) {
+            yield separator + JSON.stringify(value);
+            separator = ",";
+        }
+        yield separator === "[" ? "[]" : "]";
+    }
+    return new Response(streamOf(json()), {
+        status,
+        headers: [
+            ["content-type", "application/json"],
+        ],
+    });
+}
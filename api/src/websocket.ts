@@ -0,0 +1,47 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+import type { ChiselRequest } from "./request.ts";
+
+/**
+ * A WebSocket connection to a route exporting a `websocket` handler.
+ *
+ * Sockets can be kept, by their id, to send messages to their clients from
+ * other requests.
+ */
+export class ChiselSocket {
+    constructor(readonly id: string) {}
+
+    /** Sends a text message, or a binary one, to the client. */
+    send(data: string | Uint8Array) {
+        if (typeof data === "string") {
+            Deno.core.opSync("op_chisel_socket_send", this.id, data, null);
+        } else {
+            Deno.core.opSync("op_chisel_socket_send", this.id, null, data);
+        }
+    }
+
+    /** Closes the connection, after which `close` of the handler runs. */
+    close() {
+        Deno.core.opSync("op_chisel_socket_close", this.id);
+    }
+}
+
+/**
+ * The handler of the WebSocket connections of a route, exported by it as
+ * `websocket`. Each function runs in a transaction of its own, as the user
+ * who opened the socket.
+ */
+export interface WebSocketHandler {
+    /**
+     * Runs when a client asks to open a socket, with its upgrade request.
+     * Throwing refuses the socket.
+     */
+    open?: (socket: ChiselSocket, req: ChiselRequest) => Promise<void> | void;
+    /** Runs for each message the client sends. */
+    message?: (
+        socket: ChiselSocket,
+        data: string | Uint8Array,
+    ) => Promise<void> | void;
+    /** Runs once the connection is closed, by either side. */
+    close?: (socket: ChiselSocket) => Promise<void> | void;
+}
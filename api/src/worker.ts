@@ -1,6 +1,7 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
 import * as Chisel from "./api.ts";
+import { runUnitTests as runUnitTestsImpl } from "./testing.ts";
 
 // Hack to pretend we are not in a web worker. On workers 'window'
 // doesn't exist, but globalThis does. They are not exactly the same,
@@ -21,6 +22,38 @@ Deno.core.opSync(
     },
 );
 
+// fetch() fails in requests that `chisel fault inject --kind fetch` applies to,
+// and is timed in profiled requests.
+const realFetch = globalThis.fetch;
+globalThis.fetch = async (
+    input: string | URL | Request,
+    init?: RequestInit,
+): Promise<Response> => {
+    const timing = Deno.core.opSync(
+        "op_chisel_check_fetch",
+        fetchTarget(input, init),
+    );
+    try {
+        return await realFetch(input, init);
+    } finally {
+        if (timing !== null) {
+            Deno.core.opSync("op_chisel_end_fetch", timing);
+        }
+    }
+};
+
+// The method and URL of a fetch(), without the query string, which could
+// hold secrets.
+function fetchTarget(
+    input: string | URL | Request,
+    init?: RequestInit,
+): string {
+    const request = input instanceof Request ? input : undefined;
+    const method = init?.method ?? request?.method ?? "GET";
+    const url = request?.url ?? input.toString();
+    return `${method.toUpperCase()} ${url.split(/[?#]/)[0]}`;
+}
+
 type requestHandler = (req: Request) => Promise<Response>;
 // Handlers that have been compiled but are not yet serving
 // requests. The function activateEndpoint moves handler from
@@ -29,10 +62,38 @@ const nextHandlers: Record<string, requestHandler> = {};
 // A map from paths to functions that handle requests for that path.
 const handlers: Record<string, requestHandler> = {};
 
+// Functions exported as `authenticate` by route modules, which identify the
+// user of requests when the `function:<route>` auth provider is configured.
+const nextAuthenticators: Record<string, Chisel.Authenticator> = {};
+const authenticators: Record<string, Chisel.Authenticator> = {};
+
+// Handlers exported as `websocket` by route modules.
+const nextSocketHandlers: Record<string, Chisel.WebSocketHandler> = {};
+const socketHandlers: Record<string, Chisel.WebSocketHandler> = {};
+
+// Who opened each open socket, by id, since its handler runs as them.
+type SocketContext = {
+    userId?: string;
+    claims?: Record<string, unknown>;
+    tenant?: string;
+    apiKey?: string;
+};
+const sockets: Record<string, SocketContext> = {};
+
 type eventHandler = (event: Chisel.ChiselEvent) => Promise<void>;
 const nextEventHandlers: Record<string, eventHandler> = {};
 const eventHandlers: Record<string, eventHandler> = {};
 
+// Modules of the `jobs/` directory, with their schedules.
+type Job = { schedule: string; handler: () => Promise<void> | void };
+const nextJobs: Record<string, Job> = {};
+const jobs: Record<string, Job> = {};
+
+// Modules of the `tasks/` directory, run by Chisel.enqueue().
+type taskHandler = (payload: unknown) => Promise<void> | void;
+const nextTaskHandlers: Record<string, taskHandler> = {};
+const taskHandlers: Record<string, taskHandler> = {};
+
 const requestContext = Chisel.requestContext;
 const ChiselRequest = Chisel.ChiselRequest;
 const loggedInUser = Chisel.loggedInUser;
@@ -72,15 +133,32 @@ type Endpoint = { path: string; apiVersion: string; version: number };
 
 type EventHandler = { path: string; apiVersion: string; version: number };
 
-function importEndpoints(endpoints: [Endpoint], eventHandlers: [EventHandler]) {
+type JobModule = { path: string; apiVersion: string };
+
+type TaskModule = { path: string; apiVersion: string };
+
+function importEndpoints(
+    endpoints: [Endpoint],
+    eventHandlers: [EventHandler],
+    jobModules: [JobModule],
+    taskModules: [TaskModule],
+) {
     handleMsg(() => {
-        return importEndpointsImpl(endpoints, eventHandlers);
+        return importEndpointsImpl(
+            endpoints,
+            eventHandlers,
+            jobModules,
+            taskModules,
+        );
     });
 }
 
+// Returns the schedules of the jobs, by path, for the server to check.
 async function importEndpointsImpl(
     endpoints: [Endpoint],
     eventHandlers: [EventHandler],
+    jobModules: [JobModule],
+    taskModules: [TaskModule],
 ) {
     for (const endpoint of endpoints) {
         const { path, apiVersion } = endpoint;
@@ -101,13 +179,27 @@ async function importEndpointsImpl(
             mod = await import(url);
         }
 
-        const handler = mod.default;
+        let handler = mod.default;
+        if (handler === undefined && typeof mod.websocket === "object") {
+            // A route that only serves WebSockets.
+            handler = () =>
+                new Response("Upgrade Required", {
+                    status: 426,
+                    headers: { "Upgrade": "websocket" },
+                });
+        }
         if (typeof handler !== "function") {
             throw new Error(
                 "expected type `v8::data::Function`, got `v8::data::Value`",
             );
         }
         nextHandlers[fullPath] = handler;
+        if (typeof mod.websocket === "object") {
+            nextSocketHandlers[fullPath] = mod.websocket;
+        }
+        if (typeof mod.authenticate === "function") {
+            nextAuthenticators[fullPath] = mod.authenticate;
+        }
     }
     for (const eventHandler of eventHandlers) {
         const { path, apiVersion } = eventHandler;
@@ -128,12 +220,60 @@ async function importEndpointsImpl(
         }
         nextEventHandlers[fullPath] = handler;
     }
+    const schedules: Record<string, string> = {};
+    for (const job of jobModules) {
+        const { path, apiVersion } = job;
+
+        requestContext.path = path;
+        const fullPath = "/" + apiVersion + path;
+
+        const url = `file:///${apiVersion}/jobs${path}`;
+        const mod = await import(url);
+        const handler = mod.default;
+        if (typeof handler !== "function") {
+            throw new Error(`job ${fullPath} must export a default function`);
+        }
+        if (typeof mod.schedule !== "string") {
+            throw new Error(
+                `job ${fullPath} must export its schedule, like "*/5 * * * *"`,
+            );
+        }
+        nextJobs[fullPath] = { schedule: mod.schedule, handler };
+        schedules[fullPath] = mod.schedule;
+    }
+    for (const task of taskModules) {
+        const { path, apiVersion } = task;
+
+        requestContext.path = path;
+        const fullPath = "/" + apiVersion + path;
+
+        const url = `file:///${apiVersion}/tasks${path}`;
+        const mod = await import(url);
+        const handler = mod.default;
+        if (typeof handler !== "function") {
+            throw new Error(`task ${fullPath} must export a default function`);
+        }
+        nextTaskHandlers[fullPath] = handler;
+    }
+    return schedules;
 }
 
 function activateEndpoint(path: string) {
     handleMsg(() => {
         handlers[path] = nextHandlers[path];
         delete nextHandlers[path];
+        if (path in nextAuthenticators) {
+            authenticators[path] = nextAuthenticators[path];
+            delete nextAuthenticators[path];
+        } else {
+            delete authenticators[path];
+        }
+        if (path in nextSocketHandlers) {
+            socketHandlers[path] = nextSocketHandlers[path];
+            delete nextSocketHandlers[path];
+        } else {
+            delete socketHandlers[path];
+        }
     });
 }
 
@@ -144,6 +284,21 @@ function activateEventHandler(path: string) {
     });
 }
 
+function activateJob(path: string) {
+    handleMsg(() => {
+        jobs[path] = nextJobs[path];
+        delete nextJobs[path];
+        return jobs[path].schedule;
+    });
+}
+
+function activateTask(path: string) {
+    handleMsg(() => {
+        taskHandlers[path] = nextTaskHandlers[path];
+        delete nextTaskHandlers[path];
+    });
+}
+
 async function rollback_on_failure<T>(func: () => Promise<T>): Promise<T> {
     try {
         return await func();
@@ -226,9 +381,22 @@ async function callHandlerImpl(
         sendBodyPart(undefined, id);
         return start.Special;
     }
-    const { userid, url, method, headers, body_rid } = start.Js;
+    const {
+        url,
+        method,
+        headers,
+        body_rid,
+        authenticator,
+        tenant,
+        api_key,
+        socket_id,
+    } = start.Js;
+    let { userid, claims } = start.Js;
     requestContext.method = method;
     requestContext.userId = userid;
+    requestContext.claims = claims;
+    requestContext.tenant = tenant;
+    requestContext.apiKey = api_key;
     requestContext.headers = headers;
 
     // FIXME: maybe defer creating the transaction until we need one, to avoid doing it for
@@ -236,6 +404,48 @@ async function callHandlerImpl(
     // it should be safe to unwrap.
     await Deno.core.opAsync("op_chisel_create_transaction");
 
+    if (authenticator !== null) {
+        // Called once the transaction exists, so that the function can look
+        // up credentials in the database.
+        const authenticate = authenticators["/" + apiVersion + authenticator];
+        if (authenticate === undefined) {
+            throw new Error(
+                `route ${authenticator} exports no authenticate function`,
+            );
+        }
+        let principal;
+        let error: string | null = null;
+        try {
+            principal = await authenticate(new Request(url, { headers }));
+        } catch (e) {
+            // Rejected like invalid credentials found by the server.
+            error = e instanceof Error ? e.message : String(e);
+        }
+        // Without a principal, the server tries the providers after the
+        // function.
+        const authenticated = await Deno.core.opAsync(
+            "op_chisel_authenticated",
+            principal?.userId ?? null,
+            principal?.claims ?? {},
+            error,
+        );
+        const { denied } = authenticated;
+        userid = authenticated.userid;
+        claims = authenticated.claims;
+        requestContext.userId = userid;
+        requestContext.claims = claims;
+        requestContext.apiKey = authenticated.api_key;
+        // The tenant may come from the claims just returned.
+        requestContext.tenant = authenticated.tenant;
+        if (denied) {
+            closeResources();
+            Deno.core.opSync("op_chisel_rollback_transaction");
+            sendBodyPart(denied.body, id);
+            sendBodyPart(undefined, id);
+            return denied;
+        }
+    }
+
     const init: RequestInit = {
         method,
         headers,
@@ -258,8 +468,23 @@ async function callHandlerImpl(
         path,
         pathParams,
         user,
+        claims,
     );
 
+    const socketHandler = socketHandlers[fullPath];
+    if (socket_id !== null && socketHandler !== undefined) {
+        await socketHandler.open?.(new Chisel.ChiselSocket(socket_id), req);
+        sockets[socket_id] = {
+            userId: requestContext.userId,
+            claims: requestContext.claims,
+            tenant: requestContext.tenant,
+            apiKey: requestContext.apiKey,
+        };
+        // Commits the transaction of `open`.
+        sendBody(undefined, id);
+        return { status: 101, headers: [] };
+    }
+
     let res = await handlers[fullPath](req);
     const resHeaders = [];
     // FIXME: we could try to building a ReadableStream from
@@ -268,8 +493,17 @@ async function callHandlerImpl(
     if (res?.constructor.name != "Response") {
         if (typeof res === "string") {
             res = new Response(res);
+        } else if (res instanceof ReadableStream) {
+            res = new Response(res);
+        } else if (res instanceof Chisel.ChiselCursor) {
+            // Sent as the rows come, without materializing them.
+            res = Chisel.responseFromStream(res);
         } else {
-            res = Chisel.responseFromJson(res);
+            res = Chisel.responseFromValue(
+                res,
+                200,
+                requestContext.headers["accept"],
+            );
         }
     }
 
@@ -310,6 +544,8 @@ async function callEventHandlerImpl(
 ) {
     requestContext.method = "POST";
     requestContext.apiVersion = apiVersion;
+    // Events aren't made by a tenant, so tenant-scoped entities are out of reach.
+    requestContext.tenant = undefined;
 
     await Deno.core.opAsync("op_chisel_start_event_handler");
 
@@ -345,6 +581,139 @@ function callEventHandler(
     });
 }
 
+async function callJobHandlerImpl(path: string, apiVersion: string) {
+    requestContext.method = "POST";
+    requestContext.apiVersion = apiVersion;
+    requestContext.path = path;
+    requestContext.headers = {};
+    requestContext.userId = undefined;
+    requestContext.claims = undefined;
+    requestContext.apiKey = undefined;
+    // Jobs aren't run by a tenant, like events.
+    requestContext.tenant = undefined;
+
+    await Deno.core.opAsync("op_chisel_start_event_handler");
+
+    await Deno.core.opAsync("op_chisel_create_transaction");
+
+    await jobs["/" + apiVersion + path].handler();
+
+    closeResources();
+
+    await Deno.core.opAsync("op_chisel_commit_transaction");
+}
+
+function callJobHandler(path: string, apiVersion: string) {
+    handleMsg(() => {
+        return rollback_on_failure(() => {
+            return callJobHandlerImpl(path, apiVersion);
+        });
+    });
+}
+
+async function callTaskHandlerImpl(
+    path: string,
+    apiVersion: string,
+    payload: string,
+) {
+    requestContext.method = "POST";
+    requestContext.apiVersion = apiVersion;
+    requestContext.path = path;
+    requestContext.headers = {};
+    requestContext.userId = undefined;
+    requestContext.claims = undefined;
+    requestContext.apiKey = undefined;
+    // Tasks aren't run by a tenant, like events.
+    requestContext.tenant = undefined;
+
+    await Deno.core.opAsync("op_chisel_start_event_handler");
+
+    await Deno.core.opAsync("op_chisel_create_transaction");
+
+    await taskHandlers["/" + apiVersion + path](JSON.parse(payload));
+
+    closeResources();
+
+    await Deno.core.opAsync("op_chisel_commit_transaction");
+}
+
+function callTaskHandler(path: string, apiVersion: string, payload: string) {
+    handleMsg(() => {
+        return rollback_on_failure(() => {
+            return callTaskHandlerImpl(path, apiVersion, payload);
+        });
+    });
+}
+
+async function callSocketHandlerImpl(
+    path: string,
+    apiVersion: string,
+    socketId: string,
+    event: string,
+    data: string | ArrayBuffer | null,
+) {
+    const context = sockets[socketId];
+    if (event === "close") {
+        delete sockets[socketId];
+    }
+    requestContext.method = "POST";
+    requestContext.apiVersion = apiVersion;
+    requestContext.path = path;
+    requestContext.headers = {};
+    requestContext.userId = context?.userId;
+    requestContext.claims = context?.claims;
+    requestContext.tenant = context?.tenant;
+    requestContext.apiKey = context?.apiKey;
+
+    await Deno.core.opAsync("op_chisel_start_event_handler");
+
+    await Deno.core.opAsync("op_chisel_create_transaction");
+
+    // The route may be gone since the socket was opened.
+    const handler = socketHandlers["/" + apiVersion + path];
+    const socket = new Chisel.ChiselSocket(socketId);
+    if (event === "close") {
+        await handler?.close?.(socket);
+    } else if (data !== null) {
+        const message = typeof data === "string" ? data : new Uint8Array(data);
+        await handler?.message?.(socket, message);
+    }
+
+    closeResources();
+
+    await Deno.core.opAsync("op_chisel_commit_transaction");
+}
+
+function callSocketHandler(
+    path: string,
+    apiVersion: string,
+    socketId: string,
+    event: string,
+    data: string | ArrayBuffer | null,
+) {
+    handleMsg(() => {
+        return rollback_on_failure(() => {
+            return callSocketHandlerImpl(
+                path,
+                apiVersion,
+                socketId,
+                event,
+                data,
+            );
+        });
+    });
+}
+
+function runUnitTests(
+    apiVersion: string,
+    tests: string[],
+    coverage: boolean,
+) {
+    handleMsg(() => {
+        return runUnitTestsImpl(apiVersion, tests, coverage);
+    });
+}
+
 function endOfRequest(id: number) {
     if (id == currentRequestId) {
         currentRequestId = undefined;
@@ -361,7 +730,7 @@ onmessage = function (e) {
             initWorker(d.id);
             break;
         case "importEndpoints":
-            importEndpoints(d.endpoints, d.eventHandlers);
+            importEndpoints(d.endpoints, d.eventHandlers, d.jobs, d.tasks);
             break;
         case "activateEndpoint":
             activateEndpoint(d.path);
@@ -369,6 +738,12 @@ onmessage = function (e) {
         case "activateEventHandler":
             activateEventHandler(d.path);
             break;
+        case "activateJob":
+            activateJob(d.path);
+            break;
+        case "activateTask":
+            activateTask(d.path);
+            break;
         case "callHandler":
             callHandler(
                 d.path,
@@ -384,6 +759,24 @@ onmessage = function (e) {
                 d.value,
             );
             break;
+        case "callJobHandler":
+            callJobHandler(d.path, d.apiVersion);
+            break;
+        case "callTaskHandler":
+            callTaskHandler(d.path, d.apiVersion, d.payload);
+            break;
+        case "callSocketHandler":
+            callSocketHandler(
+                d.path,
+                d.apiVersion,
+                d.socketId,
+                d.event,
+                d.data,
+            );
+            break;
+        case "runUnitTests":
+            runUnitTests(d.apiVersion, d.tests, d.coverage);
+            break;
         case "endOfRequest":
             endOfRequest(d.id);
             break;
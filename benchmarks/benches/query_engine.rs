@@ -0,0 +1,126 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use benchmarks::Database;
+use chisel_server::bench::{BenchDb, BenchEntity};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use serde_json::Value as JsonValue;
+use tokio::runtime::Runtime;
+
+/// Rows streamed by the select benchmarks.
+const SELECTED_ROWS: usize = 1000;
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn connect(rt: &Runtime, database: &Database) -> BenchDb {
+    rt.block_on(BenchDb::connect(&database.uri)).unwrap()
+}
+
+fn rows(entity: &BenchEntity, count: usize) -> Vec<JsonValue> {
+    (0..count).map(|i| entity.row(i)).collect()
+}
+
+fn add_row(c: &mut Criterion) {
+    let database = Database::from_env().unwrap();
+    let rt = runtime();
+    let db = connect(&rt, &database);
+    let entity = BenchEntity::new("AddRow", 8).unwrap();
+    rt.block_on(db.create_table(&entity)).unwrap();
+
+    let mut i = 0;
+    c.bench_function("add_row", |b| {
+        b.to_async(&rt).iter_batched(
+            || {
+                i += 1;
+                entity.row(i)
+            },
+            |row| {
+                let (db, entity) = (&db, &entity);
+                async move { db.add_row(entity, &row).await.unwrap() }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    rt.block_on(db.drop_table(&entity)).unwrap();
+}
+
+fn bulk_insert(c: &mut Criterion) {
+    let database = Database::from_env().unwrap();
+    let rt = runtime();
+    let db = connect(&rt, &database);
+    let entity = BenchEntity::new("BulkInsert", 8).unwrap();
+    rt.block_on(db.create_table(&entity)).unwrap();
+
+    let mut group = c.benchmark_group("bulk_insert");
+    for count in [10, 100, 1000] {
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, &count| {
+            b.to_async(&rt).iter_batched(
+                || rows(&entity, count),
+                |rows| {
+                    let (db, entity) = (&db, &entity);
+                    async move { db.add_rows(entity, &rows).await.unwrap() }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+    rt.block_on(db.drop_table(&entity)).unwrap();
+}
+
+fn select(c: &mut Criterion) {
+    let database = Database::from_env().unwrap();
+    let rt = runtime();
+    let db = connect(&rt, &database);
+
+    let mut group = c.benchmark_group("select");
+    group.throughput(Throughput::Elements(SELECTED_ROWS as u64));
+    for width in [2, 8, 32] {
+        let entity = BenchEntity::new("Select", width).unwrap();
+        rt.block_on(async {
+            db.create_table(&entity).await?;
+            db.add_rows(&entity, &rows(&entity, SELECTED_ROWS)).await
+        })
+        .unwrap();
+        group.bench_with_input(BenchmarkId::new("width", width), &entity, |b, entity| {
+            b.to_async(&rt).iter(|| async {
+                let count = db.select_all(entity).await.unwrap();
+                assert_eq!(count, SELECTED_ROWS);
+            })
+        });
+        rt.block_on(db.drop_table(&entity)).unwrap();
+    }
+    group.finish();
+}
+
+fn ddl(c: &mut Criterion) {
+    let database = Database::from_env().unwrap();
+    let rt = runtime();
+    let db = connect(&rt, &database);
+
+    let mut group = c.benchmark_group("create_and_drop_table");
+    for width in [2, 32] {
+        group.bench_with_input(BenchmarkId::new("width", width), &width, |b, &width| {
+            b.to_async(&rt).iter_batched(
+                || BenchEntity::new("Ddl", width).unwrap(),
+                |entity| {
+                    let db = &db;
+                    async move {
+                        db.create_table(&entity).await.unwrap();
+                        db.drop_table(&entity).await.unwrap();
+                    }
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, add_row, bulk_insert, select, ddl);
+criterion_main!(benches);
@@ -0,0 +1,31 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Benchmarks of the query engine, which are in `benches/`.
+//!
+//! They run against SQLite, in a temporary file, unless `CHISEL_BENCH_DB`
+//! is set to the URI of another database, such as
+//! `postgres://localhost/chisel_bench`.
+
+use anyhow::Result;
+use tempfile::NamedTempFile;
+
+/// The database the benchmarks run against.
+pub struct Database {
+    pub uri: String,
+    /// The SQLite file, removed when the benchmarks are done.
+    _file: Option<NamedTempFile>,
+}
+
+impl Database {
+    pub fn from_env() -> Result<Self> {
+        if let Ok(uri) = std::env::var("CHISEL_BENCH_DB") {
+            return Ok(Self { uri, _file: None });
+        }
+        let file = NamedTempFile::new()?;
+        let uri = format!("sqlite://{}?mode=rwc", file.path().to_string_lossy());
+        Ok(Self {
+            uri,
+            _file: Some(file),
+        })
+    }
+}
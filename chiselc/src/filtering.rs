@@ -3,6 +3,7 @@
 use crate::query::Filter;
 use indexmap::IndexSet;
 use serde::Serialize;
+use swc_common::Span;
 use swc_ecmascript::ast::{ObjectLit, Prop, PropName, PropOrSpread};
 
 /// Filter properties.
@@ -68,3 +69,33 @@ fn prop_name_to_string(prop_name: &PropName) -> Option<String> {
         _ => None,
     }
 }
+
+/// Filter fallback.
+///
+/// This struct describes a ChiselStrike query API filter call whose predicate
+/// cannot be transformed into a query expression. The runtime evaluates such
+/// predicates in the isolate over a full scan of the entity, which is something
+/// users want to know about.
+#[derive(Debug, Serialize)]
+pub struct FilterFallback {
+    pub entity_name: String,
+    /// Source code of the predicate.
+    pub expression: String,
+    /// Line of the predicate in the source file (1-based).
+    pub line: usize,
+    #[serde(skip)]
+    pub span: Span,
+}
+
+impl FilterFallback {
+    pub fn new(entity_name: String, span: Span) -> Self {
+        // The expression and line are resolved against the source map once
+        // the whole module is rewritten.
+        Self {
+            entity_name,
+            expression: String::new(),
+            line: 0,
+            span,
+        }
+    }
+}
@@ -145,6 +145,20 @@ pub fn compile<W: Write>(
         Target::FilterProperties => {
             writeln!(&mut output, "{}", serde_json::to_string(&rewriter.indexes)?)?;
         }
+        Target::FilterFallbacks => {
+            for fallback in rewriter.fallbacks.iter_mut() {
+                fallback.line = ctx.sm.lookup_char_pos(fallback.span.lo).line;
+                fallback.expression = ctx
+                    .sm
+                    .span_to_snippet(fallback.span)
+                    .map_err(|e| anyhow!("Failed to look up predicate source: {:?}", e))?;
+            }
+            writeln!(
+                &mut output,
+                "{}",
+                serde_json::to_string(&rewriter.fallbacks)?
+            )?;
+        }
     }
     Ok(())
 }
@@ -2,8 +2,9 @@
 
 //! AST rewriter that transforms TypeScript code into query expressions.
 
-use crate::filtering::FilterProperties;
+use crate::filtering::{FilterFallback, FilterProperties};
 use crate::symbols::Symbols;
+use crate::transforms::fallback::infer_fallback;
 use crate::transforms::filter::emit::to_ts_expr;
 use crate::transforms::filter::infer_filter;
 use crate::transforms::find::infer_find;
@@ -27,6 +28,8 @@ pub enum Target {
     TypeScript,
     /// Emit properties that are used in ChiselStrike filter() calls as JSON. The runtime uses this information for auto-indexing purposes.
     FilterProperties,
+    /// Emit filter() calls whose predicates cannot be transformed into query expressions as JSON.
+    FilterFallbacks,
 }
 
 type TargetParseError = &'static str;
@@ -38,6 +41,7 @@ impl FromStr for Target {
             "js" => Ok(Target::JavaScript),
             "ts" => Ok(Target::TypeScript),
             "filter-properties" => Ok(Target::FilterProperties),
+            "filter-fallbacks" => Ok(Target::FilterFallbacks),
             _ => Err("Unknown target"),
         }
     }
@@ -47,6 +51,8 @@ pub struct Rewriter {
     symbols: Symbols,
     // Accumulated predicate indexes.
     pub indexes: Vec<FilterProperties>,
+    // Accumulated filters that fall back to evaluation in the isolate.
+    pub fallbacks: Vec<FilterFallback>,
 }
 
 impl Rewriter {
@@ -54,6 +60,7 @@ impl Rewriter {
         Self {
             symbols,
             indexes: vec![],
+            fallbacks: vec![],
         }
     }
 
@@ -262,6 +269,9 @@ impl Rewriter {
         if let Some(filter) = filter {
             return to_ts_expr(&filter);
         }
+        if let Some(fallback) = infer_fallback(call_expr, &self.symbols) {
+            self.fallbacks.push(fallback);
+        }
         let args = call_expr
             .args
             .iter()
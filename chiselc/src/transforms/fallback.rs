@@ -0,0 +1,43 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::filtering::FilterFallback;
+use crate::symbols::Symbols;
+use crate::transforms::filter::is_rewritable_filter;
+use crate::transforms::filter::splitting::split_and_convert_expr;
+use crate::transforms::find::infer_rewritable_find;
+use crate::transforms::utils::lookup_callee_entity_type;
+
+use swc_ecmascript::ast::{BlockStmtOrExpr, CallExpr, Expr, ReturnStmt, Stmt};
+
+/// Detect filter calls whose lambda predicate cannot be transformed into a
+/// query expression, not even partially by filter splitting.
+///
+/// Such predicates are evaluated in the isolate over a full scan of the entity.
+pub fn infer_fallback(call_expr: &CallExpr, symbols: &Symbols) -> Option<FilterFallback> {
+    if !is_rewritable_filter(&call_expr.callee, symbols)
+        && infer_rewritable_find(call_expr, symbols).is_none()
+    {
+        return None;
+    }
+    let entity_type = lookup_callee_entity_type(&call_expr.callee).ok()?;
+    if call_expr.args.len() != 1 {
+        return None;
+    }
+    let arrow = match &*call_expr.args[0].expr {
+        Expr::Arrow(arrow_expr) => arrow_expr,
+        _ => return None,
+    };
+    let (query, _) = match &arrow.body {
+        BlockStmtOrExpr::Expr(expr) => split_and_convert_expr(expr),
+        BlockStmtOrExpr::BlockStmt(block_stmt) => match block_stmt.stmts.as_slice() {
+            [Stmt::Return(ReturnStmt {
+                arg: Some(expr), ..
+            })] => split_and_convert_expr(expr),
+            _ => (None, None),
+        },
+    };
+    if query.is_some() {
+        return None;
+    }
+    Some(FilterFallback::new(entity_type, arrow.span))
+}
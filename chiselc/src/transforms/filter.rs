@@ -26,7 +26,7 @@ pub fn infer_filter(
     extract_filter(call_expr, entity_type, "__filter".to_string())
 }
 
-fn is_rewritable_filter(callee: &Callee, symbols: &Symbols) -> bool {
+pub(crate) fn is_rewritable_filter(callee: &Callee, symbols: &Symbols) -> bool {
     match callee {
         Callee::Expr(expr) => match &**expr {
             Expr::Member(member_expr) if is_ident_member_prop(&member_expr.prop, "filter") => {
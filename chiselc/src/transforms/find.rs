@@ -24,7 +24,7 @@ pub fn infer_find(
     extract_filter(call_expr, entity_type, function)
 }
 
-fn infer_rewritable_find(call_expr: &CallExpr, symbols: &Symbols) -> Option<String> {
+pub(crate) fn infer_rewritable_find(call_expr: &CallExpr, symbols: &Symbols) -> Option<String> {
     if is_rewritable_find("findMany", &call_expr.callee, symbols) {
         return Some("__findMany".to_string());
     }
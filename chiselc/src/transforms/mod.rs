@@ -1,5 +1,6 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
+pub mod fallback;
 pub mod filter;
 pub mod find;
 pub mod utils;
@@ -0,0 +1,70 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use super::*;
+use chiselc::rewrite::Target;
+use serde_json::Value;
+
+#[test]
+fn filter() {
+    let compiled: Value = compile!(
+        r#"
+        await Person.cursor().filter({ name: "Pekka" }).toArray();
+        await Person.cursor().filter((p) => { return p.age > 4 }).toArray();
+        await Person.cursor().filter((p) => p.age > 4 && fetch(p.name)).toArray();
+        await Person.cursor().filter((p) => reverse(p.name) == "akkeP").toArray();
+        await Person.cursor().filter((p) => {
+            const name = reverse(p.name);
+            return name == "akkeP";
+        }).toArray();
+        "#,
+        "Person";
+        Target::FilterFallbacks
+    )
+    .parse()
+    .unwrap();
+
+    let expected = serde_json::json!([
+        {
+            "entity_name": "Person",
+            "expression": "(p) => reverse(p.name) == \"akkeP\"",
+            "line": 5
+        },
+        {
+            "entity_name": "Person",
+            "expression": "(p) => {\n            const name = reverse(p.name);\n            return name == \"akkeP\";\n        }",
+            "line": 6
+        }
+    ]);
+
+    assert_eq!(compiled, expected);
+}
+
+#[test]
+fn find() {
+    let compiled: Value = compile!(
+        r#"
+        await Person.findMany((p) => p.age > 4);
+        await Person.findMany((p) => p.name.startsWith("P"));
+        await Person.findOne((p) => p.name.startsWith("P"));
+        "#,
+        "Person";
+        Target::FilterFallbacks
+    )
+    .parse()
+    .unwrap();
+
+    let expected = serde_json::json!([
+        {
+            "entity_name": "Person",
+            "expression": "(p) => p.name.startsWith(\"P\")",
+            "line": 3
+        },
+        {
+            "entity_name": "Person",
+            "expression": "(p) => p.name.startsWith(\"P\")",
+            "line": 4
+        }
+    ]);
+
+    assert_eq!(compiled, expected);
+}
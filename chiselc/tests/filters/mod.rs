@@ -1,5 +1,6 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
+mod filter_fallbacks;
 mod filter_properties;
 mod filter_splitting;
 mod transform_filter;
@@ -10,5 +10,7 @@ fn main() -> Result<()> {
     let mut config = Config::default();
     *config.git_mut().semver_kind_mut() = SemverKind::Lightweight;
     vergen(config)?;
+    // For `chisel upgrade` to download the tarball of this target.
+    println!("cargo:rustc-env=TARGET={}", std::env::var("TARGET")?);
     Ok(())
 }
@@ -10,3 +10,7 @@ macro_rules! execute {
 
 pub(crate) mod apply;
 pub(crate) mod dev;
+pub(crate) mod doctor;
+pub(crate) mod generate;
+pub(crate) mod test;
+pub(crate) mod upgrade;
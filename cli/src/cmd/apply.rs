@@ -1,11 +1,19 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
 pub mod deno;
+pub mod hooks;
+pub mod lint;
 pub mod node;
-
-use crate::project::{read_manifest, read_to_string, AutoIndex, Module, Optimize};
-use crate::proto::chisel_rpc_client::ChiselRpcClient;
-use crate::proto::{ChiselApplyRequest, IndexCandidate, PolicyUpdateRequest};
+pub mod policies;
+
+use crate::project::{
+    read_manifest, read_to_string, AutoIndex, LintLevel, Manifest, Module, Optimize,
+};
+use crate::proto::{
+    type_plan::Action, AddTypeRequest, ApplyPlan, ChiselApplyRequest, ChiselApplyResponse,
+    EndpointSchema, IndexCandidate, NamingStrategy, PolicyUpdateRequest,
+};
+use crate::server::connect;
 use anyhow::{anyhow, Context, Result};
 use serde_json::Value;
 use std::collections::HashMap;
@@ -15,16 +23,19 @@ use std::process::Stdio;
 
 static DEFAULT_APP_NAME: &str = "ChiselStrike Application";
 
+#[derive(Clone, Copy)]
 pub(crate) enum AllowTypeDeletion {
     No,
     Yes,
+    /// Also drop the fields of other types that relate to the deleted ones.
+    Cascade,
 }
 
 impl From<AllowTypeDeletion> for bool {
     fn from(v: AllowTypeDeletion) -> Self {
         match v {
             AllowTypeDeletion::No => false,
-            AllowTypeDeletion::Yes => true,
+            AllowTypeDeletion::Yes | AllowTypeDeletion::Cascade => true,
         }
     }
 }
@@ -38,6 +49,29 @@ impl From<bool> for AllowTypeDeletion {
     }
 }
 
+pub(crate) enum AllowDataLoss {
+    No,
+    Yes,
+}
+
+impl From<AllowDataLoss> for bool {
+    fn from(v: AllowDataLoss) -> Self {
+        match v {
+            AllowDataLoss::No => false,
+            AllowDataLoss::Yes => true,
+        }
+    }
+}
+
+impl From<bool> for AllowDataLoss {
+    fn from(v: bool) -> Self {
+        match v {
+            false => AllowDataLoss::No,
+            true => AllowDataLoss::Yes,
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub(crate) enum TypeChecking {
     No,
@@ -71,16 +105,26 @@ impl From<bool> for TypeChecking {
 /// something special depending on the source file type.
 pub(crate) type SourceMap = HashMap<String, String>;
 
-pub(crate) async fn apply(
-    server_url: String,
-    version: String,
-    allow_type_deletion: AllowTypeDeletion,
-    type_check: TypeChecking,
-) -> Result<()> {
+/// What is applied of a project, built and checked without the server.
+pub(crate) struct Build {
+    manifest: Manifest,
+    types: Vec<AddTypeRequest>,
+    sources: SourceMap,
+    index_candidates: Vec<IndexCandidate>,
+    policies: Vec<PolicyUpdateRequest>,
+    endpoint_schemas: Vec<EndpointSchema>,
+    static_files: HashMap<String, Vec<u8>>,
+}
+
+/// Builds the project in the current directory, checking its models against
+/// the lint rules of its manifest and its policies against its models.
+pub(crate) async fn build(type_check: TypeChecking) -> Result<Build> {
     let manifest = read_manifest().context("Could not read manifest file")?;
     let models = manifest.models()?;
     let endpoints = manifest.endpoints()?;
     let events = manifest.events()?;
+    let jobs = manifest.jobs()?;
+    let tasks = manifest.tasks()?;
     let policies = manifest.policies()?;
 
     let types_req = crate::ts::parse_types(&models)?;
@@ -90,6 +134,7 @@ pub(crate) async fn apply(
         .iter()
         .map(|type_req| type_req.name.clone())
         .collect();
+    let endpoint_schemas = crate::ts::parse_endpoint_schemas(&endpoints, &entities)?;
     let chiselc_available = is_chiselc_available();
     if !chiselc_available {
         println!(
@@ -98,19 +143,38 @@ pub(crate) async fn apply(
     }
     let optimize = chiselc_available && manifest.optimize == Optimize::Yes;
     let auto_index = chiselc_available && manifest.auto_index == AutoIndex::Yes;
-    let (sources, index_candidates) = if manifest.modules == Module::Node {
+    let find_indexes =
+        chiselc_available && (auto_index || manifest.lint.missing_index != LintLevel::Off);
+    let (sources, mut index_candidates) = if manifest.modules == Module::Node {
         node::apply(
             &endpoints,
             &events,
+            &jobs,
+            &tasks,
             &entities,
             optimize,
-            auto_index,
+            find_indexes,
             &type_check,
         )
         .await
     } else {
-        deno::apply(&endpoints, &events, &entities, optimize, auto_index).await
+        deno::apply(
+            &endpoints,
+            &events,
+            &jobs,
+            &tasks,
+            &entities,
+            optimize,
+            find_indexes,
+        )
+        .await
     }?;
+    // Filtered properties are only linted if they won't be auto-indexed.
+    let filtered = (find_indexes && !auto_index).then(|| index_candidates.as_slice());
+    lint::check(&manifest.lint, &types_req, filtered)?;
+    if !auto_index {
+        index_candidates.clear();
+    }
 
     for p in &policies {
         policy_req.push(PolicyUpdateRequest {
@@ -118,6 +182,40 @@ pub(crate) async fn apply(
             path: p.display().to_string(),
         });
     }
+    policies::check(&policy_req, &types_req)?;
+    let mut static_files = HashMap::new();
+    for (path, file) in manifest.static_files()? {
+        let content =
+            std::fs::read(&file).with_context(|| format!("Could not read {}", file.display()))?;
+        static_files.insert(path, content);
+    }
+    Ok(Build {
+        manifest,
+        types: types_req,
+        sources,
+        index_candidates,
+        policies: policy_req,
+        endpoint_schemas,
+        static_files,
+    })
+}
+
+pub(crate) async fn apply(
+    server_url: String,
+    version: String,
+    allow_type_deletion: AllowTypeDeletion,
+    allow_data_loss: AllowDataLoss,
+    type_check: TypeChecking,
+) -> Result<()> {
+    let Build {
+        manifest,
+        types,
+        sources,
+        index_candidates,
+        policies,
+        endpoint_schemas,
+        static_files,
+    } = build(type_check).await?;
 
     let package = match read_to_string("./package.json") {
         Ok(x) => {
@@ -149,17 +247,94 @@ pub(crate) async fn apply(
         None => version_tag,
     };
 
-    let mut client = ChiselRpcClient::connect(server_url.clone()).await?;
+    let mut client = connect(server_url.clone()).await?;
     let mut req = ChiselApplyRequest {
-        types: types_req,
+        types,
         sources: Default::default(),
         index_candidates,
-        policies: policy_req,
+        policies,
         allow_type_deletion: allow_type_deletion.into(),
+        cascade_type_deletion: matches!(allow_type_deletion, AllowTypeDeletion::Cascade),
         version,
         version_tag,
         app_name,
+        dry_run: false,
+        endpoint_schemas,
+        static_files,
+        login_providers: manifest.login.clone(),
+        naming: Some(NamingStrategy {
+            table_prefix: manifest.naming.table_prefix.clone(),
+            snake_case: manifest.naming.snake_case,
+        }),
+    };
+
+    let plan_req = ChiselApplyRequest {
+        sources: sources.clone(),
+        dry_run: true,
+        ..req.clone()
     };
+    let plan = execute!(client.apply(tonic::Request::new(plan_req)).await)
+        .plan
+        .unwrap_or_default();
+    print_plan(&plan);
+    let rows_lost: u64 = plan.types.iter().map(|ty| ty.rows_lost).sum();
+    if rows_lost > 0 && !bool::from(allow_data_loss) {
+        anyhow::bail!(
+            r"Applying would lose the data of {} rows, as planned above.
+
+To proceed, try:
+
+'npx chisel apply --yes' (if installed from npm)
+
+or
+
+'chisel apply --yes' (otherwise)",
+            rows_lost
+        );
+    }
+
+    let mut metadata = serde_json::json!({
+        "version": req.version,
+        "versionTag": req.version_tag,
+        "appName": req.app_name,
+        "serverUrl": server_url,
+        "plan": plan_lines(&plan),
+    });
+    hooks::run(
+        "pre-apply",
+        &manifest.hooks.pre_apply,
+        &manifest.modules,
+        &metadata,
+    )?;
+
+    req.sources = sources;
+    if let Some(msg) = send_apply(server_url, req).await? {
+        metadata["applied"] = serde_json::json!({
+            "models": msg.types,
+            "routes": msg.endpoints,
+            "eventHandlers": msg.event_handlers,
+            "jobs": msg.jobs,
+            "tasks": msg.tasks,
+            "labels": msg.labels,
+        });
+        hooks::run(
+            "post-apply",
+            &manifest.hooks.post_apply,
+            &manifest.modules,
+            &metadata,
+        )?;
+    }
+    Ok(())
+}
+
+/// Applies `req`, unless the server stages it because its changes need
+/// approval. Returns the response of the server if applied.
+pub(crate) async fn send_apply(
+    server_url: String,
+    mut req: ChiselApplyRequest,
+) -> Result<Option<ChiselApplyResponse>> {
+    let mut client = connect(server_url.clone()).await?;
+    let sources = std::mem::take(&mut req.sources);
 
     // According to the spec
     // (https://html.spec.whatwg.org/multipage/webappapis.html#module-map),
@@ -173,8 +348,19 @@ pub(crate) async fn apply(
     //
     // FIXME: We should have a more fine gained way to recreate just
     // the worker without loading the sources from the DB.
-    execute!(client.apply(tonic::Request::new(req.clone())).await);
+    let msg = execute!(client.apply(tonic::Request::new(req.clone())).await);
     req.sources = sources;
+    if msg.staged_migration.is_some() {
+        // Stage the apply again, with its code, for it to be complete when
+        // approved.
+        let msg = execute!(client.apply(tonic::Request::new(req)).await);
+        let id = msg.staged_migration.unwrap_or_default();
+        println!(
+            "Not applied: dropping models or fields needs approval. Run `chisel migrate approve {}` to apply it.",
+            id
+        );
+        return Ok(None);
+    }
     crate::restart(server_url).await?;
 
     let msg = execute!(client.apply(tonic::Request::new(req)).await);
@@ -189,11 +375,68 @@ pub(crate) async fn apply(
     if !msg.event_handlers.is_empty() {
         println!("  {} event handlers", msg.event_handlers.len());
     }
+    if !msg.jobs.is_empty() {
+        println!("  {} jobs", msg.jobs.len());
+    }
+    if !msg.tasks.is_empty() {
+        println!("  {} tasks", msg.tasks.len());
+    }
     if !msg.labels.is_empty() {
         println!("  {} labels", msg.labels.len());
     }
 
-    Ok(())
+    Ok(Some(msg))
+}
+
+fn print_plan(plan: &ApplyPlan) {
+    let lines = plan_lines(plan);
+    if !lines.is_empty() {
+        println!("Plan:");
+        for line in lines {
+            println!("  {}", line);
+        }
+    }
+}
+
+fn plan_lines(plan: &ApplyPlan) -> Vec<String> {
+    let mut lines = vec![];
+    for ty in &plan.types {
+        let (sign, rows_lost) = match ty.action() {
+            Action::Create => ("+", ""),
+            Action::Alter => ("~", "lose values in"),
+            Action::Drop => ("-", "delete"),
+        };
+        if ty.rows_lost > 0 {
+            lines.push(format!(
+                "{} model {} (will {} {} rows)",
+                sign, ty.name, rows_lost, ty.rows_lost
+            ));
+        } else {
+            lines.push(format!("{} model {}", sign, ty.name));
+        }
+        for change in &ty.changes {
+            lines.push(format!("    {}", change));
+        }
+        for index in &ty.indexes {
+            lines.push(format!("    build index on ({})", index));
+        }
+    }
+    for path in &plan.endpoints_added {
+        lines.push(format!("+ route {}", path));
+    }
+    for path in &plan.endpoints_removed {
+        lines.push(format!("- route {}", path));
+    }
+    for path in &plan.event_handlers_added {
+        lines.push(format!("+ event handler {}", path));
+    }
+    for path in &plan.event_handlers_removed {
+        lines.push(format!("- event handler {}", path));
+    }
+    if plan.policies_changed {
+        lines.push("~ policies".to_owned());
+    }
+    lines
 }
 
 fn parse_indexes(code: String, entities: &[String]) -> Result<Vec<IndexCandidate>> {
@@ -219,6 +462,26 @@ fn parse_indexes(code: String, entities: &[String]) -> Result<Vec<IndexCandidate
     Ok(index_candidates)
 }
 
+/// Warn about filters in `path` that the optimizer can't transform into
+/// query expressions, because they are evaluated over a full table scan.
+fn report_filter_fallbacks(path: &str, code: String, entities: &[String]) -> Result<()> {
+    let fallbacks = chiselc_output(code, "filter-fallbacks", entities)?;
+    let fallbacks: Value = serde_json::from_str(&fallbacks)?;
+    if let Some(fallbacks) = fallbacks.as_array() {
+        for fallback in fallbacks {
+            println!(
+                "Warning: {}:{}: filter on `{}` can't be translated to a database query \
+                 and will scan the whole table: {}",
+                path,
+                fallback["line"],
+                fallback["entity_name"].as_str().unwrap_or_default(),
+                fallback["expression"].as_str().unwrap_or_default(),
+            );
+        }
+    }
+    Ok(())
+}
+
 fn output_to_string(out: &std::process::Output) -> Option<String> {
     Some(
         std::str::from_utf8(&out.stdout)
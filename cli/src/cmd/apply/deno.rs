@@ -2,7 +2,9 @@
 
 use crate::cmd::apply::chiselc_output;
 use crate::cmd::apply::parse_indexes;
+use crate::cmd::apply::report_filter_fallbacks;
 use crate::cmd::apply::SourceMap;
+use crate::project::read_to_string;
 use crate::proto::IndexCandidate;
 use anyhow::{anyhow, Context, Result};
 use endpoint_tsc::compile_endpoints;
@@ -11,12 +13,18 @@ use std::path::PathBuf;
 pub(crate) async fn apply(
     endpoints: &[PathBuf],
     events: &[PathBuf],
+    jobs: &[PathBuf],
+    tasks: &[PathBuf],
     entities: &[String],
     optimize: bool,
-    auto_index: bool,
+    find_indexes: bool,
 ) -> Result<(SourceMap, Vec<IndexCandidate>)> {
     let mut index_candidates = vec![];
-    let modules = endpoints.iter().chain(events.iter());
+    let modules = endpoints
+        .iter()
+        .chain(events.iter())
+        .chain(jobs.iter())
+        .chain(tasks.iter());
     let paths: Result<Vec<_>> = modules
         .clone()
         .map(|f| f.to_str().ok_or_else(|| anyhow!("Path is not UTF8")))
@@ -28,10 +36,11 @@ pub(crate) async fn apply(
         let path = f.to_str().unwrap();
         let orig = output.get_mut(path).unwrap();
         if optimize {
+            report_filter_fallbacks(path, read_to_string(f)?, entities)?;
             *orig = chiselc_output(orig.to_string(), "js", entities)?;
         }
 
-        if auto_index {
+        if find_indexes {
             let mut indexes = parse_indexes(orig.clone(), entities)?;
             index_candidates.append(&mut indexes);
         }
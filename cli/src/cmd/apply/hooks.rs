@@ -0,0 +1,85 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! The hooks that `chisel apply` runs before and after applying, from the
+//! `[hooks]` table of the manifest.
+//!
+//! A hook is either a shell command, or the path of a TypeScript or
+//! JavaScript module whose default export is a function. Both get the
+//! metadata of the apply as JSON in the `CHISEL_APPLY` environment
+//! variable, and functions also get it parsed as their argument. Functions
+//! are run by `deno` in projects with Deno modules, and by `ts-node`
+//! otherwise.
+
+use crate::project::Module;
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::path::Path;
+use std::process::Command;
+
+/// Runs `hooks` in order, in the project directory, stopping at the first
+/// that fails.
+pub(crate) fn run(stage: &str, hooks: &[String], modules: &Module, metadata: &Value) -> Result<()> {
+    let metadata = metadata.to_string();
+    for hook in hooks {
+        println!("Running {} hook `{}`", stage, hook);
+        let mut cmd = command(hook, modules)?;
+        let status = cmd
+            .env("CHISEL_APPLY", &metadata)
+            .status()
+            .with_context(|| format!("could not run {} hook `{}`", stage, hook))?;
+        anyhow::ensure!(
+            status.success(),
+            "{} hook `{}` failed with {}",
+            stage,
+            hook,
+            status
+        );
+    }
+    Ok(())
+}
+
+fn command(hook: &str, modules: &Module) -> Result<Command> {
+    let is_module = [".ts", ".js", ".mjs"].iter().any(|ext| hook.ends_with(ext))
+        && !hook.contains(char::is_whitespace);
+    if !is_module {
+        return Ok(shell(hook));
+    }
+    let path = Path::new(hook)
+        .canonicalize()
+        .with_context(|| format!("hook module `{}` not found", hook))?;
+    let path = path.display().to_string().replace('\\', "/");
+    Ok(match modules {
+        Module::Deno => {
+            let code = format!(
+                "import hook from 'file:///{}'; \
+                 await hook(JSON.parse(Deno.env.get('CHISEL_APPLY')));",
+                path.trim_start_matches('/')
+            );
+            let mut cmd = Command::new("deno");
+            cmd.args(["eval", &code]);
+            cmd
+        }
+        Module::Node => {
+            let code = format!(
+                "Promise.resolve(require('{}').default(JSON.parse(process.env.CHISEL_APPLY))) \
+                 .catch((e) => {{ console.error(e); process.exit(1); }});",
+                path
+            );
+            let mut cmd = Command::new("npx");
+            cmd.args(["ts-node", "-e", &code]);
+            cmd
+        }
+    })
+}
+
+fn shell(hook: &str) -> Command {
+    if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", hook]);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", hook]);
+        cmd
+    }
+}
@@ -0,0 +1,115 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::project::{Lint, LintLevel};
+use crate::proto::{type_msg::TypeEnum, AddTypeRequest, IndexCandidate};
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+
+/// Checks `types` against the `lint` rules, printing warnings and failing if
+/// a rule configured as an error is broken. `filtered` are the properties
+/// the routes filter by that won't be auto-indexed, or None if there are
+/// none or they are unknown.
+pub(crate) fn check(
+    lint: &Lint,
+    types: &[AddTypeRequest],
+    filtered: Option<&[IndexCandidate]>,
+) -> Result<()> {
+    let mut findings = vec![];
+
+    if let Some(filtered) = filtered {
+        let mut seen = vec![];
+        for candidate in filtered {
+            if is_indexed(types, candidate) || seen.contains(&candidate) {
+                continue;
+            }
+            seen.push(candidate);
+            findings.push((
+                lint.missing_index,
+                "missing_index",
+                format!(
+                    "{} is filtered by ({}), which has no index",
+                    candidate.entity_name,
+                    candidate.properties.join(", ")
+                ),
+            ));
+        }
+    }
+
+    let pii_fields = patterns(&lint.pii_fields).context("invalid pii_fields pattern")?;
+    let money_fields = patterns(&lint.money_fields).context("invalid money_fields pattern")?;
+    for ty in types {
+        for field in &ty.field_defs {
+            let named_like = |patterns: &[Regex]| patterns.iter().any(|p| p.is_match(&field.name));
+            if field.labels.is_empty() && named_like(&pii_fields) {
+                findings.push((
+                    lint.pii_labels,
+                    "pii_labels",
+                    format!(
+                        "{}.{} looks like personal data, but has no label",
+                        ty.name, field.name
+                    ),
+                ));
+            }
+            if matches!(field.field_type()?, TypeEnum::Number(_)) && named_like(&money_fields) {
+                findings.push((
+                    lint.money_float,
+                    "money_float",
+                    format!(
+                        "{}.{} looks like an amount of money, but is a floating-point number",
+                        ty.name, field.name
+                    ),
+                ));
+            }
+        }
+    }
+
+    let mut errors = vec![];
+    for (level, rule, message) in findings {
+        match level {
+            LintLevel::Off => {}
+            LintLevel::Warn => println!("Warning: {} [{}]", message, rule),
+            LintLevel::Error => errors.push(format!("{} [{}]", message, rule)),
+        }
+    }
+    anyhow::ensure!(
+        errors.is_empty(),
+        "The models break the lint rules of Chisel.toml:\n  {}",
+        errors.join("\n  ")
+    );
+    Ok(())
+}
+
+/// Returns whether filtering by `candidate` uses an index even without
+/// auto-indexing: the primary key, a unique field or a declared index.
+fn is_indexed(types: &[AddTypeRequest], candidate: &IndexCandidate) -> bool {
+    let declared = types
+        .iter()
+        .filter(|ty| ty.name == candidate.entity_name)
+        .flat_map(|ty| &ty.indexes)
+        .any(|index| !index.search && !index.counted && index.fields == candidate.properties);
+    if declared {
+        return true;
+    }
+    let field = match candidate.properties.as_slice() {
+        [field] => field,
+        _ => return false,
+    };
+    field == "id"
+        || types
+            .iter()
+            .filter(|ty| ty.name == candidate.entity_name)
+            .flat_map(|ty| &ty.field_defs)
+            .any(|f| &f.name == field && f.is_unique)
+}
+
+fn patterns(patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|p| {
+            RegexBuilder::new(p)
+                .case_insensitive(true)
+                .build()
+                .with_context(|| format!("bad regular expression {:?}", p))
+        })
+        .collect()
+}
@@ -2,6 +2,7 @@
 
 use crate::cmd::apply::chiselc_spawn;
 use crate::cmd::apply::parse_indexes;
+use crate::cmd::apply::report_filter_fallbacks;
 use crate::cmd::apply::{SourceMap, TypeChecking};
 use crate::project::read_to_string;
 use crate::proto::IndexCandidate;
@@ -16,9 +17,11 @@ use tokio::task::{spawn_blocking, JoinHandle};
 pub(crate) async fn apply(
     endpoints: &[PathBuf],
     events: &[PathBuf],
+    jobs: &[PathBuf],
+    tasks: &[PathBuf],
     entities: &[String],
     optimize: bool,
-    auto_index: bool,
+    find_indexes: bool,
     type_check: &TypeChecking,
 ) -> Result<(SourceMap, Vec<IndexCandidate>)> {
     let mut sources = SourceMap::new();
@@ -48,6 +51,11 @@ pub(crate) async fn apply(
 
     let mut handle_code = |endpoint: &PathBuf, gen_dir: &PathBuf| {
         if optimize {
+            report_filter_fallbacks(
+                &endpoint.display().to_string(),
+                read_to_string(endpoint)?,
+                entities,
+            )?;
             let endpoint_file_path = endpoint.clone();
             let mut components = endpoint_file_path.components();
             components.next();
@@ -100,6 +108,20 @@ pub(crate) async fn apply(
         handle_code(event, &event_gen_dir)?
     }
 
+    let job_gen_dir = cwd.join(".jobgen");
+    fs::create_dir_all(&job_gen_dir)?;
+
+    for job in jobs.iter() {
+        handle_code(job, &job_gen_dir)?
+    }
+
+    let task_gen_dir = cwd.join(".taskgen");
+    fs::create_dir_all(&task_gen_dir)?;
+
+    for task in tasks.iter() {
+        handle_code(task, &task_gen_dir)?
+    }
+
     let mut bundler_file_mapping = vec![];
 
     let mut bundler_cmd_args = vec![];
@@ -126,10 +148,11 @@ pub(crate) async fn apply(
         let mut import_path = import_path.clone();
         import_path.set_extension("");
 
+        // Named exports, like the schedule of a job, are kept too.
+        let module = format!("{}/{}", cwd.display(), import_path.display());
         let code = format!(
-            "import fun from \"{}/{}\";\nexport default fun",
-            cwd.display(),
-            import_path.display()
+            "import fun from \"{0}\";\nexport * from \"{0}\";\nexport default fun",
+            module
         );
         file.write_all(code.as_bytes())?;
         file.flush()?;
@@ -166,7 +189,7 @@ pub(crate) async fn apply(
         let code = read_to_string(bundler_output_file)?;
 
         sources.insert(endpoint_file_path.display().to_string(), code);
-        if auto_index {
+        if find_indexes {
             let code = read_to_string(endpoint_file_path.clone())?;
             let mut indexes = parse_indexes(code, entities)?;
             index_candidates.append(&mut indexes);
@@ -176,6 +199,46 @@ pub(crate) async fn apply(
     Ok((sources, index_candidates))
 }
 
+/// Bundles each of the `tests` modules with the modules it imports. Also
+/// returns the directory the bundles were written to, which the paths in
+/// their inline source maps are relative to.
+pub(crate) async fn bundle_tests(
+    tests: &[PathBuf],
+    source_maps: bool,
+) -> Result<(SourceMap, PathBuf)> {
+    let output_dir = tempfile::tempdir()?;
+    let mut args: Vec<String> = tests.iter().map(|t| t.display().to_string()).collect();
+    args.extend_from_slice(&[
+        "--bundle".to_string(),
+        "--color=true".to_string(),
+        "--target=esnext".to_string(),
+        "--external:@chiselstrike".to_string(),
+        "--format=esm".to_string(),
+        "--tsconfig=./tsconfig.json".to_string(),
+        "--platform=node".to_string(),
+        "--outbase=.".to_string(),
+    ]);
+    if source_maps {
+        args.push("--sourcemap=inline".to_string());
+    }
+    args.push(format!("--outdir={}", output_dir.path().display()));
+    let res = npx("esbuild", &args, None).await.unwrap()?;
+    if !res.status.success() {
+        let out = String::from_utf8(res.stdout).expect("command output not utf-8");
+        let err = String::from_utf8(res.stderr).expect("command output not utf-8");
+        return Err(anyhow!("{}\n{}", out, err))
+            .context("Could not bundle tests with esbuild (using node-style modules)");
+    }
+
+    let mut sources = SourceMap::new();
+    for test in tests {
+        let mut output = output_dir.path().join(test);
+        output.set_extension("js");
+        sources.insert(test.display().to_string(), read_to_string(output)?);
+    }
+    Ok((sources, output_dir.path().to_path_buf()))
+}
+
 fn npx<A: AsRef<OsStr>>(
     command: &'static str,
     args: &[A],
@@ -0,0 +1,573 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::proto::{AddTypeRequest, PolicyUpdateRequest};
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+/// The transforms of values, which routes can also apply to labels.
+const VALUE_TRANSFORMS: &[&str] = &[
+    "anonymize",
+    "redact",
+    "hash",
+    "truncate(<length>)",
+    "mask-email",
+];
+const TRANSFORMS: &[&str] = &[
+    "anonymize",
+    "redact",
+    "hash",
+    "truncate(<length>)",
+    "mask-email",
+    "omit",
+    "match_login",
+    "match_tenant",
+];
+const QUOTA_LIMITS: &[&str] = &["requests_per_minute", "cpu_ms_per_minute", "rows"];
+const WEBHOOK_EVENTS: &[&str] = &["create", "update", "delete"];
+
+/// Checks the YAML policy files of `policies` before they are sent to the
+/// server, which would silently ignore unknown keys and labels that no
+/// field of `types` has. Prints warnings, and fails listing the errors with
+/// their positions if there are any.
+pub(crate) fn check(policies: &[PolicyUpdateRequest], types: &[AddTypeRequest]) -> Result<()> {
+    let field_labels: HashSet<&str> = types
+        .iter()
+        .flat_map(|ty| &ty.field_defs)
+        .flat_map(|field| &field.labels)
+        .map(String::as_str)
+        .collect();
+    let mut checker = Checker {
+        path: "",
+        field_labels,
+        entities: types.iter().map(|ty| ty.name.as_str()).collect(),
+        errors: vec![],
+        warnings: vec![],
+        labels: HashMap::default(),
+        tenant: None,
+        routes: HashMap::default(),
+    };
+    for policy in policies {
+        if Path::new(&policy.path).extension().and_then(|s| s.to_str()) == Some("ts") {
+            continue;
+        }
+        checker.check_file(&policy.path, &policy.policy_config);
+    }
+    for warning in &checker.warnings {
+        println!("Warning: {}", warning);
+    }
+    anyhow::ensure!(
+        checker.errors.is_empty(),
+        "The policies are invalid:\n  {}",
+        checker.errors.join("\n  ")
+    );
+    Ok(())
+}
+
+/// A YAML node, with the position it starts at.
+struct Node {
+    kind: NodeKind,
+    line: usize,
+    col: usize,
+}
+
+enum NodeKind {
+    Scalar(String),
+    Seq(Vec<Node>),
+    Map(Vec<(Node, Node)>),
+    Alias,
+}
+
+impl Node {
+    fn new(kind: NodeKind, mark: Marker) -> Self {
+        Node {
+            kind,
+            line: mark.line(),
+            col: mark.col() + 1,
+        }
+    }
+}
+
+/// Builds the documents of a YAML file from the events of its parser.
+#[derive(Default)]
+struct TreeBuilder {
+    docs: Vec<Node>,
+    /// Collections being built, with the key waiting for its value if the
+    /// collection is a map.
+    stack: Vec<(Node, Option<Node>)>,
+}
+
+impl TreeBuilder {
+    fn add(&mut self, node: Node) {
+        match self.stack.last_mut() {
+            None => self.docs.push(node),
+            Some((parent, pending_key)) => match &mut parent.kind {
+                NodeKind::Seq(elements) => elements.push(node),
+                NodeKind::Map(entries) => match pending_key.take() {
+                    Some(key) => entries.push((key, node)),
+                    None => *pending_key = Some(node),
+                },
+                _ => unreachable!("only collections are on the stack"),
+            },
+        }
+    }
+}
+
+impl MarkedEventReceiver for TreeBuilder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(value, ..) => self.add(Node::new(NodeKind::Scalar(value), mark)),
+            Event::Alias(_) => self.add(Node::new(NodeKind::Alias, mark)),
+            Event::SequenceStart(_) => self
+                .stack
+                .push((Node::new(NodeKind::Seq(vec![]), mark), None)),
+            Event::MappingStart(_) => self
+                .stack
+                .push((Node::new(NodeKind::Map(vec![]), mark), None)),
+            Event::SequenceEnd | Event::MappingEnd => {
+                let (node, _) = self.stack.pop().expect("unbalanced YAML events");
+                self.add(node);
+            }
+            _ => {}
+        }
+    }
+}
+
+struct Checker<'a> {
+    /// Path of the file being checked.
+    path: &'a str,
+    field_labels: HashSet<&'a str>,
+    entities: HashSet<&'a str>,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+    /// Where each label was defined.
+    labels: HashMap<String, String>,
+    /// Where the tenant was configured.
+    tenant: Option<String>,
+    /// Where each route path was given users, claims or a mandatory header.
+    routes: HashMap<(String, &'static str), String>,
+}
+
+impl<'a> Checker<'a> {
+    fn check_file(&mut self, path: &'a str, yaml: &str) {
+        self.path = path;
+        let mut builder = TreeBuilder::default();
+        if let Err(e) = Parser::new(yaml.chars()).load(&mut builder, true) {
+            self.errors.push(format!("{}: {}", path, e));
+            return;
+        }
+        for doc in &builder.docs {
+            self.check_doc(doc);
+        }
+    }
+
+    fn position(&self, node: &Node) -> String {
+        format!("{}:{}:{}", self.path, node.line, node.col)
+    }
+
+    fn error(&mut self, node: &Node, msg: String) {
+        self.errors
+            .push(format!("{}: {}", self.position(node), msg));
+    }
+
+    /// Returns the entries of map `node`, reporting the keys which aren't
+    /// `keys` or are repeated. Any key is allowed if `keys` is None.
+    fn map<'n>(
+        &mut self,
+        node: &'n Node,
+        what: &str,
+        keys: Option<&[&str]>,
+    ) -> Vec<(&'n str, &'n Node)> {
+        let entries = match &node.kind {
+            NodeKind::Map(entries) => entries,
+            // An empty document or section.
+            NodeKind::Scalar(s) if s.is_empty() || s == "~" || s == "null" => return vec![],
+            _ => {
+                self.error(node, format!("{} must be a map", what));
+                return vec![];
+            }
+        };
+        let mut ret: Vec<(&str, &Node)> = vec![];
+        for (key, value) in entries {
+            let name = match &key.kind {
+                NodeKind::Scalar(name) => name.as_str(),
+                _ => {
+                    self.error(key, format!("the keys of {} must be strings", what));
+                    continue;
+                }
+            };
+            if let Some(keys) = keys {
+                if !keys.contains(&name) {
+                    self.error(
+                        key,
+                        format!(
+                            "unknown key `{}` in {}, expected one of: {}",
+                            name,
+                            what,
+                            keys.join(", ")
+                        ),
+                    );
+                    continue;
+                }
+            }
+            if ret.iter().any(|(other, _)| *other == name) {
+                self.error(key, format!("key `{}` is repeated in {}", name, what));
+                continue;
+            }
+            ret.push((name, value));
+        }
+        ret
+    }
+
+    fn seq<'n>(&mut self, node: &'n Node, what: &str) -> &'n [Node] {
+        match &node.kind {
+            NodeKind::Seq(elements) => elements,
+            _ => {
+                self.error(node, format!("{} must be a list", what));
+                &[]
+            }
+        }
+    }
+
+    fn string<'n>(&mut self, node: &'n Node, what: &str) -> Option<&'n str> {
+        match &node.kind {
+            NodeKind::Scalar(s) => Some(s),
+            _ => {
+                self.error(node, format!("{} must be a string", what));
+                None
+            }
+        }
+    }
+
+    /// Returns the strings of `node`, either one string or a list of them.
+    fn strings<'n>(&mut self, node: &'n Node, what: &str) -> Vec<(&'n Node, &'n str)> {
+        match &node.kind {
+            NodeKind::Seq(elements) => elements
+                .iter()
+                .filter_map(|element| Some((element, self.string(element, what)?)))
+                .collect(),
+            _ => self
+                .string(node, what)
+                .map(|s| (node, s))
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    fn regex(&mut self, node: &Node, what: &str) {
+        if let Some(pattern) = self.string(node, what) {
+            if let Err(e) = regex::Regex::new(pattern) {
+                self.error(
+                    node,
+                    format!("{} is not a valid regular expression: {}", what, e),
+                );
+            }
+        }
+    }
+
+    fn check_doc(&mut self, doc: &Node) {
+        let sections = [
+            "labels",
+            "tenant",
+            "webhooks",
+            "rows",
+            "routes",
+            "endpoints",
+        ];
+        for (key, value) in self.map(doc, "the policies", Some(&sections)) {
+            match key {
+                "labels" => {
+                    for label in self.seq(value, "labels") {
+                        self.check_label(label);
+                    }
+                }
+                "tenant" => self.check_tenant(value),
+                "webhooks" => {
+                    for webhook in self.seq(value, "webhooks") {
+                        self.check_webhook(webhook);
+                    }
+                }
+                "rows" => {
+                    for rule in self.seq(value, "rows") {
+                        self.check_row_policy(rule);
+                    }
+                }
+                _ => {
+                    for route in self.seq(value, key) {
+                        self.check_route(route);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checks that `node` is one of `transforms`.
+    fn transform(&mut self, node: &Node, transforms: &[&str]) {
+        let transform = match self.string(node, "the transform") {
+            Some(transform) => transform,
+            None => return,
+        };
+        let is_truncate = transform
+            .strip_prefix("truncate(")
+            .and_then(|s| s.strip_suffix(')'))
+            .map_or(false, |length| length.trim().parse::<usize>().is_ok());
+        if !is_truncate && !transforms.contains(&transform) {
+            self.error(
+                node,
+                format!(
+                    "unknown transform `{}`, expected one of: {}",
+                    transform,
+                    transforms.join(", ")
+                ),
+            );
+        }
+    }
+
+    fn check_label(&mut self, label: &Node) {
+        let entries = self.map(label, "a label", Some(&["name", "transform", "except_uri"]));
+        let mut name = None;
+        for (key, value) in entries {
+            match key {
+                "name" => name = self.string(value, "the label name"),
+                "transform" => self.transform(value, TRANSFORMS),
+                _ => self.regex(value, "except_uri"),
+            }
+        }
+        let name = match name {
+            Some(name) => name,
+            None => return self.error(label, "label without a name".to_owned()),
+        };
+        let position = self.position(label);
+        if let Some(previous) = self.labels.insert(name.to_owned(), position) {
+            self.error(
+                label,
+                format!("label `{}` is already defined at {}", name, previous),
+            );
+        }
+        if !self.field_labels.contains(name) {
+            self.warnings.push(format!(
+                "{}: label `{}` is used by no field, so its policy has no effect",
+                self.position(label),
+                name
+            ));
+        }
+    }
+
+    fn check_tenant(&mut self, tenant: &Node) {
+        let sources = ["header", "subdomain_of", "claim"];
+        let keys = ["header", "subdomain_of", "claim", "quotas"];
+        let entries = self.map(tenant, "the tenant", Some(&keys));
+        let mut configured = vec![];
+        for (key, value) in entries {
+            if key == "quotas" {
+                self.check_quotas(value);
+            } else {
+                self.string(value, key);
+                configured.push(key);
+            }
+        }
+        if configured.len() != 1 {
+            self.error(
+                tenant,
+                format!(
+                    "the tenant must have exactly one of: {}",
+                    sources.join(", ")
+                ),
+            );
+        }
+        let position = self.position(tenant);
+        if let Some(previous) = self.tenant.replace(position) {
+            self.error(
+                tenant,
+                format!("the tenant is already configured at {}", previous),
+            );
+        }
+    }
+
+    fn check_quotas(&mut self, quotas: &Node) {
+        let keys: Vec<&str> = QUOTA_LIMITS.iter().copied().chain(["tenants"]).collect();
+        for (key, value) in self.map(quotas, "the quotas", Some(&keys)) {
+            if key == "tenants" {
+                for (_, quota) in self.map(value, "the tenant quotas", None) {
+                    for (name, limit) in self.map(quota, "a quota", Some(QUOTA_LIMITS)) {
+                        self.check_limit(name, limit);
+                    }
+                }
+            } else {
+                self.check_limit(key, value);
+            }
+        }
+    }
+
+    fn check_limit(&mut self, name: &str, limit: &Node) {
+        let valid = matches!(&limit.kind, NodeKind::Scalar(s) if s.parse::<u64>().is_ok());
+        if !valid {
+            self.error(
+                limit,
+                format!("quota {} must be a non-negative integer", name),
+            );
+        }
+    }
+
+    fn check_webhook(&mut self, webhook: &Node) {
+        let keys = ["url", "secret_value_ref", "entities", "events"];
+        let entries = self.map(webhook, "a webhook", Some(&keys));
+        if !entries.iter().any(|(key, _)| *key == "url") {
+            self.error(webhook, "webhook without a url".to_owned());
+        }
+        for (key, value) in entries {
+            match key {
+                "entities" => {
+                    for (node, entity) in self.strings(value, "an entity") {
+                        if !self.entities.contains(entity) {
+                            self.warnings.push(format!(
+                                "{}: webhook entity `{}` is no entity, so it gets no events",
+                                self.position(node),
+                                entity
+                            ));
+                        }
+                    }
+                }
+                "events" => {
+                    for (node, event) in self.strings(value, "an event") {
+                        if !WEBHOOK_EVENTS.contains(&event) {
+                            self.error(
+                                node,
+                                format!(
+                                    "unknown webhook event `{}`, expected one of: {}",
+                                    event,
+                                    WEBHOOK_EVENTS.join(", ")
+                                ),
+                            );
+                        }
+                    }
+                }
+                _ => {
+                    self.string(value, key);
+                }
+            }
+        }
+    }
+
+    /// Checks a row policy. Its condition is parsed by the server.
+    fn check_row_policy(&mut self, rule: &Node) {
+        let entries = self.map(rule, "a row policy", Some(&["match", "read"]));
+        for required in ["match", "read"] {
+            if !entries.iter().any(|(key, _)| *key == required) {
+                self.error(rule, format!("row policy without `{}`", required));
+            }
+        }
+        for (key, value) in entries {
+            if key == "read" {
+                self.string(value, "the read condition");
+                continue;
+            }
+            if let Some(entity) = self.string(value, "the matched entity") {
+                if !self.entities.contains(entity) {
+                    // A typo would leave the rows unprotected.
+                    self.error(
+                        value,
+                        format!("row policy matches `{}`, which is no entity", entity),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_route(&mut self, route: &Node) {
+        let entries = self.map(
+            route,
+            "a route",
+            Some(&[
+                "path",
+                "users",
+                "claims",
+                "api_key",
+                "mandatory_header",
+                "transforms",
+            ]),
+        );
+        let path = entries
+            .iter()
+            .find(|(key, _)| *key == "path")
+            .map(|&(_, value)| value)
+            .and_then(|value| self.string(value, "the route path"));
+        let path = match path {
+            Some(path) => path,
+            None => return self.error(route, "route without a path".to_owned()),
+        };
+        for (key, value) in entries {
+            let rule = match key {
+                "users" => {
+                    self.regex(value, "users");
+                    "users"
+                }
+                "claims" => {
+                    for (name, pattern) in self.map(value, "the route claims", None) {
+                        self.regex(pattern, &format!("claim {}", name));
+                    }
+                    "claims"
+                }
+                "api_key" => {
+                    if !matches!(&value.kind, NodeKind::Scalar(s) if s == "true" || s == "false") {
+                        self.error(value, "api_key must be true or false".to_owned());
+                    }
+                    "api_key"
+                }
+                "mandatory_header" => {
+                    self.check_header(value);
+                    "mandatory_header"
+                }
+                "transforms" => {
+                    for (label, transform) in self.map(value, "the route transforms", None) {
+                        self.transform(transform, VALUE_TRANSFORMS);
+                        if !self.field_labels.contains(label) {
+                            self.warnings.push(format!(
+                                "{}: label `{}` is used by no field, so its transform has no effect",
+                                self.position(transform),
+                                label
+                            ));
+                        }
+                    }
+                    "transforms"
+                }
+                _ => continue,
+            };
+            let position = self.position(value);
+            if let Some(previous) = self.routes.insert((path.to_owned(), rule), position) {
+                self.error(
+                    value,
+                    format!(
+                        "{} of route {} is already configured at {}",
+                        rule, path, previous
+                    ),
+                );
+            }
+        }
+    }
+
+    fn check_header(&mut self, header: &Node) {
+        let keys = ["name", "secret_value_ref", "only_for_methods"];
+        let entries = self.map(header, "the mandatory header", Some(&keys));
+        for required in ["name", "secret_value_ref"] {
+            if !entries.iter().any(|(key, _)| *key == required) {
+                self.error(header, format!("the mandatory header has no {}", required));
+            }
+        }
+        for (key, value) in entries {
+            if key != "only_for_methods" {
+                self.string(value, key);
+                continue;
+            }
+            if let NodeKind::Seq(methods) = &value.kind {
+                for method in methods {
+                    self.string(method, "a method");
+                }
+            } else {
+                self.string(value, "only_for_methods");
+            }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
-use crate::cmd::apply::{apply, AllowTypeDeletion, TypeChecking};
+use crate::cmd::apply::{apply, AllowDataLoss, AllowTypeDeletion, TypeChecking};
 use crate::project::read_manifest;
 use crate::server::wait;
 use crate::DEFAULT_API_VERSION;
@@ -71,6 +71,20 @@ pub(crate) async fn cmd_dev(
             tracked.insert(dir);
         }
     }
+
+    if let Some(jobs) = &manifest.jobs {
+        for dir in jobs {
+            let dir = cwd.join(dir);
+            tracked.insert(dir);
+        }
+    }
+
+    if let Some(tasks) = &manifest.tasks {
+        for dir in tasks {
+            let dir = cwd.join(dir);
+            tracked.insert(dir);
+        }
+    }
     apply_watcher.watch(&cwd, RecursiveMode::Recursive)?;
 
     loop {
@@ -115,6 +129,8 @@ async fn apply_from_dev(server_url: String, type_check: TypeChecking) {
         server_url,
         DEFAULT_API_VERSION.to_string(),
         AllowTypeDeletion::No,
+        // Models under development evolve without asking for confirmation.
+        AllowDataLoss::Yes,
         type_check,
     )
     .await
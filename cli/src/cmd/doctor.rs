@@ -0,0 +1,104 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! `chisel doctor`, which checks that the server is up and works with this
+//! chisel, and shows the crash reports it left behind.
+
+use crate::proto::chisel_rpc_client::ChiselRpcClient;
+use crate::proto::StatusRequest;
+use crate::server::check_version;
+use anyhow::{anyhow, Context, Result};
+use serde_derive::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// How many of the latest crash reports are shown.
+const RECENT_CRASHES: usize = 5;
+
+/// The parts of a crash report of chiseld that are shown.
+#[derive(Deserialize)]
+struct CrashReport {
+    time: String,
+    kind: String,
+    message: String,
+    location: Option<String>,
+    #[serde(default)]
+    requests: Vec<ActiveRequest>,
+}
+
+#[derive(Deserialize)]
+struct ActiveRequest {
+    method: String,
+    path: String,
+}
+
+pub(crate) async fn cmd_doctor(server_url: String, crash_dir: &Path) -> Result<()> {
+    match server_status(server_url.clone()).await {
+        Ok((status, Ok(version))) => {
+            println!(
+                "Server at {} is {}, running chiseld {}",
+                server_url, status, version
+            )
+        }
+        Ok((status, Err(e))) => println!("Server at {} is {}, but {}", server_url, status, e),
+        Err(e) => println!("Server at {} is not reachable: {}", server_url, e),
+    }
+
+    let reports = crash_reports(crash_dir)?;
+    if reports.is_empty() {
+        println!("No crashes reported in {}", crash_dir.display());
+        return Ok(());
+    }
+    println!(
+        "{} crashes reported in {}, latest first:",
+        reports.len(),
+        crash_dir.display()
+    );
+    for path in reports.iter().rev().take(RECENT_CRASHES) {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        let report: CrashReport = serde_json::from_str(&json)
+            .with_context(|| format!("Could not parse {}", path.display()))?;
+        let message = report.message.lines().next().unwrap_or_default();
+        println!("  {} {}: {}", report.time, report.kind, message);
+        if let Some(location) = &report.location {
+            println!("    at {}", location);
+        }
+        for request in &report.requests {
+            println!("    while running {} {}", request.method, request.path);
+        }
+        println!("    report: {}", path.display());
+    }
+    Ok(())
+}
+
+/// The status of the server, and its version if it works with this chisel.
+async fn server_status(server_url: String) -> Result<(String, Result<String>)> {
+    let mut client = ChiselRpcClient::connect(server_url.clone()).await?;
+    let request = tonic::Request::new(StatusRequest { verbose: false });
+    let response = execute!(client.get_status(request).await);
+    let version = check_version(&mut client, &server_url).await;
+    Ok((response.message, version))
+}
+
+/// The crash reports in `dir`, oldest first.
+fn crash_reports(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut reports = vec![];
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("Could not read {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        if name.starts_with("crash-") && name.ends_with(".json") {
+            reports.push(path);
+        }
+    }
+    // Reports are named after the time of the crash in milliseconds, which
+    // all have the same number of digits for a long time to come.
+    reports.sort();
+    Ok(reports)
+}
@@ -0,0 +1,377 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Generation of a typed TypeScript client of the API of a version, from
+//! what the server describes of it.
+//!
+//! The client has an interface per entity and a property per endpoint.
+//! Endpoints serving the CRUD API of an entity get typed methods; others
+//! only get a method per HTTP method, since their request and response
+//! types aren't known.
+
+use crate::proto::type_msg::TypeEnum;
+use crate::proto::{TypeDefinition, VersionDefinition};
+use crate::snapshot;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Members of the generated `Client` class, which endpoint properties can't
+/// be named after.
+const CLIENT_MEMBERS: &[&str] = &["baseUrl", "options", "request", "constructor"];
+
+/// The code of the generated client that doesn't depend on the version.
+const RUNTIME: &str = r#"/** A page of the entities listed by a CRUD endpoint. */
+export interface Page<T> {
+    results: T[];
+    /** URL of the next page, for `page()`. */
+    next_page?: string;
+    /** URL of the previous page, for `page()`. */
+    prev_page?: string;
+}
+
+/** A page of the entities listed by a CRUD endpoint with envelope pagination. */
+export interface Envelope<T> {
+    items: T[];
+    /** The `cursor` of the next page, if there may be one. */
+    next_cursor?: string;
+    /** The number of entities, if it was cheap to know. */
+    total?: number;
+}
+
+/** The metadata of a file, which the file fields of entities hold. */
+export interface ChiselFile {
+    key: string;
+    name: string;
+    /** The media type of the content. */
+    type: string;
+    /** The size of the content, in bytes. */
+    size: number;
+}
+
+export interface ListParams {
+    /** Field to sort by, prefixed by - for descending order. */
+    sort?: string;
+    limit?: number;
+    offset?: number;
+    /** The `next_cursor` of a page, to get the page after it. */
+    cursor?: string;
+    /** Filters like `{ ".name": "Dune", ".year~gt": "2000" }`. */
+    filters?: Record<string, string>;
+}
+
+export interface ClientOptions {
+    /** Headers sent with every request, such as credentials. */
+    headers?: Record<string, string>;
+    /** The fetch() to make requests with, the global one by default. */
+    fetch?: typeof fetch;
+}
+
+/** A request that the server answered with an error status. */
+export class ClientError extends Error {
+    constructor(readonly status: number, readonly body: string) {
+        super(`Request failed with status ${status}: ${body}`);
+    }
+}
+
+export class Endpoint {
+    constructor(protected client: ClientBase, readonly path: string) {}
+
+    get(query?: Record<string, string>): Promise<unknown> {
+        return this.client.request("GET", this.path, undefined, query);
+    }
+
+    post(body?: unknown): Promise<unknown> {
+        return this.client.request("POST", this.path, body);
+    }
+
+    put(body?: unknown): Promise<unknown> {
+        return this.client.request("PUT", this.path, body);
+    }
+
+    patch(body?: unknown): Promise<unknown> {
+        return this.client.request("PATCH", this.path, body);
+    }
+
+    delete(query?: Record<string, string>): Promise<unknown> {
+        return this.client.request("DELETE", this.path, undefined, query);
+    }
+}
+
+/**
+ * The CRUD API of an entity, whose lists are of type `List`, according to
+ * the pagination of the endpoint.
+ */
+export class CrudEndpoint<T extends { id: string }, New, List = Page<T>> extends Endpoint {
+    async list(params: ListParams = {}): Promise<List> {
+        const query: Record<string, string> = { ...params.filters };
+        if (params.sort !== undefined) {
+            query.sort = params.sort;
+        }
+        if (params.limit !== undefined) {
+            query.limit = String(params.limit);
+        }
+        if (params.offset !== undefined) {
+            query.offset = String(params.offset);
+        }
+        if (params.cursor !== undefined) {
+            query.cursor = params.cursor;
+        }
+        return await this.client.request("GET", this.path, undefined, query) as List;
+    }
+
+    /** Gets the page at `url`, the `next_page` or `prev_page` of another. */
+    async page(url: string): Promise<Page<T>> {
+        return await this.client.request("GET", url) as Page<T>;
+    }
+
+    async find(id: string): Promise<T> {
+        return await this.client.request("GET", this.itemPath(id)) as T;
+    }
+
+    async create(entity: New): Promise<T> {
+        return await this.client.request("POST", this.path, entity) as T;
+    }
+
+    async replace(id: string, entity: New): Promise<T> {
+        return await this.client.request("PUT", this.itemPath(id), entity) as T;
+    }
+
+    async update(id: string, fields: Partial<New>): Promise<T> {
+        return await this.client.request("PATCH", this.itemPath(id), fields) as T;
+    }
+
+    async remove(id: string): Promise<void> {
+        await this.client.request("DELETE", this.itemPath(id));
+    }
+
+    private itemPath(id: string): string {
+        return `${this.path}/${encodeURIComponent(id)}`;
+    }
+}
+
+export class ClientBase {
+    constructor(readonly baseUrl: string, readonly options: ClientOptions = {}) {}
+
+    /**
+     * Makes a request to `path`, or to `path` as a URL if it is one, and
+     * returns its JSON or text response.
+     */
+    async request(
+        method: string,
+        path: string,
+        body?: unknown,
+        query?: Record<string, string>,
+    ): Promise<unknown> {
+        const url = new URL(path, this.baseUrl);
+        for (const [key, value] of Object.entries(query ?? {})) {
+            url.searchParams.append(key, value);
+        }
+        const headers: Record<string, string> = { ...this.options.headers };
+        if (body !== undefined) {
+            headers["Content-Type"] = "application/json";
+        }
+        const fetchFn = this.options.fetch ?? fetch;
+        const response = await fetchFn(url.toString(), {
+            method,
+            headers,
+            body: body === undefined ? undefined : JSON.stringify(body),
+        });
+        const text = await response.text();
+        if (!response.ok) {
+            throw new ClientError(response.status, text);
+        }
+        const contentType = response.headers.get("Content-Type") ?? "";
+        return contentType.includes("json") && text ? JSON.parse(text) : text;
+    }
+}
+"#;
+
+/// Writes the client of `version` to `index.ts` in `out`.
+pub(crate) async fn generate_client(
+    server_url: String,
+    version: String,
+    out: &Path,
+    offline: bool,
+) -> Result<()> {
+    let response = snapshot::describe(server_url, offline).await?;
+    let version_def = response
+        .version_defs
+        .into_iter()
+        .find(|def| def.version == version)
+        .ok_or_else(|| anyhow!("version {} is not applied", version))?;
+    let source = client_source(&version_def)?;
+
+    std::fs::create_dir_all(out).with_context(|| format!("Could not create {}", out.display()))?;
+    let path = out.join("index.ts");
+    std::fs::write(&path, source).with_context(|| format!("Could not write {}", path.display()))?;
+    println!(
+        "Client of version {} written to {}",
+        version,
+        path.display()
+    );
+    Ok(())
+}
+
+fn client_source(version_def: &VersionDefinition) -> Result<String> {
+    let entities: HashSet<&str> = version_def
+        .type_defs
+        .iter()
+        .map(|def| def.name.as_str())
+        .collect();
+    let mut source = String::new();
+    writeln!(
+        source,
+        "// Generated by `chisel generate client` from version {} of the API.\n\
+         // Generate it again instead of editing it.\n",
+        version_def.version
+    )?;
+    for def in &version_def.type_defs {
+        write_entity(&mut source, def, &entities)?;
+    }
+    source += RUNTIME;
+
+    writeln!(source, "\nexport class Client extends ClientBase {{")?;
+    let prefix = format!("/{}/", version_def.version);
+    let mut names = HashSet::new();
+    for endpoint in &version_def.endpoint_defs {
+        let relative = endpoint
+            .path
+            .strip_prefix(&prefix)
+            .unwrap_or(&endpoint.path);
+        let mut name = property_name(relative);
+        while CLIENT_MEMBERS.contains(&name.as_str()) || names.contains(&name) {
+            name.push('_');
+        }
+        let path = serde_json::to_string(&endpoint.path)?;
+        match &endpoint.crud_entity {
+            Some(entity) if entities.contains(entity.as_str()) => {
+                let list = match endpoint.crud_pagination.as_str() {
+                    "envelope" => format!(", Envelope<{}>", entity),
+                    "array" => format!(", Array<{}>", entity),
+                    _ => String::new(),
+                };
+                writeln!(
+                    source,
+                    "    readonly {} = new CrudEndpoint<{}, New{}{}>(this, {});",
+                    name, entity, entity, list, path
+                )?
+            }
+            _ => writeln!(
+                source,
+                "    readonly {} = new Endpoint(this, {});",
+                name, path
+            )?,
+        }
+        names.insert(name);
+    }
+    writeln!(source, "}}")?;
+    Ok(source)
+}
+
+/// Writes the interface of an entity, as the API returns it, and the type of
+/// the new entities to create, without an id and with the fields that have
+/// a default value optional.
+fn write_entity(source: &mut String, def: &TypeDefinition, entities: &HashSet<&str>) -> Result<()> {
+    if let Some(description) = &def.description {
+        source.push_str(&doc_comment("", description));
+    }
+    writeln!(source, "export interface {} {{", def.name)?;
+    writeln!(source, "    id: string;")?;
+    let mut defaulted = vec![];
+    for field in &def.field_defs {
+        if let Some(description) = &field.description {
+            source.push_str(&doc_comment("    ", description));
+        }
+        let optional = if field.is_optional { "?" } else { "" };
+        let ty = ts_type(field.field_type()?, entities)?;
+        writeln!(source, "    {}{}: {};", field.name, optional, ty)?;
+        if field.default_value.is_some() && !field.is_optional {
+            defaulted.push(serde_json::to_string(&field.name)?);
+        }
+    }
+    writeln!(source, "}}\n")?;
+    if defaulted.is_empty() {
+        writeln!(
+            source,
+            "export type New{} = Omit<{}, \"id\">;\n",
+            def.name, def.name
+        )?;
+    } else {
+        let defaulted = defaulted.join(" | ");
+        writeln!(
+            source,
+            "export type New{} = Omit<{}, \"id\" | {}> & Partial<Pick<{}, {}>>;\n",
+            def.name, def.name, defaulted, def.name, defaulted
+        )?;
+    }
+    Ok(())
+}
+
+/// `description` as a doc comment, with its lines indented by `indent`.
+pub(crate) fn doc_comment(indent: &str, description: &str) -> String {
+    let lines: Vec<&str> = description.lines().collect();
+    match lines.as_slice() {
+        [line] => format!("{}/** {} */\n", indent, line),
+        lines => {
+            let mut comment = format!("{}/**\n", indent);
+            for line in lines {
+                comment += format!("{} * {}", indent, line).trim_end();
+                comment.push('\n');
+            }
+            comment + indent + " */\n"
+        }
+    }
+}
+
+/// The TypeScript type of the JSON of a field.
+fn ts_type(ty: &TypeEnum, entities: &HashSet<&str>) -> Result<String> {
+    Ok(match ty {
+        // Dates are serialized as ISO 8601 strings.
+        TypeEnum::Entity(name) if name == "Date" => "string".to_owned(),
+        TypeEnum::Entity(name) if name == "JSONValue" => "unknown".to_owned(),
+        TypeEnum::Entity(name) if name == "ChiselFile" => "ChiselFile".to_owned(),
+        TypeEnum::Entity(name) if entities.contains(name.as_str()) => name.clone(),
+        // Built-in entities, such as AuthUser.
+        TypeEnum::Entity(_) => "Record<string, unknown>".to_owned(),
+        // The objects of a polymorphic relation say which entity they are of.
+        TypeEnum::UnionType(union) => union
+            .entities
+            .iter()
+            .map(|name| format!("({name} & {{ __type: \"{name}\" }})"))
+            .collect::<Vec<_>>()
+            .join(" | "),
+        TypeEnum::Array(inner) => {
+            let inner = inner
+                .value_type
+                .as_ref()
+                .and_then(|ty| ty.type_enum.as_ref())
+                .context("value_type of ContainerType is None")?;
+            format!("Array<{}>", ts_type(inner, entities)?)
+        }
+        ty => ty.to_string(),
+    })
+}
+
+/// The name of the property of the endpoint at `path`, relative to the
+/// version, like `adminUsers` for `admin/users`.
+fn property_name(path: &str) -> String {
+    let mut name = String::new();
+    for word in path.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            if name.is_empty() {
+                name.push(first.to_ascii_lowercase());
+            } else {
+                name.push(first.to_ascii_uppercase());
+            }
+            name.extend(chars);
+        }
+    }
+    if name.is_empty() {
+        name = "root".to_owned();
+    } else if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
@@ -0,0 +1,153 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+mod coverage;
+
+use crate::cmd::apply::node;
+use crate::project::{read_manifest, Module};
+use crate::proto::UnitTestRequest;
+use crate::server::connect;
+use anyhow::{anyhow, Context, Result};
+use endpoint_tsc::compile_endpoints_with_source_maps;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Test modules are the files of the test directories named `*.test.ts` or
+/// `*.test.js`. Other files there can be helpers imported by the tests.
+pub(crate) fn is_test_module(path: &Path) -> bool {
+    let name = path.file_name().and_then(|name| name.to_str());
+    name.map_or(false, |name| {
+        name.ends_with(".test.ts") || name.ends_with(".test.js")
+    })
+}
+
+/// The file with the fetch() calls recorded by the tests of `test`, like
+/// `tests/greet.test.cassette.json` for `tests/greet.test.ts`.
+fn cassette_path(test: &Path) -> PathBuf {
+    test.with_extension("cassette.json")
+}
+
+/// Reads the cassettes of the test modules `paths` that have one.
+fn read_cassettes(paths: &[&str]) -> Result<HashMap<String, String>> {
+    let mut cassettes = HashMap::new();
+    for path in paths {
+        let cassette = cassette_path(Path::new(path));
+        if cassette.exists() {
+            let json = std::fs::read_to_string(&cassette)
+                .with_context(|| format!("Could not read {}", cassette.display()))?;
+            cassettes.insert(path.to_string(), json);
+        }
+    }
+    Ok(cassettes)
+}
+
+/// Writes the `recorded` cassettes of the test modules `paths`, removing
+/// those of the modules whose tests didn't call fetch().
+fn write_cassettes(paths: &[&str], recorded: &HashMap<String, String>) -> Result<()> {
+    for path in paths {
+        let cassette = cassette_path(Path::new(path));
+        match recorded.get(*path) {
+            Some(json) => std::fs::write(&cassette, json)
+                .with_context(|| format!("Could not write {}", cassette.display()))?,
+            None if cassette.exists() => std::fs::remove_file(&cassette)
+                .with_context(|| format!("Could not remove {}", cassette.display()))?,
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Runs the tests. With `coverage_dir`, also writes a coverage report there.
+/// With `record`, the fetch() calls of the tests are made and recorded in
+/// cassette files, which later runs replay.
+pub(crate) async fn cmd_test(
+    server_url: String,
+    unit: bool,
+    coverage_dir: Option<&Path>,
+    record: bool,
+) -> Result<()> {
+    anyhow::ensure!(
+        unit,
+        "only unit tests are supported for now, run `chisel test --unit`"
+    );
+    let manifest = read_manifest().context("Could not read manifest file")?;
+    let tests: Vec<_> = manifest
+        .tests()?
+        .into_iter()
+        .filter(|path| is_test_module(path))
+        .collect();
+    if tests.is_empty() {
+        println!("No tests found.");
+        return Ok(());
+    }
+
+    let paths: Result<Vec<_>> = tests
+        .iter()
+        .map(|f| f.to_str().ok_or_else(|| anyhow!("Path is not UTF8")))
+        .collect();
+    let paths = paths?;
+    let coverage = coverage_dir.is_some();
+    let (sources, root) = if manifest.modules == Module::Node {
+        node::bundle_tests(&tests, coverage).await?
+    } else {
+        let sources = compile_endpoints_with_source_maps(&paths, coverage)
+            .await
+            .context("Could not compile tests (using deno-style modules)")?;
+        (sources, std::env::current_dir()?)
+    };
+
+    let cassettes = if record {
+        HashMap::new()
+    } else {
+        read_cassettes(&paths)?
+    };
+
+    let mut client = connect(server_url).await?;
+    let response = execute!(
+        client
+            .run_unit_tests(tonic::Request::new(UnitTestRequest {
+                sources: sources.clone(),
+                tests: paths.iter().map(|p| p.to_string()).collect(),
+                coverage,
+                cassettes,
+                record,
+            }))
+            .await
+    );
+    let mut failed = 0;
+    for result in &response.results {
+        let status = match &result.error {
+            None => "ok",
+            Some(_) => {
+                failed += 1;
+                "FAILED"
+            }
+        };
+        println!(
+            "{} > {} ... {} ({:.1}ms)",
+            result.file, result.name, status, result.duration_ms
+        );
+        if let Some(error) = &result.error {
+            println!("{}", error);
+        }
+    }
+    println!(
+        "\n{} passed, {} failed",
+        response.results.len() - failed,
+        failed
+    );
+    if record {
+        write_cassettes(&paths, &response.cassettes)?;
+        println!(
+            "Recorded the fetch() calls of {} test modules",
+            response.cassettes.len()
+        );
+    }
+    if let Some(dir) = coverage_dir {
+        let report = coverage::Report::new(&response.coverage, &sources, &root)?;
+        report.write(dir)?;
+        println!("\n{}", report.summary());
+        println!("Coverage report written to {}", dir.display());
+    }
+    anyhow::ensure!(failed == 0, "{} unit tests failed", failed);
+    Ok(())
+}
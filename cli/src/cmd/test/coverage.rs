@@ -0,0 +1,387 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Code coverage of `chisel test --coverage`.
+//!
+//! chiseld returns the V8 block coverage of each module the tests loaded.
+//! The modules are compiled with inline source maps, through which the
+//! execution counts of the JavaScript are mapped back to lines of the
+//! TypeScript files of the project. Those are written as an lcov tracefile
+//! and an HTML report.
+
+use crate::cmd::apply::SourceMap;
+use crate::cmd::test::is_test_module;
+use crate::proto::ScriptCoverage;
+use anyhow::{Context, Result};
+use serde_derive::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FunctionCoverage {
+    function_name: String,
+    ranges: Vec<CoverageRange>,
+}
+
+/// Offsets are in UTF-16 code units, which source maps count columns in too.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CoverageRange {
+    start_offset: usize,
+    end_offset: usize,
+    count: u64,
+}
+
+struct Function {
+    name: String,
+    line: u32,
+    count: u64,
+}
+
+/// Execution counts of a source file.
+#[derive(Default)]
+struct FileCoverage {
+    /// Counts by line, starting at 1.
+    lines: BTreeMap<u32, u64>,
+    functions: Vec<Function>,
+}
+
+impl FileCoverage {
+    fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|count| **count > 0).count()
+    }
+}
+
+/// Coverage of the source files of the project, by path relative to it.
+#[derive(Default)]
+pub(crate) struct Report {
+    files: BTreeMap<PathBuf, FileCoverage>,
+}
+
+impl Report {
+    /// Maps the coverage of the `scripts` compiled into `sources` back to the
+    /// files of the project. The source maps of a script are relative to its
+    /// path under `root`.
+    pub(crate) fn new(
+        scripts: &[ScriptCoverage],
+        sources: &SourceMap,
+        root: &Path,
+    ) -> Result<Self> {
+        let project = std::env::current_dir()?;
+        let sources: HashMap<PathBuf, &String> = sources
+            .iter()
+            .map(|(path, code)| (normalize(Path::new(path)), code))
+            .collect();
+
+        let mut report = Report::default();
+        for script in scripts {
+            let path = normalize(Path::new(&script.path));
+            let code = match sources.get(&path) {
+                Some(code) => code,
+                None => continue,
+            };
+            let map = match inline_source_map(code)? {
+                Some(map) => map,
+                None => continue,
+            };
+            let functions: Vec<FunctionCoverage> = serde_json::from_str(&script.functions)
+                .with_context(|| format!("Invalid coverage of {}", script.path))?;
+            let dir = normalize(&root.join(&path));
+            let dir = dir.parent().unwrap_or(&dir);
+            let locate =
+                |line: usize, column: usize| original_position(&map, dir, &project, line, column);
+
+            let lines = line_offsets(code);
+            let ranges: Vec<&CoverageRange> = functions.iter().flat_map(|f| &f.ranges).collect();
+            for (line, (start, first_column)) in lines.iter().enumerate() {
+                let column = match first_column {
+                    Some(column) => *column,
+                    None => continue,
+                };
+                let count = match count_at(&ranges, start + column) {
+                    Some(count) => count,
+                    None => continue,
+                };
+                if let Some((file, line)) = locate(line, column) {
+                    // A line compiled to several is only covered if all are.
+                    let file = report.files.entry(file).or_default();
+                    let line_count = file.lines.entry(line).or_insert(count);
+                    *line_count = (*line_count).min(count);
+                }
+            }
+            for function in &functions {
+                let range = match function.ranges.first() {
+                    Some(range) if !function.function_name.is_empty() => range,
+                    _ => continue,
+                };
+                let line = lines.partition_point(|(start, _)| *start <= range.start_offset) - 1;
+                let column = range.start_offset - lines[line].0;
+                if let Some((file, line)) = locate(line, column) {
+                    report
+                        .files
+                        .entry(file)
+                        .or_default()
+                        .functions
+                        .push(Function {
+                            name: function.function_name.clone(),
+                            line,
+                            count: range.count,
+                        });
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Writes `lcov.info` and `index.html` into `dir`.
+    pub(crate) fn write(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Could not create directory {}", dir.display()))?;
+        fs::write(dir.join("lcov.info"), self.lcov())?;
+        fs::write(dir.join("index.html"), self.html())?;
+        Ok(())
+    }
+
+    /// One line per file with the lines it has covered.
+    pub(crate) fn summary(&self) -> String {
+        let mut out = String::new();
+        let (mut hit, mut found) = (0, 0);
+        for (path, file) in &self.files {
+            let file_hit = file.lines_hit();
+            writeln!(
+                out,
+                "{} {}/{} lines ({:.1}%)",
+                path.display(),
+                file_hit,
+                file.lines.len(),
+                percent(file_hit, file.lines.len())
+            )
+            .unwrap();
+            hit += file_hit;
+            found += file.lines.len();
+        }
+        write!(
+            out,
+            "Total {}/{} lines ({:.1}%)",
+            hit,
+            found,
+            percent(hit, found)
+        )
+        .unwrap();
+        out
+    }
+
+    fn lcov(&self) -> String {
+        let mut out = String::new();
+        for (path, file) in &self.files {
+            writeln!(out, "TN:").unwrap();
+            writeln!(out, "SF:{}", path.display()).unwrap();
+            for function in &file.functions {
+                writeln!(out, "FN:{},{}", function.line, function.name).unwrap();
+            }
+            for function in &file.functions {
+                writeln!(out, "FNDA:{},{}", function.count, function.name).unwrap();
+            }
+            let functions_hit = file.functions.iter().filter(|f| f.count > 0).count();
+            writeln!(out, "FNF:{}", file.functions.len()).unwrap();
+            writeln!(out, "FNH:{}", functions_hit).unwrap();
+            for (line, count) in &file.lines {
+                writeln!(out, "DA:{},{}", line, count).unwrap();
+            }
+            writeln!(out, "LF:{}", file.lines.len()).unwrap();
+            writeln!(out, "LH:{}", file.lines_hit()).unwrap();
+            writeln!(out, "end_of_record").unwrap();
+        }
+        out
+    }
+
+    fn html(&self) -> String {
+        let mut out = String::new();
+        out.push_str(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>Coverage report</title>\n<style>\n\
+             body { font-family: sans-serif; }\n\
+             td { padding: 0 1em 0 0; }\n\
+             pre { line-height: 1.3; }\n\
+             .hit { background: #dfd; }\n\
+             .miss { background: #fdd; }\n\
+             .count { color: #888; display: inline-block; width: 5em; text-align: right; }\n\
+             </style>\n</head>\n<body>\n<h1>Coverage report</h1>\n<table>\n",
+        );
+        for (i, (path, file)) in self.files.iter().enumerate() {
+            let hit = file.lines_hit();
+            writeln!(
+                out,
+                "<tr><td><a href=\"#file{}\">{}</a></td><td>{}/{} lines</td><td>{:.1}%</td></tr>",
+                i,
+                escape_html(&path.display().to_string()),
+                hit,
+                file.lines.len(),
+                percent(hit, file.lines.len())
+            )
+            .unwrap();
+        }
+        out.push_str("</table>\n");
+        for (i, (path, file)) in self.files.iter().enumerate() {
+            let path_str = escape_html(&path.display().to_string());
+            writeln!(out, "<h2 id=\"file{}\">{}</h2>\n<pre>", i, path_str).unwrap();
+            let source = fs::read_to_string(path).unwrap_or_default();
+            for (n, text) in source.lines().enumerate() {
+                let (class, count) = match file.lines.get(&(n as u32 + 1)) {
+                    Some(0) => ("miss", "0".to_string()),
+                    Some(count) => ("hit", count.to_string()),
+                    None => ("", String::new()),
+                };
+                writeln!(
+                    out,
+                    "<span class=\"{}\"><span class=\"count\">{}</span> {}</span>",
+                    class,
+                    count,
+                    escape_html(text)
+                )
+                .unwrap();
+            }
+            out.push_str("</pre>\n");
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+fn percent(hit: usize, found: usize) -> f64 {
+    if found == 0 {
+        100.0
+    } else {
+        100.0 * hit as f64 / found as f64
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Removes the `.` and `..` components of `path` without looking at the
+/// file system, where it may not exist anymore.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+/// The offset at which each line of `code` starts, and the column of its
+/// first non-blank character, if any. Both are in UTF-16 code units.
+fn line_offsets(code: &str) -> Vec<(usize, Option<usize>)> {
+    let mut lines = vec![];
+    let mut offset = 0;
+    for line in code.split('\n') {
+        let mut column = 0;
+        let mut first = None;
+        for c in line.chars() {
+            if first.is_none() && !c.is_whitespace() {
+                first = Some(column);
+            }
+            column += c.len_utf16();
+        }
+        lines.push((offset, first));
+        offset += column + 1;
+    }
+    lines
+}
+
+/// V8 nests the ranges of blocks in those of functions, so the count of an
+/// offset is the one of the innermost range containing it.
+fn count_at(ranges: &[&CoverageRange], offset: usize) -> Option<u64> {
+    ranges
+        .iter()
+        .filter(|r| r.start_offset <= offset && offset < r.end_offset)
+        .min_by_key(|r| r.end_offset - r.start_offset)
+        .map(|r| r.count)
+}
+
+fn inline_source_map(code: &str) -> Result<Option<sourcemap::SourceMap>> {
+    let url = match sourcemap::locate_sourcemap_reference_slice(code.as_bytes())? {
+        Some(reference) => reference.get_url().to_owned(),
+        None => return Ok(None),
+    };
+    if !url.starts_with("data:") {
+        return Ok(None);
+    }
+    match sourcemap::decode_data_url(&url)? {
+        sourcemap::DecodedMap::Regular(map) => Ok(Some(map)),
+        _ => Ok(None),
+    }
+}
+
+/// Maps a position of a compiled script in `dir` to a line of a file of the
+/// project. Positions in test modules and dependencies aren't reported.
+fn original_position(
+    map: &sourcemap::SourceMap,
+    dir: &Path,
+    project: &Path,
+    line: usize,
+    column: usize,
+) -> Option<(PathBuf, u32)> {
+    let token = map.lookup_token(line as u32, column as u32)?;
+    if token.get_dst_line() as usize != line {
+        return None;
+    }
+    let source = token.get_source()?;
+    let source = source.strip_prefix("file://").unwrap_or(source);
+    let path = normalize(&dir.join(source));
+    let path = path.strip_prefix(project).ok()?;
+    if is_test_module(path) || path.components().any(|c| c.as_os_str() == "node_modules") {
+        return None;
+    }
+    Some((path.to_path_buf(), token.get_src_line() + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start_offset: usize, end_offset: usize, count: u64) -> CoverageRange {
+        CoverageRange {
+            start_offset,
+            end_offset,
+            count,
+        }
+    }
+
+    #[test]
+    fn innermost_range_counts() {
+        let function = range(0, 100, 3);
+        let block = range(10, 20, 0);
+        let ranges = [&function, &block];
+        assert_eq!(count_at(&ranges, 5), Some(3));
+        assert_eq!(count_at(&ranges, 15), Some(0));
+        assert_eq!(count_at(&ranges, 20), Some(3));
+        assert_eq!(count_at(&ranges, 100), None);
+    }
+
+    #[test]
+    fn offsets_in_utf16() {
+        let lines = line_offsets("a\n  é😀b\n\n");
+        assert_eq!(lines, [(0, Some(0)), (2, Some(2)), (9, None), (10, None)]);
+    }
+
+    #[test]
+    fn normalizes_lexically() {
+        assert_eq!(
+            normalize(Path::new("/tmp/out/./tests/../../../p/routes/a.ts")),
+            PathBuf::from("/p/routes/a.ts")
+        );
+    }
+}
@@ -0,0 +1,292 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! `chisel upgrade`, which replaces the ChiselStrike binaries next to chisel
+//! with those of another release.
+//!
+//! Releases are the GitHub releases of ChiselStrike. Their assets are the
+//! tarballs made by `scripts/build-tarball.sh`, one per target, each with
+//! its SHA-256 checksum. A tarball is only installed if it matches its
+//! checksum, and each binary is swapped by renaming it over the old one, so
+//! that a failed upgrade leaves the old binaries working.
+
+use anyhow::{anyhow, Context, Result};
+use flate2::read::GzDecoder;
+use serde_derive::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Where releases are listed, which `CHISEL_RELEASES_URL` overrides.
+const RELEASES_URL: &str = "https://api.github.com/repos/chiselstrike/chiselstrike/releases";
+
+/// The target chisel was built for, as in the names of the tarballs.
+const TARGET: &str = env!("TARGET");
+
+/// The binaries of a release.
+const BINARIES: &[&str] = &["chisel", "chiselc", "chiseld"];
+
+/// Which releases to upgrade to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Channel {
+    Stable,
+    /// Pre-releases too.
+    Beta,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    prerelease: bool,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn version(&self) -> &str {
+        self.tag_name.trim_start_matches('v')
+    }
+
+    fn asset(&self, name: &str) -> Result<&Asset> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or_else(|| anyhow!("Release {} has no {}", self.tag_name, name))
+    }
+}
+
+pub(crate) async fn cmd_upgrade(channel: Channel, version: Option<String>) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .user_agent(concat!("chisel/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let url = std::env::var("CHISEL_RELEASES_URL").unwrap_or_else(|_| RELEASES_URL.to_owned());
+    let releases: Vec<Release> = client
+        .get(&url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("Could not list the releases at {}", url))?
+        .json()
+        .await
+        .with_context(|| format!("Could not parse the releases at {}", url))?;
+    let release = pick_release(&releases, channel, version.as_deref())?;
+
+    let current = env!("CARGO_PKG_VERSION");
+    if release.version() == current {
+        println!("chisel {} is up to date", current);
+        return Ok(());
+    }
+
+    let tarball_name = format!("chiselstrike-{}-{}.tar.gz", release.tag_name, TARGET);
+    let tarball = release.asset(&tarball_name)?;
+    let checksum = release.asset(&format!("{}.sha256", tarball_name))?;
+    println!("Downloading {}", tarball.browser_download_url);
+    let tarball = download(&client, &tarball.browser_download_url).await?;
+    let checksum = download(&client, &checksum.browser_download_url).await?;
+    verify(&tarball, &String::from_utf8_lossy(&checksum))
+        .with_context(|| format!("Could not verify {}", tarball_name))?;
+
+    let exe = std::env::current_exe()?.canonicalize()?;
+    let dir = exe.parent().unwrap();
+    let installed = install(&tarball, dir)
+        .with_context(|| format!("Could not install {} in {}", tarball_name, dir.display()))?;
+    println!(
+        "Upgraded {} in {} from {} to {}",
+        installed.join(", "),
+        dir.display(),
+        current,
+        release.version()
+    );
+    println!("Restart chiseld for the running servers to be upgraded too.");
+    Ok(())
+}
+
+/// The release of `version`, or else the latest one of `channel`.
+fn pick_release<'a>(
+    releases: &'a [Release],
+    channel: Channel,
+    version: Option<&str>,
+) -> Result<&'a Release> {
+    let mut published = releases.iter().filter(|release| !release.draft);
+    match version {
+        Some(version) => {
+            let version = version.trim_start_matches('v');
+            published
+                .find(|release| release.version() == version)
+                .ok_or_else(|| anyhow!("There is no release of version {}", version))
+        }
+        // Releases are listed newest first.
+        None => published
+            .find(|release| channel == Channel::Beta || !release.prerelease)
+            .ok_or_else(|| anyhow!("There are no releases in the {:?} channel", channel)),
+    }
+}
+
+async fn download(client: &reqwest::Client, url: &str) -> Result<Vec<u8>> {
+    let bytes = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("Could not download {}", url))?
+        .bytes()
+        .await
+        .with_context(|| format!("Could not download {}", url))?;
+    Ok(bytes.to_vec())
+}
+
+/// Checks `data` against a checksum in the format of `sha256sum`.
+fn verify(data: &[u8], checksum: &str) -> Result<()> {
+    let expected = checksum
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| anyhow!("the checksum is empty"))?;
+    let actual = hex::encode(Sha256::digest(data));
+    anyhow::ensure!(
+        actual.eq_ignore_ascii_case(expected),
+        "its SHA-256 checksum is {}, but should be {}",
+        actual,
+        expected
+    );
+    Ok(())
+}
+
+/// Installs the binaries of `tarball` in `dir`, and returns their names.
+/// They are all extracted before any is replaced, so that chisel, chiselc
+/// and chiseld stay of the same version if one can't be extracted.
+fn install(tarball: &[u8], dir: &Path) -> Result<Vec<String>> {
+    let mut extracted: Vec<(String, PathBuf)> = vec![];
+    let result = (|| -> Result<()> {
+        let mut archive = tar::Archive::new(GzDecoder::new(tarball));
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = match entry.path()?.file_name().and_then(|name| name.to_str()) {
+                Some(name) if BINARIES.contains(&name) => name.to_owned(),
+                _ => continue,
+            };
+            // Next to the old binary, so that it can be renamed over it.
+            let tmp = dir.join(format!(".{}.upgrade", name));
+            let mut file = File::create(&tmp)?;
+            extracted.push((name, tmp.clone()));
+            std::io::copy(&mut entry, &mut file)?;
+            file.flush()?;
+            fs::set_permissions(&tmp, fs::Permissions::from_mode(0o755))?;
+        }
+        anyhow::ensure!(
+            extracted.iter().any(|(name, _)| name == "chisel"),
+            "the tarball has no chisel"
+        );
+        Ok(())
+    })();
+    if let Err(e) = result {
+        for (_, tmp) in &extracted {
+            fs::remove_file(tmp).ok();
+        }
+        return Err(e);
+    }
+
+    let mut installed = vec![];
+    for (name, tmp) in extracted {
+        fs::rename(&tmp, dir.join(&name))?;
+        installed.push(name);
+    }
+    Ok(installed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(tag_name: &str, prerelease: bool) -> Release {
+        Release {
+            tag_name: tag_name.to_owned(),
+            draft: false,
+            prerelease,
+            assets: vec![],
+        }
+    }
+
+    fn tarball(files: &[(&str, &str)]) -> Vec<u8> {
+        let encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, data.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn picked_release() {
+        let mut draft = release("v0.14.0", false);
+        draft.draft = true;
+        let releases = [
+            draft,
+            release("v0.13.1-beta.1", true),
+            release("v0.13.0", false),
+            release("v0.12.0", false),
+        ];
+        let pick = |channel, version| {
+            pick_release(&releases, channel, version).map(|release| release.tag_name.as_str())
+        };
+        assert_eq!(pick(Channel::Stable, None).unwrap(), "v0.13.0");
+        assert_eq!(pick(Channel::Beta, None).unwrap(), "v0.13.1-beta.1");
+        assert_eq!(pick(Channel::Stable, Some("0.12.0")).unwrap(), "v0.12.0");
+        assert_eq!(pick(Channel::Stable, Some("v0.12.0")).unwrap(), "v0.12.0");
+        assert!(pick(Channel::Stable, Some("0.14.0")).is_err());
+    }
+
+    #[test]
+    fn verified() {
+        let checksum =
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824  hello.tar.gz";
+        verify(b"hello", checksum).unwrap();
+        verify(b"hello", &checksum.to_uppercase()).unwrap();
+        assert!(verify(b"hello!", checksum).is_err());
+        assert!(verify(b"hello", "").is_err());
+    }
+
+    #[test]
+    fn installed() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("chisel"), "old").unwrap();
+        let archive = tarball(&[
+            ("chiselstrike-linux/chisel", "new chisel"),
+            ("chiselstrike-linux/chiseld", "new chiseld"),
+            ("chiselstrike-linux/README", "readme"),
+        ]);
+        let installed = install(&archive, dir.path()).unwrap();
+        assert_eq!(installed, ["chisel", "chiseld"]);
+        let chisel = dir.path().join("chisel");
+        assert_eq!(fs::read_to_string(&chisel).unwrap(), "new chisel");
+        let mode = fs::metadata(&chisel).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert!(!dir.path().join("README").exists());
+        assert!(!dir.path().join(".chisel.upgrade").exists());
+
+        let archive = tarball(&[("chiselstrike/chiseld", "chiseld only")]);
+        assert!(install(&archive, dir.path()).is_err());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("chiseld")).unwrap(),
+            "new chiseld"
+        );
+        assert!(!dir.path().join(".chiseld.upgrade").exists());
+    }
+}
@@ -1,26 +1,39 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
-use crate::cmd::apply::apply;
+use crate::cmd::apply::{apply, build, send_apply, AllowTypeDeletion};
 use crate::cmd::dev::cmd_dev;
-use crate::project::{create_project, CreateProjectOptions};
-use crate::server::{start_server, wait, wait_with_cond};
+use crate::cmd::doctor::cmd_doctor;
+use crate::cmd::generate::{doc_comment, generate_client};
+use crate::cmd::test::cmd_test;
+use crate::cmd::upgrade::{cmd_upgrade, Channel};
+use crate::project::{
+    create_project, ensure_server_config, read_manifest, read_to_string, CreateProjectOptions,
+};
+use crate::server::{connect, start_server, wait, wait_with_cond};
 use anyhow::{anyhow, Result};
 use futures::{pin_mut, Future, FutureExt};
-use proto::chisel_rpc_client::ChiselRpcClient;
 use proto::{
-    type_msg::TypeEnum, ChiselDeleteRequest, DescribeRequest, PopulateRequest, RestartRequest,
-    StatusRequest,
+    type_msg::TypeEnum, ApproveMigrationRequest, BodySchema, ChiselDeleteRequest,
+    ClearFaultsRequest, CreateApiKeyRequest, ExplainRequest, FaultKind, FaultRule, IdMode,
+    InjectFaultRequest, KillRequest, ListApiKeysRequest, ListFaultsRequest, ListMigrationsRequest,
+    ListProfilesRequest, ListReadOnlyRequest, PolicyImpactRequest, PopulateRequest,
+    PrivacyEraseRequest, PrivacyExportRequest, PsRequest, RejectMigrationRequest, RestartRequest,
+    RevokeApiKeyRequest, StartReadOnlyRequest, StatsRequest, StatusRequest, StopReadOnlyRequest,
+    VerifyRequest,
 };
+use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::io::ErrorKind;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 use tokio::process::Child;
 
 mod cmd;
 mod project;
 mod server;
+mod snapshot;
 mod ts;
 
 #[allow(clippy::all)]
@@ -33,6 +46,59 @@ fn parse_version(version: &str) -> anyhow::Result<String> {
     Ok(version.to_string())
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DescribeFormat {
+    Text,
+    OpenApi,
+}
+
+fn parse_describe_format(format: &str) -> anyhow::Result<DescribeFormat> {
+    match format {
+        "text" => Ok(DescribeFormat::Text),
+        "openapi" => Ok(DescribeFormat::OpenApi),
+        _ => anyhow::bail!("format must be text or openapi"),
+    }
+}
+
+fn parse_channel(channel: &str) -> anyhow::Result<Channel> {
+    match channel {
+        "stable" => Ok(Channel::Stable),
+        "beta" => Ok(Channel::Beta),
+        _ => anyhow::bail!("channel must be stable or beta"),
+    }
+}
+
+fn parse_id_mode(mode: &str) -> anyhow::Result<IdMode> {
+    match mode {
+        "preserve" => Ok(IdMode::Preserve),
+        "remap" => Ok(IdMode::Remap),
+        "regenerate" => Ok(IdMode::Regenerate),
+        _ => anyhow::bail!("ID mode must be one of preserve, remap or regenerate"),
+    }
+}
+
+fn parse_fault_kind(kind: &str) -> anyhow::Result<FaultKind> {
+    match kind {
+        "database" => Ok(FaultKind::Database),
+        "slow-query" => Ok(FaultKind::SlowQuery),
+        "fetch" => Ok(FaultKind::Fetch),
+        "out-of-memory" => Ok(FaultKind::OutOfMemory),
+        _ => {
+            anyhow::bail!("fault kind must be one of database, slow-query, fetch or out-of-memory")
+        }
+    }
+}
+
+fn fault_kind_name(kind: i32) -> &'static str {
+    match FaultKind::from_i32(kind) {
+        Some(FaultKind::Database) => "database",
+        Some(FaultKind::SlowQuery) => "slow-query",
+        Some(FaultKind::Fetch) => "fetch",
+        Some(FaultKind::OutOfMemory) => "out-of-memory",
+        None => "unknown",
+    }
+}
+
 pub(crate) static DEFAULT_API_VERSION: &str = "dev";
 
 #[derive(StructOpt, Debug)]
@@ -63,7 +129,26 @@ enum Command {
         auto_index: bool,
     },
     /// Describe the endpoints, types, and policies.
-    Describe,
+    Describe {
+        /// `text`, or `openapi` for an OpenAPI 3.0 document of the
+        /// endpoints and entities.
+        #[structopt(long, default_value = "text", parse(try_from_str = parse_describe_format))]
+        format: DescribeFormat,
+        /// Describe the API as the server last did, without contacting it.
+        #[structopt(long)]
+        offline: bool,
+    },
+    /// Check that the server is up and show the crashes it reported.
+    Doctor {
+        /// Directory chiseld writes its crash reports to, as its --crash-dir.
+        #[structopt(long, default_value = ".chiseld-crashes")]
+        crash_dir: PathBuf,
+    },
+    /// Generate code from the API of a version.
+    Generate {
+        #[structopt(subcommand)]
+        cmd: GenerateCommand,
+    },
     /// Start a ChiselStrike server for local development.
     Dev {
         /// calls tsc --noEmit to check types. Useful if your IDE isn't doing it.
@@ -72,6 +157,9 @@ enum Command {
         /// Activate inspector and let a debugger attach at any time.
         #[structopt(long)]
         inspect: bool,
+        /// Let `chisel test --coverage` collect code coverage.
+        #[structopt(long)]
+        coverage: bool,
     },
     /// Create a new ChiselStrike project.
     New {
@@ -90,19 +178,37 @@ enum Command {
     /// Start the ChiselStrike server.
     Start,
     /// Show ChiselStrike server status.
-    Status,
+    Status {
+        /// Also show the memory used by each executor and by the modules of each version.
+        #[structopt(long, short)]
+        verbose: bool,
+    },
     /// Wait for the ChiselStrike server to start.
     Wait,
     /// Apply configuration to the ChiselStrike server.
     Apply {
         #[structopt(long)]
         allow_type_deletion: bool,
+        /// With --allow-type-deletion, also drop the fields of other models that relate to the
+        /// deleted ones.
+        #[structopt(long, requires = "allow-type-deletion")]
+        cascade: bool,
+        /// Proceed even if the plan deletes data.
+        #[structopt(long)]
+        yes: bool,
         #[structopt(long, default_value = DEFAULT_API_VERSION, parse(try_from_str=parse_version))]
         version: String,
         /// calls tsc --noEmit to check types. Useful if your IDE isn't doing it.
         #[structopt(long)]
         type_check: bool,
     },
+    /// Check the models and policies of the project against each other and
+    /// the lint rules of Chisel.toml, without applying them.
+    Lint {
+        /// calls tsc --noEmit to check types. Useful if your IDE isn't doing it.
+        #[structopt(long)]
+        type_check: bool,
+    },
     /// Delete configuration from the ChiselStrike server.
     Delete {
         #[structopt(long, default_value = DEFAULT_API_VERSION, parse(try_from_str=parse_version))]
@@ -113,12 +219,296 @@ enum Command {
         version: String,
         #[structopt(long)]
         from: String,
+        /// How to assign IDs to the copies: `preserve` the original IDs,
+        /// `remap` them deterministically, or `regenerate` them. References
+        /// between the copies are fixed up.
+        #[structopt(long, default_value = "preserve", parse(try_from_str = parse_id_mode))]
+        ids: IdMode,
+        /// Namespace of remapped IDs. Populates with the same namespace map
+        /// an ID to the same new one. Defaults to one per pair of versions.
+        #[structopt(long)]
+        id_namespace: Option<String>,
+        /// Only copy the objects whose value of this field, such as an
+        /// update timestamp, grew since the last populate between the same
+        /// versions. Objects of entities without the field are all copied.
+        #[structopt(long)]
+        changed_field: Option<String>,
+    },
+    /// Show row counts and, optionally, column statistics of entities.
+    Stats {
+        /// Only show entities of this version.
+        #[structopt(long)]
+        version: Option<String>,
+        /// Also show per-column statistics.
+        #[structopt(long)]
+        columns: bool,
+    },
+    /// Show row counts and content checksums of the entities of a version,
+    /// or compare them with another version.
+    Verify {
+        #[structopt(long, default_value = DEFAULT_API_VERSION, parse(try_from_str=parse_version))]
+        version: String,
+        /// Compare with the entities of this version.
+        #[structopt(long)]
+        against: Option<String>,
+        /// Leave IDs and references out of the checksums, to compare copies
+        /// made with new IDs.
+        #[structopt(long)]
+        ignore_ids: bool,
+    },
+    /// Export or erase the data of a user.
+    Privacy {
+        #[structopt(subcommand)]
+        cmd: PrivacyCommand,
+    },
+    /// List the requests and event handlers the server is running.
+    Ps,
+    /// Cancel a running request.
+    Kill {
+        /// Id of the request, as shown by `chisel ps`.
+        id: u64,
+    },
+    /// Show the latest execution profiles of endpoints, with the time their
+    /// requests spent running JavaScript, SQL statements and fetch() calls.
+    /// The server profiles the requests with a `ChiselProfile` header in
+    /// debug mode, and a sample of all requests with --profile-sample-rate.
+    Profile {
+        /// Only show the profiles of this version.
+        #[structopt(long, parse(try_from_str=parse_version))]
+        version: Option<String>,
+        /// Only show the profiles of this endpoint, such as `/people`, and the
+        /// ones under it.
+        endpoint: Option<String>,
+    },
+    /// Show the SQL statements that the latest profiled request to an
+    /// endpoint ran, with the plans the database chooses for them. Profile a
+    /// request first, as for `chisel profile`.
+    Explain {
+        /// Only explain requests to this version.
+        #[structopt(long, parse(try_from_str=parse_version))]
+        version: Option<String>,
+        /// The endpoint, such as `/people`, or one above it.
+        endpoint: String,
+    },
+    /// Reject writes to a version while it is under maintenance.
+    ReadOnly {
+        #[structopt(subcommand)]
+        cmd: ReadOnlyCommand,
+    },
+    /// Manage the API keys that clients send in the `X-API-Key` header, with
+    /// the `api-key` auth provider.
+    #[structopt(name = "apikey")]
+    ApiKey {
+        #[structopt(subcommand)]
+        cmd: ApiKeyCommand,
+    },
+    /// Make requests fail on purpose, to test how the application and its
+    /// clients handle failures. Needs the server to run in debug mode, as
+    /// `chisel dev` does.
+    Fault {
+        #[structopt(subcommand)]
+        cmd: FaultCommand,
+    },
+    /// Review the applies staged by a server that runs with
+    /// `--require-migration-approval`, because they would drop models or
+    /// fields.
+    Migrate {
+        #[structopt(subcommand)]
+        cmd: MigrateCommand,
+    },
+    /// Report how the policies of the project, or of --file, would affect
+    /// the data of a version if applied, from a sample of its rows.
+    PolicyImpact {
+        #[structopt(long, default_value = DEFAULT_API_VERSION, parse(try_from_str=parse_version))]
+        version: String,
+        /// Policy file to assess instead of the one of the project.
+        #[structopt(long)]
+        file: Option<PathBuf>,
+        /// Rows of each entity to sample.
+        #[structopt(long, default_value = "1000")]
+        sample: u64,
+    },
+    /// Run the tests in the `tests` directory against a running server.
+    Test {
+        /// Run unit tests, which keep entities in memory instead of the
+        /// database and can mock fetch().
+        #[structopt(long)]
+        unit: bool,
+        /// Write an lcov and HTML report of the code coverage of the tests.
+        /// The server must run with `--coverage`.
+        #[structopt(long)]
+        coverage: bool,
+        /// Directory to write the coverage report to.
+        #[structopt(long, default_value = "coverage")]
+        coverage_dir: PathBuf,
+        /// Make the fetch() calls of unit tests that no mock answers for
+        /// real, and record them next to the tests, to be replayed by later
+        /// runs.
+        #[structopt(long)]
+        record: bool,
+    },
+    /// Replace chisel, chiselc and chiseld with those of the latest release,
+    /// or of --version.
+    Upgrade {
+        /// `stable`, or `beta` to include pre-releases.
+        #[structopt(long, default_value = "stable", parse(try_from_str = parse_channel))]
+        channel: Channel,
+        /// Install this version, like 0.12.0, instead of the latest one.
+        #[structopt(long)]
+        version: Option<String>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum GenerateCommand {
+    /// Generate a typed TypeScript client of the endpoints and entities.
+    Client {
+        #[structopt(long, default_value = DEFAULT_API_VERSION, parse(try_from_str=parse_version))]
+        version: String,
+        /// Directory to write the client to, as `index.ts`.
+        #[structopt(long, default_value = "client")]
+        out: PathBuf,
+        /// Generate the client from the API as the server last described
+        /// it, without contacting it.
+        #[structopt(long)]
+        offline: bool,
     },
 }
 
+#[derive(StructOpt, Debug)]
+enum PrivacyCommand {
+    /// Print all data linked to a user as JSON.
+    Export {
+        /// Id of the user.
+        #[structopt(long)]
+        user: String,
+    },
+    /// Delete all data linked to a user.
+    Erase {
+        /// Id of the user.
+        #[structopt(long)]
+        user: String,
+        /// Keep the data, only removing personal details of the user itself.
+        #[structopt(long)]
+        anonymize: bool,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum ReadOnlyCommand {
+    /// Make a version, or some of its entities, read-only.
+    Start {
+        #[structopt(long, default_value = DEFAULT_API_VERSION, parse(try_from_str=parse_version))]
+        version: String,
+        /// Only make this entity read-only. Can be repeated.
+        #[structopt(long = "entity")]
+        entities: Vec<String>,
+        /// Make the version writable again after this many seconds.
+        #[structopt(long)]
+        duration: Option<u64>,
+    },
+    /// Make a version writable again.
+    Stop {
+        #[structopt(long, default_value = DEFAULT_API_VERSION, parse(try_from_str=parse_version))]
+        version: String,
+    },
+    /// List the read-only versions.
+    Status,
+}
+
+#[derive(StructOpt, Debug)]
+enum ApiKeyCommand {
+    /// Create a key and print it. The server only keeps its hash, so it
+    /// can't be shown again.
+    Create {
+        /// Name of the key, which routes see as `requestContext.apiKey`.
+        name: String,
+        /// ID of the user that requests with the key are made by. Defaults to
+        /// the name of the key.
+        #[structopt(long)]
+        user: Option<String>,
+    },
+    /// Revoke a key, rejecting the requests made with it from then on.
+    Revoke {
+        /// Name of the key.
+        name: String,
+    },
+    /// List the keys.
+    List,
+}
+
+#[derive(StructOpt, Debug)]
+enum FaultCommand {
+    /// Inject a fault into some requests.
+    Inject {
+        #[structopt(long, default_value = DEFAULT_API_VERSION, parse(try_from_str=parse_version))]
+        version: String,
+        /// Only inject the fault into requests to this endpoint, such as `/people`.
+        #[structopt(long)]
+        endpoint: Option<String>,
+        /// `database` fails the database statements of the requests,
+        /// `slow-query` slows them down by --delay, `fetch` fails their
+        /// fetch() calls and `out-of-memory` fails them as if the isolate
+        /// ran out of memory.
+        #[structopt(long, parse(try_from_str = parse_fault_kind))]
+        kind: FaultKind,
+        /// Percentage of the requests to inject the fault into.
+        #[structopt(long, default_value = "100")]
+        percent: f64,
+        /// Milliseconds each statement is slowed down by, for `slow-query`.
+        #[structopt(long, default_value = "1000")]
+        delay: u64,
+    },
+    /// Stop injecting faults.
+    Clear {
+        /// Only remove this rule, as shown by `chisel fault list`.
+        #[structopt(long)]
+        id: Option<u64>,
+    },
+    /// List the faults being injected.
+    List,
+}
+
+#[derive(StructOpt, Debug)]
+enum MigrateCommand {
+    /// List the staged applies, with the changes that need approval.
+    List,
+    /// Approve the changes of a staged apply, and apply it.
+    Approve {
+        /// Id of the staged apply, as shown by `chisel migrate list`.
+        id: u64,
+    },
+    /// Drop a staged apply without applying it.
+    Reject {
+        /// Id of the staged apply, as shown by `chisel migrate list`.
+        id: u64,
+    },
+}
+
+/// The type of an endpoint body, like `{ title: string; pages?: number }`.
+fn body_signature(body: &BodySchema) -> Result<String> {
+    if let Some(ty) = body.body_type.as_ref().and_then(|ty| ty.type_enum.as_ref()) {
+        return Ok(ty.to_string());
+    }
+    let mut fields = vec![];
+    for field in &body.fields {
+        let optional = if field.is_optional { "?" } else { "" };
+        fields.push(format!(
+            "{}{}: {}",
+            field.name,
+            optional,
+            field.field_type()?
+        ));
+    }
+    if fields.is_empty() {
+        return Ok("{}".to_owned());
+    }
+    Ok(format!("{{ {} }}", fields.join("; ")))
+}
+
 async fn delete<S: ToString>(server_url: String, version: S) -> Result<()> {
     let version = version.to_string();
-    let mut client = ChiselRpcClient::connect(server_url).await?;
+    let mut client = connect(server_url).await?;
 
     let msg = execute!(
         client
@@ -129,14 +519,24 @@ async fn delete<S: ToString>(server_url: String, version: S) -> Result<()> {
     Ok(())
 }
 
-async fn populate(server_url: String, to_version: String, from_version: String) -> Result<()> {
-    let mut client = ChiselRpcClient::connect(server_url).await?;
+async fn populate(
+    server_url: String,
+    to_version: String,
+    from_version: String,
+    ids: IdMode,
+    id_namespace: Option<String>,
+    changed_field: Option<String>,
+) -> Result<()> {
+    let mut client = connect(server_url).await?;
 
     let msg = execute!(
         client
             .populate(tonic::Request::new(PopulateRequest {
                 to_version,
                 from_version,
+                id_mode: ids as i32,
+                id_namespace: id_namespace.unwrap_or_default(),
+                changed_field: changed_field.unwrap_or_default(),
             }))
             .await
     );
@@ -144,8 +544,559 @@ async fn populate(server_url: String, to_version: String, from_version: String)
     Ok(())
 }
 
+async fn stats(server_url: String, version: Option<String>, columns: bool) -> Result<()> {
+    let mut client = connect(server_url).await?;
+
+    let response = execute!(
+        client
+            .stats(tonic::Request::new(StatsRequest {
+                version: version.unwrap_or_default(),
+                columns,
+            }))
+            .await
+    );
+    for entity in response.entities {
+        println!(
+            "{}/{}: {} rows",
+            entity.version, entity.entity_name, entity.row_count
+        );
+        for column in entity.columns {
+            let most_common = column
+                .most_common
+                .iter()
+                .map(|v| format!("{:?} ({})", v.value, v.count))
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!(
+                "  {}: {} distinct, {} null; most common: {}",
+                column.name, column.distinct_count, column.null_count, most_common
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn verify(
+    server_url: String,
+    version: String,
+    against: Option<String>,
+    ignore_ids: bool,
+) -> Result<()> {
+    let mut client = connect(server_url).await?;
+
+    let response = execute!(
+        client
+            .verify(tonic::Request::new(VerifyRequest {
+                version: version.clone(),
+                ignore_ids,
+            }))
+            .await
+    );
+    let against = match against {
+        Some(against) => against,
+        None => {
+            for entity in response.entities {
+                println!(
+                    "{}: {} rows, checksum {}",
+                    entity.entity_name, entity.row_count, entity.checksum
+                );
+            }
+            return Ok(());
+        }
+    };
+
+    let other = execute!(
+        client
+            .verify(tonic::Request::new(VerifyRequest {
+                version: against.clone(),
+                ignore_ids,
+            }))
+            .await
+    );
+    let mut other: HashMap<_, _> = other
+        .entities
+        .into_iter()
+        .map(|entity| (entity.entity_name.clone(), entity))
+        .collect();
+    let mut mismatches = 0;
+    for entity in response.entities {
+        match other.remove(&entity.entity_name) {
+            Some(theirs)
+                if theirs.row_count == entity.row_count && theirs.checksum == entity.checksum =>
+            {
+                println!("{}: OK, {} rows", entity.entity_name, entity.row_count);
+            }
+            Some(theirs) => {
+                mismatches += 1;
+                println!(
+                    "{}: MISMATCH, {} rows in {} and {} rows in {}",
+                    entity.entity_name, entity.row_count, version, theirs.row_count, against
+                );
+            }
+            None => {
+                mismatches += 1;
+                println!("{}: MISSING in {}", entity.entity_name, against);
+            }
+        }
+    }
+    let mut missing: Vec<_> = other.into_keys().collect();
+    missing.sort();
+    for entity_name in missing {
+        mismatches += 1;
+        println!("{}: MISSING in {}", entity_name, version);
+    }
+    anyhow::ensure!(
+        mismatches == 0,
+        "{} entities differ between {} and {}",
+        mismatches,
+        version,
+        against
+    );
+    Ok(())
+}
+
+async fn ps(server_url: String) -> Result<()> {
+    let mut client = connect(server_url).await?;
+
+    let response = execute!(client.ps(tonic::Request::new(PsRequest {})).await);
+    println!(
+        "{:>8} {:>8}  {:<8} {:<7} ROUTE",
+        "ID", "AGE", "VERSION", "METHOD"
+    );
+    for request in response.requests {
+        println!(
+            "{:>8} {:>7.1}s  {:<8} {:<7} {}",
+            request.id,
+            request.age_ms as f64 / 1000.0,
+            request.version,
+            request.method,
+            request.path
+        );
+        if let Some(sql) = request.sql {
+            println!("{:>8} {}", "", sql);
+        }
+    }
+    Ok(())
+}
+
+async fn kill(server_url: String, id: u64) -> Result<()> {
+    let mut client = connect(server_url).await?;
+
+    execute!(client.kill(tonic::Request::new(KillRequest { id })).await);
+    println!("Request {} killed", id);
+    Ok(())
+}
+
+async fn status(server_url: String, verbose: bool) -> Result<()> {
+    let mut client = connect(server_url).await?;
+    let request = tonic::Request::new(StatusRequest { verbose });
+    let response = execute!(client.get_status(request).await);
+    println!("Server status is {}", response.message);
+    if !verbose {
+        return Ok(());
+    }
+    let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+    println!("Executor memory:");
+    for isolate in response.isolates {
+        println!(
+            concat!(
+                "  executor {}: {:.1} MiB used of {:.1} MiB heap limit ",
+                "({:.1} MiB allocated, {:.1} MiB external)"
+            ),
+            isolate.executor,
+            mib(isolate.heap_used),
+            mib(isolate.heap_limit),
+            mib(isolate.heap_total),
+            mib(isolate.external)
+        );
+    }
+    println!("Module memory:");
+    for version in response.versions {
+        println!(
+            "  {}: {:.1} KiB",
+            version.version,
+            version.module_bytes as f64 / 1024.0
+        );
+    }
+    Ok(())
+}
+
+async fn profile(
+    server_url: String,
+    version: Option<String>,
+    endpoint: Option<String>,
+) -> Result<()> {
+    let mut client = connect(server_url).await?;
+
+    let response = execute!(
+        client
+            .list_profiles(tonic::Request::new(ListProfilesRequest {
+                version,
+                endpoint
+            }))
+            .await
+    );
+    if response.profiles.is_empty() {
+        println!("No requests were profiled");
+    }
+    for profile in response.profiles {
+        println!(
+            "{} /{}{}  {:.1}ms, {:.1}s ago",
+            profile.method,
+            profile.version,
+            profile.path,
+            profile.total_ms,
+            profile.age_ms as f64 / 1000.0
+        );
+        println!("  {:>9.1}ms  js", profile.js_ms);
+        for kind in ["sql", "fetch"] {
+            let spans: Vec<_> = profile.spans.iter().filter(|s| s.kind == kind).collect();
+            if spans.is_empty() {
+                continue;
+            }
+            let total: f64 = spans.iter().map(|span| span.duration_ms).sum();
+            println!("  {:>9.1}ms  {}", total, kind);
+            for span in spans {
+                println!(
+                    "    {:>7.1}ms  {}x {}",
+                    span.duration_ms, span.count, span.label
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn explain(server_url: String, version: Option<String>, endpoint: String) -> Result<()> {
+    let mut client = connect(server_url).await?;
+
+    let response = execute!(
+        client
+            .explain(tonic::Request::new(ExplainRequest { version, endpoint }))
+            .await
+    );
+    println!(
+        "{} /{}{}, profiled {:.1}s ago",
+        response.method,
+        response.version,
+        response.path,
+        response.age_ms as f64 / 1000.0
+    );
+    if response.statements.is_empty() {
+        println!("No SQL statements were run");
+    }
+    for statement in response.statements {
+        println!();
+        println!("{}", statement.sql);
+        for line in statement.plan {
+            println!("  {}", line);
+        }
+    }
+    Ok(())
+}
+
+async fn read_only(server_url: String, cmd: ReadOnlyCommand) -> Result<()> {
+    let mut client = connect(server_url).await?;
+
+    match cmd {
+        ReadOnlyCommand::Start {
+            version,
+            entities,
+            duration,
+        } => {
+            let what = if entities.is_empty() {
+                format!("Version {}", version)
+            } else {
+                format!("{} of version {}", entities.join(", "), version)
+            };
+            execute!(
+                client
+                    .start_read_only(tonic::Request::new(StartReadOnlyRequest {
+                        version,
+                        entities,
+                        duration_secs: duration,
+                    }))
+                    .await
+            );
+            match duration {
+                Some(duration) => println!("{} read-only for {}s", what, duration),
+                None => println!("{} read-only", what),
+            }
+        }
+        ReadOnlyCommand::Stop { version } => {
+            execute!(
+                client
+                    .stop_read_only(tonic::Request::new(StopReadOnlyRequest {
+                        version: version.clone(),
+                    }))
+                    .await
+            );
+            println!("Version {} writable", version);
+        }
+        ReadOnlyCommand::Status => {
+            let response = execute!(
+                client
+                    .list_read_only(tonic::Request::new(ListReadOnlyRequest {}))
+                    .await
+            );
+            for window in response.windows {
+                let entities = if window.entities.is_empty() {
+                    "all entities".to_string()
+                } else {
+                    window.entities.join(", ")
+                };
+                match window.remaining_secs {
+                    Some(remaining) => {
+                        println!("{}: {}, {}s remaining", window.version, entities, remaining)
+                    }
+                    None => println!("{}: {}", window.version, entities),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn api_key(server_url: String, cmd: ApiKeyCommand) -> Result<()> {
+    let mut client = connect(server_url).await?;
+
+    match cmd {
+        ApiKeyCommand::Create { name, user } => {
+            let response = execute!(
+                client
+                    .create_api_key(tonic::Request::new(CreateApiKeyRequest {
+                        name,
+                        user_id: user,
+                    }))
+                    .await
+            );
+            println!("{}", response.key);
+        }
+        ApiKeyCommand::Revoke { name } => {
+            execute!(
+                client
+                    .revoke_api_key(tonic::Request::new(RevokeApiKeyRequest {
+                        name: name.clone(),
+                    }))
+                    .await
+            );
+            println!("API key {} revoked", name);
+        }
+        ApiKeyCommand::List => {
+            let response = execute!(
+                client
+                    .list_api_keys(tonic::Request::new(ListApiKeysRequest {}))
+                    .await
+            );
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            println!("{:<24} {:<24} {:>6}", "NAME", "USER", "AGE");
+            for key in response.keys {
+                let age_days = now.saturating_sub(key.created_at) / (24 * 60 * 60);
+                println!("{:<24} {:<24} {:>5}d", key.name, key.user_id, age_days);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn migrate(server_url: String, cmd: MigrateCommand) -> Result<()> {
+    let mut client = connect(server_url.clone()).await?;
+
+    match cmd {
+        MigrateCommand::List => {
+            let response = execute!(
+                client
+                    .list_migrations(tonic::Request::new(ListMigrationsRequest {}))
+                    .await
+            );
+            for migration in response.migrations {
+                println!("{}: {}", migration.id, migration.version);
+                for change in migration.changes {
+                    println!("    {}", change);
+                }
+            }
+        }
+        MigrateCommand::Approve { id } => {
+            let response = execute!(
+                client
+                    .approve_migration(tonic::Request::new(ApproveMigrationRequest { id }))
+                    .await
+            );
+            let request = response
+                .request
+                .ok_or_else(|| anyhow!("staged migration {} has no apply", id))?;
+            send_apply(server_url, request).await?;
+        }
+        MigrateCommand::Reject { id } => {
+            execute!(
+                client
+                    .reject_migration(tonic::Request::new(RejectMigrationRequest { id }))
+                    .await
+            );
+            println!("Migration {} rejected", id);
+        }
+    }
+    Ok(())
+}
+
+async fn policy_impact(
+    server_url: String,
+    version: String,
+    file: Option<PathBuf>,
+    sample: u64,
+) -> Result<()> {
+    let file = match file {
+        Some(file) => Some(file),
+        None => {
+            let manifest = read_manifest()?;
+            let mut files = manifest
+                .policies()?
+                .into_iter()
+                .filter(|p| p.extension().and_then(|s| s.to_str()) != Some("ts"));
+            let file = files.next();
+            anyhow::ensure!(
+                files.next().is_none(),
+                "Currently only one policy file supported"
+            );
+            file
+        }
+    };
+    // Without a policy file, the impact of removing all policies is shown.
+    let policy_config = match &file {
+        Some(file) => read_to_string(file)?,
+        None => String::new(),
+    };
+
+    let mut client = connect(server_url).await?;
+    let response = execute!(
+        client
+            .policy_impact(tonic::Request::new(PolicyImpactRequest {
+                version,
+                policy_config,
+                sample,
+            }))
+            .await
+    );
+    if response.entities.is_empty() {
+        println!("The policies would not affect any data");
+    }
+    for entity in response.entities {
+        println!(
+            "{}: sampled {} of {} rows",
+            entity.entity_name, entity.sampled_rows, entity.total_rows
+        );
+        if entity.newly_filtered_rows > 0 {
+            println!(
+                "  {} rows would newly be filtered out for other users or tenants",
+                entity.newly_filtered_rows
+            );
+        }
+        for field in entity.fields {
+            println!(
+                "  {}: {} -> {}, {} rows affected",
+                field.field_name, field.before, field.after, field.affected_rows
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn fault(server_url: String, cmd: FaultCommand) -> Result<()> {
+    let mut client = connect(server_url).await?;
+
+    match cmd {
+        FaultCommand::Inject {
+            version,
+            endpoint,
+            kind,
+            percent,
+            delay,
+        } => {
+            let response = execute!(
+                client
+                    .inject_fault(tonic::Request::new(InjectFaultRequest {
+                        rule: Some(FaultRule {
+                            id: 0,
+                            version,
+                            endpoint,
+                            kind: kind as i32,
+                            percent,
+                            delay_ms: delay,
+                        }),
+                    }))
+                    .await
+            );
+            println!("Fault rule {} added", response.id);
+        }
+        FaultCommand::Clear { id } => {
+            execute!(
+                client
+                    .clear_faults(tonic::Request::new(ClearFaultsRequest { id }))
+                    .await
+            );
+            match id {
+                Some(id) => println!("Fault rule {} removed", id),
+                None => println!("All fault rules removed"),
+            }
+        }
+        FaultCommand::List => {
+            let response = execute!(
+                client
+                    .list_faults(tonic::Request::new(ListFaultsRequest {}))
+                    .await
+            );
+            for rule in response.rules {
+                let mut what = fault_kind_name(rule.kind).to_string();
+                if FaultKind::from_i32(rule.kind) == Some(FaultKind::SlowQuery) {
+                    what = format!("{} ({}ms)", what, rule.delay_ms);
+                }
+                println!(
+                    "{}: {} in {}% of requests to {}{}",
+                    rule.id,
+                    what,
+                    rule.percent,
+                    rule.version,
+                    rule.endpoint.unwrap_or_default()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn privacy(server_url: String, cmd: PrivacyCommand) -> Result<()> {
+    let mut client = connect(server_url).await?;
+
+    match cmd {
+        PrivacyCommand::Export { user } => {
+            let msg = execute!(
+                client
+                    .privacy_export(tonic::Request::new(PrivacyExportRequest { user_id: user }))
+                    .await
+            );
+            println!("{}", msg.data);
+        }
+        PrivacyCommand::Erase { user, anonymize } => {
+            let msg = execute!(
+                client
+                    .privacy_erase(tonic::Request::new(PrivacyEraseRequest {
+                        user_id: user.clone(),
+                        anonymize,
+                    }))
+                    .await
+            );
+            let action = if anonymize { "anonymized" } else { "erased" };
+            println!(
+                "User {} {}, {} rows deleted",
+                user, action, msg.deleted_rows
+            );
+        }
+    }
+    Ok(())
+}
+
 pub(crate) async fn restart(server_url: String) -> Result<()> {
-    let mut client = ChiselRpcClient::connect(server_url.clone()).await?;
+    let mut client = connect(server_url.clone()).await?;
     let response = execute!(client.restart(tonic::Request::new(RestartRequest {})).await);
     anyhow::ensure!(response.ok);
     wait_with_cond(server_url.clone(), |status| {
@@ -204,16 +1155,75 @@ async fn main() -> Result<()> {
             };
             create_project(&cwd, opts)?;
         }
-        Command::Describe => {
-            let mut client = ChiselRpcClient::connect(server_url).await?;
-            let request = tonic::Request::new(DescribeRequest {});
-            let response = execute!(client.describe(request).await);
+        Command::Describe { format, offline } => {
+            let response = snapshot::describe(server_url, offline).await?;
+            if format == DescribeFormat::OpenApi {
+                println!("{}", response.openapi);
+                return Ok(());
+            }
 
             for version_def in response.version_defs {
                 println!("Version: {} {{", version_def.version);
                 for def in &version_def.type_defs {
+                    if let Some(description) = &def.description {
+                        print!("{}", doc_comment("  ", description));
+                    }
+                    for index in &def.indexes {
+                        let fields = index.fields.iter().map(|f| format!("\"{}\"", f));
+                        let decorator = if index.search {
+                            "searchable"
+                        } else if index.counted {
+                            "counted"
+                        } else {
+                            "index"
+                        };
+                        let fields = fields.collect::<Vec<_>>().join(", ");
+                        println!("  @{}({})", decorator, fields);
+                    }
+                    if let Some(partition) = &def.partition {
+                        let options = match partition.by.as_str() {
+                            "time" if partition.retention > 0 => format!(
+                                "interval: \"{}\", retention: {}",
+                                partition.interval, partition.retention
+                            ),
+                            "time" => format!("interval: \"{}\"", partition.interval),
+                            _ => format!("partitions: {}", partition.partitions),
+                        };
+                        println!(
+                            "  @partition({{ by: \"{}\", field: \"{}\", {} }})",
+                            partition.by, partition.field, options
+                        );
+                    }
+                    if let Some(archive) = &def.archive {
+                        println!(
+                            "  @archive({{ field: \"{}\", afterDays: {} }})",
+                            archive.field, archive.after_days
+                        );
+                    }
+                    if let Some(connector) = &def.connector {
+                        let mut options = format!("url: \"{}\"", connector.url);
+                        if !connector.items.is_empty() {
+                            options += &format!(", items: \"{}\"", connector.items);
+                        }
+                        if !connector.fields.is_empty() {
+                            let mut fields = connector
+                                .fields
+                                .iter()
+                                .map(|(field, path)| format!("{}: \"{}\"", field, path))
+                                .collect::<Vec<_>>();
+                            fields.sort();
+                            options += &format!(", fields: {{ {} }}", fields.join(", "));
+                        }
+                        println!(
+                            "  @connector({{ {}, ttl: {}, rateLimit: {} }})",
+                            options, connector.ttl, connector.rate_limit
+                        );
+                    }
                     println!("  class {} {{", def.name);
                     for field in &def.field_defs {
+                        if let Some(description) = &field.description {
+                            print!("{}", doc_comment("    ", description));
+                        }
                         let labels = if field.labels.is_empty() {
                             "".into()
                         } else {
@@ -228,18 +1238,25 @@ async fn main() -> Result<()> {
                             labels.pop();
                             format!("@labels({}) ", labels)
                         };
+                        let column = match &field.column {
+                            Some(column) => format!("@column(\"{}\") ", column),
+                            None => "".into(),
+                        };
                         let field_type = field.field_type()?;
+                        let is_string =
+                            matches!(field_type, TypeEnum::String(_) | TypeEnum::EnumType(_));
                         println!(
-                            "    {}{}{}{}: {}{};",
+                            "    {}{}{}{}{}: {}{};",
                             if field.is_unique { "@unique " } else { "" },
                             labels,
+                            column,
                             field.name,
                             if field.is_optional { "?" } else { "" },
                             field_type,
                             field
                                 .default_value
                                 .as_ref()
-                                .map(|d| if matches!(field_type, TypeEnum::String(_)) {
+                                .map(|d| if is_string {
                                     format!(" = \"{}\"", d)
                                 } else {
                                     format!(" = {}", d)
@@ -249,15 +1266,74 @@ async fn main() -> Result<()> {
                     }
                     println!("  }}");
                 }
+                for def in &version_def.endpoint_defs {
+                    match (&def.crud_entity, def.crud_pagination.as_str()) {
+                        (Some(entity), "" | "pages") => {
+                            println!("  Endpoint {}: CRUD of {}", def.path, entity)
+                        }
+                        (Some(entity), pagination) => println!(
+                            "  Endpoint {}: CRUD of {}, {} pagination",
+                            def.path, entity, pagination
+                        ),
+                        (None, _) => println!("  Endpoint {}", def.path),
+                    }
+                    if let Some(request) = &def.request {
+                        println!("    request {}", body_signature(request)?);
+                    }
+                    if let Some(response) = &def.response {
+                        println!("    response {}", body_signature(response)?);
+                    }
+                }
                 for def in &version_def.label_policy_defs {
                     println!("  Label policy: {}", def.label);
                 }
+                for def in &version_def.job_defs {
+                    let status = match (&def.last_run, &def.last_error) {
+                        (None, _) => "never run".to_owned(),
+                        (Some(time), None) => format!(
+                            "last run at {} took {}ms",
+                            time,
+                            def.last_duration_ms.unwrap_or_default()
+                        ),
+                        (Some(time), Some(error)) => {
+                            format!("last run at {} failed: {}", time, error)
+                        }
+                    };
+                    println!("  Job {} \"{}\": {}", def.path, def.schedule, status);
+                }
+                for def in &version_def.webhook_defs {
+                    let entities = match def.entities.is_empty() {
+                        true => "all entities".to_owned(),
+                        false => def.entities.join(", "),
+                    };
+                    let signed = if def.signed { ", signed" } else { "" };
+                    println!(
+                        "  Webhook {}: {} of {}{}",
+                        def.url,
+                        def.events.join(", "),
+                        entities,
+                        signed
+                    );
+                }
                 println!("}}");
             }
         }
+        Command::Doctor { crash_dir } => {
+            cmd_doctor(server_url, &crash_dir).await?;
+        }
+        Command::Generate { cmd } => match cmd {
+            GenerateCommand::Client {
+                version,
+                out,
+                offline,
+            } => {
+                generate_client(server_url, version, &out, offline).await?;
+            }
+        },
         Command::Dev {
             type_check,
             inspect,
+            coverage,
         } => {
             let fut = cmd_dev(server_url.clone(), type_check);
             let cb = |mut server: Child, res| async move {
@@ -268,10 +1344,14 @@ async fn main() -> Result<()> {
 
                 Ok(())
             };
+            ensure_server_config()?;
             chiseld_args.push("--debug".to_string());
             if inspect {
                 chiseld_args.push("--inspect".to_string());
             }
+            if coverage {
+                chiseld_args.push("--coverage".to_string());
+            }
             spawn_server(chiseld_args, fut, cb).await?;
         }
         Command::New {
@@ -314,33 +1394,104 @@ async fn main() -> Result<()> {
 
             spawn_server(chiseld_args, fut, cb).await?;
         }
-        Command::Status => {
-            let mut client = ChiselRpcClient::connect(server_url).await?;
-            let request = tonic::Request::new(StatusRequest {});
-            let response = execute!(client.get_status(request).await);
-            println!("Server status is {}", response.message);
+        Command::Status { verbose } => {
+            status(server_url, verbose).await?;
         }
         Command::Wait => {
             wait(server_url).await?;
         }
         Command::Apply {
             allow_type_deletion,
+            cascade,
+            yes,
             version,
             type_check,
         } => {
+            let allow_type_deletion = if cascade {
+                AllowTypeDeletion::Cascade
+            } else {
+                allow_type_deletion.into()
+            };
             apply(
                 server_url,
                 version,
-                allow_type_deletion.into(),
+                allow_type_deletion,
+                yes.into(),
                 type_check.into(),
             )
             .await?;
         }
+        Command::Lint { type_check } => {
+            build(type_check.into()).await?;
+            println!("The project passes its checks");
+        }
         Command::Delete { version } => {
             delete(server_url, version).await?;
         }
-        Command::Populate { version, from } => {
-            populate(server_url, version, from).await?;
+        Command::Populate {
+            version,
+            from,
+            ids,
+            id_namespace,
+            changed_field,
+        } => {
+            populate(server_url, version, from, ids, id_namespace, changed_field).await?;
+        }
+        Command::Stats { version, columns } => {
+            stats(server_url, version, columns).await?;
+        }
+        Command::Verify {
+            version,
+            against,
+            ignore_ids,
+        } => {
+            verify(server_url, version, against, ignore_ids).await?;
+        }
+        Command::Privacy { cmd } => {
+            privacy(server_url, cmd).await?;
+        }
+        Command::Ps => {
+            ps(server_url).await?;
+        }
+        Command::Kill { id } => {
+            kill(server_url, id).await?;
+        }
+        Command::Profile { version, endpoint } => {
+            profile(server_url, version, endpoint).await?;
+        }
+        Command::Explain { version, endpoint } => {
+            explain(server_url, version, endpoint).await?;
+        }
+        Command::ReadOnly { cmd } => {
+            read_only(server_url, cmd).await?;
+        }
+        Command::ApiKey { cmd } => {
+            api_key(server_url, cmd).await?;
+        }
+        Command::Fault { cmd } => {
+            fault(server_url, cmd).await?;
+        }
+        Command::Migrate { cmd } => {
+            migrate(server_url, cmd).await?;
+        }
+        Command::PolicyImpact {
+            version,
+            file,
+            sample,
+        } => {
+            policy_impact(server_url, version, file, sample).await?;
+        }
+        Command::Test {
+            unit,
+            coverage,
+            coverage_dir,
+            record,
+        } => {
+            let coverage_dir = coverage.then(|| coverage_dir.as_path());
+            cmd_test(server_url, unit, coverage_dir, record).await?;
+        }
+        Command::Upgrade { channel, version } => {
+            cmd_upgrade(channel, version).await?;
         }
     }
 
@@ -12,9 +12,14 @@ use std::path::{Path, PathBuf};
 use utils::without_extension;
 
 const MANIFEST_FILE: &str = "Chisel.toml";
+const SERVER_CONFIG_FILE: &str = "chiseld.toml";
 const TYPES_DIR: &str = "./models";
 const ROUTES_DIR: &str = "./routes";
 const EVENTS_DIR: &str = "./events";
+const JOBS_DIR: &str = "./jobs";
+const TASKS_DIR: &str = "./tasks";
+const TESTS_DIR: &str = "./tests";
+const STATIC_DIR: &str = "./static";
 const LIB_DIR: &str = "./lib";
 const POLICIES_DIR: &str = "./policies";
 const VSCODE_DIR: &str = "./.vscode/";
@@ -61,6 +66,78 @@ impl Default for AutoIndex {
     }
 }
 
+/// What to do about the findings of a lint rule.
+#[derive(Deserialize, PartialEq, Clone, Copy)]
+pub(crate) enum LintLevel {
+    #[serde(rename = "off")]
+    Off,
+    #[serde(rename = "warn")]
+    Warn,
+    #[serde(rename = "error")]
+    Error,
+}
+
+impl Default for LintLevel {
+    fn default() -> Self {
+        LintLevel::Off
+    }
+}
+
+/// The schema lint rules checked by `chisel apply`, from the `[lint]` table
+/// of the manifest.
+#[derive(Deserialize)]
+#[serde(default)]
+pub(crate) struct Lint {
+    /// Entities filtered by fields that have no index. Needs `chiselc`.
+    pub(crate) missing_index: LintLevel,
+    /// Fields named like personal data that have no label.
+    pub(crate) pii_labels: LintLevel,
+    /// Regular expressions, matched case-insensitively, for the names of
+    /// fields that hold personal data.
+    pub(crate) pii_fields: Vec<String>,
+    /// Fields named like amounts of money that are floating-point numbers.
+    pub(crate) money_float: LintLevel,
+    /// Regular expressions, matched case-insensitively, for the names of
+    /// fields that hold amounts of money.
+    pub(crate) money_fields: Vec<String>,
+}
+
+impl Default for Lint {
+    fn default() -> Self {
+        let patterns = |p: &[&str]| p.iter().map(|p| p.to_string()).collect();
+        Lint {
+            missing_index: LintLevel::Off,
+            pii_labels: LintLevel::Off,
+            pii_fields: patterns(&["email", "phone", "address", "birth", "ssn"]),
+            money_float: LintLevel::Off,
+            money_fields: patterns(&["price", "amount", "cost", "balance", "salary"]),
+        }
+    }
+}
+
+/// The commands and functions run by `chisel apply`, from the `[hooks]` table
+/// of the manifest.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub(crate) struct Hooks {
+    /// Run before applying, which stops if one of them fails.
+    pub(crate) pre_apply: Vec<String>,
+    /// Run after a successful apply.
+    pub(crate) post_apply: Vec<String>,
+}
+
+/// How the tables and columns of new entities and fields are named, from the
+/// `[naming]` table of the manifest. `@table` and `@column` override it.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub(crate) struct Naming {
+    /// Prepended to the names of the tables.
+    pub(crate) table_prefix: String,
+    /// Whether tables and columns are named in snake_case, rather than like
+    /// the entities and fields.
+    pub(crate) snake_case: bool,
+}
+
 /// Manifest defines the files that describe types, routes, events, and policies.
 ///
 /// The manifest is a high-level declaration of application behavior.
@@ -77,8 +154,21 @@ pub(crate) struct Manifest {
     pub(crate) routes: Vec<String>,
     /// Vector of directories to scan for event handler definitions.
     pub(crate) events: Option<Vec<String>>,
+    /// Vector of directories to scan for scheduled jobs.
+    pub(crate) jobs: Option<Vec<String>>,
+    /// Vector of directories to scan for background tasks.
+    pub(crate) tasks: Option<Vec<String>>,
+    /// Vector of directories to scan for unit tests.
+    pub(crate) tests: Option<Vec<String>>,
     /// Vector of directories to scan for policy definitions.
     pub(crate) policies: Vec<String>,
+    /// Vector of directories of static files, served under `/<version>/static/`.
+    #[serde(rename = "static")]
+    pub(crate) static_dirs: Option<Vec<String>>,
+    /// OAuth / OpenID Connect providers that users log in with, under
+    /// `/<version>/__auth/`.
+    #[serde(default)]
+    pub(crate) login: Vec<String>,
     /// Whether to use deno-style or node-style modules
     #[serde(default)]
     pub(crate) modules: Module,
@@ -88,6 +178,15 @@ pub(crate) struct Manifest {
     /// Enable or disable auto-indexing.
     #[serde(default)]
     pub(crate) auto_index: AutoIndex,
+    /// Schema lint rules.
+    #[serde(default)]
+    pub(crate) lint: Lint,
+    /// Hooks run around applies.
+    #[serde(default)]
+    pub(crate) hooks: Hooks,
+    /// Naming of tables and columns.
+    #[serde(default)]
+    pub(crate) naming: Naming,
 }
 
 impl Manifest {
@@ -115,10 +214,67 @@ impl Manifest {
         Ok(ret)
     }
 
+    pub fn jobs(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let jobs = match &self.jobs {
+            Some(jobs) => jobs.to_owned(),
+            None => vec![JOBS_DIR.into()],
+        };
+        Self::dirs_to_paths(&jobs)
+    }
+
+    pub fn tasks(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let tasks = match &self.tasks {
+            Some(tasks) => tasks.to_owned(),
+            None => vec![TASKS_DIR.into()],
+        };
+        Self::dirs_to_paths(&tasks)
+    }
+
+    pub fn tests(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let tests = match &self.tests {
+            Some(tests) => tests.to_owned(),
+            None => vec![TESTS_DIR.into()],
+        };
+        Self::dirs_to_paths(&tests)
+    }
+
     pub fn policies(&self) -> anyhow::Result<Vec<PathBuf>> {
         Self::dirs_to_paths(&self.policies)
     }
 
+    /// The static files, with their paths relative to their directory.
+    pub fn static_files(&self) -> anyhow::Result<Vec<(String, PathBuf)>> {
+        let dirs = match &self.static_dirs {
+            Some(dirs) => dirs.to_owned(),
+            None => vec![STATIC_DIR.into()],
+        };
+        let mut files = vec![];
+        for dir in dirs {
+            for path in Self::dirs_to_paths(std::slice::from_ref(&dir))? {
+                let relative = path.strip_prefix(&dir)?;
+                let relative: Vec<_> = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect();
+                files.push((relative.join("/"), path));
+            }
+        }
+        files.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        if let Some((a, b)) = files
+            .iter()
+            .zip(files.iter().skip(1))
+            .find(|(a, b)| a.0 == b.0)
+        {
+            anyhow::bail!(
+                "Cannot add both {} and {} as static files, as both are served at static/{}",
+                a.1.display(),
+                b.1.display(),
+                a.0
+            );
+        }
+        Ok(files)
+    }
+
     fn dirs_to_paths(dirs: &[String]) -> anyhow::Result<Vec<PathBuf>> {
         // sucks to do this for all invocations but keeps things simple
         let me = Path::new("./").canonicalize()?;
@@ -284,6 +440,8 @@ pub(crate) fn create_project(path: &Path, opts: CreateProjectOptions) -> Result<
     fs::create_dir_all(path.join(TYPES_DIR))?;
     fs::create_dir_all(path.join(ROUTES_DIR))?;
     fs::create_dir_all(path.join(EVENTS_DIR))?;
+    fs::create_dir_all(path.join(JOBS_DIR))?;
+    fs::create_dir_all(path.join(TASKS_DIR))?;
     fs::create_dir_all(path.join(LIB_DIR))?;
     fs::create_dir_all(path.join(POLICIES_DIR))?;
     fs::create_dir_all(path.join(VSCODE_DIR))?;
@@ -327,11 +485,32 @@ pub(crate) fn create_project(path: &Path, opts: CreateProjectOptions) -> Result<
     Ok(())
 }
 
+/// Writes a commented-out chiseld configuration file in the current
+/// directory, unless there is one already.
+pub(crate) fn ensure_server_config() -> Result<()> {
+    let path = Path::new(SERVER_CONFIG_FILE);
+    if path.exists() {
+        return Ok(());
+    }
+    write(
+        include_str!("template/chiseld.toml"),
+        Path::new("."),
+        SERVER_CONFIG_FILE,
+    )?;
+    println!(
+        "Created {} with the default server configuration",
+        SERVER_CONFIG_FILE
+    );
+    Ok(())
+}
+
 pub(crate) fn project_exists(path: &Path) -> bool {
     path.join(Path::new(MANIFEST_FILE)).exists()
         || path.join(Path::new(TYPES_DIR)).exists()
         || path.join(Path::new(ROUTES_DIR)).exists()
         || path.join(Path::new(EVENTS_DIR)).exists()
+        || path.join(Path::new(JOBS_DIR)).exists()
+        || path.join(Path::new(TASKS_DIR)).exists()
         || path.join(Path::new(POLICIES_DIR)).exists()
 }
 
@@ -1,8 +1,8 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
 use crate::proto::chisel_rpc_client::ChiselRpcClient;
-use crate::proto::{StatusRequest, StatusResponse};
-use anyhow::Result;
+use crate::proto::{HandshakeRequest, StatusRequest, StatusResponse};
+use anyhow::{anyhow, bail, Result};
 use std::future::Future;
 use std::io::ErrorKind;
 
@@ -68,6 +68,70 @@ where
     }
 }
 
+/// Connects to the server at `server_url`, failing if its version doesn't
+/// work with this chisel.
+pub(crate) async fn connect(server_url: String) -> Result<ChiselRpcClient<Channel>> {
+    let mut client = ChiselRpcClient::connect(server_url.clone()).await?;
+    check_version(&mut client, &server_url).await?;
+    Ok(client)
+}
+
+/// Checks that the version of the server works with this chisel, both as
+/// the server sees it and as chisel does, and returns the server's version.
+pub(crate) async fn check_version(
+    client: &mut ChiselRpcClient<Channel>,
+    server_url: &str,
+) -> Result<String> {
+    let client_version = env!("CARGO_PKG_VERSION");
+    let request = tonic::Request::new(HandshakeRequest {
+        client_version: client_version.to_owned(),
+    });
+    let response = match client.handshake(request).await {
+        Ok(response) => response.into_inner(),
+        Err(status) if status.code() == tonic::Code::Unimplemented => bail!(
+            "The server at {} is older than chisel {} and may not work with it. \
+             Run `chisel upgrade --version` with the version of the server to use the \
+             chisel release of the same version.",
+            server_url,
+            client_version
+        ),
+        Err(status) => return Err(anyhow!(status.message().to_owned())),
+    };
+    let server_version = response.server_version;
+    if !utils::version_in_range(
+        client_version,
+        &response.min_client_version,
+        &response.max_client_version,
+    )? {
+        bail!(
+            "chisel {} does not work with the server at {}, which runs chiseld {} and needs \
+             chisel >= {} and < {}. Run `chisel upgrade --version {}` to install the chisel \
+             release of the same version as the server.",
+            client_version,
+            server_url,
+            server_version,
+            response.min_client_version,
+            response.max_client_version,
+            server_version
+        );
+    }
+    let (min, max) = utils::compatible_versions(client_version)?;
+    if !utils::version_in_range(&server_version, &min, &max)? {
+        bail!(
+            "The server at {} runs chiseld {}, but chisel {} needs chiseld >= {} and < {}. \
+             Run `chisel upgrade --version {}` to install the chisel release of the same \
+             version as the server.",
+            server_url,
+            server_version,
+            client_version,
+            min,
+            max,
+            server_version
+        );
+    }
+    Ok(server_version)
+}
+
 async fn connect_with_retry(server_url: String) -> Result<ChiselRpcClient<Channel>> {
     with_retry(TIMEOUT, (), |_| async {
         let c = ChiselRpcClient::connect(server_url.clone()).await;
@@ -88,7 +152,7 @@ where
 {
     let client = connect_with_retry(server_url).await?;
     with_retry(TIMEOUT, client, |mut client| async {
-        let request = tonic::Request::new(StatusRequest {});
+        let request = tonic::Request::new(StatusRequest::default());
         let s = client.get_status(request).await;
 
         match s {
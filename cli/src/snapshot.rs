@@ -0,0 +1,104 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Snapshots of what the server describes of the API, so that the commands
+//! that only read it, like `chisel describe` and `chisel generate client`,
+//! still work without a reachable server.
+//!
+//! Every describe run in a project is saved in it, one snapshot per server
+//! address, and is used instead of the server when asked to be offline or
+//! when the server can't be reached.
+
+use crate::project::project_exists;
+use crate::proto::{DescribeRequest, DescribeResponse};
+use crate::server::connect;
+use anyhow::{anyhow, Context, Result};
+use prost::Message;
+use std::path::{Path, PathBuf};
+
+/// Directory of the project the snapshots are saved in.
+const SNAPSHOT_DIR: &str = ".chisel-cache";
+
+/// The path of the snapshot of the server at `server_url`, named after its
+/// address, like `describe-localhost_50051.pb`.
+fn snapshot_path(server_url: &str) -> PathBuf {
+    let address = server_url.split_once("://").map_or(server_url, |(_, a)| a);
+    let address: String = address
+        .trim_end_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    PathBuf::from(SNAPSHOT_DIR).join(format!("describe-{}.pb", address))
+}
+
+/// Describes the API, with its OpenAPI document, from the server at
+/// `server_url`, or from its snapshot if `offline` or if the server can't
+/// be reached.
+pub(crate) async fn describe(server_url: String, offline: bool) -> Result<DescribeResponse> {
+    let path = snapshot_path(&server_url);
+    if !offline {
+        match describe_server(server_url.clone()).await {
+            Ok(response) => {
+                if project_exists(Path::new(".")) {
+                    if let Err(e) = save(&path, &response) {
+                        eprintln!("Warning: could not save the API snapshot: {:?}", e);
+                    }
+                }
+                return Ok(response);
+            }
+            Err(e) if !path.exists() => return Err(e),
+            Err(e) => eprintln!(
+                "Warning: {}, using the API it described last, saved in {}",
+                e,
+                path.display()
+            ),
+        }
+    }
+    load(&path).with_context(|| {
+        format!(
+            "No API snapshot of the server at {} to work offline with. Run a command that \
+             describes the API, like `chisel describe`, while the server is up to save one.",
+            server_url
+        )
+    })
+}
+
+async fn describe_server(server_url: String) -> Result<DescribeResponse> {
+    let mut client = connect(server_url.clone())
+        .await
+        .with_context(|| format!("could not connect to the server at {}", server_url))?;
+    let request = tonic::Request::new(DescribeRequest { openapi: true });
+    Ok(execute!(client.describe(request).await))
+}
+
+fn save(path: &Path, response: &DescribeResponse) -> Result<()> {
+    std::fs::create_dir_all(SNAPSHOT_DIR)?;
+    // Renamed into place, so that a snapshot is never read half-written.
+    let tmp = path.with_extension("pb.tmp");
+    std::fs::write(&tmp, response.encode_to_vec())?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn load(path: &Path) -> Result<DescribeResponse> {
+    let bytes = std::fs::read(path)?;
+    DescribeResponse::decode(bytes.as_slice())
+        .map_err(|e| anyhow!("Could not parse {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_paths() {
+        let path = |url| snapshot_path(url).display().to_string();
+        assert_eq!(
+            path("http://localhost:50051"),
+            ".chisel-cache/describe-localhost_50051.pb"
+        );
+        assert_eq!(
+            path("https://chisel.example.com/"),
+            ".chisel-cache/describe-chisel_example_com.pb"
+        );
+    }
+}
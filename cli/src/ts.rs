@@ -1,20 +1,26 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
-use crate::proto::{type_msg::TypeEnum, AddTypeRequest, ContainerType, FieldDefinition, TypeMsg};
+use crate::proto::{
+    type_msg::TypeEnum, AddTypeRequest, ArchiveDefinition, BodySchema, CacheHint,
+    ConnectorDefinition, ContainerType, EndpointSchema, EnumType, FieldDefinition, IndexDefinition,
+    PartitionDefinition, TypeMsg, UnionType,
+};
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use chisel_server::is_auth_entity_name;
 use std::collections::BTreeSet;
 use std::fmt;
 use std::path::Path;
+use swc_common::comments::{CommentKind, Comments, SingleThreadedComments};
 use swc_common::sync::Lrc;
 use swc_common::{
     errors::{emitter, Handler},
-    SourceMap, Spanned,
+    BytePos, SourceMap, Spanned,
 };
 use swc_ecma_ast::PropName;
 use swc_ecma_ast::{
-    ClassMember, ClassProp, Decl, Decorator, Expr, Ident, Lit, ModuleDecl, ModuleItem,
-    TsEntityName, TsKeywordTypeKind, TsType, TsTypeAnn,
+    ClassMember, ClassProp, Decl, Decorator, Expr, ExprOrSpread, Ident, Lit, Module, ModuleDecl,
+    ModuleItem, ObjectLit, Prop, PropOrSpread, TsEntityName, TsKeywordTypeKind, TsLit, TsType,
+    TsTypeAnn, TsTypeElement, TsTypeRef, TsUnionOrIntersectionType, UnaryOp,
 };
 use swc_ecma_parser::{lexer::Lexer, Parser, StringInput, Syntax, TsConfig};
 use swc_ecmascript::ast::{self as swc_ecma_ast};
@@ -60,6 +66,14 @@ impl fmt::Display for TypeEnum {
             TypeEnum::Number(_) => f.write_str("number"),
             TypeEnum::Bool(_) => f.write_str("boolean"),
             TypeEnum::Entity(name) => name.fmt(f),
+            TypeEnum::EnumType(EnumType { variants }) => {
+                let variants: Vec<_> = variants
+                    .iter()
+                    .map(|variant| serde_json::to_string(variant).unwrap())
+                    .collect();
+                f.write_str(&variants.join(" | "))
+            }
+            TypeEnum::UnionType(UnionType { entities }) => f.write_str(&entities.join(" | ")),
             TypeEnum::Array(inner) => {
                 let inner = inner.value_type().unwrap();
                 write!(f, "Array<{inner}>")
@@ -101,10 +115,17 @@ fn map_array_type(handler: &Handler, x: &TsType) -> Result<TypeEnum> {
                 _ => Err(swc_err(handler, x, "type keyword not supported")),
             },
             TsType::TsArrayType(_) => map_array_type(handler, &*array_type.elem_type),
+            // An array of entities is a many-to-many relation.
+            TsType::TsTypeRef(tr) => match &tr.type_name {
+                TsEntityName::Ident(id) => Ok(TypeEnum::Entity(ident_to_string(id))),
+                TsEntityName::TsQualifiedName(_) => {
+                    Err(anyhow!("qualified names are not supported"))
+                }
+            },
             _ => Err(swc_err(
                 handler,
                 x,
-                "only arrays of primitive types are supported",
+                "only arrays of primitive types and entities are supported",
             )),
         }
         .map(TypeEnum::array),
@@ -128,6 +149,34 @@ fn map_type(handler: &Handler, x: &TsType) -> Result<TypeEnum> {
             TsEntityName::TsQualifiedName(_) => Err(anyhow!("qualified names are not supported")),
         },
         TsType::TsArrayType(_) => map_array_type(handler, x),
+        TsType::TsUnionOrIntersectionType(TsUnionOrIntersectionType::TsUnionType(union)) => {
+            // A union of entities, like `Post | Photo`, is a polymorphic
+            // relation.
+            if let TsType::TsTypeRef(_) = &*union.types[0] {
+                let mut entities = vec![];
+                for ty in &union.types {
+                    match &**ty {
+                        TsType::TsTypeRef(TsTypeRef {
+                            type_name: TsEntityName::Ident(id),
+                            ..
+                        }) => entities.push(ident_to_string(id)),
+                        _ => return Err(swc_err(handler, ty, "expected an entity")),
+                    }
+                }
+                return Ok(TypeEnum::UnionType(UnionType { entities }));
+            }
+            let mut variants = vec![];
+            for ty in &union.types {
+                match &**ty {
+                    TsType::TsLitType(lit) => match &lit.lit {
+                        TsLit::Str(s) => variants.push(s.value.to_string()),
+                        _ => return Err(swc_err(handler, ty, "expected a string literal")),
+                    },
+                    _ => return Err(swc_err(handler, ty, "expected a string literal")),
+                }
+            }
+            Ok(TypeEnum::EnumType(EnumType { variants }))
+        }
         t => Err(swc_err(handler, t, "type is not supported")),
     }
 }
@@ -167,9 +216,92 @@ fn get_field_value(handler: &Handler, x: &Expr) -> Result<Option<(String, TypeEn
     }
 }
 
-fn get_type_decorators(handler: &Handler, x: &[Decorator]) -> Result<(Vec<String>, bool)> {
+/// Name of the type of the fields that hold any JSON value.
+const JSON_TYPE: &str = "JSONValue";
+/// Name of the type of the fields that hold a file of the blob store.
+const FILE_TYPE: &str = "ChiselFile";
+/// Static methods of a model class that run around its writes.
+const ENTITY_HOOKS: [&str; 6] = [
+    "beforeCreate",
+    "afterCreate",
+    "beforeUpdate",
+    "afterUpdate",
+    "beforeDelete",
+    "afterDelete",
+];
+
+/// Whether type `name`, referred to like an entity, is a builtin type whose
+/// values aren't entities.
+fn is_value_type(name: &str) -> bool {
+    name == "Date" || name == JSON_TYPE || name == FILE_TYPE
+}
+
+/// Converts `x` to JSON if it is made of literals, arrays and objects only,
+/// for the default value of a JSONValue field.
+fn get_json_value(x: &Expr) -> Option<serde_json::Value> {
+    fn number(n: f64) -> Option<serde_json::Value> {
+        if n.fract() == 0.0 && n.abs() < 1e15 {
+            Some((n as i64).into())
+        } else {
+            serde_json::Number::from_f64(n).map(Into::into)
+        }
+    }
+
+    let value = match x {
+        Expr::Lit(Lit::Str(s)) => s.value.to_string().into(),
+        Expr::Lit(Lit::Bool(b)) => b.value.into(),
+        Expr::Lit(Lit::Num(n)) => number(n.value)?,
+        Expr::Lit(Lit::Null(_)) => serde_json::Value::Null,
+        Expr::Unary(u) if u.op == UnaryOp::Minus => match &*u.arg {
+            Expr::Lit(Lit::Num(n)) => number(-n.value)?,
+            _ => return None,
+        },
+        Expr::Paren(p) => get_json_value(&p.expr)?,
+        Expr::Array(array) => array
+            .elems
+            .iter()
+            .map(|elem| match elem {
+                Some(ExprOrSpread { spread: None, expr }) => get_json_value(expr),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?
+            .into(),
+        Expr::Object(object) => {
+            let mut map = serde_json::Map::new();
+            for prop in &object.props {
+                let kv = match prop {
+                    PropOrSpread::Prop(prop) => match &**prop {
+                        Prop::KeyValue(kv) => kv,
+                        _ => return None,
+                    },
+                    PropOrSpread::Spread(_) => return None,
+                };
+                let key = match &kv.key {
+                    PropName::Ident(id) => ident_to_string(id),
+                    PropName::Str(s) => s.value.to_string(),
+                    _ => return None,
+                };
+                map.insert(key, get_json_value(&kv.value)?);
+            }
+            map.into()
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Parses the decorators of a field, returning its labels, and whether it is
+/// unique and whether it is indexed.
+/// Parses the decorators of a field, returning its labels, whether it is
+/// unique or indexed, and the column of `@column`, if any.
+fn get_type_decorators(
+    handler: &Handler,
+    x: &[Decorator],
+) -> Result<(Vec<String>, bool, bool, Option<String>)> {
     let mut output = vec![];
     let mut is_unique = false;
+    let mut is_indexed = false;
+    let mut column = None;
     for dec in x.iter() {
         match &*dec.expr {
             Expr::Call(call) => {
@@ -177,6 +309,14 @@ fn get_type_decorators(handler: &Handler, x: &[Decorator]) -> Result<(Vec<String
                     anyhow!("expected expression, got {:?} instead", call.callee.clone())
                 })?;
                 let name = get_ident_string(handler, &callee)?;
+                if name == "column" {
+                    ensure!(column.is_none(), "@column can only be used once per field");
+                    column = match call.args.as_slice() {
+                        [arg] => Some(string_option(handler, &arg.expr)?),
+                        _ => bail!("@column takes the name of the column"),
+                    };
+                    continue;
+                }
                 ensure!(
                     name == "labels",
                     format!("decorator '{}' is not supported by ChiselStrike", name)
@@ -195,18 +335,325 @@ fn get_type_decorators(handler: &Handler, x: &[Decorator]) -> Result<(Vec<String
                 let name = ident_to_string(x);
                 ensure!(name != "labels", "expected a call-like decorator");
 
-                ensure!(
-                    name == "unique",
-                    format!("decorator '{}' is not supported by ChiselStrike", name)
-                );
-                is_unique = true;
+                match name.as_str() {
+                    "unique" => is_unique = true,
+                    "index" => is_indexed = true,
+                    _ => bail!("decorator '{}' is not supported by ChiselStrike", name),
+                }
             }
             z => {
                 return Err(swc_err(handler, z, "expected a call-like decorator"));
             }
         };
     }
-    Ok((output, is_unique))
+    Ok((output, is_unique, is_indexed, column))
+}
+
+/// What the decorators of an entity class declare.
+#[derive(Default)]
+struct ClassDecorators {
+    cache: Option<CacheHint>,
+    indexes: Vec<IndexDefinition>,
+    partition: Option<PartitionDefinition>,
+    archive: Option<ArchiveDefinition>,
+    connector: Option<ConnectorDefinition>,
+    crud_path: Option<String>,
+    crud_pagination: String,
+    table: Option<String>,
+}
+
+/// Parses the decorators of an entity class, which are `@cache`,
+/// `@searchable`, `@partition`, `@archive`, `@connector`, `@autoCrud`,
+/// `@table` and any number of `@index` and `@counted`.
+fn get_class_decorators(handler: &Handler, x: &[Decorator]) -> Result<ClassDecorators> {
+    let mut decorators = ClassDecorators::default();
+    for dec in x.iter() {
+        let call = match &*dec.expr {
+            Expr::Call(call) => call,
+            z => return Err(swc_err(handler, z, "expected a call-like decorator")),
+        };
+        let callee =
+            call.callee.clone().expr().ok_or_else(|| {
+                anyhow!("expected expression, got {:?} instead", call.callee.clone())
+            })?;
+        let name = get_ident_string(handler, &callee)?;
+        if name == "index" || name == "searchable" || name == "counted" {
+            let mut fields = vec![];
+            for arg in &call.args {
+                match get_field_value(handler, &arg.expr)? {
+                    Some((field, TypeEnum::String(_))) => fields.push(field),
+                    _ => return Err(swc_err(handler, &*arg.expr, "expected a field name")),
+                }
+            }
+            if name == "counted" {
+                // A counter per field, or one of all the rows.
+                let mut groups: Vec<_> = fields.into_iter().map(|f| vec![f]).collect();
+                if groups.is_empty() {
+                    groups.push(vec![]);
+                }
+                for fields in groups {
+                    decorators.indexes.push(IndexDefinition {
+                        fields,
+                        search: false,
+                        counted: true,
+                    });
+                }
+                continue;
+            }
+            ensure!(
+                !fields.is_empty(),
+                "@{} takes the names of the fields to index",
+                name
+            );
+            let search = name == "searchable";
+            ensure!(
+                !search || !decorators.indexes.iter().any(|i| i.search),
+                "@searchable can only be used once per class"
+            );
+            decorators.indexes.push(IndexDefinition {
+                fields,
+                search,
+                counted: false,
+            });
+            continue;
+        }
+        if name == "partition" {
+            ensure!(
+                decorators.partition.is_none(),
+                "@partition can only be used once per class"
+            );
+            decorators.partition = Some(get_partition(handler, &call.args)?);
+            continue;
+        }
+        if name == "archive" {
+            ensure!(
+                decorators.archive.is_none(),
+                "@archive can only be used once per class"
+            );
+            decorators.archive = Some(get_archive(handler, &call.args)?);
+            continue;
+        }
+        if name == "connector" {
+            ensure!(
+                decorators.connector.is_none(),
+                "@connector can only be used once per class"
+            );
+            decorators.connector = Some(get_connector(handler, &call.args)?);
+            continue;
+        }
+        if name == "table" {
+            ensure!(
+                decorators.table.is_none(),
+                "@table can only be used once per class"
+            );
+            decorators.table = match call.args.as_slice() {
+                [arg] => Some(string_option(handler, &arg.expr)?),
+                _ => bail!("@table takes the name of the table"),
+            };
+            continue;
+        }
+        if name == "autoCrud" {
+            ensure!(
+                decorators.crud_path.is_none(),
+                "@autoCrud can only be used once per class"
+            );
+            // The path of the route and its options, both optional.
+            let (path, options) = match call.args.as_slice() {
+                [] => (String::new(), None),
+                [options] if matches!(&*options.expr, Expr::Object(_)) => {
+                    (String::new(), Some(options))
+                }
+                [path] => (string_option(handler, &path.expr)?, None),
+                [path, options] => (string_option(handler, &path.expr)?, Some(options)),
+                _ => bail!("@autoCrud takes at most the path of the route and its options"),
+            };
+            if let Some(options) = options {
+                let options = std::slice::from_ref(options);
+                for (key, value) in get_options(handler, "autoCrud", options)? {
+                    match key.as_str() {
+                        "pagination" => decorators.crud_pagination = string_option(handler, value)?,
+                        key => bail!("unknown @autoCrud option '{}'", key),
+                    }
+                }
+                ensure!(
+                    ["pages", "envelope", "array"].contains(&decorators.crud_pagination.as_str()),
+                    "the pagination of @autoCrud must be \"pages\", \"envelope\" or \"array\", got \"{}\"",
+                    decorators.crud_pagination
+                );
+            }
+            decorators.crud_path = Some(path);
+            continue;
+        }
+        ensure!(
+            name == "cache",
+            format!("decorator '{}' is not supported by ChiselStrike", name)
+        );
+        let hint = match call.args.as_slice() {
+            [arg] => match &*arg.expr {
+                Expr::Object(hint) => hint,
+                z => return Err(swc_err(handler, z, "expected an object literal")),
+            },
+            _ => bail!("@cache takes a single object argument"),
+        };
+        let mut ttl = None;
+        let mut stale_while_revalidate = 0;
+        for prop in &hint.props {
+            let (key, value) = match prop {
+                PropOrSpread::Prop(prop) => match &**prop {
+                    Prop::KeyValue(kv) => (get_field_info(handler, &kv.key)?.0, &kv.value),
+                    z => return Err(swc_err(handler, z, "expected a `key: value` property")),
+                },
+                PropOrSpread::Spread(z) => {
+                    return Err(swc_err(handler, z, "expected a `key: value` property"))
+                }
+            };
+            let seconds = match &**value {
+                Expr::Lit(Lit::Num(n)) if n.value >= 0.0 && n.value.fract() == 0.0 => {
+                    n.value as u64
+                }
+                z => return Err(swc_err(handler, z, "expected a whole number of seconds")),
+            };
+            match key.as_str() {
+                "ttl" => ttl = Some(seconds),
+                "staleWhileRevalidate" => stale_while_revalidate = seconds,
+                key => bail!("unknown @cache option '{}'", key),
+            }
+        }
+        decorators.cache = Some(CacheHint {
+            ttl: ttl.context("@cache requires a ttl")?,
+            stale_while_revalidate,
+        });
+    }
+    Ok(decorators)
+}
+
+/// Parses the options of `@partition`, like `{ by: "time", field: "createdAt",
+/// interval: "month", retention: 12 }` or `{ by: "hash", field: "name",
+/// partitions: 8 }`.
+fn get_partition(handler: &Handler, args: &[ExprOrSpread]) -> Result<PartitionDefinition> {
+    let mut partition = PartitionDefinition::default();
+    for (key, value) in get_options(handler, "partition", args)? {
+        match key.as_str() {
+            "by" => partition.by = string_option(handler, value)?,
+            "field" => partition.field = string_option(handler, value)?,
+            "interval" => partition.interval = string_option(handler, value)?,
+            "retention" => partition.retention = number_option(handler, value)?,
+            "partitions" => partition.partitions = number_option(handler, value)?,
+            key => bail!("unknown @partition option '{}'", key),
+        }
+    }
+    match partition.by.as_str() {
+        "time" => ensure!(
+            !partition.interval.is_empty(),
+            "@partition by time requires an interval"
+        ),
+        "hash" => ensure!(
+            partition.partitions > 0,
+            "@partition by hash requires a number of partitions"
+        ),
+        by => bail!("@partition by '{}' is not supported, use time or hash", by),
+    }
+    ensure!(!partition.field.is_empty(), "@partition requires a field");
+    Ok(partition)
+}
+
+/// Parses the options of `@archive`, like `{ field: "createdAt", afterDays:
+/// 90 }`.
+fn get_archive(handler: &Handler, args: &[ExprOrSpread]) -> Result<ArchiveDefinition> {
+    let mut archive = ArchiveDefinition::default();
+    for (key, value) in get_options(handler, "archive", args)? {
+        match key.as_str() {
+            "field" => archive.field = string_option(handler, value)?,
+            "afterDays" => archive.after_days = number_option(handler, value)?,
+            key => bail!("unknown @archive option '{}'", key),
+        }
+    }
+    ensure!(!archive.field.is_empty(), "@archive requires a field");
+    ensure!(archive.after_days > 0, "@archive requires afterDays");
+    Ok(archive)
+}
+
+/// Parses the options of `@connector`, like `{ url: "https://example.com/items",
+/// items: "data", fields: { name: "attributes.name" }, ttl: 60, rateLimit: 10
+/// }`.
+fn get_connector(handler: &Handler, args: &[ExprOrSpread]) -> Result<ConnectorDefinition> {
+    let mut connector = ConnectorDefinition {
+        ttl: 60,
+        rate_limit: 60,
+        ..Default::default()
+    };
+    for (key, value) in get_options(handler, "connector", args)? {
+        match key.as_str() {
+            "url" => connector.url = string_option(handler, value)?,
+            "items" => connector.items = string_option(handler, value)?,
+            "fields" => {
+                let fields = match value {
+                    Expr::Object(fields) => fields,
+                    z => return Err(swc_err(handler, z, "expected an object literal")),
+                };
+                for (field, path) in object_props(handler, fields)? {
+                    connector
+                        .fields
+                        .insert(field, string_option(handler, path)?);
+                }
+            }
+            "ttl" => connector.ttl = number_option(handler, value)?.into(),
+            "rateLimit" => connector.rate_limit = number_option(handler, value)?,
+            key => bail!("unknown @connector option '{}'", key),
+        }
+    }
+    ensure!(!connector.url.is_empty(), "@connector requires a url");
+    Ok(connector)
+}
+
+/// The `key: value` properties of the single object argument of the
+/// `@decorator` called with `args`.
+fn get_options<'a>(
+    handler: &Handler,
+    decorator: &str,
+    args: &'a [ExprOrSpread],
+) -> Result<Vec<(String, &'a Expr)>> {
+    let options = match args {
+        [arg] => match &*arg.expr {
+            Expr::Object(options) => options,
+            z => return Err(swc_err(handler, z, "expected an object literal")),
+        },
+        _ => bail!("@{} takes a single object argument", decorator),
+    };
+    object_props(handler, options)
+}
+
+/// The `key: value` properties of an object literal.
+fn object_props<'a>(handler: &Handler, object: &'a ObjectLit) -> Result<Vec<(String, &'a Expr)>> {
+    let mut props = vec![];
+    for prop in &object.props {
+        match prop {
+            PropOrSpread::Prop(prop) => match &**prop {
+                Prop::KeyValue(kv) => {
+                    props.push((get_field_info(handler, &kv.key)?.0, &*kv.value));
+                }
+                z => return Err(swc_err(handler, z, "expected a `key: value` property")),
+            },
+            PropOrSpread::Spread(z) => {
+                return Err(swc_err(handler, z, "expected a `key: value` property"))
+            }
+        }
+    }
+    Ok(props)
+}
+
+fn string_option(handler: &Handler, value: &Expr) -> Result<String> {
+    match value {
+        Expr::Lit(Lit::Str(s)) => Ok(s.value.to_string()),
+        z => Err(swc_err(handler, z, "expected a string")),
+    }
+}
+
+fn number_option(handler: &Handler, value: &Expr) -> Result<u32> {
+    match value {
+        Expr::Lit(Lit::Num(n)) if n.value >= 0.0 && n.value.fract() == 0.0 => Ok(n.value as u32),
+        z => Err(swc_err(handler, z, "expected a whole number")),
+    }
 }
 
 fn validate_type_vec(type_vec: &[AddTypeRequest], valid_entities: &BTreeSet<String>) -> Result<()> {
@@ -221,12 +668,52 @@ fn validate_type_vec(type_vec: &[AddTypeRequest], valid_entities: &BTreeSet<Stri
                     );
                 }
             }
+            if let TypeEnum::UnionType(UnionType { entities }) = field.field_type()? {
+                for name in entities {
+                    if valid_entities.get(name).is_none() {
+                        bail!(
+                            "field '{}' in class '{}' refers to '{name}', which is not an entity of the project",
+                            field.name,
+                            t.name
+                        );
+                    }
+                }
+            }
         }
     }
     Ok(())
 }
 
-fn parse_class_prop(x: &ClassProp, class_name: &str, handler: &Handler) -> Result<FieldDefinition> {
+/// The text of the doc comment, like `/** A blog post. */`, that ends right
+/// before one of `positions`, without the comment markers.
+fn doc_comment(comments: &SingleThreadedComments, positions: &[BytePos]) -> Option<String> {
+    positions.iter().find_map(|pos| {
+        let leading = comments.get_leading(*pos)?;
+        let comment = leading
+            .iter()
+            .rev()
+            .find(|c| matches!(c.kind, CommentKind::Block) && c.text.starts_with('*'))?;
+        let text = comment.text.strip_prefix('*').unwrap_or(&comment.text);
+        let lines: Vec<&str> = text
+            .lines()
+            .map(|line| {
+                let line = line.trim();
+                let line = line.strip_prefix('*').unwrap_or(line);
+                line.strip_prefix(' ').unwrap_or(line)
+            })
+            .collect();
+        Some(lines.join("\n").trim().to_owned()).filter(|text| !text.is_empty())
+    })
+}
+
+/// Parses a field of an entity class, returning its definition and whether
+/// it is indexed.
+fn parse_class_prop(
+    x: &ClassProp,
+    class_name: &str,
+    handler: &Handler,
+    comments: &SingleThreadedComments,
+) -> Result<(FieldDefinition, bool)> {
     macro_rules! swc_err {
         ($span:ident, $msg:literal, $($args:tt)*) => {{
             let formatted_msg = format!($msg, $($args)*);
@@ -240,10 +727,20 @@ fn parse_class_prop(x: &ClassProp, class_name: &str, handler: &Handler) -> Resul
     let (field_type, default_value) = match (&x.type_ann, &x.value) {
         (Some(type_ann), Some(value)) => {
             let field_type = get_field_type(handler, type_ann)?;
-            let default_value = if let Some((default_value, value_type)) =
-                get_field_value(handler, value)?
-            {
-                anyhow::ensure!(field_type == value_type, swc_err!(x,
+            let is_json = matches!(&field_type, TypeEnum::Entity(name) if name == JSON_TYPE);
+            let default_value = if is_json {
+                // Like for other types, a default that isn't constant is
+                // only computed by the runtime.
+                get_json_value(value).map(|json| json.to_string())
+            } else if let Some((default_value, value_type)) = get_field_value(handler, value)? {
+                let is_variant = match &field_type {
+                    TypeEnum::EnumType(EnumType { variants }) => {
+                        matches!(value_type, TypeEnum::String(_))
+                            && variants.contains(&default_value)
+                    }
+                    _ => false,
+                };
+                anyhow::ensure!(field_type == value_type || is_variant, swc_err!(x,
                     "field `{field_name}` is of type {field_type} but is default initialized by a value of type {value_type}",
                 ));
                 Some(default_value)
@@ -273,10 +770,10 @@ fn parse_class_prop(x: &ClassProp, class_name: &str, handler: &Handler) -> Resul
         )),
     };
 
-    let (labels, is_unique) = get_type_decorators(handler, &x.decorators)?;
+    let (labels, is_unique, is_indexed, column) = get_type_decorators(handler, &x.decorators)?;
 
     match &field_type {
-        TypeEnum::Entity(name) if !is_optional => match &x.value {
+        TypeEnum::Entity(name) if !is_optional && !is_value_type(name) => match &x.value {
             None => {
                 eprintln!(
                         "Warning: Entity `{class_name}` contains field `{field_name}` of entity type `{name}` which is not default-initialized.\n\
@@ -295,7 +792,11 @@ fn parse_class_prop(x: &ClassProp, class_name: &str, handler: &Handler) -> Resul
         _ => {}
     };
 
-    Ok(FieldDefinition {
+    let doc_positions: Vec<BytePos> = std::iter::once(x.span.lo)
+        .chain(x.decorators.iter().map(|d| d.span.lo))
+        .chain(std::iter::once(x.key.span().lo))
+        .collect();
+    let field = FieldDefinition {
         name: field_name,
         is_optional,
         is_unique,
@@ -304,15 +805,20 @@ fn parse_class_prop(x: &ClassProp, class_name: &str, handler: &Handler) -> Resul
             type_enum: field_type.into(),
         }),
         labels,
-    })
+        description: doc_comment(comments, &doc_positions),
+        column,
+    };
+    Ok((field, is_indexed))
 }
 
 fn parse_class_decl<P: AsRef<Path>>(
     handler: &Handler,
+    comments: &SingleThreadedComments,
     filename: &P,
     type_vec: &mut Vec<AddTypeRequest>,
     valid_types: &mut BTreeSet<String>,
     decl: &Decl,
+    export_pos: BytePos,
 ) -> Result<()> {
     match decl {
         Decl::Class(x) => {
@@ -321,18 +827,48 @@ fn parse_class_decl<P: AsRef<Path>>(
             if !valid_types.insert(name.clone()) {
                 bail!("Model {} defined twice", name);
             }
+            let ClassDecorators {
+                cache,
+                mut indexes,
+                partition,
+                archive,
+                connector,
+                crud_path,
+                crud_pagination,
+                table,
+            } = get_class_decorators(handler, &x.class.decorators)
+                .with_context(|| format!("While parsing class {}", name))?;
 
+            let mut hooks = vec![];
             for member in &x.class.body {
                 match member {
-                    ClassMember::ClassProp(x) => match parse_class_prop(x, &name, handler) {
-                        Err(err) => {
-                            handler.span_err(x.span(), &format!("While parsing class {}", name));
-                            bail!("{}", err);
+                    ClassMember::Method(method) if method.is_static => {
+                        if let PropName::Ident(id) = &method.key {
+                            let method_name = ident_to_string(id);
+                            if ENTITY_HOOKS.contains(&method_name.as_str()) {
+                                hooks.push(method_name);
+                            }
                         }
-                        Ok(fd) => {
-                            field_defs.push(fd);
+                    }
+                    ClassMember::ClassProp(x) => {
+                        match parse_class_prop(x, &name, handler, comments) {
+                            Err(err) => {
+                                handler
+                                    .span_err(x.span(), &format!("While parsing class {}", name));
+                                bail!("{}", err);
+                            }
+                            Ok((fd, is_indexed)) => {
+                                if is_indexed {
+                                    indexes.push(IndexDefinition {
+                                        fields: vec![fd.name.clone()],
+                                        search: false,
+                                        counted: false,
+                                    });
+                                }
+                                field_defs.push(fd);
+                            }
                         }
-                    },
+                    }
                     ClassMember::Constructor(_x) => {
                         handler.span_err(member.span(), "Constructors not allowed in ChiselStrike model definitions. Consider adding default values so one is not needed, or call ChiselEntity's create method");
                         bail!("invalid type file {}", filename.as_ref().display());
@@ -340,7 +876,76 @@ fn parse_class_decl<P: AsRef<Path>>(
                     _ => {}
                 }
             }
-            type_vec.push(AddTypeRequest { name, field_defs });
+            for index in &indexes {
+                for field in &index.fields {
+                    ensure!(
+                        field_defs.iter().any(|fd| &fd.name == field),
+                        "index of class {} is over unknown field `{}`",
+                        name,
+                        field
+                    );
+                }
+            }
+            if let Some(partition) = &partition {
+                ensure!(
+                    partition.field == "id"
+                        || field_defs.iter().any(|fd| fd.name == partition.field),
+                    "class {} is partitioned by unknown field `{}`",
+                    name,
+                    partition.field
+                );
+            }
+            if let Some(archive) = &archive {
+                ensure!(
+                    field_defs.iter().any(|fd| fd.name == archive.field),
+                    "class {} is archived by unknown field `{}`",
+                    name,
+                    archive.field
+                );
+            }
+            if let Some(connector) = &connector {
+                for field in connector.fields.keys() {
+                    ensure!(
+                        field_defs.iter().any(|fd| &fd.name == field),
+                        "class {} maps unknown field `{}` in its connector",
+                        name,
+                        field
+                    );
+                }
+            }
+            // The routes of @autoCrud can't import the class, so they
+            // wouldn't run its hooks.
+            if let (Some(_), Some(hook)) = (&crud_path, hooks.first()) {
+                bail!(
+                    "@autoCrud can't be used on class {}, which defines hook {}: export {}.crud() from a route instead",
+                    name,
+                    hook,
+                    name
+                );
+            }
+            // The route is named after the class, unless given a path.
+            let crud_path = match crud_path {
+                Some(path) if path.is_empty() => name.to_lowercase(),
+                path => path.unwrap_or_default(),
+            };
+            // Decorators may come before or after `export`.
+            let doc_positions: Vec<BytePos> = [export_pos, x.class.span.lo]
+                .into_iter()
+                .chain(x.class.decorators.iter().map(|d| d.span.lo))
+                .collect();
+            type_vec.push(AddTypeRequest {
+                description: doc_comment(comments, &doc_positions),
+                name,
+                field_defs,
+                cache,
+                indexes,
+                partition,
+                archive,
+                connector,
+                crud_path,
+                crud_pagination,
+                table,
+            });
         }
         z => {
             handler.span_err(z.span(), "Only class definitions allowed in the types file");
@@ -350,11 +955,7 @@ fn parse_class_decl<P: AsRef<Path>>(
     Ok(())
 }
 
-fn parse_one_file<P: AsRef<Path>>(
-    filename: &P,
-    type_vec: &mut Vec<AddTypeRequest>,
-    valid_types: &mut BTreeSet<String>,
-) -> Result<()> {
+fn parse_module<P: AsRef<Path>>(filename: &P) -> Result<(Handler, Module, SingleThreadedComments)> {
     let cm: Lrc<SourceMap> = Default::default();
 
     let emitter = Box::new(emitter::EmitterWriter::new(
@@ -374,12 +975,14 @@ fn parse_one_file<P: AsRef<Path>>(
     };
     config.decorators = true;
 
+    // Kept for the doc comments of models.
+    let comments = SingleThreadedComments::default();
     let lexer = Lexer::new(
         // We want to parse typescript with decorators support
         Syntax::Typescript(config),
         Default::default(),
         StringInput::from(&*fm),
-        None,
+        Some(&comments),
     );
 
     let mut parser = Parser::new_from(lexer);
@@ -396,11 +999,28 @@ fn parse_one_file<P: AsRef<Path>>(
         e.into_diagnostic(&handler).emit();
         anyhow!("Exiting on script parsing errors")
     })?;
+    Ok((handler, x, comments))
+}
+
+fn parse_one_file<P: AsRef<Path>>(
+    filename: &P,
+    type_vec: &mut Vec<AddTypeRequest>,
+    valid_types: &mut BTreeSet<String>,
+) -> Result<()> {
+    let (handler, x, comments) = parse_module(filename)?;
 
     for decl in &x.body {
         match decl {
             ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(exp)) => {
-                parse_class_decl(&handler, filename, type_vec, valid_types, &exp.decl)?;
+                parse_class_decl(
+                    &handler,
+                    &comments,
+                    filename,
+                    type_vec,
+                    valid_types,
+                    &exp.decl,
+                    exp.span.lo,
+                )?;
             }
             ModuleItem::ModuleDecl(ModuleDecl::Import(_)) => {
                 // Right now just accept imports, but don't try to parse them.
@@ -431,3 +1051,141 @@ pub(crate) fn parse_types<P: AsRef<Path>>(files: &[P]) -> Result<Vec<AddTypeRequ
     validate_type_vec(&type_vec, &valid_types)?;
     Ok(type_vec)
 }
+
+/// The type an endpoint module exports to declare the body of its requests.
+const REQUEST_BODY: &str = "RequestBody";
+/// The type an endpoint module exports to declare the body of its responses.
+const RESPONSE_BODY: &str = "ResponseBody";
+
+/// Parses the properties of an object type of a body.
+fn parse_body_members(handler: &Handler, members: &[TsTypeElement]) -> Result<BodySchema> {
+    let mut fields = vec![];
+    for member in members {
+        let prop = match member {
+            TsTypeElement::TsPropertySignature(prop) => prop,
+            z => bail!(swc_err(
+                handler,
+                z,
+                "only properties are supported in a body type"
+            )),
+        };
+        let name = get_ident_string(handler, &prop.key)?;
+        let field_type = match &prop.type_ann {
+            Some(type_ann) => get_field_type(handler, type_ann)?,
+            None => bail!(swc_err(
+                handler,
+                prop,
+                &format!("property `{name}` needs a type annotation")
+            )),
+        };
+        fields.push(FieldDefinition {
+            name,
+            field_type: Some(TypeMsg {
+                type_enum: Some(field_type),
+            }),
+            is_optional: prop.optional,
+            ..Default::default()
+        });
+    }
+    Ok(BodySchema {
+        fields,
+        body_type: None,
+    })
+}
+
+/// Parses `decl` if it declares the request or response body of an
+/// endpoint, as an interface or a type alias, and returns its name and body.
+fn parse_body_decl(handler: &Handler, decl: &Decl) -> Result<Option<(String, BodySchema)>> {
+    let name = match decl {
+        Decl::TsInterface(x) => ident_to_string(&x.id),
+        Decl::TsTypeAlias(x) => ident_to_string(&x.id),
+        _ => return Ok(None),
+    };
+    if name != REQUEST_BODY && name != RESPONSE_BODY {
+        return Ok(None);
+    }
+    let body = match decl {
+        Decl::TsInterface(x) => parse_body_members(handler, &x.body.body)?,
+        Decl::TsTypeAlias(x) => match &*x.type_ann {
+            TsType::TsTypeLit(lit) => parse_body_members(handler, &lit.members)?,
+            ty => BodySchema {
+                fields: vec![],
+                body_type: Some(TypeMsg {
+                    type_enum: Some(map_type(handler, ty)?),
+                }),
+            },
+        },
+        _ => unreachable!(),
+    };
+    Ok(Some((name, body)))
+}
+
+/// The entity that `ty`, or its elements if it is an array, is of.
+fn entity_of(ty: &TypeEnum) -> Result<Option<&str>> {
+    match ty {
+        TypeEnum::Entity(name) => Ok(Some(name)),
+        TypeEnum::Array(inner) => entity_of(inner.value_type()?),
+        _ => Ok(None),
+    }
+}
+
+/// Parses the `RequestBody` and `ResponseBody` types that the endpoint
+/// modules in `files` export, so that the server can describe what their
+/// endpoints take and return. Bodies are typed like the fields of entities,
+/// and may refer to the `entities`.
+pub(crate) fn parse_endpoint_schemas<P: AsRef<Path>>(
+    files: &[P],
+    entities: &[String],
+) -> Result<Vec<EndpointSchema>> {
+    let mut schemas = vec![];
+    for filename in files {
+        let path = filename.as_ref();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("ts") {
+            continue;
+        }
+        let (handler, module, _) = parse_module(filename)?;
+        let mut schema = EndpointSchema {
+            path: path.display().to_string(),
+            ..Default::default()
+        };
+        for item in &module.body {
+            let decl = match item {
+                ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(exp)) => &exp.decl,
+                _ => continue,
+            };
+            let (name, body) = match parse_body_decl(&handler, decl)? {
+                Some(body) => body,
+                None => continue,
+            };
+            let mut types = vec![];
+            for field in &body.fields {
+                types.push(field.field_type()?);
+            }
+            if let Some(ty) = &body.body_type {
+                types.extend(ty.type_enum.as_ref());
+            }
+            for ty in types {
+                if let Some(entity) = entity_of(ty)? {
+                    ensure!(
+                        entities.iter().any(|e| e == entity)
+                            || is_auth_entity_name(entity)
+                            || is_value_type(entity),
+                        "{} of {} refers to unknown entity type '{}'",
+                        name,
+                        path.display(),
+                        entity
+                    );
+                }
+            }
+            if name == REQUEST_BODY {
+                schema.request = Some(body);
+            } else {
+                schema.response = Some(body);
+            }
+        }
+        if schema.request.is_some() || schema.response.is_some() {
+            schemas.push(schema);
+        }
+    }
+    Ok(schemas)
+}
@@ -330,6 +330,16 @@ impl TestableOutput {
         self
     }
 
+    /// Returns the whole output.
+    pub fn as_str(&self) -> &str {
+        &self.output
+    }
+
+    /// Parses the whole output as JSON.
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.output).expect("output is not valid JSON")
+    }
+
     #[allow(dead_code)]
     pub fn show(&self) {
         println!("{}", self.output);
@@ -608,6 +618,10 @@ impl RequestBuilder {
         self.map(|b| b.header(name, value))
     }
 
+    pub fn body<B: Into<reqwest::Body>>(self, body: B) -> Self {
+        self.map(|b| b.body(body))
+    }
+
     pub async fn send(self) -> Response {
         let request = self.builder.build().unwrap();
         let (method, url) = (request.method().clone(), request.url().clone());
@@ -675,11 +689,9 @@ impl Response {
         self
     }
 
-    /*
     pub fn body(&self) -> Bytes {
         self.body.clone()
     }
-    */
 
     pub fn text(&self) -> String {
         match str::from_utf8(&self.body) {
@@ -0,0 +1,86 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static MODELS: &str = r##"
+    import { ChiselEntity } from '@chiselstrike/api';
+    export class Product extends ChiselEntity {
+        name: string;
+        category: string;
+        price: number;
+        discount?: number;
+    }
+"##;
+
+static ROUTE_STATS: &str = r##"
+    import { Product } from '../models/models.ts';
+    export default async function (req: Request) {
+        switch (new URL(req.url).searchParams.get('q')) {
+            case 'total':
+                return await Product.aggregate({ count: true, sum: 'price', avg: 'price' });
+            case 'grouped':
+                return await Product.aggregate({ count: true, avg: 'price', groupBy: 'category' });
+            case 'filtered':
+                return await Product.cursor().filter({ category: 'books' }).aggregate({ count: true });
+            case 'discount':
+                return await Product.aggregate({ avg: 'discount' });
+            case 'name':
+                return await Product.aggregate({ sum: 'name' });
+        }
+        throw new Error('unknown statistic');
+    }
+"##;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn aggregate_entities(c: TestContext) {
+    c.chisel.write_unindent("models/models.ts", MODELS);
+    c.chisel.write_unindent("routes/stats.ts", ROUTE_STATS);
+    c.chisel.write_unindent(
+        "routes/products.ts",
+        r##"
+        import { Product } from '../models/models.ts';
+        export default Product.crud();
+    "##,
+    );
+    c.chisel.apply_ok().await;
+
+    assert_eq!(
+        c.chisel.get_json("/dev/stats?q=total").await,
+        json!({"count": 0, "sum": 0, "avg": null})
+    );
+
+    for (name, category, price) in [("a", "books", 10), ("b", "books", 20), ("c", "games", 6)] {
+        c.chisel
+            .post_json(
+                "/dev/products",
+                json!({"name": name, "category": category, "price": price}),
+            )
+            .await;
+    }
+
+    assert_eq!(
+        c.chisel.get_json("/dev/stats?q=total").await,
+        json!({"count": 3, "sum": 36, "avg": 12})
+    );
+    assert_eq!(
+        c.chisel.get_json("/dev/stats?q=grouped").await,
+        json!([
+            {"category": "books", "count": 2, "avg": 15},
+            {"category": "games", "count": 1, "avg": 6},
+        ])
+    );
+    assert_eq!(
+        c.chisel.get_json("/dev/stats?q=filtered").await,
+        json!({"count": 2})
+    );
+    assert_eq!(
+        c.chisel.get_json("/dev/stats?q=discount").await,
+        json!({ "avg": null })
+    );
+    c.chisel
+        .get("/dev/stats?q=name")
+        .send()
+        .await
+        .assert_status(500)
+        .assert_text_contains("field 'name' of entity 'Product' is not a number");
+}
@@ -0,0 +1,79 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn deleted_counts(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/model.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Person extends ChiselEntity {
+            name: string = "";
+            age: number = 0;
+        }
+
+        export class Company extends ChiselEntity {
+            name: string = "";
+            ceo: Person = new Person();
+        }
+        "#,
+    );
+    c.chisel.write_unindent(
+        "routes/setup.ts",
+        r#"
+        import { Company, Person } from "../models/model.ts";
+
+        export default async function chisel() {
+            await Person.createMany([
+                { name: "Al", age: 20 },
+                { name: "Bo", age: 20 },
+                { name: "Cy", age: 30 },
+            ]);
+            const ceo = await Person.create({ name: "Di", age: 40 });
+            await Company.create({ name: "Acme", ceo });
+            return "ok";
+        }
+        "#,
+    );
+    c.chisel.write_unindent(
+        "routes/delete.ts",
+        r#"
+        import { Company, Person } from "../models/model.ts";
+
+        export default async function chisel(req: Request) {
+            const params = new URL(req.url).searchParams;
+            if (params.has("ceo")) {
+                return await Company.delete({
+                    ceo: { name: params.get("ceo")! },
+                } as Partial<Company>);
+            }
+            return await Person.delete({ age: Number(params.get("age")) });
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel.post("/dev/setup").send().await.assert_ok();
+    c.chisel
+        .post("/dev/delete?age=20")
+        .send()
+        .await
+        .assert_json(json!(2));
+    c.chisel
+        .post("/dev/delete?age=20")
+        .send()
+        .await
+        .assert_json(json!(0));
+    c.chisel
+        .post("/dev/delete?ceo=Di")
+        .send()
+        .await
+        .assert_json(json!(1));
+    c.chisel
+        .post("/dev/delete?age=30")
+        .send()
+        .await
+        .assert_json(json!(1));
+}
@@ -272,3 +272,98 @@ pub async fn sql_keywords(c: TestContext) {
         json_is_subset(&c.chisel.get_json(&url).await, &expected_json).unwrap();
     }
 }
+
+#[chisel_macros::test(modules = Deno, optimize = Both)]
+pub async fn typed_filter(c: TestContext) {
+    c.chisel.write("models/person.ts", PERSON_MODEL);
+    c.chisel.write("routes/people.ts", PEOPLE_CRUD);
+    c.chisel.write(
+        "routes/query.ts",
+        r#"
+        import { ChiselRequest } from '@chiselstrike/api';
+        import { Person } from "../models/person.ts";
+
+        export default async function chisel(req: ChiselRequest) {
+            const age = req.query.getNumber("age") ?? 0;
+            const prefix = req.query.get("prefix") ?? "%";
+            return await Person.filter(p => p.age.gt(age).and(p.first_name.like(prefix)))
+                .sortBy("first_name")
+                .map(p => p.first_name)
+                .toArray();
+        }"#,
+    );
+    c.chisel.apply_ok().await;
+    store_people(&c.chisel).await;
+
+    assert_eq!(
+        c.chisel.get_json("/dev/query?age=0").await,
+        json!(["Glauber", "Pekka"])
+    );
+    assert_eq!(
+        c.chisel.get_json("/dev/query?age=700").await,
+        json!(["Pekka"])
+    );
+    assert_eq!(
+        c.chisel.get_json("/dev/query?age=-1000&prefix=J%25").await,
+        json!(["Jan"])
+    );
+}
+
+#[chisel_macros::test(modules = Deno, optimize = Both)]
+pub async fn typed_filter_method_names(c: TestContext) {
+    c.chisel.write(
+        "models/word.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Word extends ChiselEntity {
+            like: string = "";
+            eq: number = 0;
+        }"#,
+    );
+    c.chisel.write(
+        "routes/words.ts",
+        r#"
+        import { Word } from "../models/word.ts";
+        export default Word.crud();
+        "#,
+    );
+    c.chisel.write(
+        "routes/query.ts",
+        r#"
+        import { Word } from "../models/word.ts";
+
+        export default async function chisel() {
+            return await Word.filter(w => w.like.like("a%").and(w.eq.eq(1)).or(w.eq.eq(0)))
+                .sortBy("like")
+                .map(w => w.like)
+                .toArray();
+        }"#,
+    );
+    c.chisel.apply_ok().await;
+    for (like, eq) in [("ab", 1), ("ac", 2), ("b", 1), ("c", 0)] {
+        c.chisel
+            .post_json("dev/words", json!({"like": like, "eq": eq}))
+            .await;
+    }
+    assert_eq!(c.chisel.get_json("/dev/query").await, json!(["ab", "c"]));
+}
+
+#[chisel_macros::test(modules = Deno, optimize = Both)]
+pub async fn typed_filter_type_error(c: TestContext) {
+    c.chisel.write("models/person.ts", PERSON_MODEL);
+    c.chisel.write(
+        "routes/query.ts",
+        r#"
+        import { Person } from "../models/person.ts";
+
+        export default async function chisel() {
+            return await Person.filter(p => p.age.gt("old")).toArray();
+        }"#,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("Argument of type 'string' is not assignable to parameter of type 'number'");
+}
@@ -120,6 +120,56 @@ pub async fn find_many(c: TestContext) {
     }
 }
 
+#[chisel_macros::test(modules = Deno, optimize = Both)]
+pub async fn find_many_paginated(c: TestContext) {
+    c.chisel.copy_to_dir("examples/person.ts", "models");
+    c.chisel.copy_to_dir("examples/store.ts", "routes");
+    c.chisel.write(
+        "routes/pages.ts",
+        r##"
+        import { ChiselRequest } from "@chiselstrike/api"
+        import { Person } from "../models/person.ts";
+
+        export default async function chisel(req: ChiselRequest) {
+            const restrictions = req.query.getBool("humans") ? { human: true } : {};
+            const limit = req.query.getNumber("limit")!;
+            let cursor = req.query.get("cursor");
+            const pages = [];
+            do {
+                const page = await Person.findMany(restrictions, { cursor, limit });
+                pages.push(page.results.map(p => p.first_name));
+                cursor = page.nextCursor;
+            } while (cursor !== undefined);
+            return pages;
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+    store_people(&c.chisel).await;
+
+    let pages = c.chisel.get_json("/dev/pages?limit=2").await;
+    let pages = pages.as_array().unwrap();
+    assert_eq!(pages.len(), 2);
+    assert_eq!(pages[0].as_array().unwrap().len(), 2);
+    assert_eq!(pages[1].as_array().unwrap().len(), 1);
+
+    let pages = c.chisel.get_json("/dev/pages?limit=1&humans=true").await;
+    let mut names: Vec<&str> = pages
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|page| page.as_array().unwrap()[0].as_str().unwrap())
+        .collect();
+    names.sort_unstable();
+    assert_eq!(names, ["Glauber", "Jan"]);
+
+    c.chisel
+        .get("/dev/pages?limit=1&cursor=bogus")
+        .send()
+        .await
+        .assert_status(500);
+}
+
 #[chisel_macros::test(modules = Deno, optimize = Both)]
 pub async fn find_many_invalid_argument(c: TestContext) {
     c.chisel.copy_to_dir("examples/person.ts", "models");
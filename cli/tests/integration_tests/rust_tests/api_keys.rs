@@ -0,0 +1,89 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn managed_keys(mut c: TestContext) {
+    c.chisel.write_unindent(
+        "routes/whoami.ts",
+        r##"
+        import { requestContext } from "@chiselstrike/api";
+        export default function () {
+            return { userId: requestContext.userId ?? null, apiKey: requestContext.apiKey ?? null };
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/public.ts",
+        r##"
+        export default function () {
+            return "public";
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "policies/p.yaml",
+        r##"
+        routes:
+          - path: /whoami
+            api_key: true
+        "##,
+    );
+    c.chisel
+        .write(".env", r##"{ "CHISELD_AUTH_PROVIDERS": ["api-key"] }"##);
+    c.restart_chiseld().await;
+    c.chisel.apply_ok().await;
+
+    c.chisel.get("/dev/whoami").send().await.assert_status(403);
+    c.chisel.get("/dev/public").send().await.assert_ok();
+
+    let output = c
+        .chisel
+        .exec("apikey", &["create", "bot", "--user", "u1"])
+        .await
+        .expect("chisel apikey create failed");
+    let key = output.stdout.as_str().trim().to_owned();
+    c.chisel
+        .get("/dev/whoami")
+        .header("X-API-Key", &key)
+        .send()
+        .await
+        .assert_json(json!({"userId": "u1", "apiKey": "bot"}));
+    c.chisel
+        .get("/dev/whoami")
+        .header("X-API-Key", &format!("{}x", key))
+        .send()
+        .await
+        .assert_status(401);
+
+    c.chisel
+        .exec("apikey", &["create", "bot"])
+        .await
+        .expect_err("chisel apikey create of an existing key succeeded")
+        .stderr
+        .peek("there is an API key named bot already");
+    c.chisel
+        .exec("apikey", &["list"])
+        .await
+        .expect("chisel apikey list failed")
+        .stdout
+        .read("bot")
+        .read("u1");
+
+    c.chisel
+        .exec("apikey", &["revoke", "bot"])
+        .await
+        .expect("chisel apikey revoke failed")
+        .stdout
+        .read("API key bot revoked");
+    c.chisel
+        .get("/dev/whoami")
+        .header("X-API-Key", &key)
+        .send()
+        .await
+        .assert_status(401);
+    c.chisel
+        .exec("apikey", &["revoke", "bot"])
+        .await
+        .expect_err("chisel apikey revoke of a revoked key succeeded");
+}
@@ -59,3 +59,227 @@ pub async fn cant_save_in_get(c: TestContext) {
         .assert_text_contains("Error: Mutating the backend is not allowed during GET");
     c.chisel.post("/dev/store/").send().await.assert_ok();
 }
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn create_many(c: TestContext) {
+    c.chisel.write(
+        "models/model.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Company extends ChiselEntity {
+            name: string = "";
+        }
+
+        export class Person extends ChiselEntity {
+            name: string = "";
+            age?: number;
+            employer: Company = new Company();
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/people.ts",
+        r#"
+        import { Company, Person } from "../models/model.ts";
+
+        export default async function chisel(req: Request) {
+            if (req.method === "POST") {
+                const acme = await Company.create({ name: "Acme" });
+                // Enough rows to split the INSERT in several batches.
+                const values = [];
+                for (let i = 0; i < 1000; i++) {
+                    const age = i % 2 == 0 ? i : undefined;
+                    values.push({ name: `p${i}`, age, employer: acme });
+                }
+                const people = await Person.createMany(values);
+                return people.filter((p) => p.id !== undefined).length;
+            }
+            const people = await Person.findAll();
+            const ages = people.filter((p) => p.age !== undefined).length;
+            const ids = new Set(people.map((p) => p.id)).size;
+            const companies = (await Company.findAll()).length;
+            return { people: people.length, ages, ids, companies };
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post("/dev/people")
+        .send()
+        .await
+        .assert_json(json!(1000));
+    c.chisel.get("/dev/people").send().await.assert_json(json!({
+        "people": 1000,
+        "ages": 500,
+        "ids": 1000,
+        "companies": 1,
+    }));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn update(c: TestContext) {
+    c.chisel.write(
+        "models/model.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Company extends ChiselEntity {
+            name: string = "";
+        }
+
+        export class Person extends ChiselEntity {
+            name: string = "";
+            age?: number;
+            employer: Company = new Company();
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/people.ts",
+        r#"
+        import { Company, Person } from "../models/model.ts";
+
+        export default async function chisel(req: Request) {
+            if (req.method === "POST") {
+                const acme = await Company.create({ name: "Acme" });
+                const person = await Person.create({
+                    name: "Al",
+                    age: 30,
+                    employer: acme,
+                });
+                return person.id;
+            }
+            if (req.method === "PATCH") {
+                const id = new URL(req.url).searchParams.get("id")!;
+                const globex = await Company.create({ name: "Globex" });
+                return await Person.update(id, { employer: globex });
+            }
+            const people = await Person.findAll();
+            return people.map((p) => ({
+                name: p.name,
+                age: p.age,
+                employer: p.employer.name,
+            }));
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/update.ts",
+        r#"
+        import { Person } from "../models/model.ts";
+
+        export default async function chisel(req: Request) {
+            const id = new URL(req.url).searchParams.get("id")!;
+            const changes = JSON.parse(await req.text());
+            return await Person.update(id, changes);
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    let id = c.chisel.post("/dev/people").send().await.assert_ok().json();
+    let id = id.as_str().unwrap();
+    let update = |changes: serde_json::Value| {
+        c.chisel
+            .post(&format!("/dev/update?id={}", id))
+            .json(changes)
+            .send()
+    };
+    update(json!({"age": 31})).await.assert_json(json!(true));
+    update(json!({"height": 2})).await.assert_status(500);
+    update(json!({"id": "3ee8e6d4-58bd-4bf6-9e0f-0e9c9d2f5ab3"}))
+        .await
+        .assert_status(500);
+    update(json!({ "name": null })).await.assert_status(500);
+    update(json!({"age": "old"})).await.assert_status(500);
+    c.chisel
+        .get("/dev/people")
+        .send()
+        .await
+        .assert_json(json!([{"name": "Al", "age": 31, "employer": "Acme"}]));
+
+    c.chisel
+        .patch(&format!("/dev/people?id={}", id))
+        .send()
+        .await
+        .assert_json(json!(true));
+    c.chisel
+        .get("/dev/people")
+        .send()
+        .await
+        .assert_json(json!([{"name": "Al", "age": 31, "employer": "Globex"}]));
+    update(json!({ "age": null }))
+        .await
+        .assert_json(json!(true));
+    c.chisel
+        .get("/dev/people")
+        .send()
+        .await
+        .assert_json(json!([{"name": "Al", "employer": "Globex"}]));
+
+    c.chisel
+        .post("/dev/update?id=3ee8e6d4-58bd-4bf6-9e0f-0e9c9d2f5ab3")
+        .json(json!({"age": 1}))
+        .send()
+        .await
+        .assert_json(json!(false));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn upsert_unique(c: TestContext) {
+    c.chisel.write(
+        "models/model.ts",
+        r#"
+        import { ChiselEntity, unique } from "@chiselstrike/api";
+
+        export class User extends ChiselEntity {
+            @unique email: string = "";
+            name: string = "";
+            visits: number = 0;
+        }
+        "#,
+    );
+    c.chisel.write(
+        "routes/users.ts",
+        r#"
+        import { User } from "../models/model.ts";
+
+        export default async function chisel(req: Request) {
+            if (req.method === "POST") {
+                const { email, name } = JSON.parse(await req.text());
+                const user = await User.upsert({
+                    restrictions: { email },
+                    create: { email, name, visits: 1 },
+                    update: { name },
+                });
+                return { name: user.name, visits: user.visits };
+            }
+            const users = await User.findAll();
+            return users.map((u) => ({ email: u.email, name: u.name }));
+        }
+        "#,
+    );
+    c.chisel.apply_ok().await;
+
+    let upsert = |email: &str, name: &str| {
+        c.chisel
+            .post("/dev/users")
+            .json(json!({"email": email, "name": name}))
+            .send()
+    };
+    upsert("al@example.com", "Al")
+        .await
+        .assert_json(json!({"name": "Al", "visits": 1}));
+    upsert("al@example.com", "Alan")
+        .await
+        .assert_json(json!({"name": "Alan", "visits": 1}));
+    upsert("bo@example.com", "Bo")
+        .await
+        .assert_json(json!({"name": "Bo", "visits": 1}));
+    c.chisel.get("/dev/users").send().await.assert_json(json!([
+        {"email": "al@example.com", "name": "Alan"},
+        {"email": "bo@example.com", "name": "Bo"},
+    ]));
+}
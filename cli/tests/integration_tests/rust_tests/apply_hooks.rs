@@ -0,0 +1,75 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_manifest(c: &TestContext, hooks: &str) {
+    let manifest = format!(
+        r#"
+        models = ["models"]
+        routes = ["routes"]
+        events = ["events"]
+        policies = ["policies"]
+        modules = "deno"
+
+        [hooks]
+        {}
+        "#,
+        hooks
+    );
+    c.chisel.write_unindent("Chisel.toml", &manifest);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn apply_hooks(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from '@chiselstrike/api';
+        export class Book extends ChiselEntity {
+            title: string;
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/books.ts",
+        r##"
+        import { Book } from '../models/models.ts';
+        export default Book.crud();
+    "##,
+    );
+
+    // A failing pre-apply hook stops the apply.
+    write_manifest(&c, r#"pre_apply = ["echo checking", "exit 3"]"#);
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("pre-apply hook `exit 3` failed");
+    c.chisel.get("/dev/books").send().await.assert_status(404);
+
+    write_manifest(
+        &c,
+        r#"
+        pre_apply = ["printf '%s' \"$CHISEL_APPLY\" > pre.json"]
+        post_apply = ["printf '%s' \"$CHISEL_APPLY\" > post.json"]
+        "#,
+    );
+    c.chisel
+        .apply_ok()
+        .await
+        .stdout
+        .peek("Running post-apply hook");
+    c.chisel.get("/dev/books").send().await.assert_ok();
+
+    let read_json = |name: &str| -> serde_json::Value {
+        let path = c.chisel.tmp_dir.path().join(name);
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    };
+    let pre = read_json("pre.json");
+    assert_eq!(pre["version"], json!("dev"));
+    assert_eq!(pre["plan"][0], json!("+ model Book"));
+    assert!(pre.get("applied").is_none());
+    let post = read_json("post.json");
+    assert_eq!(post["applied"]["models"], json!(["Book"]));
+    assert_eq!(post["applied"]["routes"], json!(["/dev/books"]));
+}
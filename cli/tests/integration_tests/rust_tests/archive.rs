@@ -0,0 +1,127 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+use std::time::Duration;
+
+fn models(decorator: &str) -> String {
+    format!(
+        r##"
+        import {{ ChiselEntity, archive }} from '@chiselstrike/api';
+        {}
+        export class Order extends ChiselEntity {{
+            item: string;
+            placedAt: Date;
+        }}
+    "##,
+        decorator
+    )
+}
+
+static ROUTE_ORDERS: &str = r##"
+    import { Order } from '../models/models.ts';
+    export default async function (req: Request) {
+        const url = new URL(req.url);
+        if (req.method == 'POST') {
+            const { item, placedAt } = await req.json();
+            await Order.create({ item, placedAt: new Date(placedAt) });
+            return 'ok';
+        }
+        if (req.method == 'DELETE') {
+            await Order.delete({ item: url.searchParams.get('item')! });
+            return 'ok';
+        }
+        let cursor = Order.cursor();
+        if (url.searchParams.has('archived')) {
+            cursor = cursor.withArchived();
+        }
+        const items = (await cursor.toArray()).map((o) => o.item);
+        return items.sort();
+    }
+"##;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn archived_entities(mut c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        &models(r#"@archive({ field: "placedAt", afterDays: 30 })"#),
+    );
+    c.chisel.write_unindent("routes/orders.ts", ROUTE_ORDERS);
+    c.chisel
+        .apply_ok()
+        .await
+        .stdout
+        .peek("archive rows 30 days after their placedAt");
+    c.chisel
+        .describe_ok()
+        .await
+        .stdout
+        .peek(r#"@archive({ field: "placedAt", afterDays: 30 })"#);
+
+    for (item, placed_at) in [
+        ("old", "2000-01-01T00:00:00.000Z"),
+        ("older", "1999-01-01T00:00:00.000Z"),
+        ("new", "2100-01-01T00:00:00.000Z"),
+    ] {
+        c.chisel
+            .post_json("/dev/orders", json!({"item": item, "placedAt": placed_at}))
+            .await;
+    }
+
+    // Rows are archived when chiseld starts.
+    c.restart_chiseld().await;
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    assert_eq!(c.chisel.get_json("/dev/orders").await, json!(["new"]));
+    assert_eq!(
+        c.chisel.get_json("/dev/orders?archived").await,
+        json!(["new", "old", "older"])
+    );
+
+    // Deletes apply to the archived rows too.
+    c.chisel
+        .delete("/dev/orders?item=old")
+        .send()
+        .await
+        .assert_ok();
+    assert_eq!(
+        c.chisel.get_json("/dev/orders?archived").await,
+        json!(["new", "older"])
+    );
+
+    // Archived rows are restored when archiving stops.
+    c.chisel.write_unindent("models/models.ts", &models(""));
+    c.chisel
+        .apply_ok()
+        .await
+        .stdout
+        .peek("stop archiving, restoring archived rows");
+    assert_eq!(
+        c.chisel.get_json("/dev/orders").await,
+        json!(["new", "older"])
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_archives(c: TestContext) {
+    for (decorator, error) in [
+        (
+            r#"@archive({ field: "missing", afterDays: 30 })"#,
+            "class Order is archived by unknown field `missing`",
+        ),
+        (
+            r#"@archive({ field: "item", afterDays: 30 })"#,
+            "entity 'Order' is archived by field 'item', which isn't a Date",
+        ),
+        (
+            r#"@archive({ field: "placedAt" })"#,
+            "@archive requires afterDays",
+        ),
+        (
+            r#"@archive({ field: "placedAt", afterDays: 30, after: 1 })"#,
+            "unknown @archive option 'after'",
+        ),
+    ] {
+        c.chisel
+            .write_unindent("models/models.ts", &models(decorator));
+        c.chisel.apply_err().await.stderr.peek(error);
+    }
+}
@@ -0,0 +1,141 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn auto_crud_routes(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, autoCrud } from '@chiselstrike/api';
+        @autoCrud()
+        export class Book extends ChiselEntity {
+            title: string;
+            pages: number = 100;
+        }
+        @autoCrud("library/authors")
+        export class Author extends ChiselEntity {
+            name: string;
+        }
+    "##,
+    );
+    c.chisel
+        .apply_ok()
+        .await
+        .stdout
+        .peek("+ route /dev/book")
+        .peek("+ route /dev/library/authors");
+
+    let dune = c
+        .chisel
+        .post("/dev/book")
+        .json(json!({"title": "Dune", "pages": 412}))
+        .send()
+        .await
+        .assert_ok()
+        .json();
+    let id = dune["id"].as_str().unwrap();
+    c.chisel
+        .post_json("/dev/book", json!({"title": "Emma"}))
+        .await;
+    assert_eq!(
+        c.chisel.get_json(&format!("/dev/book/{}", id)).await,
+        json!({"id": id, "title": "Dune", "pages": 412})
+    );
+    c.chisel
+        .put(&format!("/dev/book/{}", id))
+        .json(json!({"title": "Dune", "pages": 500}))
+        .send()
+        .await
+        .assert_ok();
+
+    let page = c.chisel.get_json("/dev/book?sort=title&limit=1").await;
+    assert_eq!(
+        page["results"],
+        json!([{"id": id, "title": "Dune", "pages": 500}])
+    );
+    assert!(page["next_page"].is_string());
+    let page = c.chisel.get_json("/dev/book?.pages=100").await;
+    assert_eq!(page["results"][0]["title"], json!("Emma"));
+
+    c.chisel
+        .delete("/dev/book?.title=Emma")
+        .send()
+        .await
+        .assert_ok();
+    c.chisel
+        .delete(&format!("/dev/book/{}", id))
+        .send()
+        .await
+        .assert_ok();
+    assert_eq!(c.chisel.get_json("/dev/book").await["results"], json!([]));
+
+    c.chisel
+        .post_json("/dev/library/authors", json!({"name": "Austen"}))
+        .await;
+    assert_eq!(
+        c.chisel.get_json("/dev/library/authors").await["results"][0]["name"],
+        json!("Austen")
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn auto_crud_conflicts(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, autoCrud } from '@chiselstrike/api';
+        @autoCrud("books")
+        export class Book extends ChiselEntity {
+            title: string;
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/books.ts",
+        r##"
+        export default async function (req: Request) {
+            return 'mine';
+        }
+    "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("route /dev/books of @autoCrud on Book is also defined by /dev/routes/books");
+
+    c.chisel.remove_file("routes/books.ts");
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, autoCrud } from '@chiselstrike/api';
+        @autoCrud("../books")
+        export class Book extends ChiselEntity {
+            title: string;
+        }
+    "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("the @autoCrud path of Book must be letters, digits, - and _, separated by /");
+
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, autoCrud } from '@chiselstrike/api';
+        @autoCrud()
+        export class Book extends ChiselEntity {
+            title: string;
+            static beforeCreate(book: Book) {
+                book.title = book.title.trim();
+            }
+        }
+    "##,
+    );
+    c.chisel.apply_err().await.stderr.peek(
+        "@autoCrud can't be used on class Book, which defines hook beforeCreate: export Book.crud() from a route instead",
+    );
+}
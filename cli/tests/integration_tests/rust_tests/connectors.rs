@@ -0,0 +1,124 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use std::time::Duration;
+
+use crate::framework::prelude::*;
+
+fn models(decorator: &str) -> String {
+    format!(
+        r##"
+        import {{ ChiselEntity, connector }} from '@chiselstrike/api';
+        export class Source extends ChiselEntity {{
+            title: string;
+            stars: number;
+        }}
+        {}
+        export class Repo extends ChiselEntity {{
+            name: string;
+            stars: number = 0;
+        }}
+    "##,
+        decorator
+    )
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn connected_entities(c: TestContext) {
+    c.chisel.write_unindent(
+        "routes/feed.ts",
+        r##"
+        import { Source } from '../models/models.ts';
+        export default async function (req: Request) {
+            if (req.method == 'POST') {
+                await Source.create(await req.json());
+                return 'ok';
+            }
+            const repos = await Source.findAll();
+            return { data: repos.map((r) => ({ attributes: r })) };
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/repos.ts",
+        r##"
+        import { Repo } from '../models/models.ts';
+        export default async function (req: Request) {
+            if (req.method == 'POST') {
+                await Repo.create({ name: 'local' });
+                return 'ok';
+            }
+            const repos = await Repo.cursor().filter((r) => r.stars > 1).toArray();
+            return repos.map((r) => r.name).sort();
+        }
+    "##,
+    );
+    let decorator = format!(
+        r#"@connector({{ url: "http://{}/dev/feed", items: "data", fields: {{ name: "attributes.title", stars: "attributes.stars" }}, ttl: 1 }})"#,
+        c.chisel.api_address
+    );
+    c.chisel
+        .write_unindent("models/models.ts", &models(&decorator));
+    c.chisel
+        .apply_ok()
+        .await
+        .stdout
+        .peek("fetch rows from http://");
+    c.chisel
+        .describe_ok()
+        .await
+        .stdout
+        .peek(r#"fields: { name: "attributes.title", stars: "attributes.stars" }, ttl: 1, rateLimit: 60 })"#);
+
+    c.chisel
+        .post_json("/dev/feed", json!({"title": "chisel", "stars": 5}))
+        .await;
+    c.chisel
+        .post_json("/dev/feed", json!({"title": "tiny", "stars": 1}))
+        .await;
+    assert_eq!(c.chisel.get_json("/dev/repos").await, json!(["chisel"]));
+
+    // The fetched rows are served until the ttl is over.
+    c.chisel
+        .post_json("/dev/feed", json!({"title": "deno", "stars": 3}))
+        .await;
+    assert_eq!(c.chisel.get_json("/dev/repos").await, json!(["chisel"]));
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(
+        c.chisel.get_json("/dev/repos").await,
+        json!(["chisel", "deno"])
+    );
+
+    c.chisel.post("/dev/repos").send().await.assert_status(500);
+
+    // Without the connector, the fetched rows are kept as local ones.
+    c.chisel.write_unindent("models/models.ts", &models(""));
+    c.chisel
+        .apply_ok()
+        .await
+        .stdout
+        .peek("stop fetching rows from http://");
+    c.chisel.post("/dev/repos").send().await.assert_text("ok");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_connectors(c: TestContext) {
+    for (decorator, error) in [
+        (r#"@connector({ ttl: 10 })"#, "@connector requires a url"),
+        (
+            r#"@connector({ url: "ftp://example.com/repos" })"#,
+            "entity 'Repo' has a connector with URL 'ftp://example.com/repos', which isn't HTTP",
+        ),
+        (
+            r#"@connector({ url: "http://example.com/repos", fields: { owner: "owner.login" } })"#,
+            "class Repo maps unknown field `owner` in its connector",
+        ),
+        (
+            r#"@connector({ url: "http://example.com/repos", rateLimit: 0 })"#,
+            "the connector of entity 'Repo' must allow at least one fetch a minute",
+        ),
+    ] {
+        c.chisel
+            .write_unindent("models/models.ts", &models(decorator));
+        c.chisel.apply_err().await.stderr.peek(error);
+    }
+}
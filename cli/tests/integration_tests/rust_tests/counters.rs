@@ -0,0 +1,159 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn models(decorator: &str) -> String {
+    format!(
+        r##"
+        import {{ ChiselEntity, counted }} from '@chiselstrike/api';
+        {}
+        export class Product extends ChiselEntity {{
+            name: string;
+            category: string;
+            price: number;
+        }}
+    "##,
+        decorator
+    )
+}
+
+static ROUTE_COUNTS: &str = r##"
+    import { Product } from '../models/models.ts';
+    export default async function (req: Request) {
+        return {
+            total: await Product.count(),
+            grouped: await Product.aggregate({ count: true, groupBy: 'category' }),
+            books: await Product.cursor().filter({ category: 'books' }).count(),
+        };
+    }
+"##;
+
+async fn store_product(chisel: &Chisel, name: &str, category: &str) -> String {
+    let resp = chisel
+        .post("/dev/products")
+        .json(json!({"name": name, "category": category, "price": 1}))
+        .send()
+        .await
+        .assert_ok()
+        .json();
+    resp["id"].as_str().unwrap().into()
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn counted_entities(c: TestContext) {
+    c.chisel.write_unindent("models/models.ts", &models(""));
+    c.chisel.write_unindent("routes/counts.ts", ROUTE_COUNTS);
+    c.chisel.write_unindent(
+        "routes/products.ts",
+        r##"
+        import { Product } from '../models/models.ts';
+        export default Product.crud();
+    "##,
+    );
+    c.chisel.apply_ok().await;
+    let a = store_product(&c.chisel, "a", "books").await;
+    store_product(&c.chisel, "b", "games").await;
+
+    // The rows already in the table are counted.
+    c.chisel
+        .write_unindent("models/models.ts", &models(r#"@counted("category")"#));
+    c.chisel
+        .apply_ok()
+        .await
+        .stdout
+        .peek("count rows by category");
+    c.chisel
+        .describe_ok()
+        .await
+        .stdout
+        .peek(r#"@counted("category")"#);
+    let c_id = store_product(&c.chisel, "c", "books").await;
+    assert_eq!(
+        c.chisel.get_json("/dev/counts").await,
+        json!({
+            "total": 3,
+            "grouped": [
+                {"category": "books", "count": 2},
+                {"category": "games", "count": 1},
+            ],
+            "books": 2,
+        })
+    );
+
+    // Moving a row to another group, and deleting the last row of a group.
+    c.chisel
+        .put(&format!("/dev/products/{}", a))
+        .json(json!({"name": "a", "category": "games", "price": 1}))
+        .send()
+        .await
+        .assert_ok();
+    c.chisel
+        .delete(&format!("/dev/products/{}", c_id))
+        .send()
+        .await
+        .assert_ok();
+    assert_eq!(
+        c.chisel.get_json("/dev/counts").await,
+        json!({
+            "total": 2,
+            "grouped": [{"category": "games", "count": 2}],
+            "books": 0,
+        })
+    );
+
+    c.chisel
+        .write_unindent("models/models.ts", &models("@counted()"));
+    c.chisel
+        .apply_ok()
+        .await
+        .stdout
+        .peek("stop: count rows by category");
+    c.chisel
+        .delete("/dev/products?.name=b")
+        .send()
+        .await
+        .assert_ok();
+    assert_eq!(
+        c.chisel.get_json("/dev/counts").await,
+        json!({
+            "total": 1,
+            "grouped": [{"category": "games", "count": 1}],
+            "books": 0,
+        })
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_counters(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, counted } from '@chiselstrike/api';
+        @counted("note")
+        export class Product extends ChiselEntity {
+            note?: string;
+        }
+    "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("type 'Product' is counted by optional field 'note'");
+
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, counted } from '@chiselstrike/api';
+        @counted("tags")
+        export class Product extends ChiselEntity {
+            tags: string[];
+        }
+    "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("type 'Product' can't be counted by field 'tags' of type Array<string>");
+}
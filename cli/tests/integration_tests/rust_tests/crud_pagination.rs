@@ -0,0 +1,124 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn envelope(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, autoCrud, counted } from '@chiselstrike/api';
+        @autoCrud("books", { pagination: "envelope" })
+        @counted()
+        export class Book extends ChiselEntity {
+            title: string;
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+    for title in ["Dune", "Emma", "Ulysses"] {
+        c.chisel
+            .post_json("/dev/books", json!({ "title": title }))
+            .await;
+    }
+
+    let page = c.chisel.get_json("/dev/books?sort=title&limit=2").await;
+    assert_eq!(page["items"][0]["title"], json!("Dune"));
+    assert_eq!(page["items"][1]["title"], json!("Emma"));
+    // Counted by the counter of @counted.
+    assert_eq!(page["total"], json!(3));
+    assert!(page.get("results").is_none());
+    let cursor = page["next_cursor"].as_str().unwrap();
+
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("limit", "2")
+        .append_pair("cursor", cursor)
+        .finish();
+    let page = c.chisel.get_json(&format!("/dev/books?{}", query)).await;
+    assert_eq!(page["items"][0]["title"], json!("Ulysses"));
+    assert!(page.get("next_cursor").is_none());
+
+    // Filtered entities can't be counted by the counter, so their total is
+    // only known if they fit in a page.
+    let page = c.chisel.get_json("/dev/books?.title~ne=Emma").await;
+    assert_eq!(page["total"], json!(2));
+    let page = c.chisel.get_json("/dev/books?.title~ne=Emma&limit=1").await;
+    assert!(page.get("total").is_none());
+
+    c.chisel
+        .describe_ok()
+        .await
+        .stdout
+        .peek("Endpoint /dev/books: CRUD of Book, envelope pagination");
+    c.chisel
+        .exec("generate", &["client"])
+        .await
+        .expect("chisel generate client failed");
+    let source = std::fs::read_to_string(c.chisel.tmp_dir.path().join("client/index.ts")).unwrap();
+    assert!(source.contains(
+        "readonly books = new CrudEndpoint<Book, NewBook, Envelope<Book>>(this, \"/dev/books\");"
+    ));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn bare_array(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from '@chiselstrike/api';
+        export class Author extends ChiselEntity {
+            name: string;
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/authors.ts",
+        r##"
+        import { crud } from '@chiselstrike/api';
+        import { Author } from '../models/models.ts';
+        export default crud(Author, "", { pagination: "array" });
+    "##,
+    );
+    c.chisel.apply_ok().await;
+    for name in ["Austen", "Herbert", "Joyce"] {
+        c.chisel
+            .post_json("/dev/authors", json!({ "name": name }))
+            .await;
+    }
+
+    let response = c
+        .chisel
+        .get("/dev/authors?sort=name&limit=2")
+        .send()
+        .await
+        .assert_ok();
+    let names = response.json();
+    assert_eq!(names[0]["name"], json!("Austen"));
+    assert_eq!(names[1]["name"], json!("Herbert"));
+    let link = response.header("link");
+    let next = link
+        .strip_prefix('<')
+        .and_then(|link| link.strip_suffix(">; rel=\"next\""))
+        .unwrap();
+    let next = url::Url::parse(next).unwrap();
+    let next = format!("{}?{}", next.path(), next.query().unwrap());
+    let names = c.chisel.get_json(&next).await;
+    assert_eq!(names.as_array().unwrap().len(), 1);
+    assert_eq!(names[0]["name"], json!("Joyce"));
+
+    let response = c.chisel.get("/dev/authors").send().await.assert_ok();
+    assert_eq!(response.header("x-total-count"), "3");
+
+    let doc = c
+        .chisel
+        .exec("describe", &["--format", "openapi"])
+        .await
+        .expect("describe failed")
+        .stdout
+        .json();
+    assert_eq!(
+        doc["paths"]["/dev/authors"]["get"]["responses"]["200"]["content"]["application/json"]
+            ["schema"],
+        json!({"type": "array", "items": {"$ref": "#/components/schemas/dev.Author"}})
+    );
+}
@@ -0,0 +1,82 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn store_and_filter(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/flight.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Flight extends ChiselEntity {
+            name: string = "";
+            at: Date = new Date();
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/store.ts",
+        r##"
+        import { Flight } from "../models/flight.ts";
+
+        export default async function (req: Request) {
+            await Flight.create({ name: "launch", at: new Date("2022-03-04T07:06:07.089+02:00") });
+            const flights = await Flight.findAll();
+            return flights.map((f) => f.at instanceof Date);
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/flights.ts",
+        r##"
+        import { Flight } from "../models/flight.ts";
+
+        export default Flight.crud();
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    assert_eq!(
+        c.chisel.post_json_text("/dev/store", json!({})).await,
+        "[true]"
+    );
+    // Dates are also given as milliseconds since the epoch.
+    c.chisel
+        .post_json(
+            "/dev/flights",
+            json!({"name": "landing", "at": 1646456767089u64}),
+        )
+        .await;
+    c.chisel
+        .post_json(
+            "/dev/flights",
+            json!({"name": "takeoff", "at": "2022-03-03T00:00:00Z"}),
+        )
+        .await;
+
+    let names = |flights: serde_json::Value| -> Vec<String> {
+        flights["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["name"].as_str().unwrap().to_owned())
+            .collect()
+    };
+    let flights = c.chisel.get_json("/dev/flights?sort=at").await;
+    assert_eq!(names(flights.clone()), ["takeoff", "launch", "landing"]);
+    assert_eq!(
+        flights["results"][1]["at"],
+        json!("2022-03-04T05:06:07.089Z")
+    );
+    assert_eq!(
+        flights["results"][2]["at"],
+        json!("2022-03-05T05:06:07.089Z")
+    );
+
+    let flights = c
+        .chisel
+        .get_json("/dev/flights?.at~gt=2022-03-04T06:00:00%2B01:00&sort=-at")
+        .await;
+    assert_eq!(names(flights), ["landing", "launch"]);
+}
@@ -0,0 +1,43 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn recent_crashes(c: TestContext) {
+    c.chisel
+        .exec("doctor", &[])
+        .await
+        .expect("chisel doctor failed")
+        .stdout
+        .read("is OK, running chiseld")
+        .read("No crashes reported in .chiseld-crashes");
+
+    for (millis, message) in [(1671235200000u64, "first"), (1671235300000, "second")] {
+        c.chisel.write(
+            &format!(".chiseld-crashes/crash-{}.json", millis),
+            &json!({
+                "time": "2022-12-17T00:00:00.000Z",
+                "version": "0.13.0",
+                "kind": "panic",
+                "message": format!("{}\nmore details", message),
+                "location": "server/src/deno.rs:42:5",
+                "thread": "main",
+                "backtrace": "",
+                "log": [],
+                "requests": [{"id": 1, "version": "dev", "method": "GET", "path": "/dev/books", "age_ms": 3, "sql": null}],
+            })
+            .to_string(),
+        );
+    }
+    c.chisel
+        .exec("doctor", &[])
+        .await
+        .expect("chisel doctor failed")
+        .stdout
+        .read("2 crashes reported in .chiseld-crashes, latest first:")
+        .read("panic: second")
+        .read("at server/src/deno.rs:42:5")
+        .read("while running GET /dev/books")
+        .read("crash-1671235300000.json")
+        .read("panic: first");
+}
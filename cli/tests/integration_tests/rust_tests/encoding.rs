@@ -0,0 +1,85 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static ROUTE: &str = r#"
+    export default function chisel(req: Request) {
+        return { a: 1, b: [true, null], c: "x" };
+    }"#;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn negotiate_encoding(c: TestContext) {
+    c.chisel.write("routes/value.ts", ROUTE);
+    c.chisel.apply_ok().await;
+
+    let resp = c.chisel.get("/dev/value").send().await;
+    assert_eq!(resp.header("content-type"), "application/json");
+    resp.assert_json(json!({"a": 1, "b": [true, null], "c": "x"}));
+
+    let resp = c
+        .chisel
+        .get("/dev/value")
+        .header("Accept", "application/msgpack")
+        .send()
+        .await;
+    assert_eq!(resp.header("content-type"), "application/msgpack");
+    assert_eq!(
+        &resp.body()[..],
+        b"\x83\xa1a\x01\xa1b\x92\xc3\xc0\xa1c\xa1x"
+    );
+
+    let resp = c
+        .chisel
+        .get("/dev/value")
+        .header("Accept", "application/json;q=0.5, application/cbor")
+        .send()
+        .await;
+    assert_eq!(resp.header("content-type"), "application/cbor");
+    assert_eq!(
+        &resp.body()[..],
+        b"\xa3\x61a\x01\x61b\x82\xf5\xf6\x61c\x61x"
+    );
+
+    let resp = c
+        .chisel
+        .get("/dev/value")
+        .header("Accept", "text/html, */*")
+        .send()
+        .await;
+    assert_eq!(resp.header("content-type"), "application/json");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn crud_msgpack(c: TestContext) {
+    c.chisel.write(
+        "models/types.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string;
+        }"#,
+    );
+    c.chisel.write(
+        "routes/persons.ts",
+        r#"
+        import { Person } from "../models/types.ts";
+        export default Person.crud();"#,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post_json("/dev/persons", json!({"name": "Al"}))
+        .await;
+    let resp = c
+        .chisel
+        .get("/dev/persons?.name=Al")
+        .header("Accept", "application/x-msgpack")
+        .send()
+        .await;
+    resp.assert_ok();
+    assert_eq!(resp.header("content-type"), "application/msgpack");
+    let body = resp.body();
+    assert_eq!(body[0] & 0xf0, 0x80, "expected a MessagePack map");
+    assert!(body.windows(8).any(|w| w == b"\xa7results"));
+    assert!(body.windows(8).any(|w| w == b"\xa4name\xa2Al"));
+}
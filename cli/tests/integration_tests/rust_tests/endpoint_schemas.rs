@@ -0,0 +1,97 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn describe_bodies(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Book extends ChiselEntity {
+            title: string;
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/search.ts",
+        r##"
+        import { Book } from "../models/models.ts";
+
+        export interface RequestBody {
+            query: string;
+            limit?: number;
+            order: "asc" | "desc";
+        }
+        export type ResponseBody = Book[];
+
+        export default async function (req: Request): Promise<ResponseBody> {
+            const body: RequestBody = await req.json();
+            return await Book.findMany({ title: body.query });
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/hello.ts",
+        r##"
+        export default async function (req: Request) {
+            return "hello";
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .describe_ok()
+        .await
+        .stdout
+        .read("Endpoint /dev/hello")
+        .read("Endpoint /dev/search")
+        .read(r#"request { query: string; limit?: number; order: "asc" | "desc" }"#)
+        .read("response Array<Book>");
+
+    let output = c
+        .chisel
+        .exec("describe", &["--format", "openapi"])
+        .await
+        .expect("describe failed");
+    let doc = output.stdout.json();
+    let search = &doc["paths"]["/dev/search"]["post"];
+    assert_eq!(
+        search["requestBody"]["content"]["application/json"]["schema"],
+        json!({
+            "type": "object",
+            "properties": {
+                "query": {"type": "string"},
+                "limit": {"type": "number"},
+                "order": {"type": "string", "enum": ["asc", "desc"]},
+            },
+            "required": ["query", "order"],
+        })
+    );
+    assert_eq!(
+        search["responses"]["200"]["content"]["application/json"]["schema"],
+        json!({"type": "array", "items": {"$ref": "#/components/schemas/dev.Book"}})
+    );
+    assert_eq!(doc["paths"]["/dev/hello"], json!({}));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn unknown_entity(c: TestContext) {
+    c.chisel.write_unindent(
+        "routes/authors.ts",
+        r##"
+        export interface ResponseBody {
+            authors: Author[];
+        }
+        export default async function (req: Request) {
+            return { authors: [] };
+        }
+    "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("ResponseBody of routes/authors.ts refers to unknown entity type 'Author'");
+}
@@ -0,0 +1,109 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn store_and_evolve(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/post.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Post extends ChiselEntity {
+            title: string = "";
+            status: "draft" | "published" = "draft";
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/posts.ts",
+        r##"
+        import { Post } from "../models/post.ts";
+
+        export default Post.crud();
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post_json("/dev/posts", json!({"title": "a", "status": "published"}))
+        .await;
+    c.chisel
+        .post_json("/dev/posts", json!({"title": "b"}))
+        .await;
+    assert!(
+        c.chisel
+            .post_json_status("/dev/posts", json!({"title": "c", "status": "deleted"}))
+            .await
+            >= 400
+    );
+
+    let statuses = |posts: serde_json::Value| -> Vec<serde_json::Value> {
+        posts["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["status"].clone())
+            .collect()
+    };
+    let posts = c.chisel.get_json("/dev/posts?sort=title").await;
+    assert_eq!(statuses(posts), [json!("published"), json!("draft")]);
+    let posts = c.chisel.get_json("/dev/posts?.status=draft").await;
+    assert_eq!(statuses(posts), [json!("draft")]);
+
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .peek(r#"status: "draft" | "published" = "draft";"#);
+
+    // Variants can be added, but not removed.
+    c.chisel.write_unindent(
+        "models/post.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Post extends ChiselEntity {
+            title: string = "";
+            status: "draft" | "published" | "archived" = "draft";
+        }
+        "##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json("/dev/posts", json!({"title": "c", "status": "archived"}))
+        .await;
+
+    c.chisel.write_unindent(
+        "models/post.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Post extends ChiselEntity {
+            title: string = "";
+            status: "draft" | "archived" = "draft";
+        }
+        "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("Incompatible change");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn default_must_be_a_variant(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/post.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Post extends ChiselEntity {
+            status: "draft" | "published" = "deleted";
+        }
+        "##,
+    );
+    c.chisel.apply_err().await;
+}
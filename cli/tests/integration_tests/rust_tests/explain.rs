@@ -0,0 +1,61 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn explain_profiled_request(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/person.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Person extends ChiselEntity {
+            name: string = "";
+            age: number = 0;
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r##"
+        import { Person } from "../models/person.ts";
+
+        export default async function () {
+            await Person.build({ name: "Al", age: 30 }).save();
+            const people = await Person.cursor()
+                .filter({ name: "Al" })
+                .sortBy("age")
+                .toArray();
+            return people.length;
+        }
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec("explain", &["/people"])
+        .await
+        .expect_err("chisel explain should fail without a profiled request")
+        .stderr
+        .peek("no request to /people was profiled");
+
+    c.chisel
+        .get("/dev/people")
+        .header("ChiselProfile", "1")
+        .send()
+        .await
+        .assert_text("1");
+
+    // The query is explained with the filter and sort pushed down, and the
+    // insert, which binds arguments, is only listed.
+    c.chisel
+        .exec("explain", &["/people"])
+        .await
+        .expect("chisel explain failed")
+        .stdout
+        .read("GET /dev/people, profiled")
+        .peek("INSERT INTO")
+        .peek("(only queries and deletes are explained)")
+        .peek("WHERE")
+        .peek(r#"ORDER BY "#);
+}
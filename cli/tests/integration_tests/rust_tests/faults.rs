@@ -0,0 +1,110 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+use std::time::{Duration, Instant};
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn inject_and_clear(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/person.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Person extends ChiselEntity {
+            name: string = "";
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r##"
+        import { Person } from "../models/person.ts";
+        export default Person.crud();
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/fetch.ts",
+        r##"
+        export default async function () {
+            try {
+                await fetch("https://example.com");
+                return "fetched";
+            } catch (e) {
+                return e.message;
+            }
+        }
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    let post = || {
+        c.chisel
+            .post("/dev/people")
+            .json(json!({"name": "Al"}))
+            .send()
+    };
+    c.chisel
+        .exec(
+            "fault",
+            &["inject", "--kind", "database", "--endpoint", "/people"],
+        )
+        .await
+        .expect("chisel fault inject failed")
+        .stdout
+        .read("Fault rule 1 added");
+    post().await.assert_status(500);
+
+    c.chisel
+        .exec("fault", &["inject", "--kind", "fetch", "--percent", "0"])
+        .await
+        .expect("chisel fault inject failed");
+    c.chisel
+        .exec("fault", &["inject", "--kind", "fetch"])
+        .await
+        .expect("chisel fault inject failed");
+    c.chisel
+        .get("/dev/fetch")
+        .send()
+        .await
+        .assert_text("network error (injected fault)");
+    c.chisel
+        .exec("fault", &["list"])
+        .await
+        .expect("chisel fault list failed")
+        .stdout
+        .read("1: database in 100% of requests to dev/people")
+        .read("2: fetch in 0% of requests to dev")
+        .read("3: fetch in 100% of requests to dev");
+
+    c.chisel
+        .exec("fault", &["clear"])
+        .await
+        .expect("chisel fault clear failed");
+    post().await.assert_ok();
+
+    c.chisel
+        .exec(
+            "fault",
+            &["inject", "--kind", "slow-query", "--delay", "500"],
+        )
+        .await
+        .expect("chisel fault inject failed");
+    let start = Instant::now();
+    post().await.assert_ok();
+    assert!(start.elapsed() >= Duration::from_millis(500));
+
+    c.chisel
+        .exec("fault", &["inject", "--kind", "out-of-memory"])
+        .await
+        .expect("chisel fault inject failed");
+    c.chisel.get("/dev/people").send().await.assert_status(500);
+    c.chisel
+        .exec("fault", &["clear", "--id", "5"])
+        .await
+        .expect("chisel fault clear failed");
+    c.chisel.get("/dev/people").send().await.assert_ok();
+    c.chisel
+        .exec("fault", &["clear", "--id", "5"])
+        .await
+        .expect_err("removing a removed rule succeeded");
+}
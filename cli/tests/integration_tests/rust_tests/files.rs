@@ -0,0 +1,62 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn upload(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, ChiselFile } from "@chiselstrike/api";
+
+        export class Profile extends ChiselEntity {
+            name: string;
+            avatar?: ChiselFile;
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/profiles.ts",
+        r##"
+        import { ChiselRequest, readFile } from "@chiselstrike/api";
+        import { Profile } from "../models/models.ts";
+
+        export default async function (req: ChiselRequest) {
+            if (req.method == "POST") {
+                const [avatar] = await req.files();
+                const profile = await Profile.create({ name: "alice", avatar });
+                return { field: avatar.field, avatar: profile.avatar };
+            }
+            const profile = await Profile.findOne({ name: "alice" });
+            return new TextDecoder().decode(await readFile(profile!.avatar!));
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+
+    let body = "--boundary\r\n\
+        Content-Disposition: form-data; name=\"name\"\r\n\r\n\
+        alice\r\n\
+        --boundary\r\n\
+        Content-Disposition: form-data; name=\"picture\"; filename=\"alice.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        hello, alice\r\n\
+        --boundary--\r\n";
+    let uploaded = c
+        .chisel
+        .post("/dev/profiles")
+        .header("Content-Type", "multipart/form-data; boundary=boundary")
+        .body(body)
+        .send()
+        .await
+        .assert_ok()
+        .json();
+    assert_eq!(uploaded["field"], json!("picture"));
+    let avatar = &uploaded["avatar"];
+    assert_eq!(avatar["name"], json!("alice.txt"));
+    assert_eq!(avatar["type"], json!("text/plain"));
+    assert_eq!(avatar["size"], json!(12));
+
+    let content = c.chisel.get_json("/dev/profiles").await;
+    assert_eq!(content, json!("hello, alice"));
+}
@@ -0,0 +1,69 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn typed_client(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from '@chiselstrike/api';
+        export class Author extends ChiselEntity {
+            name: string;
+        }
+        export class Book extends ChiselEntity {
+            title: string;
+            author: Author;
+            published?: Date;
+            pages: number = 0;
+            status: "draft" | "published";
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/books.ts",
+        r##"
+        import { Book } from '../models/models.ts';
+        export default Book.crud();
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/admin/hello-world.ts",
+        r##"
+        export default async function (req: Request) {
+            return 'hello';
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec("generate", &["client", "--out", "sdk"])
+        .await
+        .expect("chisel generate client failed")
+        .stdout
+        .peek("Client of version dev written to sdk/index.ts");
+    let source = std::fs::read_to_string(c.chisel.tmp_dir.path().join("sdk/index.ts")).unwrap();
+    for expected in [
+        "export interface Book {\n    id: string;\n    title: string;\n    author: Author;\n    \
+         published?: string;\n    pages: number;\n    status: \"draft\" | \"published\";\n}",
+        "export type NewBook = Omit<Book, \"id\" | \"pages\"> & Partial<Pick<Book, \"pages\">>;",
+        "export type NewAuthor = Omit<Author, \"id\">;",
+        "readonly books = new CrudEndpoint<Book, NewBook>(this, \"/dev/books\");",
+        "readonly adminHelloWorld = new Endpoint(this, \"/dev/admin/hello-world\");",
+    ] {
+        assert!(
+            source.contains(expected),
+            "{} not in the client:\n{}",
+            expected,
+            source
+        );
+    }
+
+    c.chisel
+        .exec("generate", &["client", "--version", "prod"])
+        .await
+        .expect_err("generating the client of a missing version should fail")
+        .stderr
+        .peek("version prod is not applied");
+}
@@ -0,0 +1,87 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static ROUTE_PEOPLE: &str = r##"
+    import { Person } from '../models/models.ts';
+    export default async function (req: Request) {
+        if (req.method == 'POST') {
+            await Person.create(await req.json());
+            return 'ok';
+        }
+        const people = await Person.findMany({ lastName: 'Doe', firstName: 'John' });
+        return people.map((p) => p.age);
+    }
+"##;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn declared_indexes(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, index } from '@chiselstrike/api';
+        @index("lastName", "firstName")
+        export class Person extends ChiselEntity {
+            firstName: string;
+            lastName: string;
+            @index age: number;
+        }
+    "##,
+    );
+    c.chisel.write_unindent("routes/people.ts", ROUTE_PEOPLE);
+    c.chisel.apply_ok().await;
+    c.chisel
+        .describe_ok()
+        .await
+        .stdout
+        .peek(r#"@index("lastName", "firstName")"#)
+        .peek(r#"@index("age")"#)
+        .peek("class Person {");
+
+    for (first, last, age) in [("John", "Doe", 42), ("Jane", "Doe", 41)] {
+        c.chisel
+            .post_json(
+                "/dev/people",
+                json!({"firstName": first, "lastName": last, "age": age}),
+            )
+            .await;
+    }
+    assert_eq!(c.chisel.get_json("/dev/people").await, json!([42]));
+
+    // Dropping a declaration drops its index.
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, index } from '@chiselstrike/api';
+        export class Person extends ChiselEntity {
+            firstName: string;
+            lastName: string;
+            @index age: number;
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+    let output = c.chisel.describe_ok().await;
+    output.stdout.peek(r#"@index("age")"#);
+    assert!(!output.stdout.as_str().contains("lastName\", "));
+    assert_eq!(c.chisel.get_json("/dev/people").await, json!([42]));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn index_over_unknown_field(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, index } from '@chiselstrike/api';
+        @index("name", "nickname")
+        export class Person extends ChiselEntity {
+            name: string;
+        }
+    "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("index of class Person is over unknown field `nickname`");
+}
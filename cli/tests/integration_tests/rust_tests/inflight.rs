@@ -0,0 +1,71 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+use std::time::Duration;
+
+static MODELS: &str = r##"
+    import { ChiselEntity } from '@chiselstrike/api';
+    export class Person extends ChiselEntity {
+        name: string;
+    }
+"##;
+
+// Keeps querying the datastore for a minute, unless it is killed.
+static ROUTE_SLOW: &str = r##"
+    import { Person } from '../models/models.ts';
+    export default async function () {
+        const end = Date.now() + 60_000;
+        while (Date.now() < end) {
+            await Person.findMany({});
+            await new Promise((resolve) => setTimeout(resolve, 100));
+        }
+        return 'done';
+    }
+"##;
+
+/// Polls `chisel ps` until a request to `route` shows up with the SQL it is
+/// running, and returns its id.
+async fn find_request(chisel: &Chisel, route: &str) -> String {
+    for _ in 0..100 {
+        let output = chisel.exec("ps", &[]).await.expect("chisel ps failed");
+        let mut lines = output.stdout.as_str().lines();
+        while let Some(line) = lines.next() {
+            let sql = lines.clone().next().unwrap_or_default();
+            if line.ends_with(route) && sql.contains("SELECT") {
+                return line.split_whitespace().next().unwrap().to_owned();
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("request to {} not found by chisel ps", route);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn kill_request(c: TestContext) {
+    c.chisel.write_unindent("models/models.ts", MODELS);
+    c.chisel.write_unindent("routes/slow.ts", ROUTE_SLOW);
+    c.chisel.apply_ok().await;
+
+    let killer = async {
+        let id = find_request(&c.chisel, "/slow").await;
+        c.chisel
+            .exec("kill", &[&id])
+            .await
+            .expect("chisel kill failed")
+            .stdout
+            .read(&format!("Request {} killed", id));
+        id
+    };
+    let (response, id) = futures::join!(c.chisel.get("/dev/slow").send(), killer);
+    response
+        .assert_status(500)
+        .assert_text_contains(&format!("request {} was killed", id));
+
+    // The request is gone once it fails.
+    let output = c.chisel.exec("ps", &[]).await.expect("chisel ps failed");
+    assert!(!output.stdout.as_str().contains("/slow"));
+    c.chisel
+        .exec("kill", &[&id])
+        .await
+        .expect_err("chisel kill of a finished request succeeded");
+}
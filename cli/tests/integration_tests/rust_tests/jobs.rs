@@ -0,0 +1,74 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn describe_jobs(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from '@chiselstrike/api';
+        export class Tick extends ChiselEntity {
+            at: number;
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "jobs/tick.ts",
+        r##"
+        import { Tick } from '../models/models.ts';
+        export const schedule = "*/5 * * * *";
+        export default async function () {
+            await Tick.create({ at: Date.now() });
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await.stdout.peek("1 jobs");
+
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .peek(r#"Job /dev/tick "*/5 * * * *": never run"#);
+
+    // Removing the job unschedules it.
+    c.chisel.remove_file("jobs/tick.ts");
+    c.chisel.apply_ok().await;
+    let output = c
+        .chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed");
+    output.stdout.peek("class Tick");
+    assert!(!output.stdout.as_str().contains("Job /dev/tick"));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_jobs(c: TestContext) {
+    c.chisel.write_unindent(
+        "jobs/bad.ts",
+        r##"
+        export const schedule = "*/5 * * *";
+        export default function () {}
+    "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("job /dev/bad")
+        .peek("must have 5 fields");
+
+    c.chisel.write_unindent(
+        "jobs/bad.ts",
+        r##"
+        export default function () {}
+    "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("job /dev/bad must export its schedule");
+}
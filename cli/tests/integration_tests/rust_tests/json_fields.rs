@@ -0,0 +1,82 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn store_and_evolve(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/event.ts",
+        r##"
+        import { ChiselEntity, JSONValue } from "@chiselstrike/api";
+
+        export class Webhook extends ChiselEntity {
+            source: string = "";
+            payload: JSONValue = {};
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/webhooks.ts",
+        r##"
+        import { Webhook } from "../models/event.ts";
+
+        export default Webhook.crud();
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    let payload = json!({"id": 7, "tags": ["a", "b"], "nested": {"ok": true, "n": null}});
+    c.chisel
+        .post_json(
+            "/dev/webhooks",
+            json!({"source": "github", "payload": payload}),
+        )
+        .await;
+    c.chisel
+        .post_json(
+            "/dev/webhooks",
+            json!({"source": "stripe", "payload": "it's text"}),
+        )
+        .await;
+    c.chisel
+        .post_json("/dev/webhooks", json!({"source": "empty"}))
+        .await;
+
+    let payloads = |webhooks: serde_json::Value, field: &str| -> Vec<serde_json::Value> {
+        webhooks["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|w| w[field].clone())
+            .collect()
+    };
+    let webhooks = c.chisel.get_json("/dev/webhooks?sort=source").await;
+    assert_eq!(
+        payloads(webhooks, "payload"),
+        [json!({}), payload.clone(), json!("it's text")]
+    );
+
+    // Existing rows read the default of an added field.
+    c.chisel.write_unindent(
+        "models/event.ts",
+        r##"
+        import { ChiselEntity, JSONValue } from "@chiselstrike/api";
+
+        export class Webhook extends ChiselEntity {
+            source: string = "";
+            payload: JSONValue = {};
+            meta: JSONValue = { retries: [1, -2.5], owner: "ops's" };
+        }
+        "##,
+    );
+    c.chisel.apply_ok().await;
+    let webhooks = c.chisel.get_json("/dev/webhooks?sort=source").await;
+    assert_eq!(
+        payloads(webhooks, "meta")[0],
+        json!({"retries": [1, -2.5], "owner": "ops's"})
+    );
+
+    // JSON fields can't be filtered by.
+    let response = c.chisel.get("/dev/webhooks?.payload=x").send().await;
+    assert!(response.status() >= 400);
+}
@@ -0,0 +1,68 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+// Records the URL of every copy of this module that gets imported, so that
+// a response tells which versions have been loaded so far.
+static ROUTE_LOADED: &str = r##"
+    const loaded: string[] = (globalThis as any).loadedModules ??= [];
+    loaded.push(import.meta.url);
+    export default function () {
+        return loaded;
+    }
+"##;
+
+async fn loaded_modules(chisel: &Chisel, url: &str) -> Vec<String> {
+    serde_json::from_value(chisel.get_json(url).await).unwrap()
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn loaded_on_first_request(mut c: TestContext) {
+    c.chisel.write_unindent("routes/loaded.ts", ROUTE_LOADED);
+    c.chisel.apply_ok().await;
+    c.chisel
+        .exec("apply", &["--version", "lazy"])
+        .await
+        .expect("chisel apply --version lazy failed");
+    c.chisel
+        .write("chiseld.toml", r#"lazy_versions = ["lazy"]"#);
+    c.restart_chiseld().await;
+
+    let loaded = loaded_modules(&c.chisel, "/dev/loaded").await;
+    assert_eq!(loaded.len(), 1);
+    assert!(loaded[0].contains("/dev/"));
+
+    let loaded = loaded_modules(&c.chisel, "/lazy/loaded").await;
+    assert_eq!(loaded.len(), 2);
+    assert!(loaded[1].contains("/lazy/"));
+
+    // The version is only loaded once.
+    let loaded = loaded_modules(&c.chisel, "/lazy/loaded").await;
+    assert_eq!(loaded.len(), 2);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn eager_overrides_lazy(mut c: TestContext) {
+    c.chisel.write_unindent("routes/loaded.ts", ROUTE_LOADED);
+    c.chisel.apply_ok().await;
+    c.chisel
+        .exec("apply", &["--version", "eager"])
+        .await
+        .expect("chisel apply --version eager failed");
+    c.chisel.write(
+        "chiseld.toml",
+        r#"
+        lazy_versions = ["*"]
+        eager_versions = ["eager"]
+        "#,
+    );
+    c.restart_chiseld().await;
+
+    let loaded = loaded_modules(&c.chisel, "/eager/loaded").await;
+    assert_eq!(loaded.len(), 1);
+    assert!(loaded[0].contains("/eager/"));
+
+    let loaded = loaded_modules(&c.chisel, "/dev/loaded").await;
+    assert_eq!(loaded.len(), 2);
+    assert!(loaded[1].contains("/dev/"));
+}
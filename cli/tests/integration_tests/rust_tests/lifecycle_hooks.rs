@@ -0,0 +1,118 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn hooks(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Log extends ChiselEntity {
+            message: string;
+        }
+
+        export class Post extends ChiselEntity {
+            title: string;
+            slug: string = "";
+
+            static beforeCreate(post: Post) {
+                post.slug = post.title.toLowerCase().replaceAll(" ", "-");
+            }
+
+            static async afterUpdate(post: Post, old: Post) {
+                await Log.create({ message: `${old.title} -> ${post.title}` });
+            }
+
+            static beforeDelete(old: Post) {
+                if (old.title == "Pinned") {
+                    throw new Error("Pinned posts can't be deleted");
+                }
+            }
+
+            static async afterDelete(old: Post) {
+                await Log.create({ message: `deleted ${old.title}` });
+            }
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/posts.ts",
+        r##"
+        import { Post } from "../models/models.ts";
+        export default Post.crud();
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/rename.ts",
+        r##"
+        import { ChiselRequest } from "@chiselstrike/api";
+        import { Post } from "../models/models.ts";
+
+        export default async function (req: ChiselRequest) {
+            const id = req.query.get("id")!;
+            return await Post.update(id, { title: req.query.get("title")! });
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/log.ts",
+        r##"
+        import { Log } from "../models/models.ts";
+
+        export default async function () {
+            return (await Log.findAll()).map((log) => log.message).sort();
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+
+    let post = c
+        .chisel
+        .post("/dev/posts")
+        .json(json!({"title": "Hello World"}))
+        .send()
+        .await
+        .assert_ok()
+        .json();
+    assert_eq!(post["slug"], json!("hello-world"));
+    let id = post["id"].as_str().unwrap();
+    c.chisel
+        .post_json("/dev/posts", json!({"title": "Pinned"}))
+        .await;
+
+    c.chisel
+        .patch(&format!("/dev/posts/{}", id))
+        .json(json!({"title": "Hi World"}))
+        .send()
+        .await
+        .assert_ok();
+    c.chisel
+        .post(&format!("/dev/rename?id={}&title=Bye", id))
+        .send()
+        .await
+        .assert_ok();
+    // The slug is only set on creation.
+    assert_eq!(
+        c.chisel.get_json(&format!("/dev/posts/{}", id)).await["slug"],
+        json!("hello-world")
+    );
+
+    c.chisel
+        .delete("/dev/posts?all=true")
+        .send()
+        .await
+        .assert_status(500)
+        .assert_text_contains("Pinned posts can't be deleted");
+    c.chisel
+        .delete(&format!("/dev/posts/{}", id))
+        .send()
+        .await
+        .assert_ok();
+
+    assert_eq!(
+        c.chisel.get_json("/dev/log").await,
+        json!(["Hello World -> Hi World", "Hi World -> Bye", "deleted Bye"])
+    );
+}
@@ -0,0 +1,181 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+// A provider that logs Ann in right away, served by the project itself.
+static ROUTE_IDP: &str = r##"
+    import { ChiselRequest } from "@chiselstrike/api";
+
+    export default async function (req: ChiselRequest) {
+        switch (req.pathComponents()[0]) {
+            case "authorize": {
+                const url = new URL(req.query.get("redirect_uri")!);
+                url.searchParams.set("code", "code-of-ann");
+                url.searchParams.set("state", req.query.get("state")!);
+                return new Response(null, {
+                    status: 302,
+                    headers: { location: url.toString() },
+                });
+            }
+            case "token": {
+                const form = await req.formData();
+                if (
+                    form.get("code") !== "code-of-ann" ||
+                    form.get("client_secret") !== "s3cret" ||
+                    !form.get("code_verifier")
+                ) {
+                    return new Response("invalid grant", { status: 400 });
+                }
+                return { access_token: "token-of-ann", token_type: "bearer" };
+            }
+            case "userinfo":
+                if (req.headers.get("authorization") !== "Bearer token-of-ann") {
+                    return new Response("invalid token", { status: 401 });
+                }
+                return { sub: "ann-1", email: "ann@example.com", email_verified: true, name: "Ann" };
+        }
+    }
+"##;
+
+/// The `name=value` part of the cookie that `response` sets.
+fn set_cookie(response: &reqwest::Response) -> String {
+    let cookie = response.headers()["set-cookie"].to_str().unwrap();
+    cookie.split(';').next().unwrap().to_owned()
+}
+
+fn location(response: &reqwest::Response) -> String {
+    response.headers()["location"].to_str().unwrap().to_owned()
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn oidc_login(mut c: TestContext) {
+    c.chisel.write_unindent(
+        "Chisel.toml",
+        r#"
+        models = ["models"]
+        routes = ["routes"]
+        policies = ["policies"]
+        modules = "deno"
+        login = ["fake"]
+        "#,
+    );
+    c.chisel.write_unindent("routes/idp.ts", ROUTE_IDP);
+    let idp = format!("http://{}/dev/idp", c.chisel.api_address);
+    c.chisel.write(
+        ".env",
+        &json!({
+            "CHISELD_AUTH_PROVIDERS": ["session"],
+            "CHISELD_OIDC_PROVIDERS": {
+                "fake": {
+                    "clientId": "app",
+                    "clientSecret": "s3cret",
+                    "authorizationUrl": format!("{}/authorize", idp),
+                    "tokenUrl": format!("{}/token", idp),
+                    "userinfoUrl": format!("{}/userinfo", idp),
+                }
+            }
+        })
+        .to_string(),
+    );
+    c.restart_chiseld().await;
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .get("/dev/__auth")
+        .send()
+        .await
+        .assert_json(json!({"providers": ["fake"]}));
+    c.chisel
+        .get("/dev/__auth/login/other")
+        .send()
+        .await
+        .assert_status(404);
+
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let base = format!("http://{}", c.chisel.api_address);
+    let login = client
+        .get(format!("{}/dev/__auth/login/fake?redirect=/dev/home", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(login.status(), 302);
+    assert!(location(&login).starts_with(&format!("{}/authorize?", idp)));
+    let state = set_cookie(&login);
+
+    let authorize = client.get(location(&login)).send().await.unwrap();
+    let callback_url = location(&authorize);
+    assert!(callback_url.starts_with(&format!("{}/dev/__auth/callback/fake?", base)));
+
+    // The state of the login must come from the same browser.
+    let forged = client.get(&callback_url).send().await.unwrap();
+    assert_eq!(forged.status(), 400);
+
+    let callback = client
+        .get(&callback_url)
+        .header("Cookie", &state)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(callback.status(), 302);
+    assert_eq!(location(&callback), "/dev/home");
+    let session = set_cookie(&callback);
+    assert!(session.starts_with("next-auth.session-token="));
+
+    let user = c
+        .chisel
+        .get("/dev/__auth/session")
+        .header("Cookie", &session)
+        .send()
+        .await
+        .json();
+    assert_eq!(user["user"]["email"], "ann@example.com");
+    assert_eq!(user["user"]["name"], "Ann");
+
+    // Logging in again finds the same user.
+    let login = client
+        .get(format!("{}/dev/__auth/login/fake", base))
+        .send()
+        .await
+        .unwrap();
+    let authorize = client.get(location(&login)).send().await.unwrap();
+    let callback = client
+        .get(location(&authorize))
+        .header("Cookie", set_cookie(&login))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(callback.status(), 200);
+    let again = callback.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(again["user"]["id"], user["user"]["id"]);
+
+    c.chisel
+        .post("/dev/__auth/logout")
+        .header("Cookie", &session)
+        .send()
+        .await
+        .assert_status(204);
+    c.chisel
+        .get("/dev/__auth/session")
+        .header("Cookie", &session)
+        .send()
+        .await
+        .assert_status(401);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn login_route_is_reserved(c: TestContext) {
+    c.chisel.write_unindent(
+        "routes/__auth.ts",
+        r##"
+        export default () => "mine";
+        "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("route /dev/__auth is reserved for login");
+}
@@ -0,0 +1,148 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn save_and_load(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/types.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Author extends ChiselEntity {
+            name: string = "";
+        }
+
+        export class Book extends ChiselEntity {
+            title: string = "";
+            authors: Author[] = [];
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/store.ts",
+        r##"
+        import { Author, Book } from "../models/types.ts";
+
+        export default async function (req: Request) {
+            const names = await req.json();
+            const book = Book.build({ title: "Good Omens" });
+            book.authors = names.map((name: string) => Author.build({ name }));
+            await book.save();
+            return book.authors.map((author) => author.id !== undefined);
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/rename.ts",
+        r##"
+        import { Author, Book } from "../models/types.ts";
+
+        export default async function (req: Request) {
+            const book = await Book.findOne({ title: "Good Omens" });
+            book!.authors = [book!.authors[1], Author.build({ name: "Anonymous" })];
+            await book!.save();
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/books.ts",
+        r##"
+        import { Book } from "../models/types.ts";
+
+        export default async function (req: Request) {
+            return (await Book.findAll()).map((book) => {
+                return { title: book.title, authors: book.authors.map((a) => a.name) };
+            });
+        }
+        "##,
+    );
+
+    let output = c.chisel.apply_ok().await;
+    output.stdout.peek("create join table for authors");
+
+    // The ids of the related objects are backfilled.
+    assert_eq!(
+        c.chisel
+            .post_json_text("/dev/store", json!(["Terry Pratchett", "Neil Gaiman"]))
+            .await,
+        "[true,true]"
+    );
+    assert_eq!(
+        c.chisel.get_json("/dev/books").await,
+        json!([{"title": "Good Omens", "authors": ["Terry Pratchett", "Neil Gaiman"]}])
+    );
+
+    // Saving the object replaces its links, keeping the related objects.
+    c.chisel.post_json("/dev/rename", json!({})).await;
+    assert_eq!(
+        c.chisel.get_json("/dev/books").await,
+        json!([{"title": "Good Omens", "authors": ["Neil Gaiman", "Anonymous"]}])
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn delete_unlinks(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/types.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Author extends ChiselEntity {
+            name: string = "";
+        }
+
+        export class Book extends ChiselEntity {
+            title: string = "";
+            authors: Author[] = [];
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/authors.ts",
+        r##"
+        import { Author } from "../models/types.ts";
+
+        export default Author.crud();
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/books.ts",
+        r##"
+        import { Author, Book } from "../models/types.ts";
+
+        export default async function (req: Request) {
+            if (req.method == "POST") {
+                const authors = (await req.json()).map((name: string) => Author.build({ name }));
+                await Book.create({ title: "Good Omens", authors });
+            }
+            return (await Book.findAll()).map((book) => {
+                return { title: book.title, authors: book.authors.map((a) => a.name) };
+            });
+        }
+        "##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json("/dev/books", json!(["Terry Pratchett", "Neil Gaiman"]))
+        .await;
+
+    let authors = c.chisel.get_json("/dev/authors?sort=name").await;
+    let id = authors["results"][1]["id"].as_str().unwrap().to_owned();
+    c.chisel
+        .delete(&format!("/dev/authors/{}", id))
+        .send()
+        .await
+        .assert_ok();
+    // A new object with the id of the deleted one isn't linked in its place.
+    c.chisel
+        .put(&format!("/dev/authors/{}", id))
+        .json(json!({"name": "Impostor"}))
+        .send()
+        .await
+        .assert_ok();
+    assert_eq!(
+        c.chisel.get_json("/dev/books").await,
+        json!([{"title": "Good Omens", "authors": ["Neil Gaiman"]}])
+    );
+}
@@ -0,0 +1,38 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn status_reports_memory(c: TestContext) {
+    c.chisel.write_unindent(
+        "routes/hello.ts",
+        r##"
+        export default function () {
+            return "hello";
+        }
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    let output = c
+        .chisel
+        .exec("status", &[])
+        .await
+        .expect("chisel status failed");
+    output.stdout.peek("Server status is OK");
+    assert!(!output.stdout.as_str().contains("Executor memory"));
+
+    let output = c
+        .chisel
+        .exec("status", &["--verbose"])
+        .await
+        .expect("chisel status --verbose failed");
+    output
+        .stdout
+        .peek("Server status is OK")
+        .peek("Executor memory:")
+        .peek("  executor 0: ")
+        .peek("MiB heap limit")
+        .peek("Module memory:")
+        .peek("  dev: ");
+}
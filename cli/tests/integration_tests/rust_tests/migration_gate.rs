@@ -0,0 +1,91 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn approve_dropped_field(mut c: TestContext) {
+    c.chisel.write_unindent(
+        "models/person.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Person extends ChiselEntity {
+            name: string = "";
+            age: number = 0;
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r##"
+        import { Person } from "../models/person.ts";
+        export default Person.crud();
+        "##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json("/dev/people", json!({"name": "Al", "age": 3}))
+        .await;
+    c.chisel
+        .write_unindent("chiseld.toml", "require_migration_approval = true");
+    c.restart_chiseld().await;
+
+    // Changes that don't drop anything are applied right away.
+    c.chisel.write_unindent(
+        "models/person.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Person extends ChiselEntity {
+            name: string = "";
+            age: number = 0;
+            city: string = "";
+        }
+        "##,
+    );
+    c.chisel.apply_ok().await.stdout.read("Applied:");
+
+    c.chisel.write_unindent(
+        "models/person.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Person extends ChiselEntity {
+            name: string = "";
+            city: string = "";
+        }
+        "##,
+    );
+    c.chisel
+        .exec("apply", &["--yes"])
+        .await
+        .expect("chisel apply failed")
+        .stdout
+        .read("Not applied: dropping models or fields needs approval.");
+    c.chisel
+        .exec("migrate", &["list"])
+        .await
+        .expect("chisel migrate list failed")
+        .stdout
+        .read("2: dev")
+        .read("Person: drop age: number");
+    let people = c.chisel.get_json("/dev/people").await;
+    assert_eq!(people["results"][0]["age"], json!(3));
+
+    c.chisel
+        .exec("migrate", &["approve", "1"])
+        .await
+        .expect_err("approving a replaced migration succeeded");
+    c.chisel
+        .exec("migrate", &["approve", "2"])
+        .await
+        .expect("chisel migrate approve failed")
+        .stdout
+        .read("Applied:");
+    let people = c.chisel.get_json("/dev/people").await;
+    assert_eq!(people["results"][0].get("age"), None);
+    c.chisel
+        .exec("migrate", &["approve", "2"])
+        .await
+        .expect_err("approving an applied migration succeeded");
+}
@@ -89,7 +89,17 @@ pub async fn remove_field(c: TestContext) {
             a: string = "";
         }"##,
     );
-    c.chisel.apply_ok().await;
+    // Dropping a column with data needs confirmation.
+    let mut output = c.chisel.apply_err().await;
+    output
+        .stdout
+        .read("~ model Evolving (will lose values in 1 rows)");
+    output.stdout.read("drop b: string");
+    output.stderr.read("Applying would lose the data of 1 rows");
+    c.chisel
+        .exec("apply", &["--yes"])
+        .await
+        .expect("chisel apply --yes failed");
 
     let r = c.chisel.get_json("/dev/evolving").await;
     json_is_subset(
@@ -335,3 +345,52 @@ pub async fn remove_unique_constraint(c: TestContext) {
     );
     c.chisel.apply_ok().await;
 }
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn evolve_many_models(c: TestContext) {
+    // Enough models for their tables to be changed in several transactions.
+    let names = ["A", "B", "C", "D", "E", "F", "G", "H"];
+    let write_models = |field: &str| {
+        let models: String = names
+            .iter()
+            .map(|name| format!("export class {name} extends ChiselEntity {{ {field} }}\n"))
+            .collect();
+        c.chisel.write(
+            "models/models.ts",
+            &format!("import {{ ChiselEntity }} from \"@chiselstrike/api\";\n{models}"),
+        );
+    };
+    c.chisel.write(
+        "routes/fill.ts",
+        r##"
+        import * as models from "../models/models.ts";
+        export default async function () {
+            // The models all have the same fields.
+            const all = Object.values(models) as (typeof models.A)[];
+            for (const model of all) {
+                await model.create({});
+            }
+            const found = await Promise.all(
+                all.map((model) => model.findMany({})),
+            );
+            return found.map((rows) => rows.length).join(",");
+        }
+        "##,
+    );
+
+    write_models("a: string = \"\";");
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post("/dev/fill")
+        .send()
+        .await
+        .assert_text("1,1,1,1,1,1,1,1");
+
+    write_models("a: string = \"\"; b: number = 1;");
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post("/dev/fill")
+        .send()
+        .await
+        .assert_text("2,2,2,2,2,2,2,2");
+}
@@ -0,0 +1,130 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_manifest(c: &TestContext, snake_case: bool, table_prefix: &str) {
+    let manifest = format!(
+        r#"
+        models = ["models"]
+        routes = ["routes"]
+        events = ["events"]
+        policies = ["policies"]
+        modules = "deno"
+        optimize = "yes"
+        auto_index = "no"
+
+        [naming]
+        snake_case = {}
+        table_prefix = "{}"
+        "#,
+        snake_case, table_prefix
+    );
+    c.chisel.write_unindent("Chisel.toml", &manifest);
+}
+
+fn write_routes(c: &TestContext) {
+    c.chisel.write_unindent(
+        "routes/posts.ts",
+        r##"
+        import { BlogPost } from "../models/post.ts";
+
+        export default BlogPost.crud();
+        "##,
+    );
+}
+
+#[chisel_macros::test(modules = Deno, optimize = Yes)]
+pub async fn snake_case(c: TestContext) {
+    write_manifest(&c, true, "app_");
+    c.chisel.write_unindent(
+        "models/post.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class BlogPost extends ChiselEntity {
+            title: string = "";
+            createdAt: number = 0;
+        }
+        "##,
+    );
+    write_routes(&c);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post_json("/dev/posts", json!({"title": "hello", "createdAt": 1}))
+        .await;
+    let posts = c.chisel.get_json("/dev/posts?.title=hello").await;
+    assert_eq!(posts["results"][0]["createdAt"], json!(1));
+
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("chisel describe failed")
+        .stdout
+        .peek(r#"@column("created_at") createdAt: number"#);
+
+    // Turning the strategy off doesn't rename the columns that exist.
+    write_manifest(&c, false, "");
+    c.chisel.apply_ok().await;
+    let posts = c.chisel.get_json("/dev/posts").await;
+    assert_eq!(posts["results"][0]["createdAt"], json!(1));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn explicit_names(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/post.ts",
+        r##"
+        import { ChiselEntity, column, table } from "@chiselstrike/api";
+
+        @table("legacy_posts")
+        export class BlogPost extends ChiselEntity {
+            @column("post_title") title: string = "";
+        }
+        "##,
+    );
+    write_routes(&c);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post_json("/dev/posts", json!({"title": "hello"}))
+        .await;
+    let posts = c.chisel.get_json("/dev/posts").await;
+    assert_eq!(posts["results"][0]["title"], json!("hello"));
+
+    // The column of an existing field can't change.
+    c.chisel.write_unindent(
+        "models/post.ts",
+        r##"
+        import { ChiselEntity, column, table } from "@chiselstrike/api";
+
+        @table("legacy_posts")
+        export class BlogPost extends ChiselEntity {
+            @column("headline") title: string = "";
+        }
+        "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("changing the column of field title from post_title to headline");
+
+    // Nor can the table of an existing entity.
+    c.chisel.write_unindent(
+        "models/post.ts",
+        r##"
+        import { ChiselEntity, column, table } from "@chiselstrike/api";
+
+        @table("posts")
+        export class BlogPost extends ChiselEntity {
+            @column("post_title") title: string = "";
+        }
+        "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("the table of `BlogPost` can't change from `legacy_posts` to `posts`");
+}
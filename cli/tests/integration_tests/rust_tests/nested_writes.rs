@@ -0,0 +1,68 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn save_graph(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Country extends ChiselEntity {
+            name: string;
+        }
+
+        export class Company extends ChiselEntity {
+            name: string;
+            country: Country;
+        }
+
+        export class Contract extends ChiselEntity {
+            employer: Company;
+            client: Company;
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/contracts.ts",
+        r##"
+        import { Company, Contract, Country } from "../models/models.ts";
+
+        export default async function (req: Request) {
+            if (req.method == "GET") {
+                return {
+                    countries: (await Country.findAll()).length,
+                    companies: (await Company.findAll()).length,
+                };
+            }
+            const acme = Company.build({ name: "acme" });
+            acme.country = Country.build({ name: "Finland" });
+            const contract = new Contract();
+            contract.employer = acme;
+            contract.client = acme;
+            await contract.save();
+            return contract;
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+
+    let contract = c
+        .chisel
+        .post("/dev/contracts")
+        .send()
+        .await
+        .assert_ok()
+        .json();
+    let employer = &contract["employer"];
+    assert!(contract["id"].is_string());
+    assert!(employer["id"].is_string());
+    assert!(employer["country"]["id"].is_string());
+    assert_eq!(employer["id"], contract["client"]["id"]);
+    // The company nested twice is saved once.
+    assert_eq!(
+        c.chisel.get_json("/dev/contracts").await,
+        json!({"countries": 1, "companies": 1})
+    );
+}
@@ -0,0 +1,81 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use std::time::Duration;
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn notify_webhook(mut c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from '@chiselstrike/api';
+        export class Alert extends ChiselEntity {
+            event: string;
+            subject: string;
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/alerts.ts",
+        r##"
+        import { Alert } from '../models/models.ts';
+        export default async function (req: Request) {
+            if (req.method == 'POST') {
+                const { event, subject } = await req.json();
+                await Alert.create({ event, subject });
+                return 'ok';
+            }
+            const alerts = await Alert.findAll();
+            return alerts.map((a) => `${a.event} ${a.subject}`).sort();
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/busy.ts",
+        r##"
+        import { Alert } from '../models/models.ts';
+        export default async function (req: Request) {
+            for (let i = 0; i < 3; i++) {
+                await Alert.findOne({ event: 'none' });
+            }
+            return 'ok';
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel.write_unindent(
+        "chiseld.toml",
+        &format!(
+            r#"
+            query_budget = 2
+            notify_url = ["http://{}/dev/alerts"]
+            "#,
+            c.chisel.api_address
+        ),
+    );
+    c.restart_chiseld().await;
+
+    // Repeated events are only sent once.
+    for _ in 0..2 {
+        c.chisel.get("/dev/busy").send().await.assert_ok();
+    }
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from '@chiselstrike/api';
+        export class Alert extends ChiselEntity {
+            event: string;
+            subject: number;
+        }
+    "##,
+    );
+    c.chisel.apply_err().await;
+
+    // Wait for the notifications to be delivered.
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    assert_eq!(
+        c.chisel.get_json("/dev/alerts").await,
+        json!(["apply_failed dev", "query_budget_exceeded /dev/busy"])
+    );
+}
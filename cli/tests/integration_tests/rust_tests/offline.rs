@@ -0,0 +1,108 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn describe_snapshot(c: TestContext) {
+    c.chisel
+        .exec("describe", &["--offline"])
+        .await
+        .expect_err("describing offline without a snapshot should fail")
+        .stderr
+        .peek("No API snapshot of the server at");
+
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Book extends ChiselEntity {
+            title: string;
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel.describe_ok().await.stdout.peek("class Book");
+
+    // Offline, the API is the one described last, not the one applied.
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Book extends ChiselEntity {
+            title: string;
+        }
+        export class Author extends ChiselEntity {
+            name: string;
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+    let output = c
+        .chisel
+        .exec("describe", &["--offline"])
+        .await
+        .expect("chisel describe --offline failed");
+    output.stdout.peek("class Book");
+    assert!(!output.stdout.as_str().contains("class Author"));
+
+    c.chisel
+        .exec("generate", &["client", "--offline"])
+        .await
+        .expect("chisel generate client --offline failed")
+        .stdout
+        .peek("Client of version dev written to client/index.ts");
+    let source = std::fs::read_to_string(c.chisel.tmp_dir.path().join("client/index.ts")).unwrap();
+    assert!(source.contains("export interface Book {"));
+    assert!(!source.contains("export interface Author {"));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn lint_without_applying(c: TestContext) {
+    c.chisel.write_unindent(
+        "Chisel.toml",
+        r#"
+        models = ["models"]
+        routes = ["routes"]
+        policies = ["policies"]
+        modules = "deno"
+
+        [lint]
+        pii_labels = "error"
+    "#,
+    );
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, labels } from "@chiselstrike/api";
+        export class User extends ChiselEntity {
+            @labels("pii") email: string;
+        }
+    "##,
+    );
+    c.chisel
+        .exec("lint", &[])
+        .await
+        .expect("chisel lint failed")
+        .stdout
+        .peek("The project passes its checks");
+
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class User extends ChiselEntity {
+            email: string;
+        }
+    "##,
+    );
+    c.chisel
+        .exec("lint", &[])
+        .await
+        .expect_err("chisel lint should fail")
+        .stderr
+        .read("The models break the lint rules of Chisel.toml:")
+        .read("User.email looks like personal data, but has no label [pii_labels]");
+    // Nothing was applied.
+    let output = c.chisel.describe_ok().await;
+    assert!(!output.stdout.as_str().contains("class User"));
+}
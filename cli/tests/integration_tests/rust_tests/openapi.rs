@@ -0,0 +1,132 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn openapi_document(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from '@chiselstrike/api';
+        export class Author extends ChiselEntity {
+            name: string;
+        }
+        export class Book extends ChiselEntity {
+            title: string;
+            pages?: number;
+            published: Date;
+            status: "draft" | "out" = "draft";
+            author: Author;
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/books.ts",
+        r##"
+        import { Book } from '../models/models.ts';
+        export default Book.crud();
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/hello.ts",
+        r##"
+        export default async function (req: Request) {
+            return 'hello';
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+
+    let output = c
+        .chisel
+        .exec("describe", &["--format", "openapi"])
+        .await
+        .expect("describe failed");
+    let doc = output.stdout.json();
+    assert_eq!(doc["openapi"], json!("3.0.3"));
+    assert_eq!(
+        doc["components"]["schemas"]["dev.Book"],
+        json!({
+            "type": "object",
+            "properties": {
+                "id": {"type": "string", "readOnly": true},
+                "title": {"type": "string"},
+                "pages": {"type": "number"},
+                "published": {"type": "string", "format": "date-time"},
+                "status": {"type": "string", "enum": ["draft", "out"]},
+                "author": {"$ref": "#/components/schemas/dev.Author"},
+            },
+            "required": ["id", "title", "published", "author"],
+        })
+    );
+
+    let paths = &doc["paths"];
+    assert_eq!(paths["/dev/hello"], json!({}));
+    let books = &paths["/dev/books"];
+    assert_eq!(
+        books["post"]["requestBody"]["content"]["application/json"]["schema"],
+        json!({"$ref": "#/components/schemas/dev.Book"})
+    );
+    assert_eq!(
+        books["get"]["responses"]["200"]["content"]["application/json"]["schema"]["properties"]
+            ["results"]["items"],
+        json!({"$ref": "#/components/schemas/dev.Book"})
+    );
+    for method in ["get", "put", "patch", "delete"] {
+        assert!(paths["/dev/books/{id}"][method].is_object(), "{}", method);
+    }
+
+    c.chisel
+        .exec("describe", &["--format", "yaml"])
+        .await
+        .expect_err("describe with an unknown format should fail")
+        .stderr
+        .peek("format must be text or openapi");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn doc_comments(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from '@chiselstrike/api';
+        /** A blog post. */
+        export class Post extends ChiselEntity {
+            /**
+             * The title,
+             * shown in listings.
+             */
+            title: string;
+            // Not a doc comment.
+            body: string;
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec("describe", &[])
+        .await
+        .expect("describe failed")
+        .stdout
+        .read("/** A blog post. */")
+        .read("class Post")
+        .read("/**")
+        .read(" * The title,")
+        .read(" * shown in listings.")
+        .read(" */")
+        .read("title: string;");
+
+    let output = c
+        .chisel
+        .exec("describe", &["--format", "openapi"])
+        .await
+        .expect("describe failed");
+    let post = &output.stdout.json()["components"]["schemas"]["dev.Post"];
+    assert_eq!(post["description"], json!("A blog post."));
+    assert_eq!(
+        post["properties"]["title"],
+        json!({"type": "string", "description": "The title,\nshown in listings."})
+    );
+    assert_eq!(post["properties"]["body"], json!({"type": "string"}));
+}
@@ -0,0 +1,91 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use std::time::Duration;
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn deliver_after_commit(c: TestContext) {
+    c.chisel.write(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Order extends ChiselEntity {
+            item: string = "";
+        }
+
+        export class Delivery extends ChiselEntity {
+            item: string = "";
+        }"##,
+    );
+    c.chisel.write(
+        "routes/order.ts",
+        r##"
+        import { ChiselRequest, outbox } from "@chiselstrike/api";
+        import { Order } from "../models/models.ts";
+
+        export default async function chisel(req: ChiselRequest) {
+            const item = req.query.get("item")!;
+            await Order.create({ item });
+            await outbox.enqueue(req.query.get("hook")!, { item });
+            if (req.query.getBool("fail")) {
+                throw new Error("rolling back");
+            }
+            return "ok";
+        }"##,
+    );
+    c.chisel.write(
+        "routes/hook.ts",
+        r##"
+        import { Delivery } from "../models/models.ts";
+
+        export default async function chisel(req: Request) {
+            if (req.method == "POST") {
+                const { item } = await req.json();
+                await Delivery.create({ item });
+                return "ok";
+            }
+            return (await Delivery.findAll()).map(d => d.item);
+        }"##,
+    );
+    c.chisel.apply_ok().await;
+
+    let hook = format!("http://{}/dev/hook", c.chisel.api_address);
+    c.chisel
+        .post(&format!("/dev/order?item=apple&hook={hook}"))
+        .send()
+        .await
+        .assert_ok();
+    c.chisel
+        .post(&format!("/dev/order?item=pear&hook={hook}&fail=true"))
+        .send()
+        .await
+        .assert_status(500);
+
+    // Wait for the delivery task to pick up the message.
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert_eq!(c.chisel.get_json("/dev/hook").await, json!(["apple"]));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn reject_unsupported_target(c: TestContext) {
+    c.chisel.write(
+        "routes/enqueue.ts",
+        r##"
+        import { outbox } from "@chiselstrike/api";
+
+        export default async function chisel(req: Request) {
+            await outbox.enqueue("ftp://example.com/inbox", {});
+            return "ok";
+        }"##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post("/dev/enqueue")
+        .send()
+        .await
+        .assert_status(500)
+        .assert_text_contains("unsupported outbox target scheme `ftp`");
+}
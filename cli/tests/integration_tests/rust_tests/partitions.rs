@@ -0,0 +1,167 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static ROUTE_EVENTS: &str = r##"
+    import { Event, Session } from '../models/models.ts';
+    export default async function (req: Request) {
+        if (req.method == 'POST') {
+            const { name, at } = await req.json();
+            await Event.create({ name, at: at ? new Date(at) : new Date() });
+            await Session.create({ user: name });
+            return 'ok';
+        }
+        if (req.method == 'PUT') {
+            const { name, user } = await req.json();
+            const session = await Session.findOne({ user: name });
+            session!.user = user;
+            await session!.save();
+            return 'ok';
+        }
+        const events = (await Event.findAll()).map((e) => e.name);
+        const users = (await Session.findAll()).map((s) => s.user);
+        return { events: events.sort(), users: users.sort() };
+    }
+"##;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn partitioned_entities(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, partition } from '@chiselstrike/api';
+        @partition({ by: "time", field: "at", interval: "month", retention: 12 })
+        export class Event extends ChiselEntity {
+            name: string;
+            at: Date;
+        }
+        @partition({ by: "hash", field: "user", partitions: 4 })
+        export class Session extends ChiselEntity {
+            user: string;
+        }
+    "##,
+    );
+    c.chisel.write_unindent("routes/events.ts", ROUTE_EVENTS);
+    c.chisel.apply_ok().await;
+    c.chisel
+        .describe_ok()
+        .await
+        .stdout
+        .peek(r#"@partition({ by: "time", field: "at", interval: "month", retention: 12 })"#)
+        .peek(r#"@partition({ by: "hash", field: "user", partitions: 4 })"#);
+
+    // Rows outside of the partitions created so far go to the default one.
+    for (name, at) in [
+        ("now", json!(null)),
+        ("later", json!("2100-01-01T00:00:00.000Z")),
+    ] {
+        c.chisel
+            .post_json("/dev/events", json!({"name": name, "at": at}))
+            .await;
+    }
+    // Moves the session to another partition, which must not leave a copy
+    // behind.
+    c.chisel
+        .put("/dev/events")
+        .json(json!({"name": "later", "user": "renamed"}))
+        .send()
+        .await
+        .assert_status(200);
+    assert_eq!(
+        c.chisel.get_json("/dev/events").await,
+        json!({"events": ["later", "now"], "users": ["now", "renamed"]})
+    );
+
+    // Only the retention can change.
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, partition } from '@chiselstrike/api';
+        @partition({ by: "time", field: "at", interval: "month", retention: 24 })
+        export class Event extends ChiselEntity {
+            name: string;
+            at: Date;
+        }
+        @partition({ by: "hash", field: "user", partitions: 8 })
+        export class Session extends ChiselEntity {
+            user: string;
+        }
+    "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("changing how Session is partitioned. Incompatible change");
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, partition } from '@chiselstrike/api';
+        @partition({ by: "time", field: "at", interval: "month", retention: 24 })
+        export class Event extends ChiselEntity {
+            name: string;
+            at: Date;
+        }
+        @partition({ by: "hash", field: "user", partitions: 4 })
+        export class Session extends ChiselEntity {
+            user: string;
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .describe_ok()
+        .await
+        .stdout
+        .peek(r#"@partition({ by: "time", field: "at", interval: "month", retention: 24 })"#);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_partitions(c: TestContext) {
+    let models = |decorator: &str| {
+        format!(
+            r##"
+            import {{ ChiselEntity, partition, unique }} from '@chiselstrike/api';
+            {}
+            export class Event extends ChiselEntity {{
+                name: string;
+                at: Date;
+                note?: string;
+                @unique code: string = "";
+            }}
+        "##,
+            decorator
+        )
+    };
+
+    for (decorator, error) in [
+        (
+            r#"@partition({ by: "time", field: "missing", interval: "day" })"#,
+            "class Event is partitioned by unknown field `missing`",
+        ),
+        (
+            r#"@partition({ by: "time", field: "name", interval: "day" })"#,
+            "entity 'Event' is partitioned by time, but field 'name' isn't a Date",
+        ),
+        (
+            r#"@partition({ by: "time", field: "at", interval: "week" })"#,
+            "unknown partition interval 'week', expected day, month or year",
+        ),
+        (
+            r#"@partition({ by: "hash", field: "note", partitions: 2 })"#,
+            "entity 'Event' is partitioned by optional field 'note'",
+        ),
+        (
+            r#"@partition({ by: "hash", field: "name", partitions: 2 })"#,
+            "entity 'Event' is partitioned, so its field 'code' can't be @unique",
+        ),
+        (
+            r#"@partition({ by: "range", field: "name" })"#,
+            "@partition by 'range' is not supported, use time or hash",
+        ),
+    ] {
+        c.chisel
+            .write_unindent("models/models.ts", &models(decorator));
+        c.chisel.apply_err().await.stderr.peek(error);
+    }
+}
@@ -187,6 +187,72 @@ async fn transform_omit(c: TestContext) {
     );
 }
 
+#[self::test(modules = Deno, optimize = Both)]
+async fn transform_values(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/contact.ts",
+        r##"
+        import { ChiselEntity, labels } from "@chiselstrike/api";
+
+        export class Contact extends ChiselEntity {
+            @labels("pii") email: string = "";
+            @labels("digest") phone: string = "";
+            @labels("short") bio: string = "";
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/contacts.ts",
+        r##"
+        import { Contact } from "../models/contact.ts";
+        export default Contact.crud();
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/admin/contacts.ts",
+        r##"
+        import { Contact } from "../../models/contact.ts";
+        export default Contact.crud();
+        "##,
+    );
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+        labels:
+          - name: pii
+            transform: redact
+          - name: digest
+            transform: hash
+          - name: short
+            transform: truncate(3)
+        routes:
+          - path: /admin
+            transforms:
+              pii: mask-email
+        "##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json(
+            "/dev/contacts",
+            json!({"email": "jane@example.com", "phone": "555-1234", "bio": "hello"}),
+        )
+        .await;
+
+    let phone_hash = "24886b1e9942f612a3e4cdf5898f9b89987fe8cc6ba4ca6996ef1affa15cdf27";
+    json_is_subset(
+        &c.chisel.get_json("/dev/contacts").await,
+        &json!({"results": [{"email": null, "phone": phone_hash, "bio": "hel"}]}),
+    )
+    .unwrap();
+    // The transform of the route replaces the one of the label.
+    json_is_subset(
+        &c.chisel.get_json("/dev/admin/contacts").await,
+        &json!({"results": [{"email": "j***@example.com", "phone": phone_hash, "bio": "hel"}]}),
+    )
+    .unwrap();
+}
+
 #[self::test(modules = Deno, optimize = Both)]
 async fn transform_anonymize_related_entities(c: TestContext) {
     c.chisel.write_unindent(
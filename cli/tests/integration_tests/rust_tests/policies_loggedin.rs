@@ -341,6 +341,321 @@ async fn transform_match_login_related_entities(c: TestContext) {
         .assert_json(json!(["first blog post by al", "second blog post by al",]));
 }
 
+#[self::test(modules = Deno, optimize = Both)]
+async fn transform_match_login_lists(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/post.ts",
+        r##"
+        import { ChiselEntity, AuthUser, labels } from '@chiselstrike/api'
+        export class Post extends ChiselEntity {
+            text: string = "";
+            @labels("protect") author: AuthUser;
+        }
+        export class Feed extends ChiselEntity {
+            posts: Post[] = [];
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/feed.ts",
+        r##"
+        import { Feed, Post } from '../models/post.ts';
+        import { loggedInUser } from '@chiselstrike/api';
+        export default async function (req: Request) {
+            const feed = (await Feed.findOne({})) ?? Feed.build({});
+            if (req.method == 'POST') {
+                const author = (await loggedInUser())!;
+                feed.posts.push(await Post.create({ text: await req.text(), author }));
+                await feed.save();
+            } else {
+                return feed.posts.map((post) => post.text);
+            }
+        }
+        "##,
+    );
+    c.chisel
+        .write(".env", r#"{ "CHISELD_AUTH_SECRET": "dud" }"#);
+    c.chisel.apply_ok().await;
+
+    let id_al = store_user(&c.chisel, "Al", "al").await;
+    let id_als = store_user(&c.chisel, "Als", "als").await;
+    for (id, text) in [(&id_al, "post by al"), (&id_als, "post by als")] {
+        c.chisel
+            .post("/dev/feed")
+            .header("ChiselUID", id)
+            .body(text)
+            .send()
+            .await
+            .assert_ok();
+    }
+
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+            labels:
+            - name: protect
+              transform: match_login
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .get("/dev/feed")
+        .header("ChiselUID", &id_al)
+        .send()
+        .await
+        .assert_json(json!(["post by al"]));
+    c.chisel
+        .get("/dev/feed")
+        .header("ChiselUID", &id_als)
+        .send()
+        .await
+        .assert_json(json!(["post by als"]));
+}
+
+#[self::test(modules = Deno, optimize = Both)]
+async fn transform_match_login_unions(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/post.ts",
+        r##"
+        import { ChiselEntity, AuthUser, labels } from '@chiselstrike/api'
+        export class Post extends ChiselEntity {
+            text: string = "";
+            @labels("protect") author: AuthUser;
+        }
+        export class Photo extends ChiselEntity {
+            url: string = "";
+        }
+        export class Comment extends ChiselEntity {
+            text: string = "";
+            target?: Post | Photo;
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/comments.ts",
+        r##"
+        import { Comment, Post } from '../models/post.ts';
+        import { loggedInUser } from '@chiselstrike/api';
+        export default async function (req: Request) {
+            if (req.method == 'POST') {
+                const author = (await loggedInUser())!;
+                const text = await req.text();
+                const target = await Post.create({ text, author });
+                await Comment.create({ text: "on " + text, target });
+            } else {
+                return (await Comment.findAll()).map((comment) => ({
+                    text: comment.text,
+                    target: (comment.target as Post | undefined)?.text ?? null,
+                }));
+            }
+        }
+        "##,
+    );
+    c.chisel
+        .write(".env", r#"{ "CHISELD_AUTH_SECRET": "dud" }"#);
+    c.chisel.apply_ok().await;
+
+    let id_al = store_user(&c.chisel, "Al", "al").await;
+    let id_als = store_user(&c.chisel, "Als", "als").await;
+    for (id, text) in [(&id_al, "post by al"), (&id_als, "post by als")] {
+        c.chisel
+            .post("/dev/comments")
+            .header("ChiselUID", id)
+            .body(text)
+            .send()
+            .await
+            .assert_ok();
+    }
+
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+            labels:
+            - name: protect
+              transform: match_login
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .get("/dev/comments")
+        .header("ChiselUID", &id_al)
+        .send()
+        .await
+        .assert_json(json!([
+            {"text": "on post by al", "target": "post by al"},
+            {"text": "on post by als", "target": null},
+        ]));
+}
+
+#[self::test(modules = Deno, optimize = Both)]
+async fn row_policies(c: TestContext) {
+    c.chisel.write_unindent("models/post.ts", MODEL_POST);
+    c.chisel.write_unindent("routes/posts.ts", ROUTE_POSTS);
+    c.chisel.write_unindent("routes/blogs.ts", ROUTE_BLOGS);
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+            rows:
+            - match: Post
+              read: author == @user.id || text == "welcome"
+        "##,
+    );
+    c.chisel
+        .write(".env", r#"{ "CHISELD_AUTH_SECRET": "dud" }"#);
+    c.chisel.apply_ok().await;
+
+    let id_al = store_user(&c.chisel, "Al", "al").await;
+    let id_als = store_user(&c.chisel, "Als", "als").await;
+
+    store_post(&c.chisel, &id_al, "first post by al").await;
+    store_post(&c.chisel, &id_als, "first post by als").await;
+    store_post(&c.chisel, &id_als, "welcome").await;
+    store_blog_post(&c.chisel, &id_al, "blog post by al").await;
+    store_blog_post(&c.chisel, &id_als, "blog post by als").await;
+
+    c.chisel
+        .get("/dev/posts")
+        .header("ChiselUID", &id_al)
+        .send()
+        .await
+        .assert_json(json!([
+            {"text": "first post by al"},
+            {"text": "welcome"},
+            {"text": "blog post by al"},
+        ]));
+    // Anonymous requests only see what anyone can.
+    c.chisel
+        .get("/dev/posts")
+        .send()
+        .await
+        .assert_json(json!([{"text": "welcome"}]));
+    // Rows referring to rows the user can't read are filtered out too.
+    c.chisel
+        .get("/dev/blogs")
+        .header("ChiselUID", &id_als)
+        .send()
+        .await
+        .assert_json(json!(["blog post by als"]));
+}
+
+#[self::test(modules = Deno, optimize = Both)]
+async fn row_policies_related_objects(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/post.ts",
+        r##"
+        import { ChiselEntity, AuthUser } from '@chiselstrike/api'
+        export class Post extends ChiselEntity {
+            text: string = "";
+            author: AuthUser;
+        }
+        export class Photo extends ChiselEntity {
+            url: string = "";
+        }
+        export class Feed extends ChiselEntity {
+            posts: Post[] = [];
+            pinned?: Post | Photo;
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/feeds.ts",
+        r##"
+        import { Feed, Post } from '../models/post.ts';
+        import { loggedInUser } from '@chiselstrike/api';
+        export default async function (req: Request) {
+            if (req.method == 'POST') {
+                const author = (await loggedInUser())!;
+                const mine = await Post.create({ text: "mine", author });
+                const welcome = await Post.create({ text: "welcome", author });
+                await Feed.create({ posts: [mine, welcome], pinned: mine });
+                await Feed.create({ posts: [], pinned: welcome });
+            } else {
+                return (await Feed.findAll()).map((feed) => ({
+                    posts: feed.posts.map((post) => post.text),
+                    pinned: (feed.pinned as Post | undefined)?.text ?? null,
+                }));
+            }
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+            rows:
+            - match: Post
+              read: author == @user.id || text == "welcome"
+        "##,
+    );
+    c.chisel
+        .write(".env", r#"{ "CHISELD_AUTH_SECRET": "dud" }"#);
+    c.chisel.apply_ok().await;
+
+    let id_al = store_user(&c.chisel, "Al", "al").await;
+    let id_als = store_user(&c.chisel, "Als", "als").await;
+    c.chisel
+        .post("/dev/feeds")
+        .header("ChiselUID", &id_al)
+        .send()
+        .await
+        .assert_ok();
+
+    c.chisel
+        .get("/dev/feeds")
+        .header("ChiselUID", &id_al)
+        .send()
+        .await
+        .assert_json(json!([
+            {"posts": ["mine", "welcome"], "pinned": "mine"},
+            {"posts": [], "pinned": "welcome"},
+        ]));
+    // Related objects the user can't read are left out of lists and
+    // polymorphic relations alike.
+    c.chisel
+        .get("/dev/feeds")
+        .header("ChiselUID", &id_als)
+        .send()
+        .await
+        .assert_json(json!([
+            {"posts": ["welcome"], "pinned": null},
+            {"posts": [], "pinned": "welcome"},
+        ]));
+}
+
+#[self::test(modules = Deno)]
+async fn invalid_row_policies(c: TestContext) {
+    c.chisel.write_unindent("models/post.ts", MODEL_POST);
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+            rows:
+            - match: Posts
+              read: author == @user.id
+        "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("The policies are invalid:")
+        .read("policies/pol.yaml:2:10: row policy matches `Posts`, which is no entity");
+
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+            rows:
+            - match: Post
+              read: author = @user.id
+        "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("invalid read condition of Post `author = @user.id`: unexpected `=`");
+}
+
 // NOTE: we add the CORS headers to every response, even though it should be used only in
 // response to a CORS preflight request (using method OPTIONS)
 //
@@ -0,0 +1,74 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn report_affected_rows(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/person.ts",
+        r##"
+        import { ChiselEntity, labels } from "@chiselstrike/api";
+
+        export class Person extends ChiselEntity {
+            @labels("pii") name: string = "";
+            @labels("private") nickname?: string;
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r##"
+        import { Person } from "../models/person.ts";
+
+        export default Person.crud();
+        "##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json("/dev/people", json!({"name": "Alice", "nickname": "Al"}))
+        .await;
+    c.chisel
+        .post_json("/dev/people", json!({"name": "Bob", "nickname": "Bobby"}))
+        .await;
+    c.chisel
+        .post_json("/dev/people", json!({"name": "Carol"}))
+        .await;
+
+    c.chisel
+        .exec("policy-impact", &[])
+        .await
+        .expect("chisel policy-impact failed")
+        .stdout
+        .read("The policies would not affect any data");
+
+    // The proposed policies aren't applied.
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+        labels:
+          - name: pii
+            transform: anonymize
+          - name: private
+            transform: omit
+            except_uri: ^/dev/admin
+        "##,
+    );
+    c.chisel
+        .exec("policy-impact", &[])
+        .await
+        .expect("chisel policy-impact failed")
+        .stdout
+        .read("Person: sampled 3 of 3 rows")
+        .read("  name: none -> anonymize (pii), 3 rows affected")
+        .read("  nickname: none -> omit (private) except for ^/dev/admin, 2 rows affected");
+    c.chisel
+        .exec("policy-impact", &["--sample", "1"])
+        .await
+        .expect("chisel policy-impact failed")
+        .stdout
+        .read("Person: sampled 1 of 3 rows");
+    assert_eq!(
+        c.chisel.get_json("/dev/people").await["results"][0]["name"],
+        json!("Alice")
+    );
+}
@@ -0,0 +1,58 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalid_policies(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/person.ts",
+        r##"
+        import { ChiselEntity, labels } from "@chiselstrike/api";
+
+        export class Person extends ChiselEntity {
+            @labels("pii") name: string = "";
+        }
+        "##,
+    );
+
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+        labels:
+          - name: pii
+            transfrom: anonymize
+          - name: pii
+            transform: hide
+        routes:
+          - path: /people
+            users: ^admin$
+          - path: /people
+            users: ^root$
+        "##,
+    );
+    let mut output = c.chisel.apply_err().await;
+    output
+        .stderr
+        .read("The policies are invalid:")
+        .read("policies/pol.yaml:3:5: unknown key `transfrom` in a label, expected one of: name, transform, except_uri")
+        .read("policies/pol.yaml:5:16: unknown transform `hide`, expected one of: anonymize, redact, hash, truncate(<length>), mask-email, omit, match_login, match_tenant")
+        .read("policies/pol.yaml:4:5: label `pii` is already defined at policies/pol.yaml:2:5")
+        .read("policies/pol.yaml:10:12: users of route /people is already configured at policies/pol.yaml:8:12");
+
+    // Labels no field has are only warned about.
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+        labels:
+          - name: pii
+            transform: anonymize
+          - name: secret
+            transform: omit
+        "##,
+    );
+    c.chisel
+        .apply_ok()
+        .await
+        .stdout
+        .read("Warning: policies/pol.yaml:4:5: label `secret` is used by no field, so its policy has no effect");
+}
@@ -0,0 +1,136 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static MODELS: &str = r##"
+    import { ChiselEntity } from "@chiselstrike/api";
+
+    export class Post extends ChiselEntity {
+        title: string = "";
+    }
+
+    export class Photo extends ChiselEntity {
+        url: string = "";
+        rating: number = 0;
+    }
+
+    export class Comment extends ChiselEntity {
+        text: string = "";
+        target?: Post | Photo;
+    }
+"##;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn comments(c: TestContext) {
+    c.chisel.write_unindent("models/types.ts", MODELS);
+    c.chisel.write_unindent(
+        "routes/store.ts",
+        r##"
+        import { Comment, Photo, Post } from "../models/types.ts";
+
+        export default async function (req: Request) {
+            const post = Post.build({ title: "Hello" });
+            await Comment.build({ text: "a", target: post }).save();
+            await Comment.build({ text: "b", target: post }).save();
+            const photo = Photo.build({ url: "cat.png", rating: 4.5 });
+            await Comment.create({ text: "c", target: photo });
+            await Comment.create({ text: "d" });
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/retarget.ts",
+        r##"
+        import { Comment, Photo } from "../models/types.ts";
+
+        export default async function (req: Request) {
+            const comment = await Comment.findOne({ text: "b" });
+            const photo = await Photo.findOne({ url: "cat.png" });
+            await Comment.update(comment!.id!, { target: photo! });
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/comments.ts",
+        r##"
+        import { Comment } from "../models/types.ts";
+
+        export default Comment.crud();
+        "##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel.post_json("/dev/store", json!({})).await;
+
+    let targets = |comments: serde_json::Value| -> Vec<serde_json::Value> {
+        comments["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| {
+                let mut target = c["target"].clone();
+                if let Some(target) = target.as_object_mut() {
+                    assert!(target.remove("id").unwrap().is_string());
+                }
+                json!([c["text"], target])
+            })
+            .collect()
+    };
+    assert_eq!(
+        targets(c.chisel.get_json("/dev/comments?sort=text").await),
+        vec![
+            json!(["a", {"__type": "Post", "title": "Hello"}]),
+            json!(["b", {"__type": "Post", "title": "Hello"}]),
+            json!(["c", {"__type": "Photo", "url": "cat.png", "rating": 4.5}]),
+            json!(["d", null]),
+        ]
+    );
+
+    c.chisel.post_json("/dev/retarget", json!({})).await;
+    c.chisel
+        .post_json(
+            "/dev/comments",
+            json!({"text": "e", "target": {"__type": "Post", "title": "Bye"}}),
+        )
+        .await;
+    assert_eq!(
+        targets(c.chisel.get_json("/dev/comments?sort=text").await)[1..],
+        vec![
+            json!(["b", {"__type": "Photo", "url": "cat.png", "rating": 4.5}]),
+            json!(["c", {"__type": "Photo", "url": "cat.png", "rating": 4.5}]),
+            json!(["d", null]),
+            json!(["e", {"__type": "Post", "title": "Bye"}]),
+        ]
+    );
+
+    // The object doesn't say which entity it is of.
+    c.chisel
+        .post("/dev/comments")
+        .json(json!({"text": "f", "target": {"title": "Who?"}}))
+        .send()
+        .await
+        .assert_status(500);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn unknown_member(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/types.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Post extends ChiselEntity {
+            title: string = "";
+        }
+
+        export class Comment extends ChiselEntity {
+            text: string = "";
+            target?: Post | Video;
+        }
+        "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("field 'target' in class 'Comment' refers to 'Video', which is not an entity of the project");
+}
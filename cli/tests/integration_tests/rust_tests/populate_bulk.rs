@@ -0,0 +1,55 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static MODELS: &str = r##"
+    import { ChiselEntity } from '@chiselstrike/api';
+    export class Item extends ChiselEntity {
+        name: string;
+        note?: string;
+        count: number;
+        active: boolean;
+    }
+"##;
+
+static ROUTE_ITEMS: &str = r##"
+    import { Item } from '../models/models.ts';
+    export default async function (req: Request) {
+        if (req.method == 'POST') {
+            const n = await req.json();
+            for (let i = 0; i < n; i++) {
+                const note = i % 3 == 0 ? undefined : `"quoted", ${i}\nand more`;
+                await Item.create({ name: `item ${i}`, note, count: i / 2, active: i % 2 == 0 });
+            }
+            return 'ok';
+        }
+        const items = await Item.findMany({});
+        items.sort((a, b) => a.count - b.count);
+        return items.map((i) => ({ name: i.name, note: i.note ?? null, count: i.count, active: i.active }));
+    }
+"##;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn populate_many_rows(c: TestContext) {
+    c.chisel.write_unindent("models/models.ts", MODELS);
+    c.chisel.write_unindent("routes/items.ts", ROUTE_ITEMS);
+    c.chisel.apply_ok().await;
+    c.chisel
+        .exec("apply", &["--version", "staging"])
+        .await
+        .expect("chisel apply --version staging failed");
+    c.chisel.post_json("/dev/items", json!(1200)).await;
+    let items = c.chisel.get_json("/dev/items").await;
+    assert_eq!(items.as_array().unwrap().len(), 1200);
+    assert_eq!(items[1]["note"], json!("\"quoted\", 1\nand more"));
+    assert_eq!(items[3]["note"], json!(null));
+
+    // Populating again upserts the same rows.
+    for _ in 0..2 {
+        c.chisel
+            .exec("populate", &["--version", "staging", "--from", "dev"])
+            .await
+            .expect("chisel populate failed");
+        assert_eq!(c.chisel.get_json("/staging/items").await, items);
+    }
+}
@@ -0,0 +1,110 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static MODELS: &str = r##"
+    import { ChiselEntity } from '@chiselstrike/api';
+    export class Person extends ChiselEntity {
+        name: string;
+        updatedAt: number;
+    }
+    export class Pet extends ChiselEntity {
+        name: string;
+    }
+"##;
+
+async fn names(chisel: &Chisel, url: &str) -> Vec<String> {
+    let json = chisel.get_json(url).await;
+    let mut names: Vec<String> = json["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|person| person["name"].as_str().unwrap().to_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+async fn populate(chisel: &Chisel) {
+    chisel
+        .exec(
+            "populate",
+            &[
+                "--version",
+                "staging",
+                "--from",
+                "dev",
+                "--changed-field",
+                "updatedAt",
+            ],
+        )
+        .await
+        .expect("chisel populate failed");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn copies_changes(c: TestContext) {
+    c.chisel.write_unindent("models/models.ts", MODELS);
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r##"
+        import { Person } from '../models/models.ts';
+        import { crud } from '@chiselstrike/api';
+        export default crud(Person, '');
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/pets.ts",
+        r##"
+        import { Pet } from '../models/models.ts';
+        import { crud } from '@chiselstrike/api';
+        export default crud(Pet, '');
+        "##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .exec("apply", &["--version", "staging"])
+        .await
+        .expect("chisel apply --version staging failed");
+
+    c.chisel
+        .post_json("/dev/people", json!({"name": "Al", "updatedAt": 1}))
+        .await;
+    c.chisel
+        .post_json("/dev/people", json!({"name": "Bo", "updatedAt": 2}))
+        .await;
+    c.chisel
+        .post_json("/dev/pets", json!({"name": "Rex"}))
+        .await;
+
+    // The first run copies everything.
+    populate(&c.chisel).await;
+    assert_eq!(names(&c.chisel, "/staging/people").await, ["Al", "Bo"]);
+    assert_eq!(names(&c.chisel, "/staging/pets").await, ["Rex"]);
+
+    // Change the copy of Al, to tell whether it gets copied again.
+    let people = c.chisel.get_json("/staging/people?.name=Al").await;
+    let id = people["results"][0]["id"].as_str().unwrap().to_owned();
+    c.chisel
+        .put(&format!("/staging/people/{}", id))
+        .json(json!({"name": "Changed", "updatedAt": 1}))
+        .send()
+        .await
+        .assert_ok();
+
+    c.chisel
+        .post_json("/dev/people", json!({"name": "Cy", "updatedAt": 3}))
+        .await;
+    c.chisel
+        .post_json("/dev/pets", json!({"name": "Tom"}))
+        .await;
+
+    // Only Bo, at the last mark, and Cy are copied. Pets have no updatedAt
+    // so they are all copied again.
+    populate(&c.chisel).await;
+    assert_eq!(
+        names(&c.chisel, "/staging/people").await,
+        ["Bo", "Changed", "Cy"]
+    );
+    assert_eq!(names(&c.chisel, "/staging/pets").await, ["Rex", "Tom"]);
+}
@@ -0,0 +1,111 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static MODELS: &str = r##"
+    import { ChiselEntity } from '@chiselstrike/api';
+    export class Employee extends ChiselEntity {
+        name: string;
+    }
+    export class Company extends ChiselEntity {
+        name: string;
+        ceo: Employee;
+    }
+"##;
+
+static ROUTE_COMPANIES: &str = r##"
+    import { Company, Employee } from '../models/models.ts';
+    export default async function (req: Request) {
+        if (req.method == 'POST') {
+            const ceo = Employee.build({ name: 'Adalbrecht' });
+            await Company.create({ name: 'Bananas inc.', ceo });
+            return 'ok';
+        }
+        const companies = await Company.findMany({});
+        return companies.map((c) => ({ id: c.id, ceo: c.ceo.name, ceoId: c.ceo.id }));
+    }
+"##;
+
+async fn setup(chisel: &Chisel) -> serde_json::Value {
+    chisel.write_unindent("models/models.ts", MODELS);
+    chisel.write_unindent("routes/companies.ts", ROUTE_COMPANIES);
+    chisel.apply_ok().await;
+    chisel
+        .exec("apply", &["--version", "staging"])
+        .await
+        .expect("chisel apply --version staging failed");
+    chisel.post("/dev/companies").send().await.assert_ok();
+    let companies = chisel.get_json("/dev/companies").await;
+    companies[0].clone()
+}
+
+async fn populate(chisel: &Chisel, ids: &str) -> Vec<serde_json::Value> {
+    chisel
+        .exec(
+            "populate",
+            &["--version", "staging", "--from", "dev", "--ids", ids],
+        )
+        .await
+        .expect("chisel populate failed");
+    let companies = chisel.get_json("/staging/companies").await;
+    companies.as_array().unwrap().clone()
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn preserve(c: TestContext) {
+    let original = setup(&c.chisel).await;
+    assert_eq!(
+        populate(&c.chisel, "preserve").await,
+        vec![original.clone()]
+    );
+    assert_eq!(populate(&c.chisel, "preserve").await, vec![original]);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn remap(c: TestContext) {
+    let original = setup(&c.chisel).await;
+    let companies = populate(&c.chisel, "remap").await;
+    assert_eq!(companies.len(), 1);
+    let copy = &companies[0];
+    assert_ne!(copy["id"], original["id"]);
+    assert_ne!(copy["ceoId"], original["ceoId"]);
+    assert_eq!(copy["ceo"], json!("Adalbrecht"));
+
+    // Populating again maps to the same IDs.
+    assert_eq!(populate(&c.chisel, "remap").await, companies);
+
+    // Another namespace gives other IDs.
+    c.chisel
+        .exec(
+            "populate",
+            &[
+                "--version",
+                "staging",
+                "--from",
+                "dev",
+                "--ids",
+                "remap",
+                "--id-namespace",
+                "other",
+            ],
+        )
+        .await
+        .expect("chisel populate failed");
+    let companies = c.chisel.get_json("/staging/companies").await;
+    assert_eq!(companies.as_array().unwrap().len(), 2);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn regenerate(c: TestContext) {
+    let original = setup(&c.chisel).await;
+    let companies = populate(&c.chisel, "regenerate").await;
+    assert_eq!(companies.len(), 1);
+    assert_ne!(companies[0]["id"], original["id"]);
+    assert_eq!(companies[0]["ceo"], json!("Adalbrecht"));
+
+    let companies = populate(&c.chisel, "regenerate").await;
+    assert_eq!(companies.len(), 2);
+    for company in companies {
+        assert_eq!(company["ceo"], json!("Adalbrecht"));
+    }
+}
@@ -0,0 +1,169 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+use std::time::Duration;
+
+static MODELS: &str = r##"
+    import { ChiselEntity, AuthUser } from '@chiselstrike/api'
+    export class Post extends ChiselEntity {
+        text: string;
+        author: AuthUser;
+    }
+    export class Comment extends ChiselEntity {
+        text: string;
+        post: Post;
+        author: AuthUser;
+    }
+"##;
+
+static ROUTE_POSTS: &str = r##"
+    import { Post, Comment } from '../models/models.ts';
+    import { loggedInUser } from '@chiselstrike/api';
+    export default async function (req: Request) {
+        const author = await loggedInUser();
+        if (author === undefined) return new Response('Must be logged in', {status: 401});
+        const { text, on } = await req.json();
+        const post = await Post.create({ text, author });
+        const commented = on == null ? post : await Post.findOne({ text: on });
+        await Comment.create({ text: `${author.name} on ${commented!.text}`, post: commented!, author });
+        return 'ok';
+    }
+"##;
+
+async fn store_user(chisel: &Chisel, name: &str, email: &str) -> String {
+    let user_json = chisel
+        .post("/__chiselstrike/auth/users")
+        .header("ChiselAuth", "dud")
+        .json(json!({"name": name, "email": email}))
+        .send()
+        .await
+        .json();
+
+    user_json["id"].as_str().unwrap().into()
+}
+
+/// Stores a post by user `uid`, who comments on the post `on`, or on their own.
+async fn store_post(chisel: &Chisel, uid: &str, text: &str, on: Option<&str>) {
+    chisel
+        .post("/dev/posts")
+        .header("ChiselUID", uid)
+        .json(json!({ "text": text, "on": on }))
+        .send()
+        .await
+        .assert_ok();
+}
+
+async fn setup(c: &TestContext) -> (String, String) {
+    c.chisel.write_unindent("models/models.ts", MODELS);
+    c.chisel.write_unindent("routes/posts.ts", ROUTE_POSTS);
+    c.chisel
+        .write(".env", r#"{ "CHISELD_AUTH_SECRET": "dud" }"#);
+    c.chisel.apply_ok().await;
+
+    let id_al = store_user(&c.chisel, "Al", "al@example.com").await;
+    let id_bo = store_user(&c.chisel, "Bo", "bo@example.com").await;
+    store_post(&c.chisel, &id_al, "post by al", None).await;
+    store_post(&c.chisel, &id_bo, "post by bo", Some("post by al")).await;
+    (id_al, id_bo)
+}
+
+async fn export_user(chisel: &Chisel, uid: &str) -> serde_json::Value {
+    chisel
+        .exec("privacy", &["export", "--user", uid])
+        .await
+        .expect("chisel privacy export failed")
+        .stdout
+        .json()
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn export(c: TestContext) {
+    let (id_al, _) = setup(&c).await;
+
+    let data = export_user(&c.chisel, &id_al).await;
+    assert_eq!(data["dev"]["Post"].as_array().unwrap().len(), 1);
+    assert_eq!(data["dev"]["Post"][0]["text"], json!("post by al"));
+    // Bo's comment on Al's post is Bo's data, not Al's.
+    assert_eq!(data["dev"]["Comment"].as_array().unwrap().len(), 1);
+    assert_eq!(data["dev"]["Comment"][0]["text"], json!("Al on post by al"));
+    assert_eq!(
+        data["__chiselstrike"]["AuthUser"][0]["email"],
+        json!("al@example.com")
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn erase(mut c: TestContext) {
+    let (id_al, id_bo) = setup(&c).await;
+    c.chisel.write_unindent(
+        "models/drafts.ts",
+        r##"
+        import { ChiselEntity, AuthUser, archive } from '@chiselstrike/api'
+        @archive({ field: "savedAt", afterDays: 30 })
+        export class Draft extends ChiselEntity {
+            text: string;
+            savedAt: Date;
+            author: AuthUser;
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/drafts.ts",
+        r##"
+        import { Draft } from '../models/drafts.ts';
+        import { loggedInUser } from '@chiselstrike/api';
+        export default async function (req: Request) {
+            if (req.method == 'POST') {
+                const author = (await loggedInUser())!;
+                await Draft.create({ text: await req.text(), savedAt: new Date(0), author });
+                return 'ok';
+            }
+            return (await Draft.cursor().withArchived().toArray()).map((d) => d.text);
+        }
+        "##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post("/dev/drafts")
+        .header("ChiselUID", &id_al)
+        .body("draft by al")
+        .send()
+        .await
+        .assert_ok();
+    // Rows are archived when chiseld starts.
+    c.restart_chiseld().await;
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    assert_eq!(
+        c.chisel.get_json("/dev/drafts").await,
+        json!(["draft by al"])
+    );
+
+    c.chisel
+        .exec("privacy", &["erase", "--user", &id_al])
+        .await
+        .expect("chisel privacy erase failed")
+        .stdout
+        .read(&format!("User {} erased, 4 rows deleted", id_al));
+
+    assert_eq!(export_user(&c.chisel, &id_al).await, json!({}));
+    let data = export_user(&c.chisel, &id_bo).await;
+    assert_eq!(data["dev"]["Comment"][0]["text"], json!("Bo on post by al"));
+    // The archived rows of the user are erased too.
+    assert_eq!(c.chisel.get_json("/dev/drafts").await, json!([]));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn anonymize(c: TestContext) {
+    let (id_al, _) = setup(&c).await;
+
+    c.chisel
+        .exec("privacy", &["erase", "--user", &id_al, "--anonymize"])
+        .await
+        .expect("chisel privacy erase --anonymize failed")
+        .stdout
+        .read(&format!("User {} anonymized, 0 rows deleted", id_al));
+
+    let data = export_user(&c.chisel, &id_al).await;
+    assert_eq!(data["dev"]["Post"][0]["text"], json!("post by al"));
+    assert_eq!(data["__chiselstrike"]["AuthUser"][0]["email"], json!(null));
+}
@@ -0,0 +1,67 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn profile_request(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/person.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Person extends ChiselEntity {
+            name: string = "";
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r##"
+        import { Person } from "../models/person.ts";
+
+        export default async function () {
+            await Person.build({ name: "Al" }).save();
+            const people = await Person.findMany({});
+            return people.length;
+        }
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec("profile", &[])
+        .await
+        .expect("chisel profile failed")
+        .stdout
+        .read("No requests were profiled");
+
+    let response = c
+        .chisel
+        .get("/dev/people")
+        .header("ChiselProfile", "1")
+        .send()
+        .await;
+    response.assert_text("1");
+    let timing = response.header("server-timing");
+    assert!(timing.starts_with("js;dur="), "{}", timing);
+    assert!(timing.contains("sql;dur="), "{}", timing);
+    assert!(timing.contains("desc=\"2 statements\""), "{}", timing);
+    assert!(timing.contains("desc=\"0 calls\""), "{}", timing);
+
+    c.chisel
+        .exec("profile", &["/people"])
+        .await
+        .expect("chisel profile failed")
+        .stdout
+        .read("GET /dev/people")
+        .peek("ms  js")
+        .peek("ms  sql")
+        .peek("1x INSERT INTO")
+        .peek("1x SELECT");
+    c.chisel
+        .exec("profile", &["/other"])
+        .await
+        .expect("chisel profile failed")
+        .stdout
+        .read("No requests were profiled");
+}
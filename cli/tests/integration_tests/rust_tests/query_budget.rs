@@ -0,0 +1,81 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static MODELS: &str = r##"
+    import { ChiselEntity } from '@chiselstrike/api';
+    export class Person extends ChiselEntity {
+        name: string;
+    }
+"##;
+
+// Looks up each person on its own, the way an N+1 pattern does.
+static ROUTE_PEOPLE: &str = r##"
+    import { Person } from '../models/models.ts';
+    export default async function (req: Request) {
+        if (req.method == 'POST') {
+            for (const name of ['Al', 'Bo', 'Cy']) {
+                await Person.create({ name });
+            }
+            return 'ok';
+        }
+        const names = [];
+        for (const { id } of await Person.findMany({})) {
+            const person = await Person.findOne({ id });
+            names.push(person!.name);
+        }
+        return names.sort().join(',');
+    }
+"##;
+
+async fn setup(c: &mut TestContext, config: &str) {
+    c.chisel.write_unindent("models/models.ts", MODELS);
+    c.chisel.write_unindent("routes/people.ts", ROUTE_PEOPLE);
+    c.chisel.apply_ok().await;
+    c.chisel.post("/dev/people").send().await.assert_ok();
+    c.chisel.write_unindent("chiseld.toml", config);
+    c.restart_chiseld().await;
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn within_budget(mut c: TestContext) {
+    setup(
+        &mut c,
+        r#"
+        query_budget = 4
+        enforce_query_budget = true
+        "#,
+    )
+    .await;
+    c.chisel
+        .get("/dev/people")
+        .send()
+        .await
+        .assert_text("Al,Bo,Cy");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn over_budget(mut c: TestContext) {
+    setup(&mut c, "query_budget = 2").await;
+    // Without enforcement, the request is only reported.
+    c.chisel
+        .get("/dev/people")
+        .send()
+        .await
+        .assert_text("Al,Bo,Cy");
+
+    c.chisel.write_unindent(
+        "chiseld.toml",
+        r#"
+        query_budget = 2
+        enforce_query_budget = true
+        "#,
+    );
+    c.restart_chiseld().await;
+    c.chisel
+        .get("/dev/people")
+        .send()
+        .await
+        .assert_status(500)
+        .assert_text_contains("/dev/people issued 3 SQL statements, over the query budget of 2");
+}
@@ -0,0 +1,129 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static MODELS: &str = r##"
+    import { ChiselEntity } from '@chiselstrike/api';
+    export class Person extends ChiselEntity {
+        name: string;
+    }
+    export class Pet extends ChiselEntity {
+        name: string;
+    }
+"##;
+
+fn write_crud(chisel: &Chisel) {
+    chisel.write_unindent("models/models.ts", MODELS);
+    chisel.write_unindent(
+        "routes/people.ts",
+        r##"
+        import { Person } from '../models/models.ts';
+        import { crud } from '@chiselstrike/api';
+        export default crud(Person, '');
+        "##,
+    );
+    chisel.write_unindent(
+        "routes/pets.ts",
+        r##"
+        import { Pet } from '../models/models.ts';
+        import { crud } from '@chiselstrike/api';
+        export default crud(Pet, '');
+        "##,
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn version(c: TestContext) {
+    write_crud(&c.chisel);
+    c.chisel.apply_ok().await;
+    c.chisel
+        .post_json("/dev/people", json!({"name": "Al"}))
+        .await;
+
+    c.chisel
+        .exec("read-only", &["start", "--duration", "600"])
+        .await
+        .expect("chisel read-only start failed")
+        .stdout
+        .read("Version dev read-only for 600s");
+    c.chisel
+        .exec("read-only", &["status"])
+        .await
+        .expect("chisel read-only status failed")
+        .stdout
+        .read("dev: all entities");
+
+    let response = c
+        .chisel
+        .post("/dev/people")
+        .json(json!({"name": "Bo"}))
+        .send()
+        .await;
+    response.assert_status(503);
+    let retry_after: u64 = response.header("retry-after").parse().unwrap();
+    assert!(retry_after > 0 && retry_after <= 600);
+    c.chisel
+        .delete("/dev/people?.name=Al")
+        .send()
+        .await
+        .assert_status(503);
+
+    // Reads still work, and the rejected writes were rolled back.
+    let people = c.chisel.get_json("/dev/people").await;
+    assert_eq!(people["results"].as_array().unwrap().len(), 1);
+
+    c.chisel
+        .exec("read-only", &["stop"])
+        .await
+        .expect("chisel read-only stop failed")
+        .stdout
+        .read("Version dev writable");
+    c.chisel
+        .post("/dev/people")
+        .json(json!({"name": "Bo"}))
+        .send()
+        .await
+        .assert_ok();
+    c.chisel
+        .exec("read-only", &["stop"])
+        .await
+        .expect_err("chisel read-only stop of a writable version succeeded");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn entities(c: TestContext) {
+    write_crud(&c.chisel);
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec("read-only", &["start", "--entity", "Pet"])
+        .await
+        .expect("chisel read-only start failed")
+        .stdout
+        .read("Pet of version dev read-only");
+    c.chisel
+        .exec("read-only", &["status"])
+        .await
+        .expect("chisel read-only status failed")
+        .stdout
+        .read("dev: Pet");
+
+    c.chisel
+        .post("/dev/pets")
+        .json(json!({"name": "Rex"}))
+        .send()
+        .await
+        .assert_status(503)
+        .header("retry-after");
+    c.chisel
+        .post("/dev/people")
+        .json(json!({"name": "Al"}))
+        .send()
+        .await
+        .assert_ok();
+
+    c.chisel
+        .exec("read-only", &["start", "--entity", "Cat"])
+        .await
+        .expect_err("chisel read-only start of an unknown entity succeeded");
+}
@@ -0,0 +1,208 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static MODELS: &str = r##"
+    import { ChiselEntity } from '@chiselstrike/api';
+    export class Person extends ChiselEntity {
+        name: string;
+    }
+"##;
+
+// Counts its invocations, so that a response tells whether it was cached.
+static ROUTE_COUNTER: &str = r##"
+    import { Person } from '../models/models.ts';
+    let calls = 0;
+    export default async function (req: Request) {
+        if (req.method == 'POST') {
+            await Person.create({ name: 'Al' });
+            return 'ok';
+        }
+        calls += 1;
+        const url = new URL(req.url);
+        return new Response(`${calls}`, {
+            headers: { 'Cache-Control': url.searchParams.get('cache') ?? 'no-store' },
+        });
+    }
+"##;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn cached(c: TestContext) {
+    c.chisel.write_unindent("models/models.ts", MODELS);
+    c.chisel.write_unindent("routes/counter.ts", ROUTE_COUNTER);
+    c.chisel.apply_ok().await;
+
+    let url = "/dev/counter?cache=public,s-maxage=60";
+    c.chisel.get(url).send().await.assert_text("1");
+    let response = c.chisel.get(url).send().await;
+    response.assert_text("1");
+    response.header("age");
+
+    // Other query strings are cached separately.
+    c.chisel
+        .get("/dev/counter?cache=s-maxage=60")
+        .send()
+        .await
+        .assert_text("2");
+    c.chisel
+        .get("/dev/counter?cache=s-maxage=60")
+        .send()
+        .await
+        .assert_text("2");
+
+    // A request can skip the cache, and its response replaces the cached one.
+    c.chisel
+        .get(url)
+        .header("Cache-Control", "no-cache")
+        .send()
+        .await
+        .assert_text("3");
+    c.chisel.get(url).send().await.assert_text("3");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn not_cached(c: TestContext) {
+    c.chisel.write_unindent("models/models.ts", MODELS);
+    c.chisel.write_unindent("routes/counter.ts", ROUTE_COUNTER);
+    c.chisel.apply_ok().await;
+
+    c.chisel.get("/dev/counter").send().await.assert_text("1");
+    c.chisel.get("/dev/counter").send().await.assert_text("2");
+    // max-age alone is meant for private caches.
+    let url = "/dev/counter?cache=max-age=60";
+    c.chisel.get(url).send().await.assert_text("3");
+    c.chisel.get(url).send().await.assert_text("4");
+    let url = "/dev/counter?cache=private,s-maxage=60";
+    c.chisel.get(url).send().await.assert_text("5");
+    c.chisel.get(url).send().await.assert_text("6");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn invalidated_by_writes(c: TestContext) {
+    c.chisel.write_unindent("models/models.ts", MODELS);
+    c.chisel.write_unindent("routes/counter.ts", ROUTE_COUNTER);
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r##"
+        import { Person } from '../models/models.ts';
+        import { crud } from '@chiselstrike/api';
+        export default crud(Person, '', { cacheControl: 'public, s-maxage=60' });
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    let response = c.chisel.get("/dev/people").send().await;
+    assert_eq!(response.header("cache-control"), "public, s-maxage=60");
+    assert_eq!(response.json()["results"], json!([]));
+
+    let url = "/dev/counter?cache=public,s-maxage=60";
+    c.chisel.get(url).send().await.assert_text("1");
+    c.chisel.get(url).send().await.assert_text("1");
+
+    c.chisel.post("/dev/counter").send().await.assert_ok();
+    c.chisel.get(url).send().await.assert_text("2");
+    let people = c.chisel.get("/dev/people").send().await.json();
+    assert_eq!(people["results"][0]["name"], json!("Al"));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn entity_hints(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, cache } from '@chiselstrike/api';
+        @cache({ ttl: 60 })
+        export class Person extends ChiselEntity {
+            name: string;
+        }
+        export class Note extends ChiselEntity {
+            text: string;
+        }
+        "##,
+    );
+    c.chisel.write_unindent("routes/counter.ts", ROUTE_COUNTER);
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r##"
+        import { Note, Person } from '../models/models.ts';
+        let calls = 0;
+        export default async function (req: Request) {
+            calls += 1;
+            const entity = new URL(req.url).searchParams.get('entity');
+            const rows = entity === 'note' ? await Note.findAll() : await Person.findAll();
+            return `${calls} ${rows.length}`;
+        }
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel.get("/dev/people").send().await.assert_text("1 0");
+    c.chisel.get("/dev/people").send().await.assert_text("1 0");
+    c.chisel
+        .get("/dev/people")
+        .header("ChiselConsistency", "strong")
+        .send()
+        .await
+        .assert_text("2 0");
+
+    // Writes are visible right away.
+    c.chisel.post("/dev/counter").send().await.assert_ok();
+    c.chisel.get("/dev/people").send().await.assert_text("3 1");
+    c.chisel.get("/dev/people").send().await.assert_text("3 1");
+
+    // Responses that read entities without hints are not cached.
+    let url = "/dev/people?entity=note";
+    c.chisel.get(url).send().await.assert_text("4 0");
+    c.chisel.get(url).send().await.assert_text("5 0");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn not_cached_for_users_and_tenants(c: TestContext) {
+    c.chisel.write_unindent("models/models.ts", MODELS);
+    c.chisel.write_unindent("routes/counter.ts", ROUTE_COUNTER);
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+        tenant:
+          header: X-Tenant
+        "##,
+    );
+    c.chisel
+        .write(".env", r#"{ "CHISELD_AUTH_SECRET": "dud" }"#);
+    c.chisel.apply_ok().await;
+
+    let user = c
+        .chisel
+        .post("/__chiselstrike/auth/users")
+        .header("ChiselAuth", "dud")
+        .json(json!({"name": "Al", "email": "al"}))
+        .send()
+        .await
+        .json();
+    let user_id = user["id"].as_str().unwrap();
+
+    let url = "/dev/counter?cache=public,s-maxage=60";
+    c.chisel.get(url).send().await.assert_text("1");
+    c.chisel.get(url).send().await.assert_text("1");
+    // Requests by a user are neither served from the cache nor cached.
+    c.chisel
+        .get(url)
+        .header("ChiselUID", user_id)
+        .send()
+        .await
+        .assert_text("2");
+    c.chisel
+        .get(url)
+        .header("ChiselUID", user_id)
+        .send()
+        .await
+        .assert_text("3");
+    // Nor are requests for a tenant.
+    c.chisel
+        .get(url)
+        .header("X-Tenant", "acme")
+        .send()
+        .await
+        .assert_text("4");
+    c.chisel.get(url).send().await.assert_text("1");
+}
@@ -0,0 +1,88 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+fn write_manifest(c: &TestContext, lint: &str) {
+    let manifest = format!(
+        r#"
+        models = ["models"]
+        routes = ["routes"]
+        events = ["events"]
+        policies = ["policies"]
+        modules = "deno"
+        optimize = "yes"
+        auto_index = "no"
+
+        [lint]
+        {}
+        "#,
+        lint
+    );
+    c.chisel.write_unindent("Chisel.toml", &manifest);
+}
+
+#[chisel_macros::test(modules = Deno, optimize = Yes)]
+pub async fn lint_rules(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/order.ts",
+        r##"
+        import { ChiselEntity, labels, unique } from "@chiselstrike/api";
+
+        export class Order extends ChiselEntity {
+            @unique code: string = "";
+            email: string = "";
+            @labels("pii") phone: string = "";
+            price: number = 0;
+            city: string = "";
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/orders.ts",
+        r##"
+        import { Order } from "../models/order.ts";
+
+        export default async function (req: Request) {
+            const params = new URL(req.url).searchParams;
+            const byCode = await Order.findOne({ code: params.get("code")! });
+            const byCity = await Order.findMany({ city: params.get("city")! });
+            return [byCode, byCity];
+        }
+        "##,
+    );
+
+    // The rules are off by default.
+    write_manifest(&c, "");
+    let output = c.chisel.apply_ok().await;
+    output.stdout.peek("Applied:");
+    assert!(!output.stdout.as_str().contains("Warning: Order"));
+
+    write_manifest(
+        &c,
+        r#"missing_index = "warn"
+        pii_labels = "warn"
+        money_float = "warn""#,
+    );
+    c.chisel
+        .apply_ok()
+        .await
+        .stdout
+        .read("Warning: Order is filtered by (city), which has no index [missing_index]")
+        .read("Warning: Order.email looks like personal data, but has no label [pii_labels]")
+        .read(
+            "Warning: Order.price looks like an amount of money, but is a floating-point number [money_float]",
+        )
+        .read("Applied:");
+
+    write_manifest(
+        &c,
+        r#"pii_labels = "error"
+        pii_fields = ["mail"]"#,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .read("The models break the lint rules of Chisel.toml:")
+        .read("Order.email looks like personal data, but has no label [pii_labels]");
+}
@@ -0,0 +1,132 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static USERS: &str = "/__chiselstrike/auth/scim/v2/Users";
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn provisioning(mut c: TestContext) {
+    // Provisioning is disabled until a token is configured.
+    c.chisel.get(USERS).send().await.assert_status(404);
+
+    c.chisel.write(
+        ".env",
+        r##"{ "CHISELD_SCIM_TOKEN": "tok", "CHISELD_AUTH_SECRET": "sec" }"##,
+    );
+    c.restart_chiseld().await;
+    c.chisel
+        .get(USERS)
+        .header("Authorization", "Bearer nope")
+        .send()
+        .await
+        .assert_status(401);
+
+    let user = c
+        .chisel
+        .post(USERS)
+        .header("Authorization", "Bearer tok")
+        .json(json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:User"],
+            "userName": "al@example.com",
+            "displayName": "Al",
+            "externalId": "ext-1",
+        }))
+        .send()
+        .await
+        .assert_status(201)
+        .json();
+    assert_eq!(user["active"], json!(true));
+    assert_eq!(user["externalId"], json!("ext-1"));
+    let id = user["id"].as_str().unwrap().to_owned();
+    let url = format!("{}/{}", USERS, id);
+
+    // The user exists as an AuthUser.
+    let users = c
+        .chisel
+        .get("/__chiselstrike/auth/users")
+        .header("ChiselAuth", "sec")
+        .send()
+        .await
+        .json();
+    assert_eq!(users["results"][0]["id"], json!(id));
+    assert_eq!(users["results"][0]["name"], json!("Al"));
+    assert_eq!(users["results"][0]["email"], json!("al@example.com"));
+
+    c.chisel
+        .post(USERS)
+        .header("Authorization", "Bearer tok")
+        .json(json!({"userName": "al@example.com"}))
+        .send()
+        .await
+        .assert_status(409);
+
+    let list = c
+        .chisel
+        .get(&format!(
+            "{}?filter=userName%20eq%20%22al@example.com%22",
+            USERS
+        ))
+        .header("Authorization", "Bearer tok")
+        .send()
+        .await
+        .json();
+    assert_eq!(list["totalResults"], json!(1));
+    assert_eq!(list["Resources"][0]["id"], json!(id));
+
+    // Deactivation logs the user out.
+    c.chisel
+        .post("/__chiselstrike/auth/sessions")
+        .header("ChiselAuth", "sec")
+        .json(json!({"sessionToken": "s1", "userId": id, "expires": "2100-01-01"}))
+        .send()
+        .await
+        .assert_ok();
+    let user = c
+        .chisel
+        .patch(&url)
+        .header("Authorization", "Bearer tok")
+        .json(json!({
+            "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+            "Operations": [{"op": "replace", "path": "active", "value": false}],
+        }))
+        .send()
+        .await
+        .assert_ok()
+        .json();
+    assert_eq!(user["active"], json!(false));
+    c.chisel
+        .get("/__chiselstrike/auth/sessions")
+        .header("ChiselAuth", "sec")
+        .send()
+        .await
+        .assert_json(json!({"results": []}));
+
+    c.chisel
+        .delete(&url)
+        .header("Authorization", "Bearer tok")
+        .send()
+        .await
+        .assert_status(204);
+    c.chisel
+        .get(&url)
+        .header("Authorization", "Bearer tok")
+        .send()
+        .await
+        .assert_status(404);
+
+    let audit = c
+        .chisel
+        .get("/__chiselstrike/auth/audit")
+        .header("ChiselAuth", "sec")
+        .send()
+        .await
+        .json();
+    let mut actions: Vec<&str> = audit["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| event["action"].as_str().unwrap())
+        .collect();
+    actions.sort_unstable();
+    assert_eq!(actions, ["create", "deactivate", "delete"]);
+}
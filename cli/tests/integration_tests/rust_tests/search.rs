@@ -0,0 +1,124 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static ROUTE_POSTS: &str = r##"
+    import { Post } from '../models/models.ts';
+    export default async function (req: Request) {
+        if (req.method == 'POST') {
+            await Post.create(await req.json());
+            return 'ok';
+        }
+        if (req.method == 'PUT') {
+            const { from, to } = await req.json();
+            const post = await Post.findOne({ title: from });
+            post!.title = to;
+            await post!.save();
+            return 'ok';
+        }
+        const url = new URL(req.url);
+        let posts = Post.search(url.searchParams.get('q') ?? '');
+        const take = url.searchParams.get('take');
+        if (take !== null) {
+            posts = posts.take(Number(take));
+        }
+        const titles = (await posts.toArray()).map((p) => p.title);
+        return titles.sort();
+    }
+"##;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn search_entities(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity, searchable } from '@chiselstrike/api';
+        @searchable("title", "body")
+        export class Post extends ChiselEntity {
+            title: string;
+            body: string;
+            views: number;
+        }
+    "##,
+    );
+    c.chisel.write_unindent("routes/posts.ts", ROUTE_POSTS);
+    c.chisel.apply_ok().await;
+    c.chisel
+        .describe_ok()
+        .await
+        .stdout
+        .peek(r#"@searchable("title", "body")"#)
+        .peek("class Post {");
+
+    for (title, body) in [
+        ("Rust databases", "Storing rows with sqlx"),
+        (
+            "Deno endpoints",
+            "Writing routes in TypeScript, with a database",
+        ),
+        ("Gardening", "Tomatoes don't like it's cold"),
+    ] {
+        c.chisel
+            .post_json(
+                "/dev/posts",
+                json!({"title": title, "body": body, "views": 0}),
+            )
+            .await;
+    }
+
+    assert_eq!(
+        c.chisel.get_json("/dev/posts?q=database").await,
+        json!(["Deno endpoints"])
+    );
+    assert_eq!(
+        c.chisel.get_json("/dev/posts?q=rows%20rust").await,
+        json!(["Rust databases"])
+    );
+    assert_eq!(
+        c.chisel.get_json("/dev/posts?q=it's").await,
+        json!(["Gardening"])
+    );
+    assert_eq!(c.chisel.get_json("/dev/posts?q=").await, json!([]));
+    assert_eq!(
+        c.chisel
+            .get_json("/dev/posts?q=with&take=1")
+            .await
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // Updates are indexed too.
+    c.chisel
+        .put("/dev/posts")
+        .json(json!({"from": "Gardening", "to": "Gardening with a database"}))
+        .send()
+        .await
+        .assert_status(200);
+    assert_eq!(
+        c.chisel.get_json("/dev/posts?q=database").await,
+        json!(["Deno endpoints", "Gardening with a database"])
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn search_without_searchable_fields(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from '@chiselstrike/api';
+        export class Post extends ChiselEntity {
+            title: string;
+        }
+    "##,
+    );
+    c.chisel.write_unindent("routes/posts.ts", ROUTE_POSTS);
+    c.chisel.apply_ok().await;
+    c.chisel
+        .get("/dev/posts?q=anything")
+        .send()
+        .await
+        .assert_status(500)
+        .assert_text_contains("entity 'Post' has no @searchable fields");
+}
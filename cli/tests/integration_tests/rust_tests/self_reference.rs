@@ -0,0 +1,158 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn tree(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/types.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Category extends ChiselEntity {
+            name: string = "";
+            parent?: Category;
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/store.ts",
+        r##"
+        import { Category } from "../models/types.ts";
+
+        export default async function (req: Request) {
+            const root = Category.build({ name: "root" });
+            const books = Category.build({ name: "books", parent: root });
+            await Category.build({ name: "novels", parent: books }).save();
+            await Category.build({ name: "games", parent: root }).save();
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/children.ts",
+        r##"
+        import { Category } from "../models/types.ts";
+
+        export default async function (req: Request) {
+            const params = new URL(req.url).searchParams;
+            let cursor = Category.cursor().filter({ name: params.get("name")! });
+            for (let i = 0; i < Number(params.get("depth") ?? "1"); i++) {
+                cursor = cursor.children("parent");
+            }
+            return (await cursor.toArray()).map((c) => c.name).sort();
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/parent.ts",
+        r##"
+        import { Category } from "../models/types.ts";
+
+        export default async function (req: Request) {
+            const novels = await Category.findOne({ name: "novels" });
+            return { parent: novels!.parent!.name, grandparent: novels!.parent!.parent };
+        }
+        "##,
+    );
+
+    c.chisel.apply_ok().await;
+    c.chisel.post_json("/dev/store", json!({})).await;
+
+    assert_eq!(
+        c.chisel.get_json("/dev/children?name=root").await,
+        json!(["books", "games"])
+    );
+    assert_eq!(
+        c.chisel.get_json("/dev/children?name=root&depth=2").await,
+        json!(["novels"])
+    );
+    assert_eq!(
+        c.chisel.get_json("/dev/children?name=games").await,
+        json!([])
+    );
+
+    // The parent is loaded, but not the parent of the parent.
+    assert_eq!(
+        c.chisel.get_json("/dev/parent").await,
+        json!({"parent": "books"})
+    );
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn required_self_reference(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/types.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Category extends ChiselEntity {
+            name: string = "";
+            parent: Category;
+        }
+        "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("field `parent` of `Category` refers to `Category` itself, so it must be optional");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn related(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/types.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Tag extends ChiselEntity {
+            name: string = "";
+        }
+
+        export class Post extends ChiselEntity {
+            title: string = "";
+            tags: Tag[] = [];
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/store.ts",
+        r##"
+        import { Post, Tag } from "../models/types.ts";
+
+        export default async function (req: Request) {
+            const rust = Tag.build({ name: "rust" });
+            const deno = Tag.build({ name: "deno" });
+            const sql = Tag.build({ name: "sql" });
+            await Post.build({ title: "one", tags: [rust, deno] }).save();
+            await Post.build({ title: "two", tags: [rust, sql] }).save();
+            await Post.build({ title: "three", tags: [] }).save();
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/tags.ts",
+        r##"
+        import { Post, Tag } from "../models/types.ts";
+
+        export default async function (req: Request) {
+            const title = new URL(req.url).searchParams.get("title")!;
+            const tags = Post.cursor().filter({ title }).related("tags", Tag);
+            return (await tags.toArray()).map((t) => t.name).sort();
+        }
+        "##,
+    );
+
+    c.chisel.apply_ok().await;
+    c.chisel.post_json("/dev/store", json!({})).await;
+
+    assert_eq!(
+        c.chisel.get_json("/dev/tags?title=one").await,
+        json!(["deno", "rust"])
+    );
+    assert_eq!(
+        c.chisel.get_json("/dev/tags?title=two").await,
+        json!(["rust", "sql"])
+    );
+    assert_eq!(c.chisel.get_json("/dev/tags?title=three").await, json!([]));
+}
@@ -0,0 +1,71 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn serve(c: TestContext) {
+    c.chisel.write("static/index.html", "<h1>Hello</h1>");
+    c.chisel.write("static/css/app.css", "h1 { color: red; }");
+    c.chisel.apply_ok().await;
+
+    let index = c.chisel.get("/dev/static/").send().await;
+    index.assert_ok();
+    assert_eq!(index.text(), "<h1>Hello</h1>");
+    assert_eq!(index.header("content-type"), "text/html; charset=utf-8");
+
+    let css = c.chisel.get("/dev/static/css/app.css").send().await;
+    css.assert_ok();
+    assert_eq!(css.text(), "h1 { color: red; }");
+    assert_eq!(css.header("content-type"), "text/css; charset=utf-8");
+    assert_eq!(css.header("cache-control"), "no-cache");
+    let etag = css.header("etag");
+    c.chisel
+        .get("/dev/static/css/app.css")
+        .header("If-None-Match", &etag)
+        .send()
+        .await
+        .assert_status(304);
+
+    c.chisel
+        .get("/dev/static/missing.js")
+        .send()
+        .await
+        .assert_status(404);
+
+    // Files change with every apply, and so do their tags.
+    c.chisel.write("static/css/app.css", "h1 { color: blue; }");
+    c.chisel.apply_ok().await;
+    let css = c
+        .chisel
+        .get("/dev/static/css/app.css")
+        .header("If-None-Match", &etag)
+        .send()
+        .await;
+    css.assert_ok();
+    assert_eq!(css.text(), "h1 { color: blue; }");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn route_collision(c: TestContext) {
+    c.chisel.write("static/index.html", "<h1>Hello</h1>");
+    c.chisel.write_unindent(
+        "routes/static.ts",
+        r##"
+        export default function () {
+            return "route";
+        }
+        "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("route /dev/static would shadow the static files served under /dev/static/");
+
+    // Without static files, the route is fine.
+    c.chisel.remove_file("static/index.html");
+    c.chisel.apply_ok().await;
+    let route = c.chisel.get("/dev/static").send().await;
+    route.assert_ok();
+    assert_eq!(route.text(), "route");
+}
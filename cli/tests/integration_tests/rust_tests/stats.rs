@@ -0,0 +1,40 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn column_stats(c: TestContext) {
+    c.chisel.write(
+        "models/types.ts",
+        r#"
+        import { ChiselEntity } from "@chiselstrike/api";
+        export class Person extends ChiselEntity {
+            name: string;
+            city?: string;
+        }"#,
+    );
+    c.chisel.write(
+        "routes/store.ts",
+        r#"
+        import { Person } from "../models/types.ts";
+        export default async function chisel(req: Request) {
+            await Person.create({ name: "Alice", city: "Oslo" });
+            await Person.create({ name: "Bob", city: "Oslo" });
+            await Person.create({ name: "Carol", city: "Rome" });
+            await Person.create({ name: "Dave" });
+            return "ok";
+        }"#,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel.post("/dev/store").send().await.assert_ok();
+
+    let mut output = c.chisel.exec("stats", &[]).await.unwrap();
+    output.stdout.read("dev/Person: 4 rows");
+
+    let mut output = c.chisel.exec("stats", &["--columns"]).await.unwrap();
+    output
+        .stdout
+        .read("dev/Person: 4 rows")
+        .read("name: 4 distinct, 0 null")
+        .read(r#"city: 2 distinct, 1 null; most common: "Oslo" (2), "Rome" (1)"#);
+}
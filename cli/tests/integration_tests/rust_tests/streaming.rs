@@ -0,0 +1,75 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn server_sent_events(c: TestContext) {
+    c.chisel.write_unindent(
+        "routes/events.ts",
+        r##"
+        import { responseFromEvents } from '@chiselstrike/api';
+        async function* events() {
+            yield "first";
+            // Long enough for the first event to arrive alone.
+            await new Promise((resolve) => setTimeout(resolve, 1000));
+            yield { event: "tick", id: "2", data: "two\nlines" };
+        }
+        export default async function () {
+            return responseFromEvents(events());
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+
+    let response = c.chisel.get("/dev/events").send().await;
+    response
+        .assert_ok()
+        .assert_text("data: first\n\nevent: tick\nid: 2\ndata: two\ndata: lines\n\n");
+    assert_eq!(response.header("content-type"), "text/event-stream");
+
+    let url = format!("http://{}/dev/events", c.chisel.api_address);
+    let mut response = reqwest::get(url).await.unwrap();
+    let chunk = response.chunk().await.unwrap().unwrap();
+    assert_eq!(&chunk[..], b"data: first\n\n");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn cursor(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from '@chiselstrike/api';
+        export class Person extends ChiselEntity {
+            name: string;
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r##"
+        import { Person } from '../models/models.ts';
+        export default async function (req: Request) {
+            if (req.method === "POST") {
+                for (const name of ["carol", "alice", "bob"]) {
+                    await Person.create({ name });
+                }
+                return "ok";
+            }
+            return Person.cursor().sortBy("name").select("name");
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .get("/dev/people")
+        .send()
+        .await
+        .assert_json(json!([]));
+    c.chisel.post("/dev/people").send().await.assert_ok();
+    c.chisel.get("/dev/people").send().await.assert_json(json!([
+        {"name": "alice"},
+        {"name": "bob"},
+        {"name": "carol"},
+    ]));
+}
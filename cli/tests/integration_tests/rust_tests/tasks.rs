@@ -0,0 +1,80 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use std::time::Duration;
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn run_after_commit(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Order extends ChiselEntity {
+            item: string = "";
+        }
+
+        export class Shipment extends ChiselEntity {
+            item: string = "";
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "tasks/ship.ts",
+        r##"
+        import { Shipment } from "../models/models.ts";
+
+        export default async function (payload: { item: string }) {
+            await Shipment.create({ item: payload.item });
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/order.ts",
+        r##"
+        import { ChiselRequest, enqueue } from "@chiselstrike/api";
+        import { Order, Shipment } from "../models/models.ts";
+
+        export default async function chisel(req: ChiselRequest) {
+            if (req.method == "GET") {
+                return (await Shipment.findAll()).map(s => s.item);
+            }
+            const item = req.query.get("item")!;
+            await Order.create({ item });
+            await enqueue(req.query.get("task") ?? "ship", { item });
+            if (req.query.getBool("fail")) {
+                throw new Error("rolling back");
+            }
+            return "ok";
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await.stdout.peek("1 tasks");
+
+    c.chisel
+        .post("/dev/order?item=apple")
+        .send()
+        .await
+        .assert_ok();
+    c.chisel
+        .post("/dev/order?item=pear&fail=true")
+        .send()
+        .await
+        .assert_status(500);
+    c.chisel
+        .post("/dev/order?item=plum&task=nope")
+        .send()
+        .await
+        .assert_status(500)
+        .assert_text_contains("there is no task nope in version dev");
+
+    // Wait for a worker to run the task.
+    for _ in 0..50 {
+        if c.chisel.get_json("/dev/order").await == json!(["apple"]) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("the task to ship the order did not run");
+}
@@ -0,0 +1,213 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+static NOTE: &str = r##"
+    import { ChiselEntity, labels } from "@chiselstrike/api";
+
+    export class Note extends ChiselEntity {
+        text: string = "";
+        @labels("tenant") org: string = "";
+    }
+    "##;
+
+static NOTES_ROUTE: &str = r##"
+    import { Note } from "../models/note.ts";
+    export default Note.crud();
+    "##;
+
+async fn texts(chisel: &Chisel, tenant: Option<&str>) -> Vec<String> {
+    let mut req = chisel.get("/dev/notes");
+    if let Some(tenant) = tenant {
+        req = req.header("X-Tenant", tenant);
+    }
+    let notes = req.send().await.assert_ok().json();
+    let mut texts: Vec<String> = notes["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|note| note["text"].as_str().unwrap().to_owned())
+        .collect();
+    texts.sort_unstable();
+    texts
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn header(c: TestContext) {
+    c.chisel.write_unindent("models/note.ts", NOTE);
+    c.chisel.write_unindent("routes/notes.ts", NOTES_ROUTE);
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+        tenant:
+          header: X-Tenant
+        labels:
+          - name: tenant
+            transform: match_tenant
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    let note = c
+        .chisel
+        .post("/dev/notes")
+        .header("X-Tenant", "acme")
+        .json(json!({"text": "a"}))
+        .send()
+        .await
+        .assert_ok()
+        .json();
+    let acme_id = note["id"].as_str().unwrap().to_owned();
+    c.chisel
+        .post("/dev/notes")
+        .header("X-Tenant", "globex")
+        .json(json!({"text": "b"}))
+        .send()
+        .await
+        .assert_ok();
+
+    let note = c
+        .chisel
+        .get(&format!("/dev/notes/{}", acme_id))
+        .header("X-Tenant", "acme")
+        .send()
+        .await
+        .json();
+    assert_eq!(note["org"], json!("acme"));
+    c.chisel
+        .get(&format!("/dev/notes/{}", acme_id))
+        .header("X-Tenant", "globex")
+        .send()
+        .await
+        .assert_status(404);
+    assert_eq!(texts(&c.chisel, Some("acme")).await, ["a"]);
+    assert_eq!(texts(&c.chisel, Some("globex")).await, ["b"]);
+    assert!(texts(&c.chisel, None).await.is_empty());
+
+    // Writes can't escape the tenant of the request.
+    c.chisel
+        .post("/dev/notes")
+        .json(json!({"text": "c"}))
+        .send()
+        .await
+        .assert_status(500);
+    c.chisel
+        .post("/dev/notes")
+        .header("X-Tenant", "acme")
+        .json(json!({"text": "c", "org": "globex"}))
+        .send()
+        .await
+        .assert_status(500);
+    c.chisel
+        .put(&format!("/dev/notes/{}", acme_id))
+        .header("X-Tenant", "globex")
+        .json(json!({"text": "stolen"}))
+        .send()
+        .await
+        .assert_status(500);
+    c.chisel
+        .delete(&format!("/dev/notes/{}", acme_id))
+        .header("X-Tenant", "globex")
+        .send()
+        .await
+        .assert_ok();
+    assert_eq!(texts(&c.chisel, Some("acme")).await, ["a"]);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn claim(mut c: TestContext) {
+    c.chisel.write_unindent("models/note.ts", NOTE);
+    c.chisel.write_unindent("routes/notes.ts", NOTES_ROUTE);
+    c.chisel.write_unindent(
+        "routes/auth.ts",
+        r##"
+        export default function () {
+            return "ok";
+        }
+        export function authenticate(req: Request) {
+            const org = req.headers.get('x-org');
+            return org === null ? undefined : { userId: 'u', claims: { org } };
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+        tenant:
+          claim: org
+        labels:
+          - name: tenant
+            transform: match_tenant
+        "##,
+    );
+    c.chisel.write(
+        ".env",
+        r##"{ "CHISELD_AUTH_PROVIDERS": ["function:/auth"] }"##,
+    );
+    c.restart_chiseld().await;
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .post("/dev/notes")
+        .header("x-org", "acme")
+        .json(json!({"text": "a"}))
+        .send()
+        .await
+        .assert_ok();
+    let notes = c
+        .chisel
+        .get("/dev/notes")
+        .header("x-org", "acme")
+        .send()
+        .await
+        .json();
+    assert_eq!(notes["results"][0]["org"], json!("acme"));
+    c.chisel
+        .get("/dev/notes")
+        .header("x-org", "globex")
+        .send()
+        .await
+        .assert_json(json!({"results": []}));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn quotas(c: TestContext) {
+    c.chisel.write_unindent("models/note.ts", NOTE);
+    c.chisel.write_unindent("routes/notes.ts", NOTES_ROUTE);
+    c.chisel.write_unindent(
+        "policies/pol.yaml",
+        r##"
+        tenant:
+          header: X-Tenant
+          quotas:
+            requests_per_minute: 4
+            rows: 1
+            tenants:
+              acme:
+                rows: 2
+        labels:
+          - name: tenant
+            transform: match_tenant
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    let post = |tenant: &'static str, text: &'static str| {
+        c.chisel
+            .post("/dev/notes")
+            .header("X-Tenant", tenant)
+            .json(json!({ "text": text }))
+            .send()
+    };
+    post("globex", "a").await.assert_ok();
+    post("globex", "b").await.assert_status(500);
+    post("acme", "a").await.assert_ok();
+    post("acme", "b").await.assert_ok();
+    post("acme", "c").await.assert_status(500);
+
+    // globex runs out of requests, while acme has one left.
+    post("globex", "c").await.assert_status(500);
+    post("globex", "d").await.assert_status(500);
+    post("globex", "e").await.assert_status(429);
+    assert_eq!(texts(&c.chisel, Some("acme")).await, ["a", "b"]);
+}
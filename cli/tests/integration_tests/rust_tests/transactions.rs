@@ -0,0 +1,71 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn nested(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/person.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Person extends ChiselEntity {
+            name: string = "";
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r##"
+        import { ChiselRequest } from "@chiselstrike/api";
+        import { Person } from "../models/person.ts";
+
+        export default async function (req: ChiselRequest) {
+            if (req.method === "GET") {
+                const people = await Person.findAll();
+                return people.map((p) => p.name).sort();
+            }
+            await Person.create({ name: "Al" });
+            try {
+                await req.transaction(async () => {
+                    await Person.create({ name: "Bo" });
+                    throw new Error("oops");
+                });
+            } catch (_) {
+                // Bo isn't saved, but Al is.
+            }
+            const cy = await req.transaction(() => Person.create({ name: "Cy" }));
+            await req.transaction(async () => {
+                await Person.create({ name: "Di" });
+                try {
+                    await req.transaction(async () => {
+                        await Person.update(cy.id!, { name: "Ed" });
+                        throw new Error("oops");
+                    });
+                } catch (_) {
+                    // Cy keeps its name.
+                }
+            });
+            if (req.method === "PUT") {
+                throw new Error("the whole request fails");
+            }
+            return "ok";
+        }
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel.put("/dev/people").send().await.assert_status(500);
+    c.chisel
+        .get("/dev/people")
+        .send()
+        .await
+        .assert_json(json!([]));
+
+    c.chisel.post("/dev/people").send().await.assert_text("ok");
+    c.chisel
+        .get("/dev/people")
+        .send()
+        .await
+        .assert_json(json!(["Al", "Cy", "Di"]));
+}
@@ -0,0 +1,75 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn cascade(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Company extends ChiselEntity {
+            name: string;
+        }
+
+        export class Person extends ChiselEntity {
+            name: string;
+            company?: Company;
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r##"
+        import { Company, Person } from "../models/models.ts";
+
+        export default async function (req: Request) {
+            const company = Company.build({ name: "acme" });
+            await Person.create({ name: "alice", company });
+            await Person.create({ name: "bob" });
+            return "ok";
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel.post("/dev/people").send().await.assert_ok();
+
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Person extends ChiselEntity {
+            name: string;
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r##"
+        import { Person } from "../models/models.ts";
+
+        export default async function (req: Request) {
+            return (await Person.findAll()).map((p) => p.name).sort();
+        }
+    "##,
+    );
+    c.chisel
+        .exec("apply", &["--allow-type-deletion", "--yes"])
+        .await
+        .expect_err("deleting a model that others refer to should need --cascade")
+        .stderr
+        .read("Trying to remove models that other models still refer to:")
+        .read("Company, referred to by Person.company (1 elements)")
+        .read("chisel apply --allow-type-deletion --cascade");
+
+    c.chisel
+        .exec("apply", &["--allow-type-deletion", "--cascade", "--yes"])
+        .await
+        .expect("chisel apply --cascade failed");
+    assert_eq!(
+        c.chisel.get_json("/dev/people").await,
+        json!(["alice", "bob"])
+    );
+}
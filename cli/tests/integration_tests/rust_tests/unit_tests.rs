@@ -0,0 +1,201 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn memory_store_and_mocked_fetch(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/person.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Person extends ChiselEntity {
+            name: string = "";
+            greeting: string = "";
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "routes/people.ts",
+        r##"
+        import { ChiselRequest } from "@chiselstrike/api";
+        import { Person } from "../models/person.ts";
+
+        export default async function (req: ChiselRequest) {
+            if (req.method === "POST") {
+                const res = await fetch("https://example.com/greeting");
+                const person = Person.build({
+                    name: req.query.get("name")!,
+                    greeting: await res.text(),
+                });
+                await person.save();
+                return person;
+            }
+            return (await Person.findAll()).length;
+        }
+        "##,
+    );
+    c.chisel.write_unindent(
+        "tests/people.test.ts",
+        r##"
+        import { mockFetch, test, testRequest } from "@chiselstrike/api";
+        import { Person } from "../models/person.ts";
+        import handler from "../routes/people.ts";
+
+        test("saves a person", async () => {
+            mockFetch(() => new Response("hi"));
+            await handler(testRequest("/people?name=Al", { method: "POST" }));
+            const people = await Person.findMany({ name: "Al" });
+            if (people.length !== 1 || people[0].greeting !== "hi") {
+                throw new Error(`unexpected people ${JSON.stringify(people)}`);
+            }
+        });
+
+        test("starts empty", async () => {
+            const count = await handler(testRequest("/people"));
+            if (count !== 0) {
+                throw new Error(`${count} people left by the previous test`);
+            }
+        });
+
+        test("unmocked fetch", async () => {
+            await handler(testRequest("/people?name=Bo", { method: "POST" }));
+        });
+        "##,
+    );
+    c.chisel.apply_ok().await;
+
+    let mut output = c
+        .chisel
+        .exec("test", &["--unit"])
+        .await
+        .expect_err("chisel test with a failing test succeeded");
+    output
+        .stdout
+        .read("saves a person ... ok")
+        .read("starts empty ... ok")
+        .read("unmocked fetch ... FAILED")
+        .read("fetch(https://example.com/greeting) in a unit test")
+        .read("2 passed, 1 failed");
+
+    // The tests didn't touch the database.
+    assert_eq!(c.chisel.get_json("/dev/people").await, json!(0));
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn test_clock(c: TestContext) {
+    c.chisel.write_unindent(
+        "tests/clock.test.ts",
+        r##"
+        import { test, testClock } from "@chiselstrike/api";
+
+        function check(what: string, actual: unknown, expected: unknown) {
+            if (actual !== expected) {
+                throw new Error(`${what} is ${actual}, expected ${expected}`);
+            }
+        }
+
+        test("starts at a fixed time", () => {
+            check("now", new Date().toISOString(), "2022-01-01T00:00:00.000Z");
+            check("Date.now()", Date.now(), testClock.now());
+        });
+
+        test("fires timers in order", async () => {
+            const fired: string[] = [];
+            setTimeout(() => fired.push("b"), 2000);
+            setTimeout(() => fired.push("a"), 1000);
+            const id = setInterval(() => fired.push("i"), 1500);
+            await testClock.advance(999);
+            check("fired", fired.join(), "");
+            await testClock.advance(2001);
+            check("fired", fired.join(), "a,i,b,i");
+            clearInterval(id);
+            await testClock.set(new Date("2022-02-01T00:00:00Z"));
+            check("fired", fired.join(), "a,i,b,i");
+            check("Date.now()", Date.now(), Date.UTC(2022, 1, 1));
+        });
+
+        test("starts without timers", async () => {
+            let fired = false;
+            setTimeout(() => fired = true, 10);
+            check("now", Date.now(), Date.UTC(2022, 0, 1));
+            await testClock.advance(10);
+            check("fired", fired, true);
+        });
+        "##,
+    );
+    c.chisel
+        .exec("test", &["--unit"])
+        .await
+        .expect("chisel test failed")
+        .stdout
+        .read("starts at a fixed time ... ok")
+        .read("fires timers in order ... ok")
+        .read("starts without timers ... ok")
+        .read("3 passed, 0 failed");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn record_fetch(c: TestContext) {
+    c.chisel.write_unindent(
+        "routes/greeting.ts",
+        r##"
+        export default () => "hi";
+        "##,
+    );
+    c.chisel.write_unindent(
+        "tests/greeting.test.ts",
+        &format!(
+            r##"
+            import {{ test }} from "@chiselstrike/api";
+
+            test("gets a greeting", async () => {{
+                const res = await fetch("http://{}/dev/greeting");
+                const greeting = await res.text();
+                if (greeting !== "hi") {{
+                    throw new Error(`unexpected greeting ${{greeting}}`);
+                }}
+            }});
+            "##,
+            c.chisel.api_address
+        ),
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel
+        .exec("test", &["--unit"])
+        .await
+        .expect_err("chisel test without a recording succeeded")
+        .stdout
+        .read("gets a greeting ... FAILED")
+        .read("record it with `chisel test --unit --record`");
+    c.chisel
+        .exec("test", &["--unit", "--record"])
+        .await
+        .expect("chisel test --record failed")
+        .stdout
+        .read("gets a greeting ... ok")
+        .read("Recorded the fetch() calls of 1 test modules");
+    assert!(c
+        .chisel
+        .tmp_dir
+        .path()
+        .join("tests/greeting.test.cassette.json")
+        .exists());
+
+    // Later runs replay the recorded response.
+    c.chisel.write_unindent(
+        "routes/greeting.ts",
+        r##"
+        export default () => "bye";
+        "##,
+    );
+    c.chisel.apply_ok().await;
+    c.chisel
+        .exec("test", &["--unit"])
+        .await
+        .expect("chisel test failed")
+        .stdout
+        .read("gets a greeting ... ok")
+        .read("1 passed, 0 failed");
+}
@@ -0,0 +1,106 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+use crate::framework::ProcessOutput;
+
+static MODELS: &str = r##"
+    import { ChiselEntity } from '@chiselstrike/api';
+    export class Person extends ChiselEntity {
+        name: string;
+        age: number;
+    }
+"##;
+
+static ROUTE_PEOPLE: &str = r##"
+    import { Person } from '../models/models.ts';
+    import { crud } from '@chiselstrike/api';
+    export default crud(Person, '');
+"##;
+
+async fn setup(chisel: &Chisel) {
+    chisel.write_unindent("models/models.ts", MODELS);
+    chisel.write_unindent("routes/people.ts", ROUTE_PEOPLE);
+    chisel.apply_ok().await;
+    chisel
+        .exec("apply", &["--version", "staging"])
+        .await
+        .expect("chisel apply --version staging failed");
+    chisel
+        .post_json("/dev/people", json!({"name": "Al", "age": 40}))
+        .await;
+    chisel
+        .post_json("/dev/people", json!({"name": "Bo", "age": 7.5}))
+        .await;
+}
+
+async fn verify(chisel: &Chisel, args: &[&str]) -> Result<ProcessOutput, ProcessOutput> {
+    let args = [&["--version", "staging", "--against", "dev"], args].concat();
+    chisel.exec("verify", &args).await
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn checksums(c: TestContext) {
+    setup(&c.chisel).await;
+    c.chisel
+        .exec("verify", &[])
+        .await
+        .expect("chisel verify failed")
+        .stdout
+        .read("Person: 2 rows, checksum ");
+
+    let mut output = verify(&c.chisel, &[]).await.expect_err("verify succeeded");
+    output
+        .stdout
+        .read("Person: MISMATCH, 0 rows in staging and 2 rows in dev");
+
+    c.chisel
+        .exec("populate", &["--version", "staging", "--from", "dev"])
+        .await
+        .expect("chisel populate failed");
+    verify(&c.chisel, &[])
+        .await
+        .expect("verify failed")
+        .stdout
+        .read("Person: OK, 2 rows");
+
+    // The same rows with other contents don't match.
+    let people = c.chisel.get_json("/staging/people?.name=Bo").await;
+    let id = people["results"][0]["id"].as_str().unwrap().to_owned();
+    c.chisel
+        .put(&format!("/staging/people/{}", id))
+        .json(json!({"name": "Bo", "age": 8}))
+        .send()
+        .await
+        .assert_ok();
+    verify(&c.chisel, &[])
+        .await
+        .expect_err("verify succeeded")
+        .stdout
+        .read("Person: MISMATCH, 2 rows in staging and 2 rows in dev");
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn ignore_ids(c: TestContext) {
+    setup(&c.chisel).await;
+    c.chisel
+        .exec(
+            "populate",
+            &[
+                "--version",
+                "staging",
+                "--from",
+                "dev",
+                "--ids",
+                "regenerate",
+            ],
+        )
+        .await
+        .expect("chisel populate failed");
+
+    verify(&c.chisel, &[]).await.expect_err("verify succeeded");
+    verify(&c.chisel, &["--ignore-ids"])
+        .await
+        .expect("verify --ignore-ids failed")
+        .stdout
+        .read("Person: OK, 2 rows");
+}
@@ -0,0 +1,142 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use std::time::Duration;
+
+use crate::framework::prelude::*;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn deliver_signed(mut c: TestContext) {
+    c.chisel.write(".env", r##"{"HOOK_SECRET": "sekrit"}"##);
+    c.restart_chiseld().await;
+
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from "@chiselstrike/api";
+
+        export class Order extends ChiselEntity {
+            item: string = "";
+        }
+
+        export class Delivery extends ChiselEntity {
+            event: string = "";
+            item: string = "";
+            signed: boolean = false;
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/orders.ts",
+        r##"
+        import { Order } from "../models/models.ts";
+        export default Order.crud();
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/hook.ts",
+        r##"
+        import { getSecret } from "@chiselstrike/api";
+        import { Delivery } from "../models/models.ts";
+
+        async function sign(message: string): Promise<string> {
+            const encoder = new TextEncoder();
+            const key = await crypto.subtle.importKey(
+                "raw",
+                encoder.encode(getSecret("HOOK_SECRET") as string),
+                { name: "HMAC", hash: "SHA-256" },
+                false,
+                ["sign"],
+            );
+            const mac = await crypto.subtle.sign("HMAC", key, encoder.encode(message));
+            return [...new Uint8Array(mac)]
+                .map((b) => b.toString(16).padStart(2, "0"))
+                .join("");
+        }
+
+        export default async function chisel(req: Request) {
+            if (req.method == "POST") {
+                const body = await req.text();
+                const [t, v1] = req.headers.get("ChiselStrike-Signature")!
+                    .split(",")
+                    .map((part) => part.split("=")[1]);
+                const { event, object } = JSON.parse(body);
+                await Delivery.create({
+                    event,
+                    item: object?.item ?? "",
+                    signed: v1 === await sign(`${t}.${body}`),
+                });
+                return "ok";
+            }
+            return (await Delivery.findAll())
+                .map((d) => `${d.event} ${d.item} ${d.signed}`)
+                .sort();
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "policies/webhooks.yaml",
+        &format!(
+            r##"
+            webhooks:
+              - url: http://{}/dev/hook
+                secret_value_ref: HOOK_SECRET
+                entities: [Order]
+            "##,
+            c.chisel.api_address
+        ),
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel.describe_ok().await.stdout.peek(&format!(
+        "Webhook http://{}/dev/hook: create, update, delete of Order, signed",
+        c.chisel.api_address
+    ));
+
+    let order = c
+        .chisel
+        .post("/dev/orders")
+        .json(json!({"item": "apple"}))
+        .send()
+        .await
+        .assert_ok()
+        .json();
+    let id = order["id"].as_str().unwrap();
+    c.chisel
+        .patch(&format!("/dev/orders/{}", id))
+        .json(json!({"item": "pear"}))
+        .send()
+        .await
+        .assert_ok();
+    c.chisel
+        .delete(&format!("/dev/orders/{}", id))
+        .send()
+        .await
+        .assert_ok();
+
+    // Wait for the worker to deliver the payloads.
+    let expected = json!(["create apple true", "delete  true", "update pear true"]);
+    for _ in 0..50 {
+        if c.chisel.get_json("/dev/hook").await == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(c.chisel.get_json("/dev/hook").await, expected);
+}
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn reject_unknown_event(c: TestContext) {
+    c.chisel.write_unindent(
+        "policies/webhooks.yaml",
+        r##"
+        webhooks:
+          - url: http://localhost/hook
+            events: [upsert]
+    "##,
+    );
+    c.chisel
+        .apply_err()
+        .await
+        .stderr
+        .peek("unknown webhook event `upsert`, expected one of: create, update, delete");
+}
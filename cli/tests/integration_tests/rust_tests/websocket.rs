@@ -0,0 +1,75 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+use crate::framework::prelude::*;
+use futures::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message;
+
+#[chisel_macros::test(modules = Deno)]
+pub async fn echo(c: TestContext) {
+    c.chisel.write_unindent(
+        "models/models.ts",
+        r##"
+        import { ChiselEntity } from '@chiselstrike/api';
+        export class Said extends ChiselEntity {
+            text: string;
+        }
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/echo.ts",
+        r##"
+        import { ChiselRequest, ChiselSocket } from '@chiselstrike/api';
+        import { Said } from '../models/models.ts';
+        export const websocket = {
+            open(socket: ChiselSocket, req: ChiselRequest) {
+                socket.send("welcome " + req.query.get("name"));
+            },
+            async message(socket: ChiselSocket, data: string | Uint8Array) {
+                if (data === "bye") {
+                    socket.close();
+                    return;
+                }
+                await Said.create({ text: String(data) });
+                socket.send("echo: " + data);
+            },
+        };
+    "##,
+    );
+    c.chisel.write_unindent(
+        "routes/said.ts",
+        r##"
+        import { Said } from '../models/models.ts';
+        export default async function () {
+            return (await Said.findAll()).map((s) => s.text).join(",");
+        }
+    "##,
+    );
+    c.chisel.apply_ok().await;
+
+    c.chisel.get("/dev/echo").send().await.assert_status(426);
+
+    let url = format!("ws://{}/dev/echo?name=ana", c.chisel.api_address);
+    let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+    assert_eq!(
+        ws.next().await.unwrap().unwrap(),
+        Message::Text("welcome ana".into())
+    );
+    for text in ["one", "two"] {
+        ws.send(Message::Text(text.into())).await.unwrap();
+        assert_eq!(
+            ws.next().await.unwrap().unwrap(),
+            Message::Text(format!("echo: {}", text))
+        );
+    }
+    ws.send(Message::Text("bye".into())).await.unwrap();
+    assert!(matches!(
+        ws.next().await.unwrap().unwrap(),
+        Message::Close(_)
+    ));
+
+    c.chisel
+        .get("/dev/said")
+        .send()
+        .await
+        .assert_text("one,two");
+}
@@ -18,6 +18,17 @@ impl Compiler {
     pub async fn compile_endpoints(
         &mut self,
         file_names: &[&str],
+    ) -> Result<HashMap<String, String>> {
+        self.compile_endpoints_with_source_maps(file_names, false)
+            .await
+    }
+
+    /// Like `compile_endpoints()`, but can append inline source maps to the
+    /// emitted JavaScript.
+    pub async fn compile_endpoints_with_source_maps(
+        &mut self,
+        file_names: &[&str],
+        source_maps: bool,
     ) -> Result<HashMap<String, String>> {
         let mut mods = HashMap::from([(
             "@chiselstrike/api".to_string(),
@@ -30,6 +41,7 @@ impl Compiler {
 
         let opts = CompileOptions {
             extra_libs: mods,
+            source_maps,
             ..Default::default()
         };
 
@@ -44,3 +56,13 @@ pub async fn compile_endpoints(file_names: &[&str]) -> Result<HashMap<String, St
     let mut compiler = Compiler::new(true);
     compiler.compile_endpoints(file_names).await
 }
+
+pub async fn compile_endpoints_with_source_maps(
+    file_names: &[&str],
+    source_maps: bool,
+) -> Result<HashMap<String, String>> {
+    let mut compiler = Compiler::new(true);
+    compiler
+        .compile_endpoints_with_source_maps(file_names, source_maps)
+        .await
+}
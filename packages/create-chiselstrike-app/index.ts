@@ -36,6 +36,8 @@ function run(projectDirectory: string, chiselVersion: string) {
 
     const routesPath = path.join(projectDirectory, "routes");
     const eventsPath = path.join(projectDirectory, "events");
+    const jobsPath = path.join(projectDirectory, "jobs");
+    const tasksPath = path.join(projectDirectory, "tasks");
     const modelsPath = path.join(projectDirectory, "models");
     const policiesPath = path.join(projectDirectory, "policies");
 
@@ -43,6 +45,10 @@ function run(projectDirectory: string, chiselVersion: string) {
     fs.mkdirSync(routesPath);
     fs.mkdirSync(eventsPath);
     fs.closeSync(fs.openSync(path.join(eventsPath, ".gitkeep"), "w"));
+    fs.mkdirSync(jobsPath);
+    fs.closeSync(fs.openSync(path.join(jobsPath, ".gitkeep"), "w"));
+    fs.mkdirSync(tasksPath);
+    fs.closeSync(fs.openSync(path.join(tasksPath, ".gitkeep"), "w"));
     fs.mkdirSync(modelsPath);
     fs.closeSync(fs.openSync(path.join(modelsPath, ".gitkeep"), "w"));
     fs.mkdirSync(policiesPath);
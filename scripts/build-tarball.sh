@@ -7,7 +7,8 @@
 # This scripts builds a tarball of ChiselStrike for distribution. You need to
 # specify the target (i.e. operating system and machine architecture) you are
 # building for with the `-t TARGET` command line option. Please see `rustc
-# --print target-list` for a list of available targets.
+# --print target-list` for a list of available targets. The SHA-256 checksum
+# of the tarball is written next to it.
 
 program="chiselstrike"
 
@@ -34,4 +35,12 @@ for file in ${files[@]}
 do
   cp "target/$target/release/$file" "builds/$program-$target"
 done
-tar -C builds -czvf "$program-$version-$target.tar.gz" "$program-$target/"
+tarball="$program-$version-$target.tar.gz"
+tar -C builds -czvf "$tarball" "$program-$target/"
+# `chisel upgrade` only installs tarballs that match their checksum.
+if command -v sha256sum > /dev/null
+then
+  sha256sum "$tarball" > "$tarball.sha256"
+else
+  shasum -a 256 "$tarball" > "$tarball.sha256"
+fi
@@ -1,30 +1,42 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
+use crate::notifications::{notify, EventKind};
 use crate::prefix_map::PrefixMap;
-use anyhow::{Error, Result};
+use crate::response_cache::{self, Lookup};
+use anyhow::{Context as _, Error, Result};
 use deno_core::futures;
 use futures::future::LocalBoxFuture;
 use futures::ready;
 use futures::stream::Stream;
-use hyper::body::HttpBody;
+use hyper::body::{Bytes, HttpBody};
 use hyper::header::HeaderValue;
+use hyper::server::accept;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{HeaderMap, Request, Response, Server, StatusCode};
+use rustls_pemfile::Item;
 use socket2::{Domain, Protocol, Socket, Type};
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::convert::TryFrom;
-use std::io::Cursor;
+use std::fs::File;
+use std::io::{BufReader, Cursor};
 use std::net::ToSocketAddrs;
+use std::path::Path;
 use std::pin::Pin;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
 
 type JsStream = Pin<Box<dyn Stream<Item = Result<Box<[u8]>>>>>;
 
 pub enum Body {
     Const(Option<Box<[u8]>>),
+    /// Content shared between responses, like that of a static file.
+    Shared(Option<Bytes>),
     Stream(JsStream),
 }
 
@@ -41,7 +53,7 @@ impl Default for Body {
 }
 
 impl HttpBody for Body {
-    type Data = Cursor<Box<[u8]>>;
+    type Data = Cursor<Bytes>;
     type Error = Error;
 
     fn poll_data(
@@ -49,9 +61,10 @@ impl HttpBody for Body {
         cx: &mut Context<'_>,
     ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
         let r = match self.get_mut() {
-            Body::Const(ref mut inner) => inner.take().map(|x| Ok(Cursor::new(x))),
+            Body::Const(ref mut inner) => inner.take().map(|x| Ok(Cursor::new(x.into()))),
+            Body::Shared(ref mut inner) => inner.take().map(|x| Ok(Cursor::new(x))),
             Body::Stream(ref mut stream) => {
-                ready!(stream.as_mut().poll_next(cx)).map(|x| x.map(Cursor::new))
+                ready!(stream.as_mut().poll_next(cx)).map(|x| x.map(|x| Cursor::new(x.into())))
             }
         };
         Poll::Ready(r)
@@ -223,7 +236,11 @@ impl ApiService {
 
     async fn route_impl(&self, req: Request<hyper::Body>) -> Result<Response<Body>> {
         if let Some(route_fn) = self.find_route_fn(req.uri().path()) {
-            return route_fn(req).await;
+            return match response_cache::lookup(&req) {
+                Lookup::Hit(response) => Ok(response),
+                Lookup::Miss(slot) => response_cache::store(slot, route_fn(req).await?).await,
+                Lookup::Uncacheable => route_fn(req).await,
+            };
         }
         ApiService::not_found()
     }
@@ -235,11 +252,16 @@ impl ApiService {
         }
     }
 
+    /// Runs the handlers of all API versions for an event on `topic`.
+    ///
+    /// A failing handler is retried up to `max_retries` times, backing off
+    /// exponentially, before the event is dropped for that handler.
     pub async fn handle_event(
         &self,
         topic: String,
         key: Option<Vec<u8>>,
         value: Option<Vec<u8>>,
+        max_retries: u32,
     ) -> Result<()> {
         let versions: Vec<String> = {
             let info = self.info.lock().unwrap();
@@ -252,8 +274,32 @@ impl ApiService {
             }
             let path = format!("/{}/{}", version, topic);
             if let Some(event_fn) = self.find_event_fn(&path) {
-                if let Err(err) = event_fn(key.clone(), value.clone()).await {
-                    println!("Warning: event handler for {} failed: {}", path, err);
+                let mut attempt = 0;
+                while let Err(err) = event_fn(key.clone(), value.clone()).await {
+                    if attempt == max_retries {
+                        println!(
+                            "Warning: event handler for {} failed, dropping the event: {}",
+                            path, err
+                        );
+                        notify(
+                            EventKind::EventDropped,
+                            &path,
+                            format!(
+                                "event handler for {} failed {} times, dropped the event: {}",
+                                path,
+                                attempt + 1,
+                                err
+                            ),
+                        );
+                        break;
+                    }
+                    let backoff = Duration::from_millis(100 << attempt.min(10));
+                    println!(
+                        "Warning: event handler for {} failed, retrying in {:?}: {}",
+                        path, backoff, err
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
                 }
             } else {
                 println!("Warning: event handler for {} not found.", path);
@@ -310,17 +356,151 @@ where
     }
 }
 
+/// HTTP settings of the API server.
+#[derive(Clone)]
+pub struct HttpConfig {
+    /// Accept HTTP/2 connections in addition to HTTP/1.1.
+    pub http2: bool,
+    /// Keep HTTP/1.1 connections open between requests.
+    pub keep_alive: bool,
+    /// Interval of TCP keep-alive probes and HTTP/2 keep-alive pings. An
+    /// HTTP/2 connection is closed if a ping isn't answered in this time.
+    pub keep_alive_timeout: Option<Duration>,
+    /// Maximum number of concurrent HTTP/2 streams per connection.
+    pub max_concurrent_streams: Option<u32>,
+    /// Maximum length of the queue of pending connections.
+    pub tcp_backlog: i32,
+    /// Terminate TLS with this configuration.
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl HttpConfig {
+    /// Loads the TLS configuration from a PEM certificate chain and a PEM
+    /// private key.
+    pub fn load_tls(&mut self, cert_path: &Path, key_path: &Path) -> Result<()> {
+        let cert_file = File::open(cert_path)
+            .with_context(|| format!("could not open {}", cert_path.display()))?;
+        let certs = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+            .with_context(|| format!("could not read certificates from {}", cert_path.display()))?
+            .into_iter()
+            .map(rustls::Certificate)
+            .collect::<Vec<_>>();
+        anyhow::ensure!(
+            !certs.is_empty(),
+            "no certificate found in {}",
+            cert_path.display()
+        );
+
+        let key_file = File::open(key_path)
+            .with_context(|| format!("could not open {}", key_path.display()))?;
+        let mut key_reader = BufReader::new(key_file);
+        let key = loop {
+            match rustls_pemfile::read_one(&mut key_reader)? {
+                Some(Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key)) => {
+                    break rustls::PrivateKey(key);
+                }
+                Some(_) => continue,
+                None => anyhow::bail!("no private key found in {}", key_path.display()),
+            }
+        };
+
+        let mut tls = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .context("invalid TLS certificate or key")?;
+        tls.alpn_protocols = if self.http2 {
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        } else {
+            vec![b"http/1.1".to_vec()]
+        };
+        self.tls = Some(Arc::new(tls));
+        Ok(())
+    }
+}
+
+/// Maximum number of TLS handshakes in progress per listening socket.
+const TLS_HANDSHAKE_CONCURRENCY: usize = 64;
+
+/// Turns a listener into a stream of TLS connections. Failed handshakes are
+/// logged and skipped, as they shouldn't stop the server.
+fn tls_incoming(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+) -> impl Stream<Item = std::io::Result<TlsStream<TcpStream>>> {
+    use futures::stream::StreamExt;
+
+    futures::stream::unfold(listener, |listener| async move {
+        let conn = listener.accept().await;
+        Some((conn, listener))
+    })
+    .map(move |conn| {
+        let acceptor = acceptor.clone();
+        async move { acceptor.accept(conn?.0).await }
+    })
+    .buffer_unordered(TLS_HANDSHAKE_CONCURRENCY)
+    .filter_map(|conn| async move {
+        match conn {
+            Ok(conn) => Some(Ok(conn)),
+            Err(e) => {
+                debug!("TLS connection failed: {}", e);
+                None
+            }
+        }
+    })
+}
+
 /// Spawn an API server
 ///
 /// # Arguments
 /// * `api` - the API service of the server
 /// * `listen_addr` - the listen address of the API server
+/// * `config` - HTTP settings of the API server
 /// * `shutdown` - channel that notifies the server of shutdown
 pub fn spawn(
     api: Rc<ApiService>,
     listen_addr: String,
+    config: &HttpConfig,
     shutdown: async_channel::Receiver<()>,
 ) -> Result<Vec<tokio::task::JoinHandle<Result<(), hyper::Error>>>> {
+    // The builders for plain and TLS connections have different types, so
+    // they can't share a function.
+    macro_rules! serve {
+        ($builder:expr) => {{
+            let make_svc = make_service_fn(move |_conn| {
+                let api = api.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req| {
+                        let api = api.clone();
+                        async move { api.route(req).await }
+                    }))
+                }
+            });
+            let mut builder = $builder
+                .executor(LocalExec)
+                .http1_keepalive(config.keep_alive)
+                .http2_max_concurrent_streams(config.max_concurrent_streams);
+            if !config.http2 {
+                builder = builder.http1_only(true);
+            }
+            if let Some(timeout) = config.keep_alive_timeout {
+                builder = builder
+                    .http2_keep_alive_interval(Some(timeout))
+                    .http2_keep_alive_timeout(timeout);
+            }
+            let server = builder.serve(make_svc);
+            tokio::task::spawn_local(async move {
+                let ret = server
+                    .with_graceful_shutdown(async {
+                        shutdown.recv().await.ok();
+                    })
+                    .await;
+                debug!("hyper shutdown");
+                ret
+            })
+        }};
+    }
+
     let mut tasks = Vec::new();
     let sock_addrs = listen_addr.to_socket_addrs()?;
     for addr in sock_addrs {
@@ -335,30 +515,20 @@ pub fn spawn(
         let sk = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
         let addr = socket2::SockAddr::from(addr);
         sk.set_reuse_port(true)?;
+        sk.set_keepalive(config.keep_alive_timeout)?;
         sk.bind(&addr)?;
-        sk.listen(1024)?;
-
-        let make_svc = make_service_fn(move |_conn| {
-            let api = api.clone();
-            async move {
-                Ok::<_, Infallible>(service_fn(move |req| {
-                    let api = api.clone();
-                    async move { api.route(req).await }
-                }))
+        sk.listen(config.tcp_backlog)?;
+        let listener = sk.into_tcp_listener();
+
+        let task = match &config.tls {
+            None => serve!(Server::from_tcp(listener)?),
+            Some(tls) => {
+                listener.set_nonblocking(true)?;
+                let listener = TcpListener::from_std(listener)?;
+                let incoming = tls_incoming(listener, TlsAcceptor::from(tls.clone()));
+                serve!(Server::builder(accept::from_stream(incoming)))
             }
-        });
-        let server = Server::from_tcp(sk.into_tcp_listener())?
-            .executor(LocalExec)
-            .serve(make_svc);
-        let task = tokio::task::spawn_local(async move {
-            let ret = server
-                .with_graceful_shutdown(async {
-                    shutdown.recv().await.ok();
-                })
-                .await;
-            debug!("hyper shutdown");
-            ret
-        });
+        };
         tasks.push(task);
     }
     Ok(tasks)
@@ -1,27 +1,45 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
 use crate::api::ApiInfo;
+use crate::datastore::engine::TableChange;
 use crate::datastore::{MetaService, QueryEngine};
+use crate::deno::endpoint_path_from_source_path;
 use crate::policies::{EntityPolicy, Policies, VersionPolicy};
 use crate::proto::{
-    type_msg::TypeEnum, ChiselApplyRequest, ContainerType, IndexCandidate, TypeMsg,
+    type_msg::TypeEnum, type_plan::Action, ApplyPlan, ChiselApplyRequest, ContainerType,
+    EndpointSchema, EnumType, IndexCandidate, TypeMsg, TypePlan, UnionType,
 };
-use crate::proto::{AddTypeRequest, FieldDefinition, PolicyUpdateRequest};
+use crate::proto::{
+    AddTypeRequest, ArchiveDefinition, ConnectorDefinition, FieldDefinition, PartitionDefinition,
+    PolicyUpdateRequest,
+};
+use crate::response_cache::CacheHint;
 use crate::types::{
-    DbIndex, Entity, Field, NewField, NewObject, ObjectType, Type, TypeSystem, TypeSystemError,
+    parse_enum_name, Archiving, Connector, DbIndex, Entity, Field, FieldAttrDelta, NamingStrategy,
+    NewField, NewObject, ObjectDelta, ObjectType, PartitionInterval, PartitionScheme, Partitioning,
+    Type, TypeId, TypeSystem, TypeSystemError,
 };
 use crate::FEATURES;
 use anyhow::{Context, Result};
 use petgraph::graphmap::GraphMap;
 use petgraph::Directed;
 use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 pub struct ApplyResult {
     pub type_names_user_order: Vec<String>,
     pub labels: Vec<String>,
     pub version_policy: VersionPolicy,
+    /// Caching hints of the entities, by name.
+    pub cache_hints: HashMap<String, CacheHint>,
+    /// The bodies the endpoints declare, by endpoint path.
+    pub endpoint_schemas: HashMap<String, EndpointSchema>,
+    /// What the apply changes, without the endpoints.
+    pub plan: ApplyPlan,
 }
 
 pub struct ParsedPolicies {
@@ -68,6 +86,19 @@ impl ParsedPolicies {
     }
 }
 
+/// Maximum number of transactions the table changes of an apply are spread
+/// over.
+static DDL_PARALLELISM: AtomicUsize = AtomicUsize::new(4);
+
+pub fn configure(ddl_parallelism: usize) -> Result<()> {
+    anyhow::ensure!(
+        ddl_parallelism > 0,
+        "the DDL parallelism must be at least 1"
+    );
+    DDL_PARALLELISM.store(ddl_parallelism, Ordering::Relaxed);
+    Ok(())
+}
+
 pub async fn apply(
     query_engine: &QueryEngine,
     meta: &MetaService,
@@ -129,11 +160,107 @@ or
         );
     }
     // if we got here, either the slice is empty anyway, or the user is forcing the deletion.
+
+    // The models that are kept can't relate to removed ones, so their fields that do are
+    // dropped, and the relations they hold are lost, unless there are none.
+    let removed_names: BTreeSet<&str> = to_remove
+        .iter()
+        .chain(to_remove_has_data.iter().map(|(ty, _)| ty))
+        .map(|ty| ty.name())
+        .collect();
+    let mut dependents = vec![];
+    for (name, ty) in version_types.custom_types.iter() {
+        if !type_names.contains(name) {
+            continue;
+        }
+        for field in ty.user_fields() {
+            let target = match &field.type_id {
+                TypeId::Entity { name, .. } => name,
+                TypeId::Union { names, .. } => {
+                    match names
+                        .iter()
+                        .find(|name| removed_names.contains(name.as_str()))
+                    {
+                        Some(name) => name,
+                        None => continue,
+                    }
+                }
+                TypeId::Array(elem_type) => match &**elem_type {
+                    TypeId::Entity { name, .. } => name,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            if !removed_names.contains(target.as_str()) {
+                continue;
+            }
+            let cnt = if field.type_id.is_many_to_many() {
+                meta.count_links(&mut transaction, ty, field).await?
+            } else {
+                meta.count_rows_with_values(&mut transaction, ty, std::slice::from_ref(field))
+                    .await?
+            };
+            if cnt > 0 {
+                dependents.push(format!(
+                    "\t{}, referred to by {}.{} ({} elements)",
+                    target, name, field.name, cnt
+                ));
+            }
+        }
+    }
+    if !dependents.is_empty() && !apply_request.cascade_type_deletion {
+        dependents.sort_unstable();
+        anyhow::bail!(
+            r"Trying to remove models that other models still refer to:
+{}
+
+Deleting them also drops the fields that refer to them. To proceed, try:
+
+'npx chisel apply --allow-type-deletion --cascade' (if installed from npm)
+
+or
+
+'chisel apply --allow-type-deletion --cascade' (otherwise)",
+            dependents.join("\n")
+        );
+    }
+    let mut drop_plans: Vec<_> = to_remove
+        .iter()
+        .map(|ty| (ty, 0))
+        .chain(to_remove_has_data.iter().map(|(ty, cnt)| (ty, *cnt)))
+        .map(|(ty, cnt)| TypePlan {
+            name: ty.name().to_owned(),
+            action: Action::Drop.into(),
+            rows_lost: cnt as u64,
+            ..Default::default()
+        })
+        .collect();
+    drop_plans.sort_unstable_by(|a, b| a.name.cmp(&b.name));
     to_remove.extend(to_remove_has_data.iter().map(|x| x.0.clone()));
 
+    let mut type_plans = vec![];
     let mut decorators = BTreeSet::default();
     let mut new_types = HashMap::<String, Entity>::default();
+    let mut cache_hints = HashMap::new();
     let indexes = aggregate_indexes(&apply_request.index_candidates);
+    let endpoint_schemas: HashMap<String, EndpointSchema> = apply_request
+        .endpoint_schemas
+        .iter()
+        .map(|schema| {
+            let path = endpoint_path_from_source_path(&format!("/{}/{}", api_version, schema.path));
+            let schema = EndpointSchema {
+                path: path.clone(),
+                ..schema.clone()
+            };
+            (path, schema)
+        })
+        .collect();
+
+    let naming = apply_request
+        .naming
+        .clone()
+        .map(NamingStrategy::from)
+        .unwrap_or_default();
 
     // No changes are made to the type system in this loop. We re-read the database after we
     // apply the changes, and this way we don't have to deal with the case of succeding to
@@ -143,14 +270,48 @@ or
         if type_system.lookup_builtin_type(&name).is_ok() {
             anyhow::bail!("custom type expected, got `{}` instead", name);
         }
+        if let Some(hint) = &type_def.cache {
+            cache_hints.insert(
+                name.clone(),
+                CacheHint {
+                    ttl: Duration::from_secs(hint.ttl),
+                    stale_while_revalidate: Duration::from_secs(hint.stale_while_revalidate),
+                },
+            );
+        }
 
+        // Tables and columns keep the names they were created with.
+        let old_type = version_types.lookup_custom_type(&name).ok();
         let mut fields = Vec::new();
         for field in type_def.field_defs {
             for label in &field.labels {
                 decorators.insert(label.clone());
             }
+            let column = field_column(&naming, &field, old_type.as_deref());
 
             let field_ty = field.field_type()?;
+            // The type doesn't exist yet, so a field referring to it, as in
+            // trees, only gets its type id.
+            if let Some(type_id) = field_ty.self_reference(&name, &api_version)? {
+                anyhow::ensure!(
+                    field.is_optional || type_id.is_many_to_many(),
+                    "field `{}` of `{}` refers to `{}` itself, so it must be optional",
+                    field.name,
+                    name,
+                    name
+                );
+                fields.push(
+                    Field::new(
+                        &NewField::new(&field.name, type_id, &api_version)?,
+                        field.labels,
+                        field.default_value,
+                        field.is_optional,
+                        field.is_unique,
+                    )
+                    .with_column(column),
+                );
+                continue;
+            }
             let field_ty = if field_ty.is_builtin(type_system)? {
                 field_ty.get_builtin(type_system)?
             } else if let TypeEnum::Entity(entity_name) = field_ty {
@@ -160,25 +321,132 @@ or
                         "field type `{entity_name}` is neither a built-in nor a custom type",
                     ),
                 }
+            } else if let TypeEnum::UnionType(UnionType { entities }) = field_ty {
+                let mut members = vec![];
+                for entity_name in entities {
+                    anyhow::ensure!(
+                        *entity_name != name,
+                        "field `{}` of `{}` can't refer to `{}` itself in a union",
+                        field.name,
+                        name,
+                        name
+                    );
+                    match new_types.get(entity_name) {
+                        Some(ty) => members.push(ty.clone()),
+                        None => anyhow::bail!(
+                            "field type `{field_ty}` is not a union of custom types, `{entity_name}` isn't one",
+                        ),
+                    }
+                }
+                Type::Union(members)
+            } else if let Some(entity_name) = field_ty.many_to_many_entity()? {
+                match new_types.get(entity_name) {
+                    Some(ty) => Type::Array(Box::new(Type::Entity(ty.clone()))),
+                    None => anyhow::bail!(
+                        "field type `{entity_name}[]` is not an array of a custom type",
+                    ),
+                }
             } else {
                 anyhow::bail!("field type must either contain an entity or be a builtin");
             };
 
-            fields.push(Field::new(
-                &NewField::new(&field.name, field_ty, &api_version)?,
-                field.labels,
-                field.default_value,
-                field.is_optional,
-                field.is_unique,
-            ));
+            match (&field_ty, &field.default_value) {
+                (Type::Json, Some(default)) => {
+                    serde_json::from_str::<serde_json::Value>(default).with_context(|| {
+                        format!("the default value of field `{}` is not JSON", field.name)
+                    })?;
+                }
+                (Type::File, Some(_)) => {
+                    anyhow::bail!("file field `{}` can't have a default value", field.name)
+                }
+                (Type::Enum(variants), Some(default)) => anyhow::ensure!(
+                    variants.contains(default),
+                    "the default value of field `{}` is not one of {}",
+                    field.name,
+                    field_ty.name()
+                ),
+                _ => {}
+            }
+            fields.push(
+                Field::new(
+                    &NewField::new(&field.name, field_ty, &api_version)?,
+                    field.labels,
+                    field.default_value,
+                    field.is_optional,
+                    field.is_unique,
+                )
+                .with_description(field.description)
+                .with_column(column),
+            );
+        }
+        let mut ty_indexes = indexes.get(&name).cloned().unwrap_or_default();
+        for index in type_def.indexes {
+            anyhow::ensure!(
+                index.counted || !index.fields.is_empty(),
+                "an index of `{}` has no fields",
+                name
+            );
+            let exists = ty_indexes.iter().any(|idx| {
+                idx.fields == index.fields && !idx.search && idx.counted == index.counted
+            });
+            if index.search {
+                ty_indexes.push(DbIndex::new_search(index.fields));
+            } else if exists {
+                continue;
+            } else if index.counted {
+                ty_indexes.push(DbIndex::new_counted(index.fields));
+            } else {
+                ty_indexes.push(DbIndex::new_from_fields(index.fields));
+            }
         }
-        let ty_indexes = indexes.get(&name).cloned().unwrap_or_default();
 
-        let ty = Arc::new(ObjectType::new(
-            &NewObject::new(&name, &api_version),
-            fields,
-            ty_indexes,
-        )?);
+        let partition = type_def.partition.map(Partitioning::try_from).transpose()?;
+        let archive = type_def.archive.map(Archiving::from);
+        let connector = type_def.connector.map(Connector::from);
+        let backing_table = match &old_type {
+            Some(old) => {
+                if let Some(table) = &type_def.table {
+                    anyhow::ensure!(
+                        table == old.backing_table(),
+                        "the table of `{}` can't change from `{}` to `{}`",
+                        name,
+                        old.backing_table(),
+                        table
+                    );
+                }
+                Some(old.backing_table().to_owned())
+            }
+            None => type_def.table.clone().or_else(|| naming.table(&name)),
+        };
+        let desc = match backing_table {
+            Some(table) => {
+                if old_type.is_none() {
+                    let owner = type_system
+                        .versions
+                        .values()
+                        .flat_map(|version| version.custom_types.values())
+                        .chain(new_types.values())
+                        .find(|ty| ty.backing_table() == table);
+                    if let Some(owner) = owner {
+                        anyhow::bail!(
+                            "table `{}` of `{}` is already the table of `{}`",
+                            table,
+                            name,
+                            owner.persisted_name()
+                        );
+                    }
+                }
+                NewObject::with_backing_table(&name, &api_version, table)
+            }
+            None => NewObject::new(&name, &api_version),
+        };
+        let ty = Arc::new(
+            ObjectType::new(&desc, fields, ty_indexes)?
+                .with_partition(partition)?
+                .with_archive(archive)?
+                .with_connector(connector)?
+                .with_description(type_def.description),
+        );
 
         let policy = entity_policies.remove(&name);
         new_types.insert(
@@ -191,23 +459,66 @@ or
 
         match version_types.lookup_custom_type(&name) {
             Ok(old_type) => {
-                let is_empty = meta.count_rows(&mut transaction, &old_type).await? == 0;
-                let delta = TypeSystem::generate_type_delta(&old_type, ty, type_system, is_empty)?;
+                let rows = meta.count_rows(&mut transaction, &old_type).await?;
+                let delta = TypeSystem::generate_type_delta(&old_type, ty, type_system, rows == 0)?;
+                let rows_lost = meta
+                    .count_rows_with_values(&mut transaction, &old_type, &delta.removed_fields)
+                    .await?;
+                let plan = alter_plan(&old_type, &delta, rows_lost as u64);
+                if !plan.changes.is_empty() || !plan.indexes.is_empty() {
+                    type_plans.push(plan);
+                }
                 to_update.push((old_type.clone(), delta));
             }
             Err(TypeSystemError::NoSuchType(_) | TypeSystemError::NoSuchVersion(_)) => {
+                type_plans.push(create_plan(&ty));
                 to_insert.push(ty.clone());
             }
             Err(e) => anyhow::bail!(e),
         }
     }
 
+    type_plans.extend(drop_plans);
+    let old_policy = meta
+        .load_policy_version(&mut transaction, &api_version)
+        .await?;
+    let plan = ApplyPlan {
+        types: type_plans,
+        policies_changed: old_policy.unwrap_or_default() != version_policy.1,
+        ..Default::default()
+    };
+    let labels: Vec<String> = version_policy
+        .0
+        .labels
+        .keys()
+        .map(|x| x.to_owned())
+        .collect();
+    if apply_request.dry_run {
+        return Ok(ApplyResult {
+            type_names_user_order,
+            labels,
+            version_policy: version_policy.0,
+            cache_hints,
+            endpoint_schemas,
+            plan,
+        });
+    }
+
     meta.persist_policy_version(&mut transaction, &api_version, &version_policy.1)
         .await?;
 
     meta.persist_api_info(&mut transaction, &api_version, api_info)
         .await?;
 
+    meta.persist_cache_hints(&mut transaction, &api_version, &cache_hints)
+        .await?;
+
+    meta.persist_endpoint_schemas(&mut transaction, &api_version, &endpoint_schemas)
+        .await?;
+
+    meta.persist_static_files(&mut transaction, &api_version, &apply_request.static_files)
+        .await?;
+
     for ty in to_insert.iter() {
         // FIXME: Consistency between metadata and backing store updates.
         meta.insert_type(&mut transaction, ty).await?;
@@ -224,12 +535,6 @@ or
 
     MetaService::commit_transaction(transaction).await?;
 
-    let labels: Vec<String> = version_policy
-        .0
-        .labels
-        .keys()
-        .map(|x| x.to_owned())
-        .collect();
     *type_system = meta.load_type_system().await?;
 
     policies
@@ -257,29 +562,212 @@ or
         })
         .collect::<Result<Vec<_>, _>>()?;
 
-    let mut transaction = query_engine.begin_transaction().await?;
-    for ty in to_insert.into_iter() {
-        query_engine.create_table(&mut transaction, &ty).await?;
-    }
-
-    for ty in to_remove.into_iter() {
-        query_engine.drop_table(&mut transaction, &ty).await?;
-    }
-
-    for (old, delta) in to_update.into_iter() {
-        query_engine
-            .alter_table(&mut transaction, &old, delta)
-            .await?;
-    }
-    QueryEngine::commit_transaction(transaction).await?;
+    let changes = to_insert
+        .into_iter()
+        .map(TableChange::Create)
+        .chain(to_remove.into_iter().map(TableChange::Drop))
+        .chain(
+            to_update
+                .into_iter()
+                .map(|(old, delta)| TableChange::Alter(old, delta)),
+        )
+        .collect();
+    query_engine
+        .change_tables(changes, DDL_PARALLELISM.load(Ordering::Relaxed))
+        .await?;
 
     Ok(ApplyResult {
         type_names_user_order,
         labels,
         version_policy: version_policy.0,
+        cache_hints,
+        endpoint_schemas,
+        plan,
     })
 }
 
+fn describe_field(
+    name: &str,
+    type_name: &str,
+    default: &Option<String>,
+    is_optional: bool,
+    is_unique: bool,
+) -> String {
+    let mut desc = format!(
+        "{}{}: {}",
+        name,
+        if is_optional { "?" } else { "" },
+        type_name
+    );
+    match default {
+        Some(default) if type_name == "string" || parse_enum_name(type_name).is_some() => {
+            write!(desc, " = {:?}", default).unwrap()
+        }
+        Some(default) => write!(desc, " = {}", default).unwrap(),
+        None => {}
+    }
+    if is_unique {
+        desc.push_str(" (unique)");
+    }
+    desc
+}
+
+impl Field {
+    fn describe(&self) -> String {
+        describe_field(
+            &self.name,
+            &self.type_id.name(),
+            self.user_provided_default(),
+            self.is_optional,
+            self.is_unique,
+        )
+    }
+}
+
+impl FieldAttrDelta {
+    fn describe(&self, name: &str) -> String {
+        describe_field(
+            name,
+            &self.type_id.name(),
+            &self.default,
+            self.is_optional,
+            self.is_unique,
+        )
+    }
+}
+
+fn create_plan(ty: &ObjectType) -> TypePlan {
+    TypePlan {
+        name: ty.name().to_owned(),
+        action: Action::Create.into(),
+        changes: ty
+            .user_fields()
+            .map(|field| format!("add {}", field.describe()))
+            .chain(
+                ty.many_to_many_fields()
+                    .map(|field| format!("create join table for {}", field.name)),
+            )
+            .chain(ty.partition().map(Partitioning::describe))
+            .chain(ty.archive().map(Archiving::describe))
+            .chain(ty.connector().map(Connector::describe))
+            .chain(
+                ty.indexes()
+                    .iter()
+                    .filter(|idx| idx.counted)
+                    .map(describe_counter),
+            )
+            .collect(),
+        indexes: ty
+            .indexes()
+            .iter()
+            .filter(|idx| !idx.counted)
+            .map(|idx| idx.fields.join(", "))
+            .collect(),
+        rows_lost: 0,
+    }
+}
+
+fn describe_counter(counter: &DbIndex) -> String {
+    match counter.fields.first() {
+        Some(field) => format!("count rows by {}", field),
+        None => "count rows".to_owned(),
+    }
+}
+
+fn alter_plan(old: &ObjectType, delta: &ObjectDelta, rows_lost: u64) -> TypePlan {
+    let mut changes = vec![];
+    for field in &delta.added_fields {
+        changes.push(format!("add {}", field.describe()));
+    }
+    for field in &delta.removed_fields {
+        changes.push(format!("drop {}", field.describe()));
+    }
+    for field in &delta.added_fields {
+        if field.type_id.is_many_to_many() {
+            changes.push(format!("create join table for {}", field.name));
+        }
+    }
+    for field in &delta.removed_fields {
+        if field.type_id.is_many_to_many() {
+            changes.push(format!("drop join table for {}", field.name));
+        }
+    }
+    for field_delta in &delta.updated_fields {
+        let old_field = match old.user_fields().find(|f| f.id == Some(field_delta.id)) {
+            Some(field) => field,
+            None => continue,
+        };
+        if let Some(attrs) = &field_delta.attrs {
+            changes.push(format!(
+                "alter {} -> {}",
+                old_field.describe(),
+                attrs.describe(&old_field.name)
+            ));
+        }
+        if let Some(labels) = &field_delta.labels {
+            changes.push(format!(
+                "relabel {}: [{}]",
+                old_field.name,
+                labels.join(", ")
+            ));
+        }
+    }
+    for idx in &delta.removed_indexes {
+        if idx.counted {
+            changes.push(format!("stop: {}", describe_counter(idx)));
+        } else {
+            changes.push(format!("drop index on ({})", idx.fields.join(", ")));
+        }
+    }
+    for idx in delta.added_indexes.iter().filter(|idx| idx.counted) {
+        changes.push(describe_counter(idx));
+    }
+    if let Some(partition) = &delta.partition {
+        changes.push(partition.describe());
+    }
+    match &delta.archive {
+        Some(Some(archive)) => changes.push(archive.describe()),
+        Some(None) => changes.push("stop archiving, restoring archived rows".to_owned()),
+        None => {}
+    }
+    match (&delta.connector, old.connector()) {
+        (Some(Some(connector)), _) => changes.push(connector.describe()),
+        (Some(None), Some(connector)) => {
+            changes.push(format!("stop fetching rows from {}", connector.url))
+        }
+        _ => {}
+    }
+    TypePlan {
+        name: old.name().to_owned(),
+        action: Action::Alter.into(),
+        changes,
+        indexes: delta
+            .added_indexes
+            .iter()
+            .filter(|idx| !idx.counted)
+            .map(|idx| idx.fields.join(", "))
+            .collect(),
+        rows_lost,
+    }
+}
+
+/// The column of `field` of entity `old_type`, if it exists: the one given
+/// by `@column`, else the one the field already has, else the one the naming
+/// strategy gives new fields.
+fn field_column(
+    naming: &NamingStrategy,
+    field: &FieldDefinition,
+    old_type: Option<&ObjectType>,
+) -> Option<String> {
+    if field.column.is_some() {
+        return field.column.clone();
+    }
+    match old_type.and_then(|ty| ty.get_field(&field.name)) {
+        Some(old) => old.column.clone(),
+        None => naming.column(&field.name),
+    }
+}
+
 fn aggregate_indexes(indexes: &Vec<IndexCandidate>) -> HashMap<String, Vec<DbIndex>> {
     let mut index_map = HashMap::<String, Vec<DbIndex>>::new();
     for candidate in indexes {
@@ -305,12 +793,17 @@ fn sort_custom_types(
         ty_pos.insert(ty.name.as_str(), pos);
         for field in &ty.field_defs {
             let field_type = field.field_type()?;
-            match field_type {
-                TypeEnum::Entity(name) if !field_type.is_builtin(ts)? => {
-                    graph.add_node(name);
-                    graph.add_edge(name, ty.name.as_str(), ());
+            let related: Vec<&str> = match field_type {
+                TypeEnum::Entity(name) if !field_type.is_builtin(ts)? => vec![name],
+                TypeEnum::UnionType(UnionType { entities }) => {
+                    entities.iter().map(String::as_str).collect()
                 }
-                _ => (),
+                _ => field_type.many_to_many_entity()?.into_iter().collect(),
+            };
+            // A type referring to itself doesn't depend on another type.
+            for name in related.into_iter().filter(|name| *name != ty.name) {
+                graph.add_node(name);
+                graph.add_edge(name, ty.name.as_str(), ());
             }
         }
     }
@@ -359,19 +852,57 @@ impl ContainerType {
 impl TypeEnum {
     fn is_builtin(&self, ts: &TypeSystem) -> Result<bool> {
         let is_builtin = match self {
-            TypeEnum::String(_) | TypeEnum::Number(_) | TypeEnum::Bool(_) => true,
+            TypeEnum::String(_)
+            | TypeEnum::Number(_)
+            | TypeEnum::Bool(_)
+            | TypeEnum::EnumType(_) => true,
+            TypeEnum::UnionType(_) => false,
             TypeEnum::Entity(name) => ts.lookup_builtin_type(name).is_ok(),
             TypeEnum::Array(inner) => inner.value_type()?.is_builtin(ts)?,
         };
         Ok(is_builtin)
     }
 
+    /// Returns the related entity if this is the type of a many-to-many
+    /// relation, an array of entities.
+    fn many_to_many_entity(&self) -> Result<Option<&str>> {
+        match self {
+            TypeEnum::Array(inner) => match inner.value_type()? {
+                TypeEnum::Entity(name) => Ok(Some(name)),
+                _ => Ok(None),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the type id of a field of this type if it refers to type `name`
+    /// of `api_version` that it belongs to, like `parent?: Category` or
+    /// `related: Category[]` in `Category`.
+    fn self_reference(&self, name: &str, api_version: &str) -> Result<Option<TypeId>> {
+        let entity = || TypeId::Entity {
+            name: name.to_owned(),
+            api_version: api_version.to_owned(),
+        };
+        Ok(match self {
+            TypeEnum::Entity(entity_name) if entity_name == name => Some(entity()),
+            _ if self.many_to_many_entity()? == Some(name) => {
+                Some(TypeId::Array(Box::new(entity())))
+            }
+            _ => None,
+        })
+    }
+
     fn get_builtin(&self, ts: &TypeSystem) -> Result<Type> {
         let ty = match self {
             TypeEnum::String(_) => Type::String,
             TypeEnum::Number(_) => Type::Float,
             TypeEnum::Bool(_) => Type::Boolean,
             TypeEnum::Entity(name) => ts.lookup_builtin_type(name)?,
+            TypeEnum::EnumType(EnumType { variants }) => {
+                anyhow::ensure!(!variants.is_empty(), "enum types must have variants");
+                Type::Enum(variants.clone())
+            }
+            TypeEnum::UnionType(_) => anyhow::bail!("unions of entities are not built-in types"),
             TypeEnum::Array(inner) => Type::Array(Box::new(inner.value_type()?.get_builtin(ts)?)),
         };
         Ok(ty)
@@ -384,7 +915,14 @@ impl From<Type> for TypeMsg {
             Type::Float => TypeEnum::Number(true),
             Type::String => TypeEnum::String(true),
             Type::Boolean => TypeEnum::Bool(true),
+            Type::DateTime => TypeEnum::Entity("Date".to_owned()),
+            Type::Json => TypeEnum::Entity("JSONValue".to_owned()),
+            Type::File => TypeEnum::Entity("ChiselFile".to_owned()),
+            Type::Enum(variants) => TypeEnum::EnumType(EnumType { variants }),
             Type::Entity(entity) => TypeEnum::Entity(entity.name().to_owned()),
+            Type::Union(entities) => TypeEnum::UnionType(UnionType {
+                entities: entities.iter().map(|ty| ty.name().to_owned()).collect(),
+            }),
             Type::Array(elem_type) => {
                 let inner_msg = (*elem_type).into();
                 TypeEnum::Array(Box::new(ContainerType {
@@ -397,3 +935,98 @@ impl From<Type> for TypeMsg {
         }
     }
 }
+
+impl TryFrom<PartitionDefinition> for Partitioning {
+    type Error = anyhow::Error;
+    fn try_from(def: PartitionDefinition) -> Result<Self> {
+        let scheme = match def.by.as_str() {
+            "time" => PartitionScheme::Time {
+                interval: PartitionInterval::parse(&def.interval)?,
+                retention: def.retention,
+            },
+            "hash" => PartitionScheme::Hash {
+                partitions: def.partitions,
+            },
+            by => anyhow::bail!("unknown partitioning `{}`, expected time or hash", by),
+        };
+        Ok(Partitioning {
+            field: def.field,
+            scheme,
+        })
+    }
+}
+
+impl From<&Partitioning> for PartitionDefinition {
+    fn from(partition: &Partitioning) -> Self {
+        let mut def = PartitionDefinition {
+            field: partition.field.clone(),
+            ..Default::default()
+        };
+        match partition.scheme {
+            PartitionScheme::Time {
+                interval,
+                retention,
+            } => {
+                def.by = "time".to_owned();
+                def.interval = interval.name().to_owned();
+                def.retention = retention;
+            }
+            PartitionScheme::Hash { partitions } => {
+                def.by = "hash".to_owned();
+                def.partitions = partitions;
+            }
+        }
+        def
+    }
+}
+
+impl From<crate::proto::NamingStrategy> for NamingStrategy {
+    fn from(naming: crate::proto::NamingStrategy) -> Self {
+        NamingStrategy {
+            table_prefix: naming.table_prefix,
+            snake_case: naming.snake_case,
+        }
+    }
+}
+
+impl From<ArchiveDefinition> for Archiving {
+    fn from(def: ArchiveDefinition) -> Self {
+        Archiving {
+            field: def.field,
+            after_days: def.after_days,
+        }
+    }
+}
+
+impl From<&Archiving> for ArchiveDefinition {
+    fn from(archive: &Archiving) -> Self {
+        ArchiveDefinition {
+            field: archive.field.clone(),
+            after_days: archive.after_days,
+        }
+    }
+}
+
+impl From<ConnectorDefinition> for Connector {
+    fn from(def: ConnectorDefinition) -> Self {
+        Connector {
+            url: def.url,
+            items: def.items,
+            fields: def.fields.into_iter().collect(),
+            ttl: def.ttl,
+            rate_limit: def.rate_limit,
+        }
+    }
+}
+
+impl From<&Connector> for ConnectorDefinition {
+    fn from(connector: &Connector) -> Self {
+        ConnectorDefinition {
+            url: connector.url.clone(),
+            items: connector.items.clone(),
+            fields: connector.fields.clone().into_iter().collect(),
+            ttl: connector.ttl,
+            rate_limit: connector.rate_limit,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Archiving of the old rows of entities declared with `@archive`.
+//!
+//! A task moves the rows that got old enough to the archive table of their
+//! entity when chiseld starts, and then periodically.
+
+use crate::datastore::{DbConnection, MetaService, QueryEngine};
+use anyhow::Result;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+/// How often rows are archived. Archiving is by days, so rows staying a bit
+/// longer in the backing table than they have to is fine.
+const ARCHIVING_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub(crate) fn spawn(
+    db: DbConnection,
+    shutdown: async_channel::Receiver<()>,
+) -> JoinHandle<Result<()>> {
+    tokio::task::spawn(async move {
+        loop {
+            if let Err(e) = archive_rows(&db).await {
+                warn!("Archiving failed: {:?}", e);
+            }
+            tokio::select! {
+                _ = sleep(ARCHIVING_INTERVAL) => {},
+                _ = shutdown.recv() => {
+                    break;
+                }
+            };
+        }
+        Ok(())
+    })
+}
+
+async fn archive_rows(db: &DbConnection) -> Result<()> {
+    let meta = MetaService::local_connection(db, 1).await?;
+    let query_engine = QueryEngine::local_connection(db, 1).await?;
+    let ts = meta.load_type_system().await?;
+    let now = OffsetDateTime::now_utc();
+    for version in ts.versions.values() {
+        for entity in version.custom_types.values() {
+            if entity.archive().is_none() {
+                continue;
+            }
+            // Each table in its own transaction, so that one failing doesn't
+            // hold back the others.
+            let mut transaction = query_engine.begin_transaction().await?;
+            match query_engine
+                .archive_rows(&mut transaction, entity, now)
+                .await
+            {
+                Ok(archived) => {
+                    QueryEngine::commit_transaction(transaction).await?;
+                    if archived > 0 {
+                        debug!("Archived {} rows of {}", archived, entity.name());
+                    }
+                }
+                Err(e) => warn!("Could not archive the rows of {}: {:?}", entity.name(), e),
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,242 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Static files, which `chisel apply` sends from the `static/` directory of
+//! the project, or the directories that the `static` key of its manifest
+//! lists. A version serves its files under `/<version>/static/`, so that a
+//! small frontend can be deployed with the API it uses. A version with
+//! static files can't have routes under `/static`, which would shadow them.
+//!
+//! A file is served with the content type that its extension implies, and
+//! with an `ETag`, which clients revalidate their copy with, as files
+//! change with every apply. A directory serves its `index.html`. Request
+//! paths are percent-decoded, so `a%20b.txt` serves `a b.txt`.
+
+use crate::api::{response_template, ApiService, Body};
+use crate::auth_provider::hex;
+use anyhow::Result;
+use deno_core::futures::FutureExt;
+use hyper::body::Bytes;
+use hyper::header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use hyper::{Method, Request, Response, StatusCode};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+struct StaticFile {
+    content: Bytes,
+    content_type: &'static str,
+    etag: String,
+}
+
+/// The static files of each version, by version and path under `static/`.
+static FILES: Lazy<Mutex<HashMap<String, HashMap<String, Arc<StaticFile>>>>> =
+    Lazy::new(Default::default);
+
+/// Replaces the static files of `version`, by path under `static/`.
+pub(crate) fn set_files(version: &str, files: HashMap<String, Vec<u8>>) {
+    let mut all = FILES.lock().unwrap();
+    if files.is_empty() {
+        all.remove(version);
+        return;
+    }
+    let files = files
+        .into_iter()
+        .map(|(path, content)| {
+            let file = StaticFile {
+                content_type: content_type(&path),
+                etag: format!("\"{}\"", &hex(&Sha256::digest(&content))[..32]),
+                content: content.into(),
+            };
+            (path, Arc::new(file))
+        })
+        .collect();
+    all.insert(version.to_owned(), files);
+}
+
+/// Whether `version` has static files to serve.
+pub(crate) fn has_files(version: &str) -> bool {
+    FILES.lock().unwrap().contains_key(version)
+}
+
+/// Serves the static files of every version that has some from `api`.
+pub(crate) fn add_routes(api: &ApiService) {
+    let versions: Vec<String> = FILES.lock().unwrap().keys().cloned().collect();
+    for version in versions {
+        add_route(api, &version);
+    }
+}
+
+/// Serves the static files of `version` from `api`.
+pub(crate) fn add_route(api: &ApiService, version: &str) {
+    let prefix = format!("/{}/static", version);
+    let version = version.to_owned();
+    api.add_route(
+        prefix.clone(),
+        Arc::new(move |req| {
+            let response = serve(&version, &prefix, req);
+            async move { response }.boxed_local()
+        }),
+    );
+}
+
+fn serve(version: &str, prefix: &str, req: Request<hyper::Body>) -> Result<Response<Body>> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return Ok(Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header("Allow", "GET, HEAD")
+            .body(Body::default())?);
+    }
+    let path = match req.uri().path().strip_prefix(prefix) {
+        // The prefix must end at a segment boundary: `/static/a`, but not
+        // `/staticky`.
+        Some(path) if path.is_empty() || path.starts_with('/') => path,
+        _ => return ApiService::not_found(),
+    };
+    let path = match percent_decode(path.trim_start_matches('/')) {
+        Some(path) => path,
+        None => return ApiService::not_found(),
+    };
+    let file = {
+        let files = FILES.lock().unwrap();
+        let files = files.get(version);
+        let lookup = |path: &str| files.and_then(|files| files.get(path)).cloned();
+        if path.is_empty() || path.ends_with('/') {
+            lookup(&format!("{}index.html", path))
+        } else {
+            lookup(&path)
+        }
+    };
+    let file = match file {
+        Some(file) => file,
+        None => return ApiService::not_found(),
+    };
+
+    let builder = response_template()
+        .header(CACHE_CONTROL, "no-cache")
+        .header(ETAG, &file.etag);
+    let fresh = req
+        .headers()
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == file.etag || tag.trim() == "*");
+    if fresh {
+        return Ok(builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::default())?);
+    }
+    let builder = builder
+        .header(CONTENT_TYPE, file.content_type)
+        .header(CONTENT_LENGTH, file.content.len());
+    let body = if req.method() == Method::HEAD {
+        Body::default()
+    } else {
+        Body::Shared(Some(file.content.clone()))
+    };
+    Ok(builder.body(body)?)
+}
+
+/// Decodes the percent-encoded bytes of URL path `path`, like `%20`. Returns
+/// None if the decoded path isn't UTF-8.
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit));
+        match hex {
+            Some(hex) => {
+                let hex = std::str::from_utf8(hex).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// The content type of the file at `path`, from its extension.
+fn content_type(path: &str) -> &'static str {
+    let extension = path
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_types() {
+        assert_eq!(content_type("index.html"), "text/html; charset=utf-8");
+        assert_eq!(
+            content_type("js/app.min.JS"),
+            "text/javascript; charset=utf-8"
+        );
+        assert_eq!(content_type("fonts/a.woff2"), "font/woff2");
+        assert_eq!(content_type("LICENSE"), "application/octet-stream");
+        assert_eq!(content_type(".well-known/x"), "application/octet-stream");
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("a%20b.txt").unwrap(), "a b.txt");
+        assert_eq!(percent_decode("caf%C3%A9/").unwrap(), "caf\u{e9}/");
+        assert_eq!(percent_decode("100%.txt").unwrap(), "100%.txt");
+        assert_eq!(percent_decode("%zz%4").unwrap(), "%zz%4");
+        assert_eq!(percent_decode("%FF"), None);
+    }
+
+    #[test]
+    fn serves_under_prefix() {
+        let files = HashMap::from([
+            ("a b.txt".to_owned(), b"spaced".to_vec()),
+            ("docs/index.html".to_owned(), b"docs".to_vec()),
+        ]);
+        set_files("assets_test", files);
+        let status = |path: &str| {
+            let req = Request::get(path).body(hyper::Body::empty()).unwrap();
+            serve("assets_test", "/assets_test/static", req)
+                .unwrap()
+                .status()
+        };
+        assert_eq!(status("/assets_test/static/a%20b.txt"), StatusCode::OK);
+        assert_eq!(status("/assets_test/static/docs/"), StatusCode::OK);
+        assert_eq!(
+            status("/assets_test/staticky/a%20b.txt"),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -17,14 +17,25 @@ pub const AUTH_USER_NAME: &str = "AuthUser";
 pub const AUTH_SESSION_NAME: &str = "AuthSession";
 pub const AUTH_TOKEN_NAME: &str = "AuthToken";
 pub const AUTH_ACCOUNT_NAME: &str = "AuthAccount";
+pub const AUTH_SCIM_USER_NAME: &str = "AuthScimUser";
+pub const AUTH_AUDIT_EVENT_NAME: &str = "AuthAuditEvent";
 
-const AUTH_ENTITY_NAMES: [&str; 4] = [
+const AUTH_ENTITY_NAMES: [&str; 6] = [
     AUTH_USER_NAME,
     AUTH_SESSION_NAME,
     AUTH_TOKEN_NAME,
     AUTH_ACCOUNT_NAME,
+    AUTH_SCIM_USER_NAME,
+    AUTH_AUDIT_EVENT_NAME,
 ];
 
+/// Path of the SCIM endpoints within the `__chiselstrike` version.
+pub const SCIM_PATH_PREFIX: &str = "/auth/scim/v2/";
+
+/// Path of the login route within a version whose manifest lists login
+/// providers.
+pub const LOGIN_ROUTE: &str = "/__auth";
+
 pub fn is_auth_entity_name(entity_name: &str) -> bool {
     AUTH_ENTITY_NAMES.contains(&entity_name)
 }
@@ -55,11 +66,24 @@ export default {type_name}.crud()"#
     crate::server::add_endpoints(sources, api).await
 }
 
+/// Adds the SCIM 2.0 provisioning endpoint. Requests to it are authorized
+/// by the `CHISELD_SCIM_TOKEN` secret instead of `CHISELD_AUTH_SECRET`.
+async fn add_scim_endpoint(api: &mut ApiService) -> Result<()> {
+    let mut sources = HashMap::new();
+    sources.insert(
+        format!("/__chiselstrike/routes{}Users", SCIM_PATH_PREFIX),
+        include_str!("scim.ts").to_owned(),
+    );
+    crate::server::add_endpoints(sources, api).await
+}
+
 pub async fn init(api: &mut ApiService) -> Result<()> {
     add_crud_endpoint_for_type(AUTH_USER_NAME, "users", api).await?;
     add_crud_endpoint_for_type(AUTH_SESSION_NAME, "sessions", api).await?;
     add_crud_endpoint_for_type(AUTH_TOKEN_NAME, "tokens", api).await?;
-    add_crud_endpoint_for_type(AUTH_ACCOUNT_NAME, "accounts", api).await
+    add_crud_endpoint_for_type(AUTH_ACCOUNT_NAME, "accounts", api).await?;
+    add_crud_endpoint_for_type(AUTH_AUDIT_EVENT_NAME, "audit", api).await?;
+    add_scim_endpoint(api).await
 }
 
 /// Extracts the username of the logged-in user, or None if there was no login.
@@ -0,0 +1,122 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Entry points into the query engine for the benchmarks of the `benchmarks`
+//! crate, which are only built with the `bench` feature.
+
+use crate::datastore::query::QueryPlan;
+use crate::datastore::{DbConnection, QueryEngine};
+use crate::types::{Entity, Field, NewField, NewObject, ObjectType, Type, TypeSystem};
+use anyhow::Result;
+use deno_core::futures::StreamExt;
+use serde_json::{json, Value as JsonValue};
+use std::sync::Arc;
+
+const VERSION: &str = "bench";
+
+/// A database, and the query engine over it.
+pub struct BenchDb {
+    engine: Arc<QueryEngine>,
+}
+
+/// An entity with `width` fields, alternately strings and numbers.
+pub struct BenchEntity {
+    entity: Entity,
+    ts: TypeSystem,
+}
+
+impl BenchEntity {
+    /// Makes an entity named `name`, with a backing table of its own.
+    pub fn new(name: &str, width: usize) -> Result<Self> {
+        let mut fields = vec![];
+        for i in 0..width {
+            let ty = if i % 2 == 0 {
+                Type::String
+            } else {
+                Type::Float
+            };
+            let field_name = format!("f{}", i);
+            let desc = NewField::new(&field_name, ty, VERSION)?;
+            fields.push(Field::new(&desc, vec![], None, false, false));
+        }
+        let desc = NewObject::new(name, VERSION);
+        let entity = Entity::Custom {
+            object: Arc::new(ObjectType::new(&desc, fields, vec![])?),
+            policy: None,
+        };
+        let mut ts = TypeSystem::default();
+        ts.add_custom_type(entity.clone())?;
+        Ok(Self { entity, ts })
+    }
+
+    /// The `i`-th row of the entity.
+    pub fn row(&self, i: usize) -> JsonValue {
+        let mut row = serde_json::Map::new();
+        for (idx, field) in self.entity.user_fields().enumerate() {
+            let value = if idx % 2 == 0 {
+                json!(format!("value {} of row {}", field.name, i))
+            } else {
+                json!(i)
+            };
+            row.insert(field.name.clone(), value);
+        }
+        JsonValue::Object(row)
+    }
+}
+
+impl BenchDb {
+    pub async fn connect(db_uri: &str) -> Result<Self> {
+        let conn = DbConnection::connect(db_uri, 1).await?;
+        let engine = QueryEngine::local_connection(&conn, 1).await?;
+        Ok(Self {
+            engine: Arc::new(engine),
+        })
+    }
+
+    pub async fn create_table(&self, entity: &BenchEntity) -> Result<()> {
+        let mut transaction = self.engine.begin_transaction().await?;
+        self.engine
+            .create_table(&mut transaction, &entity.entity)
+            .await?;
+        QueryEngine::commit_transaction(transaction).await
+    }
+
+    pub async fn drop_table(&self, entity: &BenchEntity) -> Result<()> {
+        let mut transaction = self.engine.begin_transaction().await?;
+        self.engine
+            .drop_table(&mut transaction, &entity.entity)
+            .await?;
+        QueryEngine::commit_transaction(transaction).await
+    }
+
+    /// Inserts `row` with `QueryEngine::add_row()`, as `Entity.save()` does.
+    pub async fn add_row(&self, entity: &BenchEntity, row: &JsonValue) -> Result<()> {
+        let row = row.as_object().expect("rows are objects");
+        self.engine
+            .add_row(&entity.entity, row, None, &entity.ts)
+            .await?;
+        Ok(())
+    }
+
+    /// Inserts `rows` with `QueryEngine::add_rows()`, as
+    /// `Entity.createMany()` does.
+    pub async fn add_rows(&self, entity: &BenchEntity, rows: &[JsonValue]) -> Result<()> {
+        self.engine
+            .add_rows(&entity.entity, rows, None, &entity.ts)
+            .await?;
+        Ok(())
+    }
+
+    /// Streams all the rows of `entity` out of the database, and returns how
+    /// many there are.
+    pub async fn select_all(&self, entity: &BenchEntity) -> Result<usize> {
+        let transaction = self.engine.clone().begin_transaction_static().await?;
+        let query_plan = QueryPlan::from_type(&entity.entity);
+        let mut rows = self.engine.query(transaction, query_plan)?;
+        let mut count = 0;
+        while let Some(row) = rows.next().await {
+            row?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
@@ -0,0 +1,384 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Storage of the content of files, which entities refer to with fields of
+//! type `ChiselFile`. The database only stores the metadata of a file, its
+//! key in the blob store, name, media type and size.
+//!
+//! The store is given with `--blob-store`, either a local directory, like
+//! the default `.chiseld-blobs` (a `file://` prefix is optional), or an
+//! S3-compatible bucket, like `s3://bucket/prefix?region=eu-west-1`. An
+//! `endpoint` parameter points to another S3-compatible service, like
+//! `s3://bucket?endpoint=http://localhost:9000`. The credentials are read
+//! from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`, and the bucket is
+//! addressed in the path, which all the S3-compatible services support.
+//!
+//! The content of a file is stored as soon as it is uploaded, and isn't
+//! deleted with the entities that refer to it: endpoints do that with
+//! `deleteFile()`.
+
+use crate::auth_provider::{hex, hmac_sha256};
+use anyhow::{anyhow, bail, Context, Result};
+use once_cell::sync::Lazy;
+use reqwest::{Method, StatusCode, Url};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// How long a request to an S3-compatible store may take.
+const S3_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What a field of type `ChiselFile` stores.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct FileMetadata {
+    pub key: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub media_type: String,
+    pub size: u64,
+}
+
+impl FileMetadata {
+    pub(crate) fn from_json(value: &JsonValue) -> Result<Self> {
+        let object = value.as_object().context("a file must be an object")?;
+        let string = |name: &str| -> Result<String> {
+            object
+                .get(name)
+                .and_then(JsonValue::as_str)
+                .map(str::to_owned)
+                .with_context(|| format!("a file must have a string `{}`", name))
+        };
+        let key = string("key")?;
+        check_key(&key)?;
+        let size = object
+            .get("size")
+            .and_then(JsonValue::as_f64)
+            .filter(|size| *size >= 0.0 && size.fract() == 0.0)
+            .context("a file must have a non-negative integer `size`")?;
+        Ok(Self {
+            key,
+            name: string("name")?,
+            media_type: string("type")?,
+            size: size as u64,
+        })
+    }
+}
+
+/// Keys are generated by the store, so anything else than what it generates
+/// is refused, instead of becoming a path or an object name.
+fn check_key(key: &str) -> Result<()> {
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        bail!("invalid file key `{}`", key);
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct S3Bucket {
+    endpoint: Url,
+    bucket: String,
+    prefix: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+}
+
+#[derive(Debug)]
+enum BlobStore {
+    Local(PathBuf),
+    S3(S3Bucket),
+}
+
+impl BlobStore {
+    fn parse(spec: &str) -> Result<Self> {
+        if !spec.starts_with("s3://") {
+            let dir = spec.strip_prefix("file://").unwrap_or(spec);
+            return Ok(Self::Local(PathBuf::from(dir)));
+        }
+        let url = Url::parse(spec).with_context(|| format!("invalid blob store {}", spec))?;
+        let bucket = url
+            .host_str()
+            .with_context(|| format!("blob store {} has no bucket", spec))?
+            .to_owned();
+        let mut prefix = url.path().trim_matches('/').to_owned();
+        if !prefix.is_empty() {
+            prefix.push('/');
+        }
+        let mut region = "us-east-1".to_owned();
+        let mut endpoint = None;
+        for (name, value) in url.query_pairs() {
+            match name.as_ref() {
+                "region" => region = value.into_owned(),
+                "endpoint" => {
+                    endpoint = Some(
+                        Url::parse(&value)
+                            .with_context(|| format!("invalid blob store endpoint {}", value))?,
+                    )
+                }
+                _ => bail!("unknown parameter `{}` of blob store {}", name, spec),
+            }
+        }
+        let endpoint = match endpoint {
+            Some(endpoint) => endpoint,
+            None => Url::parse(&format!("https://s3.{}.amazonaws.com", region))?,
+        };
+        let credential = |name: &str| {
+            std::env::var(name)
+                .with_context(|| format!("{} is needed by blob store {}", name, spec))
+        };
+        Ok(Self::S3(S3Bucket {
+            endpoint,
+            bucket,
+            prefix,
+            region,
+            access_key: credential("AWS_ACCESS_KEY_ID")?,
+            secret_key: credential("AWS_SECRET_ACCESS_KEY")?,
+        }))
+    }
+
+    async fn put(&self, key: &str, content: Vec<u8>) -> Result<()> {
+        match self {
+            Self::Local(dir) => {
+                let dir = dir.clone();
+                let key = key.to_owned();
+                tokio::task::spawn_blocking(move || -> Result<()> {
+                    std::fs::create_dir_all(&dir)?;
+                    // Renamed into place, so that a file is never read half-written.
+                    let tmp = dir.join(format!("{}.tmp", key));
+                    std::fs::write(&tmp, content)?;
+                    std::fs::rename(&tmp, dir.join(&key))?;
+                    Ok(())
+                })
+                .await?
+            }
+            Self::S3(bucket) => {
+                bucket.send(Method::PUT, key, content).await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        match self {
+            Self::Local(dir) => {
+                let path = dir.join(key);
+                tokio::task::spawn_blocking(move || std::fs::read(&path))
+                    .await?
+                    .map_err(|e| match e.kind() {
+                        std::io::ErrorKind::NotFound => anyhow!("file `{}` not found", key),
+                        _ => e.into(),
+                    })
+            }
+            Self::S3(bucket) => bucket.send(Method::GET, key, vec![]).await,
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match self {
+            Self::Local(dir) => {
+                let path = dir.join(key);
+                match tokio::task::spawn_blocking(move || std::fs::remove_file(&path)).await? {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                    _ => Ok(()),
+                }
+            }
+            Self::S3(bucket) => {
+                bucket.send(Method::DELETE, key, vec![]).await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl S3Bucket {
+    /// Sends a request about the object of `key`, signed with AWS Signature
+    /// Version 4, and returns the body of the response.
+    async fn send(&self, method: Method, key: &str, content: Vec<u8>) -> Result<Vec<u8>> {
+        let path = format!(
+            "{}/{}/{}{}",
+            self.endpoint.path().trim_end_matches('/'),
+            uri_encode(&self.bucket),
+            uri_encode(&self.prefix),
+            key
+        );
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => bail!("blob store endpoint {} has no host", self.endpoint),
+        };
+
+        let now = OffsetDateTime::now_utc();
+        let date = format!("{:04}{:02}{:02}", now.year(), now.month() as u8, now.day());
+        let amz_date = format!(
+            "{}T{:02}{:02}{:02}Z",
+            date,
+            now.hour(),
+            now.minute(),
+            now.second()
+        );
+        let content_sha256 = hex(&Sha256::digest(&content));
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, host, content_sha256, amz_date, signed_headers, content_sha256
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = signing_key(&self.secret_key, &date, &self.region, "s3");
+        let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature
+        );
+
+        let response = reqwest::Client::new()
+            .request(method.clone(), url)
+            .header("x-amz-content-sha256", content_sha256)
+            .header("x-amz-date", amz_date)
+            .header("authorization", authorization)
+            .body(content)
+            .timeout(S3_TIMEOUT)
+            .send()
+            .await?;
+        let status = response.status();
+        if method == Method::GET && status == StatusCode::NOT_FOUND {
+            bail!("file `{}` not found", path);
+        }
+        if !status.is_success() && !(method == Method::DELETE && status == StatusCode::NOT_FOUND) {
+            bail!(
+                "{} of {} failed with {}: {}",
+                method,
+                path,
+                status,
+                response.text().await.unwrap_or_default()
+            );
+        }
+        Ok(response.bytes().await?.to_vec())
+    }
+}
+
+/// The key that Signature Version 4 signs with on `date` (`YYYYMMDD`).
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> [u8; 32] {
+    let key = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+/// Percent-encodes `s` as Signature Version 4 canonical URIs need, keeping
+/// the `/`.
+fn uri_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+static STORE: Lazy<Mutex<Option<Arc<BlobStore>>>> = Lazy::new(Default::default);
+
+pub(crate) fn configure(spec: &str) -> Result<()> {
+    *STORE.lock().unwrap() = Some(Arc::new(BlobStore::parse(spec)?));
+    Ok(())
+}
+
+fn store() -> Result<Arc<BlobStore>> {
+    STORE
+        .lock()
+        .unwrap()
+        .clone()
+        .context("no blob store is configured")
+}
+
+/// Stores `content` as a new file, and returns what refers to it.
+pub(crate) async fn put(
+    name: String,
+    media_type: String,
+    content: Vec<u8>,
+) -> Result<FileMetadata> {
+    let key = uuid::Uuid::new_v4().to_string();
+    let size = content.len() as u64;
+    store()?
+        .put(&key, content)
+        .await
+        .with_context(|| format!("could not store file `{}`", name))?;
+    Ok(FileMetadata {
+        key,
+        name,
+        media_type,
+        size,
+    })
+}
+
+pub(crate) async fn get(key: &str) -> Result<Vec<u8>> {
+    check_key(key)?;
+    store()?.get(key).await
+}
+
+pub(crate) async fn delete(key: &str) -> Result<()> {
+    check_key(key)?;
+    store()?.delete(key).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn aws_signing_key() {
+        // The example of the AWS documentation of Signature Version 4.
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn metadata() {
+        let file = json!({"key": "3f2a-9c", "name": "a.png", "type": "image/png", "size": 12});
+        assert_eq!(
+            FileMetadata::from_json(&file).unwrap(),
+            FileMetadata {
+                key: "3f2a-9c".to_owned(),
+                name: "a.png".to_owned(),
+                media_type: "image/png".to_owned(),
+                size: 12,
+            }
+        );
+        let file = json!({"key": "../secrets", "name": "a", "type": "text/plain", "size": 1});
+        assert!(FileMetadata::from_json(&file).is_err());
+        let file = json!({"key": "abc", "name": "a", "type": "text/plain", "size": 1.5});
+        assert!(FileMetadata::from_json(&file).is_err());
+    }
+
+    #[test]
+    fn stores() {
+        assert!(matches!(
+            BlobStore::parse("file:///var/blobs").unwrap(),
+            BlobStore::Local(dir) if dir == PathBuf::from("/var/blobs")
+        ));
+        assert!(BlobStore::parse("s3://bucket?color=red").is_err());
+    }
+}
@@ -0,0 +1,125 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Fetching of the rows of entities declared with `@connector` from their
+//! external API.
+//!
+//! The rows are fetched again into the backing table of an entity when a
+//! query reads them after its connector's TTL is over. Fetches are rate
+//! limited per entity. Queries over the limit, or whose fetch fails, read
+//! the rows fetched last, unless none were fetched yet.
+
+use crate::datastore::QueryEngine;
+use crate::types::{Connector, Entity};
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The period the rate limits of connectors are over.
+const RATE_PERIOD: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct ConnectorState {
+    /// When the rows in the table were fetched.
+    fetched: Option<Instant>,
+    /// When the fetches of the last `RATE_PERIOD` started.
+    fetches: VecDeque<Instant>,
+}
+
+/// The state of the connector of each entity, by backing table and URL, so
+/// that changing the URL fetches the rows again.
+static STATES: Lazy<Mutex<HashMap<(String, String), ConnectorState>>> = Lazy::new(Default::default);
+
+/// Held while rows are fetched, so that the queries waiting on a fetch don't
+/// start others.
+static FETCHING: Lazy<async_lock::Mutex<()>> = Lazy::new(Default::default);
+
+static HTTP: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
+
+/// Fetches the rows of `entities` whose TTL is over.
+pub(crate) async fn refresh(engine: QueryEngine, entities: Vec<Entity>) -> Result<()> {
+    for ty in &entities {
+        if let Some(connector) = ty.connector() {
+            refresh_entity(&engine, ty, connector).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn refresh_entity(engine: &QueryEngine, ty: &Entity, connector: &Connector) -> Result<()> {
+    let _fetching = FETCHING.lock().await;
+    let now = Instant::now();
+    let ever_fetched = {
+        let mut states = STATES.lock().unwrap();
+        let state = states.entry(state_key(ty, connector)).or_default();
+        let ttl = Duration::from_secs(connector.ttl);
+        if state.fetched.map_or(false, |fetched| now - fetched < ttl) {
+            return Ok(());
+        }
+        while let Some(start) = state.fetches.front() {
+            if now - *start < RATE_PERIOD {
+                break;
+            }
+            state.fetches.pop_front();
+        }
+        if state.fetches.len() >= connector.rate_limit as usize {
+            anyhow::ensure!(
+                state.fetched.is_some(),
+                "the connector of {} is over its rate limit of {} fetches a minute",
+                ty.name(),
+                connector.rate_limit
+            );
+            return Ok(());
+        }
+        state.fetches.push_back(now);
+        state.fetched.is_some()
+    };
+
+    let fetched = async {
+        let rows = fetch_rows(ty, connector).await?;
+        engine.replace_rows(ty, &rows).await
+    };
+    match fetched.await {
+        Ok(()) => {
+            let mut states = STATES.lock().unwrap();
+            if let Some(state) = states.get_mut(&state_key(ty, connector)) {
+                state.fetched = Some(now);
+            }
+            Ok(())
+        }
+        Err(e) if ever_fetched => {
+            warn!(
+                "Could not fetch the rows of {}, serving the ones fetched last: {:?}",
+                ty.name(),
+                e
+            );
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn state_key(ty: &Entity, connector: &Connector) -> (String, String) {
+    (ty.backing_table().to_owned(), connector.url.clone())
+}
+
+async fn fetch_rows(ty: &Entity, connector: &Connector) -> Result<Vec<crate::JsonObject>> {
+    let context = || {
+        format!(
+            "failed to fetch the rows of {} from {}",
+            ty.name(),
+            connector.url
+        )
+    };
+    let response = HTTP
+        .get(&connector.url)
+        .header("accept", "application/json")
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(context)?;
+    let body = response.bytes().await.with_context(context)?;
+    let body = serde_json::from_slice(&body).with_context(context)?;
+    connector.rows(ty, &body)
+}
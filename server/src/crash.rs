@@ -0,0 +1,259 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Crash reports of chiseld.
+//!
+//! When chiseld panics or exits with an error, it writes a report to
+//! `--crash-dir` as JSON, with the message, a backtrace, the last lines it
+//! logged and the requests it was running. `chisel doctor` shows the
+//! recent reports. With `--crash-report-url`, reports are also POSTed
+//! there.
+//!
+//! Only the first crash of a process is reported: a panic on an executor
+//! thread brings chiseld down with more panics, which add nothing.
+
+use crate::inflight;
+use crate::types::datetime;
+use anyhow::Result;
+use log::{Log, Metadata, Record};
+use once_cell::sync::{Lazy, OnceCell};
+use serde_derive::Serialize;
+use std::collections::VecDeque;
+use std::panic::PanicInfo;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use time::OffsetDateTime;
+
+/// How many of the last lines logged a report has.
+const LOG_LINES: usize = 200;
+
+/// How long sending a report to `--crash-report-url` may take.
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+static RECENT_LOG: Lazy<Mutex<VecDeque<String>>> = Lazy::new(Default::default);
+
+struct Config {
+    dir: PathBuf,
+    url: Option<String>,
+}
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+static CRASHED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Serialize)]
+struct CrashReport {
+    time: String,
+    version: &'static str,
+    /// `panic` or `error`.
+    kind: &'static str,
+    message: String,
+    /// Where the panic happened.
+    location: Option<String>,
+    thread: Option<String>,
+    backtrace: String,
+    log: Vec<String>,
+    requests: Vec<ActiveRequest>,
+}
+
+#[derive(Debug, Serialize)]
+struct ActiveRequest {
+    id: u64,
+    version: String,
+    method: String,
+    path: String,
+    age_ms: u128,
+    sql: Option<String>,
+}
+
+/// A logger that keeps the last lines it logs for crash reports, besides
+/// logging them with env_logger.
+struct RecordingLogger {
+    inner: env_logger::Logger,
+}
+
+impl Log for RecordingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.matches(record) {
+            let line = format!(
+                "{} {} - {}",
+                datetime::format(OffsetDateTime::now_utc()),
+                record.level(),
+                record.args()
+            );
+            // Don't wait on a lock that a panicking thread may hold.
+            if let Ok(mut log) = RECENT_LOG.try_lock() {
+                if log.len() == LOG_LINES {
+                    log.pop_front();
+                }
+                log.push_back(line);
+            }
+        }
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Installs the logger of `builder`, keeping the last lines logged for crash
+/// reports.
+pub fn init_logger(mut builder: env_logger::Builder) -> Result<()> {
+    let inner = builder.build();
+    log::set_max_level(inner.filter());
+    log::set_boxed_logger(Box::new(RecordingLogger { inner }))?;
+    Ok(())
+}
+
+/// Writes a crash report when chiseld panics.
+pub fn install_crash_handler(dir: PathBuf, url: Option<String>) {
+    if CONFIG.set(Config { dir, url }).is_err() {
+        return;
+    }
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if !CRASHED.swap(true, Ordering::SeqCst) {
+            report(panic_report(info));
+        }
+        default_hook(info);
+    }));
+}
+
+/// Writes a crash report for the error chiseld exits with.
+pub fn report_error(error: &anyhow::Error) {
+    if !CRASHED.swap(true, Ordering::SeqCst) {
+        report(new_report("error", format!("{:?}", error)));
+    }
+}
+
+fn panic_report(info: &PanicInfo) -> CrashReport {
+    let payload = info.payload();
+    let message = if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Box<dyn Any>".to_owned()
+    };
+    let mut report = new_report("panic", message);
+    report.location = info.location().map(|l| l.to_string());
+    report
+}
+
+fn new_report(kind: &'static str, message: String) -> CrashReport {
+    let log = match RECENT_LOG.try_lock() {
+        Ok(log) => log.iter().cloned().collect(),
+        Err(_) => vec![],
+    };
+    let requests = inflight::try_list()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|r| ActiveRequest {
+            id: r.id,
+            version: r.api_version,
+            method: r.method,
+            path: r.path,
+            age_ms: r.age.as_millis(),
+            sql: r.sql,
+        })
+        .collect();
+    CrashReport {
+        time: datetime::format(OffsetDateTime::now_utc()),
+        version: env!("VERGEN_GIT_SEMVER_LIGHTWEIGHT"),
+        kind,
+        message,
+        location: None,
+        thread: std::thread::current().name().map(str::to_owned),
+        backtrace: format!("{:?}", backtrace::Backtrace::new()),
+        log,
+        requests,
+    }
+}
+
+fn report(report: CrashReport) {
+    let config = match CONFIG.get() {
+        Some(config) => config,
+        None => return,
+    };
+    let json = match serde_json::to_string_pretty(&report) {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("Could not serialize the crash report: {}", e);
+            return;
+        }
+    };
+    match write_report(&config.dir, &json) {
+        Ok(path) => eprintln!("Crash report written to {}", path.display()),
+        Err(e) => eprintln!("Could not write the crash report: {:?}", e),
+    }
+    if let Some(url) = &config.url {
+        if let Err(e) = send_report(url.clone(), json) {
+            eprintln!("Could not send the crash report to {}: {:?}", url, e);
+        }
+    }
+}
+
+fn write_report(dir: &Path, json: &str) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = dir.join(format!("crash-{}.json", millis));
+    std::fs::write(&path, json)?;
+    Ok(path)
+}
+
+/// Sends the report from a thread of its own, since the crashing thread may
+/// be running a Tokio runtime, which can't block on another.
+fn send_report(url: String, json: String) -> Result<()> {
+    std::thread::spawn(move || -> Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        runtime.block_on(async {
+            reqwest::Client::new()
+                .post(url)
+                .header("Content-Type", "application/json")
+                .body(json)
+                .timeout(SEND_TIMEOUT)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    })
+    .join()
+    .map_err(|_| anyhow::anyhow!("the thread sending the report panicked"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn written_report() {
+        RECENT_LOG
+            .lock()
+            .unwrap()
+            .push_back("INFO - started".to_owned());
+        let report = new_report("error", "could not bind".to_owned());
+        let json = serde_json::to_string(&report).unwrap();
+        let tmp_dir = TempDir::new("crash_report").unwrap();
+        let dir = tmp_dir.path().join("crashes");
+        let path = write_report(&dir, &json).unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("crash-") && name.ends_with(".json"));
+
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written["kind"], "error");
+        assert_eq!(written["message"], "could not bind");
+        assert_eq!(written["log"][0], "INFO - started");
+        assert!(!written["backtrace"].as_str().unwrap().is_empty());
+    }
+}
@@ -2,8 +2,10 @@
 
 use crate::datastore::engine::{QueryEngine, TransactionStatic};
 use crate::datastore::expr::{BinaryExpr, BinaryOp, Expr, PropertyAccess, Value as ExprValue};
-use crate::datastore::query::{Mutation, QueryOp, QueryPlan, RequestContext, SortBy, SortKey};
-use crate::types::{Entity, Type, TypeSystem};
+use crate::datastore::query::{
+    Aggregation, Mutation, QueryOp, QueryPlan, RequestContext, SortBy, SortKey,
+};
+use crate::types::{datetime, Entity, Type, TypeSystem};
 use crate::JsonObject;
 use anyhow::{Context, Result};
 use deno_core::futures;
@@ -18,6 +20,32 @@ pub struct QueryParams {
     #[serde(rename = "typeName")]
     type_name: String,
     url: Url,
+    #[serde(default)]
+    pagination: Pagination,
+}
+
+/// How a CRUD query returns its page of entities.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pagination {
+    /// `{ results, next_page, prev_page }`, with the URLs of the pages
+    /// around the page.
+    Pages,
+    /// `{ items, next_cursor, total }`, with the cursor of the next page if
+    /// there may be one, and the number of entities if it is cheap to know.
+    Envelope,
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Pagination::Pages
+    }
+}
+
+impl QueryParams {
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
 }
 
 /// Parses CRUD `params` and runs the query with provided `query_engine`.
@@ -47,6 +75,15 @@ fn run_query_impl(
     let ops = query.make_query_ops()?;
     let query_plan = QueryPlan::from_ops(context, base_type, ops)?;
     let stream = query_engine.query(tr.clone(), query_plan)?;
+    // All the entities can be counted cheaply if the query has no filters
+    // but its cursor and there is a counter of them.
+    let unfiltered = query.filters.len() == usize::from(query.cursor.is_some());
+    let count_plan = if params.pagination == Pagination::Envelope && unfiltered {
+        let plan = QueryPlan::from_ops(context, base_type, vec![])?;
+        plan.has_counter().then(|| plan)
+    } else {
+        None
+    };
 
     Ok(async move {
         let mut results = stream
@@ -64,6 +101,30 @@ fn run_query_impl(
             }
         }
 
+        if params.pagination == Pagination::Envelope {
+            let count = results.len() as u64;
+            let mut ret = JsonObject::new();
+            if count >= query.page_size && count > 0 {
+                let pivot = results.last().unwrap();
+                let cursor = cursor_from_pivot(&query, pivot, true)?;
+                ret.insert("next_cursor".into(), json!(cursor.to_string()?));
+            }
+            if query.cursor.is_none() && query.offset.is_none() && count < query.page_size {
+                ret.insert("total".into(), json!(count));
+            } else if let Some(count_plan) = count_plan {
+                let aggregation = Aggregation {
+                    count: true,
+                    ..Default::default()
+                };
+                let counted = query_engine
+                    .query_aggregate(tr, count_plan, aggregation)?
+                    .await?;
+                ret.insert("total".into(), counted["count"].clone());
+            }
+            ret.insert("items".into(), json!(results));
+            return Ok(ret);
+        }
+
         let mut ret = JsonObject::new();
 
         let next_page = get_next_page(&params, &query, &host, &results)?;
@@ -138,20 +199,7 @@ fn cursor_from_pivot(query: &Query, pivot_element: &JsonObject, forward: bool) -
     } else {
         query.sort.keys.clone()
     };
-    assert!(!sort_keys.is_empty());
-
-    let mut axes = vec![];
-    for key in sort_keys {
-        let value = pivot_element
-            .get(&key.field_name)
-            .cloned()
-            .with_context(|| {
-                format!("failed to create cursor axis for field `{}", key.field_name)
-            })?;
-        axes.push(CursorAxis { key, value });
-    }
-    let cursor = Cursor::new(axes, forward);
-    Ok(cursor)
+    Cursor::from_pivot(sort_keys, pivot_element, forward)
 }
 
 /// Generates URL that can be used to retrieve previous/next page.
@@ -282,7 +330,7 @@ impl Query {
     }
 }
 
-fn ensure_sort_by_id(sort: &mut SortBy) {
+pub(crate) fn ensure_sort_by_id(sort: &mut SortBy) {
     if !sort.keys.iter().any(|k| k.field_name == "id") {
         sort.keys.push(SortKey {
             field_name: "id".into(),
@@ -291,8 +339,10 @@ fn ensure_sort_by_id(sort: &mut SortBy) {
     }
 }
 
+/// Keyset cursor pointing in between two elements of a sorted result set.
+/// It is handed to clients as an opaque base64 encoded string.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct Cursor {
+pub(crate) struct Cursor {
     axes: Vec<CursorAxis>,
     forward: bool,
     inclusive: bool,
@@ -314,8 +364,30 @@ impl Cursor {
         }
     }
 
+    /// Makes a cursor pointing after (or before, if not `forward`) the
+    /// `pivot_element` in the sort given by `sort_keys`.
+    pub(crate) fn from_pivot(
+        sort_keys: Vec<SortKey>,
+        pivot_element: &JsonObject,
+        forward: bool,
+    ) -> Result<Self> {
+        assert!(!sort_keys.is_empty());
+
+        let mut axes = vec![];
+        for key in sort_keys {
+            let value = pivot_element
+                .get(&key.field_name)
+                .cloned()
+                .with_context(|| {
+                    format!("failed to create cursor axis for field `{}", key.field_name)
+                })?;
+            axes.push(CursorAxis { key, value });
+        }
+        Ok(Cursor::new(axes, forward))
+    }
+
     /// Parses Cursor from base64 encoded JSON.
-    fn from_string(cursor_str: &str) -> Result<Self> {
+    pub(crate) fn from_string(cursor_str: &str) -> Result<Self> {
         let cursor_json = base64::decode(cursor_str)
             .context("Failed to decode cursor from base64 encoded string")?;
         let cursor: Cursor =
@@ -325,7 +397,7 @@ impl Cursor {
     }
 
     /// Serializes cursor to base64 encoded JSON.
-    fn to_string(&self) -> Result<String> {
+    pub(crate) fn to_string(&self) -> Result<String> {
         let cursor = serde_json::to_string(&self).context("failed to serialize cursor to json")?;
         Ok(base64::encode(cursor))
     }
@@ -338,7 +410,7 @@ impl Cursor {
         }
     }
 
-    fn get_sort(&self) -> SortBy {
+    pub(crate) fn get_sort(&self) -> SortBy {
         SortBy {
             keys: self
                 .axes
@@ -355,7 +427,7 @@ impl Cursor {
     /// The crux of this function is using the sort axes (each axis represents one dimension
     /// in lexicographical sort) to create a filter that filters for entries that are after
     /// the last element in the given sort.
-    fn get_filter(&self, base_type: &Entity, ts: &TypeSystem) -> Result<Expr> {
+    pub(crate) fn get_filter(&self, base_type: &Entity, ts: &TypeSystem) -> Result<Expr> {
         let mut cmp_pairs: Vec<(Expr, BinaryOp, Expr)> = vec![];
         for axis in &self.axes {
             let op = if axis.key.ascending == self.forward {
@@ -405,13 +477,19 @@ fn json_to_value(field_type: &Type, value: &serde_json::Value) -> Result<Expr> {
         }};
     }
     let expr_val = match field_type {
-        Type::Entity(_) | Type::Array(_) => anyhow::bail!(
-            "trying to filter by property of type '{}' which is not supported",
-            field_type.name()
-        ),
-        Type::String => ExprValue::String(convert!(as_str, "string")),
+        Type::Entity(_) | Type::Union(_) | Type::Array(_) | Type::Json | Type::File => {
+            anyhow::bail!(
+                "trying to filter by property of type '{}' which is not supported",
+                field_type.name()
+            )
+        }
+        Type::String | Type::Enum(_) => ExprValue::String(convert!(as_str, "string")),
         Type::Float => ExprValue::F64(convert!(as_f64, "float")),
         Type::Boolean => ExprValue::Bool(convert!(as_bool, "bool")),
+        Type::DateTime => ExprValue::String(
+            datetime::from_json(value)
+                .with_context(|| format!("failed to convert filter value '{}' to Date", value))?,
+        ),
     };
     Ok(expr_val.into())
 }
@@ -479,14 +557,19 @@ fn filter_from_param(
 
     let err_msg = |ty_name| format!("failed to convert filter value '{}' to {}", value, ty_name);
     let expr_value = match &field_type {
-        Type::String => ExprValue::String(value.to_owned()),
+        Type::String | Type::Enum(_) => ExprValue::String(value.to_owned()),
         Type::Float => ExprValue::F64(value.parse::<f64>().with_context(|| err_msg("f64"))?),
         Type::Boolean => ExprValue::Bool(value.parse::<bool>().with_context(|| err_msg("bool"))?),
-        Type::Entity(_) | Type::Array(_) => anyhow::bail!(
-            "trying to filter by property '{}' of type '{}' which is not supported",
-            fields.last().unwrap(),
-            field_type.name()
-        ),
+        Type::DateTime => {
+            ExprValue::String(datetime::from_str(value).with_context(|| err_msg("Date"))?)
+        }
+        Type::Entity(_) | Type::Union(_) | Type::Array(_) | Type::Json | Type::File => {
+            anyhow::bail!(
+                "trying to filter by property '{}' of type '{}' which is not supported",
+                fields.last().unwrap(),
+                field_type.name()
+            )
+        }
     };
 
     Ok(BinaryExpr::new(operator, property_chain, expr_value.into()).into())
@@ -577,7 +660,7 @@ mod tests {
         VERSION,
     };
     use crate::policies::Policies;
-    use crate::types::{FieldDescriptor, ObjectDescriptor};
+    use crate::types::{FieldDescriptor, ObjectDescriptor, TypeId};
     use crate::JsonObject;
 
     use itertools::Itertools;
@@ -597,8 +680,8 @@ mod tests {
         fn id(&self) -> Option<i32> {
             None
         }
-        fn ty(&self) -> Type {
-            self.ty.clone()
+        fn type_id(&self) -> TypeId {
+            self.ty.clone().into()
         }
         fn api_version(&self) -> String {
             "whatever".to_string()
@@ -772,6 +855,16 @@ mod tests {
         url: Url,
         qe: &QueryEngine,
         headers: HashMap<String, String>,
+    ) -> Result<JsonObject> {
+        run_query_paginated(entity_name, url, qe, headers, Pagination::Pages).await
+    }
+
+    async fn run_query_paginated(
+        entity_name: &str,
+        url: Url,
+        qe: &QueryEngine,
+        headers: HashMap<String, String>,
+        pagination: Pagination,
     ) -> Result<JsonObject> {
         let qe = Arc::new(qe.clone());
         let tr = qe.clone().begin_transaction_static().await.unwrap();
@@ -781,12 +874,14 @@ mod tests {
                 ts: &make_type_system(&*ENTITIES),
                 api_version: VERSION.to_owned(),
                 user_id: None,
+                tenant: None,
                 path: "".to_string(),
                 headers,
             },
             QueryParams {
                 type_name: entity_name.to_owned(),
                 url,
+                pagination,
             },
             qe,
             tr,
@@ -1055,6 +1150,45 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_envelope() {
+        let (query_engine, _db_file) = setup_clear_db(&*ENTITIES).await;
+        let qe = &query_engine;
+        for name in ["Alan", "Alex", "John"] {
+            let person = json!({"name": name, "age": json!(30f32)});
+            add_row(qe, &PERSON_TY, &person, &TYPE_SYSTEM).await;
+        }
+        let envelope =
+            |url| run_query_paginated("Person", url, qe, HashMap::default(), Pagination::Envelope);
+        let names = |r: &JsonObject| -> Vec<String> {
+            r["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|x| x["name"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        // A page with all the entities knows their total, and that there
+        // are no more.
+        let r = envelope(url("sort=name")).await.unwrap();
+        assert_eq!(names(&r), vec!["Alan", "Alex", "John"]);
+        assert_eq!(r["total"], json!(3));
+        assert!(!r.contains_key("next_cursor"));
+        assert!(!r.contains_key("results"));
+
+        let r = envelope(url("sort=name&limit=2")).await.unwrap();
+        assert_eq!(names(&r), vec!["Alan", "Alex"]);
+        assert!(!r.contains_key("total"));
+        let mut next = url("limit=2");
+        next.query_pairs_mut()
+            .append_pair("cursor", r["next_cursor"].as_str().unwrap());
+        let r = envelope(next).await.unwrap();
+        assert_eq!(names(&r), vec!["John"]);
+        assert!(!r.contains_key("next_cursor"));
+        assert!(!r.contains_key("total"));
+    }
+
     #[tokio::test]
     async fn test_query_str_to_ops_errors() {
         let (query_engine, _db_file) = setup_clear_db(&*ENTITIES).await;
@@ -1099,6 +1233,7 @@ mod tests {
                     ts: &make_type_system(&*ENTITIES),
                     api_version: VERSION.to_owned(),
                     user_id: None,
+                    tenant: None,
                     path: "".to_string(),
                     headers: HashMap::default(),
                 },
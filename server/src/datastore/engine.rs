@@ -1,10 +1,18 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
+use crate::blobs;
+use crate::connectors;
+use crate::datastore::crud::{self, Cursor};
 use crate::datastore::query::{
-    KeepOrOmitField, Mutation, QueriedEntity, QueryField, QueryPlan, SqlValue, TargetDatabase,
+    escape_string, search_document, AggregateQuery, Aggregation, KeepOrOmitField, ListedField,
+    Mutation, QueriedEntity, QueryField, QueryOp, QueryOpChain, QueryPlan, RequestContext, SortBy,
+    SqlValue, TargetDatabase,
 };
 use crate::datastore::DbConnection;
-use crate::types::{DbIndex, Field, ObjectDelta, ObjectType, Type, TypeId, TypeSystem};
+use crate::types::{
+    datetime, DbIndex, Entity, Field, ObjectDelta, ObjectType, PartitionScheme, Partitioning, Type,
+    TypeId, TypeSystem, TYPE_KEY,
+};
 use crate::JsonObject;
 use anyhow::{anyhow, Context as AnyhowContext, Result};
 use async_lock::Mutex;
@@ -19,14 +27,16 @@ use pin_project::pin_project;
 use sea_query::{Alias, ColumnDef, Index, PostgresQueryBuilder, Table};
 use serde::Serialize;
 use serde_json::json;
+use serde_json::Value as JsonValue;
 use sqlx::any::{Any, AnyArguments, AnyKind, AnyRow};
-use sqlx::{Executor, Row, Transaction, ValueRef};
+use sqlx::postgres::PgConnection;
+use sqlx::{Connection, Executor, Row, Transaction, ValueRef};
 use std::collections::{HashMap, HashSet};
-use std::fmt::Write;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use time::{Date, OffsetDateTime};
 use uuid::Uuid;
 
 /// A query row is a JSON object that represent the queried entities.
@@ -37,6 +47,10 @@ pub type QueryResults = BoxStream<'static, Result<ResultRow>>;
 
 pub type TransactionStatic = Arc<Mutex<Transaction<'static, Any>>>;
 
+/// Number of intervals after the current one that tables partitioned by time
+/// have partitions for, so that they exist before rows are inserted into them.
+const PARTITIONS_AHEAD: i32 = 2;
+
 pub fn extract_transaction(transaction: TransactionStatic) -> Transaction<'static, Any> {
     let transaction = Arc::try_unwrap(transaction).expect("Transaction still has references held!");
     transaction.into_inner()
@@ -90,7 +104,7 @@ impl<T: Stream<Item = Result<AnyRow>>> Stream for RawQueryResults<T> {
 impl TryFrom<&Field> for ColumnDef {
     type Error = anyhow::Error;
     fn try_from(field: &Field) -> Result<Self> {
-        let mut column_def = ColumnDef::new(Alias::new(&field.name));
+        let mut column_def = ColumnDef::new(Alias::new(field.column_name()));
         if field.is_unique {
             column_def.unique_key();
         }
@@ -99,14 +113,33 @@ impl TryFrom<&Field> for ColumnDef {
             TypeId::Id => column_def.text().primary_key(),
             TypeId::Float => column_def.double(),
             TypeId::Boolean => column_def.boolean(),
+            // Stored as ISO-8601 strings of the same length, which sort like the
+            // times they represent. sqlx's Any driver can't bind timestamps.
+            TypeId::DateTime => column_def.text(),
+            // JSONB on Postgres, TEXT on SQLite.
+            TypeId::Json => column_def.json_binary(),
+            // The metadata of the file, as a JSON object.
+            TypeId::File => column_def.text(),
+            // Constrained to the variants by `check_enum()` on Postgres.
+            TypeId::Enum(_) => column_def.text(),
             TypeId::Entity { .. } => column_def.text(), // Foreign key, must the be same type as Type::Id
-            TypeId::Array(_) => column_def.text(),      // Arrays are stored as serialized JSONs.
+            // The id, next to the discriminator column naming its entity.
+            TypeId::Union { .. } => column_def.text(),
+            TypeId::Array(_) => column_def.text(), // Arrays are stored as serialized JSONs.
         };
 
         Ok(column_def)
     }
 }
 
+/// The column storing the entity of the objects of `field`, if it is a
+/// polymorphic relation.
+fn discriminator_column_def(field: &Field) -> Option<ColumnDef> {
+    field
+        .discriminator_column()
+        .map(|name| ColumnDef::new(Alias::new(&name)).text().to_owned())
+}
+
 /// An SQL string with placeholders, plus its argument values.  Keeps them all alive so they can be fed to
 /// sqlx::Query by reference.
 #[derive(Debug)]
@@ -154,6 +187,30 @@ impl SqlWithArguments {
 pub struct IdTree {
     pub id: String,
     children: HashMap<String, IdTree>,
+    /// The trees of the objects of many-to-many relations, by field name.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    lists: HashMap<String, Vec<IdTree>>,
+}
+
+impl IdTree {
+    /// Number of objects in the tree, i.e. of rows inserted to store it.
+    pub fn object_count(&self) -> usize {
+        1 + self
+            .children
+            .values()
+            .chain(self.lists.values().flatten())
+            .map(IdTree::object_count)
+            .sum::<usize>()
+    }
+}
+
+/// One page of the results of `QueryEngine::query_relation_paginated()`.
+#[derive(Debug, Serialize)]
+pub struct Page {
+    pub results: Vec<ResultRow>,
+    /// Opaque cursor to pass in to get the next page. Missing on the last page.
+    #[serde(rename = "nextCursor", skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
 }
 
 fn column_is_null(row: &AnyRow, column_idx: usize) -> bool {
@@ -172,6 +229,85 @@ fn id_idx(entity: &QueriedEntity) -> usize {
     panic!("No id field among Entity children");
 }
 
+/// Name of the partition of `table` with `suffix`. Backing tables end in a
+/// UUID, which keeps the names of their partitions unique and short enough.
+fn partition_name(table: &str, suffix: &str) -> String {
+    let tail = &table[table.len().saturating_sub(32)..];
+    format!("part_{}_{}", tail, suffix)
+}
+
+/// The quoted columns of the backing table of `ty`, separated by commas.
+fn column_list(ty: &ObjectType) -> String {
+    ty.column_fields()
+        .flat_map(field_columns)
+        .map(|column| format!("\"{}\"", column))
+        .join(", ")
+}
+
+/// The columns storing `field`: its own, and the discriminator column of a
+/// polymorphic relation.
+fn field_columns(field: &Field) -> impl Iterator<Item = String> {
+    std::iter::once(field.column_name().to_owned()).chain(field.discriminator_column())
+}
+
+/// The discriminator of polymorphic relation `field` in `row`, a row whose
+/// relations hold the ids of the objects, and whose discriminator columns
+/// the names of their entities, like the rows of `QueryPlan::from_type()`.
+fn discriminator_argument(field: &Field, row: &JsonObject) -> Result<SqlValue> {
+    let column = field
+        .discriminator_column()
+        .with_context(|| format!("field `{}` is not a polymorphic relation", field.name))?;
+    let name = row
+        .get(&column)
+        .and_then(JsonValue::as_str)
+        .with_context(|| format!("`{}` of field `{}` is missing", column, field.name))?;
+    anyhow::ensure!(
+        matches!(&field.type_id, TypeId::Union { names, .. } if names.iter().any(|n| n == name)),
+        "`{}` is not one of {}, the types of field `{}`",
+        name,
+        field.type_id.name(),
+        field.name
+    );
+    Ok(SqlValue::String(name.to_owned()))
+}
+
+/// The entity among `entities`, those of polymorphic relation `field`, that
+/// object `value` names in its `__type` property.
+fn union_member<'a>(
+    field: &Field,
+    entities: &'a [Entity],
+    value: &JsonObject,
+) -> Result<&'a Entity> {
+    let name = value
+        .get(TYPE_KEY)
+        .and_then(JsonValue::as_str)
+        .with_context(|| {
+            format!(
+                "the object of field `{}` doesn't say which of {} it is in `{}`",
+                field.name,
+                field.type_id.name(),
+                TYPE_KEY
+            )
+        })?;
+    entities
+        .iter()
+        .find(|ty| ty.name() == name)
+        .with_context(|| {
+            format!(
+                "`{}` is not one of {}, the types of field `{}`",
+                name,
+                field.type_id.name(),
+                field.name
+            )
+        })
+}
+
+/// The bound of a partition by time that starts at the beginning of `date`,
+/// as an SQL literal of the stored value of `Date` fields.
+fn partition_bound(date: Date) -> String {
+    escape_string(&datetime::format(date.midnight().assume_utc()))
+}
+
 /// Query engine.
 ///
 /// The query engine provides a way to transactionally mutate entities and
@@ -184,6 +320,13 @@ fn id_idx(entity: &QueriedEntity) -> usize {
 /// some parts of a mutation or query need to run through the policy engine,
 /// which is not always offloadable to a database.
 #[derive(Clone)]
+/// A change to the backing tables of an entity.
+pub enum TableChange {
+    Create(Entity),
+    Drop(Entity),
+    Alter(Entity, ObjectDelta),
+}
+
 pub struct QueryEngine {
     db: Arc<DbConnection>,
 }
@@ -197,19 +340,33 @@ impl QueryEngine {
         Ok(Self::new(Arc::new(conn.local_connection(nr_conn).await?)))
     }
 
-    fn target_db(&self) -> TargetDatabase {
+    pub(crate) fn target_db(&self) -> TargetDatabase {
         match self.db.pool.any_kind() {
             AnyKind::Postgres => TargetDatabase::Postgres,
             AnyKind::Sqlite => TargetDatabase::Sqlite,
         }
     }
 
+    /// The type an argument for a column of `type_id` is cast to. JSON values
+    /// are bound as text, which Postgres doesn't convert to JSONB implicitly.
+    fn argument_cast(&self, type_id: &TypeId) -> Option<&'static str> {
+        match (type_id, self.target_db()) {
+            (TypeId::Json, TargetDatabase::Postgres) => Some("JSONB"),
+            _ => None,
+        }
+    }
+
     pub async fn drop_table(
         &self,
         transaction: &mut Transaction<'_, Any>,
         ty: &ObjectType,
     ) -> Result<()> {
         self.drop_indexes(transaction, ty, ty.indexes()).await?;
+        for field in ty.many_to_many_fields() {
+            Self::drop_join_table(transaction, &ty.join_table(field)?).await?;
+        }
+        let drop_archive = format!(r#"DROP TABLE IF EXISTS "{}""#, ty.archive_table());
+        transaction.execute(drop_archive.as_str()).await?;
 
         let drop_table = Table::drop()
             .table(Alias::new(ty.backing_table()))
@@ -240,6 +397,38 @@ impl QueryEngine {
         Ok(())
     }
 
+    /// Starts a transaction nested in `transaction`, which can be rolled back
+    /// without rolling back the rest of `transaction`. `depth` is the number
+    /// of nested transactions it is itself nested in.
+    pub async fn begin_nested_transaction(
+        transaction: &mut Transaction<'_, Any>,
+        depth: usize,
+    ) -> Result<()> {
+        let sql = format!("SAVEPOINT chisel_nested_{}", depth);
+        transaction.execute(sqlx::query(&sql)).await?;
+        Ok(())
+    }
+
+    /// Commits the nested transaction at `depth` into the one it is nested in.
+    pub async fn commit_nested_transaction(
+        transaction: &mut Transaction<'_, Any>,
+        depth: usize,
+    ) -> Result<()> {
+        let sql = format!("RELEASE SAVEPOINT chisel_nested_{}", depth);
+        transaction.execute(sqlx::query(&sql)).await?;
+        Ok(())
+    }
+
+    /// Rolls back the nested transaction at `depth`, and ends it.
+    pub async fn rollback_nested_transaction(
+        transaction: &mut Transaction<'_, Any>,
+        depth: usize,
+    ) -> Result<()> {
+        let sql = format!("ROLLBACK TO SAVEPOINT chisel_nested_{}", depth);
+        transaction.execute(sqlx::query(&sql)).await?;
+        QueryEngine::commit_nested_transaction(transaction, depth).await
+    }
+
     pub async fn create_table(
         &self,
         transaction: &mut Transaction<'_, Any>,
@@ -250,16 +439,211 @@ impl QueryEngine {
             .if_not_exists()
             .to_owned();
 
-        for field in ty.all_fields() {
+        // Only Postgres partitions tables.
+        let partition = ty.partition().filter(|_| self.target_db().is_postgres());
+        for field in ty.column_fields() {
             let mut column_def = ColumnDef::try_from(field)?;
+            if let Some(partition) = partition {
+                // The primary key of a partitioned table has to include the
+                // column it is partitioned by.
+                if field.type_id == TypeId::Id && partition.field != field.name {
+                    column_def = ColumnDef::new(Alias::new(field.column_name()));
+                    column_def.text().not_null();
+                    create_table.primary_key(
+                        Index::create()
+                            .col(Alias::new(field.column_name()))
+                            .col(Alias::new(ty.column(&partition.field))),
+                    );
+                }
+            }
             create_table.col(&mut column_def);
+            if let Some(mut column_def) = discriminator_column_def(field) {
+                create_table.col(&mut column_def);
+            }
+        }
+        let mut create_table = create_table.build_any(self.db.query_builder());
+        if let Some(partition) = partition {
+            let method = match partition.scheme {
+                PartitionScheme::Time { .. } => "RANGE",
+                PartitionScheme::Hash { .. } => "HASH",
+            };
+            create_table = format!(
+                "{} PARTITION BY {} (\"{}\")",
+                create_table,
+                method,
+                ty.column(&partition.field)
+            );
         }
-        let create_table = create_table.build_any(self.db.query_builder());
 
         let create_table = sqlx::query(&create_table);
         transaction.execute(create_table).await?;
+        if let Some(partition) = partition {
+            self.create_partitions(transaction, ty, partition).await?;
+        }
+        if ty.archive().is_some() {
+            self.create_archive_table(transaction, ty).await?;
+        }
+        for field in ty.column_fields() {
+            if matches!(field.type_id, TypeId::Enum(_)) {
+                self.check_enum(transaction, ty, field).await?;
+            }
+        }
+        for field in ty.many_to_many_fields() {
+            Self::create_join_table(transaction, &ty.join_table(field)?).await?;
+        }
+
+        self.create_indexes(transaction, ty, ty.indexes()).await?;
+        Ok(())
+    }
+
+    /// Creates the partitions of the backing table of `ty`, which is
+    /// partitioned as `partition` says. Tables partitioned by time get a
+    /// default partition, for the rows outside of all the others, besides
+    /// the ones `maintain_partitions()` creates.
+    async fn create_partitions(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+        partition: &Partitioning,
+    ) -> Result<()> {
+        let table = ty.backing_table();
+        match partition.scheme {
+            PartitionScheme::Time { .. } => {
+                let sql = format!(
+                    r#"CREATE TABLE IF NOT EXISTS "{}" PARTITION OF "{}" DEFAULT"#,
+                    partition_name(table, "default"),
+                    table
+                );
+                transaction.execute(sqlx::query(&sql)).await?;
+                let today = OffsetDateTime::now_utc().date();
+                self.maintain_partitions(transaction, ty, today).await?;
+            }
+            PartitionScheme::Hash { partitions } => {
+                for i in 0..partitions {
+                    let sql = format!(
+                        r#"CREATE TABLE IF NOT EXISTS "{}" PARTITION OF "{}" FOR VALUES WITH (MODULUS {}, REMAINDER {})"#,
+                        partition_name(table, &format!("h{}", i)),
+                        table,
+                        partitions,
+                        i
+                    );
+                    transaction.execute(sqlx::query(&sql)).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Brings the partitions of `ty`, if it is partitioned by time, up to
+    /// date with `today`. The partitions of the current interval and of the
+    /// next few are created, moving their rows out of the default partition,
+    /// and those past retention are dropped, along with the rows past
+    /// retention in the default partition. On SQLite, which doesn't partition
+    /// tables, only the rows past retention are deleted.
+    pub async fn maintain_partitions(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+        today: Date,
+    ) -> Result<()> {
+        let (field, interval, retention) = match ty.partition() {
+            Some(Partitioning {
+                field,
+                scheme:
+                    PartitionScheme::Time {
+                        interval,
+                        retention,
+                    },
+            }) => (ty.column(field), *interval, *retention),
+            _ => return Ok(()),
+        };
+        let table = ty.backing_table();
+        let current = interval.start(today);
+        let cutoff = if retention > 0 {
+            Some(interval.advance(current, -(retention as i32)))
+        } else {
+            None
+        };
+
+        let mut expired_rows_table = table.to_owned();
+        if self.target_db().is_postgres() {
+            let default = partition_name(table, "default");
+            let query = sqlx::query(
+                r#"
+                SELECT child.relname AS name
+                FROM pg_inherits
+                INNER JOIN pg_class parent ON pg_inherits.inhparent = parent.oid
+                INNER JOIN pg_class child ON pg_inherits.inhrelid = child.oid
+                WHERE parent.relname = $1"#,
+            )
+            .bind(table);
+            let existing: Vec<String> = transaction
+                .fetch_all(query)
+                .await?
+                .iter()
+                .map(|row| row.get("name"))
+                .collect();
+
+            for n in 0..=PARTITIONS_AHEAD {
+                let start = interval.advance(current, n);
+                if cutoff.map_or(false, |cutoff| start < cutoff) {
+                    continue;
+                }
+                let name = partition_name(table, &interval.suffix(start));
+                if existing.contains(&name) {
+                    continue;
+                }
+                let (from, to) = (
+                    partition_bound(start),
+                    partition_bound(interval.advance(start, 1)),
+                );
+                let statements = [
+                    format!(
+                        r#"CREATE TABLE "{}" (LIKE "{}" INCLUDING DEFAULTS INCLUDING CONSTRAINTS)"#,
+                        name, table
+                    ),
+                    format!(
+                        r#"WITH "moved" AS (DELETE FROM "{}" WHERE "{}" >= {} AND "{}" < {} RETURNING *) INSERT INTO "{}" SELECT * FROM "moved""#,
+                        default, field, from, field, to, name
+                    ),
+                    format!(
+                        r#"ALTER TABLE "{}" ATTACH PARTITION "{}" FOR VALUES FROM ({}) TO ({})"#,
+                        table, name, from, to
+                    ),
+                ];
+                for statement in statements {
+                    transaction.execute(statement.as_str()).await?;
+                }
+            }
 
-        Self::create_indexes(transaction, ty, ty.indexes()).await?;
+            if let Some(cutoff) = cutoff {
+                // The suffixes sort like the intervals they are for.
+                let cutoff_suffix = interval.suffix(cutoff);
+                let prefix = partition_name(table, "");
+                for name in &existing {
+                    let expired = name.strip_prefix(&prefix).map_or(false, |suffix| {
+                        suffix.len() == cutoff_suffix.len()
+                            && suffix.chars().all(|c| c.is_ascii_digit())
+                            && *suffix < *cutoff_suffix
+                    });
+                    if expired {
+                        let sql = format!(r#"DROP TABLE "{}""#, name);
+                        transaction.execute(sqlx::query(&sql)).await?;
+                    }
+                }
+            }
+            expired_rows_table = default;
+        }
+
+        if let Some(cutoff) = cutoff {
+            let sql = format!(
+                r#"DELETE FROM "{}" WHERE "{}" < {}"#,
+                expired_rows_table,
+                field,
+                partition_bound(cutoff)
+            );
+            transaction.execute(sqlx::query(&sql)).await?;
+        }
         Ok(())
     }
 
@@ -271,6 +655,12 @@ impl QueryEngine {
     ) -> Result<()> {
         self.drop_indexes(transaction, ty, &delta.removed_indexes)
             .await?;
+        // The archive table has the same columns as the backing table.
+        let archive_exists = self.table_exists(transaction, &ty.archive_table()).await?;
+        let mut tables = vec![ty.backing_table().to_owned()];
+        if archive_exists {
+            tables.push(ty.archive_table());
+        }
 
         // using a macro as async closures are unstable
         macro_rules! do_query {
@@ -301,23 +691,79 @@ impl QueryEngine {
         // that those are still safe. Adding columns is always safe, but removals may not be if
         // they are used in relations or indexes (see the document above)
         for field in delta.added_fields.iter() {
+            if field.type_id.is_many_to_many() {
+                // The added field only has an id in `ty`, which was reloaded.
+                let field = ty
+                    .get_field(&field.name)
+                    .context("added field is missing from the type")?;
+                Self::create_join_table(transaction, &ty.join_table(field)?).await?;
+                continue;
+            }
             let mut column_def = ColumnDef::try_from(field)?;
-            let table = Table::alter()
-                .table(Alias::new(ty.backing_table()))
-                .add_column(&mut column_def)
-                .to_owned();
-
-            do_query!(table)?;
+            if field.type_id == TypeId::Json && self.target_db().as_sqlite().is_some() {
+                // The JSONB type of the Postgres syntax would get numeric
+                // affinity on SQLite, which would turn JSON numbers into
+                // numbers.
+                column_def.text();
+            }
+            let discriminator = discriminator_column_def(field);
+            for table in &tables {
+                let table = Table::alter()
+                    .table(Alias::new(table))
+                    .add_column(&mut column_def.clone())
+                    .to_owned();
+
+                do_query!(table)?;
+                if let Some(discriminator) = &discriminator {
+                    let table = Table::alter()
+                        .table(Alias::new(table))
+                        .add_column(&mut discriminator.clone())
+                        .to_owned();
+
+                    do_query!(table)?;
+                }
+            }
+            if matches!(field.type_id, TypeId::Enum(_)) {
+                // Like for join tables, the field only has an id in `ty`.
+                let field = ty
+                    .get_field(&field.name)
+                    .context("added field is missing from the type")?;
+                self.check_enum(transaction, ty, field).await?;
+            }
         }
 
         for field in delta.removed_fields.iter() {
-            let table = Table::alter()
-                .table(Alias::new(ty.backing_table()))
-                .drop_column(Alias::new(&field.name))
-                .to_owned();
-
-            do_query!(table)?;
+            if field.type_id.is_many_to_many() {
+                Self::drop_join_table(transaction, &ty.join_table(field)?).await?;
+                continue;
+            }
+            for table in &tables {
+                let table = Table::alter()
+                    .table(Alias::new(table))
+                    .drop_column(Alias::new(field.column_name()))
+                    .to_owned();
+
+                do_query!(table)?;
+                if let Some(discriminator) = field.discriminator_column() {
+                    let table = Table::alter()
+                        .table(Alias::new(table))
+                        .drop_column(Alias::new(&discriminator))
+                        .to_owned();
+
+                    do_query!(table)?;
+                }
+            }
         }
+        // Enums can gain variants, or become strings.
+        for field in delta.updated_fields.iter() {
+            if field.attrs.is_none() {
+                continue;
+            }
+            if let Some(field) = ty.user_fields().find(|f| f.id == Some(field.id)) {
+                self.check_enum(transaction, ty, field).await?;
+            }
+        }
+
         // We don't loop over the modified part of the delta: SQLite doesn't support modify columns
         // at all, but that is fine since the currently supported field modifications are handled
         // by ChiselStrike directly and require no modifications to the tables.
@@ -326,12 +772,212 @@ impl QueryEngine {
         // since we always write with defaults. For all others, we should error out way before we
         // get here.
 
-        Self::create_indexes(transaction, ty, ty.indexes()).await?;
+        self.create_indexes(transaction, ty, ty.indexes()).await?;
+
+        match (ty.archive(), archive_exists) {
+            (Some(_), false) => self.create_archive_table(transaction, ty).await?,
+            (None, true) => self.restore_archived_rows(transaction, ty).await?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn table_exists(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        name: &str,
+    ) -> Result<bool> {
+        let exists = match self.target_db() {
+            TargetDatabase::Postgres => "SELECT 1 FROM pg_class WHERE relname = $1",
+            TargetDatabase::Sqlite => "SELECT 1 FROM sqlite_master WHERE name = $1",
+        };
+        let exists = sqlx::query(exists).bind(name);
+        Ok(transaction.fetch_optional(exists).await?.is_some())
+    }
+
+    /// Creates the table that the archived rows of `ty` are moved to, with
+    /// the same columns as its backing table.
+    async fn create_archive_table(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+    ) -> Result<()> {
+        let mut create_table = Table::create()
+            .table(Alias::new(&ty.archive_table()))
+            .if_not_exists()
+            .to_owned();
+        for field in ty.column_fields() {
+            create_table.col(&mut ColumnDef::try_from(field)?);
+            if let Some(mut column_def) = discriminator_column_def(field) {
+                create_table.col(&mut column_def);
+            }
+        }
+        let create_table = create_table.build_any(self.db.query_builder());
+        transaction.execute(create_table.as_str()).await?;
+        Ok(())
+    }
+
+    /// Moves the archived rows of `ty`, which isn't archived anymore, back to
+    /// its backing table, and drops the archive table.
+    async fn restore_archived_rows(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+    ) -> Result<()> {
+        let columns = column_list(ty);
+        let statements = [
+            format!(
+                r#"INSERT INTO "{}" ({1}) SELECT {1} FROM "{}""#,
+                ty.backing_table(),
+                columns,
+                ty.archive_table()
+            ),
+            format!(r#"DROP TABLE "{}""#, ty.archive_table()),
+        ];
+        for statement in statements {
+            transaction.execute(statement.as_str()).await?;
+        }
+        Ok(())
+    }
+
+    /// Moves the rows of `ty` that are old enough at `now` to its archive
+    /// table, returning how many were moved. A row that was saved again
+    /// after being archived replaces its archived copy.
+    pub async fn archive_rows(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+        now: OffsetDateTime,
+    ) -> Result<u64> {
+        let archive = match ty.archive() {
+            Some(archive) => archive,
+            None => return Ok(0),
+        };
+        let (table, archive_table) = (ty.backing_table(), ty.archive_table());
+        let condition = format!(
+            r#""{}" < {}"#,
+            ty.column(&archive.field),
+            escape_string(&archive.cutoff(now))
+        );
+        let columns = column_list(ty);
+        let statements = [
+            format!(
+                r#"DELETE FROM "{}" WHERE "id" IN (SELECT "id" FROM "{}" WHERE {})"#,
+                archive_table, table, condition
+            ),
+            format!(
+                r#"INSERT INTO "{}" ({1}) SELECT {1} FROM "{}" WHERE {}"#,
+                archive_table, columns, table, condition
+            ),
+        ];
+        for statement in statements {
+            transaction.execute(statement.as_str()).await?;
+        }
+        let delete = format!(r#"DELETE FROM "{}" WHERE {}"#, table, condition);
+        Ok(transaction.execute(delete.as_str()).await?.rows_affected())
+    }
+
+    async fn change_table(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        change: TableChange,
+    ) -> Result<()> {
+        match change {
+            TableChange::Create(ty) => self.create_table(transaction, &ty).await,
+            TableChange::Drop(ty) => self.drop_table(transaction, &ty).await,
+            TableChange::Alter(ty, delta) => self.alter_table(transaction, &ty, delta).await,
+        }
+    }
+
+    /// Makes `changes` to the backing tables. On Postgres, they are spread
+    /// over up to `parallelism` concurrent transactions. The changes of
+    /// different entities touch different tables, so they don't conflict.
+    ///
+    /// The transactions are only committed once all the changes are made, so
+    /// a failed change rolls all of them back. A failure to commit can still
+    /// leave the transactions committed before it in place.
+    pub async fn change_tables(&self, changes: Vec<TableChange>, parallelism: usize) -> Result<()> {
+        let transactions = match self.target_db() {
+            // SQLite has a single writer, so the transactions would only
+            // wait on each other.
+            TargetDatabase::Sqlite => 1,
+            TargetDatabase::Postgres => {
+                // Each transaction holds a connection until the end.
+                let max_connections = self.db.pool.options().get_max_connections() as usize;
+                parallelism.min(max_connections).min(changes.len()).max(1)
+            }
+        };
+        let changes = &Mutex::new(changes.into_iter());
+        let make_changes = || async move {
+            let mut transaction = self.begin_transaction().await?;
+            loop {
+                let change = changes.lock().await.next();
+                match change {
+                    Some(change) => self.change_table(&mut transaction, change).await?,
+                    None => return Ok::<_, anyhow::Error>(transaction),
+                }
+            }
+        };
+        let transactions =
+            futures::future::try_join_all((0..transactions).map(|_| make_changes())).await?;
+        for transaction in transactions {
+            Self::commit_transaction(transaction).await?;
+        }
+        Ok(())
+    }
+
+    /// Constrains the column of `field` to the variants of its type if it is
+    /// an enum, replacing the constraint it had. SQLite can't alter
+    /// constraints, so there, values are only checked as they are written.
+    async fn check_enum(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+        field: &Field,
+    ) -> Result<()> {
+        if self.target_db().as_sqlite().is_some() {
+            return Ok(());
+        }
+        let table = ty.backing_table();
+        let id = field.id.context("enum field has no id")?;
+        let constraint = format!("enum_{}", id);
+        let sql = format!(
+            r#"ALTER TABLE "{}" DROP CONSTRAINT IF EXISTS "{}""#,
+            table, constraint
+        );
+        transaction.execute(sqlx::query(&sql)).await?;
+        if let TypeId::Enum(variants) = &field.type_id {
+            let sql = format!(
+                r#"ALTER TABLE "{}" ADD CONSTRAINT "{}" CHECK ("{}" IN ({}))"#,
+                table,
+                constraint,
+                field.column_name(),
+                variants.iter().map(|v| escape_string(v)).join(", ")
+            );
+            transaction.execute(sqlx::query(&sql)).await?;
+        }
+        Ok(())
+    }
+
+    /// Creates the join table of a many-to-many relation, which links each
+    /// object to the related objects in order.
+    async fn create_join_table(transaction: &mut Transaction<'_, Any>, table: &str) -> Result<()> {
+        let sql = format!(
+            r#"CREATE TABLE IF NOT EXISTS "{}" ("owner" TEXT NOT NULL, "item" TEXT NOT NULL, "position" INTEGER NOT NULL, PRIMARY KEY ("owner", "position"))"#,
+            table
+        );
+        transaction.execute(sqlx::query(&sql)).await?;
+        Ok(())
+    }
 
+    async fn drop_join_table(transaction: &mut Transaction<'_, Any>, table: &str) -> Result<()> {
+        let sql = format!("DROP TABLE IF EXISTS \"{}\"", table);
+        transaction.execute(sqlx::query(&sql)).await?;
         Ok(())
     }
 
     pub async fn create_indexes(
+        &self,
         transaction: &mut Transaction<'_, Any>,
         ty: &ObjectType,
         indexes: &[DbIndex],
@@ -340,7 +986,21 @@ impl QueryEngine {
             let idx_name = index
                 .name()
                 .context("index must have a name at a time of table creation")?;
-            let columns = index.fields.iter().join(", ");
+            if index.search {
+                self.create_search_index(transaction, ty, &idx_name, &index.fields)
+                    .await?;
+                continue;
+            }
+            if index.counted {
+                self.create_counter(transaction, ty, &idx_name, index.fields.first())
+                    .await?;
+                continue;
+            }
+            let columns = index
+                .fields
+                .iter()
+                .map(|field| format!("\"{}\"", ty.column(field)))
+                .join(", ");
             let create_index = format!(
                 r#"
                 CREATE INDEX IF NOT EXISTS "{idx_name}" ON "{}" ({columns});
@@ -353,6 +1013,227 @@ impl QueryEngine {
         Ok(())
     }
 
+    /// Creates the full-text search index `name` over `fields` of `ty`. On
+    /// Postgres, it is a GIN index over their `tsvector`. On SQLite, it is an
+    /// FTS5 table over the backing table, which triggers keep up to date.
+    async fn create_search_index(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+        name: &str,
+        fields: &[String],
+    ) -> Result<()> {
+        let table = ty.backing_table();
+        let mut statements = vec![];
+        match self.target_db() {
+            TargetDatabase::Postgres => statements.push(format!(
+                r#"CREATE INDEX IF NOT EXISTS "{}" ON "{}" USING GIN ({})"#,
+                name,
+                table,
+                search_document(ty, fields)
+            )),
+            TargetDatabase::Sqlite => {
+                let exists = sqlx::query("SELECT 1 FROM sqlite_master WHERE name = $1").bind(name);
+                if transaction.fetch_optional(exists).await?.is_some() {
+                    return Ok(());
+                }
+                // The columns of the FTS5 table are those of its content table.
+                let columns = fields
+                    .iter()
+                    .map(|f| format!("\"{}\"", ty.column(f)))
+                    .join(", ");
+                let values = |row: &str| {
+                    fields
+                        .iter()
+                        .map(|f| format!("{}.\"{}\"", row, ty.column(f)))
+                        .join(", ")
+                };
+                let delete = format!(
+                    r#"INSERT INTO "{0}" ("{0}", rowid, {1}) VALUES ('delete', old.rowid, {2});"#,
+                    name,
+                    columns,
+                    values("old")
+                );
+                let insert = format!(
+                    r#"INSERT INTO "{}" (rowid, {}) VALUES (new.rowid, {});"#,
+                    name,
+                    columns,
+                    values("new")
+                );
+                statements.push(format!(
+                    r#"CREATE VIRTUAL TABLE "{}" USING fts5({}, content='{}')"#,
+                    name, columns, table
+                ));
+                statements.push(format!(
+                    r#"CREATE TRIGGER "{0}_insert" AFTER INSERT ON "{1}" BEGIN {2} END"#,
+                    name, table, insert
+                ));
+                statements.push(format!(
+                    r#"CREATE TRIGGER "{0}_delete" AFTER DELETE ON "{1}" BEGIN {2} END"#,
+                    name, table, delete
+                ));
+                statements.push(format!(
+                    r#"CREATE TRIGGER "{0}_update" AFTER UPDATE ON "{1}" BEGIN {2} {3} END"#,
+                    name, table, delete, insert
+                ));
+                // Indexes the rows already in the table.
+                statements.push(format!(
+                    r#"INSERT INTO "{0}" ("{0}") VALUES ('rebuild')"#,
+                    name
+                ));
+            }
+        }
+        for statement in statements {
+            transaction.execute(statement.as_str()).await?;
+        }
+        Ok(())
+    }
+
+    /// Creates the table `name` counting the rows of `ty` with each value of
+    /// `group_by`, or all of them, and the triggers keeping the counts up to
+    /// date as rows are written, in the same transaction. Groups without
+    /// rows are deleted, so the table has the same groups as a `GROUP BY`.
+    async fn create_counter(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+        name: &str,
+        group_by: Option<&String>,
+    ) -> Result<()> {
+        if self.table_exists(transaction, name).await? {
+            return Ok(());
+        }
+        let table = ty.backing_table();
+        let (column, column_type) = match group_by {
+            Some(field_name) => {
+                let field = ty
+                    .get_field(field_name)
+                    .context("counted field is missing from the type")?;
+                let column_type = match field.type_id {
+                    TypeId::Float => "DOUBLE PRECISION",
+                    TypeId::Boolean => "BOOLEAN",
+                    _ => "TEXT",
+                };
+                (format!("\"{}\"", field_name), column_type)
+            }
+            // All the rows are counted in a single row, with a constant key.
+            None => ("\"key\"".to_owned(), "INTEGER"),
+        };
+        // The counter is keyed by the field, read from its column in the rows.
+        let key = |row: &str| match group_by {
+            Some(field_name) => format!("{}.\"{}\"", row, ty.column(field_name)),
+            None => "0".to_owned(),
+        };
+        let increment = format!(
+            r#"INSERT INTO "{0}" ({1}, "count") VALUES ({2}, 1)
+                ON CONFLICT ({1}) DO UPDATE SET "count" = "{0}"."count" + 1;"#,
+            name,
+            column,
+            key("new")
+        );
+        let decrement = format!(
+            r#"UPDATE "{0}" SET "count" = "count" - 1 WHERE {1} = {2};
+                DELETE FROM "{0}" WHERE {1} = {2} AND "count" = 0;"#,
+            name,
+            column,
+            key("old")
+        );
+
+        let mut statements = vec![
+            format!(
+                r#"CREATE TABLE "{}" ({} {} PRIMARY KEY, "count" BIGINT NOT NULL)"#,
+                name, column, column_type
+            ),
+            // Counts the rows already in the table.
+            format!(
+                r#"INSERT INTO "{}" ({}, "count") SELECT {}, COUNT(*) FROM "{}" GROUP BY 1"#,
+                name,
+                column,
+                group_by.map_or("0".to_owned(), |f| format!("\"{}\"", ty.column(f))),
+                table
+            ),
+        ];
+        match self.target_db() {
+            TargetDatabase::Postgres => {
+                let (update, unchanged) = match group_by {
+                    Some(field_name) => (
+                        format!(r#" OR UPDATE OF "{}""#, ty.column(field_name)),
+                        format!(
+                            "IF TG_OP = 'UPDATE' AND {} = {} THEN RETURN NULL; END IF;",
+                            key("old"),
+                            key("new")
+                        ),
+                    ),
+                    None => (String::new(), String::new()),
+                };
+                statements.push(format!(
+                    r#"CREATE FUNCTION "{}"() RETURNS TRIGGER AS $$
+                    BEGIN
+                        {}
+                        IF TG_OP <> 'INSERT' THEN {} END IF;
+                        IF TG_OP <> 'DELETE' THEN {} END IF;
+                        RETURN NULL;
+                    END
+                    $$ LANGUAGE plpgsql"#,
+                    name, unchanged, decrement, increment
+                ));
+                statements.push(format!(
+                    r#"CREATE TRIGGER "{0}" AFTER INSERT OR DELETE{1} ON "{2}"
+                    FOR EACH ROW EXECUTE PROCEDURE "{0}"()"#,
+                    name, update, table
+                ));
+            }
+            TargetDatabase::Sqlite => {
+                statements.push(format!(
+                    r#"CREATE TRIGGER "{0}_insert" AFTER INSERT ON "{1}" BEGIN {2} END"#,
+                    name, table, increment
+                ));
+                statements.push(format!(
+                    r#"CREATE TRIGGER "{0}_delete" AFTER DELETE ON "{1}" BEGIN {2} END"#,
+                    name, table, decrement
+                ));
+                if let Some(field_name) = group_by {
+                    statements.push(format!(
+                        r#"CREATE TRIGGER "{0}_update" AFTER UPDATE OF "{1}" ON "{2}"
+                        WHEN {3} IS NOT {4} BEGIN {5} {6} END"#,
+                        name,
+                        ty.column(field_name),
+                        table,
+                        key("old"),
+                        key("new"),
+                        decrement,
+                        increment
+                    ));
+                }
+            }
+        }
+        for statement in statements {
+            transaction.execute(statement.as_str()).await?;
+        }
+        Ok(())
+    }
+
+    /// Drops the counter `name` created by `create_counter()`.
+    async fn drop_counter(&self, transaction: &mut Transaction<'_, Any>, name: &str) -> Result<()> {
+        let mut statements = vec![];
+        match self.target_db() {
+            // Drops the trigger with the function.
+            TargetDatabase::Postgres => {
+                statements.push(format!(r#"DROP FUNCTION IF EXISTS "{}"() CASCADE"#, name))
+            }
+            TargetDatabase::Sqlite => {
+                for trigger in ["insert", "delete", "update"] {
+                    statements.push(format!(r#"DROP TRIGGER IF EXISTS "{}_{}""#, name, trigger));
+                }
+            }
+        }
+        statements.push(format!(r#"DROP TABLE IF EXISTS "{}""#, name));
+        for statement in statements {
+            transaction.execute(statement.as_str()).await?;
+        }
+        Ok(())
+    }
+
     pub async fn drop_indexes(
         &self,
         transaction: &mut Transaction<'_, Any>,
@@ -360,6 +1241,25 @@ impl QueryEngine {
         indexes: &[DbIndex],
     ) -> Result<()> {
         for removed_idx in indexes {
+            if removed_idx.counted {
+                let name = removed_idx
+                    .name()
+                    .context("index must have a name when dropped")?;
+                self.drop_counter(transaction, &name).await?;
+                continue;
+            }
+            if removed_idx.search && self.target_db().is_sqlite() {
+                let name = removed_idx
+                    .name()
+                    .context("index must have a name when dropped")?;
+                for trigger in ["insert", "delete", "update"] {
+                    let drop_trigger = format!(r#"DROP TRIGGER IF EXISTS "{}_{}""#, name, trigger);
+                    transaction.execute(drop_trigger.as_str()).await?;
+                }
+                let drop_table = format!(r#"DROP TABLE IF EXISTS "{}""#, name);
+                transaction.execute(drop_table.as_str()).await?;
+                continue;
+            }
             let drop_idx = Index::drop()
                 .name(
                     &removed_idx
@@ -376,9 +1276,26 @@ impl QueryEngine {
         Ok(())
     }
 
-    fn row_to_json(db_kind: AnyKind, entity: &QueriedEntity, row: &AnyRow) -> Result<ResultRow> {
+    /// Converts `row` to the JSON object of `entity`. Only the fields in
+    /// `allowed_fields`, if set, are converted, and the values of text
+    /// columns are copied once, straight out of the row.
+    fn row_to_json(
+        db_kind: AnyKind,
+        entity: &QueriedEntity,
+        row: &AnyRow,
+        allowed_fields: Option<&HashSet<String>>,
+    ) -> Result<ResultRow> {
         let mut ret = JsonObject::default();
         for s_field in &entity.fields {
+            let field_name = match s_field {
+                QueryField::Scalar { name, .. }
+                | QueryField::Entity { name, .. }
+                | QueryField::EntityList { name, .. }
+                | QueryField::EntityUnion { name, .. } => name,
+            };
+            if allowed_fields.map_or(false, |allowed| !allowed.contains(field_name)) {
+                continue;
+            }
             match s_field {
                 QueryField::Scalar {
                     name,
@@ -393,12 +1310,6 @@ impl QueryEngine {
                     if omit_field || (*is_optional && column_is_null(row, *column_idx)) {
                         continue;
                     }
-                    macro_rules! to_json {
-                        ($value_type:ty) => {{
-                            let val = row.get::<$value_type, _>(column_idx);
-                            json!(val)
-                        }};
-                    }
                     let mut val = match type_id {
                         TypeId::Float => {
                             // https://github.com/launchbadge/sqlx/issues/1596
@@ -406,29 +1317,43 @@ impl QueryEngine {
                             let val: f64 = row.get_unchecked(column_idx);
                             json!(val)
                         }
-                        TypeId::String => to_json!(&str),
-                        TypeId::Id => to_json!(&str),
+                        TypeId::String | TypeId::DateTime | TypeId::Enum(_) | TypeId::Id => {
+                            JsonValue::String(row.get::<&str, _>(column_idx).to_owned())
+                        }
                         TypeId::Boolean => {
                             // Similarly to the float issue, type information is not filled in
                             // *if* this value was put in as a result of coalesce() (default).
                             match db_kind {
                                 AnyKind::Sqlite => {
-                                    let val: String = row.get_unchecked(column_idx);
-                                    json!(val == "1" || val.to_lowercase() == "true")
+                                    let val: &str = row.get_unchecked(column_idx);
+                                    JsonValue::Bool(val == "1" || val.eq_ignore_ascii_case("true"))
                                 }
-                                _ => to_json!(bool),
+                                _ => JsonValue::Bool(row.get::<bool, _>(column_idx)),
                             }
                         }
-                        TypeId::Entity { .. } => anyhow::bail!("object is not a scalar"),
+                        TypeId::Entity { .. } | TypeId::Union { .. } => {
+                            anyhow::bail!("object is not a scalar")
+                        }
                         TypeId::Array(_) => {
                             let array_str = row.get::<&str, _>(column_idx);
                             serde_json::from_str(array_str)
                                 .context("failed to deserialize array from raw JSON string")?
                         }
+                        TypeId::Json => {
+                            let json_str = row.get::<&str, _>(column_idx);
+                            serde_json::from_str(json_str)
+                                .context("failed to deserialize JSON field from raw JSON string")?
+                        }
+                        TypeId::File => {
+                            let json_str = row.get::<&str, _>(column_idx);
+                            serde_json::from_str(json_str).context(
+                                "failed to deserialize file metadata from raw JSON string",
+                            )?
+                        }
                     };
                     if let Some(tr) = transform {
                         // Apply policy transformation
-                        val = tr(val);
+                        val = tr.apply(val);
                     }
                     ret.insert(name.clone(), val);
                 }
@@ -443,36 +1368,146 @@ impl QueryEngine {
                     if omit_field || (*is_optional && column_is_null(row, id_idx(child_entity))) {
                         continue;
                     }
-                    let mut val = json!(Self::row_to_json(db_kind, child_entity, row)?);
+                    let mut val =
+                        JsonValue::Object(Self::row_to_json(db_kind, child_entity, row, None)?);
                     if let Some(tr) = transform {
                         // Apply policy transformation
-                        val = tr(val);
+                        val = tr.apply(val);
                     }
                     ret.insert(name.clone(), val);
                 }
-            }
-        }
-        Ok(ret)
-    }
-
-    fn project(
-        o: Result<ResultRow>,
-        allowed_fields: &Option<HashSet<String>>,
-    ) -> Result<JsonObject> {
-        let mut o = o?;
-        if let Some(allowed_fields) = &allowed_fields {
-            let removed_keys = o
-                .iter()
-                .map(|(k, _)| k.to_owned())
-                .filter(|k| !allowed_fields.contains(k))
-                .collect::<Vec<String>>();
-            for k in &removed_keys {
-                o.remove(k);
-            }
-        }
-        Ok(o)
-    }
-
+                QueryField::EntityList {
+                    name,
+                    column_idx,
+                    fields,
+                    transform,
+                    keep_or_omit,
+                } => {
+                    if matches!(keep_or_omit, KeepOrOmitField::Omit) {
+                        continue;
+                    }
+                    let objects = row.get::<&str, _>(column_idx);
+                    let mut val = Self::listed_objects_to_json(fields, objects)?;
+                    if let Some(tr) = transform {
+                        // Apply policy transformation
+                        val = tr.apply(val);
+                    }
+                    ret.insert(name.clone(), val);
+                }
+                QueryField::EntityUnion {
+                    name,
+                    column_idx,
+                    members,
+                    transform,
+                    keep_or_omit,
+                } => {
+                    // The object is NULL if the field is, or if it was deleted.
+                    let omit_field = matches!(keep_or_omit, KeepOrOmitField::Omit);
+                    if omit_field || column_is_null(row, *column_idx) {
+                        continue;
+                    }
+                    let object = row.get::<&str, _>(column_idx);
+                    let mut val = Self::union_object_to_json(members, object)?;
+                    if let Some(tr) = transform {
+                        // Apply policy transformation
+                        val = tr.apply(val);
+                    }
+                    ret.insert(name.clone(), val);
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Converts the related objects of a many-to-many relation, which the
+    /// database aggregated into the JSON array `objects`, to their JSON
+    /// values.
+    fn listed_objects_to_json(fields: &[ListedField], objects: &str) -> Result<JsonValue> {
+        let objects: Vec<JsonObject> = serde_json::from_str(objects)
+            .context("failed to deserialize related objects from raw JSON string")?;
+        let mut ret = vec![];
+        for object in objects {
+            ret.push(JsonValue::Object(Self::listed_object_to_json(
+                fields, object,
+            )?));
+        }
+        Ok(JsonValue::Array(ret))
+    }
+
+    /// Converts `object`, which the database built out of `fields` in a
+    /// subquery, to its JSON value.
+    fn listed_object_to_json(fields: &[ListedField], mut object: JsonObject) -> Result<JsonObject> {
+        let mut converted = JsonObject::default();
+        for field in fields {
+            if matches!(field.keep_or_omit, KeepOrOmitField::Omit) {
+                continue;
+            }
+            // The objects are ours, so their keys and values are moved
+            // rather than copied.
+            let (name, val) = object
+                .remove_entry(&field.name)
+                .unwrap_or_else(|| (field.name.clone(), JsonValue::Null));
+            if field.is_optional && val.is_null() {
+                continue;
+            }
+            let mut val = match (&field.type_id, val) {
+                // SQLite has no booleans, and stores them as numbers.
+                (TypeId::Boolean, JsonValue::Number(n)) => json!(n.as_f64() != Some(0.0)),
+                (TypeId::Boolean, JsonValue::String(s)) => {
+                    json!(s == "1" || s.eq_ignore_ascii_case("true"))
+                }
+                (TypeId::Array(_), JsonValue::String(s)) => serde_json::from_str(&s)
+                    .context("failed to deserialize array from raw JSON string")?,
+                (TypeId::Json, JsonValue::String(s)) => serde_json::from_str(&s)
+                    .context("failed to deserialize JSON field from raw JSON string")?,
+                (TypeId::File, JsonValue::String(s)) => serde_json::from_str(&s)
+                    .context("failed to deserialize file metadata from raw JSON string")?,
+                (_, val) => val,
+            };
+            if let Some(tr) = &field.transform {
+                val = tr.apply(val);
+            }
+            converted.insert(name, val);
+        }
+        Ok(converted)
+    }
+
+    /// Converts the object of a polymorphic relation, which the database
+    /// built in a subquery out of the fields of the entity in `members` that
+    /// it is of, to its JSON value, which names the entity in `__type`.
+    fn union_object_to_json(
+        members: &[(String, Vec<ListedField>)],
+        object: &str,
+    ) -> Result<JsonValue> {
+        let mut object: JsonObject = serde_json::from_str(object)
+            .context("failed to deserialize related object from raw JSON string")?;
+        let name = match object.remove(TYPE_KEY) {
+            Some(JsonValue::String(name)) => name,
+            _ => anyhow::bail!("related object has no `{}`", TYPE_KEY),
+        };
+        let fields = members
+            .iter()
+            .find(|(member, _)| *member == name)
+            .map(|(_, fields)| fields)
+            .with_context(|| format!("related object is of unexpected type {}", name))?;
+        let mut converted = Self::listed_object_to_json(fields, object)?;
+        converted.insert(TYPE_KEY.to_owned(), JsonValue::String(name));
+        Ok(JsonValue::Object(converted))
+    }
+
+    /// Returns the SQL that `query()` runs for `query_plan`.
+    pub fn query_sql(&self, query_plan: &QueryPlan) -> Result<String> {
+        Ok(query_plan.build_query(&self.target_db())?.raw_sql)
+    }
+
+    /// Returns the SQL that `mutate_with_transaction()` runs for `mutation`.
+    pub fn mutation_sql(&self, mutation: &Mutation) -> Result<String> {
+        let mut statements = mutation.build_join_table_sql(self.target_db())?;
+        statements.extend(mutation.build_archive_sql(self.target_db())?);
+        statements.push(mutation.build_sql(self.target_db())?);
+        Ok(statements.join(";\n"))
+    }
+
     /// Execute the given `query` and return a stream to the results.
     pub fn query(
         &self,
@@ -484,33 +1519,210 @@ impl QueryEngine {
         let db_kind = self.db.pool.any_kind();
 
         let stream = new_query_results(query.raw_sql, tr);
-        let stream = stream.map(move |row| Self::row_to_json(db_kind, &query.entity, &row?));
-        let stream = Box::pin(stream.map(move |o| Self::project(o, &allowed_fields)));
-        Ok(stream)
+        let stream = stream.map(move |row| {
+            Self::row_to_json(db_kind, &query.entity, &row?, allowed_fields.as_ref())
+        });
+        let connected = query_plan.connected_entities();
+        if connected.is_empty() {
+            return Ok(Box::pin(stream));
+        }
+        // The rows fetched by connectors are refreshed before they are read.
+        let refresh = connectors::refresh(Self::new(self.db.clone()), connected);
+        let refresh = futures::stream::once(refresh)
+            .filter_map(|res| futures::future::ready(res.err().map(Err)));
+        Ok(Box::pin(refresh.chain(stream)))
+    }
+
+    /// Runs `op_chain` and returns at most `limit` of its results that come
+    /// after `cursor`, ordered by id.
+    ///
+    /// The cursor is compiled into the SQL as a keyset predicate, so unlike
+    /// pulling pages out of the stream returned by `query()`, the database
+    /// doesn't go over the rows of the previous pages again.
+    pub fn query_relation_paginated(
+        &self,
+        tr: TransactionStatic,
+        context: &RequestContext,
+        op_chain: QueryOpChain,
+        cursor: Option<&str>,
+        limit: u64,
+    ) -> Result<impl Future<Output = Result<Page>>> {
+        anyhow::ensure!(limit > 0, "page limit must be positive");
+        anyhow::ensure!(
+            op_chain.is_unordered(),
+            "paginated queries can't be sorted, skipped or limited"
+        );
+        let base_type = context
+            .ts
+            .lookup_entity(op_chain.entity_name(), &context.api_version)?;
+
+        let mut sort = SortBy { keys: vec![] };
+        crud::ensure_sort_by_id(&mut sort);
+        let mut ops = vec![QueryOp::SortBy(sort.clone())];
+        if let Some(cursor) = cursor {
+            let cursor = Cursor::from_string(cursor)?;
+            anyhow::ensure!(
+                cursor.get_sort() == sort,
+                "cursor doesn't belong to this query"
+            );
+            ops.push(QueryOp::Filter {
+                expression: cursor.get_filter(&base_type, context.ts)?,
+            });
+        }
+        // One more row than asked for tells whether there is a next page.
+        ops.push(QueryOp::Take { count: limit + 1 });
+        let query_plan = QueryPlan::from_op_chain(context, op_chain)?.extend(ops);
+        let stream = self.query(tr, query_plan)?;
+
+        Ok(async move {
+            let mut results = stream
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>>>()
+                .context("failed to collect result rows from the database")?;
+            let mut next_cursor = None;
+            if results.len() as u64 > limit {
+                results.truncate(limit as usize);
+                let cursor = Cursor::from_pivot(sort.keys, results.last().unwrap(), true)?;
+                next_cursor = Some(cursor.to_string()?);
+            }
+            Ok(Page {
+                results,
+                next_cursor,
+            })
+        })
+    }
+
+    /// Computes `aggregation` over the rows of `query_plan` in the database,
+    /// rather than streaming the rows out. Returns an array with an object
+    /// per group if the rows are grouped, or a single object otherwise.
+    pub fn query_aggregate(
+        &self,
+        tr: TransactionStatic,
+        query_plan: QueryPlan,
+        aggregation: Aggregation,
+    ) -> Result<impl Future<Output = Result<JsonValue>>> {
+        let query = query_plan.build_aggregate_query(&self.target_db(), aggregation)?;
+        let db_kind = self.db.pool.any_kind();
+        let stream = new_query_results(query.raw_sql.clone(), tr);
+        let refresh =
+            connectors::refresh(Self::new(self.db.clone()), query_plan.connected_entities());
+
+        Ok(async move {
+            refresh.await?;
+            let rows = stream
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>>>()
+                .context("failed to collect aggregated rows from the database")?;
+            let mut groups = rows
+                .iter()
+                .map(|row| Self::aggregate_to_json(db_kind, &query, row))
+                .collect::<Result<Vec<_>>>()?;
+            if query.group_type.is_some() {
+                return Ok(JsonValue::Array(groups));
+            }
+            anyhow::ensure!(groups.len() == 1, "aggregation didn't return one row");
+            Ok(groups.pop().unwrap())
+        })
+    }
+
+    fn aggregate_to_json(
+        db_kind: AnyKind,
+        query: &AggregateQuery,
+        row: &AnyRow,
+    ) -> Result<JsonValue> {
+        let mut ret = JsonObject::default();
+        let mut column_idx = 0;
+        if let (Some(field), Some(type_id)) = (&query.aggregation.group_by, &query.group_type) {
+            let val = if column_is_null(row, column_idx) {
+                JsonValue::Null
+            } else {
+                match type_id {
+                    TypeId::Float => json!(row.get_unchecked::<f64, _>(column_idx)),
+                    TypeId::Boolean => match db_kind {
+                        AnyKind::Sqlite => {
+                            let val: &str = row.get_unchecked(column_idx);
+                            JsonValue::Bool(val == "1" || val.eq_ignore_ascii_case("true"))
+                        }
+                        _ => JsonValue::Bool(row.get::<bool, _>(column_idx)),
+                    },
+                    _ => JsonValue::String(row.get::<&str, _>(column_idx).to_owned()),
+                }
+            };
+            ret.insert(field.clone(), val);
+            column_idx += 1;
+        }
+        if query.aggregation.count {
+            ret.insert("count".to_owned(), json!(row.get::<i64, _>(column_idx)));
+            column_idx += 1;
+        }
+        if query.aggregation.sum.is_some() {
+            ret.insert(
+                "sum".to_owned(),
+                json!(row.get_unchecked::<f64, _>(column_idx)),
+            );
+            column_idx += 1;
+        }
+        if query.aggregation.avg.is_some() {
+            let avg = if column_is_null(row, column_idx) {
+                JsonValue::Null
+            } else {
+                json!(row.get_unchecked::<f64, _>(column_idx))
+            };
+            ret.insert("avg".to_owned(), avg);
+        }
+        Ok(JsonValue::Object(ret))
     }
 
     /// Execute the given `mutation`.
     ///
     /// Only for testing purposes. For any other purpose, use `mutate_with_transaction`.
     #[cfg(test)]
-    pub async fn mutate(&self, mutation: Mutation) -> Result<()> {
+    pub async fn mutate(&self, mutation: Mutation) -> Result<u64> {
         let mut transaction = self.begin_transaction().await?;
-        self.mutate_with_transaction(mutation, &mut transaction)
+        let deleted = self
+            .mutate_with_transaction(mutation, &mut transaction)
             .await?;
         QueryEngine::commit_transaction(transaction).await?;
-        Ok(())
+        Ok(deleted)
     }
 
+    /// Executes `mutation` in `transaction`. Returns the number of rows it
+    /// deleted.
     pub async fn mutate_with_transaction(
         &self,
         mutation: Mutation,
         transaction: &mut Transaction<'_, Any>,
-    ) -> Result<()> {
+    ) -> Result<u64> {
+        // The links to the rows and the archived rows go first, as finding
+        // them reads the backing table.
+        for raw_sql in mutation.build_join_table_sql(self.target_db())? {
+            transaction.execute(sqlx::query(&raw_sql)).await?;
+        }
+        let mut deleted = 0;
+        if let Some(raw_sql) = mutation.build_archive_sql(self.target_db())? {
+            let query = sqlx::query(&raw_sql);
+            deleted += transaction.execute(query).await?.rows_affected();
+        }
         let raw_sql = mutation.build_sql(self.target_db())?;
         let query = sqlx::query(&raw_sql);
-        transaction.execute(query).await?;
+        let result = transaction.execute(query).await?;
 
-        Ok(())
+        Ok(deleted + result.rows_affected())
+    }
+
+    /// Returns the ids of the rows that `mutation` would delete.
+    pub async fn mutation_ids(
+        &self,
+        mutation: &Mutation,
+        transaction: &mut Transaction<'_, Any>,
+    ) -> Result<Vec<String>> {
+        let raw_sql = mutation.build_ids_sql(self.target_db())?;
+        let rows = transaction.fetch_all(sqlx::query(&raw_sql)).await?;
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
     }
 
     /// Inserts object of type `ty` and value `ty_value` into the database.
@@ -537,16 +1749,252 @@ impl QueryEngine {
         }
     }
 
+    /// Inserts the objects of type `ty` in `values` into the database, batching
+    /// the rows of each table into multi-row INSERTs. Returns the ids of the
+    /// inserted objects, in the format of `add_row()`.
+    pub fn add_rows<'a>(
+        &'a self,
+        ty: &ObjectType,
+        values: &[serde_json::Value],
+        transaction: Option<&'a mut Transaction<'static, Any>>,
+        ts: &TypeSystem,
+    ) -> impl Future<Output = Result<Vec<IdTree>>> + 'a {
+        let res = self.prepare_bulk_insertion(ty, values, ts);
+        async {
+            let (inserts, id_trees) = res?;
+            self.run_sql_queries(&inserts, transaction).await?;
+            Ok(id_trees)
+        }
+    }
+
+    /// Sets the fields in `changes` of the object of type `ty` with id `id`,
+    /// leaving the others as they are. Entity fields are changed to refer to
+    /// the object with the `id` of the given one, which isn't saved. Returns
+    /// whether the object exists.
+    pub fn update_row<'a>(
+        &'a self,
+        ty: &ObjectType,
+        id: &str,
+        changes: &JsonObject,
+        transaction: &'a mut Transaction<'static, Any>,
+        ts: &TypeSystem,
+    ) -> impl Future<Output = Result<bool>> + 'a {
+        let res = self.prepare_update(ty, id, changes, ts);
+        async move {
+            let update = res?;
+            let result = transaction.execute(update.get_sqlx()).await?;
+            Ok(result.rows_affected() > 0)
+        }
+    }
+
+    /// Inserts object of type `ty` and value `ty_value` like `add_row()`,
+    /// unless there already is an object with the same value of the unique
+    /// field `key`. Then the fields in `changes` of the stored object are set
+    /// instead, like `update_row()` does, in the same statement. With `guard`,
+    /// the stored object is only updated if its value of the `guard` field is
+    /// the one in `ty_value`. Returns the id of the inserted or updated
+    /// object, or None if the stored object wasn't updated because of `guard`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn upsert_row<'a>(
+        &'a self,
+        ty: &ObjectType,
+        key: &str,
+        ty_value: &JsonObject,
+        changes: &JsonObject,
+        guard: Option<&str>,
+        transaction: &'a mut Transaction<'static, Any>,
+        ts: &TypeSystem,
+    ) -> impl Future<Output = Result<Option<String>>> + 'a {
+        let res = self.prepare_upsert(ty, key, ty_value, changes, guard, ts);
+        async move {
+            let (inserts, upsert) = res?;
+            self.run_sql_queries(&inserts, Some(&mut *transaction))
+                .await?;
+            let row = QueryEngine::fetch_optional_with_transaction(transaction, upsert).await?;
+            row.map(|row| Ok(row.try_get::<String, _>("id")?))
+                .transpose()
+        }
+    }
+
+    /// Replaces all the rows of `ty` with `rows`, which are inserted like
+    /// `add_row_shallow()`, in one transaction.
+    pub async fn replace_rows(&self, ty: &ObjectType, rows: &[JsonObject]) -> Result<()> {
+        let inserts = rows
+            .iter()
+            .map(|row| self.prepare_insertion_shallow(ty, row))
+            .collect::<Result<Vec<_>>>()?;
+        let mut transaction = self.begin_transaction().await?;
+        let delete = format!(r#"DELETE FROM "{}""#, ty.backing_table());
+        transaction.execute(delete.as_str()).await?;
+        self.run_sql_queries(&inserts, Some(&mut transaction))
+            .await?;
+        QueryEngine::commit_transaction(transaction).await
+    }
+
     pub async fn add_row_shallow(&self, ty: &ObjectType, ty_value: &JsonObject) -> Result<()> {
         let query = self.prepare_insertion_shallow(ty, ty_value)?;
         self.run_sql_queries(&[query], None).await?;
         Ok(())
     }
 
+    /// Upserts `rows` of type `ty` like `add_row_shallow()`, all in one
+    /// transaction. On Postgres, the rows are streamed with COPY into a
+    /// temporary table and merged from there, which is much faster than
+    /// INSERTs for large loads. On SQLite, they are upserted by multi-row
+    /// INSERTs.
+    pub async fn load_rows(&self, ty: &ObjectType, rows: &[JsonObject]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        match self.target_db() {
+            TargetDatabase::Postgres => self.copy_rows(ty, rows).await,
+            TargetDatabase::Sqlite => {
+                let mut batches: Vec<(InsertShape, Vec<RowInsert>)> = vec![];
+                for row in rows {
+                    let row = self.shallow_row(ty, row)?;
+                    match batches.iter_mut().find(|(shape, _)| *shape == row.shape) {
+                        Some((_, batch)) => batch.push(row),
+                        None => batches.push((row.shape.clone(), vec![row])),
+                    }
+                }
+                let inserts: Vec<_> = batches
+                    .into_iter()
+                    .flat_map(|(shape, rows)| shape.chunked_sql(rows))
+                    .collect();
+                let mut transaction = self.begin_transaction().await?;
+                self.run_sql_queries(&inserts, Some(&mut transaction))
+                    .await?;
+                QueryEngine::commit_transaction(transaction).await
+            }
+        }
+    }
+
+    async fn copy_rows(&self, ty: &ObjectType, rows: &[JsonObject]) -> Result<()> {
+        let columns: Vec<_> = ty.column_fields().collect();
+        let mut csv = String::new();
+        for row in rows {
+            let mut args = vec![];
+            for field in &columns {
+                let value = row.get(&field.name);
+                if field.is_optional && value.map_or(true, JsonValue::is_null) {
+                    args.extend(field_columns(field).map(|_| None));
+                    continue;
+                }
+                let arg = self
+                    .convert_to_argument(field, row)
+                    .with_context(|| QueryEngine::incompatible(field, ty))?;
+                args.push(Some(arg));
+                if field.discriminator_column().is_some() {
+                    args.push(Some(discriminator_argument(field, row)?));
+                }
+            }
+            for (i, arg) in args.into_iter().enumerate() {
+                if i > 0 {
+                    csv.push(',');
+                }
+                match arg {
+                    // An unquoted empty value is NULL.
+                    None => {}
+                    Some(SqlValue::Bool(b)) => csv.push_str(if b { "true" } else { "false" }),
+                    Some(SqlValue::F64(f)) => csv.push_str(&f.to_string()),
+                    Some(SqlValue::String(s)) => {
+                        csv.push('"');
+                        csv.push_str(&s.replace('"', "\"\""));
+                        csv.push('"');
+                    }
+                }
+            }
+            csv.push('\n');
+        }
+
+        let table = ty.backing_table();
+        let names = columns
+            .iter()
+            .copied()
+            .flat_map(field_columns)
+            .map(|column| format!("\"{}\"", column))
+            .join(",");
+        let assignments = columns
+            .iter()
+            .copied()
+            .flat_map(field_columns)
+            .map(|column| format!("\"{0}\" = excluded.\"{0}\"", column))
+            .join(",");
+        let id = columns
+            .iter()
+            .find(|field| field.type_id == TypeId::Id)
+            .map(|field| field.column_name())
+            .unwrap_or("id");
+        let partition_column = ty
+            .partition()
+            .map(|partition| ty.column(&partition.field))
+            .filter(|column| *column != id);
+        let conflict_target = match partition_column {
+            Some(field) => format!("\"{}\", \"{}\"", id, field),
+            None => format!("\"{}\"", id),
+        };
+
+        // COPY needs a connection of the Postgres driver itself.
+        let mut conn = PgConnection::connect(&self.db.conn_uri).await?;
+        let mut transaction = conn.begin().await?;
+        transaction
+            .execute(
+                format!(
+                    "CREATE TEMPORARY TABLE \"chisel_load\" (LIKE \"{}\") ON COMMIT DROP",
+                    table
+                )
+                .as_str(),
+            )
+            .await?;
+        let mut copy = transaction
+            .copy_in_raw(&format!(
+                "COPY \"chisel_load\" ({}) FROM STDIN WITH (FORMAT csv)",
+                names
+            ))
+            .await?;
+        copy.send(csv.into_bytes()).await?;
+        copy.finish().await?;
+        if let Some(field) = partition_column {
+            // The rows that move to another partition don't conflict with
+            // the ones they replace.
+            transaction
+                .execute(
+                    format!(
+                        "DELETE FROM \"{0}\" USING \"chisel_load\" WHERE \"{0}\".\"{1}\" = \"chisel_load\".\"{1}\" AND \"{0}\".\"{2}\" <> \"chisel_load\".\"{2}\"",
+                        table, id, field
+                    )
+                    .as_str(),
+                )
+                .await?;
+        }
+        transaction
+            .execute(
+                format!(
+                    "INSERT INTO \"{0}\" ({1}) SELECT {1} FROM \"chisel_load\" ON CONFLICT ({2}) DO UPDATE SET {3}",
+                    table, names, conflict_target, assignments
+                )
+                .as_str(),
+            )
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
     pub async fn fetch_one(&self, q: SqlWithArguments) -> Result<AnyRow> {
         Ok(q.get_sqlx().fetch_one(&self.db.pool).await?)
     }
 
+    pub async fn fetch_optional(&self, q: SqlWithArguments) -> Result<Option<AnyRow>> {
+        Ok(q.get_sqlx().fetch_optional(&self.db.pool).await?)
+    }
+
+    pub async fn fetch_optional_with_transaction(
+        transaction: &mut Transaction<'_, Any>,
+        q: SqlWithArguments,
+    ) -> Result<Option<AnyRow>> {
+        Ok(transaction.fetch_optional(q.get_sqlx()).await?)
+    }
+
     async fn run_sql_queries(
         &self,
         queries: &[SqlWithArguments],
@@ -584,17 +2032,105 @@ impl QueryEngine {
         ty_value: &JsonObject,
         ts: &TypeSystem,
     ) -> Result<(Vec<SqlWithArguments>, IdTree)> {
+        let (rows, id_tree) = self.prepare_row_insertion(ty, ty_value, ts)?;
+        let inserts = rows.into_iter().flat_map(RowInsert::into_sql).collect();
+        Ok((inserts, id_tree))
+    }
+
+    /// Like `prepare_insertion()`, but for all the objects in `values`, and
+    /// with the rows of the same shape inserted by a single query.
+    fn prepare_bulk_insertion(
+        &self,
+        ty: &ObjectType,
+        values: &[serde_json::Value],
+        ts: &TypeSystem,
+    ) -> Result<(Vec<SqlWithArguments>, Vec<IdTree>)> {
+        let mut rows = vec![];
+        let mut id_trees = vec![];
+        for (i, value) in values.iter().enumerate() {
+            let value = value
+                .as_object()
+                .ok_or_else(|| anyhow!("element {} of the rows to insert is not an object", i))?;
+            let (value_rows, id_tree) = self.prepare_row_insertion(ty, value, ts)?;
+            rows.extend(value_rows);
+            id_trees.push(id_tree);
+        }
+
+        // An object saved several times, like one nested in many others, ends
+        // up as it was saved last. A single INSERT can't upsert a row twice, so
+        // only keep its last row.
+        let row_key = |row: &RowInsert| (row.shape.table.clone(), row.id.clone());
+        let last_rows: HashMap<_, usize> = rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| (row_key(row), i))
+            .collect();
+        let keep: Vec<bool> = rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| last_rows[&row_key(row)] == i)
+            .collect();
+
+        let mut batches: Vec<(InsertShape, Vec<RowInsert>)> = vec![];
+        let mut links = vec![];
+        for (mut row, keep) in rows.into_iter().zip(keep) {
+            if !keep {
+                continue;
+            }
+            links.append(&mut row.links);
+            match batches.iter_mut().find(|(shape, _)| *shape == row.shape) {
+                Some((_, batch)) => batch.push(row),
+                None => batches.push((row.shape.clone(), vec![row])),
+            }
+        }
+
+        let mut inserts: Vec<_> = batches
+            .into_iter()
+            .flat_map(|(shape, rows)| shape.chunked_sql(rows))
+            .collect();
+        // Objects are linked once they all are inserted.
+        inserts.extend(links);
+        Ok((inserts, id_trees))
+    }
+
+    fn prepare_row_insertion(
+        &self,
+        ty: &ObjectType,
+        ty_value: &JsonObject,
+        ts: &TypeSystem,
+    ) -> Result<(Vec<RowInsert>, IdTree)> {
         let mut child_ids = HashMap::<String, IdTree>::new();
+        let mut list_ids = HashMap::<String, Vec<IdTree>>::new();
         let mut obj_id = Option::<String>::None;
         let mut query_args = Vec::<SqlValue>::new();
-        let mut inserts = Vec::<SqlWithArguments>::new();
+        let mut inserts = Vec::<RowInsert>::new();
+        let mut links = Vec::<SqlWithArguments>::new();
 
         for field in ty.all_fields() {
             let field_value = ty_value.get(&field.name);
+            let incompatible_data = || QueryEngine::incompatible(field, ty);
+            if field.type_id.is_many_to_many() {
+                // Without a value, the object keeps the related objects it has.
+                let values = match field_value {
+                    None | Some(serde_json::Value::Null) => continue,
+                    Some(value) => value
+                        .as_array()
+                        .context("unexpected json type (expected an array)")
+                        .with_context(incompatible_data)?,
+                };
+                // The id field comes first, so the id of the object is known.
+                let owner = obj_id.as_deref().context("the id value is missing")?;
+                let (item_inserts, item_ids) =
+                    self.prepare_list_insertion(ty, field, values, ts)?;
+                inserts.extend(item_inserts);
+                let items: Vec<&str> = item_ids.iter().map(|ids| ids.id.as_str()).collect();
+                links.extend(link_sql(&ty.join_table(field)?, owner, &items));
+                list_ids.insert(field.name.to_owned(), item_ids);
+                continue;
+            }
             if (field_value.is_none() || field_value.unwrap().is_null()) && field.is_optional {
                 continue;
             }
-            let incompatible_data = || QueryEngine::incompatible(field, ty);
             let arg = match ts.get(&field.type_id)? {
                 Type::Entity(nested_type) => {
                     let nested_value = field_value
@@ -616,7 +2152,7 @@ impl QueryEngine {
                         }
                     } else {
                         let (nested_inserts, nested_ids) =
-                            self.prepare_insertion(&nested_type, nested_value, ts)?;
+                            self.prepare_row_insertion(&nested_type, nested_value, ts)?;
                         inserts.extend(nested_inserts);
                         let nested_id = nested_ids.id.to_owned();
                         child_ids.insert(field.name.to_owned(), nested_ids);
@@ -624,6 +2160,22 @@ impl QueryEngine {
                     };
                     SqlValue::String(nested_id)
                 }
+                Type::Union(entities) => {
+                    let nested_value = field_value
+                        .context("json object doesn't have required field")
+                        .with_context(incompatible_data)?
+                        .as_object()
+                        .context("unexpected json type (expected an object)")
+                        .with_context(incompatible_data)?;
+                    let nested_type = union_member(field, &entities, nested_value)?;
+                    let (nested_inserts, nested_ids) =
+                        self.prepare_row_insertion(nested_type, nested_value, ts)?;
+                    inserts.extend(nested_inserts);
+                    query_args.push(SqlValue::String(nested_ids.id.to_owned()));
+                    query_args.push(SqlValue::String(nested_type.name().to_owned()));
+                    child_ids.insert(field.name.to_owned(), nested_ids);
+                    continue;
+                }
                 _ => self
                     .convert_to_argument(field, ty_value)
                     .with_context(incompatible_data)?,
@@ -639,21 +2191,68 @@ impl QueryEngine {
             query_args.push(arg);
         }
 
-        inserts.push(SqlWithArguments {
-            sql: self.make_insert_query(ty, ty_value)?,
-            args: query_args,
-        });
         let obj_id = obj_id
             .ok_or_else(|| anyhow!("attempting to insert an object `{}` with no id", ty.name()))?;
+        inserts.push(RowInsert {
+            shape: self.insert_shape(ty, ty_value)?,
+            id: obj_id.clone(),
+            args: query_args,
+            links,
+        });
         Ok((
             inserts,
             IdTree {
                 id: obj_id,
                 children: child_ids,
+                lists: list_ids,
             },
         ))
     }
 
+    /// Generates the inserts of the objects `values` of many-to-many relation
+    /// `field` of type `ty`, returning them with the IdTrees of the objects
+    /// in order. Like objects nested in entity fields, they are saved along
+    /// with the object that relates to them, except for auth objects, which
+    /// must already exist.
+    fn prepare_list_insertion(
+        &self,
+        ty: &ObjectType,
+        field: &Field,
+        values: &[serde_json::Value],
+        ts: &TypeSystem,
+    ) -> Result<(Vec<RowInsert>, Vec<IdTree>)> {
+        let item_type = match ts.get(&field.type_id)? {
+            Type::Array(item_type) => match *item_type {
+                Type::Entity(item_type) => item_type,
+                _ => anyhow::bail!("field {} is not a many-to-many relation", field.name),
+            },
+            _ => anyhow::bail!("field {} is not a many-to-many relation", field.name),
+        };
+        let mut inserts = vec![];
+        let mut ids = vec![];
+        for value in values {
+            let value = value
+                .as_object()
+                .context("unexpected json type (expected an array of objects)")
+                .with_context(|| QueryEngine::incompatible(field, ty))?;
+            if item_type.is_auth() {
+                match value.get("id") {
+                    Some(serde_json::Value::String(id)) => ids.push(IdTree {
+                        id: id.clone(),
+                        children: Default::default(),
+                        lists: Default::default(),
+                    }),
+                    _ => anyhow::bail!("Cannot save into type {}.", item_type.name()),
+                }
+            } else {
+                let (item_inserts, item_ids) = self.prepare_row_insertion(&item_type, value, ts)?;
+                inserts.extend(item_inserts);
+                ids.push(item_ids);
+            }
+        }
+        Ok((inserts, ids))
+    }
+
     /// Converts `field` with value `ty_value` into SqlValue while ensuring the
     /// generation of default and generable values.
     fn convert_to_argument(&self, field: &Field, ty_value: &JsonObject) -> Result<SqlValue> {
@@ -685,11 +2284,47 @@ impl QueryEngine {
         }
 
         let arg = match &field.type_id {
-            TypeId::String | TypeId::Id | TypeId::Entity { .. } => {
+            TypeId::String | TypeId::Id | TypeId::Entity { .. } | TypeId::Union { .. } => {
                 SqlValue::String(convert_json_value!(as_str, String))
             }
             TypeId::Float => SqlValue::F64(convert_json_value!(as_f64, f64)),
             TypeId::Boolean => SqlValue::Bool(convert_json_value!(as_bool, bool)),
+            TypeId::Enum(variants) => {
+                let val = convert_json_value!(as_str, String);
+                anyhow::ensure!(
+                    variants.contains(&val),
+                    "value {:?} of field `{}` is not one of {}",
+                    val,
+                    field.name,
+                    field.type_id.name()
+                );
+                SqlValue::String(val)
+            }
+            TypeId::DateTime => {
+                let val = match ty_value.get(&field.name) {
+                    Some(value_json) => datetime::from_json(value_json)?,
+                    None => {
+                        let value = field.generate_value().context("failed to generate value")?;
+                        datetime::from_str(&value)?
+                    }
+                };
+                SqlValue::String(val)
+            }
+            TypeId::Json => {
+                let val = match ty_value.get(&field.name) {
+                    Some(value_json) => serde_json::to_string(value_json)?,
+                    None => field.generate_value().context("failed to generate value")?,
+                };
+                SqlValue::String(val)
+            }
+            TypeId::File => {
+                let value_json = ty_value
+                    .get(&field.name)
+                    .with_context(|| format!("field `{}` has no file", field.name))?;
+                let file = blobs::FileMetadata::from_json(value_json)
+                    .with_context(|| format!("value of field `{}` is not a file", field.name))?;
+                SqlValue::String(serde_json::to_string(&file)?)
+            }
             TypeId::Array(element_type) => {
                 let val = match ty_value.get(&field.name) {
                     Some(value_json) => {
@@ -722,10 +2357,31 @@ impl QueryEngine {
                     TypeId::String | TypeId::Id => maybe_bail!(is_string),
                     TypeId::Float => maybe_bail!(is_number),
                     TypeId::Boolean => maybe_bail!(is_boolean),
+                    TypeId::Enum(variants) => {
+                        if !e
+                            .as_str()
+                            .map_or(false, |e| variants.iter().any(|v| v == e))
+                        {
+                            anyhow::bail!(
+                                "stored array should have elements of type '{}', but found '{:?}' at position {i}",
+                                element_type.name(),
+                                e
+                            )
+                        }
+                    }
+                    TypeId::DateTime => {
+                        datetime::from_json(e)
+                            .with_context(|| format!("invalid date at position {i}"))?;
+                    }
+                    TypeId::Json => {}
+                    TypeId::File => {
+                        blobs::FileMetadata::from_json(e)
+                            .with_context(|| format!("invalid file at position {i}"))?;
+                    }
                     TypeId::Array(inner_element) => self
                         .validate_array(inner_element, e)
                         .context("failed to validate inner array at position {i}")?,
-                    TypeId::Entity { .. } => {
+                    TypeId::Entity { .. } | TypeId::Union { .. } => {
                         unreachable!("entity can't be a contained within an array")
                     }
                 }
@@ -736,66 +2392,244 @@ impl QueryEngine {
         Ok(())
     }
 
-    /// For given object of type `ty` and its value `ty_value` computes a string
-    /// representing SQL query which inserts the object into database.
-    fn make_insert_query(&self, ty: &ObjectType, ty_value: &JsonObject) -> Result<String> {
-        let mut field_binds = String::new();
-        let mut field_names = vec![];
-        let mut id_name = String::new();
-        let mut update_binds = String::new();
-        let mut id_bind = String::new();
+    /// For given object of type `ty` and its value `ty_value` computes the
+    /// shape of the SQL query which inserts the object into database.
+    fn insert_shape(&self, ty: &ObjectType, ty_value: &JsonObject) -> Result<InsertShape> {
+        let mut columns = vec![];
+        let mut id_column = None;
 
-        let mut i = 0;
-        for f in ty.all_fields() {
+        for f in ty.column_fields() {
             let val = ty_value.get(&f.name);
             if val.is_none() && f.is_optional {
                 continue;
             }
-            let bind = if f.is_optional && val.unwrap().is_null() {
-                // sqlx has trouble binding null values in some cases; insert them verbatim.
-                "NULL".to_string()
-            } else {
-                i += 1;
-                std::format!("${}", i)
-            };
-            field_binds.push_str(&bind);
-            field_binds.push(',');
-
-            field_names.push(f.name.clone());
+            // sqlx has trouble binding null values in some cases; insert them verbatim.
+            let is_null = f.is_optional && val.unwrap().is_null();
+            let cast = self.argument_cast(&f.type_id);
             if f.type_id == TypeId::Id {
                 if let Some(idstr) = val {
                     let idstr = idstr.as_str().context("invalid ID: It is not a string")?;
                     Uuid::parse_str(idstr).map_err(|_| anyhow!("invalid ID '{}'", idstr))?;
                 }
-                anyhow::ensure!(id_bind.is_empty(), "More than one ID??");
-                id_name = f.name.to_string();
-                id_bind = bind.clone();
+                anyhow::ensure!(id_column.is_none(), "More than one ID??");
+                id_column = Some(f.name.clone());
+            }
+            columns.push(InsertColumn {
+                name: f.column_name().to_owned(),
+                is_null,
+                cast,
+            });
+            if let Some(discriminator) = f.discriminator_column() {
+                columns.push(InsertColumn {
+                    name: discriminator,
+                    is_null,
+                    cast: None,
+                });
             }
-            write!(update_binds, "\"{}\" = {},", &f.name, &bind).unwrap();
         }
-        field_binds.pop();
-        update_binds.pop();
 
         for v in ty_value.keys() {
             anyhow::ensure!(
-                field_names.contains(v),
+                v == TYPE_KEY
+                    || ty
+                        .column_fields()
+                        .any(|f| &f.name == v || f.discriminator_column().as_ref() == Some(v))
+                    || ty.many_to_many_fields().any(|f| &f.name == v),
                 "field {} not present in {}",
                 v,
                 ty.name()
             );
         }
 
-        Ok(std::format!(
-            "INSERT INTO \"{}\" ({}) VALUES ({}) ON CONFLICT ({}) DO UPDATE SET {} WHERE \"{}\".\"{}\" = {}",
-            &ty.backing_table(),
-            field_names.into_iter().map(|f| format!("\"{}\"", f)).join(","),
-            field_binds,
-            id_name,
-            update_binds,
-            &ty.backing_table(),
-            id_name,
-            id_bind,
-        ))
+        let partition_column = ty
+            .partition()
+            .filter(|partition| partition.field != "id" && self.target_db().is_postgres())
+            .map(|partition| ty.column(&partition.field).to_owned());
+        Ok(InsertShape {
+            table: ty.backing_table().to_owned(),
+            columns,
+            id_column: id_column.unwrap_or_default(),
+            partition_column,
+        })
+    }
+
+    fn prepare_update(
+        &self,
+        ty: &ObjectType,
+        id: &str,
+        changes: &JsonObject,
+        ts: &TypeSystem,
+    ) -> Result<SqlWithArguments> {
+        Uuid::parse_str(id).map_err(|_| anyhow!("invalid ID '{}'", id))?;
+        let mut args = vec![SqlValue::String(id.to_owned())];
+        let assignments = self.update_assignments(ty, changes, &mut args, ts)?;
+        anyhow::ensure!(
+            !assignments.is_empty(),
+            "no fields of {} to update",
+            ty.name()
+        );
+
+        Ok(SqlWithArguments {
+            sql: format!(
+                "UPDATE \"{}\" SET {} WHERE \"id\" = $1",
+                ty.backing_table(),
+                assignments.join(", ")
+            ),
+            args,
+        })
+    }
+
+    /// Returns the assignments of an UPDATE which sets the fields in `changes`
+    /// of an object of type `ty`. Their arguments are added to `args`, and
+    /// numbered after those already in it.
+    fn update_assignments(
+        &self,
+        ty: &ObjectType,
+        changes: &JsonObject,
+        args: &mut Vec<SqlValue>,
+        ts: &TypeSystem,
+    ) -> Result<Vec<String>> {
+        let mut assignments = vec![];
+        for (name, value) in changes {
+            let field = ty
+                .all_fields()
+                .find(|f| &f.name == name)
+                .ok_or_else(|| anyhow!("field {} not present in {}", name, ty.name()))?;
+            anyhow::ensure!(
+                field.type_id != TypeId::Id,
+                "the id of {} can't be updated",
+                ty.name()
+            );
+            anyhow::ensure!(
+                !field.type_id.is_many_to_many(),
+                "the many-to-many relation {} of {} can't be updated, save the object instead",
+                name,
+                ty.name()
+            );
+            let incompatible_data = || QueryEngine::incompatible(field, ty);
+            if value.is_null() {
+                anyhow::ensure!(
+                    field.is_optional,
+                    "field {} of {} is not optional",
+                    name,
+                    ty.name()
+                );
+                // sqlx has trouble binding null values in some cases; set them verbatim.
+                for column in field_columns(field) {
+                    assignments.push(format!("\"{}\" = NULL", column));
+                }
+                continue;
+            }
+            let arg = match ts.get(&field.type_id)? {
+                Type::Union(entities) => {
+                    let nested_value = value
+                        .as_object()
+                        .context("expected an object with an id")
+                        .with_context(incompatible_data)?;
+                    let nested_type = union_member(field, &entities, nested_value)?;
+                    let nested_id = nested_value
+                        .get("id")
+                        .and_then(|id| id.as_str())
+                        .context("expected an object with an id")
+                        .with_context(incompatible_data)?;
+                    for arg in [nested_id, nested_type.name()] {
+                        args.push(SqlValue::String(arg.to_owned()));
+                    }
+                    assignments.push(format!(
+                        "\"{}\" = ${}, \"{}\" = ${}",
+                        field.column_name(),
+                        args.len() - 1,
+                        field.discriminator_column().unwrap_or_default(),
+                        args.len()
+                    ));
+                    continue;
+                }
+                Type::Entity(_) => {
+                    let nested_id = value
+                        .get("id")
+                        .and_then(|id| id.as_str())
+                        .context("expected an object with an id")
+                        .with_context(incompatible_data)?;
+                    SqlValue::String(nested_id.to_owned())
+                }
+                _ => self
+                    .convert_to_argument(field, changes)
+                    .with_context(incompatible_data)?,
+            };
+            args.push(arg);
+            let placeholder = cast_placeholder(args.len(), self.argument_cast(&field.type_id));
+            assignments.push(format!("\"{}\" = {}", field.column_name(), placeholder));
+        }
+        Ok(assignments)
+    }
+
+    /// Returns the inserts of the objects nested in `ty_value`, followed by
+    /// the upsert of the object itself, for `upsert_row()`.
+    fn prepare_upsert(
+        &self,
+        ty: &ObjectType,
+        key: &str,
+        ty_value: &JsonObject,
+        changes: &JsonObject,
+        guard: Option<&str>,
+        ts: &TypeSystem,
+    ) -> Result<(Vec<SqlWithArguments>, SqlWithArguments)> {
+        let key_field = ty
+            .all_fields()
+            .find(|f| f.name == key)
+            .ok_or_else(|| anyhow!("field {} not present in {}", key, ty.name()))?;
+        anyhow::ensure!(
+            key_field.is_unique || key_field.type_id == TypeId::Id,
+            "field {} of {} is not unique",
+            key,
+            ty.name()
+        );
+        // The stored object may have another id than the one in `ty_value`,
+        // which the links to related objects would refer to.
+        anyhow::ensure!(
+            ty.many_to_many_fields().next().is_none(),
+            "{} has many-to-many relations, which can't be upserted",
+            ty.name()
+        );
+        let (mut rows, _) = self.prepare_row_insertion(ty, ty_value, ts)?;
+        // The object's own row comes after those of the objects nested in it.
+        let row = rows.pop().unwrap();
+        let inserts = rows.into_iter().flat_map(RowInsert::into_sql).collect();
+
+        let mut args = row.args;
+        let mut assignments = self.update_assignments(ty, changes, &mut args, ts)?;
+        if assignments.is_empty() {
+            // DO NOTHING wouldn't return the id of the stored row.
+            assignments.push(format!(
+                "\"{0}\" = excluded.\"{0}\"",
+                key_field.column_name()
+            ));
+        }
+        let condition = match guard {
+            Some(field) => format!(
+                " WHERE \"{0}\".\"{1}\" = excluded.\"{1}\"",
+                ty.backing_table(),
+                ty.column(field)
+            ),
+            None => "".to_owned(),
+        };
+        // Partitioned types have no unique fields, so they're upserted by id.
+        let (moved_rows, conflict_target) = match row.shape.partition_column {
+            Some(_) => (row.shape.moved_rows_cte(1), row.shape.conflict_target()),
+            None => ("".to_owned(), format!("\"{}\"", key_field.column_name())),
+        };
+        let upsert = SqlWithArguments {
+            sql: format!(
+                "{}{} ON CONFLICT ({}) DO UPDATE SET {}{} RETURNING \"id\"",
+                moved_rows,
+                row.shape.insert_sql(1),
+                conflict_target,
+                assignments.join(", "),
+                condition
+            ),
+            args,
+        };
+        Ok((inserts, upsert))
     }
 
     fn prepare_insertion_shallow(
@@ -803,20 +2637,238 @@ impl QueryEngine {
         ty: &ObjectType,
         ty_value: &JsonObject,
     ) -> Result<SqlWithArguments> {
-        let mut query_args = Vec::<SqlValue>::new();
-        for field in ty.all_fields() {
+        let row = self.shallow_row(ty, ty_value)?;
+        Ok(row.shape.clone().rows_sql(vec![row]))
+    }
+
+    /// The row of object `ty_value` of type `ty`, whose entity fields hold
+    /// the ids of the related objects rather than the objects themselves.
+    fn shallow_row(&self, ty: &ObjectType, ty_value: &JsonObject) -> Result<RowInsert> {
+        let shape = self.insert_shape(ty, ty_value)?;
+        let mut args = Vec::<SqlValue>::new();
+        for field in ty.column_fields() {
             if ty_value.get(&field.name).is_none() && field.is_optional {
                 continue;
             }
             let arg = self
                 .convert_to_argument(field, ty_value)
                 .with_context(|| QueryEngine::incompatible(field, ty))?;
-            query_args.push(arg);
+            args.push(arg);
+            if field.discriminator_column().is_some() {
+                args.push(discriminator_argument(field, ty_value)?);
+            }
         }
-
-        Ok(SqlWithArguments {
-            sql: self.make_insert_query(ty, ty_value)?,
-            args: query_args,
+        let id = match ty_value.get(&shape.id_column) {
+            Some(JsonValue::String(id)) => id.clone(),
+            _ => String::new(),
+        };
+        Ok(RowInsert {
+            shape,
+            id,
+            args,
+            links: vec![],
         })
     }
 }
+
+/// Upper bound of the placeholders of a multi-row INSERT, to stay within the
+/// limit of SQLite builds before 3.32.
+const MAX_INSERT_ARGS: usize = 999;
+
+/// The table and columns an object is inserted into. Rows of the same shape
+/// can be inserted by the same query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct InsertShape {
+    table: String,
+    columns: Vec<InsertColumn>,
+    id_column: String,
+    /// The column other than the id the table is partitioned by, if any.
+    /// Rows are only unique by both, so a row whose value of it changes has
+    /// to be deleted from the partition it was in.
+    partition_column: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct InsertColumn {
+    name: String,
+    /// Whether the value is NULL.
+    is_null: bool,
+    /// The type the argument is cast to, if any.
+    cast: Option<&'static str>,
+}
+
+/// Placeholder of argument `i`, cast to `cast` if set.
+fn cast_placeholder(i: usize, cast: Option<&str>) -> String {
+    match cast {
+        Some(ty) => format!("CAST(${} AS {})", i, ty),
+        None => format!("${}", i),
+    }
+}
+
+impl InsertShape {
+    /// SQL which upserts `rows` rows of this shape, with placeholders for
+    /// the non-NULL values of each row in turn.
+    fn sql(&self, rows: usize) -> String {
+        std::format!(
+            "{}{} ON CONFLICT ({}) DO UPDATE SET {}",
+            self.moved_rows_cte(rows),
+            self.insert_sql(rows),
+            self.conflict_target(),
+            self.columns
+                .iter()
+                .map(|column| format!("\"{0}\" = excluded.\"{0}\"", column.name))
+                .join(","),
+        )
+    }
+
+    /// The columns the rows are upserted on.
+    fn conflict_target(&self) -> String {
+        match &self.partition_column {
+            Some(column) => format!("\"{}\", \"{}\"", self.id_column, column),
+            None => format!("\"{}\"", self.id_column),
+        }
+    }
+
+    /// On partitioned tables, a WITH clause which deletes the stored rows
+    /// that `rows` rows of this shape replace, but which are in another
+    /// partition than they go to. It refers to the placeholders of
+    /// `insert_sql(rows)`.
+    fn moved_rows_cte(&self, rows: usize) -> String {
+        let partition_column = match &self.partition_column {
+            Some(column) => column,
+            None => return String::new(),
+        };
+        let bound: Vec<_> = self.columns.iter().filter(|c| !c.is_null).collect();
+        let placeholder = |row: usize, name: &str| {
+            let idx = bound.iter().position(|c| c.name == name)?;
+            Some(cast_placeholder(
+                row * bound.len() + idx + 1,
+                bound[idx].cast,
+            ))
+        };
+        let conditions = (0..rows)
+            .filter_map(|row| {
+                Some(format!(
+                    "(\"{}\" = {} AND \"{}\" <> {})",
+                    self.id_column,
+                    placeholder(row, &self.id_column)?,
+                    partition_column,
+                    placeholder(row, partition_column)?
+                ))
+            })
+            .join(" OR ");
+        if conditions.is_empty() {
+            return String::new();
+        }
+        format!(
+            "WITH \"moved\" AS (DELETE FROM \"{}\" WHERE {}) ",
+            self.table, conditions
+        )
+    }
+
+    /// Like `sql()`, but failing on conflicts.
+    fn insert_sql(&self, rows: usize) -> String {
+        let mut i = 0;
+        let values = (0..rows)
+            .map(|_| {
+                let binds = self
+                    .columns
+                    .iter()
+                    .map(|column| {
+                        if column.is_null {
+                            "NULL".to_string()
+                        } else {
+                            i += 1;
+                            cast_placeholder(i, column.cast)
+                        }
+                    })
+                    .join(",");
+                format!("({})", binds)
+            })
+            .join(",");
+        std::format!(
+            "INSERT INTO \"{}\" ({}) VALUES {}",
+            &self.table,
+            self.columns
+                .iter()
+                .map(|column| format!("\"{}\"", column.name))
+                .join(","),
+            values,
+        )
+    }
+
+    fn rows_sql(&self, rows: Vec<RowInsert>) -> SqlWithArguments {
+        SqlWithArguments {
+            sql: self.sql(rows.len()),
+            args: rows.into_iter().flat_map(|row| row.args).collect(),
+        }
+    }
+
+    /// Upserts `rows` by as few queries as the limit on placeholders allows.
+    fn chunked_sql(&self, rows: Vec<RowInsert>) -> Vec<SqlWithArguments> {
+        let mut inserts = vec![];
+        let mut chunk: Vec<RowInsert> = vec![];
+        let mut chunk_args = 0;
+        for row in rows {
+            if chunk_args + row.args.len() > MAX_INSERT_ARGS && !chunk.is_empty() {
+                inserts.push(self.rows_sql(std::mem::take(&mut chunk)));
+                chunk_args = 0;
+            }
+            chunk_args += row.args.len();
+            chunk.push(row);
+        }
+        if !chunk.is_empty() {
+            inserts.push(self.rows_sql(chunk));
+        }
+        inserts
+    }
+}
+
+/// A row to insert, with the values of the non-NULL columns of its shape.
+struct RowInsert {
+    shape: InsertShape,
+    id: String,
+    args: Vec<SqlValue>,
+    /// Statements linking the object to the objects of its many-to-many
+    /// relations, to run after it and them are inserted.
+    links: Vec<SqlWithArguments>,
+}
+
+impl RowInsert {
+    /// Returns the insert of the row, followed by its links.
+    fn into_sql(mut self) -> Vec<SqlWithArguments> {
+        let links = std::mem::take(&mut self.links);
+        let shape = self.shape.clone();
+        std::iter::once(shape.rows_sql(vec![self]))
+            .chain(links)
+            .collect()
+    }
+}
+
+/// Returns the statements which replace the links of object `owner` in
+/// `join_table` with links to `items`, in order.
+fn link_sql(join_table: &str, owner: &str, items: &[&str]) -> Vec<SqlWithArguments> {
+    let mut sqls = vec![SqlWithArguments {
+        sql: format!("DELETE FROM \"{}\" WHERE \"owner\" = $1", join_table),
+        args: vec![SqlValue::String(owner.to_owned())],
+    }];
+    let mut position = 0;
+    for chunk in items.chunks(MAX_INSERT_ARGS / 2) {
+        let mut args = vec![SqlValue::String(owner.to_owned())];
+        let mut values = vec![];
+        for item in chunk {
+            args.push(SqlValue::String((*item).to_owned()));
+            values.push(format!("($1, ${}, {})", args.len(), position));
+            position += 1;
+        }
+        sqls.push(SqlWithArguments {
+            sql: format!(
+                "INSERT INTO \"{}\" (\"owner\", \"item\", \"position\") VALUES {}",
+                join_table,
+                values.join(", ")
+            ),
+            args,
+        });
+    }
+    sqls
+}
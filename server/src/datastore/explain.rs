@@ -0,0 +1,55 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Query plans of the statements the query engine generates, for `chisel
+//! explain`.
+//!
+//! The plans are the ones of the backend's `EXPLAIN`, which doesn't run the
+//! statement, so explaining a `DELETE` leaves the rows in place.
+
+use crate::datastore::query::TargetDatabase;
+use crate::datastore::QueryEngine;
+use anyhow::{Context, Result};
+use sqlx::{Executor, Row};
+use std::collections::HashMap;
+
+/// Whether `sql` is a statement that can be explained. The statements that
+/// bind arguments, like inserts, can't be without their values.
+pub fn is_explainable(sql: &str) -> bool {
+    let sql = sql.trim_start().to_ascii_uppercase();
+    sql.starts_with("SELECT") || sql.starts_with("DELETE")
+}
+
+/// The lines of the plan the database chooses for `sql`.
+pub async fn explain(engine: &QueryEngine, sql: &str) -> Result<Vec<String>> {
+    let mut transaction = engine.begin_transaction().await?;
+    let context = || format!("failed to explain `{}`", sql);
+    match engine.target_db() {
+        TargetDatabase::Postgres => {
+            let explain = format!("EXPLAIN {}", sql);
+            let rows = transaction
+                .fetch_all(sqlx::query(&explain))
+                .await
+                .with_context(context)?;
+            Ok(rows.iter().map(|row| row.get::<String, _>(0)).collect())
+        }
+        TargetDatabase::Sqlite => {
+            // The rows of the plan are a tree, with each row listing its
+            // parent, and are indented by their depth in it.
+            let explain = format!("EXPLAIN QUERY PLAN {}", sql);
+            let rows = transaction
+                .fetch_all(sqlx::query(&explain))
+                .await
+                .with_context(context)?;
+            let mut depths = HashMap::new();
+            let mut lines = vec![];
+            for row in rows {
+                let id = row.get::<i64, _>(0);
+                let parent = row.get::<i64, _>(1);
+                let depth = depths.get(&parent).map_or(0, |depth| depth + 1);
+                depths.insert(id, depth);
+                lines.push(format!("{}{}", "  ".repeat(depth), row.get::<String, _>(3)));
+            }
+            Ok(lines)
+        }
+    }
+}
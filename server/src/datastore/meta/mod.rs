@@ -3,19 +3,26 @@ pub mod schema;
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
 use crate::api::{ApiInfo, ApiInfoMap};
+use crate::auth_provider::ApiKey;
 use crate::datastore::DbConnection;
 use crate::policies::Policies;
 use crate::prefix_map::PrefixMap;
+use crate::proto::EndpointSchema;
+use crate::response_cache::CacheHint;
 use crate::types::{
-    DbIndex, Entity, ExistingField, ExistingObject, Field, FieldDelta, ObjectDelta, ObjectType,
-    TypeSystem,
+    Archiving, Connector, DbIndex, Entity, ExistingField, ExistingObject, Field, FieldDelta,
+    ObjectDelta, ObjectDescriptor, ObjectType, Partitioning, TypeId, TypeSystem,
 };
 use anyhow::Context;
-use sqlx::any::{Any, AnyKind};
+use prost::Message;
+use serde::de::DeserializeOwned;
+use sqlx::any::{Any, AnyKind, AnyRow};
 use sqlx::{Execute, Executor, Row, Transaction};
+use std::collections::HashMap;
 use std::fmt::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs;
 
 /// Meta service.
@@ -63,6 +70,15 @@ where
         .with_context(|| format!("Failed to execute query {}", qstr))
 }
 
+fn api_key_from_row(row: &AnyRow) -> ApiKey {
+    let created_at: i64 = row.get("created_at");
+    ApiKey {
+        name: row.get("name"),
+        user_id: row.get("user_id"),
+        created_at: created_at as u64,
+    }
+}
+
 async fn file_exists(file: &Path) -> anyhow::Result<bool> {
     match fs::metadata(file).await {
         Ok(_) => Ok(true),
@@ -112,6 +128,13 @@ async fn update_field_query(
         execute(transaction, query).await?;
     }
 
+    if let Some(description) = &delta.description {
+        let update = sqlx::query("UPDATE fields SET description = $1 WHERE field_id = $2")
+            .bind(description.clone())
+            .bind(field_id);
+        execute(transaction, update).await?;
+    }
+
     if let Some(labels) = &delta.labels {
         let flush = sqlx::query("DELETE FROM field_labels WHERE field_id = $1").bind(field_id);
         execute(transaction, flush).await?;
@@ -126,6 +149,32 @@ async fn update_field_query(
     Ok(())
 }
 
+/// The value stored as JSON in `column` of a row of `types`, like its
+/// partitioning.
+fn load_json<T: DeserializeOwned>(row: &AnyRow, column: &str) -> anyhow::Result<Option<T>> {
+    let value: Option<&str> = row.get(column);
+    value
+        .map(|value| {
+            serde_json::from_str(value)
+                .with_context(|| format!("invalid {} {}. Database corrupted?", column, value))
+        })
+        .transpose()
+}
+
+/// Makes the type of a row of `types`.
+fn load_object_type(
+    row: &AnyRow,
+    desc: &ExistingObject,
+    fields: Vec<Field>,
+    indexes: Vec<DbIndex>,
+) -> anyhow::Result<ObjectType> {
+    ObjectType::new(desc, fields, indexes)?
+        .with_partition(load_json::<Partitioning>(row, "partition")?)?
+        .with_archive(load_json::<Archiving>(row, "archive")?)?
+        .with_connector(load_json::<Connector>(row, "connector")?)
+        .map(|ty| ty.with_description(row.get("description")))
+}
+
 async fn remove_field_query(
     transaction: &mut Transaction<'_, Any>,
     field: &Field,
@@ -160,8 +209,14 @@ async fn insert_field_query(
         None => {
             let query = sqlx::query(
                 r#"
-                INSERT INTO fields (field_type, type_id, is_optional, is_unique)
-                VALUES ($1, $2, $3, $4)
+                INSERT INTO fields (
+                    field_type,
+                    type_id,
+                    is_optional,
+                    is_unique,
+                    description,
+                    column_name)
+                VALUES ($1, $2, $3, $4, $5, $6)
                 RETURNING *"#,
             );
             query
@@ -169,6 +224,8 @@ async fn insert_field_query(
                 .bind(type_id)
                 .bind(field.is_optional)
                 .bind(field.is_unique)
+                .bind(field.description.clone())
+                .bind(field.column.clone())
         }
         Some(value) => {
             let query = sqlx::query(
@@ -178,8 +235,10 @@ async fn insert_field_query(
                     type_id,
                     default_value,
                     is_optional,
-                    is_unique)
-                VALUES ($1, $2, $3, $4, $5)
+                    is_unique,
+                    description,
+                    column_name)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
                 RETURNING *"#,
             );
             query
@@ -188,6 +247,8 @@ async fn insert_field_query(
                 .bind(value.to_owned())
                 .bind(field.is_optional)
                 .bind(field.is_unique)
+                .bind(field.description.clone())
+                .bind(field.column.clone())
         }
     };
     let add_field_name = sqlx::query(
@@ -496,6 +557,10 @@ impl MetaService {
             SELECT
                 types.type_id AS type_id,
                 types.backing_table AS backing_table,
+                types.partition AS partition,
+                types.archive AS archive,
+                types.connector AS connector,
+                types.description AS description,
                 type_names.name AS type_name
             FROM types
             INNER JOIN type_names ON types.type_id = type_names.type_id"#,
@@ -510,11 +575,11 @@ impl MetaService {
             let backing_table: &str = row.get("backing_table");
             let type_name: &str = row.get("type_name");
             let desc = ExistingObject::new(type_name, backing_table, type_id)?;
-            match self.load_type_fields(&ts, type_id).await {
+            match self.load_type_fields(&ts, type_id, &desc.name()).await {
                 Ok(fields) => {
                     let indexes = self.load_type_indexes(type_id, backing_table).await?;
 
-                    let ty = ObjectType::new(&desc, fields, indexes)?;
+                    let ty = load_object_type(&row, &desc, fields, indexes)?;
                     ts.add_custom_type(Entity::Custom {
                         object: Arc::new(ty),
                         policy: None,
@@ -539,10 +604,10 @@ impl MetaService {
             let backing_table: &str = row.get("backing_table");
             let type_name: &str = row.get("type_name");
             let desc = ExistingObject::new(type_name, backing_table, type_id)?;
-            let fields = self.load_type_fields(&ts, type_id).await?;
+            let fields = self.load_type_fields(&ts, type_id, &desc.name()).await?;
             let indexes = self.load_type_indexes(type_id, backing_table).await?;
 
-            let ty = ObjectType::new(&desc, fields, indexes)?;
+            let ty = load_object_type(&row, &desc, fields, indexes)?;
             ts.add_custom_type(Entity::Custom {
                 object: Arc::new(ty),
                 policy: None,
@@ -552,7 +617,13 @@ impl MetaService {
         Ok(ts)
     }
 
-    async fn load_type_fields(&self, ts: &TypeSystem, type_id: i32) -> anyhow::Result<Vec<Field>> {
+    /// Loads the fields of type `type_id`, which is named `type_name`.
+    async fn load_type_fields(
+        &self,
+        ts: &TypeSystem,
+        type_id: i32,
+        type_name: &str,
+    ) -> anyhow::Result<Vec<Field>> {
         let query = sqlx::query(
             r#"
             SELECT
@@ -561,7 +632,9 @@ impl MetaService {
                 fields.field_type AS field_type,
                 fields.default_value AS default_value,
                 fields.is_optional AS is_optional,
-                fields.is_unique AS is_unique
+                fields.is_unique AS is_unique,
+                fields.description AS description,
+                fields.column_name AS column_name
             FROM field_names
             INNER JOIN fields
                 ON fields.type_id = $1 AND field_names.field_id = fields.field_id;"#,
@@ -579,12 +652,20 @@ impl MetaService {
             anyhow::ensure!(split.len() == 3, "Expected version and type information as part of the field name. Got {}. Database corrupted?", db_field_name);
             let field_name = split[2].to_owned();
             let version = split[0].to_owned();
-            let desc = ExistingField::new(
-                &field_name,
-                ts.lookup_type(field_type, &version)?,
-                field_id,
-                &version,
-            );
+            // A type referring to itself isn't in the type system until it is loaded.
+            let self_type_id = || TypeId::Entity {
+                name: type_name.to_owned(),
+                api_version: version.clone(),
+            };
+            let field_type_id = match ts.lookup_type(field_type, &version) {
+                Ok(ty) => ty.into(),
+                Err(_) if field_type == type_name => self_type_id(),
+                Err(_) if field_type == format!("Array<{}>", type_name) => {
+                    TypeId::Array(Box::new(self_type_id()))
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let desc = ExistingField::new(&field_name, field_type_id, field_id, &version);
 
             let field_def: Option<String> = row.get("default_value");
             let is_optional: bool = row.get("is_optional");
@@ -602,7 +683,11 @@ impl MetaService {
                 .map(|r| r.get("label_name"))
                 .collect::<Vec<String>>();
 
-            fields.push(Field::new(&desc, labels, field_def, is_optional, is_unique));
+            fields.push(
+                Field::new(&desc, labels, field_def, is_optional, is_unique)
+                    .with_description(row.get("description"))
+                    .with_column(row.get("column_name")),
+            );
         }
         Ok(fields)
     }
@@ -616,7 +701,9 @@ impl MetaService {
             r#"
             SELECT
                 index_id,
-                fields
+                fields,
+                is_search,
+                is_counted
             FROM indexes
             WHERE type_id = $1"#,
         )
@@ -628,8 +715,21 @@ impl MetaService {
             let index_id: i32 = row.get("index_id");
             // FIXME: bind fields to fields table.
             let fields: &str = row.get("fields");
-            let fields = fields.split(';').map(|s| s.to_string()).collect();
-            indexes.push(DbIndex::new(index_id, backing_table.to_owned(), fields));
+            // Counters of all the rows have no fields.
+            let fields = fields
+                .split(';')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect();
+            let search: Option<bool> = row.get("is_search");
+            let counted: Option<bool> = row.get("is_counted");
+            indexes.push(DbIndex::new(
+                index_id,
+                backing_table.to_owned(),
+                fields,
+                search.unwrap_or_default(),
+                counted.unwrap_or_default(),
+            ));
         }
         Ok(indexes)
     }
@@ -676,13 +776,37 @@ impl MetaService {
 
         Self::delete_indexes(transaction, &delta.removed_indexes).await?;
 
-        Self::insert_indexes(
-            transaction,
-            ty.meta_id
-                .context("object must have an id when it's being updated")?,
-            &delta.added_indexes,
-        )
-        .await?;
+        let type_id = ty
+            .meta_id
+            .context("object must have an id when it's being updated")?;
+        Self::insert_indexes(transaction, type_id, &delta.added_indexes).await?;
+
+        if let Some(partition) = &delta.partition {
+            let update = sqlx::query("UPDATE types SET partition = $1 WHERE type_id = $2")
+                .bind(serde_json::to_string(partition)?)
+                .bind(type_id);
+            execute(transaction, update).await?;
+        }
+        if let Some(archive) = &delta.archive {
+            let archive = archive.as_ref().map(serde_json::to_string).transpose()?;
+            let update = sqlx::query("UPDATE types SET archive = $1 WHERE type_id = $2")
+                .bind(archive)
+                .bind(type_id);
+            execute(transaction, update).await?;
+        }
+        if let Some(connector) = &delta.connector {
+            let connector = connector.as_ref().map(serde_json::to_string).transpose()?;
+            let update = sqlx::query("UPDATE types SET connector = $1 WHERE type_id = $2")
+                .bind(connector)
+                .bind(type_id);
+            execute(transaction, update).await?;
+        }
+        if let Some(description) = &delta.description {
+            let update = sqlx::query("UPDATE types SET description = $1 WHERE type_id = $2")
+                .bind(description.clone())
+                .bind(type_id);
+            execute(transaction, update).await?;
+        }
         Ok(())
     }
 
@@ -729,6 +853,310 @@ impl MetaService {
         Ok(())
     }
 
+    /// Replaces the caching hints of the entities of `version`.
+    pub async fn persist_cache_hints(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version: &str,
+        hints: &HashMap<String, CacheHint>,
+    ) -> anyhow::Result<()> {
+        self.delete_cache_hints(transaction, version).await?;
+        for (entity_name, hint) in hints {
+            let insert = sqlx::query(
+                r#"
+                INSERT INTO cache_hints (api_version, entity_name, ttl, stale_while_revalidate)
+                VALUES ($1, $2, $3, $4)"#,
+            )
+            .bind(version.to_owned())
+            .bind(entity_name.to_owned())
+            .bind(hint.ttl.as_secs() as i64)
+            .bind(hint.stale_while_revalidate.as_secs() as i64);
+            execute(transaction, insert).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn delete_cache_hints(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version: &str,
+    ) -> anyhow::Result<()> {
+        let delete =
+            sqlx::query("DELETE FROM cache_hints WHERE api_version = $1").bind(version.to_owned());
+        execute(transaction, delete).await?;
+        Ok(())
+    }
+
+    /// Replaces the request and response bodies that the endpoints of
+    /// `version` declare, by endpoint path.
+    pub async fn persist_endpoint_schemas(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version: &str,
+        schemas: &HashMap<String, EndpointSchema>,
+    ) -> anyhow::Result<()> {
+        self.delete_endpoint_schemas(transaction, version).await?;
+        for (path, schema) in schemas {
+            let insert = sqlx::query(
+                "INSERT INTO endpoint_schemas (api_version, path, schema) VALUES ($1, $2, $3)",
+            )
+            .bind(version.to_owned())
+            .bind(path.to_owned())
+            .bind(base64::encode(schema.encode_to_vec()));
+            execute(transaction, insert).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn delete_endpoint_schemas(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version: &str,
+    ) -> anyhow::Result<()> {
+        let delete = sqlx::query("DELETE FROM endpoint_schemas WHERE api_version = $1")
+            .bind(version.to_owned());
+        execute(transaction, delete).await?;
+        Ok(())
+    }
+
+    /// Loads the endpoint bodies of all versions, by version and endpoint
+    /// path.
+    pub async fn load_endpoint_schemas(
+        &self,
+    ) -> anyhow::Result<HashMap<String, HashMap<String, EndpointSchema>>> {
+        let query = sqlx::query("SELECT api_version, path, schema FROM endpoint_schemas");
+        let rows = fetch_all(&self.db.pool, query).await?;
+
+        let mut schemas: HashMap<String, HashMap<String, EndpointSchema>> = HashMap::new();
+        for row in rows {
+            let version: String = row.get("api_version");
+            let path: String = row.get("path");
+            let schema: String = row.get("schema");
+            let schema = base64::decode(schema)
+                .context("invalid endpoint schema")
+                .and_then(|bytes| {
+                    EndpointSchema::decode(bytes.as_slice()).context("invalid endpoint schema")
+                })?;
+            schemas.entry(version).or_default().insert(path, schema);
+        }
+        Ok(schemas)
+    }
+
+    /// Replaces the static files of `version`, by path.
+    pub async fn persist_static_files(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version: &str,
+        files: &HashMap<String, Vec<u8>>,
+    ) -> anyhow::Result<()> {
+        self.delete_static_files(transaction, version).await?;
+        for (path, content) in files {
+            let insert = sqlx::query(
+                "INSERT INTO static_files (api_version, path, content) VALUES ($1, $2, $3)",
+            )
+            .bind(version.to_owned())
+            .bind(path.to_owned())
+            .bind(base64::encode(content));
+            execute(transaction, insert).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn delete_static_files(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version: &str,
+    ) -> anyhow::Result<()> {
+        let delete =
+            sqlx::query("DELETE FROM static_files WHERE api_version = $1").bind(version.to_owned());
+        execute(transaction, delete).await?;
+        Ok(())
+    }
+
+    /// Loads the static files of all versions, by version and path.
+    pub async fn load_static_files(
+        &self,
+    ) -> anyhow::Result<HashMap<String, HashMap<String, Vec<u8>>>> {
+        let query = sqlx::query("SELECT api_version, path, content FROM static_files");
+        let rows = fetch_all(&self.db.pool, query).await?;
+
+        let mut files: HashMap<String, HashMap<String, Vec<u8>>> = HashMap::new();
+        for row in rows {
+            let version: String = row.get("api_version");
+            let path: String = row.get("path");
+            let content: String = row.get("content");
+            let content = base64::decode(content).context("invalid static file")?;
+            files.entry(version).or_default().insert(path, content);
+        }
+        Ok(files)
+    }
+
+    /// Drops the queued tasks of `version`.
+    pub async fn delete_tasks(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version: &str,
+    ) -> anyhow::Result<()> {
+        let delete =
+            sqlx::query("DELETE FROM tasks WHERE api_version = $1").bind(version.to_owned());
+        execute(transaction, delete).await?;
+        Ok(())
+    }
+
+    /// Loads the caching hints of all versions, by version and entity name.
+    pub async fn load_cache_hints(
+        &self,
+    ) -> anyhow::Result<HashMap<String, HashMap<String, CacheHint>>> {
+        let query = sqlx::query(
+            "SELECT api_version, entity_name, ttl, stale_while_revalidate FROM cache_hints",
+        );
+        let rows = fetch_all(&self.db.pool, query).await?;
+
+        let mut hints: HashMap<String, HashMap<String, CacheHint>> = HashMap::new();
+        for row in rows {
+            let version: String = row.get("api_version");
+            let entity_name: String = row.get("entity_name");
+            let ttl: i64 = row.get("ttl");
+            let stale_while_revalidate: i64 = row.get("stale_while_revalidate");
+            hints.entry(version).or_default().insert(
+                entity_name,
+                CacheHint {
+                    ttl: Duration::from_secs(ttl as u64),
+                    stale_while_revalidate: Duration::from_secs(stale_while_revalidate as u64),
+                },
+            );
+        }
+        Ok(hints)
+    }
+
+    /// Loads the high-water marks of `field` left by the last differential
+    /// populate of version `to` from version `from`, by entity name.
+    pub async fn load_populate_marks(
+        &self,
+        from: &str,
+        to: &str,
+        field: &str,
+    ) -> anyhow::Result<HashMap<String, serde_json::Value>> {
+        let query = sqlx::query(
+            r#"
+            SELECT entity_name, mark FROM populate_marks
+            WHERE from_version = $1 AND to_version = $2 AND field_name = $3"#,
+        )
+        .bind(from.to_owned())
+        .bind(to.to_owned())
+        .bind(field.to_owned());
+        let rows = fetch_all(&self.db.pool, query).await?;
+
+        let mut marks = HashMap::new();
+        for row in rows {
+            let entity_name: String = row.get("entity_name");
+            let mark: &str = row.get("mark");
+            marks.insert(entity_name, serde_json::from_str(mark)?);
+        }
+        Ok(marks)
+    }
+
+    /// Replaces the high-water marks of populating version `to` from `from`.
+    pub async fn persist_populate_marks(
+        &self,
+        from: &str,
+        to: &str,
+        field: &str,
+        marks: &HashMap<String, serde_json::Value>,
+    ) -> anyhow::Result<()> {
+        let mut transaction = self.begin_transaction().await?;
+        let delete =
+            sqlx::query("DELETE FROM populate_marks WHERE from_version = $1 AND to_version = $2")
+                .bind(from.to_owned())
+                .bind(to.to_owned());
+        execute(&mut transaction, delete).await?;
+        for (entity_name, mark) in marks {
+            let insert = sqlx::query(
+                r#"
+                INSERT INTO populate_marks (from_version, to_version, entity_name, field_name, mark)
+                VALUES ($1, $2, $3, $4, $5)"#,
+            )
+            .bind(from.to_owned())
+            .bind(to.to_owned())
+            .bind(entity_name.to_owned())
+            .bind(field.to_owned())
+            .bind(mark.to_string());
+            execute(&mut transaction, insert).await?;
+        }
+        Self::commit_transaction(transaction).await
+    }
+
+    /// Forgets the populate high-water marks involving `version`.
+    pub async fn delete_populate_marks(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version: &str,
+    ) -> anyhow::Result<()> {
+        let delete =
+            sqlx::query("DELETE FROM populate_marks WHERE from_version = $1 OR to_version = $1")
+                .bind(version.to_owned());
+        execute(transaction, delete).await?;
+        Ok(())
+    }
+
+    /// Stores an API key by the hash of its value. Fails if there is a key
+    /// named `key.name` already.
+    pub async fn create_api_key(&self, key: &ApiKey, key_hash: &str) -> anyhow::Result<()> {
+        let mut transaction = self.begin_transaction().await?;
+        let query = sqlx::query("SELECT name FROM api_keys WHERE name = $1").bind(key.name.clone());
+        anyhow::ensure!(
+            fetch_all(&mut transaction, query).await?.is_empty(),
+            "there is an API key named {} already",
+            key.name
+        );
+        let insert = sqlx::query(
+            "INSERT INTO api_keys (name, user_id, key_hash, created_at) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(key.name.clone())
+        .bind(key.user_id.clone())
+        .bind(key_hash.to_owned())
+        .bind(key.created_at as i64);
+        execute(&mut transaction, insert).await?;
+        Self::commit_transaction(transaction).await
+    }
+
+    /// Deletes the API key named `name`. Returns whether there was one.
+    pub async fn revoke_api_key(&self, name: &str) -> anyhow::Result<bool> {
+        let mut transaction = self.begin_transaction().await?;
+        let delete = sqlx::query("DELETE FROM api_keys WHERE name = $1").bind(name.to_owned());
+        let deleted = execute(&mut transaction, delete).await?.rows_affected();
+        Self::commit_transaction(transaction).await?;
+        Ok(deleted > 0)
+    }
+
+    /// Loads all API keys, by name.
+    pub async fn load_api_keys(&self) -> anyhow::Result<Vec<ApiKey>> {
+        let query = sqlx::query("SELECT name, user_id, created_at FROM api_keys ORDER BY name");
+        let rows = fetch_all(&self.db.pool, query).await?;
+        Ok(rows.iter().map(api_key_from_row).collect())
+    }
+
+    /// Finds the API key whose value hashes to `key_hash`.
+    pub async fn find_api_key(&self, key_hash: &str) -> anyhow::Result<Option<ApiKey>> {
+        let query =
+            sqlx::query("SELECT name, user_id, created_at FROM api_keys WHERE key_hash = $1")
+                .bind(key_hash.to_owned());
+        let rows = fetch_all(&self.db.pool, query).await?;
+        Ok(rows.first().map(api_key_from_row))
+    }
+
+    /// Loads the policy of `version`, as it was applied.
+    pub async fn load_policy_version(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        version: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let get_policy = sqlx::query("SELECT policy_str FROM policies WHERE version = $1")
+            .bind(version.to_owned());
+        let rows = fetch_all(&mut *transaction, get_policy).await?;
+        Ok(rows.first().map(|row| row.get("policy_str")))
+    }
+
     /// Loads all policies, for all versions.
     ///
     /// Useful on startup, when we have to populate our in-memory state from the meta database.
@@ -759,15 +1187,74 @@ impl MetaService {
         Ok(cnt)
     }
 
+    /// Counts the links of the many-to-many relation `field` of `ty`.
+    pub async fn count_links(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+        field: &Field,
+    ) -> anyhow::Result<i64> {
+        let query = format!(
+            "SELECT COUNT(*) as count from \"{}\"",
+            ty.join_table(field)?
+        );
+        let count = sqlx::query(&query);
+        let row = fetch_one(transaction, count).await?;
+        let cnt: i64 = row.get("count");
+        Ok(cnt)
+    }
+
+    /// Counts the rows of `ty` that have a value in any of `fields`.
+    pub async fn count_rows_with_values(
+        &self,
+        transaction: &mut Transaction<'_, Any>,
+        ty: &ObjectType,
+        fields: &[Field],
+    ) -> anyhow::Result<i64> {
+        // The links of many-to-many relations aren't counted, as they are
+        // in join tables.
+        let fields: Vec<_> = fields
+            .iter()
+            .filter(|field| !field.type_id.is_many_to_many())
+            .collect();
+        if fields.is_empty() {
+            return Ok(0);
+        }
+        let condition = fields
+            .iter()
+            .map(|field| format!("\"{}\" IS NOT NULL", field.column_name()))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        let query = format!(
+            "SELECT COUNT(*) as count from \"{}\" WHERE {}",
+            ty.backing_table(),
+            condition
+        );
+        let count = sqlx::query(&query);
+        let row = fetch_one(transaction, count).await?;
+        let cnt: i64 = row.get("count");
+        Ok(cnt)
+    }
+
     pub async fn insert_type(
         &self,
         transaction: &mut Transaction<'_, Any>,
         ty: &ObjectType,
     ) -> anyhow::Result<()> {
-        let add_type = sqlx::query("INSERT INTO types (backing_table) VALUES ($1) RETURNING *");
+        let add_type = sqlx::query(
+            "INSERT INTO types (backing_table, partition, archive, connector, description) VALUES ($1, $2, $3, $4, $5) RETURNING *",
+        );
         let add_type_name = sqlx::query("INSERT INTO type_names (type_id, name) VALUES ($1, $2)");
 
-        let add_type = add_type.bind(ty.backing_table().to_owned());
+        let partition = ty.partition().map(serde_json::to_string).transpose()?;
+        let archive = ty.archive().map(serde_json::to_string).transpose()?;
+        let connector = ty.connector().map(serde_json::to_string).transpose()?;
+        let add_type = add_type
+            .bind(ty.backing_table().to_owned())
+            .bind(partition)
+            .bind(archive)
+            .bind(connector)
+            .bind(ty.description().map(str::to_owned));
         let row = fetch_one(transaction, add_type).await?;
 
         let id: i32 = row.get("type_id");
@@ -788,9 +1275,13 @@ impl MetaService {
     ) -> anyhow::Result<()> {
         for index in indexes {
             let fields = index.fields.join(";");
-            let add_index = sqlx::query("INSERT INTO indexes (type_id, fields) VALUES ($1, $2)")
-                .bind(type_id)
-                .bind(fields);
+            let add_index = sqlx::query(
+                "INSERT INTO indexes (type_id, fields, is_search, is_counted) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(type_id)
+            .bind(fields)
+            .bind(index.search)
+            .bind(index.counted);
             execute(transaction, add_index).await?;
         }
         Ok(())
@@ -27,6 +27,10 @@ enum Types {
     TypeId,
     BackingTable,
     ApiVersion,
+    Partition,
+    Archive,
+    Connector,
+    Description,
 }
 
 #[derive(Iden)]
@@ -45,6 +49,8 @@ enum Fields {
     DefaultValue,
     IsOptional,
     IsUnique,
+    Description,
+    ColumnName,
 }
 
 #[derive(Iden)]
@@ -67,6 +73,8 @@ enum Indexes {
     IndexId,
     TypeId,
     Fields,
+    IsSearch,
+    IsCounted,
 }
 
 #[derive(Iden)]
@@ -83,7 +91,117 @@ enum Policies {
     PolicyStr,
 }
 
-pub static CURRENT_VERSION: &str = "0.7";
+#[derive(Iden)]
+enum CacheHints {
+    Table,
+    ApiVersion,
+    EntityName,
+    Ttl,
+    StaleWhileRevalidate,
+}
+
+#[derive(Iden)]
+enum EndpointSchemas {
+    Table,
+    ApiVersion,
+    Path,
+    Schema,
+}
+
+#[derive(Iden)]
+enum StaticFiles {
+    Table,
+    ApiVersion,
+    Path,
+    Content,
+}
+
+#[derive(Iden)]
+enum Outbox {
+    Table,
+    MessageId,
+    ApiVersion,
+    Target,
+    Payload,
+    Attempts,
+    LastError,
+}
+
+#[derive(Iden)]
+enum Tasks {
+    Table,
+    TaskId,
+    ApiVersion,
+    Path,
+    Payload,
+    Attempts,
+    /// When the task is due, in milliseconds since the epoch.
+    RunAt,
+    LastError,
+}
+
+#[derive(Iden)]
+enum WebhookDeliveries {
+    Table,
+    DeliveryId,
+    ApiVersion,
+    Url,
+    Payload,
+    Signature,
+    Attempts,
+    /// When the delivery is due, in milliseconds since the epoch.
+    RunAt,
+    LastError,
+}
+
+#[derive(Iden)]
+enum PopulateMarks {
+    Table,
+    FromVersion,
+    ToVersion,
+    EntityName,
+    FieldName,
+    Mark,
+}
+
+#[derive(Iden)]
+enum EventJournal {
+    Table,
+    JournalId,
+    Executor,
+    Topic,
+    EventKey,
+    EventValue,
+}
+
+#[derive(Iden)]
+enum EventOffsets {
+    Table,
+    Executor,
+    Topic,
+    NextOffset,
+}
+
+#[derive(Iden)]
+enum JobTriggers {
+    Table,
+    Path,
+    /// The minute the job was last run for, in seconds since the epoch.
+    Due,
+    Finished,
+}
+
+#[derive(Iden)]
+enum ApiKeys {
+    Table,
+    Name,
+    UserId,
+    KeyHash,
+    /// When the key was created, in seconds since the epoch.
+    CreatedAt,
+}
+
+pub static CURRENT_VERSION: &str = "0.14";
 
 // Evolves from a version and returns the new version it evolved to
 //
@@ -99,6 +217,61 @@ pub async fn evolve_from(version: &str) -> anyhow::Result<(Vec<TableAlterStateme
                 .to_owned()];
             Ok((v, "0.7".to_string()))
         }
+        "0.7" => {
+            let v = vec![Table::alter()
+                .table(Indexes::Table)
+                .add_column(ColumnDef::new(Indexes::IsSearch).boolean().default(false))
+                .to_owned()];
+            Ok((v, "0.8".to_string()))
+        }
+        "0.8" => {
+            let v = vec![Table::alter()
+                .table(Types::Table)
+                .add_column(ColumnDef::new(Types::Partition).text())
+                .to_owned()];
+            Ok((v, "0.9".to_string()))
+        }
+        "0.9" => {
+            let v = vec![Table::alter()
+                .table(Indexes::Table)
+                .add_column(ColumnDef::new(Indexes::IsCounted).boolean().default(false))
+                .to_owned()];
+            Ok((v, "0.10".to_string()))
+        }
+        "0.10" => {
+            let v = vec![Table::alter()
+                .table(Types::Table)
+                .add_column(ColumnDef::new(Types::Archive).text())
+                .to_owned()];
+            Ok((v, "0.11".to_string()))
+        }
+        "0.11" => {
+            let v = vec![Table::alter()
+                .table(Types::Table)
+                .add_column(ColumnDef::new(Types::Connector).text())
+                .to_owned()];
+            Ok((v, "0.12".to_string()))
+        }
+        "0.12" => {
+            let v = vec![
+                Table::alter()
+                    .table(Types::Table)
+                    .add_column(ColumnDef::new(Types::Description).text())
+                    .to_owned(),
+                Table::alter()
+                    .table(Fields::Table)
+                    .add_column(ColumnDef::new(Fields::Description).text())
+                    .to_owned(),
+            ];
+            Ok((v, "0.13".to_string()))
+        }
+        "0.13" => {
+            let v = vec![Table::alter()
+                .table(Fields::Table)
+                .add_column(ColumnDef::new(Fields::ColumnName).text())
+                .to_owned()];
+            Ok((v, "0.14".to_string()))
+        }
         v => anyhow::bail!("Don't know how to evolve from version {}", v),
     }
 }
@@ -130,6 +303,10 @@ pub fn tables() -> Vec<TableCreateStatement> {
         )
         .col(ColumnDef::new(Types::BackingTable).text().unique_key())
         .col(ColumnDef::new(Types::ApiVersion).text().unique_key())
+        .col(ColumnDef::new(Types::Partition).text())
+        .col(ColumnDef::new(Types::Archive).text())
+        .col(ColumnDef::new(Types::Connector).text())
+        .col(ColumnDef::new(Types::Description).text())
         .to_owned();
     let type_names = Table::create()
         .table(TypeNames::Table)
@@ -156,6 +333,8 @@ pub fn tables() -> Vec<TableCreateStatement> {
         .col(ColumnDef::new(Fields::DefaultValue).text())
         .col(ColumnDef::new(Fields::IsOptional).boolean())
         .col(ColumnDef::new(Fields::IsUnique).boolean())
+        .col(ColumnDef::new(Fields::Description).text())
+        .col(ColumnDef::new(Fields::ColumnName).text())
         .col(ColumnDef::new(TypeNames::TypeId).integer())
         .foreign_key(
             ForeignKey::create()
@@ -199,6 +378,8 @@ pub fn tables() -> Vec<TableCreateStatement> {
         )
         .col(ColumnDef::new(Indexes::TypeId).integer())
         .col(ColumnDef::new(Indexes::Fields).text())
+        .col(ColumnDef::new(Indexes::IsSearch).boolean().default(false))
+        .col(ColumnDef::new(Indexes::IsCounted).boolean().default(false))
         .foreign_key(
             ForeignKey::create()
                 .from(Indexes::Table, Indexes::TypeId)
@@ -220,6 +401,136 @@ pub fn tables() -> Vec<TableCreateStatement> {
         .col(ColumnDef::new(Policies::PolicyStr).text())
         .to_owned();
 
+    let cache_hints = Table::create()
+        .table(CacheHints::Table)
+        .if_not_exists()
+        .col(ColumnDef::new(CacheHints::ApiVersion).text())
+        .col(ColumnDef::new(CacheHints::EntityName).text())
+        .col(ColumnDef::new(CacheHints::Ttl).big_integer())
+        .col(ColumnDef::new(CacheHints::StaleWhileRevalidate).big_integer())
+        .to_owned();
+
+    let endpoint_schemas = Table::create()
+        .table(EndpointSchemas::Table)
+        .if_not_exists()
+        .col(ColumnDef::new(EndpointSchemas::ApiVersion).text())
+        .col(ColumnDef::new(EndpointSchemas::Path).text())
+        .col(ColumnDef::new(EndpointSchemas::Schema).text())
+        .to_owned();
+
+    let static_files = Table::create()
+        .table(StaticFiles::Table)
+        .if_not_exists()
+        .col(ColumnDef::new(StaticFiles::ApiVersion).text())
+        .col(ColumnDef::new(StaticFiles::Path).text())
+        .col(ColumnDef::new(StaticFiles::Content).text())
+        .to_owned();
+
+    let outbox = Table::create()
+        .table(Outbox::Table)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Outbox::MessageId)
+                .integer()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Outbox::ApiVersion).text())
+        .col(ColumnDef::new(Outbox::Target).text())
+        .col(ColumnDef::new(Outbox::Payload).text())
+        .col(ColumnDef::new(Outbox::Attempts).integer().default(0))
+        .col(ColumnDef::new(Outbox::LastError).text())
+        .to_owned();
+
+    let tasks = Table::create()
+        .table(Tasks::Table)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Tasks::TaskId)
+                .integer()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Tasks::ApiVersion).text())
+        .col(ColumnDef::new(Tasks::Path).text())
+        .col(ColumnDef::new(Tasks::Payload).text())
+        .col(ColumnDef::new(Tasks::Attempts).integer().default(0))
+        .col(ColumnDef::new(Tasks::RunAt).big_integer())
+        .col(ColumnDef::new(Tasks::LastError).text())
+        .to_owned();
+
+    let webhook_deliveries = Table::create()
+        .table(WebhookDeliveries::Table)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(WebhookDeliveries::DeliveryId)
+                .integer()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(WebhookDeliveries::ApiVersion).text())
+        .col(ColumnDef::new(WebhookDeliveries::Url).text())
+        .col(ColumnDef::new(WebhookDeliveries::Payload).text())
+        .col(ColumnDef::new(WebhookDeliveries::Signature).text())
+        .col(
+            ColumnDef::new(WebhookDeliveries::Attempts)
+                .integer()
+                .default(0),
+        )
+        .col(ColumnDef::new(WebhookDeliveries::RunAt).big_integer())
+        .col(ColumnDef::new(WebhookDeliveries::LastError).text())
+        .to_owned();
+
+    let populate_marks = Table::create()
+        .table(PopulateMarks::Table)
+        .if_not_exists()
+        .col(ColumnDef::new(PopulateMarks::FromVersion).text())
+        .col(ColumnDef::new(PopulateMarks::ToVersion).text())
+        .col(ColumnDef::new(PopulateMarks::EntityName).text())
+        .col(ColumnDef::new(PopulateMarks::FieldName).text())
+        .col(ColumnDef::new(PopulateMarks::Mark).text())
+        .to_owned();
+
+    let event_journal = Table::create()
+        .table(EventJournal::Table)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(EventJournal::JournalId)
+                .integer()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(EventJournal::Executor).integer())
+        .col(ColumnDef::new(EventJournal::Topic).text())
+        .col(ColumnDef::new(EventJournal::EventKey).text())
+        .col(ColumnDef::new(EventJournal::EventValue).text())
+        .to_owned();
+
+    let event_offsets = Table::create()
+        .table(EventOffsets::Table)
+        .if_not_exists()
+        .col(ColumnDef::new(EventOffsets::Executor).integer())
+        .col(ColumnDef::new(EventOffsets::Topic).text())
+        .col(ColumnDef::new(EventOffsets::NextOffset).big_integer())
+        .to_owned();
+
+    let job_triggers = Table::create()
+        .table(JobTriggers::Table)
+        .if_not_exists()
+        .col(ColumnDef::new(JobTriggers::Path).text().primary_key())
+        .col(ColumnDef::new(JobTriggers::Due).big_integer())
+        .col(ColumnDef::new(JobTriggers::Finished).boolean())
+        .to_owned();
+
+    let api_keys = Table::create()
+        .table(ApiKeys::Table)
+        .if_not_exists()
+        .col(ColumnDef::new(ApiKeys::Name).text().primary_key())
+        .col(ColumnDef::new(ApiKeys::UserId).text())
+        .col(ColumnDef::new(ApiKeys::KeyHash).text().unique_key())
+        .col(ColumnDef::new(ApiKeys::CreatedAt).big_integer())
+        .to_owned();
+
     vec![
         version,
         api_info,
@@ -231,5 +542,16 @@ pub fn tables() -> Vec<TableCreateStatement> {
         indexes,
         sources,
         policies,
+        cache_hints,
+        endpoint_schemas,
+        static_files,
+        outbox,
+        tasks,
+        webhook_deliveries,
+        populate_marks,
+        event_journal,
+        event_offsets,
+        job_triggers,
+        api_keys,
     ]
 }
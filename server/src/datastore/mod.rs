@@ -55,9 +55,14 @@
 pub mod crud;
 mod dbconn;
 pub mod engine;
+pub mod explain;
 pub mod expr;
 pub mod meta;
+pub mod policy_impact;
+pub mod prefetch;
 pub mod query;
+pub mod stats;
+pub mod verify;
 
 pub use dbconn::DbConnection;
 pub use engine::QueryEngine;
@@ -0,0 +1,128 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! How a change of the policies of a version would affect the data of its
+//! entities, to assess a change before applying it.
+//!
+//! The fields whose labels would get other policies are compared on a
+//! sample of the rows of their entity: transforms and omissions affect the
+//! rows with a value in the field, while login and tenant matches filter
+//! out rows for every request but those of their owner.
+
+use crate::datastore::QueryEngine;
+use crate::policies::{Kind, VersionPolicy};
+use crate::types::Entity;
+use anyhow::{Context, Result};
+use sqlx::{Executor, Row};
+
+pub struct FieldImpact {
+    pub field_name: String,
+    /// The policies of the field, as currently applied.
+    pub before: String,
+    /// The policies of the field, as proposed.
+    pub after: String,
+    /// Sampled rows the change of policies affects.
+    pub affected_rows: u64,
+}
+
+pub struct EntityImpact {
+    pub total_rows: u64,
+    pub sampled_rows: u64,
+    /// Sampled rows that would newly be filtered out of the results of
+    /// other users or tenants.
+    pub newly_filtered_rows: u64,
+    pub fields: Vec<FieldImpact>,
+}
+
+/// Describes the policies that `policy` applies to a field with `labels`,
+/// or "none", and returns whether any of them filters rows.
+fn describe(policy: Option<&VersionPolicy>, labels: &[String]) -> (String, bool) {
+    let mut descriptions = vec![];
+    let mut filters = false;
+    for label in labels {
+        let p = match policy.and_then(|policy| policy.labels.get(label)) {
+            Some(p) => p,
+            None => continue,
+        };
+        let kind = match &p.kind {
+            Kind::Transform(transform) => transform.to_string(),
+            Kind::Omit => "omit".to_owned(),
+            Kind::MatchLogin => "match_login".to_owned(),
+            Kind::MatchTenant => "match_tenant".to_owned(),
+        };
+        filters |= matches!(p.kind, Kind::MatchLogin | Kind::MatchTenant);
+        let mut description = format!("{} ({})", kind, label);
+        if p.except_uri.as_str() != "^$" {
+            description += &format!(" except for {}", p.except_uri);
+        }
+        descriptions.push(description);
+    }
+    descriptions.sort();
+    match descriptions.is_empty() {
+        true => ("none".to_owned(), filters),
+        false => (descriptions.join(", "), filters),
+    }
+}
+
+/// Computes how replacing policies `current` with `proposed` would affect
+/// the data of `ty`, sampling at most `sample` of its rows. Returns None if
+/// it wouldn't.
+pub async fn entity_policy_impact(
+    engine: &QueryEngine,
+    ty: &Entity,
+    current: Option<&VersionPolicy>,
+    proposed: &VersionPolicy,
+    sample: u64,
+) -> Result<Option<EntityImpact>> {
+    let mut changed = vec![];
+    let mut newly_filters = false;
+    for field in ty.column_fields() {
+        let (before, filtered_before) = describe(current, &field.labels);
+        let (after, filtered_after) = describe(Some(proposed), &field.labels);
+        if before != after {
+            newly_filters |= filtered_after && !filtered_before;
+            changed.push((field, before, after, filtered_after));
+        }
+    }
+    if changed.is_empty() {
+        return Ok(None);
+    }
+
+    let table = ty.backing_table();
+    let counts: Vec<_> = changed
+        .iter()
+        .map(|(field, ..)| format!("COUNT(\"{}\")", field.column_name()))
+        .collect();
+    let sql = format!(
+        "SELECT (SELECT COUNT(*) FROM \"{table}\"), COUNT(*), {} FROM (SELECT * FROM \"{table}\" LIMIT {sample}) AS sample",
+        counts.join(", ")
+    );
+    let mut transaction = engine.begin_transaction().await?;
+    let row = transaction
+        .fetch_one(sqlx::query(&sql))
+        .await
+        .with_context(|| format!("failed to sample rows of entity {}", ty.name()))?;
+    QueryEngine::commit_transaction(transaction).await?;
+
+    let total_rows = row.get::<i64, _>(0) as u64;
+    let sampled_rows = row.get::<i64, _>(1) as u64;
+    let fields = changed
+        .into_iter()
+        .enumerate()
+        .map(|(i, (field, before, after, filters))| FieldImpact {
+            field_name: field.name.clone(),
+            before,
+            after,
+            // Filters apply to whole rows, even those without a value.
+            affected_rows: match filters {
+                true => sampled_rows,
+                false => row.get::<i64, _>(i + 2) as u64,
+            },
+        })
+        .collect();
+    Ok(Some(EntityImpact {
+        total_rows,
+        sampled_rows,
+        newly_filtered_rows: if newly_filters { sampled_rows } else { 0 },
+        fields,
+    }))
+}
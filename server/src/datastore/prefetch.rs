@@ -0,0 +1,132 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Batching and prefetching of the rows of a query.
+//!
+//! Endpoints read the rows of a query in batches, so that they pay for an op
+//! call per batch rather than per row. The first batch is small, for the
+//! first rows to arrive early, and batches double each time the endpoint
+//! takes a full one, up to `--query-batch-size` rows.
+//!
+//! Rows are prefetched into a buffer of two batches: whenever the stream is
+//! polled, it converts all the rows the database has already sent, not just
+//! the ones of the batch it returns, so that the next batch is mostly ready
+//! by the time JavaScript is done with the current one.
+
+use crate::JsonObject;
+use anyhow::Result;
+use deno_core::futures;
+use futures::stream::Stream;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+
+/// Rows in the first batch of a query.
+const INITIAL_BATCH: usize = 16;
+
+static MAX_BATCH: AtomicUsize = AtomicUsize::new(256);
+
+/// Makes batches grow up to `max_batch` rows.
+pub fn configure(max_batch: usize) -> Result<()> {
+    anyhow::ensure!(max_batch > 0, "the query batch size must be at least 1");
+    MAX_BATCH.store(max_batch, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Groups the rows of `stream` into batches, prefetching the next one.
+pub fn batched<S>(stream: S) -> Batched<S> {
+    let max_batch = MAX_BATCH.load(Ordering::Relaxed);
+    Batched {
+        inner: stream,
+        buffer: VecDeque::new(),
+        batch: INITIAL_BATCH.min(max_batch),
+        max_batch,
+        done: false,
+    }
+}
+
+pub struct Batched<S> {
+    inner: S,
+    buffer: VecDeque<Result<JsonObject>>,
+    /// The size of the next batch.
+    batch: usize,
+    max_batch: usize,
+    /// Whether `inner` has ended.
+    done: bool,
+}
+
+impl<S: Stream<Item = Result<JsonObject>> + Unpin> Stream for Batched<S> {
+    /// A batch of rows, which is never empty. An error is returned in place
+    /// of the batch that would include it, once the rows before it are.
+    type Item = Result<Vec<JsonObject>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        while !this.done && this.buffer.len() < 2 * this.batch {
+            match Pin::new(&mut this.inner).poll_next(cx) {
+                Poll::Ready(Some(row)) => this.buffer.push_back(row),
+                Poll::Ready(None) => this.done = true,
+                Poll::Pending => break,
+            }
+        }
+        let mut rows = vec![];
+        while rows.len() < this.batch {
+            match this.buffer.front() {
+                Some(Ok(_)) => {}
+                Some(Err(_)) if rows.is_empty() => {
+                    let err = this.buffer.pop_front().unwrap().unwrap_err();
+                    return Poll::Ready(Some(Err(err)));
+                }
+                _ => break,
+            }
+            rows.push(this.buffer.pop_front().unwrap().unwrap());
+        }
+        if rows.is_empty() {
+            return if this.done {
+                Poll::Ready(None)
+            } else {
+                Poll::Pending
+            };
+        }
+        if rows.len() == this.batch {
+            this.batch = (this.batch * 2).min(this.max_batch);
+        }
+        Poll::Ready(Some(Ok(rows)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use serde_json::json;
+
+    fn row(i: usize) -> Result<JsonObject> {
+        Ok(json!({ "i": i }).as_object().unwrap().clone())
+    }
+
+    #[tokio::test]
+    async fn batches_grow_up_to_the_limit() {
+        let stream = futures::stream::iter((0..100).map(row));
+        let mut stream = Batched {
+            max_batch: 32,
+            ..batched(stream)
+        };
+        let mut sizes = vec![];
+        while let Some(rows) = stream.next().await {
+            sizes.push(rows.unwrap().len());
+        }
+        assert_eq!(sizes, [16, 32, 32, 20]);
+    }
+
+    #[tokio::test]
+    async fn rows_before_an_error_come_first() {
+        let rows = (0..3)
+            .map(row)
+            .chain(std::iter::once(Err(anyhow::anyhow!("broken"))));
+        let mut stream = batched(futures::stream::iter(rows));
+        assert_eq!(stream.next().await.unwrap().unwrap().len(), 3);
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+    }
+}
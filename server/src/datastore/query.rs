@@ -2,8 +2,8 @@
 
 use crate::auth::AUTH_USER_NAME;
 use crate::datastore::expr::{BinaryExpr, Expr, PropertyAccess, Value as ExprValue};
-use crate::policies::{FieldPolicies, Policies};
-use crate::types::{Entity, Field, ObjectType, Type, TypeId, TypeSystem};
+use crate::policies::{FieldPolicies, Policies, Transform};
+use crate::types::{Entity, Field, ObjectType, Type, TypeId, TypeSystem, TYPE_KEY};
 
 use anyhow::{anyhow, Context, Result};
 use enum_as_inner::EnumAsInner;
@@ -38,6 +38,8 @@ pub struct RequestContext<'a> {
     pub api_version: String,
     /// Id of user making the request.
     pub user_id: Option<String>,
+    /// Tenant of the request, in multi-tenant versions.
+    pub tenant: Option<String>,
     /// Current URL path from which this request originated.
     pub path: String,
     /// Current HTTP headers.
@@ -48,7 +50,13 @@ impl RequestContext<'_> {
     /// Calculates field policies for the request being processed.
     fn make_field_policies(&self, ty: &ObjectType) -> FieldPolicies {
         self.policies
-            .make_field_policies(&self.user_id, &self.path, ty)
+            .make_field_policies(&self.user_id, &self.tenant, &self.path, ty)
+    }
+
+    /// The row policies of `ty` for the request, as filters of `object`.
+    fn make_row_filters(&self, ty: &ObjectType, object: &Expr) -> Vec<Expr> {
+        self.policies
+            .row_filters(self.user_id.as_deref(), ty, object)
     }
 }
 
@@ -71,7 +79,7 @@ pub enum QueryField {
         /// the database.
         column_idx: usize,
         /// Policy transformation to be applied on the resulting JSON value.
-        transform: Option<fn(JsonValue) -> JsonValue>,
+        transform: Option<Transform>,
         /// Do not include field in return json
         keep_or_omit: KeepOrOmitField,
     },
@@ -80,10 +88,52 @@ pub enum QueryField {
         name: String,
         is_optional: bool,
         /// Policy transformation to be applied on the resulting JSON value.
-        transform: Option<fn(JsonValue) -> JsonValue>,
+        transform: Option<Transform>,
         /// Do not include field in return json
         keep_or_omit: KeepOrOmitField,
     },
+    /// The related objects of a many-to-many relation, which are aggregated
+    /// into a JSON array by the database.
+    EntityList {
+        /// Name of the original Type field
+        name: String,
+        /// Index of the column containing the JSON array of the objects.
+        column_idx: usize,
+        /// Fields of the related objects.
+        fields: Vec<ListedField>,
+        /// Policy transformation to be applied on the resulting JSON value.
+        transform: Option<Transform>,
+        /// Do not include field in return json
+        keep_or_omit: KeepOrOmitField,
+    },
+    /// The object of a polymorphic relation, which the database builds into
+    /// a JSON object out of the row of the entity it is of.
+    EntityUnion {
+        /// Name of the original Type field
+        name: String,
+        /// Index of the column containing the JSON object.
+        column_idx: usize,
+        /// The entities the object may be of, with the fields of their
+        /// objects.
+        members: Vec<(String, Vec<ListedField>)>,
+        /// Policy transformation to be applied on the resulting JSON value.
+        transform: Option<Transform>,
+        /// Do not include field in return json
+        keep_or_omit: KeepOrOmitField,
+    },
+}
+
+/// A field of the objects of a many-to-many relation or of a polymorphic
+/// relation.
+#[derive(Debug, Clone)]
+pub struct ListedField {
+    pub name: String,
+    pub type_id: TypeId,
+    pub is_optional: bool,
+    /// Policy transformation to be applied on the resulting JSON value.
+    pub transform: Option<Transform>,
+    /// Do not include field in return json
+    pub keep_or_omit: KeepOrOmitField,
 }
 
 /// `Query` is a structure that represents an executable query.
@@ -167,16 +217,108 @@ pub enum QueryOp {
     Skip { count: u64 },
     /// Lexicographically sorts elements using `SortKey`s.
     SortBy(SortBy),
+    /// Keeps the elements whose searchable fields contain all the words of
+    /// `terms`.
+    Search { terms: String },
+    /// Also reads the rows moved to the archive table of an entity declared
+    /// with `@archive`.
+    WithArchived,
+}
+
+/// An aggregation of the rows of a query, which the database computes
+/// instead of returning the rows themselves.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Aggregation {
+    /// Counts the rows.
+    #[serde(default)]
+    pub count: bool,
+    /// The number field to sum up.
+    pub sum: Option<String>,
+    /// The number field to average.
+    pub avg: Option<String>,
+    /// The field by whose values the rows are grouped.
+    #[serde(rename = "groupBy")]
+    pub group_by: Option<String>,
+}
+
+/// The SQL computing an `Aggregation` over the rows of a `QueryPlan`.
+#[derive(Debug, Clone)]
+pub struct AggregateQuery {
+    /// SQL query text. Its columns are the grouping field, if any, followed
+    /// by the count, the sum and the average, for those that are asked for.
+    pub raw_sql: String,
+    /// The aggregation that is computed.
+    pub aggregation: Aggregation,
+    /// Type of the grouping field.
+    pub group_type: Option<TypeId>,
 }
 
 struct Column {
-    /// Column name which is coincidentally also the name of the Entity field
-    /// this column corresponds to.
+    /// Name of the Entity field this column corresponds to. The column of the
+    /// table is that of the field, which is usually named the same.
     name: String,
     /// Name of the table storing this column.
     table_name: String,
     /// Entity field corresponding to this column.
     field: Field,
+    /// The related objects to aggregate into this column, if the field is a
+    /// many-to-many relation.
+    list: Option<ListColumn>,
+    /// The entities of the related object to select into this column, if
+    /// the field is a polymorphic relation.
+    union: Vec<UnionMember>,
+}
+
+/// The related objects of a many-to-many relation, which are selected from
+/// the join table in a subquery.
+struct ListColumn {
+    /// Name of the join table.
+    join_table: String,
+    /// Backing table of the related objects.
+    table: String,
+    /// Alias of the backing table within the subquery.
+    alias: String,
+    /// Fields of the related objects to select.
+    fields: Vec<Field>,
+    /// Condition the related objects must satisfy to be selected, from the
+    /// policies of their entity.
+    condition: Option<String>,
+}
+
+/// One of the entities of a polymorphic relation, whose object is selected
+/// from its backing table in a subquery when the discriminator column names
+/// the entity.
+struct UnionMember {
+    /// Name of the entity.
+    name: String,
+    /// Backing table of the entity.
+    table: String,
+    /// Alias of the backing table within the subquery.
+    alias: String,
+    /// Fields of the object to select.
+    fields: Vec<Field>,
+    /// Condition the object must satisfy to be selected, from the policies
+    /// of the entity.
+    condition: Option<String>,
+}
+
+/// The JSON properties `'name', value` of `fields` of the rows of the table
+/// aliased `alias`, as the arguments of `json_object()`.
+fn json_properties(fields: &[Field], alias: &str) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            let column = Column {
+                name: field.name.to_owned(),
+                table_name: alias.to_owned(),
+                field: field.clone(),
+                list: None,
+                union: vec![],
+            };
+            format!("'{}', {}", field.name, column.value_sql())
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 impl Column {
@@ -187,6 +329,107 @@ impl Column {
             table_name: self.table_name.to_owned(),
         }
     }
+
+    /// SQL expression of the value of the column, which is the default value
+    /// of its field if NULL.
+    fn value_sql(&self) -> String {
+        let value = match self.field.default_value() {
+            Some(dfl) => {
+                let sql_default = match self.field.type_id {
+                    TypeId::String | TypeId::DateTime => format!("'{}'", dfl),
+                    TypeId::Json | TypeId::Enum(_) => escape_string(dfl),
+                    _ => dfl.to_string(),
+                };
+                format!(
+                    "coalesce(\"{}\".\"{}\",{})",
+                    self.table_name,
+                    self.field.column_name(),
+                    sql_default
+                )
+            }
+            None => format!("\"{}\".\"{}\"", self.table_name, self.field.column_name()),
+        };
+        match self.field.type_id {
+            // JSONB values on Postgres are read as text, like on SQLite.
+            TypeId::Json => format!("CAST({} AS TEXT)", value),
+            _ => value,
+        }
+    }
+
+    /// SQL expression selecting the column.
+    fn select_sql(&self, target: &TargetDatabase) -> String {
+        if !self.union.is_empty() {
+            return self.union_sql(target);
+        }
+        let list = match &self.list {
+            Some(list) => list,
+            None => return self.value_sql(),
+        };
+        let properties = json_properties(&list.fields, &list.alias);
+        let mut from = format!(
+            "FROM \"{jt}\" JOIN \"{}\" AS \"{alias}\" ON \"{alias}\".\"id\" = \"{jt}\".\"item\" WHERE \"{jt}\".\"owner\" = \"{}\".\"id\"",
+            list.table,
+            self.table_name,
+            jt = list.join_table,
+            alias = list.alias,
+        );
+        if let Some(condition) = &list.condition {
+            write!(from, " AND {}", condition).unwrap();
+        }
+        match target {
+            TargetDatabase::Sqlite => format!(
+                "(SELECT json_group_array(json(item)) FROM (SELECT json_object({}) AS item {} ORDER BY \"{}\".\"position\"))",
+                properties, from, list.join_table
+            ),
+            TargetDatabase::Postgres => format!(
+                "(SELECT coalesce(json_agg(json_build_object({}) ORDER BY \"{}\".\"position\"), '[]'::json)::text {})",
+                properties, list.join_table, from
+            ),
+        }
+    }
+
+    /// SQL expression selecting the object of a polymorphic relation as a
+    /// JSON object, from the backing table of the entity that the
+    /// discriminator column names.
+    fn union_sql(&self, target: &TargetDatabase) -> String {
+        let cases = self
+            .union
+            .iter()
+            .map(|member| {
+                let properties = format!(
+                    "'{}', '{}', {}",
+                    TYPE_KEY,
+                    member.name,
+                    json_properties(&member.fields, &member.alias)
+                );
+                let object = match target {
+                    TargetDatabase::Sqlite => format!("json_object({})", properties),
+                    TargetDatabase::Postgres => format!("json_build_object({})::text", properties),
+                };
+                let condition = match &member.condition {
+                    Some(condition) => format!(" AND {}", condition),
+                    None => "".to_owned(),
+                };
+                format!(
+                    "WHEN '{}' THEN (SELECT {} FROM \"{}\" AS \"{alias}\" WHERE \"{alias}\".\"id\" = \"{}\".\"{}\"{})",
+                    member.name,
+                    object,
+                    member.table,
+                    self.table_name,
+                    self.field.column_name(),
+                    condition,
+                    alias = member.alias,
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        format!(
+            "CASE \"{}\".\"{}\" {} END",
+            self.table_name,
+            self.field.discriminator_column().unwrap_or_default(),
+            cases
+        )
+    }
 }
 
 /// ColumnAlias is used to uniquely identify a `Column` that is to be retrieved
@@ -232,6 +475,28 @@ pub struct QueryPlan {
     join_counter: usize,
     /// Operators used to mutate the result set.
     operators: Vec<QueryOp>,
+    /// Whether the archived rows of the base type are read too.
+    with_archived: bool,
+    /// The relation the rows of the base type are reached through, if they
+    /// are reached from the objects of another plan.
+    traversal: Option<Box<Traversal>>,
+}
+
+/// A relation traversed from the objects of another plan, which restricts
+/// the base type of a plan to the objects the relation reaches.
+struct Traversal {
+    /// Plan of the objects the relation is traversed from.
+    origin: QueryPlan,
+    relation: Relation,
+}
+
+enum Relation {
+    /// The objects whose self-referential field, stored in `column`, refers
+    /// to an origin object, i.e. their children in a tree.
+    Children { column: String },
+    /// The objects that the origin objects relate to in this join table of a
+    /// many-to-many relation.
+    Related { join_table: String },
 }
 
 impl QueryPlan {
@@ -247,6 +512,8 @@ impl QueryPlan {
             allowed_fields: None,
             join_counter: 0,
             operators: vec![],
+            with_archived: false,
+            traversal: None,
         }
     }
 
@@ -254,24 +521,68 @@ impl QueryPlan {
         &self.entity.ty
     }
 
+    /// The entities the plan reads, the base type or the joined ones, whose
+    /// rows are fetched by a connector.
+    pub fn connected_entities(&self) -> Vec<Entity> {
+        fn visit(entity: &QueriedEntity, connected: &mut Vec<Entity>) {
+            if entity.ty.connector().is_some()
+                && !connected.iter().any(|ty| ty.name() == entity.ty.name())
+            {
+                connected.push(entity.ty.clone());
+            }
+            for join in entity.joins.values() {
+                visit(&join.entity, connected);
+            }
+        }
+        let mut connected = vec![];
+        visit(&self.entity, &mut connected);
+        connected
+    }
+
     /// Constructs a query builder ready to build an expression querying all fields of a
     /// given type `ty`. This is done in a shallow manner. Columns representing foreign
     /// key are returned as string, not as the related Entity.
     pub fn from_type(ty: &Entity) -> Self {
         let mut builder = Self::new(ty.clone());
-        for field in ty.all_fields() {
+        for field in ty.column_fields() {
             let mut field = field.clone();
+            let discriminator = field.discriminator_column();
             field.type_id = match field.type_id {
-                TypeId::Entity { .. } => TypeId::String, // This is actually a foreign key.
+                // These are actually foreign keys.
+                TypeId::Entity { .. } | TypeId::Union { .. } => TypeId::String,
                 ty => ty,
             };
-            let field =
+            let query_field =
                 builder.make_scalar_field(&field, ty.backing_table(), None, &KeepOrOmitField::Keep);
-            builder.entity.fields.push(field)
+            builder.entity.fields.push(query_field);
+            // The entity the foreign key of a polymorphic relation refers to.
+            if let Some(discriminator) = discriminator {
+                field.name = discriminator;
+                field.column = None;
+                let query_field = builder.make_scalar_field(
+                    &field,
+                    ty.backing_table(),
+                    None,
+                    &KeepOrOmitField::Keep,
+                );
+                builder.entity.fields.push(query_field);
+            }
         }
         builder
     }
 
+    /// Restricts the plan to the rows satisfying `expression`.
+    pub fn filter(mut self, expression: Expr) -> Self {
+        self.operators.push(QueryOp::Filter { expression });
+        self
+    }
+
+    /// Appends `operators` to the ones applied to the result set.
+    pub fn extend(mut self, operators: Vec<QueryOp>) -> Self {
+        self.extend_operators(operators);
+        self
+    }
+
     fn from_entity_name(c: &RequestContext, entity_name: &str) -> Result<Self> {
         let ty = c
             .ts
@@ -298,15 +609,80 @@ impl QueryPlan {
     /// additional helper data like `policies`, `api_version`,
     /// `userid` and `path` (url path used for policy evaluation).
     pub fn from_op_chain(context: &RequestContext, op_chain: QueryOpChain) -> Result<Self> {
-        let (entity_name, operators) = convert_ops(op_chain)?;
-        let mut builder = Self::from_entity_name(context, &entity_name)?;
+        use QueryOpChain as Op;
+        let (source, operators) = convert_ops(op_chain)?;
+        let mut builder = match source {
+            Op::Children { field, inner } => {
+                let origin = Self::from_op_chain(context, *inner)?;
+                let ty = origin.base_type().clone();
+                let is_self_reference = ty.get_field(&field).map_or(
+                    false,
+                    |f| matches!(&f.type_id, TypeId::Entity { name, .. } if name == ty.name()),
+                );
+                anyhow::ensure!(
+                    is_self_reference,
+                    "field '{}' of entity '{}' doesn't refer to '{}' itself",
+                    field,
+                    ty.name(),
+                    ty.name()
+                );
+                let column = ty.column(&field).to_owned();
+                let mut builder = Self::from_entity_name(context, ty.name())?;
+                builder.traversal = Some(Box::new(Traversal {
+                    origin,
+                    relation: Relation::Children { column },
+                }));
+                builder
+            }
+            Op::Related {
+                field,
+                entity_name,
+                inner,
+            } => {
+                let origin = Self::from_op_chain(context, *inner)?;
+                let ty = origin.base_type();
+                let relation = match ty.get_field(&field) {
+                    Some(f) if f.type_id.is_many_to_many() => f,
+                    _ => anyhow::bail!(
+                        "field '{}' of entity '{}' is not a many-to-many relation",
+                        field,
+                        ty.name()
+                    ),
+                };
+                let related_ty = match &relation.type_id {
+                    TypeId::Array(item_ty) => item_ty.name(),
+                    _ => unreachable!("many-to-many relations are arrays"),
+                };
+                anyhow::ensure!(
+                    related_ty.name() == entity_name,
+                    "field '{}' of entity '{}' relates to '{}', not '{}'",
+                    field,
+                    ty.name(),
+                    related_ty,
+                    entity_name
+                );
+                let join_table = ty.join_table(relation)?;
+                let mut builder = Self::from_entity_name(context, &entity_name)?;
+                builder.traversal = Some(Box::new(Traversal {
+                    origin,
+                    relation: Relation::Related { join_table },
+                }));
+                builder
+            }
+            // Otherwise, the chain starts from its base entity.
+            source => Self::from_entity_name(context, source.entity_name())?,
+        };
 
         builder.extend_operators(operators);
         Ok(builder)
     }
 
     fn extend_operators(&mut self, ops: Vec<QueryOp>) {
-        let ops = self.process_projections(ops);
+        let mut ops = self.process_projections(ops);
+        if ops.iter().any(|op| matches!(op, QueryOp::WithArchived)) {
+            self.with_archived = true;
+        }
+        ops.retain(|op| !matches!(op, QueryOp::WithArchived));
         self.operators.extend(ops);
     }
 
@@ -326,7 +702,7 @@ impl QueryPlan {
         &mut self,
         field: &Field,
         table_name: &str,
-        transform: Option<fn(JsonValue) -> JsonValue>,
+        transform: Option<Transform>,
         keep_or_omit: &KeepOrOmitField,
     ) -> QueryField {
         let column_idx = self.columns.len();
@@ -342,10 +718,94 @@ impl QueryPlan {
             name: field.name.to_owned(),
             table_name: table_name.to_owned(),
             field: field.clone(),
+            list: None,
+            union: vec![],
         });
         select_field
     }
 
+    /// Adds the column aggregating the objects of type `item_ty` of
+    /// many-to-many relation `field` of `ty`, returning its index with the
+    /// fields of the objects. The relations of the objects themselves aren't
+    /// loaded.
+    fn make_list_column(
+        &mut self,
+        context: &RequestContext,
+        ty: &Entity,
+        field: &Field,
+        item_ty: &Entity,
+        table_name: &str,
+    ) -> Result<(usize, Vec<ListedField>)> {
+        let (fields, listed_fields) = listed_fields(context, item_ty)?;
+        let alias = truncate_identifier(&format!("LIST{}_{}", self.join_counter, item_ty.name()))
+            .to_owned();
+        self.join_counter += 1;
+        let filters = login_filters(
+            context,
+            item_ty,
+            Expr::Parameter { position: 0 },
+            &mut vec![],
+        )?;
+        let condition = self.subquery_condition(context, item_ty, &alias, filters)?;
+
+        let column_idx = self.columns.len();
+        self.columns.push(Column {
+            name: field.name.to_owned(),
+            table_name: table_name.to_owned(),
+            field: field.clone(),
+            list: Some(ListColumn {
+                join_table: ty.join_table(field)?,
+                table: item_ty.backing_table().to_owned(),
+                alias,
+                fields,
+                condition,
+            }),
+            union: vec![],
+        });
+        Ok((column_idx, listed_fields))
+    }
+
+    /// Adds the column selecting the object of polymorphic relation `field`,
+    /// which may be of the entities `entities`, returning its index with the
+    /// fields of the objects of each entity. Like for many-to-many relations,
+    /// the relations of the object itself aren't loaded.
+    fn make_union_column(
+        &mut self,
+        context: &RequestContext,
+        field: &Field,
+        entities: &[Entity],
+        table_name: &str,
+    ) -> Result<(usize, Vec<(String, Vec<ListedField>)>)> {
+        let mut members = vec![];
+        let mut listed_members = vec![];
+        for ty in entities {
+            let (fields, listed_fields) = listed_fields(context, ty)?;
+            let alias = truncate_identifier(&format!("UNION{}_{}", self.join_counter, ty.name()))
+                .to_owned();
+            self.join_counter += 1;
+            let filters = login_filters(context, ty, Expr::Parameter { position: 0 }, &mut vec![])?;
+            let condition = self.subquery_condition(context, ty, &alias, filters)?;
+            members.push(UnionMember {
+                name: ty.name().to_owned(),
+                table: ty.backing_table().to_owned(),
+                alias,
+                fields,
+                condition,
+            });
+            listed_members.push((ty.name().to_owned(), listed_fields));
+        }
+
+        let column_idx = self.columns.len();
+        self.columns.push(Column {
+            name: field.name.to_owned(),
+            table_name: table_name.to_owned(),
+            field: field.clone(),
+            list: None,
+            union: members,
+        });
+        Ok((column_idx, listed_members))
+    }
+
     /// Prepares the retrieval of Entity of type `ty` from the database and
     /// ensures login restrictions are respected.
     fn load_entity(
@@ -353,21 +813,30 @@ impl QueryPlan {
         context: &RequestContext,
         ty: &Entity,
     ) -> anyhow::Result<QueriedEntity> {
-        self.add_login_filters_recursive(context, ty, Expr::Parameter { position: 0 })?;
-        self.load_entity_recursive(context, ty, ty.backing_table())
+        let filters = login_filters(context, ty, Expr::Parameter { position: 0 }, &mut vec![])?;
+        for expression in filters {
+            self.operators.push(QueryOp::Filter { expression });
+        }
+        self.load_entity_recursive(context, ty, ty.backing_table(), &mut vec![])
     }
 
     /// Loads QueriedEntity for a given type `ty` to be retrieved from the
     /// database. For fields that represent a nested Entity a join is
     /// generated and we attempt to retrieve them recursively as well.
+    ///
+    /// `ancestors` holds the entities the entity is nested in. An entity that
+    /// refers to itself, like the parent of a node in a tree, is loaded once
+    /// more, without the fields that would repeat it again.
     fn load_entity_recursive(
         &mut self,
         context: &RequestContext,
         ty: &Entity,
         current_table: &str,
+        ancestors: &mut Vec<String>,
     ) -> anyhow::Result<QueriedEntity> {
         let field_policies = context.make_field_policies(ty);
 
+        ancestors.push(ty.name().to_owned());
         let mut fields = vec![];
         let mut joins = HashMap::default();
         for field in ty.all_fields() {
@@ -377,25 +846,35 @@ impl QueryPlan {
                 _ => KeepOrOmitField::Keep,
             };
 
-            let ty = context.ts.get(&field.type_id)?;
+            let field_ty = context.ts.get(&field.type_id)?;
+            if let Type::Entity(nested_ty) = &field_ty {
+                if is_repeated(ancestors, nested_ty) {
+                    continue;
+                }
+            }
 
-            let query_field = if let Type::Entity(nested_ty) = &ty {
+            let query_field = if let Type::Entity(nested_ty) = &field_ty {
                 let nested_table = format!(
                     "JOIN{}_{}_TO_{}",
                     self.join_counter,
-                    ty.name(),
+                    field_ty.name(),
                     nested_ty.name()
                 );
                 // PostgreSQL has a limit on identifiers to be at most 63 bytes long.
                 let nested_table = truncate_identifier(nested_table.as_str()).to_owned();
                 self.join_counter += 1;
 
-                self.make_scalar_field(field, current_table, field_policy, &keep_or_omit);
+                self.make_scalar_field(field, current_table, field_policy.clone(), &keep_or_omit);
                 joins.insert(
                     field.name.to_owned(),
                     Join {
-                        entity: self.load_entity_recursive(context, nested_ty, &nested_table)?,
-                        lkey: field.name.to_owned(),
+                        entity: self.load_entity_recursive(
+                            context,
+                            nested_ty,
+                            &nested_table,
+                            ancestors,
+                        )?,
+                        lkey: field.column_name().to_owned(),
                         rkey: "id".to_owned(),
                     },
                 );
@@ -405,11 +884,37 @@ impl QueryPlan {
                     transform: field_policy,
                     keep_or_omit,
                 }
+            } else if let Type::Union(entities) = &field_ty {
+                let (column_idx, members) =
+                    self.make_union_column(context, field, entities, current_table)?;
+                QueryField::EntityUnion {
+                    name: field.name.clone(),
+                    column_idx,
+                    members,
+                    transform: field_policy,
+                    keep_or_omit,
+                }
+            } else if let Type::Array(item_ty) = &field_ty {
+                match &**item_ty {
+                    Type::Entity(item_ty) => {
+                        let (column_idx, fields) =
+                            self.make_list_column(context, ty, field, item_ty, current_table)?;
+                        QueryField::EntityList {
+                            name: field.name.clone(),
+                            column_idx,
+                            fields,
+                            transform: field_policy,
+                            keep_or_omit,
+                        }
+                    }
+                    _ => self.make_scalar_field(field, current_table, field_policy, &keep_or_omit),
+                }
             } else {
                 self.make_scalar_field(field, current_table, field_policy, &keep_or_omit)
             };
             fields.push(query_field);
         }
+        ancestors.pop();
 
         Ok(QueriedEntity {
             ty: ty.clone(),
@@ -419,62 +924,10 @@ impl QueryPlan {
         })
     }
 
-    /// Adds filters that ensure login constrains are satisfied for a type
-    /// `ty` that is to be retrieved from the database.
-    fn add_login_filters_recursive(
-        &mut self,
-        context: &RequestContext,
-        ty: &Entity,
-        property_chain: Expr,
-    ) -> anyhow::Result<()> {
-        let field_policies = context.make_field_policies(ty);
-        let user_id: ExprValue = match &field_policies.current_userid {
-            None => "NULL",
-            Some(id) => id.as_str(),
-        }
-        .into();
-
-        for field in ty.all_fields() {
-            let ty = context.ts.get(&field.type_id)?;
-            if let Type::Entity(nested_ty) = &ty {
-                let property_access = PropertyAccess {
-                    property: field.name.to_owned(),
-                    object: property_chain.clone().into(),
-                };
-                if nested_ty.name() == AUTH_USER_NAME {
-                    if field_policies.match_login.contains(&field.name) {
-                        let expr = BinaryExpr::eq(property_access.into(), user_id.clone().into());
-                        self.operators.push(QueryOp::Filter { expression: expr });
-                    }
-                } else {
-                    self.add_login_filters_recursive(context, nested_ty, property_access.into())?;
-                }
-            }
-        }
-
-        Ok(())
-    }
-
-    fn make_column_string(&self) -> String {
+    fn make_column_string(&self, target: &TargetDatabase) -> String {
         let mut column_string = String::new();
         for c in &self.columns {
-            let col = match c.field.default_value() {
-                Some(dfl) => {
-                    let sql_default = match c.field.type_id {
-                        TypeId::String => format!("'{}'", dfl),
-                        _ => dfl.to_string(),
-                    };
-                    format!(
-                        "coalesce(\"{}\".\"{}\",{}) AS \"{}\",",
-                        c.table_name,
-                        c.name,
-                        sql_default,
-                        c.alias()
-                    )
-                }
-                None => format!("\"{}\".\"{}\" AS \"{}\",", c.table_name, c.name, c.alias()),
-            };
-            column_string += &col;
+            column_string += &format!("{} AS \"{}\",", c.select_sql(target), c.alias());
         }
         column_string.pop();
         column_string
@@ -512,6 +965,50 @@ impl QueryPlan {
         Ok(where_cond)
     }
 
+    /// The condition that keeps the rows whose searchable fields contain all
+    /// the words of `terms`, using the full-text search index of the base
+    /// type.
+    fn search_condition(&self, target: &TargetDatabase, terms: &str) -> Result<String> {
+        let ty = self.base_type();
+        let index = ty
+            .search_index()
+            .ok_or_else(|| anyhow!("entity '{}' has no @searchable fields", ty.name()))?;
+        let words: Vec<_> = terms.split_whitespace().collect();
+        if words.is_empty() {
+            return Ok("(1 = 0)".to_owned());
+        }
+        let id = self.property_expr_to_string(&PropertyAccess {
+            property: "id".to_owned(),
+            object: Box::new(Expr::Parameter { position: 0 }),
+        })?;
+        let table = ty.backing_table();
+        let condition = match target {
+            TargetDatabase::Postgres => format!(
+                "{} @@ plainto_tsquery('simple', {})",
+                search_document(ty, &index.fields),
+                escape_string(&words.join(" "))
+            ),
+            TargetDatabase::Sqlite => {
+                let fts_table = index.name().context("search index was not created")?;
+                // Each word is quoted, so that it isn't taken for FTS5 syntax.
+                let query = words
+                    .iter()
+                    .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!(
+                    "rowid IN (SELECT rowid FROM \"{0}\" WHERE \"{0}\" MATCH {1})",
+                    fts_table,
+                    escape_string(&query)
+                )
+            }
+        };
+        Ok(format!(
+            "({} IN (SELECT \"id\" FROM \"{}\" WHERE {}))",
+            id, table, condition
+        ))
+    }
+
     fn filter_expr_to_string(&self, expr: &Expr) -> Result<String> {
         let expr_str = match &expr {
             Expr::Value { value } => match &value {
@@ -536,18 +1033,148 @@ impl QueryPlan {
         Ok(expr_str)
     }
 
-    fn property_expr_to_string(&self, prop_access: &PropertyAccess) -> Result<String> {
-        fn get_property_chain(prop_access: &PropertyAccess) -> Result<Vec<String>> {
-            match &*prop_access.object {
-                Expr::Property(obj) => {
-                    let mut properties = get_property_chain(obj)?;
-                    properties.push(prop_access.property.to_owned());
-                    Ok(properties)
+    /// Like `make_filter_string()` for all the operators of the plan, but over
+    /// the columns of the base table instead of those of the SELECT. Returns
+    /// None if there are other operators than filters, or if the filters need
+    /// joins.
+    fn base_table_filter_string(&self) -> Result<Option<String>> {
+        if !self.operators.iter().all(|op| op.as_filter().is_some()) {
+            return Ok(None);
+        }
+        let expr = match self.gather_filters(&self.operators) {
+            Some(expr) => expr,
+            None => return Ok(Some("".to_owned())),
+        };
+        let condition = self.base_table_expr_to_string(&expr)?;
+        Ok(condition.map(|condition| format!("WHERE {}", condition)))
+    }
+
+    fn base_table_expr_to_string(&self, expr: &Expr) -> Result<Option<String>> {
+        let expr_str = match expr {
+            Expr::Binary(binary_exp) => {
+                let left = self.base_table_expr_to_string(&binary_exp.left)?;
+                let right = self.base_table_expr_to_string(&binary_exp.right)?;
+                match (left, right) {
+                    (Some(left), Some(right)) => {
+                        format!("({} {} {})", left, binary_exp.op.to_sql_string(), right)
+                    }
+                    _ => return Ok(None),
+                }
+            }
+            Expr::Property(PropertyAccess { property, object }) => {
+                if !matches!(**object, Expr::Parameter { .. }) {
+                    return Ok(None);
                 }
-                Expr::Parameter { .. } => Ok(vec![prop_access.property.to_owned()]),
-                _ => anyhow::bail!("unexpected expression in property chain!"),
+                let table = self.base_type().backing_table();
+                let column = self
+                    .columns
+                    .iter()
+                    .find(|c| c.table_name == table && &c.name == property && c.list.is_none())
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "expression error: entity '{}' doesn't have field '{}'",
+                            self.base_type().name(),
+                            property
+                        )
+                    })?;
+                column.value_sql()
             }
+            _ => self.filter_expr_to_string(expr)?,
+        };
+        Ok(Some(expr_str))
+    }
+
+    /// The SQL condition keeping the objects of type `ty`, whose rows are
+    /// aliased `alias` in a subquery, that satisfy all of `filters`.
+    fn subquery_condition(
+        &mut self,
+        context: &RequestContext,
+        ty: &Entity,
+        alias: &str,
+        filters: Vec<Expr>,
+    ) -> Result<Option<String>> {
+        filters
+            .into_iter()
+            .reduce(BinaryExpr::and)
+            .map(|expr| self.subquery_expr_to_string(context, ty, alias, &expr))
+            .transpose()
+    }
+
+    /// Like `filter_expr_to_string()`, but over the row of an object of type
+    /// `ty` aliased `alias` in a subquery rather than over the columns of the
+    /// SELECT. The objects a property chain goes through are selected by
+    /// nested subqueries.
+    fn subquery_expr_to_string(
+        &mut self,
+        context: &RequestContext,
+        ty: &Entity,
+        alias: &str,
+        expr: &Expr,
+    ) -> Result<String> {
+        let expr_str = match expr {
+            Expr::Binary(binary_exp) => format!(
+                "({} {} {})",
+                self.subquery_expr_to_string(context, ty, alias, &binary_exp.left)?,
+                binary_exp.op.to_sql_string(),
+                self.subquery_expr_to_string(context, ty, alias, &binary_exp.right)?,
+            ),
+            Expr::Property(prop_access) => {
+                let properties = get_property_chain(prop_access)?;
+                self.subquery_property_to_string(context, ty, alias, &properties)?
+            }
+            _ => self.filter_expr_to_string(expr)?,
+        };
+        Ok(expr_str)
+    }
+
+    fn subquery_property_to_string(
+        &mut self,
+        context: &RequestContext,
+        ty: &Entity,
+        alias: &str,
+        properties: &[String],
+    ) -> Result<String> {
+        let field = ty.get_field(&properties[0]).ok_or_else(|| {
+            anyhow!(
+                "expression error: entity '{}' doesn't have field '{}'",
+                ty.name(),
+                properties[0]
+            )
+        })?;
+        let column = Column {
+            name: field.name.to_owned(),
+            table_name: alias.to_owned(),
+            field: field.clone(),
+            list: None,
+            union: vec![],
+        };
+        if properties.len() == 1 {
+            return Ok(column.value_sql());
         }
+        let nested_ty = match context.ts.get(&field.type_id)? {
+            Type::Entity(nested_ty) => nested_ty,
+            _ => anyhow::bail!(
+                "expression error: field '{}' of entity '{}' isn't an entity",
+                field.name,
+                ty.name()
+            ),
+        };
+        let nested_alias =
+            truncate_identifier(&format!("FILTER{}_{}", self.join_counter, nested_ty.name()))
+                .to_owned();
+        self.join_counter += 1;
+        let value =
+            self.subquery_property_to_string(context, &nested_ty, &nested_alias, &properties[1..])?;
+        Ok(format!(
+            "(SELECT {} FROM \"{}\" AS \"{alias}\" WHERE \"{alias}\".\"id\" = {})",
+            value,
+            nested_ty.backing_table(),
+            column.value_sql(),
+            alias = nested_alias,
+        ))
+    }
+
+    fn property_expr_to_string(&self, prop_access: &PropertyAccess) -> Result<String> {
         let properties = get_property_chain(prop_access)?;
         assert!(!properties.is_empty());
 
@@ -587,29 +1214,44 @@ impl QueryPlan {
         Ok(format!("\"{}\"", c_alias))
     }
 
-    fn make_sort_string(&self, sort: Option<&SortBy>) -> Result<String> {
-        let sort_str = if let Some(sort) = sort {
-            let mut order_tokens = vec![];
-            for sort_key in &sort.keys {
-                if !self.base_type().has_field(&sort_key.field_name) {
-                    anyhow::bail!(
-                        "entity '{}' has no field named '{}'",
-                        self.base_type().name(),
-                        sort_key.field_name
-                    );
-                }
-                let order = if sort_key.ascending { "ASC" } else { "DESC" };
-                let c_alias = ColumnAlias {
-                    field_name: sort_key.field_name.to_owned(),
-                    table_name: self.base_type().backing_table().to_owned(),
-                };
-                order_tokens.push(format!("\"{c_alias}\" {order}"));
-            }
-            format!("ORDER BY {}", order_tokens.join(", "))
-        } else {
-            "".into()
-        };
-        Ok(sort_str)
+    fn make_sort_string(&self, target: &TargetDatabase, keys: &[SortKey]) -> Result<String> {
+        if keys.is_empty() {
+            return Ok("".into());
+        }
+        let ty = self.base_type();
+        let mut order_tokens = vec![];
+        for sort_key in keys {
+            let field_name = &sort_key.field_name;
+            anyhow::ensure!(
+                ty.has_field(field_name),
+                "entity '{}' has no field named '{}'",
+                ty.name(),
+                field_name
+            );
+            // Relations have no column of their own to sort by.
+            anyhow::ensure!(
+                self.entity.fields.iter().any(
+                    |field| matches!(field, QueryField::Scalar { name, .. } if name == field_name)
+                ),
+                "entity '{}' can't be sorted by field '{}'",
+                ty.name(),
+                field_name
+            );
+            let c_alias = ColumnAlias {
+                field_name: field_name.to_owned(),
+                table_name: self.entity.table_alias.to_owned(),
+            };
+            // NULLs are greater than any value on Postgres, but lesser on
+            // SQLite, which is made to sort them like Postgres does.
+            let order = match (sort_key.ascending, target) {
+                (true, TargetDatabase::Postgres) => "ASC",
+                (false, TargetDatabase::Postgres) => "DESC",
+                (true, TargetDatabase::Sqlite) => "ASC NULLS LAST",
+                (false, TargetDatabase::Sqlite) => "DESC NULLS FIRST",
+            };
+            order_tokens.push(format!("\"{c_alias}\" {order}"));
+        }
+        Ok(format!("ORDER BY {}", order_tokens.join(", ")))
     }
 
     fn make_limit_and_offset_string(
@@ -628,14 +1270,63 @@ impl QueryPlan {
         }
     }
 
-    fn make_core_select(&self) -> String {
-        let column_string = self.make_column_string();
+    fn make_core_select(&self, target: &TargetDatabase) -> Result<String> {
+        let column_string = self.make_column_string(target);
         let join_string = self.make_join_string();
-        format!(
-            "SELECT {} FROM \"{}\" {}",
+        Ok(format!(
+            "SELECT {} FROM {} {} {}",
             column_string,
-            self.base_type().backing_table(),
+            self.make_base_table(),
             join_string,
+            self.make_traversal_string(target)?,
+        ))
+    }
+
+    /// The condition keeping the rows of the base type that the traversal of
+    /// the plan reaches, if any.
+    fn make_traversal_string(&self, target: &TargetDatabase) -> Result<String> {
+        let traversal = match &self.traversal {
+            Some(traversal) => traversal,
+            None => return Ok("".to_owned()),
+        };
+        let origin = &traversal.origin;
+        let origin_ids = format!(
+            "SELECT \"{}\" FROM ({}) AS origin",
+            ColumnAlias {
+                field_name: "id".to_owned(),
+                table_name: origin.entity.table_alias.to_owned(),
+            },
+            origin.make_raw_query(target)?
+        );
+        let table = &self.entity.table_alias;
+        Ok(match &traversal.relation {
+            Relation::Children { column } => {
+                format!("WHERE \"{}\".\"{}\" IN ({})", table, column, origin_ids)
+            }
+            Relation::Related { join_table } => format!(
+                "WHERE \"{}\".\"id\" IN (SELECT \"item\" FROM \"{}\" WHERE \"owner\" IN ({}))",
+                table, join_table, origin_ids
+            ),
+        })
+    }
+
+    /// The table the base type is read from. With archived rows, it is the
+    /// union of the backing and archive tables, under the name of the former.
+    /// A row saved again after being archived is read from the backing table.
+    fn make_base_table(&self) -> String {
+        let ty = self.base_type();
+        let table = ty.backing_table();
+        if !self.with_archived || ty.archive().is_none() {
+            return format!("\"{}\"", table);
+        }
+        let columns = ty
+            .column_fields()
+            .map(|field| format!("\"{}\"", field.column_name()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            r#"(SELECT {columns} FROM "{table}" UNION ALL SELECT {columns} FROM "{archive}" WHERE NOT EXISTS (SELECT 1 FROM "{table}" AS hot WHERE hot."id" = "{archive}"."id")) AS "{table}""#,
+            archive = ty.archive_table(),
         )
     }
 
@@ -669,10 +1360,19 @@ impl QueryPlan {
         expr
     }
 
-    fn find_last_sort_by<'a>(&self, ops: &'a [QueryOp]) -> Option<&'a SortBy> {
-        ops.iter()
-            .rfind(|op| op.as_sort_by().is_some())
-            .map(|op| op.as_sort_by().unwrap())
+    /// The keys `ops` sort by. Sorts are stable, so the keys of each sort
+    /// break the ties of the sorts after it, like when they are evaluated in
+    /// the isolate.
+    fn gather_sort_keys(&self, ops: &[QueryOp]) -> Vec<SortKey> {
+        let mut keys: Vec<SortKey> = vec![];
+        for sort in ops.iter().rev().filter_map(QueryOp::as_sort_by) {
+            for key in &sort.keys {
+                if !keys.iter().any(|k| k.field_name == key.field_name) {
+                    keys.push(key.clone());
+                }
+            }
+        }
+        keys
     }
 
     fn find_take_count(&self, ops: &[QueryOp]) -> Option<u64> {
@@ -690,17 +1390,25 @@ impl QueryPlan {
     }
 
     fn make_raw_query(&self, target: &TargetDatabase) -> Result<String> {
-        let mut sql_query = self.make_core_select();
+        let mut sql_query = self.make_core_select(target)?;
         let mut remaining_ops: &[QueryOp] = &self.operators[..];
         while !remaining_ops.is_empty() {
             let (ops, remainder) = self.split_on_first_take(remaining_ops);
             remaining_ops = remainder;
 
             let filter_expr = self.gather_filters(ops);
-            let filter_string = self.make_filter_string(&filter_expr)?;
+            let mut filter_string = self.make_filter_string(&filter_expr)?;
+            for terms in ops.iter().filter_map(QueryOp::as_search) {
+                let condition = self.search_condition(target, terms)?;
+                filter_string = if filter_string.is_empty() {
+                    format!("WHERE {}", condition)
+                } else {
+                    format!("{} AND {}", filter_string, condition)
+                };
+            }
 
-            let sort = self.find_last_sort_by(ops);
-            let sort_string = self.make_sort_string(sort)?;
+            let sort_keys = self.gather_sort_keys(ops);
+            let sort_string = self.make_sort_string(target, &sort_keys)?;
 
             let limit = self.find_take_count(ops);
             let offset = self.find_skip_count(ops);
@@ -722,13 +1430,177 @@ impl QueryPlan {
             allowed_fields: self.allowed_fields.clone(),
         })
     }
+
+    /// Builds the query computing `aggregation` over the rows of this plan.
+    pub fn build_aggregate_query(
+        &self,
+        target: &TargetDatabase,
+        aggregation: Aggregation,
+    ) -> Result<AggregateQuery> {
+        anyhow::ensure!(
+            aggregation.count || aggregation.sum.is_some() || aggregation.avg.is_some(),
+            "an aggregation needs at least one of count, sum or avg"
+        );
+        let mut columns = vec![];
+        let mut group_string = String::new();
+        let mut group_type = None;
+        if let Some(field) = &aggregation.group_by {
+            let (column, type_id) = self.aggregated_column(field)?;
+            anyhow::ensure!(
+                !matches!(type_id, TypeId::Array(_) | TypeId::Json | TypeId::File),
+                "entity '{}' can't be grouped by field '{}'",
+                self.base_type().name(),
+                field
+            );
+            columns.push(column.clone());
+            group_string = format!("GROUP BY {0} ORDER BY {0}", column);
+            group_type = Some(type_id);
+        }
+        if let Some(raw_sql) = self.make_counter_query(&aggregation) {
+            return Ok(AggregateQuery {
+                raw_sql,
+                aggregation,
+                group_type,
+            });
+        }
+        if aggregation.count {
+            columns.push("COUNT(*)".to_owned());
+        }
+        // Both databases take DOUBLE PRECISION, and SQLite otherwise returns
+        // integers when all the summed values are.
+        if let Some(field) = &aggregation.sum {
+            let column = self.aggregated_number(field)?;
+            columns.push(format!(
+                "CAST(COALESCE(SUM({}), 0) AS DOUBLE PRECISION)",
+                column
+            ));
+        }
+        if let Some(field) = &aggregation.avg {
+            let column = self.aggregated_number(field)?;
+            columns.push(format!("CAST(AVG({}) AS DOUBLE PRECISION)", column));
+        }
+        let raw_sql = format!(
+            "SELECT {} FROM ({}) AS aggregated {}",
+            columns.join(", "),
+            self.make_raw_query(target)?,
+            group_string
+        );
+        Ok(AggregateQuery {
+            raw_sql,
+            aggregation,
+            group_type,
+        })
+    }
+
+    /// Whether all the rows of the plan can be counted from a counter of
+    /// `@counted`, without reading them.
+    pub fn has_counter(&self) -> bool {
+        let aggregation = Aggregation {
+            count: true,
+            ..Default::default()
+        };
+        self.make_counter_query(&aggregation).is_some()
+    }
+
+    /// The query reading `aggregation` from a counter of `@counted`, if it
+    /// counts all the rows of the base type, or those with each value of a
+    /// counted field. Counts of fewer rows have to be computed.
+    fn make_counter_query(&self, aggregation: &Aggregation) -> Option<String> {
+        if !aggregation.count || aggregation.sum.is_some() || aggregation.avg.is_some() {
+            return None;
+        }
+        // Counters only count the rows of the backing table.
+        if self.with_archived || self.traversal.is_some() {
+            return None;
+        }
+        // Filters, including those of policies, and limits change which rows
+        // are counted. Sorts don't.
+        if !self.operators.iter().all(|op| op.as_sort_by().is_some()) {
+            return None;
+        }
+        let counter = self.base_type().counter(aggregation.group_by.as_deref())?;
+        let name = counter.name()?;
+        Some(match &aggregation.group_by {
+            Some(field) => format!(
+                r#"SELECT "{0}", "count" FROM "{1}" ORDER BY "{0}""#,
+                field, name
+            ),
+            None => format!(
+                r#"SELECT CAST(COALESCE(SUM("count"), 0) AS BIGINT) FROM "{}""#,
+                name
+            ),
+        })
+    }
+
+    /// The column of a scalar `field` of the base type to aggregate, and the
+    /// field's type. Fields that policies transform or omit can't be
+    /// aggregated, for that would reveal what the policies hide.
+    fn aggregated_column(&self, field_name: &str) -> Result<(String, TypeId)> {
+        let ty = self.base_type();
+        anyhow::ensure!(
+            ty.has_field(field_name),
+            "entity '{}' has no field named '{}'",
+            ty.name(),
+            field_name
+        );
+        let field = self.entity.fields.iter().find_map(|field| match field {
+            QueryField::Scalar {
+                name,
+                type_id,
+                transform,
+                keep_or_omit,
+                ..
+            } if name == field_name => Some((type_id, transform, keep_or_omit)),
+            _ => None,
+        });
+        let (type_id, transform, keep_or_omit) = field.ok_or_else(|| {
+            anyhow!(
+                "field '{}' of entity '{}' can't be aggregated",
+                field_name,
+                ty.name()
+            )
+        })?;
+        anyhow::ensure!(
+            transform.is_none() && matches!(keep_or_omit, KeepOrOmitField::Keep),
+            "field '{}' of entity '{}' is restricted by a policy and can't be aggregated",
+            field_name,
+            ty.name()
+        );
+        let c_alias = ColumnAlias {
+            field_name: field_name.to_owned(),
+            table_name: self.entity.table_alias.to_owned(),
+        };
+        Ok((format!("\"{}\"", c_alias), type_id.clone()))
+    }
+
+    fn aggregated_number(&self, field_name: &str) -> Result<String> {
+        let (column, type_id) = self.aggregated_column(field_name)?;
+        anyhow::ensure!(
+            matches!(type_id, TypeId::Float),
+            "field '{}' of entity '{}' is not a number",
+            field_name,
+            self.base_type().name()
+        );
+        Ok(column)
+    }
 }
 
 // FIXME: We should use prepared statements instead
-fn escape_string(s: &str) -> String {
+pub(crate) fn escape_string(s: &str) -> String {
     format!("{}", format_sql_query::QuotedData(s))
 }
 
+/// The Postgres `tsvector` of the searchable `fields` of a row of `ty`, which
+/// both their search index and the searches over them use.
+pub(crate) fn search_document(ty: &ObjectType, fields: &[String]) -> String {
+    let text = fields
+        .iter()
+        .map(|field| format!("coalesce(\"{}\", '')", ty.column(field)))
+        .collect::<Vec<_>>()
+        .join(" || ' ' || ");
+    format!("to_tsvector('simple', {})", text)
+}
+
 /// Returns the longest possible prefix of `s` that is at most `max_len`
 /// bytes long and ends at a character boundary so that we don't break
 /// multi-byte characters.
@@ -749,6 +1621,148 @@ pub fn truncate_identifier(s: &str) -> &str {
     max_prefix(s, 63)
 }
 
+/// The fields of the objects of `ty` that are selected into a JSON object
+/// when they are related to by a many-to-many or a polymorphic relation,
+/// with how they are read. Their own relations are left out.
+fn listed_fields(context: &RequestContext, ty: &Entity) -> Result<(Vec<Field>, Vec<ListedField>)> {
+    let field_policies = context.make_field_policies(ty);
+    let mut fields = vec![];
+    let mut listed_fields = vec![];
+    for field in ty.column_fields() {
+        if matches!(
+            context.ts.get(&field.type_id)?,
+            Type::Entity(_) | Type::Union(_)
+        ) {
+            continue;
+        }
+        listed_fields.push(ListedField {
+            name: field.name.clone(),
+            type_id: field.type_id.clone(),
+            is_optional: field.is_optional,
+            transform: field_policies.transforms.get(&field.name).cloned(),
+            keep_or_omit: match field_policies.omit.contains(&field.name) {
+                true => KeepOrOmitField::Omit,
+                _ => KeepOrOmitField::Keep,
+            },
+        });
+        fields.push(field.clone());
+    }
+    Ok((fields, listed_fields))
+}
+
+/// The filters that ensure login and tenant constraints, as well as row
+/// policies, are satisfied for objects of type `ty` reached through
+/// `property_chain`, and for the entities nested in them.
+fn login_filters(
+    context: &RequestContext,
+    ty: &Entity,
+    property_chain: Expr,
+    ancestors: &mut Vec<String>,
+) -> Result<Vec<Expr>> {
+    let field_policies = context.make_field_policies(ty);
+    let user_id: ExprValue = match &field_policies.current_userid {
+        None => "NULL",
+        Some(id) => id.as_str(),
+    }
+    .into();
+
+    let tenant: ExprValue = match &field_policies.current_tenant {
+        None => "NULL",
+        Some(tenant) => tenant.as_str(),
+    }
+    .into();
+
+    let mut filters = context.make_row_filters(ty, &property_chain);
+
+    ancestors.push(ty.name().to_owned());
+    for field in ty.all_fields() {
+        // Like in load_entity_recursive(), which leaves these fields out.
+        if let Type::Entity(nested_ty) = context.ts.get(&field.type_id)? {
+            if is_repeated(ancestors, &nested_ty) {
+                continue;
+            }
+        }
+        if field_policies.match_tenant.contains(&field.name) {
+            let property_access = PropertyAccess {
+                property: field.name.to_owned(),
+                object: property_chain.clone().into(),
+            };
+            let expr = BinaryExpr::eq(property_access.into(), tenant.clone().into());
+            filters.push(expr);
+        }
+        let ty = context.ts.get(&field.type_id)?;
+        if let Type::Entity(nested_ty) = &ty {
+            let property_access = PropertyAccess {
+                property: field.name.to_owned(),
+                object: property_chain.clone().into(),
+            };
+            if nested_ty.name() == AUTH_USER_NAME {
+                if field_policies.match_login.contains(&field.name) {
+                    let expr = BinaryExpr::eq(property_access.into(), user_id.clone().into());
+                    filters.push(expr);
+                }
+            } else {
+                filters.extend(login_filters(
+                    context,
+                    nested_ty,
+                    property_access.into(),
+                    ancestors,
+                )?);
+            }
+        }
+    }
+    ancestors.pop();
+
+    Ok(filters)
+}
+
+/// The join tables of the many-to-many relations that link objects of `ty`,
+/// with the column holding their ids: "owner" for the relations of `ty`
+/// itself, and "item" for those relating to `ty`. Only the entities of the
+/// version of `ty` relate to it, except for AuthUser, which all versions
+/// share.
+pub(crate) fn join_tables_of(ts: &TypeSystem, ty: &Entity) -> Result<Vec<(String, &'static str)>> {
+    let mut join_tables = vec![];
+    for field in ty.many_to_many_fields() {
+        join_tables.push((ty.join_table(field)?, "owner"));
+    }
+    for (api_version, version) in &ts.versions {
+        if !ty.is_auth() && *api_version != ty.api_version {
+            continue;
+        }
+        for owner in version.custom_types.values() {
+            for field in owner.many_to_many_fields() {
+                if let TypeId::Array(item) = &field.type_id {
+                    if matches!(&**item, TypeId::Entity { name, .. } if name == ty.name()) {
+                        join_tables.push((owner.join_table(field)?, "item"));
+                    }
+                }
+            }
+        }
+    }
+    Ok(join_tables)
+}
+
+/// The names of the properties of chain `prop_access`, starting from the
+/// object the chain is on.
+fn get_property_chain(prop_access: &PropertyAccess) -> Result<Vec<String>> {
+    match &*prop_access.object {
+        Expr::Property(obj) => {
+            let mut properties = get_property_chain(obj)?;
+            properties.push(prop_access.property.to_owned());
+            Ok(properties)
+        }
+        Expr::Parameter { .. } => Ok(vec![prop_access.property.to_owned()]),
+        _ => anyhow::bail!("unexpected expression in property chain!"),
+    }
+}
+
+/// Whether loading `ty` nested in `ancestors` would load it a third time,
+/// which only happens with entities that refer to themselves.
+fn is_repeated(ancestors: &[String], ty: &Entity) -> bool {
+    ancestors.iter().filter(|name| *name == ty.name()).count() >= 2
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "type")]
 pub enum QueryOpChain {
@@ -778,28 +1792,114 @@ pub enum QueryOpChain {
         keys: Vec<SortKey>,
         inner: Box<QueryOpChain>,
     },
+    #[serde(rename = "TextSearch")]
+    Search {
+        terms: String,
+        inner: Box<QueryOpChain>,
+    },
+    WithArchived {
+        inner: Box<QueryOpChain>,
+    },
+    /// The objects whose self-referential `field` refers to an object of
+    /// `inner`.
+    Children {
+        field: String,
+        inner: Box<QueryOpChain>,
+    },
+    /// The objects of entity `entity_name` that the objects of `inner`
+    /// relate to with many-to-many `field`.
+    Related {
+        field: String,
+        #[serde(rename = "entityName")]
+        entity_name: String,
+        inner: Box<QueryOpChain>,
+    },
 }
 
-/// Converts operator chain into a tuple `(entity_name, ops)`, where
-/// `entity_name` is the name taken from the BaseEntity which corresponds to
-/// Entity which is to be queried. `ops` are a Vector of Operators that
-/// are to be applied on the resulting entity elements in order that
-/// is defined by the vector.
-fn convert_ops(op: QueryOpChain) -> Result<(String, Vec<QueryOp>)> {
+impl QueryOpChain {
+    /// The name of the entity the chain queries.
+    pub fn entity_name(&self) -> &str {
+        use QueryOpChain as Op;
+        match self {
+            Op::BaseEntity { name } => name,
+            Op::Related { entity_name, .. } => entity_name,
+            Op::Filter { inner, .. }
+            | Op::Projection { inner, .. }
+            | Op::Take { inner, .. }
+            | Op::Skip { inner, .. }
+            | Op::SortBy { inner, .. }
+            | Op::Search { inner, .. }
+            | Op::WithArchived { inner }
+            | Op::Children { inner, .. } => inner.entity_name(),
+        }
+    }
+
+    /// The names of the entities the chain reads, the one it queries first,
+    /// followed by those it traverses relations from.
+    pub fn entity_names(&self) -> Vec<&str> {
+        use QueryOpChain as Op;
+        let mut names = vec![self.entity_name()];
+        let mut op = self;
+        loop {
+            op = match op {
+                Op::BaseEntity { .. } => break,
+                Op::Children { inner, .. } | Op::Related { inner, .. } => {
+                    let name = inner.entity_name();
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                    inner
+                }
+                Op::Filter { inner, .. }
+                | Op::Projection { inner, .. }
+                | Op::Take { inner, .. }
+                | Op::Skip { inner, .. }
+                | Op::SortBy { inner, .. }
+                | Op::Search { inner, .. }
+                | Op::WithArchived { inner } => inner,
+            };
+        }
+        names
+    }
+
+    /// Whether the chain leaves the order and bounds of the result set
+    /// unspecified, i.e. it only filters and projects the entity.
+    pub fn is_unordered(&self) -> bool {
+        use QueryOpChain as Op;
+        match self {
+            // The objects reached through a relation are in no order.
+            Op::BaseEntity { .. } | Op::Children { .. } | Op::Related { .. } => true,
+            Op::Filter { inner, .. }
+            | Op::Projection { inner, .. }
+            | Op::Search { inner, .. }
+            | Op::WithArchived { inner } => inner.is_unordered(),
+            Op::Take { .. } | Op::Skip { .. } | Op::SortBy { .. } => false,
+        }
+    }
+}
+
+/// Converts operator chain into a tuple `(source, ops)`, where `source` is
+/// the BaseEntity which corresponds to Entity which is to be queried, or the
+/// relation traversal its elements are reached through. `ops` are a Vector
+/// of Operators that are to be applied on the resulting entity elements in
+/// order that is defined by the vector.
+fn convert_ops(op: QueryOpChain) -> Result<(QueryOpChain, Vec<QueryOp>)> {
     use QueryOpChain as Op;
     let (query_op, inner): (QueryOp, _) = match op {
-        Op::BaseEntity { name } => {
-            return Ok((name, vec![]));
+        Op::BaseEntity { .. } | Op::Children { .. } | Op::Related { .. } => {
+            return Ok((op, vec![]));
         }
         Op::Filter { expression, inner } => (QueryOp::Filter { expression }, inner),
         Op::Projection { fields, inner } => (QueryOp::Projection { fields }, inner),
         Op::Take { count, inner } => (QueryOp::Take { count }, inner),
         Op::Skip { count, inner } => (QueryOp::Skip { count }, inner),
         Op::SortBy { keys, inner } => (QueryOp::SortBy(SortBy { keys }), inner),
+        Op::Search { terms, inner } => (QueryOp::Search { terms }, inner),
+        Op::WithArchived { inner } => (QueryOp::WithArchived, inner),
     };
-    let (entity_name, mut ops) = convert_ops(*inner)?;
+    let (source, mut ops) = convert_ops(*inner)?;
     ops.push(query_op);
-    Ok((entity_name, ops))
+    Ok((source, ops))
 }
 
 /// `Mutation` represents a statement that mutates the database state.
@@ -807,6 +1907,9 @@ pub struct Mutation {
     base_entity: Entity,
     /// Query plan used to build mutation condition.
     filter_query_plan: QueryPlan,
+    /// The join tables linking to objects of the base entity, with the
+    /// column holding their ids.
+    join_tables: Vec<(String, &'static str)>,
 }
 
 impl Mutation {
@@ -823,18 +1926,30 @@ impl Mutation {
         };
 
         let mut query_plan = QueryPlan::from_entity_name(c, type_name)?;
+        // Deleting also deletes the matching archived rows.
+        query_plan.with_archived = true;
         if let Some(expr) = filter_expr {
             query_plan.extend_operators(vec![QueryOp::Filter {
                 expression: expr.clone(),
             }]);
         }
+        let join_tables = join_tables_of(c.ts, &base_entity)?;
         Ok(Self {
             base_entity,
             filter_query_plan: query_plan,
+            join_tables,
         })
     }
 
     pub fn build_sql(&self, target: TargetDatabase) -> Result<String> {
+        // Filters on the columns of the table itself don't need a subquery.
+        if let Some(filter_string) = self.filter_query_plan.base_table_filter_string()? {
+            return Ok(format!(
+                "DELETE FROM \"{}\" {}",
+                self.base_entity.backing_table(),
+                filter_string
+            ));
+        }
         let select_sql = self.filter_query_plan.build_query(&target)?.raw_sql;
         let id_column = ColumnAlias {
             field_name: "id".to_owned(),
@@ -849,6 +1964,59 @@ impl Mutation {
         );
         Ok(raw_sql)
     }
+
+    /// Builds the query selecting the ids of the rows that the mutation
+    /// applies to.
+    pub fn build_ids_sql(&self, target: TargetDatabase) -> Result<String> {
+        let select_sql = self.filter_query_plan.build_query(&target)?.raw_sql;
+        let id_column = ColumnAlias {
+            field_name: "id".to_owned(),
+            table_name: self.base_entity.backing_table().to_owned(),
+        };
+        Ok(format!(
+            r#"SELECT "{id_column}" FROM ({select_sql}) as subquery"#
+        ))
+    }
+
+    /// Builds the statements deleting the links of many-to-many relations
+    /// from and to the rows that the mutation applies to, so that they don't
+    /// outlive the rows, nor link a row that later reuses an id.
+    pub fn build_join_table_sql(&self, target: TargetDatabase) -> Result<Vec<String>> {
+        if self.join_tables.is_empty() {
+            return Ok(vec![]);
+        }
+        let ids_sql = self.build_ids_sql(target)?;
+        Ok(self
+            .join_tables
+            .iter()
+            .map(|(join_table, column)| {
+                format!(
+                    r#"DELETE FROM "{}" WHERE "{}" IN ({})"#,
+                    join_table, column, ids_sql
+                )
+            })
+            .collect())
+    }
+
+    /// Builds the statement deleting the archived rows that the mutation
+    /// applies to, if the base entity is archived.
+    pub fn build_archive_sql(&self, target: TargetDatabase) -> Result<Option<String>> {
+        if self.base_entity.archive().is_none() {
+            return Ok(None);
+        }
+        let select_sql = self.filter_query_plan.build_query(&target)?.raw_sql;
+        let id_column = ColumnAlias {
+            field_name: "id".to_owned(),
+            table_name: self.base_entity.backing_table().to_owned(),
+        };
+        Ok(Some(format!(
+            r#"DELETE FROM "{archive_table}"
+                WHERE "id" IN (
+                    SELECT "{id_column}" FROM ({select_sql}) as subquery
+                )"#,
+            archive_table = self.base_entity.archive_table(),
+        )))
+    }
 }
 
 #[cfg(test)]
@@ -993,6 +2161,7 @@ pub mod tests {
                     ts: &make_type_system(&*ENTITIES),
                     api_version: VERSION.to_owned(),
                     user_id: None,
+                    tenant: None,
                     path: "".to_string(),
                     headers: HashMap::default(),
                 },
@@ -1049,9 +2218,116 @@ pub mod tests {
             let ops = make_sort_op(&[("age", true), ("name", true)]);
             let names = fetch_names(qe.clone(), ops.clone()).await;
             assert_eq!(names, vec!["John", "Alan", "Kek", "Max"]);
+
+            // The earlier sort breaks the ties of the later one.
+            let ops = QueryOpChain::SortBy {
+                keys: vec![SortKey {
+                    field_name: "age".to_owned(),
+                    ascending: false,
+                }],
+                inner: make_sort_op(&[("name", true)]).into(),
+            };
+            let names = fetch_names(qe.clone(), ops).await;
+            assert_eq!(names, vec!["Kek", "Max", "Alan", "John"]);
         }
     }
 
+    #[tokio::test]
+    async fn test_self_reference() {
+        let parent = types::NewField::new(
+            "parent",
+            TypeId::Entity {
+                name: "Category".to_owned(),
+                api_version: VERSION.to_owned(),
+            },
+            VERSION,
+        )
+        .unwrap();
+        let category = make_entity(
+            "Category",
+            vec![
+                make_field("name", Type::String),
+                Field::new(&parent, vec![], None, true, false),
+            ],
+        );
+        let ts = make_type_system(&[category.clone()]);
+        let context = RequestContext {
+            policies: &Policies::default(),
+            ts: &ts,
+            api_version: VERSION.to_owned(),
+            user_id: None,
+            tenant: None,
+            path: "".to_string(),
+            headers: HashMap::default(),
+        };
+        let (qe, _db_file) = setup_clear_db(&[category.clone()]).await;
+        let root = json!({"id": "8a2b2a4e-3f47-4bb0-8e3a-2b4d6c5b1f00", "name": "root"});
+        let books =
+            json!({"id": "8a2b2a4e-3f47-4bb0-8e3a-2b4d6c5b1f01", "name": "books", "parent": root});
+        let novels = json!({"name": "novels", "parent": books});
+        add_row(&qe, &category, &novels, &ts).await;
+        add_row(
+            &qe,
+            &category,
+            &json!({"name": "games", "parent": root}),
+            &ts,
+        )
+        .await;
+
+        // The parent is loaded, but not the parent of the parent.
+        let plan = QueryPlan::from_op_chain(
+            &context,
+            QueryOpChain::Filter {
+                expression: binary(&["name"], BinaryOp::Eq, "novels".into()),
+                inner: QueryOpChain::BaseEntity {
+                    name: "Category".to_owned(),
+                }
+                .into(),
+            },
+        )
+        .unwrap();
+        let rows = fetch_rows_with_plan(&qe, plan).await;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["parent"]["name"], "books");
+        assert!(rows[0]["parent"].get("parent").is_none());
+
+        let children = |inner: QueryOpChain| QueryOpChain::Children {
+            field: "parent".to_owned(),
+            inner: inner.into(),
+        };
+        let root_chain = QueryOpChain::Filter {
+            expression: binary(&["name"], BinaryOp::Eq, "root".into()),
+            inner: QueryOpChain::BaseEntity {
+                name: "Category".to_owned(),
+            }
+            .into(),
+        };
+        let names = |rows: Vec<JsonObject>| {
+            let mut names: Vec<_> = rows
+                .iter()
+                .map(|r| r["name"].as_str().unwrap().to_owned())
+                .collect();
+            names.sort();
+            names
+        };
+        let plan = QueryPlan::from_op_chain(&context, children(root_chain.clone())).unwrap();
+        let rows = fetch_rows_with_plan(&qe, plan).await;
+        assert_eq!(names(rows), vec!["books", "games"]);
+        let plan = QueryPlan::from_op_chain(&context, children(children(root_chain))).unwrap();
+        let rows = fetch_rows_with_plan(&qe, plan).await;
+        assert_eq!(names(rows), vec!["novels"]);
+
+        // Only self-referential fields have children.
+        let chain = QueryOpChain::Children {
+            field: "name".to_owned(),
+            inner: QueryOpChain::BaseEntity {
+                name: "Category".to_owned(),
+            }
+            .into(),
+        };
+        assert!(QueryPlan::from_op_chain(&context, chain).is_err());
+    }
+
     #[tokio::test]
     async fn test_delete_with_expr() {
         let delete_with_expr = |entity_name: &str, expr: Expr| {
@@ -1061,6 +2337,7 @@ pub mod tests {
                     ts: &make_type_system(&*ENTITIES),
                     api_version: VERSION.to_owned(),
                     user_id: None,
+                    tenant: None,
                     path: "".to_string(),
                     headers: HashMap::default(),
                 },
@@ -1089,7 +2366,7 @@ pub mod tests {
 
             let expr = binary(&["age"], BinaryOp::Eq, (30.).into());
             let mutation = delete_with_expr("Person", expr);
-            qe.mutate(mutation).await.unwrap();
+            assert_eq!(qe.mutate(mutation).await.unwrap(), 1);
 
             let rows = fetch_rows(&qe, &PERSON_TY).await;
             assert_eq!(rows.len(), 1);
@@ -1103,9 +2380,48 @@ pub mod tests {
 
             let expr = binary(&["ceo", "name"], BinaryOp::Eq, "John".into());
             let mutation = delete_with_expr("Company", expr);
-            qe.mutate(mutation).await.unwrap();
+            assert_eq!(qe.mutate(mutation).await.unwrap(), 1);
 
             assert_eq!(fetch_rows(&qe, &COMPANY_TY).await.len(), 0);
         }
     }
+
+    #[test]
+    fn test_delete_sql() {
+        let ts = make_type_system(&*ENTITIES);
+        let context = RequestContext {
+            policies: &Policies::default(),
+            ts: &ts,
+            api_version: VERSION.to_owned(),
+            user_id: None,
+            tenant: None,
+            path: "".to_string(),
+            headers: HashMap::default(),
+        };
+        let delete_sql = |entity_name: &str, expr: Expr| {
+            Mutation::delete_from_expr(&context, entity_name, &Some(expr))
+                .unwrap()
+                .build_sql(TargetDatabase::Sqlite)
+                .unwrap()
+        };
+
+        // Filters on the table's own columns are applied to it directly.
+        let sql = delete_sql("Person", binary(&["age"], BinaryOp::Eq, (30.).into()));
+        assert_eq!(
+            sql,
+            format!(
+                "DELETE FROM \"{0}\" WHERE (\"{0}\".\"age\" = 30)",
+                PERSON_TY.backing_table()
+            )
+        );
+        let sql = delete_sql("Company", binary(&["name"], BinaryOp::Eq, "A".into()));
+        assert!(!sql.contains("subquery"), "{}", sql);
+
+        // Filters on nested entities need their joins.
+        let sql = delete_sql(
+            "Company",
+            binary(&["ceo", "name"], BinaryOp::Eq, "John".into()),
+        );
+        assert!(sql.contains("IN ("), "{}", sql);
+    }
 }
@@ -0,0 +1,91 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Table and column statistics.
+//!
+//! Statistics are computed on demand by scanning the backing table, much like
+//! an `ANALYZE` would, so they are always current but cost a full scan per
+//! column. Nothing is kept between calls: there are no histograms, writes don't
+//! maintain any statistics, and the query planner doesn't consult them.
+
+use crate::datastore::QueryEngine;
+use crate::types::Entity;
+use anyhow::{Context, Result};
+use sqlx::{Executor, Row};
+
+/// Number of most common values reported for each column.
+const MOST_COMMON_VALUES: i64 = 5;
+
+pub struct EntityStats {
+    pub row_count: u64,
+    /// Statistics of each field stored in a column, in declaration order.
+    pub columns: Vec<ColumnStats>,
+}
+
+pub struct ColumnStats {
+    pub name: String,
+    pub distinct_count: u64,
+    pub null_count: u64,
+    /// The most common non-null values, rendered as strings, with their
+    /// number of occurrences.
+    pub most_common: Vec<(String, u64)>,
+}
+
+/// Computes statistics of the backing table of `ty`. Column statistics are
+/// only computed if `columns` is set.
+pub async fn entity_stats(engine: &QueryEngine, ty: &Entity, columns: bool) -> Result<EntityStats> {
+    let table = ty.backing_table();
+    let mut transaction = engine.begin_transaction().await?;
+
+    let sql = format!("SELECT COUNT(*) FROM \"{}\"", table);
+    let row = transaction
+        .fetch_one(sqlx::query(&sql))
+        .await
+        .with_context(|| format!("failed to count rows of entity {}", ty.name()))?;
+    let row_count = row.get::<i64, _>(0) as u64;
+
+    let mut column_stats = vec![];
+    if columns {
+        // Many-to-many fields live in join tables, and the id is unique.
+        for field in ty.column_fields().filter(|field| field.name != "id") {
+            let column = field.column_name();
+            let context = || format!("failed to analyze {}.{}", ty.name(), field.name);
+
+            let sql = format!(
+                "SELECT COUNT(DISTINCT \"{column}\"), COUNT(*) - COUNT(\"{column}\") FROM \"{table}\""
+            );
+            let row = transaction
+                .fetch_one(sqlx::query(&sql))
+                .await
+                .with_context(context)?;
+            let distinct_count = row.get::<i64, _>(0) as u64;
+            let null_count = row.get::<i64, _>(1) as u64;
+
+            let sql = format!(
+                "SELECT CAST(\"{column}\" AS TEXT), COUNT(*) AS n FROM \"{table}\"
+                    WHERE \"{column}\" IS NOT NULL
+                    GROUP BY \"{column}\" ORDER BY n DESC LIMIT {MOST_COMMON_VALUES}"
+            );
+            let rows = transaction
+                .fetch_all(sqlx::query(&sql))
+                .await
+                .with_context(context)?;
+            let most_common = rows
+                .iter()
+                .map(|row| (row.get::<String, _>(0), row.get::<i64, _>(1) as u64))
+                .collect();
+
+            column_stats.push(ColumnStats {
+                name: field.name.clone(),
+                distinct_count,
+                null_count,
+                most_common,
+            });
+        }
+    }
+    QueryEngine::commit_transaction(transaction).await?;
+
+    Ok(EntityStats {
+        row_count,
+        columns: column_stats,
+    })
+}
@@ -0,0 +1,72 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Content checksums of entities, to check that data survived a copy, a
+//! backend migration or a backup restore.
+//!
+//! Rows are hashed as the JSON values endpoints see, not as the database
+//! stores them, so checksums are comparable across backends. The checksum
+//! of an entity is the sum of the hashes of its rows, which doesn't depend
+//! on the order rows are read in.
+
+use crate::datastore::query::QueryPlan;
+use crate::datastore::QueryEngine;
+use crate::types::{Entity, TypeId};
+use anyhow::{Context, Result};
+use deno_core::futures::StreamExt;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+pub struct EntityChecksum {
+    pub row_count: u64,
+    /// Hexadecimal checksum of the contents of all rows.
+    pub checksum: String,
+}
+
+/// Computes the row count and checksum of `ty`. With `ignore_ids`, IDs and
+/// references to other objects are left out, so that copies made with new
+/// IDs match their originals.
+pub async fn entity_checksum(
+    engine: Arc<QueryEngine>,
+    ty: &Entity,
+    ignore_ids: bool,
+) -> Result<EntityChecksum> {
+    let mut fields: Vec<_> = ty
+        .all_fields()
+        .filter(|field| {
+            !ignore_ids
+                || !matches!(
+                    field.type_id,
+                    TypeId::Id | TypeId::Entity { .. } | TypeId::Union { .. }
+                )
+        })
+        .map(|field| field.name.as_str())
+        .collect();
+    fields.sort_unstable();
+
+    let tr = engine.clone().begin_transaction_static().await?;
+    let mut rows = engine.query(tr.clone(), QueryPlan::from_type(ty))?;
+    let mut row_count = 0;
+    let mut sum = 0u128;
+    while let Some(row) = rows.next().await {
+        let row = row.with_context(|| format!("failed to read entity {}", ty.name()))?;
+        let mut hasher = Sha256::new();
+        for field in &fields {
+            let value = row.get(*field).unwrap_or(&serde_json::Value::Null);
+            hasher.update(field.as_bytes());
+            hasher.update([0]);
+            hasher.update(value.to_string().as_bytes());
+            hasher.update([0]);
+        }
+        let mut hash = [0; 16];
+        hash.copy_from_slice(&hasher.finalize()[..16]);
+        sum = sum.wrapping_add(u128::from_be_bytes(hash));
+        row_count += 1;
+    }
+    drop(rows);
+    QueryEngine::commit_transaction_static(tr).await?;
+
+    Ok(EntityChecksum {
+        row_count,
+        checksum: format!("{:032x}", sum),
+    })
+}
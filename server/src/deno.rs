@@ -3,21 +3,44 @@
 use crate::api::ApiService;
 use crate::api::{response_template, Body, RequestPath};
 use crate::auth::get_username_from_id;
+use crate::auth::is_auth_entity_name;
+use crate::auth::LOGIN_ROUTE;
+use crate::auth::SCIM_PATH_PREFIX;
+use crate::auth_provider::{self, AuthProvider, Authentication, Principal};
+use crate::blobs;
 use crate::datastore::crud;
 use crate::datastore::engine::extract_transaction;
 use crate::datastore::engine::IdTree;
+use crate::datastore::engine::SqlWithArguments;
 use crate::datastore::engine::TransactionStatic;
-use crate::datastore::engine::{QueryResults, ResultRow};
+use crate::datastore::engine::{Page, QueryResults, ResultRow};
 use crate::datastore::expr::Expr;
-use crate::datastore::query::{Mutation, QueryOpChain, QueryPlan, RequestContext};
+use crate::datastore::prefetch::{self, Batched};
+use crate::datastore::query::{
+    Aggregation, Mutation, QueryOpChain, QueryPlan, RequestContext, SqlValue,
+};
 use crate::datastore::MetaService;
 use crate::datastore::QueryEngine;
-use crate::policies::Policies;
+use crate::inflight::{self, RequestToken};
+use crate::jobs;
+use crate::memory;
+use crate::notifications::{notify, EventKind};
+use crate::policies::{self, Policies, TenantSource, VersionPolicy};
+use crate::profiling;
+use crate::quotas;
 use crate::rcmut::RcMut;
+use crate::read_only;
+use crate::response_cache;
+use crate::tasks;
+use crate::types::Entity;
+use crate::types::ObjectType;
 use crate::types::Type;
+use crate::types::TypeId;
 use crate::types::TypeSystem;
 use crate::types::TypeSystemError;
 use crate::vecmap::VecMap;
+use crate::webhooks::{self, WebhookEvent};
+use crate::websocket::{self, Message, SocketId};
 use crate::JsonObject;
 use anyhow::{anyhow, Context as AnyhowContext, Result};
 use api::SOURCES_JS;
@@ -34,6 +57,8 @@ use deno_core::CancelFuture;
 use deno_core::CancelHandle;
 use deno_core::Extension;
 use deno_core::JsRuntime;
+use deno_core::JsRuntimeInspector;
+use deno_core::LocalInspectorSession;
 use deno_core::ModuleSource;
 use deno_core::ModuleSourceFuture;
 use deno_core::ModuleSpecifier;
@@ -64,6 +89,8 @@ use once_cell::unsync::OnceCell;
 use pin_project::pin_project;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
+use sqlx::any::Any;
+use sqlx::{Row, Transaction};
 use std::cell::Cell;
 use std::cell::RefCell;
 use std::collections::HashMap;
@@ -81,18 +108,20 @@ use std::rc::Weak;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::task::{Context, Poll};
+use std::time::Instant;
 use utils::without_extension;
 
 enum WorkerMsg {
     SetMeta(MetaService),
-    HandleRequest(Request<hyper::Body>),
-    HandleEvent(),
+    HandleRequest(Request<hyper::Body>, RequestToken),
+    HandleEvent(RequestToken),
     SetTypeSystem(TypeSystem),
     RemoveTypeVersion(String),
     SetQueryEngine(Arc<QueryEngine>),
     SetPolicies(Policies),
     MutatePolicies(Box<dyn FnOnce(&mut Policies) + Send>),
     SetCurrentSecrets(JsonObject),
+    SetQueryBudget(QueryBudget),
 }
 
 /// A v8 isolate doesn't want to be moved between or used from
@@ -118,10 +147,16 @@ struct DenoService {
     import_endpoints: v8::Global<v8::Function>,
     activate_endpoint: v8::Global<v8::Function>,
     activate_event_handler: v8::Global<v8::Function>,
+    activate_job: v8::Global<v8::Function>,
+    activate_task: v8::Global<v8::Function>,
     call_handler: v8::Global<v8::Function>,
     call_event_handler: v8::Global<v8::Function>,
+    call_socket_handler: v8::Global<v8::Function>,
+    call_job_handler: v8::Global<v8::Function>,
+    call_task_handler: v8::Global<v8::Function>,
     read_worker_channel: v8::Global<v8::Function>,
     end_of_request: v8::Global<v8::Function>,
+    run_unit_tests: v8::Global<v8::Function>,
 
     to_worker: Sender<WorkerMsg>,
     worker_channel_id: u32,
@@ -204,19 +239,40 @@ fn build_extensions() -> Vec<Extension> {
             op_format_file_name::decl(),
             op_chisel_read_body::decl(),
             op_chisel_store::decl(),
+            op_chisel_store_many::decl(),
+            op_chisel_update::decl(),
+            op_chisel_upsert::decl(),
+            op_chisel_coverage_start::decl(),
+            op_chisel_coverage_take::decl(),
+            op_chisel_outbox_enqueue::decl(),
+            op_chisel_task_enqueue::decl(),
+            op_chisel_file_store::decl(),
+            op_chisel_file_read::decl(),
+            op_chisel_file_delete::decl(),
             op_chisel_entity_delete::decl(),
             op_chisel_crud_delete::decl(),
             op_chisel_get_secret::decl(),
             op_chisel_crud_query::decl(),
             op_chisel_relational_query_create::decl(),
-            op_chisel_query_next::decl(),
+            op_chisel_relational_query_paginated::decl(),
+            op_chisel_relational_aggregate::decl(),
+            op_chisel_filter_fallback::decl(),
+            op_chisel_query_next_batch::decl(),
             op_chisel_commit_transaction::decl(),
             op_chisel_rollback_transaction::decl(),
+            op_chisel_begin_nested_transaction::decl(),
+            op_chisel_commit_nested_transaction::decl(),
+            op_chisel_rollback_nested_transaction::decl(),
             op_chisel_create_transaction::decl(),
             op_chisel_init_worker::decl(),
             op_chisel_read_worker_channel::decl(),
             op_chisel_start_request::decl(),
+            op_chisel_check_fetch::decl(),
+            op_chisel_end_fetch::decl(),
+            op_chisel_authenticated::decl(),
             op_chisel_start_event_handler::decl(),
+            op_chisel_socket_send::decl(),
+            op_chisel_socket_close::decl(),
         ])
         .build()]
 }
@@ -266,13 +322,20 @@ fn create_web_worker(
             compiled_wasm_module_store: None,
             npm_resolver: None,
         };
-        WebWorker::bootstrap_from_options(
+        let (mut worker, handle) = WebWorker::bootstrap_from_options(
             args.name,
             args.permissions,
             args.main_module,
             args.worker_id,
             options,
-        )
+        );
+        // The callback runs in the thread of the new worker, which is where
+        // its ops run too.
+        if maybe_inspector_server.is_some() {
+            let inspector = worker.js_runtime.inspector();
+            WORKER_INSPECTOR.with(|i| *i.borrow_mut() = Some(inspector));
+        }
+        (worker, handle)
     })
 }
 
@@ -284,10 +347,18 @@ static GLOBAL_WORKER_CHANNELS: Lazy<Mutex<GlobalChannels>> =
 
 thread_local! {
      static WORKER_CHANNEL: OnceCell<Channel> = OnceCell::new();
+     /// Inspector of the web worker of this thread, if chiseld activated it.
+     static WORKER_INSPECTOR: RefCell<Option<Rc<RefCell<JsRuntimeInspector>>>> = RefCell::new(None);
+     /// Session collecting the code coverage of unit tests.
+     static COVERAGE_SESSION: RefCell<Option<LocalInspectorSession>> = RefCell::new(None);
 }
 
 impl DenoService {
-    pub async fn new(inspect: bool, inspect_brk: bool) -> (Self, v8::Global<v8::Function>) {
+    pub async fn new(
+        inspect: bool,
+        inspect_brk: bool,
+        coverage: bool,
+    ) -> (Self, v8::Global<v8::Function>) {
         let web_worker_preload_module_cb =
             Arc::new(|worker| LocalFutureObj::new(Box::new(future::ready(Ok(worker)))));
         let web_worker_pre_execute_module_cb =
@@ -303,6 +374,12 @@ impl DenoService {
         if inspect || inspect_brk {
             let addr: SocketAddr = "127.0.0.1:9229".parse().unwrap();
             inspector = Some(Arc::new(InspectorServer::new(addr, "chisel".to_string())));
+        } else if coverage {
+            // Coverage is collected through the inspector, which only exists
+            // with an inspector server. Nothing needs to connect to it, so any
+            // free local port will do.
+            let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+            inspector = Some(Arc::new(InspectorServer::new(addr, "chisel".to_string())));
         }
 
         let bootstrap = BootstrapOptions {
@@ -380,11 +457,17 @@ impl DenoService {
             import_endpoints,
             activate_endpoint,
             activate_event_handler,
+            activate_job,
+            activate_task,
             call_handler,
             call_event_handler,
+            call_socket_handler,
+            call_job_handler,
+            call_task_handler,
             init_worker,
             read_worker_channel,
             end_of_request,
+            run_unit_tests,
         ) = {
             let runtime = &mut worker.js_runtime;
             let promise = runtime
@@ -402,12 +485,27 @@ impl DenoService {
             let activate_event_handler: v8::Local<v8::Function> =
                 get_member(module, scope, "activateEventHandler").unwrap();
             let activate_event_handler = v8::Global::new(scope, activate_event_handler);
+            let activate_job: v8::Local<v8::Function> =
+                get_member(module, scope, "activateJob").unwrap();
+            let activate_job = v8::Global::new(scope, activate_job);
+            let activate_task: v8::Local<v8::Function> =
+                get_member(module, scope, "activateTask").unwrap();
+            let activate_task = v8::Global::new(scope, activate_task);
             let call_handler: v8::Local<v8::Function> =
                 get_member(module, scope, "callHandler").unwrap();
             let call_handler = v8::Global::new(scope, call_handler);
             let call_event_handler: v8::Local<v8::Function> =
                 get_member(module, scope, "callEventHandler").unwrap();
             let call_event_handler = v8::Global::new(scope, call_event_handler);
+            let call_socket_handler: v8::Local<v8::Function> =
+                get_member(module, scope, "callSocketHandler").unwrap();
+            let call_socket_handler = v8::Global::new(scope, call_socket_handler);
+            let call_job_handler: v8::Local<v8::Function> =
+                get_member(module, scope, "callJobHandler").unwrap();
+            let call_job_handler = v8::Global::new(scope, call_job_handler);
+            let call_task_handler: v8::Local<v8::Function> =
+                get_member(module, scope, "callTaskHandler").unwrap();
+            let call_task_handler = v8::Global::new(scope, call_task_handler);
             let init_worker: v8::Local<v8::Function> =
                 get_member(module, scope, "initWorker").unwrap();
             let init_worker = v8::Global::new(scope, init_worker);
@@ -417,16 +515,25 @@ impl DenoService {
             let end_of_request: v8::Local<v8::Function> =
                 get_member(module, scope, "endOfRequest").unwrap();
             let end_of_request = v8::Global::new(scope, end_of_request);
+            let run_unit_tests: v8::Local<v8::Function> =
+                get_member(module, scope, "runUnitTests").unwrap();
+            let run_unit_tests = v8::Global::new(scope, run_unit_tests);
 
             (
                 import_endpoints,
                 activate_endpoint,
                 activate_event_handler,
+                activate_job,
+                activate_task,
                 call_handler,
                 call_event_handler,
+                call_socket_handler,
+                call_job_handler,
+                call_task_handler,
                 init_worker,
                 read_worker_channel,
                 end_of_request,
+                run_unit_tests,
             )
         };
 
@@ -442,12 +549,18 @@ impl DenoService {
                 import_endpoints,
                 activate_endpoint,
                 activate_event_handler,
+                activate_job,
+                activate_task,
                 call_handler,
                 call_event_handler,
+                call_socket_handler,
+                call_job_handler,
+                call_task_handler,
                 to_worker: to_worker_sender,
                 worker_channel_id,
                 read_worker_channel,
                 end_of_request,
+                run_unit_tests,
             },
             init_worker,
         )
@@ -525,6 +638,15 @@ struct ChiselRequestContext {
     /// Current user ID.
     #[serde(rename = "userId")]
     user_id: Option<String>,
+    /// Tenant of the request, in multi-tenant versions.
+    tenant: Option<String>,
+}
+
+impl ChiselRequestContext {
+    /// The path of the endpoint handling the request, including its version.
+    fn endpoint(&self) -> String {
+        format!("/{}{}", self.api_version, self.path)
+    }
 }
 
 impl RequestContext<'_> {
@@ -538,6 +660,7 @@ impl RequestContext<'_> {
             ts,
             api_version: context.api_version,
             user_id: context.user_id,
+            tenant: context.tenant,
             path: context.path,
             headers: context.headers,
         }
@@ -550,8 +673,169 @@ struct StoreContent {
     value: JsonObject,
 }
 
+/// Whether the route at `path` of `api_version` may write the auth entities:
+/// the routes of `__chiselstrike` under `/auth/`, and the login route of
+/// every version.
 fn is_auth_path(api_version: &str, path: &str) -> bool {
-    api_version == "__chiselstrike" && path.starts_with("/auth/")
+    (api_version == "__chiselstrike" && path.starts_with("/auth/")) || path == LOGIN_ROUTE
+}
+
+/// An object scoped to the tenant of the request, which must own it to
+/// overwrite it, or have room in its quota to add it.
+struct TenantScoped {
+    table: String,
+    /// The column of the tenant field.
+    column: String,
+    id: Option<String>,
+}
+
+/// Sets the tenant fields of `value`, an object of type `ty`, and of the
+/// objects nested in it, to the tenant of the request. The objects with tenant
+/// fields are added to `scoped`.
+fn scope_to_tenant(
+    policies: &Policies,
+    ts: &TypeSystem,
+    c: &ChiselRequestContext,
+    ty: &ObjectType,
+    value: &mut JsonObject,
+    scoped: &mut Vec<TenantScoped>,
+) -> Result<()> {
+    let field_policies = policies.make_field_policies(&c.user_id, &c.tenant, &c.path, ty);
+    for field in ty.all_fields() {
+        if field_policies.match_tenant.contains(&field.name) {
+            let tenant = c.tenant.as_ref().with_context(|| {
+                format!("Cannot save into type {} without a tenant.", ty.name())
+            })?;
+            match value.get(&field.name) {
+                // Unset fields are filled in.
+                None | Some(serde_json::Value::Null) => {}
+                Some(serde_json::Value::String(v)) if v.is_empty() || v == tenant => {}
+                Some(_) => anyhow::bail!(
+                    "Cannot save into type {}: field {} must be the tenant of the request.",
+                    ty.name(),
+                    field.name
+                ),
+            }
+            value.insert(field.name.clone(), tenant.clone().into());
+            scoped.push(TenantScoped {
+                table: ty.backing_table().to_owned(),
+                column: field.column_name().to_owned(),
+                id: value
+                    .get("id")
+                    .and_then(|id| id.as_str())
+                    .map(str::to_owned),
+            });
+        }
+        if let Type::Entity(nested_ty) = ts.get(&field.type_id)? {
+            if nested_ty.is_auth() {
+                continue;
+            }
+            if let Some(serde_json::Value::Object(nested)) = value.get_mut(&field.name) {
+                scope_to_tenant(policies, ts, c, &nested_ty, nested, scoped)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fails unless the stored objects in `scoped` belong to the tenant of the
+/// request, and the new ones fit in its row quota.
+async fn check_tenant_scoped(
+    transaction: &mut Transaction<'_, Any>,
+    scoped: &[TenantScoped],
+    c: &ChiselRequestContext,
+    rows_quota: Option<u64>,
+) -> Result<()> {
+    let tenant = match &c.tenant {
+        Some(tenant) => tenant,
+        None => return Ok(()),
+    };
+    // New objects not in the database yet, by table.
+    let mut pending = HashMap::<&str, u64>::new();
+    for o in scoped {
+        if let Some(id) = &o.id {
+            let query = SqlWithArguments {
+                sql: format!(
+                    "SELECT \"{}\" AS tenant FROM \"{}\" WHERE id = $1",
+                    o.column, o.table
+                ),
+                args: vec![SqlValue::String(id.clone())],
+            };
+            let row = QueryEngine::fetch_optional_with_transaction(transaction, query).await?;
+            if let Some(row) = row {
+                let stored: Option<String> = row.try_get("tenant")?;
+                anyhow::ensure!(
+                    stored.as_ref() == Some(tenant),
+                    "Cannot overwrite object {}: it belongs to another tenant.",
+                    id
+                );
+                continue;
+            }
+        }
+        if let Some(limit) = rows_quota {
+            let query = SqlWithArguments {
+                sql: format!(
+                    "SELECT COUNT(*) AS count FROM \"{}\" WHERE \"{}\" = $1",
+                    o.table, o.column
+                ),
+                args: vec![SqlValue::String(tenant.clone())],
+            };
+            let row = QueryEngine::fetch_optional_with_transaction(transaction, query).await?;
+            let count: i64 = row.map_or(Ok(0), |row| row.try_get("count"))?;
+            let pending = pending.entry(&o.table).or_default();
+            if count as u64 + *pending >= limit {
+                quotas::record_rejected_row(&c.api_version, tenant);
+                anyhow::bail!(
+                    "Tenant {} is over its quota of {} rows of {}.",
+                    tenant,
+                    limit,
+                    o.table
+                );
+            }
+            *pending += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Looks up the entity `type_name`, which the request is about to save into.
+fn entity_to_store(state: &OpState, type_name: &str, c: &ChiselRequestContext) -> Result<Entity> {
+    let ty = match current_type_system(state).lookup_type(type_name, &c.api_version) {
+        Ok(Type::Entity(ty)) => ty,
+        _ => anyhow::bail!("Cannot save into type {}.", type_name),
+    };
+    if ty.is_auth() && !is_auth_path(&c.api_version, &c.path) {
+        anyhow::bail!("Cannot save into type {}.", type_name);
+    }
+    check_writable(state, ty.name(), &c.api_version)?;
+    Ok(ty)
+}
+
+/// Scopes `values`, objects of type `ty`, to the tenant of the request, and
+/// returns the objects to check with `check_tenant_scoped()` and the row
+/// quota of the tenant.
+fn scope_values_to_tenant<'a>(
+    state: &OpState,
+    c: &ChiselRequestContext,
+    ty: &ObjectType,
+    values: impl IntoIterator<Item = &'a mut JsonObject>,
+) -> Result<(Vec<TenantScoped>, Option<u64>)> {
+    let policies = current_policies(state);
+    let ts = current_type_system(state);
+    let mut scoped = vec![];
+    for value in values {
+        scope_to_tenant(policies, ts, c, ty, value, &mut scoped)?;
+    }
+    let rows_quota = match &c.tenant {
+        Some(tenant) if !scoped.is_empty() => {
+            version_policy(state, &c.api_version, &c.path)?
+                .tenant_quotas
+                .get(tenant)
+                .rows
+        }
+        _ => None,
+    };
+    Ok((scoped, rows_quota))
 }
 
 #[op]
@@ -560,36 +844,420 @@ async fn op_chisel_store(
     content: StoreContent,
     c: ChiselRequestContext,
 ) -> Result<IdTree> {
-    let type_name = &content.name;
-    let value = &content.value;
+    let mut value = content.value;
 
-    let (query_engine, ty) = {
+    let (query_engine, ty, scoped, rows_quota) = {
         let state = state.borrow();
-        let ty = match current_type_system(&state).lookup_type(type_name, &c.api_version) {
-            Ok(Type::Entity(ty)) => ty,
-            _ => anyhow::bail!("Cannot save into type {}.", type_name),
-        };
-        if ty.is_auth() && !is_auth_path(&c.api_version, &c.path) {
-            anyhow::bail!("Cannot save into type {}.", type_name);
-        }
+        let ty = entity_to_store(&state, &content.name, &c)?;
+        let (scoped, rows_quota) = scope_values_to_tenant(&state, &c, &ty, [&mut value])?;
+        (query_engine_arc(&state), ty, scoped, rows_quota)
+    };
+    let transaction = {
+        let state = state.borrow();
+        current_transaction(&state)
+    };
+    let mut transaction = transaction.lock().await;
+    check_tenant_scoped(transaction.deref_mut(), &scoped, &c, rows_quota).await?;
+
+    let sql = format!("INSERT INTO \"{}\" ...", ty.backing_table());
+    let request = begin_statement(&state.borrow(), Some(&sql))?;
+    let ids = {
+        let state = state.borrow();
+        let ts = current_type_system(&state);
+        query_engine.add_row(&ty, &value, Some(transaction.deref_mut()), ts)
+    };
+    let ids = cancellable(&request, ids).await?;
+    // Storing an object that has an id overwrites the stored one.
+    let event = match value.contains_key("id") {
+        true => WebhookEvent::Update,
+        false => WebhookEvent::Create,
+    };
+    value.insert("id".into(), ids.id.clone().into());
+    let deliveries =
+        webhook_deliveries(&state.borrow(), &c.api_version, ty.name(), event, &[value])?;
+    webhooks::enqueue(transaction.deref_mut(), &c.api_version, deliveries).await?;
+    let mut state = state.borrow_mut();
+    mark_written(&mut state, ty.name(), &c.api_version);
+    record_statements(&mut state, &c.endpoint(), ids.object_count(), || Some(sql))?;
+    Ok(ids)
+}
+
+#[derive(Deserialize)]
+struct StoreManyContent {
+    name: String,
+    values: Vec<JsonObject>,
+}
+
+#[op]
+async fn op_chisel_store_many(
+    state: Rc<RefCell<OpState>>,
+    content: StoreManyContent,
+    c: ChiselRequestContext,
+) -> Result<Vec<IdTree>> {
+    let mut values = content.values;
 
-        let query_engine = query_engine_arc(&state);
-        (query_engine, ty)
+    let (query_engine, ty, scoped, rows_quota) = {
+        let state = state.borrow();
+        let ty = entity_to_store(&state, &content.name, &c)?;
+        let (scoped, rows_quota) = scope_values_to_tenant(&state, &c, &ty, &mut values)?;
+        (query_engine_arc(&state), ty, scoped, rows_quota)
     };
     let transaction = {
         let state = state.borrow();
         current_transaction(&state)
     };
     let mut transaction = transaction.lock().await;
+    check_tenant_scoped(transaction.deref_mut(), &scoped, &c, rows_quota).await?;
 
-    {
+    let sql = format!("INSERT INTO \"{}\" ...", ty.backing_table());
+    let request = begin_statement(&state.borrow(), Some(&sql))?;
+    let values: Vec<_> = values.into_iter().map(serde_json::Value::Object).collect();
+    let ids = {
         let state = state.borrow();
         let ts = current_type_system(&state);
-        query_engine.add_row(&ty, value, Some(transaction.deref_mut()), ts)
+        query_engine.add_rows(&ty, &values, Some(transaction.deref_mut()), ts)
+    };
+    let ids = cancellable(&request, ids).await?;
+    if has_webhooks(
+        &state.borrow(),
+        &c.api_version,
+        ty.name(),
+        WebhookEvent::Create,
+    ) {
+        let objects: Vec<_> = values
+            .into_iter()
+            .zip(&ids)
+            .filter_map(|(value, ids)| match value {
+                serde_json::Value::Object(mut object) => {
+                    object.insert("id".into(), ids.id.clone().into());
+                    Some(object)
+                }
+                _ => None,
+            })
+            .collect();
+        let deliveries = webhook_deliveries(
+            &state.borrow(),
+            &c.api_version,
+            ty.name(),
+            WebhookEvent::Create,
+            &objects,
+        )?;
+        webhooks::enqueue(transaction.deref_mut(), &c.api_version, deliveries).await?;
+    }
+    let mut state = state.borrow_mut();
+    mark_written(&mut state, ty.name(), &c.api_version);
+    let rows = ids.iter().map(IdTree::object_count).sum();
+    record_statements(&mut state, &c.endpoint(), rows, || Some(sql))?;
+    Ok(ids)
+}
+
+#[derive(Deserialize)]
+struct UpdateContent {
+    name: String,
+    id: String,
+    changes: JsonObject,
+}
+
+#[op]
+async fn op_chisel_update(
+    state: Rc<RefCell<OpState>>,
+    content: UpdateContent,
+    c: ChiselRequestContext,
+) -> Result<bool> {
+    let mut changes = content.changes;
+
+    let (query_engine, ty, scoped) = {
+        let state = state.borrow();
+        let ty = entity_to_store(&state, &content.name, &c)?;
+        // The id makes the tenant check apply to the stored object.
+        changes.insert("id".into(), content.id.clone().into());
+        let (scoped, _) = scope_values_to_tenant(&state, &c, &ty, [&mut changes])?;
+        changes.remove("id");
+        (query_engine_arc(&state), ty, scoped)
+    };
+    let transaction = {
+        let state = state.borrow();
+        current_transaction(&state)
+    };
+    let mut transaction = transaction.lock().await;
+    // Updates don't add rows, so they aren't held to the row quota.
+    check_tenant_scoped(transaction.deref_mut(), &scoped, &c, None).await?;
+
+    let sql = format!("UPDATE \"{}\" ...", ty.backing_table());
+    let request = begin_statement(&state.borrow(), Some(&sql))?;
+    let updated = {
+        let state = state.borrow();
+        let ts = current_type_system(&state);
+        query_engine.update_row(&ty, &content.id, &changes, transaction.deref_mut(), ts)
+    };
+    let updated = cancellable(&request, updated).await?;
+    if updated {
+        changes.insert("id".into(), content.id.into());
+        let deliveries = webhook_deliveries(
+            &state.borrow(),
+            &c.api_version,
+            ty.name(),
+            WebhookEvent::Update,
+            &[changes],
+        )?;
+        webhooks::enqueue(transaction.deref_mut(), &c.api_version, deliveries).await?;
+    }
+    let mut state = state.borrow_mut();
+    mark_written(&mut state, ty.name(), &c.api_version);
+    record_statements(&mut state, &c.endpoint(), updated as usize, || Some(sql))?;
+    Ok(updated)
+}
+
+#[derive(Deserialize)]
+struct UpsertContent {
+    name: String,
+    restrictions: JsonObject,
+    create: JsonObject,
+    update: JsonObject,
+}
+
+/// Returns the field an upsert of type `ty` can be keyed on: the only field
+/// restricted by `content`, which must be unique and have the restricted value
+/// in the object to create. Types with many-to-many relations have none.
+fn upsert_key(ty: &ObjectType, content: &UpsertContent) -> Option<String> {
+    if ty.many_to_many_fields().next().is_some() {
+        return None;
+    }
+    let restrictions: Vec<_> = content
+        .restrictions
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .collect();
+    let (name, value) = match restrictions[..] {
+        [restriction] => restriction,
+        _ => return None,
+    };
+    let field = ty.all_fields().find(|f| &f.name == name)?;
+    if !field.is_unique && field.type_id != TypeId::Id {
+        return None;
+    }
+    if content.create.get(name) != Some(value) {
+        return None;
+    }
+    Some(name.clone())
+}
+
+/// Creates the object in `content`, or updates the stored one matching its
+/// restrictions, with a single INSERT ... ON CONFLICT. Returns the id of the
+/// object, or None if the restrictions don't name a unique field, or if
+/// webhooks need to know whether the object was created or updated, for the
+/// caller to look the object up instead.
+#[op]
+async fn op_chisel_upsert(
+    state: Rc<RefCell<OpState>>,
+    content: UpsertContent,
+    c: ChiselRequestContext,
+) -> Result<Option<String>> {
+    let (query_engine, ty, key) = {
+        let state = state.borrow();
+        let ty = entity_to_store(&state, &content.name, &c)?;
+        let key = match upsert_key(&ty, &content) {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        if has_webhooks(&state, &c.api_version, ty.name(), WebhookEvent::Create)
+            || has_webhooks(&state, &c.api_version, ty.name(), WebhookEvent::Update)
+        {
+            return Ok(None);
+        }
+        (query_engine_arc(&state), ty, key)
+    };
+    let UpsertContent {
+        mut create,
+        mut update,
+        ..
+    } = content;
+    let (scoped, rows_quota, guard) = {
+        let state = state.borrow();
+        let (scoped, rows_quota) = scope_values_to_tenant(&state, &c, &ty, [&mut create])?;
+        // Fails if the update would move the object to another tenant.
+        scope_values_to_tenant(&state, &c, &ty, [&mut update])?;
+        // Only objects of the tenant of the request may be updated.
+        let guard = current_policies(&state)
+            .make_field_policies(&c.user_id, &c.tenant, &c.path, &ty)
+            .match_tenant
+            .into_iter()
+            .next();
+        (scoped, rows_quota, guard)
+    };
+    let transaction = {
+        let state = state.borrow();
+        current_transaction(&state)
+    };
+    let mut transaction = transaction.lock().await;
+    // The object may be new, so the upsert is held to the row quota.
+    check_tenant_scoped(transaction.deref_mut(), &scoped, &c, rows_quota).await?;
+
+    let sql = format!(
+        "INSERT INTO \"{}\" ... ON CONFLICT (\"{}\") DO UPDATE ...",
+        ty.backing_table(),
+        key
+    );
+    let request = begin_statement(&state.borrow(), Some(&sql))?;
+    let id = {
+        let state = state.borrow();
+        let ts = current_type_system(&state);
+        query_engine.upsert_row(
+            &ty,
+            &key,
+            &create,
+            &update,
+            guard.as_deref(),
+            transaction.deref_mut(),
+            ts,
+        )
+    };
+    let id = cancellable(&request, id).await?.with_context(|| {
+        format!(
+            "Cannot overwrite object of type {}: it belongs to another tenant.",
+            ty.name()
+        )
+    })?;
+    let mut state = state.borrow_mut();
+    mark_written(&mut state, ty.name(), &c.api_version);
+    record_statements(&mut state, &c.endpoint(), 1, || Some(sql))?;
+    Ok(Some(id))
+}
+
+/// Starts collecting the precise block coverage of the code run by this
+/// worker, as `deno test --coverage` does.
+#[op]
+async fn op_chisel_coverage_start() -> Result<()> {
+    let inspector = WORKER_INSPECTOR.with(|i| i.borrow().clone()).context(
+        "Collecting coverage needs chiseld to run with --coverage (`chisel dev --coverage`)",
+    )?;
+    let mut session = inspector.borrow().create_local_session();
+    session.post_message::<()>("Profiler.enable", None).await?;
+    session
+        .post_message(
+            "Profiler.startPreciseCoverage",
+            Some(serde_json::json!({"callCount": true, "detailed": true})),
+        )
+        .await?;
+    COVERAGE_SESSION.with(|s| *s.borrow_mut() = Some(session));
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ScriptCoverage {
+    path: String,
+    functions: serde_json::Value,
+}
+
+/// Stops collecting coverage, and returns the coverage of the scripts whose
+/// URL starts with `prefix`, by their path after it.
+#[op]
+async fn op_chisel_coverage_take(prefix: String) -> Result<Vec<ScriptCoverage>> {
+    let mut session = COVERAGE_SESSION
+        .with(|s| s.borrow_mut().take())
+        .context("Coverage is not being collected")?;
+    let mut coverage = session
+        .post_message::<()>("Profiler.takePreciseCoverage", None)
+        .await?;
+    session
+        .post_message::<()>("Profiler.stopPreciseCoverage", None)
+        .await?;
+    session.post_message::<()>("Profiler.disable", None).await?;
+
+    let mut scripts = vec![];
+    if let serde_json::Value::Array(results) = coverage["result"].take() {
+        for mut script in results {
+            let path = match script["url"]
+                .as_str()
+                .and_then(|url| url.strip_prefix(&prefix))
+            {
+                Some(path) => path.to_owned(),
+                None => continue,
+            };
+            scripts.push(ScriptCoverage {
+                path,
+                functions: script["functions"].take(),
+            });
+        }
     }
+    Ok(scripts)
+}
+
+#[derive(Deserialize)]
+struct OutboxMessage {
+    target: String,
+    payload: String,
+}
+
+#[op]
+async fn op_chisel_outbox_enqueue(
+    state: Rc<RefCell<OpState>>,
+    message: OutboxMessage,
+    c: ChiselRequestContext,
+) -> Result<()> {
+    let transaction = {
+        let state = state.borrow();
+        current_transaction(&state)
+    };
+    let mut transaction = transaction.lock().await;
+    crate::outbox::enqueue(
+        transaction.deref_mut(),
+        &c.api_version,
+        &message.target,
+        &message.payload,
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+struct Task {
+    name: String,
+    payload: String,
+}
+
+#[op]
+async fn op_chisel_task_enqueue(
+    state: Rc<RefCell<OpState>>,
+    task: Task,
+    c: ChiselRequestContext,
+) -> Result<()> {
+    let transaction = {
+        let state = state.borrow();
+        current_transaction(&state)
+    };
+    let mut transaction = transaction.lock().await;
+    tasks::enqueue(
+        transaction.deref_mut(),
+        &c.api_version,
+        &task.name,
+        &task.payload,
+    )
     .await
 }
 
+#[derive(Deserialize)]
+struct FileContent {
+    name: String,
+    #[serde(rename = "type")]
+    media_type: String,
+    content: ZeroCopyBuf,
+}
+
+#[op]
+async fn op_chisel_file_store(file: FileContent) -> Result<blobs::FileMetadata> {
+    blobs::put(file.name, file.media_type, file.content.to_vec()).await
+}
+
+#[op]
+async fn op_chisel_file_read(key: String) -> Result<ZeroCopyBuf> {
+    Ok(blobs::get(&key).await?.into())
+}
+
+#[op]
+async fn op_chisel_file_delete(key: String) -> Result<()> {
+    blobs::delete(&key).await
+}
+
 #[derive(Deserialize)]
 struct DeleteParams {
     #[serde(rename = "typeName")]
@@ -603,7 +1271,10 @@ async fn op_chisel_entity_delete(
     state: Rc<RefCell<OpState>>,
     params: DeleteParams,
     context: ChiselRequestContext,
-) -> Result<()> {
+) -> Result<u64> {
+    let endpoint = context.endpoint();
+    let context_version = context.api_version.clone();
+    check_writable(&state.borrow(), &params.type_name, &context_version)?;
     let mutation = {
         let state = state.borrow_mut();
         Mutation::delete_from_expr(
@@ -620,16 +1291,42 @@ async fn op_chisel_entity_delete(
         )?
     };
     let query_engine = query_engine_arc(&state.borrow());
+    let sql = query_engine.mutation_sql(&mutation).ok();
+    let request = begin_statement(&state.borrow(), sql.as_deref())?;
+    record_statements(&mut state.borrow_mut(), &endpoint, 1, || sql)?;
     let transaction = {
         let state = state.borrow();
         current_transaction(&state)
     };
     let mut transaction = transaction.lock().await;
-    query_engine
-        .mutate_with_transaction(mutation, &mut transaction)
-        .await?;
+    let notify = has_webhooks(
+        &state.borrow(),
+        &context_version,
+        &params.type_name,
+        WebhookEvent::Delete,
+    );
+    // The webhooks are told the ids of the objects, so find them first.
+    let deleted_ids = match notify {
+        true => Some(
+            query_engine
+                .mutation_ids(&mutation, &mut transaction)
+                .await?,
+        ),
+        false => None,
+    };
+    let deleted = cancellable(
+        &request,
+        query_engine.mutate_with_transaction(mutation, &mut transaction),
+    )
+    .await?;
+    if let Some(ids) = deleted_ids {
+        let deliveries =
+            webhook_deletions(&state.borrow(), &context_version, &params.type_name, ids)?;
+        webhooks::enqueue(&mut transaction, &context_version, deliveries).await?;
+    }
+    mark_written(&mut state.borrow_mut(), &params.type_name, &context_version);
 
-    Ok(())
+    Ok(deleted)
 }
 
 #[derive(Deserialize)]
@@ -644,7 +1341,10 @@ async fn op_chisel_crud_delete(
     state: Rc<RefCell<OpState>>,
     params: CrudDeleteParams,
     context: ChiselRequestContext,
-) -> Result<()> {
+) -> Result<u64> {
+    let endpoint = context.endpoint();
+    let api_version = context.api_version.clone();
+    check_writable(&state.borrow(), &params.type_name, &api_version)?;
     let mutation = {
         let state = state.borrow_mut();
         crud::delete_from_url(
@@ -664,22 +1364,42 @@ async fn op_chisel_crud_delete(
         let state = state.borrow();
         query_engine_arc(&state).clone()
     };
+    let sql = query_engine.mutation_sql(&mutation).ok();
+    let request = begin_statement(&state.borrow(), sql.as_deref())?;
+    record_statements(&mut state.borrow_mut(), &endpoint, 1, || sql)?;
 
     let transaction = query_engine.clone().begin_transaction_static().await?;
 
     let mut guard = transaction.lock().await;
-    query_engine
-        .mutate_with_transaction(mutation, &mut guard)
-        .await?;
+    let notify = has_webhooks(
+        &state.borrow(),
+        &api_version,
+        &params.type_name,
+        WebhookEvent::Delete,
+    );
+    let deleted_ids = match notify {
+        true => Some(query_engine.mutation_ids(&mutation, &mut guard).await?),
+        false => None,
+    };
+    let deleted = cancellable(
+        &request,
+        query_engine.mutate_with_transaction(mutation, &mut guard),
+    )
+    .await?;
+    if let Some(ids) = deleted_ids {
+        let deliveries = webhook_deletions(&state.borrow(), &api_version, &params.type_name, ids)?;
+        webhooks::enqueue(&mut guard, &api_version, deliveries).await?;
+    }
 
     drop(guard);
 
     QueryEngine::commit_transaction_static(transaction).await?;
+    response_cache::invalidate(&written_version(&params.type_name, &api_version));
 
-    Ok(())
+    Ok(deleted)
 }
 
-type DbStream = RefCell<QueryResults>;
+type DbStream = RefCell<Batched<QueryResults>>;
 
 struct QueryStreamResource {
     stream: DbStream,
@@ -717,8 +1437,11 @@ async fn op_chisel_crud_query(
     params: crud::QueryParams,
     context: ChiselRequestContext,
 ) -> Result<JsonObject> {
+    let request = begin_statement(&state.borrow(), None)?;
+    record_statements(&mut state.borrow_mut(), &context.endpoint(), 1, || None)?;
+    record_reads(&state.borrow(), &context, &[params.type_name()])?;
     // Contextualize stream creation to prevent state RC borrow living across await
-    {
+    let query = {
         let op_state = &state.borrow();
         let transaction = current_transaction(op_state);
         let query_engine = query_engine_arc(op_state);
@@ -733,8 +1456,8 @@ async fn op_chisel_crud_query(
             query_engine,
             transaction,
         )
-    }
-    .await
+    };
+    cancellable(&request, query).await
 }
 
 #[op]
@@ -743,6 +1466,8 @@ fn op_chisel_relational_query_create(
     op_chain: QueryOpChain,
     context: ChiselRequestContext,
 ) -> Result<ResourceId> {
+    let endpoint = context.endpoint();
+    record_reads(op_state, &context, &op_chain.entity_names())?;
     let query_plan = QueryPlan::from_op_chain(
         &RequestContext::new(
             current_policies(op_state),
@@ -751,33 +1476,125 @@ fn op_chisel_relational_query_create(
         ),
         op_chain,
     )?;
+    let sql = query_engine_arc(op_state).query_sql(&query_plan).ok();
+    begin_statement(op_state, sql.as_deref())?;
+    record_statements(op_state, &endpoint, 1, || sql)?;
     create_query(op_state, query_plan)
 }
 
+#[derive(Deserialize)]
+struct PaginatedQueryParams {
+    #[serde(rename = "opChain")]
+    op_chain: QueryOpChain,
+    cursor: Option<String>,
+    limit: u64,
+}
+
+#[op]
+async fn op_chisel_relational_query_paginated(
+    state: Rc<RefCell<OpState>>,
+    params: PaginatedQueryParams,
+    context: ChiselRequestContext,
+) -> Result<Page> {
+    let request = begin_statement(&state.borrow(), None)?;
+    record_statements(&mut state.borrow_mut(), &context.endpoint(), 1, || None)?;
+    record_reads(&state.borrow(), &context, &params.op_chain.entity_names())?;
+    let page = {
+        let op_state = &state.borrow();
+        let transaction = current_transaction(op_state);
+        let query_engine = query_engine_arc(op_state);
+
+        query_engine.query_relation_paginated(
+            transaction,
+            &RequestContext::new(
+                current_policies(op_state),
+                current_type_system(op_state),
+                context,
+            ),
+            params.op_chain,
+            params.cursor.as_deref(),
+            params.limit,
+        )?
+    };
+    cancellable(&request, page).await
+}
+
+#[derive(Deserialize)]
+struct AggregateParams {
+    #[serde(rename = "opChain")]
+    op_chain: QueryOpChain,
+    aggregation: Aggregation,
+}
+
+/// Computes an aggregation over the rows of a query in the database.
+#[op]
+async fn op_chisel_relational_aggregate(
+    state: Rc<RefCell<OpState>>,
+    params: AggregateParams,
+    context: ChiselRequestContext,
+) -> Result<serde_json::Value> {
+    let request = begin_statement(&state.borrow(), None)?;
+    record_statements(&mut state.borrow_mut(), &context.endpoint(), 1, || None)?;
+    record_reads(&state.borrow(), &context, &params.op_chain.entity_names())?;
+    let aggregate = {
+        let op_state = &state.borrow();
+        let transaction = current_transaction(op_state);
+        let query_engine = query_engine_arc(op_state);
+        let query_plan = QueryPlan::from_op_chain(
+            &RequestContext::new(
+                current_policies(op_state),
+                current_type_system(op_state),
+                context,
+            ),
+            params.op_chain,
+        )?;
+        query_engine.query_aggregate(transaction, query_plan, params.aggregation)?
+    };
+    cancellable(&request, aggregate).await
+}
+
+#[derive(Deserialize)]
+struct FilterFallbackParams {
+    #[serde(rename = "typeName")]
+    type_name: String,
+    expression: String,
+}
+
+/// Reports a filter predicate that couldn't be translated into a database
+/// query, so it is evaluated in the isolate over a full scan of the entity.
+#[op]
+fn op_chisel_filter_fallback(params: FilterFallbackParams, context: ChiselRequestContext) {
+    warn!(
+        "filter fallback: version={} endpoint={} entity={} expression={:?}: \
+        predicate can't be evaluated by the database, falling back to a full table scan",
+        context.api_version, context.path, params.type_name, params.expression
+    );
+}
+
 fn create_query(op_state: &mut OpState, query_plan: QueryPlan) -> Result<ResourceId> {
     let transaction = current_transaction(op_state);
     let query_engine = query_engine_arc(op_state);
     let stream = query_engine.query(transaction, query_plan)?;
     let resource = QueryStreamResource {
-        stream: RefCell::new(stream),
+        stream: RefCell::new(prefetch::batched(stream)),
         cancel: Default::default(),
     };
     let rid = op_state.resource_table.add(resource);
     Ok(rid)
 }
 
-// A future that resolves when this stream next element is available.
+// A future that resolves when this stream next batch is available.
 struct QueryNextFuture {
     resource: Weak<QueryStreamResource>,
 }
 
 impl Future for QueryNextFuture {
-    type Output = Option<Result<ResultRow>>;
+    type Output = Option<Result<Vec<ResultRow>>>;
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         match self.resource.upgrade() {
             Some(rc) => {
                 let mut stream = rc.stream.borrow_mut();
-                let stream: &mut QueryResults = &mut stream;
+                let stream: &mut Batched<QueryResults> = &mut stream;
                 Pin::new(stream).poll_next(cx)
             }
             None => Poll::Ready(Some(Err(anyhow!("Closed resource")))),
@@ -785,11 +1602,12 @@ impl Future for QueryNextFuture {
     }
 }
 
+/// Returns the next batch of rows of a query, or an empty one at its end.
 #[op]
-async fn op_chisel_query_next(
+async fn op_chisel_query_next_batch(
     state: Rc<RefCell<OpState>>,
     query_stream_rid: ResourceId,
-) -> Result<Option<ResultRow>> {
+) -> Result<Vec<ResultRow>> {
     let (resource, cancel) = {
         let rc: Rc<QueryStreamResource> = state.borrow().resource_table.get(query_stream_rid)?;
         let cancel = RcRef::map(&rc, |r| &r.cancel);
@@ -797,10 +1615,10 @@ async fn op_chisel_query_next(
     };
     let fut = QueryNextFuture { resource };
     let fut = fut.or_cancel(cancel);
-    if let Some(row) = fut.await? {
-        Ok(Some(row?))
-    } else {
-        Ok(None)
+    let request = current_request(&state.borrow());
+    match cancellable(&request, async { Ok(fut.await?) }).await? {
+        Some(rows) => rows,
+        None => Ok(vec![]),
     }
 }
 
@@ -828,8 +1646,8 @@ async fn op_chisel_read_worker_channel(state: Rc<RefCell<OpState>>) -> Result<()
     let state = &mut state;
     match msg {
         WorkerMsg::SetMeta(meta) => state.put::<Rc<MetaService>>(Rc::new(meta)),
-        WorkerMsg::HandleRequest(_req) => unreachable!("Wrong message"),
-        WorkerMsg::HandleEvent() => unreachable!("Wrong message"),
+        WorkerMsg::HandleRequest(..) => unreachable!("Wrong message"),
+        WorkerMsg::HandleEvent(_) => unreachable!("Wrong message"),
         WorkerMsg::SetTypeSystem(type_system) => state.put(type_system),
         WorkerMsg::RemoveTypeVersion(version) => {
             state.borrow_mut::<TypeSystem>().versions.remove(&version);
@@ -837,7 +1655,16 @@ async fn op_chisel_read_worker_channel(state: Rc<RefCell<OpState>>) -> Result<()
         WorkerMsg::SetQueryEngine(query_engine) => state.put(query_engine),
         WorkerMsg::SetPolicies(policies) => state.put(policies),
         WorkerMsg::MutatePolicies(func) => func(state.borrow_mut()),
-        WorkerMsg::SetCurrentSecrets(secretes) => state.put(secretes),
+        WorkerMsg::SetCurrentSecrets(secrets) => {
+            // Secrets are reloaded periodically, mostly unchanged, and
+            // building the auth providers hashes every key they list.
+            if state.try_borrow::<JsonObject>() != Some(&secrets) {
+                let providers = auth_provider::providers(&secrets);
+                state.put(AuthProviders(Rc::new(providers)));
+                state.put(secrets);
+            }
+        }
+        WorkerMsg::SetQueryBudget(budget) => state.put(budget),
     }
 
     Ok(())
@@ -875,7 +1702,12 @@ extern "C" fn promise_reject_callback(message: v8::PromiseRejectMessage) {
     }
 }
 
-pub async fn init_deno(v8_flags: Vec<String>, inspect: bool, inspect_brk: bool) -> Result<()> {
+pub async fn init_deno(
+    v8_flags: Vec<String>,
+    inspect: bool,
+    inspect_brk: bool,
+    coverage: bool,
+) -> Result<()> {
     let v8_flags = once("unused_arg0".to_owned())
         .chain(v8_flags.iter().cloned())
         .collect();
@@ -889,7 +1721,7 @@ pub async fn init_deno(v8_flags: Vec<String>, inspect: bool, inspect_brk: bool)
             unrecognized_v8_flags.join(",")
         );
     }
-    let (service, init_worker) = DenoService::new(inspect, inspect_brk).await;
+    let (service, init_worker) = DenoService::new(inspect, inspect_brk, coverage).await;
     DENO.with(|d| {
         d.set(Rc::new(RefCell::new(service)))
             .map_err(|_| ())
@@ -1084,24 +1916,21 @@ fn current_policies(st: &OpState) -> &Policies {
     st.borrow()
 }
 
-fn is_allowed_by_policy(
-    state: &OpState,
+fn version_policy<'a>(
+    state: &'a OpState,
     api_version: &str,
-    username: Option<String>,
-    req: &Request<hyper::Body>,
-    secrets: &JsonObject,
     path: &str,
-) -> Result<bool> {
-    let policies = current_policies(state);
-    match policies.versions.get(api_version) {
-        None => Err(anyhow!(
-            "found a route, but no version object for {}/{}",
-            api_version,
-            path,
-        )),
-        Some(x) => Ok(x.user_authorization.is_allowed(username, path)
-            && x.secret_authorization.is_allowed(req, secrets, path)),
-    }
+) -> Result<&'a VersionPolicy> {
+    current_policies(state)
+        .versions
+        .get(api_version)
+        .ok_or_else(|| {
+            anyhow!(
+                "found a route, but no version object for {}/{}",
+                api_version,
+                path,
+            )
+        })
 }
 
 async fn mutate_policies_impl(func: Box<dyn FnOnce(&mut Policies) + Send>) {
@@ -1119,10 +1948,273 @@ pub async fn set_policies(policies: Policies) {
     to_worker(WorkerMsg::SetPolicies(policies)).await;
 }
 
+pub async fn set_query_budget(budget: QueryBudget) {
+    to_worker(WorkerMsg::SetQueryBudget(budget)).await;
+}
+
+/// How many SQL statements a request may issue before it is reported, to
+/// expose N+1 query patterns: a query per object of a list, say.
+#[derive(Clone, Copy)]
+pub struct QueryBudget {
+    pub limit: usize,
+    /// Fail requests over the budget instead of only reporting them.
+    pub enforce: bool,
+}
+
+/// Number of example statements reported for a request over its budget.
+const STATEMENT_EXAMPLES: usize = 3;
+
+/// SQL statements issued by the current request.
+struct StatementLog {
+    endpoint: String,
+    count: usize,
+    examples: Vec<String>,
+}
+
+/// Records `count` statements issued by the current request for `endpoint`.
+/// `sql` is only called while examples are still being collected.
+fn record_statements(
+    st: &mut OpState,
+    endpoint: &str,
+    count: usize,
+    sql: impl FnOnce() -> Option<String>,
+) -> Result<()> {
+    let budget = match st.try_borrow::<QueryBudget>() {
+        Some(budget) => *budget,
+        None => return Ok(()),
+    };
+    if !st.has::<StatementLog>() {
+        st.put(StatementLog {
+            endpoint: endpoint.to_owned(),
+            count: 0,
+            examples: vec![],
+        });
+    }
+    let log = st.borrow_mut::<StatementLog>();
+    log.count += count;
+    if log.examples.len() < STATEMENT_EXAMPLES {
+        if let Some(sql) = sql() {
+            if !log.examples.contains(&sql) {
+                log.examples.push(sql);
+            }
+        }
+    }
+    if budget.enforce && log.count > budget.limit {
+        anyhow::bail!(
+            "{} issued {} SQL statements, over the query budget of {}",
+            log.endpoint,
+            log.count,
+            budget.limit
+        );
+    }
+    Ok(())
+}
+
+/// Warns if the request that just finished went over its query budget.
+fn report_statements(st: &mut OpState) {
+    let log = match st.try_take::<StatementLog>() {
+        Some(log) => log,
+        None => return,
+    };
+    let budget = *st.borrow::<QueryBudget>();
+    if log.count > budget.limit {
+        warn!(
+            "{} issued {} SQL statements, over the query budget of {}; \
+            this may be an N+1 query pattern. Example statements:\n  {}",
+            log.endpoint,
+            log.count,
+            budget.limit,
+            log.examples.join("\n  ")
+        );
+        notify(
+            EventKind::QueryBudgetExceeded,
+            &log.endpoint,
+            format!(
+                "{} issued {} SQL statements, over the query budget of {}",
+                log.endpoint, log.count, budget.limit
+            ),
+        );
+    }
+}
+
+fn current_request(st: &OpState) -> Option<RequestToken> {
+    st.try_borrow::<RequestToken>().cloned()
+}
+
+/// Checks that the current request wasn't killed and shows `sql` as the
+/// statement it is running. Returns the request, to cancel the statement.
+fn begin_statement(st: &OpState, sql: Option<&str>) -> Result<Option<RequestToken>> {
+    let request = current_request(st);
+    if let Some(request) = &request {
+        request.check()?;
+        request.faults().begin_statement()?;
+        if let Some(profile) = request.profile() {
+            profile.begin_statement(sql);
+        }
+        if let Some(sql) = sql {
+            request.set_sql(sql.to_owned());
+        }
+    }
+    Ok(request)
+}
+
+/// Records that the current request reads `type_names` and the entities nested
+/// in them, for the response cache to honor their caching hints.
+fn record_reads(st: &OpState, c: &ChiselRequestContext, type_names: &[&str]) -> Result<()> {
+    let request = match current_request(st) {
+        Some(request) => request,
+        None => return Ok(()),
+    };
+    let ts = current_type_system(st);
+    let mut entities = HashSet::new();
+    let mut pending = type_names
+        .iter()
+        .map(|name| ts.lookup_entity(name, &c.api_version))
+        .collect::<Result<Vec<_>, _>>()?;
+    while let Some(ty) = pending.pop() {
+        if !entities.insert(ty.name().to_owned()) {
+            continue;
+        }
+        for field in ty.all_fields() {
+            match ts.get(&field.type_id)? {
+                Type::Entity(nested_ty) => pending.push(nested_ty),
+                Type::Union(members) => pending.extend(members),
+                Type::Array(elem_ty) => {
+                    if let Type::Entity(nested_ty) = *elem_ty {
+                        pending.push(nested_ty);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    request.record_reads(entities, c.user_id.is_some() || c.tenant.is_some());
+    Ok(())
+}
+
+/// Fails if entity `type_name` of `api_version` is read-only, marking the
+/// current request to be answered with 503.
+fn check_writable(st: &OpState, type_name: &str, api_version: &str) -> Result<()> {
+    let version = written_version(type_name, api_version);
+    if let Some(retry_after) = read_only::check(version, type_name) {
+        if let Some(request) = current_request(st) {
+            request.set_retry_after(retry_after);
+        }
+        anyhow::bail!(
+            "Cannot write to {}: version {} is read-only for maintenance",
+            type_name,
+            version
+        );
+    }
+    if let Ok(ty) = current_type_system(st).lookup_custom_type(type_name, api_version) {
+        if let Some(connector) = ty.connector() {
+            anyhow::bail!(
+                "Cannot write to {}: its rows are fetched from {}",
+                type_name,
+                connector.url
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Runs `fut` on behalf of `request`, failing early if it gets killed, and
+/// accounts the wait to its current statement if it is profiled.
+async fn cancellable<T>(
+    request: &Option<RequestToken>,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match request {
+        Some(request) => {
+            let started = Instant::now();
+            let res = request
+                .run(async {
+                    request.faults().delay_statement().await;
+                    fut.await
+                })
+                .await;
+            if let Some(profile) = request.profile() {
+                profile.record_statement(started.elapsed());
+            }
+            res
+        }
+        None => fut.await,
+    }
+}
+
 fn take_current_transaction(state: &mut OpState) -> TransactionStatic {
     state.take()
 }
 
+/// API versions written by the current transaction, whose cached responses
+/// are dropped once it commits.
+#[derive(Default)]
+struct WrittenVersions(HashSet<String>);
+
+/// The version whose cached responses depend on entity `type_name` of
+/// `api_version`: auth entities are shared by all versions.
+fn written_version<'a>(type_name: &str, api_version: &'a str) -> &'a str {
+    if is_auth_entity_name(type_name) {
+        "__chiselstrike"
+    } else {
+        api_version
+    }
+}
+
+fn mark_written(st: &mut OpState, type_name: &str, api_version: &str) {
+    if !st.has::<WrittenVersions>() {
+        st.put(WrittenVersions::default());
+    }
+    let version = written_version(type_name, api_version).to_owned();
+    st.borrow_mut::<WrittenVersions>().0.insert(version);
+}
+
+/// Do the webhooks of `api_version` want to know of `event` on `type_name`?
+fn has_webhooks(st: &OpState, api_version: &str, type_name: &str, event: WebhookEvent) -> bool {
+    current_policies(st)
+        .versions
+        .get(api_version)
+        .map_or(false, |p| {
+            p.webhooks.iter().any(|w| w.matches(type_name, event))
+        })
+}
+
+/// The deliveries of `event` on `objects` of `type_name` to the webhooks of
+/// `api_version`.
+fn webhook_deliveries(
+    st: &OpState,
+    api_version: &str,
+    type_name: &str,
+    event: WebhookEvent,
+    objects: &[JsonObject],
+) -> Result<Vec<webhooks::Delivery>> {
+    match current_policies(st).versions.get(api_version) {
+        Some(policy) if !policy.webhooks.is_empty() => webhooks::deliveries(
+            &policy.webhooks,
+            current_secrets(st),
+            api_version,
+            type_name,
+            event,
+            objects,
+        ),
+        _ => Ok(vec![]),
+    }
+}
+
+/// The deliveries of the deletion of the objects of `type_name` with `ids`.
+fn webhook_deletions(
+    st: &OpState,
+    api_version: &str,
+    type_name: &str,
+    ids: Vec<String>,
+) -> Result<Vec<webhooks::Delivery>> {
+    let objects: Vec<_> = ids
+        .into_iter()
+        .map(|id| JsonObject::from_iter([("id".to_owned(), id.into())]))
+        .collect();
+    webhook_deliveries(st, api_version, type_name, WebhookEvent::Delete, &objects)
+}
+
 fn current_transaction(st: &OpState) -> TransactionStatic {
     st.borrow::<TransactionStatic>().clone()
 }
@@ -1190,16 +2282,27 @@ pub async fn update_secrets(secrets: JsonObject) {
 
 #[op]
 async fn op_chisel_commit_transaction(state: Rc<RefCell<OpState>>) -> Result<()> {
-    let transaction = {
+    let (transaction, written) = {
         let mut state = state.borrow_mut();
-        take_current_transaction(&mut state)
+        state.try_take::<NestedTransactions>();
+        let written = state.try_take::<WrittenVersions>().unwrap_or_default();
+        report_statements(&mut state);
+        finish_tenant_request(&mut state);
+        (take_current_transaction(&mut state), written)
     };
     crate::datastore::QueryEngine::commit_transaction_static(transaction).await?;
+    for api_version in written.0 {
+        response_cache::invalidate(&api_version);
+    }
     Ok(())
 }
 
 #[op]
 fn op_chisel_rollback_transaction(state: &mut OpState) -> Result<()> {
+    state.try_take::<NestedTransactions>();
+    state.try_take::<WrittenVersions>();
+    report_statements(state);
+    finish_tenant_request(state);
     let transaction = take_current_transaction(state);
     // Check that this is the last reference to the transaction.
     let transaction = extract_transaction(transaction);
@@ -1216,6 +2319,56 @@ async fn op_chisel_create_transaction(state: Rc<RefCell<OpState>>) -> Result<()>
     Ok(())
 }
 
+/// Number of nested transactions the request is in, as started by
+/// `ChiselRequest.transaction()`.
+#[derive(Default)]
+struct NestedTransactions(usize);
+
+fn nested_transactions(st: &mut OpState) -> &mut NestedTransactions {
+    if !st.has::<NestedTransactions>() {
+        st.put(NestedTransactions::default());
+    }
+    st.borrow_mut()
+}
+
+#[op]
+async fn op_chisel_begin_nested_transaction(state: Rc<RefCell<OpState>>) -> Result<()> {
+    let (transaction, depth) = {
+        let mut state = state.borrow_mut();
+        let depth = nested_transactions(&mut state).0;
+        (current_transaction(&state), depth)
+    };
+    let mut transaction = transaction.lock().await;
+    QueryEngine::begin_nested_transaction(transaction.deref_mut(), depth).await?;
+    nested_transactions(&mut state.borrow_mut()).0 += 1;
+    Ok(())
+}
+
+/// Ends the innermost nested transaction, returning the request's
+/// transaction and the depth of the nested one.
+fn end_nested_transaction(state: &RefCell<OpState>) -> Result<(TransactionStatic, usize)> {
+    let mut state = state.borrow_mut();
+    let nested = nested_transactions(&mut state);
+    anyhow::ensure!(nested.0 > 0, "no transaction to end");
+    nested.0 -= 1;
+    let depth = nested.0;
+    Ok((current_transaction(&state), depth))
+}
+
+#[op]
+async fn op_chisel_commit_nested_transaction(state: Rc<RefCell<OpState>>) -> Result<()> {
+    let (transaction, depth) = end_nested_transaction(&state)?;
+    let mut transaction = transaction.lock().await;
+    QueryEngine::commit_nested_transaction(transaction.deref_mut(), depth).await
+}
+
+#[op]
+async fn op_chisel_rollback_nested_transaction(state: Rc<RefCell<OpState>>) -> Result<()> {
+    let (transaction, depth) = end_nested_transaction(&state)?;
+    let mut transaction = transaction.lock().await;
+    QueryEngine::rollback_nested_transaction(transaction.deref_mut(), depth).await
+}
+
 #[derive(Serialize)]
 struct ResponseParts {
     status: u16,
@@ -1258,12 +2411,97 @@ where
     }
 }
 
+/// The auth providers configured by the current secrets.
+#[derive(Clone)]
+struct AuthProviders(Rc<Result<Vec<Box<dyn AuthProvider>>>>);
+
+impl AuthProviders {
+    fn get(&self) -> Result<&[Box<dyn AuthProvider>]> {
+        match self.0.as_ref() {
+            Ok(providers) => Ok(providers),
+            Err(e) => Err(anyhow!("{:#}", e)),
+        }
+    }
+}
+
+/// The route of a request whose user is identified by an `authenticate`
+/// function. Its user policies are checked once the function has run.
+struct PendingAuthorization {
+    api_version: String,
+    path: String,
+    /// The providers to try from index `next` on if the function finds no
+    /// credentials.
+    providers: AuthProviders,
+    next: usize,
+    /// The request without its body, which the function consumes.
+    request: Request<hyper::Body>,
+    /// Tenant resolved from the request itself.
+    tenant: Option<String>,
+    /// Claim of the user that holds the tenant instead.
+    tenant_claim: Option<String>,
+}
+
+/// A request of a tenant, whose time is counted against the tenant's quota
+/// when its transaction ends.
+struct TenantRequest {
+    api_version: String,
+    tenant: String,
+    started: Instant,
+}
+
+/// Admits a request of `tenant` to `path` of `api_version` under the
+/// tenant's quota. Returns the response to send instead if the tenant is
+/// over it.
+fn admit_tenant(
+    state: &mut OpState,
+    api_version: &str,
+    path: &str,
+    tenant: &Option<String>,
+) -> Result<Option<Response<Body>>> {
+    let tenant = match tenant {
+        Some(tenant) => tenant,
+        None => return Ok(None),
+    };
+    let quota = version_policy(state, api_version, path)?
+        .tenant_quotas
+        .get(tenant);
+    if let Some(retry_after) = quotas::admit(api_version, tenant, &quota) {
+        return Ok(Some(quotas::throttled(retry_after)?));
+    }
+    state.put(TenantRequest {
+        api_version: api_version.to_owned(),
+        tenant: tenant.clone(),
+        started: Instant::now(),
+    });
+    Ok(None)
+}
+
+fn finish_tenant_request(state: &mut OpState) {
+    if let Some(request) = state.try_take::<TenantRequest>() {
+        quotas::record_time(
+            &request.api_version,
+            &request.tenant,
+            request.started.elapsed(),
+        );
+    }
+}
+
+/// Who is making the request, as far as the server can tell.
+#[derive(Default)]
+struct RequestIdentity {
+    principal: Option<Principal>,
+    /// Route whose `authenticate` function identifies the user instead.
+    authenticator: Option<String>,
+    /// Tenant the request is scoped to.
+    tenant: Option<String>,
+}
+
 // FIXME: It would probably be cleaner to move more of this to
 // javascript.
 async fn special_response(
     state: Rc<RefCell<OpState>>,
     req: &Request<hyper::Body>,
-    userid: &Option<String>,
+    identity: &mut RequestIdentity,
 ) -> Result<Option<Response<Body>>> {
     let req_path = req.uri().path();
     // TODO: Make this optional, for users who want to reject some OPTIONS requests.
@@ -1271,7 +2509,22 @@ async fn special_response(
         // Makes CORS preflights pass.
         return Ok(Some(Response::builder().body("ok".to_string().into())?));
     }
-    if req_path.starts_with("/__chiselstrike/auth/") {
+    if req_path.starts_with(&format!("/__chiselstrike{}", SCIM_PATH_PREFIX)) {
+        // SCIM clients authenticate with a bearer token. Without one
+        // configured, provisioning is disabled.
+        let expected_token = match current_secrets(&state.borrow()).get("CHISELD_SCIM_TOKEN") {
+            Some(serde_json::Value::String(token)) => format!("Bearer {}", token),
+            _ => return Ok(Some(ApiService::not_found()?)),
+        };
+        match req.headers().get("Authorization") {
+            Some(h) if *h == expected_token => (),
+            _ => {
+                return Ok(Some(auth_provider::unauthorized(anyhow!(
+                    "invalid SCIM token"
+                ))?))
+            }
+        }
+    } else if req_path.starts_with("/__chiselstrike/auth/") {
         let auth_header = req.headers().get("ChiselAuth");
         if auth_header.is_none() {
             return Ok(Some(ApiService::forbidden(
@@ -1286,26 +2539,185 @@ async fn special_response(
             _ => return Ok(Some(ApiService::forbidden("Incorrect ChiselAuth value")?)),
         }
     } else {
-        let username = get_username_from_id(state.clone(), userid.clone()).await;
         let rp = match RequestPath::try_from(req_path) {
             Ok(rp) => rp,
             Err(_) => return Ok(Some(ApiService::not_found()?)),
         };
-        let is_allowed = is_allowed_by_policy(
-            &state.borrow(),
-            rp.api_version(),
-            username,
-            req,
-            current_secrets(&state.borrow()),
-            rp.path(),
-        )?;
+        let providers = state.borrow().borrow::<AuthProviders>().clone();
+        let mut next_provider = 0;
+        match auth_provider::authenticate(providers.get()?, &state, req).await {
+            Ok(Authentication::Principal(principal)) => identity.principal = Some(principal),
+            Ok(Authentication::Anonymous) => {}
+            Ok(Authentication::Function { route, next }) => {
+                identity.authenticator = Some(route);
+                next_provider = next;
+            }
+            Err(e) => return Ok(Some(auth_provider::unauthorized(e)?)),
+        }
+
+        let userid = identity.principal.as_ref().map(|p| p.user_id.clone());
+        let username = get_username_from_id(state.clone(), userid).await;
+        let (is_allowed, tenant_claim) = {
+            let state = state.borrow();
+            let policy = version_policy(&state, rp.api_version(), rp.path())?;
+            if let Some(source) = &policy.tenant {
+                let no_claims = JsonObject::new();
+                let claims = identity
+                    .principal
+                    .as_ref()
+                    .map_or(&no_claims, |p| &p.claims);
+                identity.tenant = source.resolve(req, claims);
+            }
+            let tenant_claim = match &policy.tenant {
+                Some(TenantSource::Claim(name)) => Some(name.clone()),
+                _ => None,
+            };
+            // Without a user yet, only secret policies can be checked now.
+            let claims = identity.principal.as_ref().map(|p| &p.claims);
+            let has_api_key = identity
+                .principal
+                .as_ref()
+                .map_or(false, |p| p.api_key.is_some());
+            let is_allowed =
+                policy
+                    .secret_authorization
+                    .is_allowed(req, current_secrets(&state), rp.path())
+                    && (identity.authenticator.is_some()
+                        || (policy
+                            .api_key_authorization
+                            .is_allowed(has_api_key, rp.path())
+                            && policy.user_authorization.is_allowed(username, rp.path())
+                            && policy.claim_authorization.is_allowed(claims, rp.path())));
+            (is_allowed, tenant_claim)
+        };
         if !is_allowed {
             return Ok(Some(ApiService::forbidden("Unauthorized")?));
         }
+        if identity.authenticator.is_some() {
+            let mut request = Request::new(hyper::Body::empty());
+            *request.method_mut() = req.method().clone();
+            *request.uri_mut() = req.uri().clone();
+            *request.headers_mut() = req.headers().clone();
+            state.borrow_mut().put(PendingAuthorization {
+                api_version: rp.api_version().to_owned(),
+                path: rp.path().to_owned(),
+                providers,
+                next: next_provider,
+                request,
+                tenant: identity.tenant.clone(),
+                tenant_claim,
+            });
+        } else if let Some(resp) = admit_tenant(
+            &mut state.borrow_mut(),
+            rp.api_version(),
+            rp.path(),
+            &identity.tenant,
+        )? {
+            return Ok(Some(resp));
+        }
     }
     Ok(None)
 }
 
+#[derive(Serialize)]
+struct Authenticated {
+    /// Response to send instead, if the user may not make the request.
+    denied: Option<ResponseParts>,
+    userid: Option<String>,
+    claims: JsonObject,
+    api_key: Option<String>,
+    tenant: Option<String>,
+}
+
+/// Called once the `authenticate` function of the request has run, with the
+/// user it identified and their claims, or with the error it threw. If the
+/// function found no credentials, tries the providers listed after it. Then
+/// checks the user policies of the route and resolves the tenant.
+#[op]
+async fn op_chisel_authenticated(
+    state: Rc<RefCell<OpState>>,
+    user_id: Option<String>,
+    claims: JsonObject,
+    error: Option<String>,
+) -> Result<Authenticated> {
+    let pending: PendingAuthorization = state.borrow_mut().take();
+    let principal = match (error, user_id) {
+        (Some(error), _) => Err(anyhow!(error)),
+        (None, Some(user_id)) => Ok(Some(Principal {
+            user_id,
+            claims,
+            api_key: None,
+        })),
+        (None, None) => {
+            let providers = &pending.providers.get()?[pending.next..];
+            match auth_provider::authenticate(providers, &state, &pending.request).await {
+                Ok(Authentication::Principal(principal)) => Ok(Some(principal)),
+                // There is only one function provider.
+                Ok(Authentication::Anonymous | Authentication::Function { .. }) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+    };
+    let principal = match principal {
+        Ok(principal) => principal,
+        Err(e) => {
+            let denied = convert_response(auth_provider::unauthorized(e)?).await?;
+            return Ok(Authenticated {
+                denied: Some(denied),
+                userid: None,
+                claims: JsonObject::new(),
+                api_key: None,
+                tenant: None,
+            });
+        }
+    };
+    let (user_id, claims, api_key) = match principal {
+        Some(principal) => (Some(principal.user_id), principal.claims, principal.api_key),
+        None => (None, JsonObject::new(), None),
+    };
+
+    let user_claims = user_id.is_some().then_some(&claims);
+    let username = get_username_from_id(state.clone(), user_id.clone()).await;
+    let is_allowed = {
+        let state = state.borrow();
+        let policy = version_policy(&state, &pending.api_version, &pending.path)?;
+        policy
+            .user_authorization
+            .is_allowed(username, &pending.path)
+            && policy
+                .claim_authorization
+                .is_allowed(user_claims, &pending.path)
+            && policy
+                .api_key_authorization
+                .is_allowed(api_key.is_some(), &pending.path)
+    };
+    let tenant = match &pending.tenant_claim {
+        Some(name) => policies::claim_tenant(&claims, name),
+        None => pending.tenant,
+    };
+    let resp = if is_allowed {
+        admit_tenant(
+            &mut state.borrow_mut(),
+            &pending.api_version,
+            &pending.path,
+            &tenant,
+        )?
+    } else {
+        Some(ApiService::forbidden("Unauthorized")?)
+    };
+    let denied = match resp {
+        Some(resp) => Some(convert_response(resp).await?),
+        None => None,
+    };
+    Ok(Authenticated {
+        denied,
+        userid: user_id,
+        claims,
+        api_key,
+        tenant,
+    })
+}
+
 async fn convert_response(mut res: Response<Body>) -> Result<ResponseParts> {
     let status = res.status().as_u16();
     let res_body = res.body_mut();
@@ -1328,7 +2740,7 @@ async fn convert_response(mut res: Response<Body>) -> Result<ResponseParts> {
     })
 }
 
-pub async fn run_js(path: String, req: Request<hyper::Body>) -> Result<Response<Body>> {
+pub async fn run_js(path: String, mut req: Request<hyper::Body>) -> Result<Response<Body>> {
     thread_local! {
         static NEXT_REQUEST_ID: Cell<u32> = Cell::new(0);
     }
@@ -1340,8 +2752,24 @@ pub async fn run_js(path: String, req: Request<hyper::Body>) -> Result<Response<
     });
     let request_handler = RequestHandler { id };
 
+    let method = req.method().clone();
+    let profile = profiling::start(req.headers());
+    let (guard, token) = {
+        let path = RequestPath::try_from(path.as_ref()).unwrap();
+        inflight::register(
+            path.api_version(),
+            path.path(),
+            method.as_str(),
+            profile.clone(),
+        )
+    };
+    token.faults().check_memory()?;
+    let socket = websocket::pending(&mut req);
     let sender = get().to_worker.clone();
-    sender.send(WorkerMsg::HandleRequest(req)).await.unwrap();
+    sender
+        .send(WorkerMsg::HandleRequest(req, token))
+        .await
+        .unwrap();
 
     let result = {
         let mut service = get();
@@ -1360,7 +2788,20 @@ pub async fn run_js(path: String, req: Request<hyper::Body>) -> Result<Response<
             .unwrap();
         v8::Global::new(scope, result)
     };
-    let result = resolve_promise(result).await?;
+    let result = resolve_promise(result).await;
+    let server_timing = profile.map(|profile| {
+        let path = RequestPath::try_from(path.as_ref()).unwrap();
+        profile.finish(path.api_version(), path.path(), method.as_str())
+    });
+    let result = match result {
+        Ok(result) => result,
+        Err(err) => {
+            return match guard.retry_after() {
+                Some(retry_after) => read_only::unavailable(retry_after),
+                None => Err(err),
+            }
+        }
+    };
 
     let body = {
         // The rust borrow checker can track fields independently, but
@@ -1420,7 +2861,18 @@ pub async fn run_js(path: String, req: Request<hyper::Body>) -> Result<Response<
             );
         }
 
-        builder.body(Body::Stream(Box::pin(stream)))?
+        if let Some(server_timing) = server_timing {
+            builder = builder.header("Server-Timing", server_timing);
+        }
+        // Dropping the socket forgets it, unless the route accepted it.
+        if let Some(socket) = socket {
+            if status == StatusCode::SWITCHING_PROTOCOLS.as_u16() {
+                socket.accept(path.clone(), builder.headers_mut().unwrap());
+            }
+        }
+        builder
+            .extension(guard.reads())
+            .body(Body::Stream(Box::pin(stream)))?
     };
 
     Ok(body)
@@ -1431,8 +2883,13 @@ pub async fn run_js_event(
     key: Option<Vec<u8>>,
     value: Option<Vec<u8>>,
 ) -> Result<()> {
+    let (_guard, token) = {
+        let path = RequestPath::try_from(path.as_ref()).unwrap();
+        inflight::register(path.api_version(), path.path(), "EVENT", None)
+    };
+    token.faults().check_memory()?;
     let sender = get().to_worker.clone();
-    sender.send(WorkerMsg::HandleEvent()).await.unwrap();
+    sender.send(WorkerMsg::HandleEvent(token)).await.unwrap();
     let result = {
         let mut service = get();
         let service: &mut DenoService = &mut service;
@@ -1467,6 +2924,113 @@ pub async fn run_js_event(
     Ok(())
 }
 
+/// Runs the job at `path`, in a transaction of its own.
+pub(crate) async fn run_js_job(path: String) -> Result<()> {
+    let (_guard, token) = {
+        let path = RequestPath::try_from(path.as_ref()).unwrap();
+        inflight::register(path.api_version(), path.path(), "JOB", None)
+    };
+    token.faults().check_memory()?;
+    let sender = get().to_worker.clone();
+    sender.send(WorkerMsg::HandleEvent(token)).await.unwrap();
+    let result = {
+        let mut service = get();
+        let service: &mut DenoService = &mut service;
+        let runtime = &mut service.worker.js_runtime;
+        let scope = &mut runtime.handle_scope();
+
+        let path = RequestPath::try_from(path.as_ref()).unwrap();
+        let call_handler = service.call_job_handler.open(scope);
+        let undefined = v8::undefined(scope).into();
+        let api_version = v8::String::new(scope, path.api_version()).unwrap().into();
+        let path = v8::String::new(scope, path.path()).unwrap().into();
+        let result = call_handler
+            .call(scope, undefined, &[path, api_version])
+            .unwrap();
+        v8::Global::new(scope, result)
+    };
+    resolve_promise(result).await?;
+    Ok(())
+}
+
+/// Runs the task at `path` with `payload`, in a transaction of its own.
+pub(crate) async fn run_js_task(path: String, payload: String) -> Result<()> {
+    let (_guard, token) = {
+        let path = RequestPath::try_from(path.as_ref()).unwrap();
+        inflight::register(path.api_version(), path.path(), "TASK", None)
+    };
+    token.faults().check_memory()?;
+    let sender = get().to_worker.clone();
+    sender.send(WorkerMsg::HandleEvent(token)).await.unwrap();
+    let result = {
+        let mut service = get();
+        let service: &mut DenoService = &mut service;
+        let runtime = &mut service.worker.js_runtime;
+        let scope = &mut runtime.handle_scope();
+
+        let path = RequestPath::try_from(path.as_ref()).unwrap();
+        let call_handler = service.call_task_handler.open(scope);
+        let undefined = v8::undefined(scope).into();
+        let api_version = v8::String::new(scope, path.api_version()).unwrap().into();
+        let path = v8::String::new(scope, path.path()).unwrap().into();
+        let payload = v8::String::new(scope, &payload).unwrap().into();
+        let result = call_handler
+            .call(scope, undefined, &[path, api_version, payload])
+            .unwrap();
+        v8::Global::new(scope, result)
+    };
+    resolve_promise(result).await?;
+    Ok(())
+}
+
+/// What happened on a WebSocket, for its route's handler.
+pub(crate) enum SocketEvent {
+    Text(String),
+    Binary(Vec<u8>),
+    Close,
+}
+
+/// Runs the `websocket` handler of the route at `path` for an event on
+/// socket `id`.
+pub(crate) async fn run_js_socket(path: String, id: u64, event: SocketEvent) -> Result<()> {
+    let (_guard, token) = {
+        let path = RequestPath::try_from(path.as_ref()).unwrap();
+        inflight::register(path.api_version(), path.path(), "WEBSOCKET", None)
+    };
+    token.faults().check_memory()?;
+    let sender = get().to_worker.clone();
+    sender.send(WorkerMsg::HandleEvent(token)).await.unwrap();
+    let result = {
+        let mut service = get();
+        let service: &mut DenoService = &mut service;
+        let runtime = &mut service.worker.js_runtime;
+        let scope = &mut runtime.handle_scope();
+
+        let path = RequestPath::try_from(path.as_ref()).unwrap();
+        let call_handler = service.call_socket_handler.open(scope);
+        let undefined = v8::undefined(scope).into();
+        let api_version = v8::String::new(scope, path.api_version()).unwrap().into();
+        let path = v8::String::new(scope, path.path()).unwrap().into();
+        let id = v8::String::new(scope, &id.to_string()).unwrap().into();
+        let (name, data) = match event {
+            SocketEvent::Text(text) => ("message", v8::String::new(scope, &text).unwrap().into()),
+            SocketEvent::Binary(data) => {
+                let data = v8::ArrayBuffer::new_backing_store_from_vec(data);
+                let data = v8::ArrayBuffer::with_backing_store(scope, &data.make_shared());
+                ("message", data.into())
+            }
+            SocketEvent::Close => ("close", v8::null(scope).into()),
+        };
+        let name = v8::String::new(scope, name).unwrap().into();
+        let result = call_handler
+            .call(scope, undefined, &[path, api_version, id, name, data])
+            .unwrap();
+        v8::Global::new(scope, result)
+    };
+    resolve_promise(result).await?;
+    Ok(())
+}
+
 #[derive(Serialize)]
 struct StartRequest {
     body_rid: Option<u32>,
@@ -1474,11 +3038,18 @@ struct StartRequest {
     method: String,
     url: String,
     userid: Option<String>,
+    claims: JsonObject,
+    authenticator: Option<String>,
+    tenant: Option<String>,
+    /// Name of the API key the request was made with.
+    api_key: Option<String>,
+    /// Id of the WebSocket the request would open, if it asks for one.
+    socket_id: Option<String>,
 }
 
 async fn handle_request(
     state: Rc<RefCell<OpState>>,
-    userid: Option<String>,
+    identity: RequestIdentity,
     req: Request<hyper::Body>,
 ) -> Result<StartRequest> {
     // FIXME: this request conversion is probably simplistic. Check deno/ext/http/lib.rs
@@ -1502,6 +3073,10 @@ async fn handle_request(
         headers.insert(k.to_string(), v.to_string());
     }
 
+    let socket_id = req
+        .extensions()
+        .get::<SocketId>()
+        .map(|id| id.0.to_string());
     let has_body = method != Method::GET && method != Method::HEAD;
     let method = method.as_str().to_string();
     let body_rid = if has_body {
@@ -1516,12 +3091,21 @@ async fn handle_request(
         None
     };
 
+    let (userid, claims, api_key) = match identity.principal {
+        Some(principal) => (Some(principal.user_id), principal.claims, principal.api_key),
+        None => (None, JsonObject::new(), None),
+    };
     Ok(StartRequest {
         body_rid,
         headers,
         method,
         url,
         userid,
+        claims,
+        authenticator: identity.authenticator,
+        tenant: identity.tenant,
+        api_key,
+        socket_id,
     })
 }
 
@@ -1535,40 +3119,92 @@ enum StartRequestRes {
 async fn op_chisel_start_request(state: Rc<RefCell<OpState>>) -> Result<StartRequestRes> {
     let receiver = WORKER_CHANNEL.with(|d| d.get().unwrap().clone());
     let req = match receiver.recv().await {
-        Ok(WorkerMsg::HandleRequest(req)) => req,
-        _ => unreachable!("Wrong message"),
-    };
-    let userid = match req.headers().get("ChiselUID").map(|v| v.to_str()) {
-        Some(Ok(str)) => Some(str.to_string()),
-        Some(Err(e)) => {
-            warn!(
-                "Weird bytes in ChiselUID value on request {:?}, error {:?}",
-                req, e
-            );
-            None
+        Ok(WorkerMsg::HandleRequest(req, token)) => {
+            state.borrow_mut().put(token);
+            req
         }
-        None => None,
+        _ => unreachable!("Wrong message"),
     };
-    if let Some(resp) = special_response(state.clone(), &req, &userid).await? {
+    let mut identity = RequestIdentity::default();
+    if let Some(resp) = special_response(state.clone(), &req, &mut identity).await? {
         let resp = convert_response(resp).await?;
         return Ok(StartRequestRes::Special(resp));
     }
 
     Ok(StartRequestRes::Js(
-        handle_request(state, userid, req).await?,
+        handle_request(state, identity, req).await?,
     ))
 }
 
+/// Fails if a fault is injected into the fetch() calls of the current request.
+/// If the request is profiled, starts timing the fetch() from `target`, and
+/// returns the id to end it with.
 #[op]
-async fn op_chisel_start_event_handler(_state: Rc<RefCell<OpState>>) -> Result<()> {
+fn op_chisel_check_fetch(state: &mut OpState, target: String) -> Result<Option<u32>> {
+    let request = match current_request(state) {
+        Some(request) => request,
+        None => return Ok(None),
+    };
+    anyhow::ensure!(!request.faults().fetch, "network error (injected fault)");
+    Ok(request
+        .profile()
+        .map(|profile| profile.begin_fetch(&target)))
+}
+
+#[op]
+fn op_chisel_end_fetch(state: &mut OpState, id: u32) {
+    if let Some(profile) = current_request(state)
+        .as_ref()
+        .and_then(RequestToken::profile)
+    {
+        profile.end_fetch(id);
+    }
+}
+
+#[op]
+async fn op_chisel_start_event_handler(state: Rc<RefCell<OpState>>) -> Result<()> {
     let receiver = WORKER_CHANNEL.with(|d| d.get().unwrap().clone());
     match receiver.recv().await {
-        Ok(WorkerMsg::HandleEvent()) => (),
+        Ok(WorkerMsg::HandleEvent(token)) => state.borrow_mut().put(token),
         _ => unreachable!("Wrong message"),
     };
     Ok(())
 }
 
+/// Sends a text message, or a binary one, to the client of WebSocket `id`.
+#[op]
+fn op_chisel_socket_send(
+    id: String,
+    text: Option<String>,
+    binary: Option<ZeroCopyBuf>,
+) -> Result<()> {
+    let message = match (text, binary) {
+        (Some(text), _) => Message::Text(text),
+        (None, Some(binary)) => Message::Binary(binary.to_vec()),
+        (None, None) => anyhow::bail!("a WebSocket message must be a string or bytes"),
+    };
+    websocket::send(&id, message)
+}
+
+#[op]
+fn op_chisel_socket_close(id: String) -> Result<()> {
+    websocket::send(&id, Message::Close(None))
+}
+
+/// Samples the heap statistics of the isolate of this executor.
+pub fn heap_usage() -> memory::HeapUsage {
+    let mut service = get();
+    let isolate = service.worker.js_runtime.v8_isolate();
+    let mut stats = v8::HeapStatistics::default();
+    isolate.get_heap_statistics(&mut stats);
+    memory::HeapUsage {
+        used: stats.used_heap_size() as u64,
+        total: stats.total_heap_size() as u64,
+        limit: stats.heap_size_limit() as u64,
+        external: stats.external_memory() as u64,
+    }
+}
+
 fn get() -> RcMut<DenoService> {
     DENO.with(|x| {
         let rc = x.get().expect("Runtime is not yet initialized.").clone();
@@ -1598,6 +3234,9 @@ pub async fn compile_endpoints(sources: HashMap<String, String>) -> Result<()> {
         let import_endpoints = service.import_endpoints.open(scope);
         let mut endpoints: Vec<v8::Local<'_, v8::Value>> = vec![];
         let mut event_handlers: Vec<v8::Local<'_, v8::Value>> = vec![];
+        let mut jobs: Vec<v8::Local<'_, v8::Value>> = vec![];
+        let mut tasks: Vec<v8::Local<'_, v8::Value>> = vec![];
+        let mut versions = HashSet::new();
 
         for (path, code) in sources {
             if let Ok(url) = Url::parse(&path) {
@@ -1606,6 +3245,9 @@ pub async fn compile_endpoints(sources: HashMap<String, String>) -> Result<()> {
                 code_map.insert(url, code);
                 continue;
             }
+            if let Some(api_version) = path.split('/').nth(1) {
+                versions.insert(api_version.to_owned());
+            }
             match path.split('/').nth(2) {
                 Some("routes") | Some("endpoints") => {
                     let path = without_extension(&path);
@@ -1639,6 +3281,38 @@ pub async fn compile_endpoints(sources: HashMap<String, String>) -> Result<()> {
                     event_handler.set(scope, api_version_key.into(), api_version);
                     event_handlers.push(event_handler.into());
                 }
+                Some("jobs") => {
+                    let path = without_extension(&path);
+                    let url = Url::parse(&format!("file://{}", path)).unwrap();
+                    code_map.insert(url, code);
+
+                    let path = endpoint_path_from_source_path(path);
+                    let path = RequestPath::try_from(path.as_ref()).unwrap();
+                    let api_version = v8::String::new(scope, path.api_version()).unwrap().into();
+                    let path = v8::String::new(scope, path.path()).unwrap().into();
+                    let job = v8::Object::new(scope);
+                    let path_key = v8::String::new(scope, "path").unwrap();
+                    job.set(scope, path_key.into(), path);
+                    let api_version_key = v8::String::new(scope, "apiVersion").unwrap();
+                    job.set(scope, api_version_key.into(), api_version);
+                    jobs.push(job.into());
+                }
+                Some("tasks") => {
+                    let path = without_extension(&path);
+                    let url = Url::parse(&format!("file://{}", path)).unwrap();
+                    code_map.insert(url, code);
+
+                    let path = endpoint_path_from_source_path(path);
+                    let path = RequestPath::try_from(path.as_ref()).unwrap();
+                    let api_version = v8::String::new(scope, path.api_version()).unwrap().into();
+                    let path = v8::String::new(scope, path.path()).unwrap().into();
+                    let task = v8::Object::new(scope);
+                    let path_key = v8::String::new(scope, "path").unwrap();
+                    task.set(scope, path_key.into(), path);
+                    let api_version_key = v8::String::new(scope, "apiVersion").unwrap();
+                    task.set(scope, api_version_key.into(), api_version);
+                    tasks.push(task.into());
+                }
                 _ => {
                     // Non endpoint files like models/...
                     let url = Url::parse(&format!("file://{}", path)).unwrap();
@@ -1646,15 +3320,37 @@ pub async fn compile_endpoints(sources: HashMap<String, String>) -> Result<()> {
                 }
             }
         }
+        // Replaced modules are still loaded, so count all of the version's.
+        for api_version in versions {
+            let prefix = format!("/{}/", api_version);
+            let bytes: usize = code_map
+                .iter()
+                .filter(|(url, _)| url.scheme() == "file" && url.path().starts_with(&prefix))
+                .map(|(_, code)| code.len())
+                .sum();
+            memory::record_modules(&api_version, bytes as u64);
+        }
         let endpoints = v8::Array::new_with_elements(scope, &endpoints).into();
         let event_handlers = v8::Array::new_with_elements(scope, &event_handlers).into();
+        let jobs = v8::Array::new_with_elements(scope, &jobs).into();
+        let tasks = v8::Array::new_with_elements(scope, &tasks).into();
         let undefined = v8::undefined(scope).into();
         let promise = import_endpoints
-            .call(scope, undefined, &[endpoints, event_handlers])
+            .call(scope, undefined, &[endpoints, event_handlers, jobs, tasks])
             .unwrap();
         v8::Global::new(scope, promise)
     };
-    resolve_promise(promise).await?;
+    let schedules = resolve_promise(promise).await?;
+    // Check the schedules before anything is applied.
+    let schedules: HashMap<String, String> = {
+        let mut service = get();
+        let scope = &mut service.worker.js_runtime.handle_scope();
+        let schedules = v8::Local::new(scope, schedules);
+        serde_v8::from_v8(scope, schedules)?
+    };
+    for (path, schedule) in schedules {
+        jobs::Schedule::parse(&schedule).with_context(|| format!("job {}", path))?;
+    }
     Ok(())
 }
 
@@ -1691,3 +3387,136 @@ pub async fn activate_event_handler(path: &str) -> Result<()> {
     resolve_promise(promise).await?;
     Ok(())
 }
+
+/// Activates the job at `path`, and schedules it.
+pub async fn activate_job(path: &str) -> Result<()> {
+    let promise = {
+        let mut service = get();
+        let service: &mut DenoService = &mut service;
+        let runtime = &mut service.worker.js_runtime;
+        let scope = &mut runtime.handle_scope();
+        let activate_job = service.activate_job.open(scope);
+        let undefined = v8::undefined(scope).into();
+        let path = v8::String::new(scope, path).unwrap().into();
+        let promise = activate_job.call(scope, undefined, &[path]).unwrap();
+        v8::Global::new(scope, promise)
+    };
+    let schedule = resolve_promise(promise).await?;
+    let schedule = {
+        let mut service = get();
+        let scope = &mut service.worker.js_runtime.handle_scope();
+        schedule.open(scope).to_rust_string_lossy(scope)
+    };
+    jobs::register(path, &schedule)
+}
+
+/// Activates the task at `path`, which requests can then enqueue.
+pub async fn activate_task(path: &str) -> Result<()> {
+    let promise = {
+        let mut service = get();
+        let service: &mut DenoService = &mut service;
+        let runtime = &mut service.worker.js_runtime;
+        let scope = &mut runtime.handle_scope();
+        let activate_task = service.activate_task.open(scope);
+        let undefined = v8::undefined(scope).into();
+        let path = v8::String::new(scope, path).unwrap().into();
+        let promise = activate_task.call(scope, undefined, &[path]).unwrap();
+        v8::Global::new(scope, promise)
+    };
+    resolve_promise(promise).await?;
+    tasks::register(path);
+    Ok(())
+}
+
+/// Outcome of a unit test, as reported by `runUnitTests()` of testing.ts.
+#[derive(Deserialize)]
+pub struct UnitTestResult {
+    pub file: String,
+    pub name: String,
+    pub error: Option<String>,
+    #[serde(rename = "durationMs")]
+    pub duration_ms: f64,
+}
+
+/// V8 coverage of a module loaded by unit tests, with the path it was loaded
+/// from.
+#[derive(Deserialize)]
+pub struct UnitTestCoverage {
+    pub path: String,
+    /// `FunctionCoverage` objects of the V8 inspector protocol.
+    pub functions: serde_json::Value,
+}
+
+/// What `runUnitTests()` of testing.ts returns.
+#[derive(Deserialize)]
+pub struct UnitTestRun {
+    pub results: Vec<UnitTestResult>,
+    pub coverage: Option<Vec<UnitTestCoverage>>,
+    /// The fetch() calls recorded, as JSON, by test module.
+    pub cassettes: HashMap<String, String>,
+}
+
+/// Loads the compiled `sources` and runs the unit tests registered by the
+/// modules in `tests`, with entities kept in memory instead of the database.
+/// With `coverage`, also collects the code coverage of the loaded modules.
+/// The fetch() calls of the tests are replayed from `cassettes`, or made and
+/// recorded with `record`.
+pub async fn run_unit_tests(
+    sources: HashMap<String, String>,
+    tests: Vec<String>,
+    coverage: bool,
+    cassettes: HashMap<String, String>,
+    record: bool,
+) -> Result<UnitTestRun> {
+    thread_local! {
+        static NEXT_RUN_ID: Cell<u32> = Cell::new(0);
+    }
+
+    // Modules are never unloaded, so each run loads the sources under a
+    // pseudo version of its own, for the tests to get fresh modules.
+    let api_version = NEXT_RUN_ID.with(|x| {
+        let v = x.get();
+        x.set(v + 1);
+        format!("__unit_test{}", v)
+    });
+    let promise = {
+        let mut service = get();
+        let service: &mut DenoService = &mut service;
+        {
+            let mut handle = service.module_loader.lock().unwrap();
+            for (path, code) in sources {
+                match Url::parse(&path) {
+                    Ok(url) => {
+                        handle.code_map.insert(url, code);
+                    }
+                    Err(_) => handle.insert_path(&format!("/{}/{}", api_version, path), &code),
+                }
+            }
+        }
+
+        let runtime = &mut service.worker.js_runtime;
+        let scope = &mut runtime.handle_scope();
+        let run_unit_tests = service.run_unit_tests.open(scope);
+        let undefined = v8::undefined(scope).into();
+        let api_version = v8::String::new(scope, &api_version).unwrap().into();
+        let tests = serde_v8::to_v8(scope, tests)?;
+        let coverage = v8::Boolean::new(scope, coverage).into();
+        let cassettes = serde_v8::to_v8(scope, cassettes)?;
+        let record = v8::Boolean::new(scope, record).into();
+        let promise = run_unit_tests
+            .call(
+                scope,
+                undefined,
+                &[api_version, tests, coverage, cassettes, record],
+            )
+            .unwrap();
+        v8::Global::new(scope, promise)
+    };
+    let results = resolve_promise(promise).await?;
+
+    let mut service = get();
+    let service: &mut DenoService = &mut service;
+    let scope = &mut service.worker.js_runtime.handle_scope();
+    let results = v8::Local::new(scope, results);
+    Ok(serde_v8::from_v8(scope, results)?)
+}
@@ -0,0 +1,182 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Fault injection, to check that an application handles failures and that
+//! its clients retry as they should.
+//!
+//! Rules, added with `chisel fault inject`, make a percentage of the
+//! requests to a version, or to one of its endpoints, fail their database
+//! statements, run them slowly, fail their fetch() calls or fail as if the
+//! isolate ran out of memory. Whether a rule fires is decided once per
+//! request, when it starts. Only chiseld in debug mode, as run by
+//! `chisel dev`, accepts rules.
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultKind {
+    /// The database statements of the request fail.
+    Database,
+    /// The database statements of the request take this much longer.
+    SlowQuery(Duration),
+    /// The fetch() calls of the request fail, like on a network error.
+    Fetch,
+    /// The request fails as if its isolate ran out of memory.
+    OutOfMemory,
+}
+
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub id: u64,
+    pub api_version: String,
+    /// The endpoint whose requests can fail, or all of the version's if None.
+    pub endpoint: Option<String>,
+    pub kind: FaultKind,
+    /// Percentage of the requests that fail, from 0 to 100.
+    pub percent: f64,
+}
+
+impl Rule {
+    fn matches(&self, api_version: &str, path: &str) -> bool {
+        if self.api_version != api_version {
+            return false;
+        }
+        match &self.endpoint {
+            Some(endpoint) => match path.strip_prefix(endpoint.as_str()) {
+                Some(rest) => rest.is_empty() || rest.starts_with('/'),
+                None => false,
+            },
+            None => true,
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static RULES: Lazy<Mutex<Vec<Rule>>> = Lazy::new(Default::default);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Lets rules be added. Meant for development servers only.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Adds a rule like `rule`, returning its id.
+pub fn inject(mut rule: Rule) -> Result<u64> {
+    anyhow::ensure!(
+        ENABLED.load(Ordering::Relaxed),
+        "Fault injection needs chiseld to run in debug mode, as `chisel dev` does"
+    );
+    anyhow::ensure!(
+        (0.0..=100.0).contains(&rule.percent),
+        "the percentage of failing requests must be between 0 and 100"
+    );
+    rule.id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let id = rule.id;
+    RULES.lock().unwrap().push(rule);
+    Ok(id)
+}
+
+/// Removes rule `id`, or all of them if None. Returns false if there was no
+/// such rule.
+pub fn clear(id: Option<u64>) -> bool {
+    let mut rules = RULES.lock().unwrap();
+    let len = rules.len();
+    match id {
+        Some(id) => rules.retain(|rule| rule.id != id),
+        None => rules.clear(),
+    }
+    id.is_none() || rules.len() < len
+}
+
+/// Lists the rules, oldest first.
+pub fn list() -> Vec<Rule> {
+    RULES.lock().unwrap().clone()
+}
+
+/// The faults injected into a request.
+#[derive(Clone, Default)]
+pub struct Faults {
+    pub database: bool,
+    pub query_delay: Option<Duration>,
+    pub fetch: bool,
+    pub out_of_memory: bool,
+    /// Set when a statement begins, for it to be delayed by `query_delay`.
+    delay_pending: Arc<AtomicBool>,
+}
+
+impl Faults {
+    /// Fails with an injected database error, or arranges for the statement
+    /// about to run to be slowed down.
+    pub fn begin_statement(&self) -> Result<()> {
+        anyhow::ensure!(!self.database, "database error (injected fault)");
+        if self.query_delay.is_some() {
+            self.delay_pending.store(true, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Slows down the statement that began last, once.
+    pub async fn delay_statement(&self) {
+        if let Some(delay) = self.query_delay {
+            if self.delay_pending.swap(false, Ordering::Relaxed) {
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+
+    /// Fails if the request must fail as if it ran out of memory.
+    pub fn check_memory(&self) -> Result<()> {
+        anyhow::ensure!(
+            !self.out_of_memory,
+            "isolate ran out of memory (injected fault)"
+        );
+        Ok(())
+    }
+}
+
+/// Decides which faults to inject into a request to `path` of `api_version`.
+pub fn roll(api_version: &str, path: &str) -> Faults {
+    let mut faults = Faults::default();
+    for rule in RULES.lock().unwrap().iter() {
+        if !rule.matches(api_version, path) || rand::random::<f64>() * 100.0 >= rule.percent {
+            continue;
+        }
+        match rule.kind {
+            FaultKind::Database => faults.database = true,
+            FaultKind::SlowQuery(delay) => {
+                faults.query_delay = Some(faults.query_delay.unwrap_or_default().max(delay))
+            }
+            FaultKind::Fetch => faults.fetch = true,
+            FaultKind::OutOfMemory => faults.out_of_memory = true,
+        }
+    }
+    faults
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(endpoint: Option<&str>) -> Rule {
+        Rule {
+            id: 0,
+            api_version: "dev".to_owned(),
+            endpoint: endpoint.map(str::to_owned),
+            kind: FaultKind::Database,
+            percent: 100.0,
+        }
+    }
+
+    #[test]
+    fn matches_endpoint_and_subpaths() {
+        let r = rule(Some("/people"));
+        assert!(r.matches("dev", "/people"));
+        assert!(r.matches("dev", "/people/1"));
+        assert!(!r.matches("dev", "/peoples"));
+        assert!(!r.matches("prod", "/people"));
+        assert!(rule(None).matches("dev", "/anything"));
+    }
+}
@@ -0,0 +1,215 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Registry of in-flight endpoint requests and event handler invocations,
+//! backing `chisel ps` and `chisel kill`.
+//!
+//! Cancellation is cooperative: killing a request flags its token, and the
+//! request fails at its next datastore operation, or right away if it is
+//! waiting on one. JavaScript code that doesn't touch the datastore runs on
+//! until it does or finishes.
+
+use crate::faults::{self, Faults};
+use crate::profiling::Profile;
+use crate::response_cache::Reads;
+use anyhow::Result;
+use deno_core::futures::future::{self, Either};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub struct RequestInfo {
+    pub id: u64,
+    pub api_version: String,
+    pub path: String,
+    pub method: String,
+    pub age: Duration,
+    /// The last SQL statement the request issued.
+    pub sql: Option<String>,
+}
+
+struct Entry {
+    api_version: String,
+    path: String,
+    method: String,
+    started: Instant,
+    sql: Option<String>,
+    /// Set if the request was rejected because of a maintenance window.
+    retry_after: Option<Duration>,
+    cancel: async_channel::Sender<()>,
+}
+
+static REQUESTS: Lazy<Mutex<HashMap<u64, Entry>>> = Lazy::new(Default::default);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Handle of a registered request, which unregisters it when dropped.
+pub struct RequestGuard {
+    id: u64,
+    reads: Arc<Mutex<Reads>>,
+}
+
+impl RequestGuard {
+    /// If the request was rejected because of a maintenance window, returns
+    /// how long the client should wait before retrying.
+    pub fn retry_after(&self) -> Option<Duration> {
+        REQUESTS.lock().unwrap().get(&self.id)?.retry_after
+    }
+
+    /// What the request reads, which is complete once its transaction ends.
+    pub fn reads(&self) -> Arc<Mutex<Reads>> {
+        self.reads.clone()
+    }
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        REQUESTS.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Lets a request record what it is doing and notice it was killed.
+#[derive(Clone)]
+pub struct RequestToken {
+    id: u64,
+    cancelled: async_channel::Receiver<()>,
+    reads: Arc<Mutex<Reads>>,
+    faults: Faults,
+    profile: Option<Arc<Profile>>,
+}
+
+impl RequestToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.is_closed()
+    }
+
+    /// Fails if the request was killed.
+    pub fn check(&self) -> Result<()> {
+        anyhow::ensure!(!self.is_cancelled(), "request {} was killed", self.id);
+        Ok(())
+    }
+
+    /// Runs `fut`, failing early if the request is killed meanwhile.
+    pub async fn run<T>(&self, fut: impl Future<Output = Result<T>>) -> Result<T> {
+        self.check()?;
+        let cancelled = self.cancelled.recv();
+        match future::select(Box::pin(fut), Box::pin(cancelled)).await {
+            Either::Left((res, _)) => res,
+            Either::Right(_) => anyhow::bail!("request {} was killed", self.id),
+        }
+    }
+
+    /// The faults injected into the request.
+    pub fn faults(&self) -> &Faults {
+        &self.faults
+    }
+
+    /// The profile of the request, if it is profiled.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_deref()
+    }
+
+    /// Records `sql` as the statement the request is running.
+    pub fn set_sql(&self, sql: String) {
+        if let Some(entry) = REQUESTS.lock().unwrap().get_mut(&self.id) {
+            entry.sql = Some(sql);
+        }
+    }
+
+    /// Records that the request was rejected because of a maintenance
+    /// window, which the client should retry after `retry_after`.
+    pub fn set_retry_after(&self, retry_after: Duration) {
+        if let Some(entry) = REQUESTS.lock().unwrap().get_mut(&self.id) {
+            entry.retry_after = Some(retry_after);
+        }
+    }
+
+    /// Records that the request read `entities`, filtered by the policies of
+    /// a user or tenant if `personalized`.
+    pub fn record_reads(&self, entities: impl IntoIterator<Item = String>, personalized: bool) {
+        let mut reads = self.reads.lock().unwrap();
+        reads.entities.extend(entities);
+        reads.personalized |= personalized;
+    }
+}
+
+/// Registers a request to `path` of `api_version`, recording its execution
+/// in `profile` if set, and decides which faults to inject into it.
+pub fn register(
+    api_version: &str,
+    path: &str,
+    method: &str,
+    profile: Option<Arc<Profile>>,
+) -> (RequestGuard, RequestToken) {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (cancel, cancelled) = async_channel::bounded(1);
+    REQUESTS.lock().unwrap().insert(
+        id,
+        Entry {
+            api_version: api_version.to_owned(),
+            path: path.to_owned(),
+            method: method.to_owned(),
+            started: Instant::now(),
+            sql: None,
+            retry_after: None,
+            cancel,
+        },
+    );
+    let reads = Arc::new(Mutex::new(Reads::default()));
+    (
+        RequestGuard {
+            id,
+            reads: reads.clone(),
+        },
+        RequestToken {
+            id,
+            cancelled,
+            reads,
+            faults: faults::roll(api_version, path),
+            profile,
+        },
+    )
+}
+
+/// Lists the in-flight requests, oldest first.
+pub fn list() -> Vec<RequestInfo> {
+    list_entries(&REQUESTS.lock().unwrap())
+}
+
+/// Lists the in-flight requests, unless the registry is locked, such as by
+/// a thread that panicked holding it.
+pub fn try_list() -> Option<Vec<RequestInfo>> {
+    REQUESTS
+        .try_lock()
+        .ok()
+        .map(|entries| list_entries(&entries))
+}
+
+fn list_entries(entries: &HashMap<u64, Entry>) -> Vec<RequestInfo> {
+    let now = Instant::now();
+    let mut requests: Vec<_> = entries
+        .iter()
+        .map(|(id, entry)| RequestInfo {
+            id: *id,
+            api_version: entry.api_version.clone(),
+            path: entry.path.clone(),
+            method: entry.method.clone(),
+            age: now.duration_since(entry.started),
+            sql: entry.sql.clone(),
+        })
+        .collect();
+    requests.sort_unstable_by_key(|request| request.id);
+    requests
+}
+
+/// Kills request `id`. Returns false if there is no such request.
+pub fn kill(id: u64) -> bool {
+    match REQUESTS.lock().unwrap().get(&id) {
+        Some(entry) => {
+            entry.cancel.close();
+            true
+        }
+        None => false,
+    }
+}
@@ -33,6 +33,10 @@ async fn route(req: Request<Body>) -> Result<Response<Body>> {
         "/status" => response("ok", 200),
         "/readiness" => response("ready", HEALTH_READY.load(Ordering::Relaxed)),
         "/liveness" => response("alive", 200),
+        "/metrics" => {
+            let metrics = crate::quotas::metrics() + &crate::memory::metrics();
+            response(&metrics, 200)
+        }
         _ => response("not found", 404),
     }
     .or_else(|e| response(&format!("{:?}", e), 500))
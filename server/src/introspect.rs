@@ -7,17 +7,27 @@
 //! metadata of the ChiselStrike server endpoints as OpenAPI 2.0 format:
 //!
 //! https://swagger.io/specification/v2/
+//!
+//! `chisel describe --format openapi` gets a more complete OpenAPI 3.0
+//! document, from `openapi_document()`, which also covers the schemas of
+//! the entities, the methods of the CRUD endpoints, and the request and
+//! response bodies that other endpoints declare.
 
 use crate::api::{response_template, ApiService, Body};
+use crate::proto::{type_msg::TypeEnum, BodySchema, EndpointSchema, TypeMsg};
 use crate::runtime;
+use crate::types::{Entity, Type, TypeSystem, VersionTypes, TYPE_KEY};
 use anyhow::Result;
 use deno_core::futures;
 use futures::FutureExt;
 use hyper::{Request, Response};
+use once_cell::sync::Lazy;
 use openapi::v2::{Info, PathItem, Spec};
 use openapi::OpenApi;
-use std::collections::BTreeMap;
-use std::sync::Arc;
+use regex::Regex;
+use serde_json::{json, Value as JsonValue};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
 
 async fn introspect(req: Request<hyper::Body>) -> Result<Response<Body>> {
     let api = runtime::get().api.clone();
@@ -95,3 +105,421 @@ pub fn init(api: &ApiService) {
     add_introspection(api, "");
     add_introspection(api, "__chiselstrike");
 }
+
+/// The request and response bodies that endpoints declare, by version and
+/// endpoint path.
+static SCHEMAS: Lazy<Mutex<HashMap<String, HashMap<String, EndpointSchema>>>> =
+    Lazy::new(Default::default);
+
+/// Replaces the bodies that the endpoints of `version` declare.
+pub(crate) fn set_schemas(version: &str, schemas: HashMap<String, EndpointSchema>) {
+    SCHEMAS.lock().unwrap().insert(version.to_owned(), schemas);
+}
+
+/// The bodies that the endpoint at `path` of `version` declares.
+pub(crate) fn endpoint_schema(version: &str, path: &str) -> Option<EndpointSchema> {
+    SCHEMAS.lock().unwrap().get(version)?.get(path).cloned()
+}
+
+/// An endpoint in the document of `openapi_document()`.
+pub(crate) struct OpenApiEndpoint {
+    /// The path of the endpoint, starting with its version.
+    pub path: String,
+    /// The entity the endpoint serves, if it is made by `crud()`.
+    pub crud: Option<Entity>,
+    /// How the endpoint lists entities, if it is made by `crud()`.
+    pub pagination: String,
+    /// The bodies the endpoint declares, if it does.
+    pub schema: Option<EndpointSchema>,
+}
+
+/// The entity of `version_types` that the endpoint module with source
+/// `code` serves, if it exports `Entity.crud()` or `crud(Entity, ...)`.
+pub(crate) fn crud_entity(code: &str, version_types: &VersionTypes) -> Option<Entity> {
+    static CRUD: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\b(\w+)\.crud\(|\bcrud\(\s*(\w+)").unwrap());
+    CRUD.captures_iter(code)
+        .filter_map(|captures| captures.get(1).or_else(|| captures.get(2)))
+        .find_map(|name| version_types.custom_types.get(name.as_str()).cloned())
+}
+
+/// How the CRUD endpoint module with source `code` lists entities, from
+/// the `pagination` option of `crud()`.
+pub(crate) fn crud_pagination(code: &str) -> String {
+    static PAGINATION: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"\bpagination:\s*["'](\w+)["']"#).unwrap());
+    PAGINATION
+        .captures(code)
+        .map_or("pages", |captures| captures.get(1).unwrap().as_str())
+        .to_owned()
+}
+
+/// An OpenAPI 3.0 document of `endpoints` and of the entities of the
+/// `versions` in `type_system`.
+pub(crate) fn openapi_document(
+    type_system: &TypeSystem,
+    versions: &[String],
+    endpoints: &[OpenApiEndpoint],
+) -> JsonValue {
+    let mut schemas = serde_json::Map::new();
+    for version in versions {
+        if let Ok(version_types) = type_system.get_version(version) {
+            for ty in version_types.custom_types.values() {
+                schemas.insert(schema_name(ty), entity_schema(type_system, ty));
+            }
+        }
+    }
+    let mut paths = serde_json::Map::new();
+    for endpoint in endpoints {
+        match (&endpoint.crud, &endpoint.schema) {
+            (Some(ty), _) => {
+                let (collection, item) = crud_paths(ty, &endpoint.pagination);
+                paths.insert(endpoint.path.clone(), collection);
+                paths.insert(format!("{}/{{id}}", endpoint.path), item);
+            }
+            (None, Some(schema)) => {
+                let version = endpoint.path.split('/').nth(1).unwrap_or_default();
+                let version_types = type_system.get_version(version).ok();
+                paths.insert(
+                    endpoint.path.clone(),
+                    declared_path(schema, version, version_types),
+                );
+            }
+            (None, None) => {
+                paths.insert(endpoint.path.clone(), json!({}));
+            }
+        }
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "ChiselStrike API",
+            "version": versions.join(", "),
+        },
+        "paths": paths,
+        "components": { "schemas": schemas },
+    })
+}
+
+fn schema_name(ty: &Entity) -> String {
+    format!("{}.{}", ty.api_version, ty.name())
+}
+
+/// The schema of the objects of a polymorphic relation, which are of one of
+/// the entities `names`. Swagger 2.0 has no `oneOf`, so only the property
+/// naming the entity is described.
+fn union_schema<'a>(names: impl Iterator<Item = &'a str>) -> JsonValue {
+    json!({
+        "type": "object",
+        "properties": {
+            TYPE_KEY: { "type": "string", "enum": names.collect::<Vec<_>>() },
+        },
+        "required": [TYPE_KEY],
+    })
+}
+
+fn schema_ref(ty: &Entity) -> JsonValue {
+    json!({ "$ref": format!("#/components/schemas/{}", schema_name(ty)) })
+}
+
+fn entity_schema(type_system: &TypeSystem, ty: &Entity) -> JsonValue {
+    let mut properties = serde_json::Map::new();
+    properties.insert("id".into(), json!({ "type": "string", "readOnly": true }));
+    let mut required = vec!["id".to_owned()];
+    for field in ty.user_fields() {
+        let mut schema = match type_system.get(&field.type_id) {
+            Ok(field_type) => type_schema(&field_type),
+            Err(_) => json!({}),
+        };
+        if let (Some(description), Some(schema)) = (&field.description, schema.as_object_mut()) {
+            schema.insert("description".into(), json!(description));
+        }
+        properties.insert(field.name.clone(), schema);
+        if !field.is_optional && field.user_provided_default().is_none() {
+            required.push(field.name.clone());
+        }
+    }
+    let mut schema = json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    });
+    if let Some(description) = ty.description() {
+        schema["description"] = json!(description);
+    }
+    schema
+}
+
+/// The schema of the metadata of a file, which file fields hold.
+fn file_schema() -> JsonValue {
+    json!({
+        "type": "object",
+        "properties": {
+            "key": { "type": "string" },
+            "name": { "type": "string" },
+            "type": { "type": "string" },
+            "size": { "type": "number" },
+        },
+        "required": ["key", "name", "type", "size"],
+    })
+}
+
+fn type_schema(ty: &Type) -> JsonValue {
+    match ty {
+        Type::String => json!({ "type": "string" }),
+        Type::Float => json!({ "type": "number" }),
+        Type::Boolean => json!({ "type": "boolean" }),
+        Type::DateTime => json!({ "type": "string", "format": "date-time" }),
+        Type::Json => json!({}),
+        Type::File => file_schema(),
+        Type::Enum(variants) => json!({ "type": "string", "enum": variants }),
+        // The auth entities are not in any version.
+        Type::Entity(ty) if ty.is_auth() => json!({ "type": "object" }),
+        Type::Entity(ty) => schema_ref(ty),
+        Type::Union(entities) => union_schema(entities.iter().map(|ty| ty.name())),
+        Type::Array(element) => json!({ "type": "array", "items": type_schema(element) }),
+    }
+}
+
+/// The schema of a declared body. Entities of `version_types` are referred
+/// to; others, like the auth entities, are plain objects. Dates are sent
+/// as strings, like the date fields of entities.
+fn body_schema(
+    body: &BodySchema,
+    version: &str,
+    version_types: Option<&VersionTypes>,
+) -> JsonValue {
+    if let Some(ty) = &body.body_type {
+        return type_msg_schema(ty, version, version_types);
+    }
+    let mut properties = serde_json::Map::new();
+    let mut required = vec![];
+    for field in &body.fields {
+        let schema = match &field.field_type {
+            Some(ty) => type_msg_schema(ty, version, version_types),
+            None => json!({}),
+        };
+        properties.insert(field.name.clone(), schema);
+        if !field.is_optional {
+            required.push(field.name.clone());
+        }
+    }
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+fn type_msg_schema(ty: &TypeMsg, version: &str, version_types: Option<&VersionTypes>) -> JsonValue {
+    match &ty.type_enum {
+        Some(TypeEnum::String(_)) => json!({ "type": "string" }),
+        Some(TypeEnum::Number(_)) => json!({ "type": "number" }),
+        Some(TypeEnum::Bool(_)) => json!({ "type": "boolean" }),
+        Some(TypeEnum::EnumType(e)) => json!({ "type": "string", "enum": e.variants }),
+        Some(TypeEnum::UnionType(u)) => union_schema(u.entities.iter().map(String::as_str)),
+        Some(TypeEnum::Entity(name)) if name == "Date" => {
+            json!({ "type": "string", "format": "date-time" })
+        }
+        Some(TypeEnum::Entity(name)) if name == "JSONValue" => json!({}),
+        Some(TypeEnum::Entity(name)) if name == "ChiselFile" => file_schema(),
+        Some(TypeEnum::Entity(name)) => {
+            match version_types.and_then(|types| types.custom_types.get(name)) {
+                Some(_) => json!({
+                    "$ref": format!("#/components/schemas/{}.{}", version, name),
+                }),
+                None => json!({ "type": "object" }),
+            }
+        }
+        Some(TypeEnum::Array(inner)) => {
+            let items = match &inner.value_type {
+                Some(element) => type_msg_schema(element, version, version_types),
+                None => json!({}),
+            };
+            json!({ "type": "array", "items": items })
+        }
+        None => json!({}),
+    }
+}
+
+/// The path item of an endpoint that declares its bodies. Its methods are
+/// not known, so it is described as taking its request body by POST, or as
+/// a GET if it declares no request body.
+fn declared_path(
+    schema: &EndpointSchema,
+    version: &str,
+    version_types: Option<&VersionTypes>,
+) -> JsonValue {
+    let mut operation = serde_json::Map::new();
+    if let Some(request) = &schema.request {
+        operation.insert(
+            "requestBody".into(),
+            json!({
+                "required": true,
+                "content": {
+                    "application/json": { "schema": body_schema(request, version, version_types) },
+                },
+            }),
+        );
+    }
+    let response = match &schema.response {
+        Some(response) => json!({
+            "description": "The response",
+            "content": {
+                "application/json": { "schema": body_schema(response, version, version_types) },
+            },
+        }),
+        None => json!({ "description": "The response" }),
+    };
+    operation.insert("responses".into(), json!({ "200": response }));
+    let method = if schema.request.is_some() {
+        "post"
+    } else {
+        "get"
+    };
+    json!({ method: operation })
+}
+
+/// The path items of a CRUD endpoint, for its collection and for each of its
+/// entities by id.
+fn crud_paths(ty: &Entity, pagination: &str) -> (JsonValue, JsonValue) {
+    let name = ty.name();
+    let entity = schema_ref(ty);
+    let json_response = |description: String, schema: &JsonValue| {
+        json!({
+            "description": description,
+            "content": { "application/json": { "schema": schema } },
+        })
+    };
+    let text_response = |description: &str| {
+        json!({
+            "description": description,
+            "content": { "text/plain": { "schema": { "type": "string" } } },
+        })
+    };
+    let body = json!({
+        "required": true,
+        "content": { "application/json": { "schema": entity } },
+    });
+    let query_parameters = json!([
+        {
+            "name": "sort",
+            "in": "query",
+            "description": "Field to sort by, prefixed by - for descending order.",
+            "schema": { "type": "string" },
+        },
+        {
+            "name": "limit",
+            "in": "query",
+            "schema": { "type": "integer", "minimum": 0 },
+        },
+        {
+            "name": "offset",
+            "in": "query",
+            "schema": { "type": "integer", "minimum": 0 },
+        },
+        {
+            "name": "cursor",
+            "in": "query",
+            "description": "Cursor of a page, from next_page, prev_page or next_cursor.",
+            "schema": { "type": "string" },
+        },
+        {
+            "name": "filters",
+            "in": "query",
+            "description": "Filters like .field=value or .field~lt=value.",
+            "style": "form",
+            "explode": true,
+            "schema": { "type": "object", "additionalProperties": { "type": "string" } },
+        },
+    ]);
+    let mut list_response = match pagination {
+        "envelope" => json_response(
+            format!("A page of {} entities", name),
+            &json!({
+                "type": "object",
+                "properties": {
+                    "items": { "type": "array", "items": entity },
+                    "next_cursor": { "type": "string" },
+                    "total": { "type": "integer" },
+                },
+                "required": ["items"],
+            }),
+        ),
+        "array" => json_response(
+            format!("A page of {} entities", name),
+            &json!({ "type": "array", "items": entity }),
+        ),
+        _ => json_response(
+            format!("A page of {} entities", name),
+            &json!({
+                "type": "object",
+                "properties": {
+                    "results": { "type": "array", "items": entity },
+                    "next_page": { "type": "string" },
+                    "prev_page": { "type": "string" },
+                },
+                "required": ["results"],
+            }),
+        ),
+    };
+    if pagination == "array" {
+        list_response["headers"] = json!({
+            "Link": {
+                "description": "The URL of the next page, as rel=\"next\".",
+                "schema": { "type": "string" },
+            },
+            "X-Total-Count": {
+                "description": "The number of entities, if it is cheap to know.",
+                "schema": { "type": "integer" },
+            },
+        });
+    }
+    let collection = json!({
+        "get": {
+            "summary": format!("List {} entities", name),
+            "parameters": query_parameters,
+            "responses": { "200": list_response },
+        },
+        "post": {
+            "summary": format!("Create a {}", name),
+            "requestBody": body,
+            "responses": { "200": json_response(format!("The new {}", name), &entity) },
+        },
+        "delete": {
+            "summary": format!("Delete the {} entities that match the filters", name),
+            "parameters": [query_parameters[4]],
+            "responses": { "200": text_response("The filters that were matched") },
+        },
+    });
+    let item = json!({
+        "parameters": [
+            { "name": "id", "in": "path", "required": true, "schema": { "type": "string" } },
+        ],
+        "get": {
+            "summary": format!("Get a {}", name),
+            "responses": {
+                "200": json_response(format!("The {}", name), &entity),
+                "404": text_response("Not found"),
+            },
+        },
+        "put": {
+            "summary": format!("Replace a {}", name),
+            "requestBody": body,
+            "responses": { "200": json_response(format!("The updated {}", name), &entity) },
+        },
+        "patch": {
+            "summary": format!("Update some fields of a {}", name),
+            "requestBody": body,
+            "responses": {
+                "200": json_response(format!("The updated {}", name), &entity),
+                "404": text_response("Not found"),
+            },
+        },
+        "delete": {
+            "summary": format!("Delete a {}", name),
+            "responses": { "200": text_response("The id that was deleted") },
+        },
+    });
+    (collection, item)
+}
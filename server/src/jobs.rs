@@ -0,0 +1,404 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Scheduled jobs.
+//!
+//! Modules in the `jobs/` directory of a project export a cron `schedule`,
+//! like `"*/5 * * * *"`, and a default function. At the start of every
+//! minute, in UTC, the scheduler runs the jobs whose schedule matches it,
+//! each in a transaction of its own, like event handlers. A job is skipped
+//! while its previous run is still going.
+//!
+//! The scheduler runs on the first executor only, so that each job runs
+//! once per minute however many executors there are. Versions with jobs
+//! are never loaded lazily, for their jobs to be scheduled.
+//!
+//! Triggers are journaled in the meta database. A job whose last run was
+//! interrupted, or which was due while the server was down, runs once on
+//! the first minute after it is scheduled again.
+
+use crate::datastore::DbConnection;
+use crate::deno;
+use crate::journal::{self, JobTrigger};
+use crate::notifications::{notify, EventKind};
+use crate::types::datetime;
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use tokio::task::JoinHandle;
+
+/// The times a cron field matches, as a bit per value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Field(u64);
+
+impl Field {
+    /// Parses a field of values from `min` to `max`, like `*`, `*/15`, `1-5`
+    /// or `0,30`.
+    fn parse(field: &str, min: u32, max: u32) -> Result<Self> {
+        let mut bits = 0u64;
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => {
+                    let step: u32 = step
+                        .parse()
+                        .with_context(|| format!("invalid step in `{}`", part))?;
+                    anyhow::ensure!(step > 0, "step of `{}` must not be 0", part);
+                    (range, step)
+                }
+                None => (part, 1),
+            };
+            let (first, last) = if range == "*" {
+                (min, max)
+            } else {
+                let (first, last) = match range.split_once('-') {
+                    Some((first, last)) => (first, last),
+                    None => (range, range),
+                };
+                let first: u32 = first
+                    .parse()
+                    .with_context(|| format!("invalid value in `{}`", part))?;
+                let last: u32 = last
+                    .parse()
+                    .with_context(|| format!("invalid value in `{}`", part))?;
+                anyhow::ensure!(
+                    min <= first && first <= last && last <= max,
+                    "`{}` is not within {}-{}",
+                    part,
+                    min,
+                    max
+                );
+                // Like `5/15`, from 5 to the end.
+                if step > 1 && first == last {
+                    (first, max)
+                } else {
+                    (first, last)
+                }
+            };
+            for value in (first..=last).step_by(step as usize) {
+                bits |= 1 << value;
+            }
+        }
+        Ok(Self(bits))
+    }
+
+    fn matches(self, value: u32) -> bool {
+        self.0 & (1 << value) != 0
+    }
+}
+
+/// A cron schedule of five fields: minute, hour, day of the month, month and
+/// day of the week.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Schedule {
+    minutes: Field,
+    hours: Field,
+    days: Field,
+    months: Field,
+    weekdays: Field,
+    /// Whether the day of the month and the day of the week were both
+    /// restricted, in which case a day matching either is matched.
+    either_day: bool,
+}
+
+impl Schedule {
+    pub(crate) fn parse(schedule: &str) -> Result<Self> {
+        let expanded = match schedule.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            schedule => schedule,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        anyhow::ensure!(
+            fields.len() == 5,
+            "schedule `{}` must have 5 fields: minute, hour, day of month, month and day of week",
+            schedule
+        );
+        let parse = |i: usize, min, max| {
+            Field::parse(fields[i], min, max)
+                .with_context(|| format!("invalid schedule `{}`", schedule))
+        };
+        let mut weekdays = parse(4, 0, 7)?;
+        // Both 0 and 7 are Sunday.
+        if weekdays.matches(7) {
+            weekdays.0 |= 1;
+        }
+        Ok(Self {
+            minutes: parse(0, 0, 59)?,
+            hours: parse(1, 0, 23)?,
+            days: parse(2, 1, 31)?,
+            months: parse(3, 1, 12)?,
+            weekdays,
+            either_day: !fields[2].starts_with('*') && !fields[4].starts_with('*'),
+        })
+    }
+
+    /// Whether the minute of `time` is in the schedule.
+    fn matches(&self, time: OffsetDateTime) -> bool {
+        let day = self.days.matches(time.day().into());
+        let weekday = self
+            .weekdays
+            .matches(time.weekday().number_days_from_sunday().into());
+        let day = if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        self.minutes.matches(time.minute().into())
+            && self.hours.matches(time.hour().into())
+            && self.months.matches(time.month() as u32)
+            && day
+    }
+
+    /// Whether a minute after `after` and before `before` is in the
+    /// schedule.
+    fn matches_between(&self, after: OffsetDateTime, before: OffsetDateTime) -> bool {
+        let mut minute = after + time::Duration::MINUTE;
+        while minute < before {
+            if self.matches(minute) {
+                return true;
+            }
+            minute += time::Duration::MINUTE;
+        }
+        false
+    }
+}
+
+/// How the last run of a job went.
+#[derive(Clone, Debug)]
+pub(crate) struct LastRun {
+    pub(crate) started: OffsetDateTime,
+    pub(crate) duration: Duration,
+    pub(crate) error: Option<String>,
+}
+
+struct Job {
+    schedule: String,
+    parsed: Schedule,
+    running: bool,
+    last_run: Option<LastRun>,
+}
+
+/// The status of a job, for `chisel describe`.
+pub(crate) struct JobStatus {
+    pub(crate) path: String,
+    pub(crate) schedule: String,
+    pub(crate) last_run: Option<LastRun>,
+}
+
+/// The active jobs, by path, like `/dev/cleanup`.
+static JOBS: Lazy<Mutex<BTreeMap<String, Job>>> = Lazy::new(Default::default);
+
+/// Schedules the job at `path`, keeping how its last run went if it was
+/// already scheduled.
+pub(crate) fn register(path: &str, schedule: &str) -> Result<()> {
+    let parsed = Schedule::parse(schedule).with_context(|| format!("job {}", path))?;
+    let mut jobs = JOBS.lock().unwrap();
+    let job = jobs.entry(path.to_owned()).or_insert_with(|| Job {
+        schedule: String::new(),
+        parsed: parsed.clone(),
+        running: false,
+        last_run: None,
+    });
+    job.schedule = schedule.to_owned();
+    job.parsed = parsed;
+    Ok(())
+}
+
+/// Unschedules the jobs under `prefix` that are not in `paths`.
+pub(crate) fn retain(prefix: &str, paths: &[String]) {
+    JOBS.lock()
+        .unwrap()
+        .retain(|path, _| !path.starts_with(prefix) || paths.contains(path));
+}
+
+/// The jobs of `api_version`.
+pub(crate) fn list(api_version: &str) -> Vec<JobStatus> {
+    let prefix = format!("/{}/", api_version);
+    JOBS.lock()
+        .unwrap()
+        .iter()
+        .filter(|(path, _)| path.starts_with(&prefix))
+        .map(|(path, job)| JobStatus {
+            path: path.clone(),
+            schedule: job.schedule.clone(),
+            last_run: job.last_run.clone(),
+        })
+        .collect()
+}
+
+/// Marks the jobs due at `minute` as running, and returns their paths. Jobs
+/// with a trigger in `journaled`, from before the server started, are also
+/// due if that run didn't finish or a run was missed since.
+fn start_due(minute: OffsetDateTime, journaled: &mut HashMap<String, JobTrigger>) -> Vec<String> {
+    let mut jobs = JOBS.lock().unwrap();
+    let mut due = vec![];
+    for (path, job) in jobs.iter_mut() {
+        let missed = journaled.remove(path).map_or(false, |trigger| {
+            !trigger.finished
+                || OffsetDateTime::from_unix_timestamp(trigger.due)
+                    .map_or(false, |last| job.parsed.matches_between(last, minute))
+        });
+        if missed {
+            info!("Running job {}, which missed a run", path);
+        } else if !job.parsed.matches(minute) {
+            continue;
+        }
+        if job.running {
+            warn!("Job {} is still running, skipping its run", path);
+            continue;
+        }
+        job.running = true;
+        due.push(path.clone());
+    }
+    due
+}
+
+async fn run(db: DbConnection, path: String, minute: OffsetDateTime) {
+    // Journaled first, so that the run is repeated if the server goes down
+    // meanwhile.
+    let due = minute.unix_timestamp();
+    if let Err(e) = journal::record_job_trigger(&db, &path, due).await {
+        warn!("Could not journal the run of job {}: {:?}", path, e);
+    }
+    let started = OffsetDateTime::now_utc();
+    let start = Instant::now();
+    let result = deno::run_js_job(path.clone()).await;
+    if let Err(e) = journal::complete_job_trigger(&db, &path, due).await {
+        warn!("Could not journal the end of job {}: {:?}", path, e);
+    }
+    let error = result.err().map(|e| format!("{:?}", e));
+    if let Some(error) = &error {
+        warn!("Job {} failed: {}", path, error);
+        notify(
+            EventKind::JobFailed,
+            &path,
+            format!("job {} failed: {}", path, error),
+        );
+    }
+    if let Some(job) = JOBS.lock().unwrap().get_mut(&path) {
+        job.running = false;
+        job.last_run = Some(LastRun {
+            started,
+            duration: start.elapsed(),
+            error,
+        });
+    }
+}
+
+/// Spawns the scheduler on the current executor.
+pub(crate) fn spawn(
+    db: DbConnection,
+    shutdown: async_channel::Receiver<()>,
+) -> JoinHandle<Result<()>> {
+    tokio::task::spawn_local(async move {
+        // Jobs are only scheduled once their version is loaded, so the
+        // journal is consulted as they show up.
+        let mut journaled = match journal::job_triggers(&db).await {
+            Ok(triggers) => triggers,
+            Err(e) => {
+                warn!("Could not read the journal of jobs: {:?}", e);
+                HashMap::new()
+            }
+        };
+        loop {
+            // Waking up a bit early must not run the jobs of the previous
+            // minute again, so this is the minute to run, not the time of
+            // waking up.
+            let now = OffsetDateTime::now_utc();
+            let next = now.unix_timestamp() / 60 * 60 + 60;
+            let next = OffsetDateTime::from_unix_timestamp(next)?;
+            let wait = (next - now).try_into().unwrap_or_default();
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = shutdown.recv() => break,
+            }
+            for path in start_due(next, &mut journaled) {
+                debug!("Running job {} of {}", path, datetime::format(next));
+                tokio::task::spawn_local(run(db.clone(), path, next));
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{Date, Month};
+
+    fn at(year: i32, month: Month, day: u8, hour: u8, minute: u8) -> OffsetDateTime {
+        Date::from_calendar_date(year, month, day)
+            .unwrap()
+            .with_hms(hour, minute, 0)
+            .unwrap()
+            .assume_utc()
+    }
+
+    #[test]
+    fn parse() {
+        let schedule = Schedule::parse("*/15 9-17 * * 1-5").unwrap();
+        // A Monday.
+        assert!(schedule.matches(at(2022, Month::October, 17, 9, 30)));
+        assert!(!schedule.matches(at(2022, Month::October, 17, 9, 31)));
+        assert!(!schedule.matches(at(2022, Month::October, 17, 18, 0)));
+        // A Sunday.
+        assert!(!schedule.matches(at(2022, Month::October, 16, 9, 30)));
+
+        let daily = Schedule::parse("@daily").unwrap();
+        assert_eq!(daily, Schedule::parse("0 0 * * *").unwrap());
+        assert!(daily.matches(at(2022, Month::October, 17, 0, 0)));
+        assert!(!daily.matches(at(2022, Month::October, 17, 0, 1)));
+
+        let sunday = Schedule::parse("0 12 * * 7").unwrap();
+        assert!(sunday.matches(at(2022, Month::October, 16, 12, 0)));
+
+        assert!(Schedule::parse("* * * *").is_err());
+        assert!(Schedule::parse("60 * * * *").is_err());
+        assert!(Schedule::parse("*/0 * * * *").is_err());
+        assert!(Schedule::parse("a * * * *").is_err());
+    }
+
+    #[test]
+    fn either_day() {
+        // The 1st of the month, or any Friday.
+        let schedule = Schedule::parse("0 0 1 * 5").unwrap();
+        assert!(schedule.matches(at(2022, Month::October, 1, 0, 0)));
+        assert!(schedule.matches(at(2022, Month::October, 21, 0, 0)));
+        assert!(!schedule.matches(at(2022, Month::October, 20, 0, 0)));
+    }
+
+    #[test]
+    fn matches_between() {
+        let hourly = Schedule::parse("@hourly").unwrap();
+        let after = at(2022, Month::October, 17, 9, 0);
+        assert!(!hourly.matches_between(after, at(2022, Month::October, 17, 10, 0)));
+        assert!(hourly.matches_between(after, at(2022, Month::October, 17, 10, 1)));
+        assert!(hourly.matches_between(after, at(2022, Month::October, 18, 0, 0)));
+    }
+
+    #[test]
+    fn registered() {
+        register("/jobs_test/a", "* * * * *").unwrap();
+        register("/jobs_test/b", "0 * * * *").unwrap();
+        assert!(register("/jobs_test/c", "bad").is_err());
+        register("/jobs_test/a", "*/5 * * * *").unwrap();
+        let schedules: Vec<_> = list("jobs_test")
+            .into_iter()
+            .map(|job| (job.path, job.schedule))
+            .collect();
+        assert_eq!(
+            schedules,
+            [
+                ("/jobs_test/a".to_owned(), "*/5 * * * *".to_owned()),
+                ("/jobs_test/b".to_owned(), "0 * * * *".to_owned()),
+            ]
+        );
+        retain("/jobs_test/", &["/jobs_test/b".to_owned()]);
+        assert_eq!(list("jobs_test").len(), 1);
+    }
+}
@@ -0,0 +1,189 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Write-ahead journal of accepted events and job triggers.
+//!
+//! Events consumed from Kafka are recorded in the meta database before they
+//! are handled, together with the offset to resume the topic from. Once the
+//! handler is done, the entry is removed. Entries still present at startup
+//! belong to events that were accepted but not processed when the server went
+//! down, and are replayed before new events are consumed. Handlers can thus
+//! see an event twice, but never lose one.
+//!
+//! Each executor consumes topics on its own, so entries and offsets are kept
+//! per executor.
+//!
+//! Likewise, the scheduler records the minute it runs each job for before
+//! running it, and marks the trigger finished once the job returns. A job
+//! whose last run didn't finish, or whose schedule matched while the server
+//! was down, is run once when the scheduler starts.
+//!
+//! Background tasks need no journal: they are rows of the `tasks` table from
+//! the moment they are enqueued until they succeed.
+
+use crate::datastore::DbConnection;
+use anyhow::{Context, Result};
+use sqlx::{Executor, Row};
+use std::collections::HashMap;
+
+pub(crate) struct JournalEntry {
+    pub id: i32,
+    pub topic: String,
+    pub key: Option<Vec<u8>>,
+    pub value: Option<Vec<u8>>,
+}
+
+/// Records an event read from `topic` at `offset`, and moves the offset to
+/// resume `topic` from past it. Returns the ID of the entry.
+pub(crate) async fn record(
+    db: &DbConnection,
+    executor: usize,
+    topic: &str,
+    offset: i64,
+    key: &Option<Vec<u8>>,
+    value: &Option<Vec<u8>>,
+) -> Result<i32> {
+    let executor = executor as i32;
+    let mut transaction = db.pool.begin().await?;
+    // An executor consumes several topics at once, so the ID of the entry
+    // must come from the insert itself.
+    let insert = sqlx::query(
+        r#"
+        INSERT INTO event_journal (executor, topic, event_key, event_value)
+        VALUES ($1, $2, $3, $4)
+        RETURNING journal_id"#,
+    )
+    .bind(executor)
+    .bind(topic.to_owned())
+    .bind(key.as_ref().map(base64::encode))
+    .bind(value.as_ref().map(base64::encode));
+    let id: i32 = transaction.fetch_one(insert).await?.get("journal_id");
+
+    let delete = sqlx::query("DELETE FROM event_offsets WHERE executor = $1 AND topic = $2")
+        .bind(executor)
+        .bind(topic.to_owned());
+    transaction.execute(delete).await?;
+    let insert = sqlx::query(
+        r#"
+        INSERT INTO event_offsets (executor, topic, next_offset)
+        VALUES ($1, $2, $3)"#,
+    )
+    .bind(executor)
+    .bind(topic.to_owned())
+    .bind(offset + 1);
+    transaction.execute(insert).await?;
+    transaction
+        .commit()
+        .await
+        .context("failed to journal event")?;
+    Ok(id)
+}
+
+/// Removes the entry of an event that was handled.
+pub(crate) async fn complete(db: &DbConnection, id: i32) -> Result<()> {
+    let query = sqlx::query("DELETE FROM event_journal WHERE journal_id = $1").bind(id);
+    db.pool.execute(query).await?;
+    Ok(())
+}
+
+/// Returns the entries of `executor` in the order their events were accepted.
+/// The first executor also takes over entries of executors that no longer
+/// exist, so that decreasing the executor count doesn't strand events.
+pub(crate) async fn pending(
+    db: &DbConnection,
+    executor: usize,
+    executor_count: usize,
+) -> Result<Vec<JournalEntry>> {
+    let query = sqlx::query(
+        r#"
+        SELECT journal_id, topic, event_key, event_value
+        FROM event_journal
+        WHERE executor = $1 OR ($1 = 0 AND executor >= $2)
+        ORDER BY journal_id"#,
+    )
+    .bind(executor as i32)
+    .bind(executor_count as i32);
+    let rows = query.fetch_all(&db.pool).await?;
+    rows.into_iter()
+        .map(|row| {
+            let decode = |column: &str| -> Result<Option<Vec<u8>>> {
+                let encoded: Option<String> = row.get(column);
+                Ok(encoded.map(base64::decode).transpose()?)
+            };
+            Ok(JournalEntry {
+                id: row.get("journal_id"),
+                topic: row.get("topic"),
+                key: decode("event_key")?,
+                value: decode("event_value")?,
+            })
+        })
+        .collect()
+}
+
+/// Returns the offset to resume `topic` from, if events of it were journaled
+/// before.
+pub(crate) async fn next_offset(
+    db: &DbConnection,
+    executor: usize,
+    topic: &str,
+) -> Result<Option<i64>> {
+    let query =
+        sqlx::query("SELECT next_offset FROM event_offsets WHERE executor = $1 AND topic = $2")
+            .bind(executor as i32)
+            .bind(topic.to_owned());
+    let row = query.fetch_optional(&db.pool).await?;
+    Ok(row.map(|row| row.get("next_offset")))
+}
+
+/// The last trigger of a job.
+pub(crate) struct JobTrigger {
+    /// The minute the job was run for, in seconds since the epoch.
+    pub due: i64,
+    pub finished: bool,
+}
+
+/// Returns the last trigger of each job, by path.
+pub(crate) async fn job_triggers(db: &DbConnection) -> Result<HashMap<String, JobTrigger>> {
+    let query = sqlx::query("SELECT path, due, finished FROM job_triggers");
+    let rows = query.fetch_all(&db.pool).await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let trigger = JobTrigger {
+                due: row.get("due"),
+                finished: row.get("finished"),
+            };
+            (row.get("path"), trigger)
+        })
+        .collect())
+}
+
+/// Records that the job at `path` is about to run for minute `due`.
+pub(crate) async fn record_job_trigger(db: &DbConnection, path: &str, due: i64) -> Result<()> {
+    let mut transaction = db.pool.begin().await?;
+    let delete = sqlx::query("DELETE FROM job_triggers WHERE path = $1").bind(path.to_owned());
+    transaction.execute(delete).await?;
+    let insert = sqlx::query(
+        r#"
+        INSERT INTO job_triggers (path, due, finished)
+        VALUES ($1, $2, $3)"#,
+    )
+    .bind(path.to_owned())
+    .bind(due)
+    .bind(false);
+    transaction.execute(insert).await?;
+    transaction
+        .commit()
+        .await
+        .context("failed to journal job trigger")?;
+    Ok(())
+}
+
+/// Marks the run of the job at `path` for minute `due` as finished.
+pub(crate) async fn complete_job_trigger(db: &DbConnection, path: &str, due: i64) -> Result<()> {
+    let query = sqlx::query("UPDATE job_triggers SET finished = $1 WHERE path = $2 AND due = $3")
+        .bind(true)
+        .bind(path.to_owned())
+        .bind(due);
+    db.pool.execute(query).await?;
+    Ok(())
+}
@@ -1,9 +1,11 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
 use crate::api::ApiService;
+use crate::datastore::DbConnection;
+use crate::journal;
 use crate::server::DoRepeat;
 use anyhow::Result;
-use deno_core::futures::{self, stream::StreamExt};
+use deno_core::futures::stream::StreamExt;
 use rskafka::client::{
     consumer::{StartOffset, StreamConsumerBuilder},
     ClientBuilder,
@@ -11,41 +13,131 @@ use rskafka::client::{
 use std::rc::Rc;
 use std::sync::Arc;
 
+/// Replays the events that `executor` accepted but didn't finish handling
+/// before the server went down. An event that can't be replayed is logged
+/// and left in the journal, so that it doesn't keep the executor from
+/// consuming new events.
+async fn replay(
+    api: &ApiService,
+    db: &DbConnection,
+    executor: usize,
+    executor_count: usize,
+    max_retries: u32,
+) -> Result<()> {
+    let entries = journal::pending(db, executor, executor_count).await?;
+    if !entries.is_empty() {
+        info!("Replaying {} journaled Kafka events", entries.len());
+    }
+    for entry in entries {
+        let topic = entry.topic.clone();
+        let result = async {
+            api.handle_event(entry.topic, entry.key, entry.value, max_retries)
+                .await?;
+            journal::complete(db, entry.id).await
+        };
+        if let Err(e) = result.await {
+            warn!(
+                "Failed to replay journaled event {} of Kafka topic {}: {:?}",
+                entry.id, topic, e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Journals and handles an event read from `topic` at `offset`.
+#[allow(clippy::too_many_arguments)]
+async fn handle(
+    api: &ApiService,
+    db: &DbConnection,
+    executor: usize,
+    topic: String,
+    offset: i64,
+    key: Option<Vec<u8>>,
+    value: Option<Vec<u8>>,
+    max_retries: u32,
+) -> Result<()> {
+    // Journal the event before handling it, so that it is replayed if the
+    // server crashes meanwhile.
+    let id = journal::record(db, executor, &topic, offset, &key, &value).await?;
+    api.handle_event(topic, key, value, max_retries).await?;
+    journal::complete(db, id).await
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn spawn(
     api: Rc<ApiService>,
+    db: DbConnection,
+    executor: usize,
+    executor_count: usize,
     connection: String,
     topics: Vec<String>,
+    max_retries: u32,
     shutdown: async_channel::Receiver<()>,
 ) -> Result<Vec<tokio::task::JoinHandle<Result<()>>>> {
+    replay(&api, &db, executor, executor_count, max_retries).await?;
+
     let client = ClientBuilder::new(vec![connection]).build().await?;
-    let streams = topics.iter().map(move |topic| {
-        let topic = topic.clone();
-        let partition_client = Arc::new(client.partition_client(topic.clone(), 0).unwrap());
-        let stream = StreamConsumerBuilder::new(partition_client, StartOffset::Latest)
+    let mut tasks = Vec::new();
+    // Each topic is consumed by a task of its own, so that retrying a failed
+    // handler only holds up the events of its topic, which stay in order.
+    for topic in topics {
+        // Topics seen before resume after the last journaled event, so events
+        // published while the server was down are not missed.
+        let start = match journal::next_offset(&db, executor, &topic).await? {
+            Some(offset) => StartOffset::At(offset),
+            None => StartOffset::Latest,
+        };
+        let partition_client = Arc::new(client.partition_client(topic.clone(), 0)?);
+        let mut stream = StreamConsumerBuilder::new(partition_client, start)
             .with_max_wait_ms(100)
             .build();
-        stream.map(move |record| (topic.clone(), record))
-    });
-    let mut streams = futures::stream::select_all(streams);
-    let mut tasks = Vec::new();
-    let task = tokio::task::spawn_local(async move {
-        loop {
-            let ret = tokio::select! {
-                _ = shutdown.recv() => DoRepeat::No,
-                topic_record_offset_opt = streams.next() => {
-                    let (topic, record_offset_opt) = topic_record_offset_opt.expect("some record");
-                    let (record, _) = record_offset_opt.expect("no error");
-                    api.handle_event(topic, record.record.key, record.record.value).await?;
-                    DoRepeat::Yes
-                },
-            };
-            if matches!(ret, DoRepeat::No) {
-                break;
+        let api = api.clone();
+        let db = db.clone();
+        let shutdown = shutdown.clone();
+        let task = tokio::task::spawn_local(async move {
+            loop {
+                let ret = tokio::select! {
+                    _ = shutdown.recv() => DoRepeat::No,
+                    record_offset_opt = stream.next() => {
+                        match record_offset_opt.expect("some record") {
+                            Ok((record, _)) => {
+                                let offset = record.offset;
+                                let key = record.record.key;
+                                let value = record.record.value;
+                                if let Err(e) = handle(
+                                    &api,
+                                    &db,
+                                    executor,
+                                    topic.clone(),
+                                    offset,
+                                    key,
+                                    value,
+                                    max_retries,
+                                )
+                                .await
+                                {
+                                    warn!(
+                                        "Failed to handle event {} of Kafka topic {}: {:?}",
+                                        offset, topic, e
+                                    );
+                                }
+                            }
+                            Err(e) => {
+                                warn!("Failed to consume from Kafka topic {}: {}", topic, e)
+                            }
+                        }
+                        DoRepeat::Yes
+                    },
+                };
+                if matches!(ret, DoRepeat::No) {
+                    break;
+                }
             }
-        }
-        Ok(())
-    });
-    tasks.push(task);
+            Ok(())
+        });
+        tasks.push(task);
+    }
     Ok(tasks)
 }
 
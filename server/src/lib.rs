@@ -6,6 +6,7 @@
 use once_cell::sync::Lazy;
 
 pub use crate::auth::is_auth_entity_name;
+pub use crate::crash::{init_logger, install_crash_handler, report_error};
 pub use crate::server::{run_all, DoRepeat, Opt};
 
 pub(crate) type JsonObject = serde_json::Map<String, serde_json::Value>;
@@ -29,21 +30,47 @@ extern crate log;
 
 pub(crate) mod api;
 pub(crate) mod apply;
+pub(crate) mod archive;
+pub(crate) mod assets;
 pub(crate) mod auth;
+pub(crate) mod auth_provider;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub(crate) mod blobs;
+pub(crate) mod connectors;
+pub(crate) mod crash;
 pub(crate) mod datastore;
 pub(crate) mod deno;
+pub(crate) mod faults;
+pub(crate) mod inflight;
 pub(crate) mod internal;
 pub(crate) mod introspect;
+pub(crate) mod jobs;
+pub(crate) mod journal;
 pub(crate) mod kafka;
+pub(crate) mod memory;
+pub(crate) mod migration_gate;
+pub(crate) mod notifications;
+pub(crate) mod outbox;
+pub(crate) mod partitions;
 pub(crate) mod policies;
 pub(crate) mod prefix_map;
+pub(crate) mod privacy;
+pub(crate) mod profiling;
+pub(crate) mod quotas;
 pub(crate) mod rcmut;
+pub(crate) mod read_only;
+pub(crate) mod response_cache;
+pub(crate) mod row_policies;
 pub(crate) mod rpc;
 pub(crate) mod runtime;
 pub(crate) mod secrets;
 pub(crate) mod server;
+pub(crate) mod tasks;
 pub(crate) mod types;
 pub(crate) mod vecmap;
+pub(crate) mod webhooks;
+pub(crate) mod websocket;
 
 #[allow(clippy::all)]
 pub(crate) mod proto {
@@ -12,9 +12,16 @@ use std::env;
 use std::ffi::CString;
 use std::io::Write;
 use std::path::PathBuf;
-use structopt::StructOpt;
+
+/// Name of the project-local configuration file, looked up in the current
+/// directory.
+const PROJECT_CONFIG_FILE: &str = "chiseld.toml";
 
 fn find_default_config_path() -> Option<PathBuf> {
+    let project_config_path = PathBuf::from(PROJECT_CONFIG_FILE);
+    if project_config_path.exists() {
+        return Some(project_config_path);
+    }
     let config_dir = dirs::config_dir()?.join("chiselstrike");
     let config_path = config_dir.join("config.toml");
     config_path.exists().then(|| config_path)
@@ -22,19 +29,6 @@ fn find_default_config_path() -> Option<PathBuf> {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info"))
-        .format(|buf, record| {
-            writeln!(
-                buf,
-                "[{}] {} - {}",
-                buf.timestamp(),
-                record.level(),
-                record.args()
-            )
-        })
-        .filter_module("sqlx::query", LevelFilter::Warn)
-        .init();
-
     let args: Vec<CString> = env::args().map(|x| CString::new(x).unwrap()).collect();
     let exe = env::current_exe()?.into_os_string().into_string().unwrap();
 
@@ -42,7 +36,7 @@ async fn main() -> Result<()> {
         let default_path = find_default_config_path();
         let opt = match default_path {
             Some(ref path) => server::Opt::from_file(path).await?,
-            None => server::Opt::from_args(),
+            None => server::Opt::from_toml("")?,
         };
 
         match opt.config {
@@ -51,13 +45,33 @@ async fn main() -> Result<()> {
         }
     };
 
+    let mut logger =
+        env_logger::Builder::from_env(Env::default().default_filter_or(&opt.log_filter));
+    logger
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "[{}] {} - {}",
+                buf.timestamp(),
+                record.level(),
+                record.args()
+            )
+        })
+        .filter_module("sqlx::query", LevelFilter::Warn);
+    server::init_logger(logger)?;
+    server::install_crash_handler(opt.crash_dir.clone(), opt.crash_report_url.clone());
+
     if opt.show_config {
         let config = serde_json::to_string(&opt)?;
         println!("{config}");
         return Ok(());
     }
 
-    if let server::DoRepeat::Yes = server::run_all(opt).await? {
+    let repeat = server::run_all(opt).await.map_err(|e| {
+        server::report_error(&e);
+        e
+    })?;
+    if let server::DoRepeat::Yes = repeat {
         info!("Restarting");
         execv(&CString::new(exe).unwrap(), &args).unwrap();
     }
@@ -0,0 +1,194 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Memory used by the V8 isolates of the executors and by the modules of each
+//! version, so that operators can rightsize worker counts and heap limits.
+//!
+//! Each executor samples the heap statistics of its isolate every few
+//! seconds. When the heap of an isolate grows past `--heap-warn-percent` of
+//! its limit, which `--v8-flags=--max-old-space-size` configures, a warning
+//! is logged. It is logged again only once the heap has shrunk back below.
+//!
+//! The size of the modules of a version is the size of their source, as they
+//! are compiled. Modules are never unloaded, so this includes the modules
+//! that later applies replaced, and deleted versions keep their memory until
+//! chiseld restarts, although they are no longer reported.
+//!
+//! Both are exported at `/metrics` in the Prometheus text format, and shown
+//! by `chisel status --verbose`.
+
+use crate::notifications::{notify, EventKind};
+use crate::quotas::escape_label;
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How often executors sample the heap statistics of their isolate.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+const MIB: u64 = 1024 * 1024;
+
+static WARN_PERCENT: AtomicU64 = AtomicU64::new(90);
+static ISOLATES: Lazy<Mutex<BTreeMap<usize, Isolate>>> = Lazy::new(Default::default);
+static MODULES: Lazy<Mutex<BTreeMap<String, u64>>> = Lazy::new(Default::default);
+
+/// Heap statistics of an isolate, in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapUsage {
+    pub used: u64,
+    pub total: u64,
+    pub limit: u64,
+    /// Memory held outside of the heap by JavaScript objects, like the
+    /// contents of array buffers.
+    pub external: u64,
+}
+
+impl HeapUsage {
+    fn is_near_limit(&self, warn_percent: u64) -> bool {
+        self.limit > 0 && self.used * 100 >= self.limit * warn_percent
+    }
+}
+
+#[derive(Default)]
+struct Isolate {
+    usage: HeapUsage,
+    warned: bool,
+}
+
+/// Warns about isolates whose heap is over `warn_percent` of its limit.
+pub fn configure(warn_percent: u64) -> Result<()> {
+    anyhow::ensure!(
+        (1..=100).contains(&warn_percent),
+        "the heap warning threshold must be a percentage between 1 and 100"
+    );
+    WARN_PERCENT.store(warn_percent, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Records a sample of the heap of the isolate of `executor`.
+pub fn record_heap(executor: usize, usage: HeapUsage) {
+    let warn_percent = WARN_PERCENT.load(Ordering::Relaxed);
+    let near_limit = usage.is_near_limit(warn_percent);
+    let mut isolates = ISOLATES.lock().unwrap();
+    let isolate = isolates.entry(executor).or_default();
+    if near_limit && !isolate.warned {
+        warn!(
+            "Executor {} is using {} MiB of its {} MiB heap limit. Consider raising \
+             it with --v8-flags=--max-old-space-size or adding executor threads",
+            executor,
+            usage.used / MIB,
+            usage.limit / MIB
+        );
+        notify(
+            EventKind::HeapNearLimit,
+            &format!("executor {}", executor),
+            format!(
+                "executor {} is using {} MiB of its {} MiB heap limit",
+                executor,
+                usage.used / MIB,
+                usage.limit / MIB
+            ),
+        );
+    }
+    *isolate = Isolate {
+        usage,
+        warned: near_limit,
+    };
+}
+
+/// The latest heap sample of each executor, by executor.
+pub fn heaps() -> Vec<(usize, HeapUsage)> {
+    let isolates = ISOLATES.lock().unwrap();
+    isolates.iter().map(|(id, i)| (*id, i.usage)).collect()
+}
+
+/// Records that the modules of `api_version` take `bytes` of source.
+pub fn record_modules(api_version: &str, bytes: u64) {
+    MODULES
+        .lock()
+        .unwrap()
+        .insert(api_version.to_owned(), bytes);
+}
+
+pub fn forget_version(api_version: &str) {
+    MODULES.lock().unwrap().remove(api_version);
+}
+
+/// The size of the modules of each version, by version.
+pub fn modules() -> Vec<(String, u64)> {
+    let modules = MODULES.lock().unwrap();
+    modules
+        .iter()
+        .map(|(v, bytes)| (v.clone(), *bytes))
+        .collect()
+}
+
+/// Renders the memory usage in the Prometheus text format.
+pub fn metrics() -> String {
+    let heaps = heaps();
+    let metrics: [(&str, &str, fn(&HeapUsage) -> u64); 4] = [
+        ("heap_used_bytes", "Heap used by the isolate.", |u| u.used),
+        ("heap_total_bytes", "Heap allocated by the isolate.", |u| {
+            u.total
+        }),
+        ("heap_limit_bytes", "Heap limit of the isolate.", |u| {
+            u.limit
+        }),
+        (
+            "external_bytes",
+            "Memory held by the isolate outside of its heap.",
+            |u| u.external,
+        ),
+    ];
+    let mut out = String::new();
+    for (name, help, value) in metrics {
+        writeln!(out, "# HELP chiseld_isolate_{} {}", name, help).unwrap();
+        writeln!(out, "# TYPE chiseld_isolate_{} gauge", name).unwrap();
+        for (executor, usage) in &heaps {
+            writeln!(
+                out,
+                "chiseld_isolate_{}{{executor=\"{}\"}} {}",
+                name,
+                executor,
+                value(usage)
+            )
+            .unwrap();
+        }
+    }
+    writeln!(
+        out,
+        "# HELP chiseld_version_module_bytes Source size of the modules of the version."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE chiseld_version_module_bytes gauge").unwrap();
+    for (api_version, bytes) in modules() {
+        writeln!(
+            out,
+            "chiseld_version_module_bytes{{version=\"{}\"}} {}",
+            escape_label(&api_version),
+            bytes
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heap_is_near_limit_past_the_threshold() {
+        let usage = |used| HeapUsage {
+            used,
+            limit: 200,
+            ..Default::default()
+        };
+        assert!(!usage(179).is_near_limit(90));
+        assert!(usage(180).is_near_limit(90));
+        assert!(!HeapUsage::default().is_near_limit(90));
+    }
+}
@@ -0,0 +1,114 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Manual approval of destructive schema changes, for teams whose change
+//! management forbids running them automatically.
+//!
+//! When chiseld runs with `--require-migration-approval`, an apply that would
+//! drop entities or fields is staged instead of run. `chisel migrate approve`
+//! approves its changes and runs it; an apply of the version with other
+//! destructive changes is staged again.
+
+use crate::proto::{type_plan::Action, ApplyPlan, ChiselApplyRequest};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+#[derive(Clone)]
+pub struct StagedApply {
+    pub id: u64,
+    pub request: ChiselApplyRequest,
+    /// The destructive changes that need approval.
+    pub changes: Vec<String>,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STAGED: Lazy<Mutex<Vec<StagedApply>>> = Lazy::new(Default::default);
+/// Approved changes not applied yet, by version.
+static APPROVED: Lazy<Mutex<HashMap<String, Vec<String>>>> = Lazy::new(Default::default);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Makes destructive changes need approval.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Describes the changes of `plan` that drop models or fields.
+pub fn destructive_changes(plan: &ApplyPlan) -> Vec<String> {
+    let mut changes = vec![];
+    for ty in &plan.types {
+        match ty.action() {
+            Action::Create => {}
+            Action::Drop => changes.push(format!("drop model {}", ty.name)),
+            Action::Alter => {
+                for change in &ty.changes {
+                    if change.starts_with("drop ") && !change.starts_with("drop index ") {
+                        changes.push(format!("{}: {}", ty.name, change));
+                    }
+                }
+            }
+        }
+    }
+    changes
+}
+
+/// Returns whether an apply of `api_version` with the destructive `changes`
+/// can run, because there are none or they were all approved. The approval
+/// is used up.
+pub fn check(api_version: &str, changes: &[String]) -> bool {
+    if changes.is_empty() {
+        return true;
+    }
+    let mut approved = APPROVED.lock().unwrap();
+    let ok = approved.get(api_version).map_or(false, |approved| {
+        changes.iter().all(|change| approved.contains(change))
+    });
+    if ok {
+        approved.remove(api_version);
+    }
+    ok
+}
+
+/// Stages `request`, which has the destructive `changes`, returning its id.
+/// Replaces the apply staged for the same version, if any.
+pub fn stage(request: ChiselApplyRequest, changes: Vec<String>) -> u64 {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut staged = STAGED.lock().unwrap();
+    staged.retain(|s| s.request.version != request.version);
+    staged.push(StagedApply {
+        id,
+        request,
+        changes,
+    });
+    id
+}
+
+/// Approves the changes of staged apply `id`, returning it for the caller to
+/// run. Returns None if there is no such apply.
+pub fn approve(id: u64) -> Option<StagedApply> {
+    let mut staged = STAGED.lock().unwrap();
+    let i = staged.iter().position(|s| s.id == id)?;
+    let apply = staged.remove(i);
+    APPROVED
+        .lock()
+        .unwrap()
+        .insert(apply.request.version.clone(), apply.changes.clone());
+    Some(apply)
+}
+
+/// Drops staged apply `id`. Returns false if there is no such apply.
+pub fn reject(id: u64) -> bool {
+    let mut staged = STAGED.lock().unwrap();
+    let len = staged.len();
+    staged.retain(|s| s.id != id);
+    staged.len() < len
+}
+
+/// Lists the staged applies, oldest first.
+pub fn list() -> Vec<StagedApply> {
+    STAGED.lock().unwrap().clone()
+}
@@ -0,0 +1,229 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Notifications of operational events, so that operators learn about
+//! failures from chiseld instead of from their users.
+//!
+//! Events are sent to the URLs given with `--notify-url`. Slack and Discord
+//! incoming webhooks are recognized by their host and get a message in their
+//! format. Other URLs are generic webhooks, which get a JSON object with the
+//! kind of the event, what it is about and a message.
+//!
+//! An event of a kind about the same thing is sent at most once per
+//! `THROTTLE`, so that a failure repeating on every request doesn't flood
+//! the channel. Events are delivered by a task, and one failing to be
+//! delivered is only logged.
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// How long the events of a kind about the same thing are not sent again.
+const THROTTLE: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum EventKind {
+    /// An apply failed.
+    ApplyFailed,
+    /// The heap of an isolate is close to its limit, past which the isolate
+    /// crashes.
+    HeapNearLimit,
+    /// A tenant went over its quota.
+    QuotaExceeded,
+    /// A request went over the query budget.
+    QueryBudgetExceeded,
+    /// An event handler kept failing and the event was dropped.
+    EventDropped,
+    /// A scheduled job failed.
+    JobFailed,
+}
+
+impl EventKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::ApplyFailed => "apply_failed",
+            Self::HeapNearLimit => "heap_near_limit",
+            Self::QuotaExceeded => "quota_exceeded",
+            Self::QueryBudgetExceeded => "query_budget_exceeded",
+            Self::EventDropped => "event_dropped",
+            Self::JobFailed => "job_failed",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Sink {
+    Slack(Url),
+    Discord(Url),
+    Webhook(Url),
+}
+
+impl Sink {
+    fn parse(url: &str) -> Result<Self> {
+        let url = Url::parse(url).with_context(|| format!("invalid notification URL {}", url))?;
+        anyhow::ensure!(
+            matches!(url.scheme(), "http" | "https"),
+            "notification URL {} must be http or https",
+            url
+        );
+        Ok(match url.host_str() {
+            Some("hooks.slack.com") => Self::Slack(url),
+            Some("discord.com" | "discordapp.com") if url.path().starts_with("/api/webhooks/") => {
+                Self::Discord(url)
+            }
+            _ => Self::Webhook(url),
+        })
+    }
+
+    fn url(&self) -> &Url {
+        match self {
+            Self::Slack(url) | Self::Discord(url) | Self::Webhook(url) => url,
+        }
+    }
+
+    fn payload(&self, event: &Event) -> serde_json::Value {
+        let text = format!("chiseld: {}", event.message);
+        match self {
+            Self::Slack(_) => json!({ "text": text }),
+            Self::Discord(_) => json!({ "content": text }),
+            Self::Webhook(_) => json!({
+                "event": event.kind.name(),
+                "subject": event.subject,
+                "message": event.message,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Event {
+    kind: EventKind,
+    subject: String,
+    message: String,
+}
+
+static SINKS: Lazy<Mutex<Vec<Sink>>> = Lazy::new(Default::default);
+
+/// When each kind of event was last sent, by kind and subject.
+static SENT: Lazy<Mutex<HashMap<(EventKind, String), Instant>>> = Lazy::new(Default::default);
+
+static QUEUE: Lazy<(async_channel::Sender<Event>, async_channel::Receiver<Event>)> =
+    Lazy::new(async_channel::unbounded);
+
+pub(crate) fn configure(urls: &[String]) -> Result<()> {
+    let sinks = urls
+        .iter()
+        .map(|url| Sink::parse(url))
+        .collect::<Result<Vec<_>>>()?;
+    *SINKS.lock().unwrap() = sinks;
+    Ok(())
+}
+
+/// Sends an event of `kind` about `subject`, unless one was sent lately.
+pub(crate) fn notify(kind: EventKind, subject: &str, message: String) {
+    if SINKS.lock().unwrap().is_empty() {
+        return;
+    }
+    if !should_send(kind, subject, Instant::now()) {
+        return;
+    }
+    let event = Event {
+        kind,
+        subject: subject.to_owned(),
+        message,
+    };
+    // The queue is unbounded, so this only fails once the channel is closed.
+    QUEUE.0.try_send(event).ok();
+}
+
+fn should_send(kind: EventKind, subject: &str, now: Instant) -> bool {
+    let mut sent = SENT.lock().unwrap();
+    match sent.get(&(kind, subject.to_owned())) {
+        Some(last) if now.duration_since(*last) < THROTTLE => false,
+        _ => {
+            sent.insert((kind, subject.to_owned()), now);
+            true
+        }
+    }
+}
+
+pub(crate) fn spawn(shutdown: async_channel::Receiver<()>) -> JoinHandle<Result<()>> {
+    tokio::task::spawn(async move {
+        let http = reqwest::Client::new();
+        loop {
+            let event = tokio::select! {
+                event = QUEUE.1.recv() => event?,
+                _ = shutdown.recv() => {
+                    break;
+                }
+            };
+            let sinks = SINKS.lock().unwrap().clone();
+            for sink in &sinks {
+                if let Err(e) = deliver(&http, sink, &event).await {
+                    warn!(
+                        "Could not send the {} notification to {}: {:?}",
+                        event.kind.name(),
+                        sink.url(),
+                        e
+                    );
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+async fn deliver(http: &reqwest::Client, sink: &Sink, event: &Event) -> Result<()> {
+    http.post(sink.url().clone())
+        .json(&sink.payload(event))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sinks() {
+        let event = Event {
+            kind: EventKind::ApplyFailed,
+            subject: "dev".to_owned(),
+            message: "apply of dev failed".to_owned(),
+        };
+        let slack = Sink::parse("https://hooks.slack.com/services/T0/B0/x").unwrap();
+        assert_eq!(
+            slack.payload(&event),
+            json!({"text": "chiseld: apply of dev failed"})
+        );
+        let discord = Sink::parse("https://discord.com/api/webhooks/1/x").unwrap();
+        assert_eq!(
+            discord.payload(&event),
+            json!({"content": "chiseld: apply of dev failed"})
+        );
+        let webhook = Sink::parse("http://localhost:8080/alerts").unwrap();
+        assert_eq!(
+            webhook.payload(&event),
+            json!({"event": "apply_failed", "subject": "dev", "message": "apply of dev failed"})
+        );
+        assert!(Sink::parse("ftp://example.com").is_err());
+    }
+
+    #[test]
+    fn throttled() {
+        let now = Instant::now();
+        let kind = EventKind::QuotaExceeded;
+        assert!(should_send(kind, "dev/acme", now));
+        assert!(!should_send(kind, "dev/acme", now + THROTTLE / 2));
+        assert!(should_send(kind, "dev/other", now));
+        assert!(should_send(EventKind::EventDropped, "dev/acme", now));
+        assert!(should_send(kind, "dev/acme", now + THROTTLE));
+    }
+}
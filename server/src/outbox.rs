@@ -0,0 +1,227 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Transactional outbox.
+//!
+//! Endpoints enqueue outbox messages in the same database transaction as
+//! their writes, so a message exists if and only if the writes were
+//! committed. A delivery task periodically pushes pending messages to their
+//! targets and removes them once delivered. Failed deliveries are retried,
+//! which makes delivery at-least-once: targets can use the
+//! `ChiselStrike-Outbox-Message-Id` header to discard duplicates.
+//!
+//! Targets are URLs: `http(s)://...` targets are webhooks receiving the
+//! payload in a POST request, and `kafka:<topic>` targets are Kafka topics on
+//! the broker given by `--kafka-connection`.
+
+use crate::datastore::DbConnection;
+use anyhow::{Context, Result};
+use reqwest::Url;
+use rskafka::client::partition::Compression;
+use rskafka::client::{Client as KafkaClient, ClientBuilder};
+use rskafka::record::Record;
+use sqlx::any::Any;
+use sqlx::{Executor, Row, Transaction};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+/// How often the delivery task looks for pending messages.
+const DELIVERY_INTERVAL: Duration = Duration::from_millis(1000);
+/// Maximum number of messages delivered in one round.
+const DELIVERY_BATCH: i64 = 100;
+/// Messages that failed this many times are no longer retried. They stay in
+/// the outbox table so that they can be inspected.
+const MAX_ATTEMPTS: i32 = 10;
+
+/// Where an outbox message is delivered to.
+enum Target {
+    Webhook(Url),
+    Kafka { topic: String },
+}
+
+impl Target {
+    fn parse(target: &str) -> Result<Self> {
+        let url =
+            Url::parse(target).with_context(|| format!("invalid outbox target `{}`", target))?;
+        match url.scheme() {
+            "http" | "https" => Ok(Target::Webhook(url)),
+            "kafka" => {
+                let topic = url.path();
+                anyhow::ensure!(
+                    !topic.is_empty() && !topic.contains('/'),
+                    "invalid Kafka topic in outbox target `{}`",
+                    target
+                );
+                Ok(Target::Kafka {
+                    topic: topic.to_owned(),
+                })
+            }
+            scheme => anyhow::bail!("unsupported outbox target scheme `{}`", scheme),
+        }
+    }
+}
+
+/// Enqueues a message for `target` as part of `transaction`.
+pub(crate) async fn enqueue(
+    transaction: &mut Transaction<'_, Any>,
+    api_version: &str,
+    target: &str,
+    payload: &str,
+) -> Result<()> {
+    Target::parse(target)?;
+    let query = sqlx::query(
+        r#"
+        INSERT INTO outbox (api_version, target, payload, attempts)
+        VALUES ($1, $2, $3, 0)"#,
+    )
+    .bind(api_version.to_owned())
+    .bind(target.to_owned())
+    .bind(payload.to_owned());
+    transaction
+        .execute(query)
+        .await
+        .context("failed to enqueue outbox message")?;
+    Ok(())
+}
+
+struct PendingMessage {
+    id: i32,
+    target: String,
+    payload: String,
+}
+
+async fn pending_messages(db: &DbConnection) -> Result<Vec<PendingMessage>> {
+    let query = sqlx::query(
+        r#"
+        SELECT message_id, target, payload
+        FROM outbox
+        WHERE attempts < $1
+        ORDER BY message_id
+        LIMIT $2"#,
+    )
+    .bind(MAX_ATTEMPTS)
+    .bind(DELIVERY_BATCH);
+    let rows = query.fetch_all(&db.pool).await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| PendingMessage {
+            id: row.get("message_id"),
+            target: row.get("target"),
+            payload: row.get("payload"),
+        })
+        .collect())
+}
+
+/// Clients used to deliver messages to their targets.
+struct Deliverer {
+    http: reqwest::Client,
+    kafka_connection: Option<String>,
+    // Connected lazily, when the first Kafka message is delivered.
+    kafka: Option<KafkaClient>,
+}
+
+impl Deliverer {
+    fn new(kafka_connection: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            kafka_connection,
+            kafka: None,
+        }
+    }
+
+    async fn deliver(&mut self, message: &PendingMessage) -> Result<()> {
+        match Target::parse(&message.target)? {
+            Target::Webhook(url) => {
+                let response = self
+                    .http
+                    .post(url)
+                    .header("content-type", "application/json")
+                    .header("ChiselStrike-Outbox-Message-Id", message.id.to_string())
+                    .body(message.payload.clone())
+                    .send()
+                    .await?;
+                response.error_for_status()?;
+            }
+            Target::Kafka { topic } => {
+                let client = self.kafka_client().await?;
+                let partition_client = client.partition_client(topic, 0)?;
+                let record = Record {
+                    key: Some(message.id.to_string().into_bytes()),
+                    value: Some(message.payload.clone().into_bytes()),
+                    headers: BTreeMap::from([(
+                        "ChiselStrike-Outbox-Message-Id".to_owned(),
+                        message.id.to_string().into_bytes(),
+                    )]),
+                    timestamp: time::OffsetDateTime::now_utc(),
+                };
+                partition_client
+                    .produce(vec![record], Compression::NoCompression)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn kafka_client(&mut self) -> Result<&KafkaClient> {
+        if self.kafka.is_none() {
+            let connection = self
+                .kafka_connection
+                .clone()
+                .context("outbox message targets Kafka, but no Kafka connection is configured")?;
+            self.kafka = Some(ClientBuilder::new(vec![connection]).build().await?);
+        }
+        Ok(self.kafka.as_ref().unwrap())
+    }
+}
+
+async fn deliver_pending(db: &DbConnection, deliverer: &mut Deliverer) -> Result<()> {
+    for message in pending_messages(db).await? {
+        match deliverer.deliver(&message).await {
+            Ok(()) => {
+                let query =
+                    sqlx::query("DELETE FROM outbox WHERE message_id = $1").bind(message.id);
+                db.pool.execute(query).await?;
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to deliver outbox message {} to {}: {}",
+                    message.id, message.target, e
+                );
+                let query = sqlx::query(
+                    r#"
+                    UPDATE outbox
+                    SET attempts = attempts + 1, last_error = $1
+                    WHERE message_id = $2"#,
+                )
+                .bind(e.to_string())
+                .bind(message.id);
+                db.pool.execute(query).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Spawns the task that delivers outbox messages until `shutdown` fires.
+pub(crate) fn spawn(
+    db: DbConnection,
+    kafka_connection: Option<String>,
+    shutdown: async_channel::Receiver<()>,
+) -> JoinHandle<Result<()>> {
+    tokio::task::spawn(async move {
+        let mut deliverer = Deliverer::new(kafka_connection);
+        loop {
+            tokio::select! {
+                _ = sleep(DELIVERY_INTERVAL) => {},
+                _ = shutdown.recv() => {
+                    break;
+                }
+            };
+            if let Err(e) = deliver_pending(&db, &mut deliverer).await {
+                warn!("Outbox delivery failed: {:?}", e);
+            }
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,67 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Maintenance of the tables of entities partitioned by time.
+//!
+//! A task periodically creates the partitions of the upcoming intervals,
+//! before rows are inserted into them, and drops those past the retention of
+//! their entity.
+
+use crate::datastore::{DbConnection, MetaService, QueryEngine};
+use anyhow::Result;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+/// How often the partitions are maintained. Tables have partitions for a few
+/// intervals ahead, so this only needs to be well under a day.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub(crate) fn spawn(
+    db: DbConnection,
+    shutdown: async_channel::Receiver<()>,
+) -> JoinHandle<Result<()>> {
+    tokio::task::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = sleep(MAINTENANCE_INTERVAL) => {},
+                _ = shutdown.recv() => {
+                    break;
+                }
+            };
+            if let Err(e) = maintain_partitions(&db).await {
+                warn!("Partition maintenance failed: {:?}", e);
+            }
+        }
+        Ok(())
+    })
+}
+
+async fn maintain_partitions(db: &DbConnection) -> Result<()> {
+    let meta = MetaService::local_connection(db, 1).await?;
+    let query_engine = QueryEngine::local_connection(db, 1).await?;
+    let ts = meta.load_type_system().await?;
+    let today = OffsetDateTime::now_utc().date();
+    for version in ts.versions.values() {
+        for entity in version.custom_types.values() {
+            if entity.partition().is_none() {
+                continue;
+            }
+            // Each table in its own transaction, so that one failing doesn't
+            // hold back the others.
+            let mut transaction = query_engine.begin_transaction().await?;
+            match query_engine
+                .maintain_partitions(&mut transaction, entity, today)
+                .await
+            {
+                Ok(()) => QueryEngine::commit_transaction(transaction).await?,
+                Err(e) => warn!(
+                    "Could not maintain the partitions of {}: {:?}",
+                    entity.name(),
+                    e
+                ),
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,22 +1,123 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
+use crate::auth_provider::hex;
+use crate::datastore::expr::Expr;
 use crate::prefix_map::PrefixMap;
+use crate::row_policies::RowPolicy;
 use crate::types::ObjectType;
+use crate::webhooks::Webhook;
 use crate::JsonObject;
 use anyhow::Result;
 use chiselc::parse::ParserContext;
 use hyper::Request;
 use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use yaml_rust::{Yaml, YamlLoader};
 
+/// How values read from storage are transformed before they are returned.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Transform {
+    /// Replaces the value with "xxxxx".
+    Anonymize,
+    /// Replaces the value with null.
+    Redact,
+    /// Replaces the value with the hex SHA-256 of its text, so that equal
+    /// values can still be told apart from different ones. Null stays null.
+    Hash,
+    /// Keeps the first characters of strings, or the first elements of
+    /// arrays.
+    Truncate(usize),
+    /// Masks the local part of email addresses but for its first
+    /// character, like `j***@example.com`. Other strings are masked whole.
+    MaskEmail,
+}
+
+impl Transform {
+    /// Parses the transform written as `spec`, if it is one.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let transform = match spec {
+            "anonymize" => Transform::Anonymize,
+            "redact" => Transform::Redact,
+            "hash" => Transform::Hash,
+            "mask-email" => Transform::MaskEmail,
+            _ => {
+                let length = spec.strip_prefix("truncate(")?.strip_suffix(')')?;
+                Transform::Truncate(length.trim().parse().ok()?)
+            }
+        };
+        Some(transform)
+    }
+
+    /// Transforms `value`, as read from storage.
+    pub fn apply(&self, value: Value) -> Value {
+        match self {
+            Transform::Anonymize => anonymize(value),
+            Transform::Redact => Value::Null,
+            Transform::Hash => {
+                let text = match &value {
+                    Value::Null => return value,
+                    Value::String(s) => s.clone(),
+                    _ => value.to_string(),
+                };
+                json!(hex(&Sha256::digest(text.as_bytes())))
+            }
+            Transform::Truncate(length) => match value {
+                Value::String(s) => json!(s.chars().take(*length).collect::<String>()),
+                Value::Array(mut elements) => {
+                    elements.truncate(*length);
+                    Value::Array(elements)
+                }
+                _ => value,
+            },
+            Transform::MaskEmail => match value {
+                Value::String(s) => json!(mask_email(&s)),
+                _ => value,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transform::Anonymize => write!(f, "anonymize"),
+            Transform::Redact => write!(f, "redact"),
+            Transform::Hash => write!(f, "hash"),
+            Transform::Truncate(length) => write!(f, "truncate({})", length),
+            Transform::MaskEmail => write!(f, "mask-email"),
+        }
+    }
+}
+
+/// `email` with all the characters of its local part but the first masked.
+fn mask_email(email: &str) -> String {
+    let (local, domain) = match email.rsplit_once('@') {
+        Some((local, domain)) => (local, Some(domain)),
+        None => (email, None),
+    };
+    let mut masked: String = match domain {
+        Some(_) => local.chars().take(1).collect(),
+        None => String::new(),
+    };
+    masked.extend(std::iter::repeat('*').take(local.chars().count() - masked.chars().count()));
+    if let Some(domain) = domain {
+        masked.push('@');
+        masked.push_str(domain);
+    }
+    masked
+}
+
 /// Different kinds of policies.
 #[derive(Clone)]
 pub enum Kind {
     /// How this policy transforms values read from storage.
-    Transform(fn(Value) -> Value),
+    Transform(Transform),
     /// Field is of AuthUser type and must match the user currently logged in.
     MatchLogin,
+    /// Field holds the tenant of the object, which must match the tenant of the request.
+    MatchTenant,
     /// Field will not be in a query's resulting json object.
     Omit,
 }
@@ -35,11 +136,15 @@ pub type LabelPolicies = HashMap<String, Policy>;
 #[derive(Clone, Default, Debug)]
 pub struct FieldPolicies {
     /// Maps a field name to the transformation we apply to that field's values.
-    pub transforms: HashMap<String, fn(Value) -> Value>,
+    pub transforms: HashMap<String, Transform>,
     /// Names of fields that must equal the currently logged-in user.
     pub match_login: HashSet<String>,
     /// ID of the currently logged-in user.
     pub current_userid: Option<String>,
+    /// Names of fields that must equal the tenant of the request.
+    pub match_tenant: HashSet<String>,
+    /// Tenant of the request.
+    pub current_tenant: Option<String>,
     /// Names of fields which will be excluded from query's resulting json object.
     pub omit: HashSet<String>,
 }
@@ -73,6 +178,100 @@ impl UserAuthorization {
     }
 }
 
+/// Describes claim-based authorization.  An endpoint request will only be allowed if the claims about its user match
+/// the patterns required for the longest path prefix present here.
+#[derive(Clone, Default, Debug)]
+pub struct ClaimAuthorization {
+    /// Claim names and the regexes their values must match, by path prefix.
+    paths: PrefixMap<Vec<(String, regex::Regex)>>,
+}
+
+impl ClaimAuthorization {
+    /// Is a user with these claims allowed to execute the endpoint at this path?
+    pub fn is_allowed(&self, claims: Option<&JsonObject>, path: &str) -> bool {
+        match self.paths.longest_prefix(path) {
+            None => true,
+            Some((_, required)) => match claims {
+                None => false, // Must be logged in if path requires claims.
+                Some(claims) => required
+                    .iter()
+                    .all(|(name, pattern)| claim_matches(claims.get(name), pattern)),
+            },
+        }
+    }
+
+    /// Requires claims matching regexes for every endpoint under this path.  Longer paths override existing
+    /// prefixes.  Error if this same path has already been added.
+    fn add(&mut self, path: &str, claims: Vec<(String, regex::Regex)>) -> Result<()> {
+        if self.paths.insert(path.into(), claims).is_some() {
+            anyhow::bail!("Repeated path in claim authorization: {path}");
+        }
+        Ok(())
+    }
+}
+
+/// Describes which endpoints can only be called with an API key, as created by `chisel apikey create`.
+#[derive(Clone, Default, Debug)]
+pub struct ApiKeyAuthorization {
+    /// Whether an API key is required, by path prefix.
+    paths: PrefixMap<bool>,
+}
+
+impl ApiKeyAuthorization {
+    /// Is a request made with an API key, or without one, allowed to execute the endpoint at this path?
+    pub fn is_allowed(&self, has_api_key: bool, path: &str) -> bool {
+        match self.paths.longest_prefix(path) {
+            Some((_, true)) => has_api_key,
+            _ => true,
+        }
+    }
+
+    /// Requires an API key, or not, for every endpoint under this path.  Longer paths override existing prefixes.
+    /// Error if this same path has already been added.
+    fn add(&mut self, path: &str, required: bool) -> Result<()> {
+        if self.paths.insert(path.into(), required).is_some() {
+            anyhow::bail!("Repeated path in API key authorization: {path}");
+        }
+        Ok(())
+    }
+}
+
+/// Describes the transforms that requests to some paths apply to the fields with some labels, instead of those of
+/// the labels themselves.
+#[derive(Clone, Default, Debug)]
+pub struct RouteTransforms {
+    /// Transforms by label, by path prefix.
+    paths: PrefixMap<HashMap<String, Transform>>,
+}
+
+impl RouteTransforms {
+    /// The transforms by label of the longest path prefix of this path.
+    pub fn get(&self, path: &str) -> Option<&HashMap<String, Transform>> {
+        self.paths
+            .longest_prefix(path)
+            .map(|(_, transforms)| transforms)
+    }
+
+    /// Transforms the fields with these labels for every endpoint under this path.  Longer paths override existing
+    /// prefixes.  Error if this same path has already been added.
+    fn add(&mut self, path: &str, transforms: HashMap<String, Transform>) -> Result<()> {
+        if self.paths.insert(path.into(), transforms).is_some() {
+            anyhow::bail!("Repeated path in route transforms: {path}");
+        }
+        Ok(())
+    }
+}
+
+/// Does `claim` match `pattern`?  A list of values, such as roles or groups, matches if any of them does.
+fn claim_matches(claim: Option<&Value>, pattern: &regex::Regex) -> bool {
+    match claim {
+        Some(Value::String(s)) => pattern.is_match(s),
+        Some(v @ (Value::Number(_) | Value::Bool(_))) => pattern.is_match(&v.to_string()),
+        Some(Value::Array(values)) => values.iter().any(|v| claim_matches(Some(v), pattern)),
+        _ => false,
+    }
+}
+
 /// Describes secret-based authorization.  An endpoint request will only be allowed if it includes a header
 /// specified in this struct.
 #[derive(Clone, Default, Debug)]
@@ -140,11 +339,112 @@ struct RequiredHeader {
     methods: Option<Vec<hyper::Method>>,
 }
 
+/// Where the tenant of a request comes from.
+#[derive(Clone, Debug)]
+pub enum TenantSource {
+    /// The value of a request header.
+    Header(String),
+    /// The subdomain of the Host header under this domain.
+    SubdomainOf(String),
+    /// A claim of the authenticated user.
+    Claim(String),
+}
+
+impl TenantSource {
+    /// Resolves the tenant of `req`, made by a user with `claims`.
+    pub fn resolve(&self, req: &Request<hyper::Body>, claims: &JsonObject) -> Option<String> {
+        let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+        match self {
+            TenantSource::Header(name) => header(name).map(str::to_owned),
+            TenantSource::SubdomainOf(domain) => {
+                let host = header("Host")?;
+                let host = host.split(':').next().unwrap_or(host);
+                let subdomain = host.strip_suffix(domain)?.strip_suffix('.')?;
+                Some(subdomain.to_owned()).filter(|s| !s.is_empty() && !s.contains('.'))
+            }
+            TenantSource::Claim(name) => claim_tenant(claims, name),
+        }
+    }
+}
+
+/// The tenant given by claim `name`, if it is a string or a number.
+pub fn claim_tenant(claims: &JsonObject, name: &str) -> Option<String> {
+    match claims.get(name)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// What a tenant may use of the server. Unset limits are unlimited.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub requests_per_minute: Option<u64>,
+    /// Milliseconds per minute spent handling the tenant's requests.
+    pub cpu_ms_per_minute: Option<u64>,
+    /// Rows of each tenant-scoped entity.
+    pub rows: Option<u64>,
+}
+
+impl Quota {
+    /// Parses a quota from `yaml`, leaving limits it doesn't set unset.
+    fn from_yaml(yaml: &Yaml) -> Result<Self> {
+        let limit = |name: &str| -> Result<Option<u64>> {
+            match &yaml[name] {
+                Yaml::BadValue => Ok(None),
+                Yaml::Integer(n) if *n >= 0 => Ok(Some(*n as u64)),
+                v => anyhow::bail!("quota {} must be a non-negative integer, got {:?}", name, v),
+            }
+        };
+        Ok(Quota {
+            requests_per_minute: limit("requests_per_minute")?,
+            cpu_ms_per_minute: limit("cpu_ms_per_minute")?,
+            rows: limit("rows")?,
+        })
+    }
+
+    /// Fills in limits unset in `self` from `fallback`.
+    fn or(&self, fallback: &Quota) -> Quota {
+        Quota {
+            requests_per_minute: self.requests_per_minute.or(fallback.requests_per_minute),
+            cpu_ms_per_minute: self.cpu_ms_per_minute.or(fallback.cpu_ms_per_minute),
+            rows: self.rows.or(fallback.rows),
+        }
+    }
+}
+
+/// Quotas of the tenants of a version.
+#[derive(Clone, Debug, Default)]
+pub struct TenantQuotas {
+    /// Applies to tenants without their own quota.
+    pub default: Quota,
+    pub tenants: HashMap<String, Quota>,
+}
+
+impl TenantQuotas {
+    pub fn get(&self, tenant: &str) -> Quota {
+        match self.tenants.get(tenant) {
+            Some(quota) => quota.or(&self.default),
+            None => self.default.clone(),
+        }
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct VersionPolicy {
     pub labels: LabelPolicies,
     pub user_authorization: UserAuthorization,
+    pub claim_authorization: ClaimAuthorization,
+    pub api_key_authorization: ApiKeyAuthorization,
     pub secret_authorization: SecretAuthorization,
+    pub route_transforms: RouteTransforms,
+    /// How requests are mapped to tenants, if the version is multi-tenant.
+    pub tenant: Option<TenantSource>,
+    pub tenant_quotas: TenantQuotas,
+    /// URLs told about the writes of the version's entities.
+    pub webhooks: Vec<Webhook>,
+    /// Rules restricting the rows of the version's entities that can be read.
+    pub rows: Vec<RowPolicy>,
 }
 
 #[derive(Clone, Default)]
@@ -163,26 +463,44 @@ impl Policies {
     pub fn make_field_policies(
         &self,
         user_id: &Option<String>,
+        tenant: &Option<String>,
         current_path: &str,
         ty: &ObjectType,
     ) -> FieldPolicies {
         let mut field_policies = FieldPolicies {
             current_userid: user_id.clone(),
+            current_tenant: tenant.clone(),
             ..Default::default()
         };
 
         if let Some(version) = self.versions.get(&ty.api_version) {
+            let route_transforms = version.route_transforms.get(current_path);
             for fld in ty.user_fields() {
                 for lbl in &fld.labels {
+                    // The transforms of the route replace those of the label, and its omission, but not its
+                    // filters.
+                    let route_transform =
+                        route_transforms.and_then(|transforms| transforms.get(lbl));
+                    if let Some(transform) = route_transform {
+                        field_policies
+                            .transforms
+                            .insert(fld.name.clone(), transform.clone());
+                    }
                     if let Some(p) = version.labels.get(lbl) {
                         if !p.except_uri.is_match(current_path) {
-                            match p.kind {
-                                Kind::Transform(f) => {
-                                    field_policies.transforms.insert(fld.name.clone(), f);
+                            match &p.kind {
+                                Kind::Transform(_) | Kind::Omit if route_transform.is_some() => {}
+                                Kind::Transform(transform) => {
+                                    field_policies
+                                        .transforms
+                                        .insert(fld.name.clone(), transform.clone());
                                 }
                                 Kind::MatchLogin => {
                                     field_policies.match_login.insert(fld.name.clone());
                                 }
+                                Kind::MatchTenant => {
+                                    field_policies.match_tenant.insert(fld.name.clone());
+                                }
                                 Kind::Omit => {
                                     field_policies.omit.insert(fld.name.clone());
                                 }
@@ -194,6 +512,21 @@ impl Policies {
         }
         field_policies
     }
+
+    /// The filters keeping the rows of `object`, of type `ty`, that user
+    /// `user_id` can read.
+    pub fn row_filters(&self, user_id: Option<&str>, ty: &ObjectType, object: &Expr) -> Vec<Expr> {
+        let version = match self.versions.get(&ty.api_version) {
+            Some(version) => version,
+            None => return vec![],
+        };
+        version
+            .rows
+            .iter()
+            .filter(|rule| rule.entity == ty.name())
+            .map(|rule| rule.read_filter(object, user_id))
+            .collect()
+    }
 }
 
 impl VersionPolicy {
@@ -213,40 +546,98 @@ impl VersionPolicy {
                 let pattern = label["except_uri"].as_str().unwrap_or("^$"); // ^$ never matches; each path has at least a '/' in it.
 
                 match label["transform"].as_str() {
-                    Some("anonymize") => {
+                    Some("omit") => {
                         policies.labels.insert(
                             name.to_owned(),
                             Policy {
-                                kind: Kind::Transform(crate::policies::anonymize),
+                                kind: Kind::Omit,
                                 except_uri: regex::Regex::new(pattern)?,
                             },
                         );
                     }
-                    Some("omit") => {
+                    Some("match_login") => {
                         policies.labels.insert(
                             name.to_owned(),
                             Policy {
-                                kind: Kind::Omit,
+                                kind: Kind::MatchLogin,
                                 except_uri: regex::Regex::new(pattern)?,
                             },
                         );
                     }
-                    Some("match_login") => {
+                    Some("match_tenant") => {
                         policies.labels.insert(
                             name.to_owned(),
                             Policy {
-                                kind: Kind::MatchLogin,
+                                kind: Kind::MatchTenant,
                                 except_uri: regex::Regex::new(pattern)?,
                             },
                         );
                     }
                     Some(x) => {
-                        anyhow::bail!("unknown transform: {} for label {}", x, name);
+                        let transform = Transform::parse(x).ok_or_else(|| {
+                            anyhow::anyhow!("unknown transform: {} for label {}", x, name)
+                        })?;
+                        policies.labels.insert(
+                            name.to_owned(),
+                            Policy {
+                                kind: Kind::Transform(transform),
+                                except_uri: regex::Regex::new(pattern)?,
+                            },
+                        );
                     }
                     None => {}
                 };
             }
 
+            let tenant = &config["tenant"];
+            if !tenant.is_badvalue() {
+                let source = match (
+                    tenant["header"].as_str(),
+                    tenant["subdomain_of"].as_str(),
+                    tenant["claim"].as_str(),
+                ) {
+                    (Some(name), None, None) => TenantSource::Header(name.to_owned()),
+                    (None, Some(domain), None) => TenantSource::SubdomainOf(domain.to_owned()),
+                    (None, None, Some(name)) => TenantSource::Claim(name.to_owned()),
+                    _ => anyhow::bail!(
+                        "tenant must have exactly one of 'header', 'subdomain_of' or 'claim'. Instead got: {:?}",
+                        tenant
+                    ),
+                };
+                if policies.tenant.is_some() {
+                    anyhow::bail!("tenant is configured more than once");
+                }
+                policies.tenant = Some(source);
+
+                let quotas = &tenant["quotas"];
+                if !quotas.is_badvalue() {
+                    policies.tenant_quotas.default = Quota::from_yaml(quotas)?;
+                    if let Some(tenants) = quotas["tenants"].as_hash() {
+                        for (name, quota) in tenants {
+                            let name = name.as_str().ok_or_else(|| {
+                                anyhow::anyhow!("tenant names must be strings, got {:?}", name)
+                            })?;
+                            policies
+                                .tenant_quotas
+                                .tenants
+                                .insert(name.to_owned(), Quota::from_yaml(quota)?);
+                        }
+                    }
+                }
+            }
+
+            if let Some(webhooks) = config["webhooks"].as_vec() {
+                for webhook in webhooks {
+                    policies.webhooks.push(Webhook::from_yaml(webhook)?);
+                }
+            }
+
+            if let Some(rows) = config["rows"].as_vec() {
+                for rule in rows {
+                    policies.rows.push(RowPolicy::from_yaml(rule)?);
+                }
+            }
+
             #[allow(clippy::or_fun_call)]
             let routes = config["routes"]
                 .as_vec()
@@ -262,6 +653,52 @@ impl VersionPolicy {
                             .user_authorization
                             .add(path, regex::Regex::new(users)?)?;
                     }
+                    match &route["claims"] {
+                        Yaml::BadValue => {}
+                        Yaml::Hash(claims) => {
+                            let claims = claims
+                                .iter()
+                                .map(|(name, pattern)| match (name, pattern) {
+                                    (Yaml::String(name), Yaml::String(pattern)) => {
+                                        Ok((name.clone(), regex::Regex::new(pattern)?))
+                                    }
+                                    _ => anyhow::bail!(
+                                        "Claims must map claim names to regexes. Instead got: {name:?}: {pattern:?}"
+                                    ),
+                                })
+                                .collect::<Result<_>>()?;
+                            policies.claim_authorization.add(path, claims)?;
+                        }
+                        x => anyhow::bail!("Unparsable claims: {x:?}"),
+                    }
+                    match &route["transforms"] {
+                        Yaml::BadValue => {}
+                        Yaml::Hash(transforms) => {
+                            let transforms = transforms
+                                .iter()
+                                .map(|(label, transform)| {
+                                    let parsed = match (label, transform) {
+                                        (Yaml::String(label), Yaml::String(transform)) => {
+                                            Transform::parse(transform).map(|t| (label.clone(), t))
+                                        }
+                                        _ => None,
+                                    };
+                                    parsed.ok_or_else(|| anyhow::anyhow!(
+                                        "Transforms must map labels to transforms. Instead got: {label:?}: {transform:?}"
+                                    ))
+                                })
+                                .collect::<Result<_>>()?;
+                            policies.route_transforms.add(path, transforms)?;
+                        }
+                        x => anyhow::bail!("Unparsable transforms: {x:?}"),
+                    }
+                    match &route["api_key"] {
+                        Yaml::BadValue => {}
+                        Yaml::Boolean(required) => {
+                            policies.api_key_authorization.add(path, *required)?
+                        }
+                        x => anyhow::bail!("api_key must be true or false, got {x:?}"),
+                    }
                     let header = &route["mandatory_header"];
                     match header {
                         Yaml::BadValue => {}
@@ -334,3 +771,41 @@ impl EntityPolicy {
         Ok(Self { policies })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_transforms() {
+        assert_eq!(Transform::parse("redact"), Some(Transform::Redact));
+        assert_eq!(
+            Transform::parse("truncate(8)"),
+            Some(Transform::Truncate(8))
+        );
+        assert_eq!(Transform::parse("truncate(x)"), None);
+        assert_eq!(Transform::parse("hide"), None);
+        for name in ["anonymize", "redact", "hash", "truncate(8)", "mask-email"] {
+            assert_eq!(Transform::parse(name).unwrap().to_string(), name);
+        }
+    }
+
+    #[test]
+    fn apply_transforms() {
+        assert_eq!(Transform::Redact.apply(json!(42)), Value::Null);
+        assert_eq!(
+            Transform::Hash.apply(json!("555-1234")),
+            json!("24886b1e9942f612a3e4cdf5898f9b89987fe8cc6ba4ca6996ef1affa15cdf27")
+        );
+        assert_eq!(Transform::Hash.apply(Value::Null), Value::Null);
+        assert_eq!(Transform::Truncate(3).apply(json!("héllo")), json!("hél"));
+        assert_eq!(Transform::Truncate(1).apply(json!([1, 2])), json!([1]));
+        assert_eq!(Transform::Truncate(1).apply(json!(12345)), json!(12345));
+        assert_eq!(
+            Transform::MaskEmail.apply(json!("jane@example.com")),
+            json!("j***@example.com")
+        );
+        assert_eq!(Transform::MaskEmail.apply(json!("jane")), json!("****"));
+        assert_eq!(Transform::MaskEmail.apply(json!("@x.com")), json!("@x.com"));
+    }
+}
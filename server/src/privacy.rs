@@ -0,0 +1,277 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Subject access and erasure of user data.
+//!
+//! The data of a user is every row with an `AuthUser` field set to their
+//! `AuthUser`, plus the user's own `AuthUser`, `AuthSession` and
+//! `AuthAccount` rows. Only these direct relations own a row: a `Comment`
+//! whose `post` has the user as `author` belongs to whoever wrote the
+//! comment, not to the author of the post. Policies are not applied: these
+//! operations run on behalf of the operator, not of an endpoint.
+
+use crate::auth::{AUTH_ACCOUNT_NAME, AUTH_SCIM_USER_NAME, AUTH_SESSION_NAME, AUTH_USER_NAME};
+use crate::datastore::expr::{BinaryExpr, Expr, PropertyAccess, Value as ExprValue};
+use crate::datastore::query::{join_tables_of, QueryOp, QueryPlan, RequestContext};
+use crate::datastore::QueryEngine;
+use crate::policies::Policies;
+use crate::types::{Entity, Field, Type, TypeSystem};
+use crate::JsonObject;
+use anyhow::{Context, Result};
+use deno_core::futures::StreamExt;
+use serde_json::Value as JsonValue;
+use sqlx::Executor;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// `AuthUser` fields holding personal data, cleared on anonymization.
+const AUTH_USER_PERSONAL_FIELDS: [&str; 4] = ["name", "email", "emailVerified", "image"];
+
+/// An entity whose rows can belong to a user.
+struct UserEntity {
+    api_version: String,
+    entity: Entity,
+    /// The fields holding the id of the `AuthUser` owning a row.
+    owner_fields: Vec<Field>,
+}
+
+/// Rows of one entity that belong to a user.
+struct UserRows {
+    api_version: String,
+    entity: Entity,
+    rows: Vec<JsonObject>,
+}
+
+/// The `AuthUser` fields of entity `ty`, through which its rows belong to a
+/// user.
+fn owner_fields(ts: &TypeSystem, ty: &Entity) -> Result<Vec<Field>> {
+    let mut fields = vec![];
+    for field in ty.column_fields() {
+        if let Type::Entity(nested_ty) = ts.get(&field.type_id)? {
+            if nested_ty.name() == AUTH_USER_NAME {
+                fields.push(field.clone());
+            }
+        }
+    }
+    Ok(fields)
+}
+
+/// The entities whose rows can belong to a user, across all API versions:
+/// the custom entities with `AuthUser` fields, then the auth entities.
+fn user_entities(ts: &TypeSystem) -> Result<Vec<UserEntity>> {
+    let mut found = vec![];
+
+    let mut versions: Vec<_> = ts.versions.iter().collect();
+    versions.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    for (api_version, version_types) in versions {
+        let mut entities: Vec<_> = version_types.custom_types.values().collect();
+        entities.sort_unstable_by(|a, b| a.name().cmp(b.name()));
+        for entity in entities {
+            let owner_fields = owner_fields(ts, entity)?;
+            if !owner_fields.is_empty() {
+                found.push(UserEntity {
+                    api_version: api_version.to_owned(),
+                    entity: entity.clone(),
+                    owner_fields,
+                });
+            }
+        }
+    }
+
+    for (name, field) in [
+        (AUTH_USER_NAME, "id"),
+        (AUTH_SESSION_NAME, "userId"),
+        (AUTH_ACCOUNT_NAME, "userId"),
+        (AUTH_SCIM_USER_NAME, "userId"),
+    ] {
+        let entity = match ts.lookup_builtin_type(name)? {
+            Type::Entity(entity) => entity,
+            _ => anyhow::bail!("Internal error: type {} is not an entity", name),
+        };
+        let owner_field = entity
+            .get_field(field)
+            .with_context(|| format!("Internal error: {} has no field {}", name, field))?
+            .clone();
+        found.push(UserEntity {
+            api_version: "__chiselstrike".to_owned(),
+            entity,
+            owner_fields: vec![owner_field],
+        });
+    }
+    Ok(found)
+}
+
+/// Builds an expression matching objects where any of `fields` equals
+/// `user_id`.
+fn user_filter(fields: &[Field], user_id: &str) -> Option<Expr> {
+    fields
+        .iter()
+        .map(|field| {
+            let property = PropertyAccess {
+                property: field.name.clone(),
+                object: Expr::Parameter { position: 0 }.into(),
+            };
+            BinaryExpr::eq(property.into(), ExprValue::from(user_id).into())
+        })
+        .reduce(BinaryExpr::or)
+}
+
+async fn fetch_rows(
+    engine: Arc<QueryEngine>,
+    ts: &TypeSystem,
+    api_version: &str,
+    entity: &Entity,
+    expression: Expr,
+) -> Result<Vec<JsonObject>> {
+    let policies = Policies::default();
+    let context = RequestContext {
+        policies: &policies,
+        ts,
+        api_version: api_version.to_owned(),
+        user_id: None,
+        tenant: None,
+        path: "".to_owned(),
+        headers: HashMap::default(),
+    };
+    let query_plan = QueryPlan::from_ops(&context, entity, vec![QueryOp::Filter { expression }])?;
+
+    let tr = engine.clone().begin_transaction_static().await?;
+    let mut rows = vec![];
+    {
+        let mut row_stream = engine.query(tr.clone(), query_plan)?;
+        while let Some(row) = row_stream.next().await {
+            rows.push(row.with_context(|| {
+                format!("failed to read user data of entity {}", entity.name())
+            })?);
+        }
+    }
+    QueryEngine::commit_transaction_static(tr).await?;
+    Ok(rows)
+}
+
+/// Finds all rows belonging to the user `user_id`, across all API versions.
+async fn user_rows(
+    engine: Arc<QueryEngine>,
+    ts: &TypeSystem,
+    user_id: &str,
+) -> Result<Vec<UserRows>> {
+    let mut found = vec![];
+    for user_entity in user_entities(ts)? {
+        let expression = match user_filter(&user_entity.owner_fields, user_id) {
+            Some(expression) => expression,
+            None => continue,
+        };
+        let rows = fetch_rows(
+            engine.clone(),
+            ts,
+            &user_entity.api_version,
+            &user_entity.entity,
+            expression,
+        )
+        .await?;
+        if !rows.is_empty() {
+            found.push(UserRows {
+                api_version: user_entity.api_version,
+                entity: user_entity.entity,
+                rows,
+            });
+        }
+    }
+    Ok(found)
+}
+
+/// Exports the data of user `user_id` as a JSON object mapping API versions
+/// to entity names to the user's rows of that entity.
+pub async fn export_user_data(
+    engine: Arc<QueryEngine>,
+    ts: &TypeSystem,
+    user_id: &str,
+) -> Result<JsonValue> {
+    let mut export = JsonObject::new();
+    for found in user_rows(engine, ts, user_id).await? {
+        let version = export
+            .entry(found.api_version)
+            .or_insert_with(|| JsonObject::new().into());
+        let rows = found.rows.into_iter().map(JsonValue::Object).collect();
+        version
+            .as_object_mut()
+            .unwrap()
+            .insert(found.entity.name().to_owned(), JsonValue::Array(rows));
+    }
+    Ok(export.into())
+}
+
+/// Erases the data of user `user_id` and returns the number of deleted rows.
+///
+/// With `anonymize`, rows of user entities are kept: the `AuthUser` row is
+/// stripped of its personal fields but keeps its id, so that the rows linking
+/// to it stay consistent. Sessions and accounts are deleted either way.
+///
+/// The rows are matched by the statements deleting them, in a single
+/// transaction, so that rows written meanwhile aren't left behind. That
+/// includes the archived rows, and the links of many-to-many relations from
+/// and to the deleted rows.
+pub async fn erase_user_data(
+    engine: Arc<QueryEngine>,
+    ts: &TypeSystem,
+    user_id: &str,
+    anonymize: bool,
+) -> Result<u64> {
+    let mut deleted = 0;
+    let mut transaction = engine.begin_transaction().await?;
+    for found in user_entities(ts)? {
+        let name = found.entity.name();
+        let table = found.entity.backing_table();
+        if anonymize && name == AUTH_USER_NAME {
+            let assignments = AUTH_USER_PERSONAL_FIELDS
+                .iter()
+                .map(|field| format!("\"{}\" = NULL", field))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let sql = format!("UPDATE \"{}\" SET {} WHERE \"id\" = $1", table, assignments);
+            transaction
+                .execute(sqlx::query(&sql).bind(user_id))
+                .await
+                .context("failed to anonymize AuthUser")?;
+            continue;
+        }
+        if anonymize && !found.entity.is_auth() {
+            continue;
+        }
+        let condition = found
+            .owner_fields
+            .iter()
+            .map(|field| format!("\"{}\" = $1", field.column_name()))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        let mut tables = vec![table.to_owned()];
+        if found.entity.archive().is_some() {
+            tables.push(found.entity.archive_table());
+        }
+        // The links go first, as finding them reads the rows.
+        let ids = tables
+            .iter()
+            .map(|table| format!("SELECT \"id\" FROM \"{}\" WHERE {}", table, condition))
+            .collect::<Vec<_>>()
+            .join(" UNION ");
+        for (join_table, column) in join_tables_of(ts, &found.entity)? {
+            let sql = format!(
+                "DELETE FROM \"{}\" WHERE \"{}\" IN ({})",
+                join_table, column, ids
+            );
+            transaction
+                .execute(sqlx::query(&sql).bind(user_id))
+                .await
+                .with_context(|| format!("failed to unlink user data of entity {}", name))?;
+        }
+        for table in tables {
+            let sql = format!("DELETE FROM \"{}\" WHERE {}", table, condition);
+            deleted += transaction
+                .execute(sqlx::query(&sql).bind(user_id))
+                .await
+                .with_context(|| format!("failed to delete user data of entity {}", name))?
+                .rows_affected();
+        }
+    }
+    QueryEngine::commit_transaction(transaction).await?;
+    Ok(deleted)
+}
@@ -0,0 +1,297 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Execution profiles of endpoint requests.
+//!
+//! A profiled request records how long each of its SQL statements and
+//! fetch() calls took. The rest of its time, until the head of its response
+//! is ready, is accounted to JavaScript execution. The breakdown is returned
+//! in a `Server-Timing` header, and the latest profiles of each endpoint are
+//! kept as exemplars for `chisel profile`.
+//!
+//! In debug mode, chiseld profiles the requests with a `ChiselProfile`
+//! header. With `--profile-sample-rate`, it also profiles a percentage of
+//! all requests. Statements and fetches that run concurrently are all
+//! counted in full, so the time left to JavaScript is a lower bound.
+
+use anyhow::Result;
+use hyper::HeaderMap;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Header that asks a server in debug mode to profile a request.
+pub const PROFILE_HEADER: &str = "ChiselProfile";
+
+/// Number of profiles kept for each endpoint.
+const EXEMPLARS_PER_ENDPOINT: usize = 10;
+
+/// Label of the statements whose SQL is only built as they run.
+const UNKNOWN_STATEMENT: &str = "(statement built at run time)";
+
+static ON_DEMAND: AtomicBool = AtomicBool::new(false);
+static SAMPLE_RATE: Lazy<Mutex<f64>> = Lazy::new(Default::default);
+static EXEMPLARS: Lazy<Mutex<HashMap<(String, String), VecDeque<Exemplar>>>> =
+    Lazy::new(Default::default);
+
+/// Profiles the requests with a `ChiselProfile` header if `on_demand`, and
+/// `sample_rate` percent of all requests.
+pub fn configure(on_demand: bool, sample_rate: f64) -> Result<()> {
+    anyhow::ensure!(
+        (0.0..=100.0).contains(&sample_rate),
+        "the percentage of requests to profile must be between 0 and 100"
+    );
+    ON_DEMAND.store(on_demand, Ordering::Relaxed);
+    *SAMPLE_RATE.lock().unwrap() = sample_rate;
+    Ok(())
+}
+
+/// Starts the profile of a request with `headers`, if it is to be profiled.
+pub fn start(headers: &HeaderMap) -> Option<Arc<Profile>> {
+    let requested = ON_DEMAND.load(Ordering::Relaxed) && headers.contains_key(PROFILE_HEADER);
+    let sample_rate = *SAMPLE_RATE.lock().unwrap();
+    let sampled = sample_rate > 0.0 && rand::random::<f64>() * 100.0 < sample_rate;
+    (requested || sampled).then(|| {
+        Arc::new(Profile {
+            started: Instant::now(),
+            state: Default::default(),
+        })
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpanKind {
+    Sql,
+    Fetch,
+}
+
+impl SpanKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpanKind::Sql => "sql",
+            SpanKind::Fetch => "fetch",
+        }
+    }
+}
+
+/// Time spent running a SQL statement, or fetching from a URL, summed over
+/// the times the request did it.
+#[derive(Clone, Debug)]
+pub struct Span {
+    pub kind: SpanKind,
+    pub label: String,
+    pub count: u64,
+    pub duration: Duration,
+}
+
+/// A finished profile.
+#[derive(Clone, Debug)]
+pub struct Exemplar {
+    pub api_version: String,
+    pub path: String,
+    pub method: String,
+    pub recorded: SystemTime,
+    pub total: Duration,
+    pub js: Duration,
+    /// The spans, longest first.
+    pub spans: Vec<Span>,
+}
+
+impl Exemplar {
+    /// The SQL of the statements the request ran, except for those built at
+    /// run time.
+    pub fn statements(&self) -> impl Iterator<Item = &str> {
+        self.spans
+            .iter()
+            .filter(|span| span.kind == SpanKind::Sql && span.label != UNKNOWN_STATEMENT)
+            .map(|span| span.label.as_str())
+    }
+}
+
+pub struct Profile {
+    started: Instant,
+    state: Mutex<ProfileState>,
+}
+
+#[derive(Default)]
+struct ProfileState {
+    spans: Vec<Span>,
+    /// The span of the statement that began last, which waits on the
+    /// database are accounted to.
+    statement: Option<usize>,
+    /// The fetch() calls in progress, by id, with their span and start.
+    fetches: HashMap<u32, (usize, Instant)>,
+    next_fetch: u32,
+}
+
+impl ProfileState {
+    /// Counts one more run of span `label` of `kind`, returning its index.
+    fn begin(&mut self, kind: SpanKind, label: &str) -> usize {
+        let idx = match self
+            .spans
+            .iter()
+            .position(|span| span.kind == kind && span.label == label)
+        {
+            Some(idx) => idx,
+            None => {
+                self.spans.push(Span {
+                    kind,
+                    label: label.to_owned(),
+                    count: 0,
+                    duration: Duration::ZERO,
+                });
+                self.spans.len() - 1
+            }
+        };
+        self.spans[idx].count += 1;
+        idx
+    }
+}
+
+impl Profile {
+    /// Records that the request begins running statement `sql`, or one whose
+    /// SQL isn't known yet.
+    pub fn begin_statement(&self, sql: Option<&str>) {
+        let mut state = self.state.lock().unwrap();
+        let idx = state.begin(SpanKind::Sql, sql.unwrap_or(UNKNOWN_STATEMENT));
+        state.statement = Some(idx);
+    }
+
+    /// Records that the request waited `duration` on its current statement,
+    /// to run it or to fetch its results.
+    pub fn record_statement(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let idx = match state.statement {
+            Some(idx) => idx,
+            None => state.begin(SpanKind::Sql, UNKNOWN_STATEMENT),
+        };
+        state.spans[idx].duration += duration;
+    }
+
+    /// Records that the request begins a fetch() from `target`, returning
+    /// the id to end it with.
+    pub fn begin_fetch(&self, target: &str) -> u32 {
+        let mut state = self.state.lock().unwrap();
+        let idx = state.begin(SpanKind::Fetch, target);
+        let id = state.next_fetch;
+        state.next_fetch += 1;
+        state.fetches.insert(id, (idx, Instant::now()));
+        id
+    }
+
+    pub fn end_fetch(&self, id: u32) {
+        let mut state = self.state.lock().unwrap();
+        if let Some((idx, started)) = state.fetches.remove(&id) {
+            state.spans[idx].duration += started.elapsed();
+        }
+    }
+
+    /// Ends the profile of a request to `path` of `api_version`, keeping it
+    /// as an exemplar of the endpoint. Returns the value of the
+    /// `Server-Timing` header of the response.
+    pub fn finish(&self, api_version: &str, path: &str, method: &str) -> String {
+        let total = self.started.elapsed();
+        let mut spans = std::mem::take(&mut self.state.lock().unwrap().spans);
+        spans.sort_by(|a, b| b.duration.cmp(&a.duration));
+        let waited = |kind| {
+            spans
+                .iter()
+                .filter(|span| span.kind == kind)
+                .fold((0, Duration::ZERO), |(count, duration), span| {
+                    (count + span.count, duration + span.duration)
+                })
+        };
+        let (statements, sql) = waited(SpanKind::Sql);
+        let (fetches, fetch) = waited(SpanKind::Fetch);
+        let js = total.saturating_sub(sql + fetch);
+        let header = format!(
+            concat!(
+                "js;dur={:.1}, sql;dur={:.1};desc=\"{} statements\", ",
+                "fetch;dur={:.1};desc=\"{} calls\", total;dur={:.1}"
+            ),
+            millis(js),
+            millis(sql),
+            statements,
+            millis(fetch),
+            fetches,
+            millis(total)
+        );
+
+        let exemplar = Exemplar {
+            api_version: api_version.to_owned(),
+            path: path.to_owned(),
+            method: method.to_owned(),
+            recorded: SystemTime::now(),
+            total,
+            js,
+            spans,
+        };
+        let mut exemplars = EXEMPLARS.lock().unwrap();
+        let endpoint = exemplars
+            .entry((api_version.to_owned(), path.to_owned()))
+            .or_default();
+        if endpoint.len() == EXEMPLARS_PER_ENDPOINT {
+            endpoint.pop_back();
+        }
+        endpoint.push_front(exemplar);
+        header
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Lists the profiles kept for the endpoints of `api_version` under
+/// `endpoint`, or for all of them, by endpoint and newest first.
+pub fn exemplars(api_version: Option<&str>, endpoint: Option<&str>) -> Vec<Exemplar> {
+    let exemplars = EXEMPLARS.lock().unwrap();
+    let mut endpoints: Vec<_> = exemplars
+        .iter()
+        .filter(|((version, path), _)| {
+            api_version.map_or(true, |v| v == version)
+                && endpoint.map_or(true, |e| is_under(path, e))
+        })
+        .collect();
+    endpoints.sort_by(|(a, _), (b, _)| a.cmp(b));
+    endpoints
+        .into_iter()
+        .flat_map(|(_, profiles)| profiles.iter().cloned())
+        .collect()
+}
+
+fn is_under(path: &str, endpoint: &str) -> bool {
+    match path.strip_prefix(endpoint) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statements_are_accounted_to_the_last_one_begun() {
+        let profile = Profile {
+            started: Instant::now(),
+            state: Default::default(),
+        };
+        profile.begin_statement(Some("SELECT 1"));
+        profile.record_statement(Duration::from_millis(2));
+        profile.record_statement(Duration::from_millis(3));
+        profile.begin_statement(None);
+        profile.record_statement(Duration::from_millis(1));
+        profile.begin_statement(Some("SELECT 1"));
+        profile.record_statement(Duration::from_millis(4));
+
+        let state = profile.state.lock().unwrap();
+        let spans: Vec<_> = state
+            .spans
+            .iter()
+            .map(|span| (span.label.as_str(), span.count, span.duration.as_millis()))
+            .collect();
+        assert_eq!(spans, [("SELECT 1", 2, 9), (UNKNOWN_STATEMENT, 1, 1)]);
+    }
+}
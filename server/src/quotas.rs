@@ -0,0 +1,174 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Per-tenant quotas of multi-tenant versions, so that one busy tenant can't
+//! starve the others of a shared server.
+//!
+//! Requests and the time spent handling them are counted in one-minute
+//! windows. A tenant over either limit of its quota is answered with
+//! `429 Too Many Requests` until the window ends. Time is measured from the
+//! start of a request to the end of its transaction. Requests of an executor
+//! interleave, so this approximates the CPU time the tenant uses.
+//!
+//! Usage is also kept in totals, which the internal server exports at
+//! `/metrics` in the Prometheus text format.
+
+use crate::api::Body;
+use crate::notifications::{notify, EventKind};
+use crate::policies::Quota;
+use anyhow::Result;
+use hyper::header::RETRY_AFTER;
+use hyper::{Response, StatusCode};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+struct Usage {
+    window_start: Instant,
+    window_requests: u64,
+    window_time: Duration,
+    requests: u64,
+    throttled: u64,
+    time: Duration,
+    rows_rejected: u64,
+}
+
+impl Usage {
+    fn new(now: Instant) -> Self {
+        Usage {
+            window_start: now,
+            window_requests: 0,
+            window_time: Duration::ZERO,
+            requests: 0,
+            throttled: 0,
+            time: Duration::ZERO,
+            rows_rejected: 0,
+        }
+    }
+
+    /// Starts a new window if the current one is over.
+    fn roll(&mut self, now: Instant) {
+        if now.duration_since(self.window_start) >= WINDOW {
+            self.window_start = now;
+            self.window_requests = 0;
+            self.window_time = Duration::ZERO;
+        }
+    }
+
+    fn is_over(&self, quota: &Quota) -> bool {
+        let over = |used: u64, limit: Option<u64>| limit.map_or(false, |limit| used >= limit);
+        over(self.window_requests, quota.requests_per_minute)
+            || over(self.window_time.as_millis() as u64, quota.cpu_ms_per_minute)
+    }
+}
+
+/// Usage by API version and tenant.
+static USAGE: Lazy<Mutex<HashMap<(String, String), Usage>>> = Lazy::new(Default::default);
+
+fn with_usage<T>(api_version: &str, tenant: &str, f: impl FnOnce(&mut Usage, Instant) -> T) -> T {
+    let now = Instant::now();
+    let mut usage = USAGE.lock().unwrap();
+    let usage = usage
+        .entry((api_version.to_owned(), tenant.to_owned()))
+        .or_insert_with(|| Usage::new(now));
+    usage.roll(now);
+    f(usage, now)
+}
+
+/// Counts a request of `tenant` to `api_version`, unless the tenant is over
+/// `quota`. Then returns how long the client should wait before retrying.
+pub fn admit(api_version: &str, tenant: &str, quota: &Quota) -> Option<Duration> {
+    with_usage(api_version, tenant, |usage, now| {
+        if usage.is_over(quota) {
+            usage.throttled += 1;
+            notify(
+                EventKind::QuotaExceeded,
+                &format!("{}/{}", api_version, tenant),
+                format!(
+                    "tenant {} of version {} is over its quota",
+                    tenant, api_version
+                ),
+            );
+            return Some(usage.window_start + WINDOW - now);
+        }
+        usage.window_requests += 1;
+        usage.requests += 1;
+        None
+    })
+}
+
+/// Records that a request of `tenant` to `api_version` took `elapsed`.
+pub fn record_time(api_version: &str, tenant: &str, elapsed: Duration) {
+    with_usage(api_version, tenant, |usage, _| {
+        usage.window_time += elapsed;
+        usage.time += elapsed;
+    });
+}
+
+/// Records that a row of `tenant` was rejected for going over its quota.
+pub fn record_rejected_row(api_version: &str, tenant: &str) {
+    with_usage(api_version, tenant, |usage, _| usage.rows_rejected += 1);
+}
+
+/// Builds the response to a request of a tenant over its quota.
+pub fn throttled(retry_after: Duration) -> Result<Response<Body>> {
+    // Retry-After is in whole seconds, so round up.
+    let seconds = (retry_after.as_millis() as u64 + 999) / 1000;
+    Ok(Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(RETRY_AFTER, seconds.max(1))
+        .body("Tenant is over its quota\n".to_string().into())?)
+}
+
+pub(crate) fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Renders the usage totals in the Prometheus text format.
+pub fn metrics() -> String {
+    let usage = USAGE.lock().unwrap();
+    let mut keys: Vec<_> = usage.keys().collect();
+    keys.sort_unstable();
+    let metrics: [(&str, &str, fn(&Usage) -> u64); 4] = [
+        ("requests_total", "Requests admitted.", |u| u.requests),
+        (
+            "throttled_total",
+            "Requests rejected for going over quota.",
+            |u| u.throttled,
+        ),
+        (
+            "cpu_milliseconds_total",
+            "Time spent handling requests.",
+            |u| u.time.as_millis() as u64,
+        ),
+        (
+            "rows_rejected_total",
+            "Rows rejected for going over quota.",
+            |u| u.rows_rejected,
+        ),
+    ];
+    let mut out = String::new();
+    for (name, help, value) in metrics {
+        writeln!(out, "# HELP chiseld_tenant_{} {}", name, help).unwrap();
+        writeln!(out, "# TYPE chiseld_tenant_{} counter", name).unwrap();
+        for key in &keys {
+            let (api_version, tenant) = key;
+            writeln!(
+                out,
+                "chiseld_tenant_{}{{version=\"{}\",tenant=\"{}\"}} {}",
+                name,
+                escape_label(api_version),
+                escape_label(tenant),
+                value(&usage[*key])
+            )
+            .unwrap();
+        }
+    }
+    out
+}
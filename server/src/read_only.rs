@@ -0,0 +1,116 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Read-only mode of versions, for maintenance windows such as migrations,
+//! backend switches or backups that need the data to stay put.
+//!
+//! While a version is read-only, writes to its entities (or only to some of
+//! them) fail, and a request that fails because of it is answered with
+//! `503 Service Unavailable` and a `Retry-After` header. Reads are not
+//! affected.
+
+use crate::api::Body;
+use anyhow::Result;
+use hyper::header::RETRY_AFTER;
+use hyper::{Response, StatusCode};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Retry delay suggested to clients when a window has no set end.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+struct Window {
+    /// The read-only entities, or empty if the whole version is.
+    entities: HashSet<String>,
+    end: Option<Instant>,
+}
+
+impl Window {
+    fn is_over(&self, now: Instant) -> bool {
+        self.end.map_or(false, |end| end <= now)
+    }
+}
+
+static WINDOWS: Lazy<Mutex<HashMap<String, Window>>> = Lazy::new(Default::default);
+
+pub struct WindowInfo {
+    pub api_version: String,
+    pub entities: Vec<String>,
+    /// Time left until the window ends by itself.
+    pub remaining: Option<Duration>,
+}
+
+/// Makes `entities` of `api_version`, or all of its entities if `entities`
+/// is empty, read-only until `stop` is called or `duration` elapses.
+/// Replaces any window the version was already in.
+pub fn start(api_version: &str, entities: Vec<String>, duration: Option<Duration>) {
+    let window = Window {
+        entities: entities.into_iter().collect(),
+        end: duration.map(|duration| Instant::now() + duration),
+    };
+    WINDOWS
+        .lock()
+        .unwrap()
+        .insert(api_version.to_owned(), window);
+}
+
+/// Makes `api_version` writable again. Returns false if it wasn't read-only.
+pub fn stop(api_version: &str) -> bool {
+    let now = Instant::now();
+    match WINDOWS.lock().unwrap().remove(api_version) {
+        Some(window) => !window.is_over(now),
+        None => false,
+    }
+}
+
+/// Lists the versions in a maintenance window.
+pub fn list() -> Vec<WindowInfo> {
+    let now = Instant::now();
+    let mut windows = WINDOWS.lock().unwrap();
+    windows.retain(|_, window| !window.is_over(now));
+    let mut list: Vec<_> = windows
+        .iter()
+        .map(|(api_version, window)| {
+            let mut entities: Vec<_> = window.entities.iter().cloned().collect();
+            entities.sort_unstable();
+            WindowInfo {
+                api_version: api_version.clone(),
+                entities,
+                remaining: window.end.map(|end| end - now),
+            }
+        })
+        .collect();
+    list.sort_unstable_by(|a, b| a.api_version.cmp(&b.api_version));
+    list
+}
+
+/// If entity `type_name` of `api_version` is read-only, returns how long
+/// clients should wait before retrying a write.
+pub fn check(api_version: &str, type_name: &str) -> Option<Duration> {
+    let now = Instant::now();
+    let mut windows = WINDOWS.lock().unwrap();
+    let window = windows.get(api_version)?;
+    if window.is_over(now) {
+        windows.remove(api_version);
+        return None;
+    }
+    if !window.entities.is_empty() && !window.entities.contains(type_name) {
+        return None;
+    }
+    let retry_after = match window.end {
+        Some(end) => end - now,
+        None => DEFAULT_RETRY_AFTER,
+    };
+    Some(retry_after)
+}
+
+/// Builds the response to a request rejected because of a maintenance window.
+pub fn unavailable(retry_after: Duration) -> Result<Response<Body>> {
+    // Retry-After is in whole seconds, so round up.
+    let seconds = (retry_after.as_millis() as u64 + 999) / 1000;
+    Ok(Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(RETRY_AFTER, seconds.max(1))
+        .body("Service is read-only for maintenance\n".to_string().into())?)
+}
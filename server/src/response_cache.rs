@@ -0,0 +1,417 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Shared cache of endpoint responses.
+//!
+//! A successful response to a GET request is cached if its `Cache-Control`
+//! header lets shared caches store it: it has `public` or `s-maxage`, and
+//! none of `private`, `no-store` or `no-cache`. It is kept for `s-maxage`, or
+//! else `max-age`, seconds, during which it is served without running the
+//! endpoint at all.
+//!
+//! Responses without a `Cache-Control` header are cached too if every entity
+//! the endpoint read has a `@cache` decorator, for the smallest `ttl` among
+//! them, unless the request was made by a user or a tenant. Once that time is
+//! up, the next request computes a fresh response, while for the smallest
+//! `staleWhileRevalidate` the others are still served the stale one.
+//!
+//! Requests with credentials, or that select a tenant of a multi-tenant
+//! version, are neither served from the cache nor cached: the cache is looked
+//! up before they are authenticated, and their responses may depend on who
+//! made them.
+//!
+//! A committed write to any entity of a version drops every cached response of
+//! that version, so a server always reads its own writes. Auth entities are
+//! shared by all versions, so writing them drops the whole cache. Requests
+//! with `Cache-Control: no-cache` or `ChiselConsistency: strong` are never
+//! served from the cache, for callers that must see writes made elsewhere.
+//!
+//! Event streams are never cached, since caching a response means reading
+//! all of its body, and they may never end.
+
+use crate::api::Body;
+use crate::policies::TenantSource;
+use crate::JsonObject;
+use anyhow::Result;
+use deno_core::futures::StreamExt;
+use hyper::header::{
+    HeaderName, HeaderValue, ACCEPT, AGE, CACHE_CONTROL, CONTENT_TYPE, SET_COOKIE, VARY,
+};
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Responses with larger bodies are not cached.
+const MAX_BODY_SIZE: usize = 1 << 20;
+/// Maximum number of cached responses, across all versions.
+const MAX_ENTRIES: usize = 10_000;
+/// Request headers that carry the credentials of a user.
+const CREDENTIAL_HEADERS: [&str; 4] = ["Authorization", "Cookie", "X-API-Key", "ChiselUID"];
+
+/// How long responses that read an entity may be cached, from the entity's
+/// `@cache` decorator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheHint {
+    pub ttl: Duration,
+    pub stale_while_revalidate: Duration,
+}
+
+/// What an endpoint read to compute a response. Responses carry it as an
+/// `Arc<Mutex<Reads>>` extension, filled in until their body is complete.
+#[derive(Clone, Debug, Default)]
+pub struct Reads {
+    pub entities: HashSet<String>,
+    /// Whether the request was made by a user or a tenant, whose policies
+    /// may have filtered what was read.
+    pub personalized: bool,
+}
+
+struct Entry {
+    /// The request headers named by the response's `Vary`, with the values
+    /// the response was computed for.
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    stored: Instant,
+    expires: Instant,
+    /// Until when the response may be served stale while a fresh one is
+    /// computed.
+    stale_until: Instant,
+    /// Set once a request went on to compute a fresh response.
+    revalidating: bool,
+    headers: HeaderMap,
+    body: Box<[u8]>,
+}
+
+impl Entry {
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| headers.get(name) == value.as_ref())
+    }
+}
+
+#[derive(Default)]
+struct VersionCache {
+    /// Bumped on every invalidation, so that responses computed before a
+    /// write but finished after it are not stored.
+    generation: u64,
+    /// Cached responses by request path and query.
+    entries: HashMap<String, Vec<Entry>>,
+    /// Caching hints of entities, by name.
+    hints: HashMap<String, CacheHint>,
+    /// How requests select a tenant, if the version is multi-tenant.
+    tenant: Option<TenantSource>,
+}
+
+#[derive(Default)]
+struct Cache {
+    versions: HashMap<String, VersionCache>,
+    len: usize,
+}
+
+impl Cache {
+    fn purge_expired(&mut self, now: Instant) {
+        for version in self.versions.values_mut() {
+            version.entries.retain(|_, entries| {
+                entries.retain(|entry| entry.stale_until > now);
+                !entries.is_empty()
+            });
+        }
+        self.len = self
+            .versions
+            .values()
+            .flat_map(|version| version.entries.values())
+            .map(Vec::len)
+            .sum();
+    }
+}
+
+static CACHE: Lazy<Mutex<Cache>> = Lazy::new(Default::default);
+
+pub enum Lookup {
+    Hit(Response<Body>),
+    /// The response should be stored in this slot once computed.
+    Miss(CacheSlot),
+    Uncacheable,
+}
+
+pub struct CacheSlot {
+    api_version: String,
+    key: String,
+    generation: u64,
+    request_headers: HeaderMap,
+}
+
+fn api_version(path: &str) -> Option<&str> {
+    path.strip_prefix('/')?.split('/').next()
+}
+
+/// Looks up a cached response to `req`.
+pub fn lookup(req: &Request<hyper::Body>) -> Lookup {
+    if req.method() != Method::GET {
+        return Lookup::Uncacheable;
+    }
+    let headers = req.headers();
+    if CREDENTIAL_HEADERS
+        .iter()
+        .any(|name| headers.contains_key(*name))
+    {
+        return Lookup::Uncacheable;
+    }
+    let api_version = match api_version(req.uri().path()) {
+        Some(api_version) => api_version.to_owned(),
+        None => return Lookup::Uncacheable,
+    };
+    let key = match req.uri().path_and_query() {
+        Some(key) => key.to_string(),
+        None => return Lookup::Uncacheable,
+    };
+    let revalidate = req
+        .headers()
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .any(|value| value.to_str().map_or(false, |v| v.contains("no-cache")))
+        || req
+            .headers()
+            .get("ChiselConsistency")
+            .map_or(false, |value| value == "strong");
+
+    let now = Instant::now();
+    let mut cache = CACHE.lock().unwrap();
+    let version = cache.versions.entry(api_version.clone()).or_default();
+    // Claims can't select a tenant here, as the request has no credentials.
+    let selects_tenant = version.tenant.as_ref().map_or(false, |source| {
+        source.resolve(req, &JsonObject::new()).is_some()
+    });
+    if selects_tenant {
+        return Lookup::Uncacheable;
+    }
+    if !revalidate {
+        let hit = version.entries.get_mut(&key).and_then(|entries| {
+            entries
+                .iter_mut()
+                .find(|entry| entry.stale_until > now && entry.matches(req.headers()))
+        });
+        // The first request for a stale response computes a fresh one.
+        let hit = hit.filter(|entry| {
+            if entry.expires > now || entry.revalidating {
+                return true;
+            }
+            entry.revalidating = true;
+            false
+        });
+        if let Some(entry) = hit {
+            let mut response = Response::new(Body::Const(Some(entry.body.clone())));
+            *response.headers_mut() = entry.headers.clone();
+            let age = now.duration_since(entry.stored).as_secs();
+            response.headers_mut().insert(AGE, age.into());
+            return Lookup::Hit(response);
+        }
+    }
+    Lookup::Miss(CacheSlot {
+        api_version,
+        key,
+        generation: version.generation,
+        request_headers: req.headers().clone(),
+    })
+}
+
+/// How long a response with these headers may be kept by a shared cache.
+fn time_to_live(headers: &HeaderMap) -> Option<Duration> {
+    if headers.contains_key(SET_COOKIE) {
+        return None;
+    }
+    let mut public = false;
+    let mut max_age = None;
+    let mut s_maxage = None;
+    for value in headers.get_all(CACHE_CONTROL) {
+        for directive in value.to_str().ok()?.split(',') {
+            let (name, arg) = match directive.split_once('=') {
+                Some((name, arg)) => (name, Some(arg.trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+            match name.trim().to_ascii_lowercase().as_str() {
+                "public" => public = true,
+                "private" | "no-store" | "no-cache" => return None,
+                "max-age" => max_age = arg?.parse::<u64>().ok(),
+                "s-maxage" => s_maxage = arg?.parse::<u64>().ok(),
+                _ => {}
+            }
+        }
+    }
+    let seconds = s_maxage.or(max_age.filter(|_| public))?;
+    (seconds > 0).then(|| Duration::from_secs(seconds))
+}
+
+/// Returns the request headers a response with these headers varies on,
+/// or `None` if it varies on anything (`Vary: *`). Responses always vary on
+/// `Accept`, as it selects their encoding.
+fn vary(
+    headers: &HeaderMap,
+    request_headers: &HeaderMap,
+) -> Option<Vec<(HeaderName, Option<HeaderValue>)>> {
+    let mut names = vec![ACCEPT];
+    for value in headers.get_all(VARY) {
+        for name in value.to_str().ok()?.split(',') {
+            let name = name.trim();
+            if name == "*" {
+                return None;
+            }
+            names.push(HeaderName::try_from(name).ok()?);
+        }
+    }
+    names.sort_unstable_by(|a, b| a.as_str().cmp(b.as_str()));
+    names.dedup();
+    Some(
+        names
+            .into_iter()
+            .map(|name| {
+                let value = request_headers.get(&name).cloned();
+                (name, value)
+            })
+            .collect(),
+    )
+}
+
+async fn read_body(body: Body) -> Result<Box<[u8]>> {
+    match body {
+        Body::Const(body) => Ok(body.unwrap_or_default()),
+        Body::Shared(body) => Ok(body.map(|body| body.to_vec().into()).unwrap_or_default()),
+        Body::Stream(mut stream) => {
+            let mut buf = vec![];
+            while let Some(chunk) = stream.next().await {
+                buf.extend_from_slice(&chunk?);
+            }
+            Ok(buf.into_boxed_slice())
+        }
+    }
+}
+
+/// How long a response that made `reads` may be cached according to the
+/// hints of the entities it read, and then served stale.
+fn hinted_time_to_live(api_version: &str, reads: &Reads) -> Option<(Duration, Duration)> {
+    if reads.personalized || reads.entities.is_empty() {
+        return None;
+    }
+    let cache = CACHE.lock().unwrap();
+    let hints = &cache.versions.get(api_version)?.hints;
+    let mut ttl = Duration::MAX;
+    let mut stale_while_revalidate = Duration::MAX;
+    for entity in &reads.entities {
+        let hint = hints.get(entity)?;
+        ttl = ttl.min(hint.ttl);
+        stale_while_revalidate = stale_while_revalidate.min(hint.stale_while_revalidate);
+    }
+    (ttl > Duration::ZERO).then(|| (ttl, stale_while_revalidate))
+}
+
+/// Stores `response` in `slot` if its headers, or else the entities it read,
+/// allow it. Returns the response to send, whose body has been read if it was
+/// stored.
+pub async fn store(slot: CacheSlot, response: Response<Body>) -> Result<Response<Body>> {
+    if response.status() != StatusCode::OK {
+        return Ok(response);
+    }
+    let headers = response.headers();
+    let is_event_stream = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with("text/event-stream"));
+    if is_event_stream {
+        return Ok(response);
+    }
+    let reads = response.extensions().get::<Arc<Mutex<Reads>>>().cloned();
+    let ttl = match (time_to_live(headers), reads) {
+        (Some(ttl), _) => Ok(ttl),
+        // What the endpoint read is only known once the body is complete.
+        (None, Some(reads))
+            if !headers.contains_key(CACHE_CONTROL) && !headers.contains_key(SET_COOKIE) =>
+        {
+            Err(reads)
+        }
+        _ => return Ok(response),
+    };
+    let vary = match vary(response.headers(), &slot.request_headers) {
+        Some(vary) => vary,
+        None => return Ok(response),
+    };
+
+    let (parts, body) = response.into_parts();
+    let body = read_body(body).await?;
+    let ttls = match ttl {
+        Ok(ttl) => Some((ttl, Duration::ZERO)),
+        Err(reads) => hinted_time_to_live(&slot.api_version, &reads.lock().unwrap()),
+    };
+    if let Some((ttl, stale_while_revalidate)) = ttls.filter(|_| body.len() <= MAX_BODY_SIZE) {
+        let now = Instant::now();
+        let mut cache = CACHE.lock().unwrap();
+        if cache.len >= MAX_ENTRIES {
+            cache.purge_expired(now);
+        }
+        let full = cache.len >= MAX_ENTRIES;
+        let version = cache.versions.entry(slot.api_version).or_default();
+        if version.generation == slot.generation && !full {
+            let entries = version.entries.entry(slot.key).or_default();
+            let before = entries.len();
+            entries.retain(|entry| entry.stale_until > now && entry.vary != vary);
+            let removed = before - entries.len();
+            entries.push(Entry {
+                vary,
+                stored: now,
+                expires: now + ttl,
+                stale_until: now + ttl + stale_while_revalidate,
+                revalidating: false,
+                headers: parts.headers.clone(),
+                body: body.clone(),
+            });
+            cache.len = cache.len + 1 - removed;
+        }
+    }
+    Ok(Response::from_parts(parts, Body::Const(Some(body))))
+}
+
+/// Sets the caching hints of the entities of `api_version`.
+pub fn set_hints(api_version: &str, hints: HashMap<String, CacheHint>) {
+    let mut cache = CACHE.lock().unwrap();
+    cache
+        .versions
+        .entry(api_version.to_owned())
+        .or_default()
+        .hints = hints;
+}
+
+/// Sets how requests to `api_version` select a tenant.
+pub fn set_tenant(api_version: &str, tenant: Option<TenantSource>) {
+    let mut cache = CACHE.lock().unwrap();
+    cache
+        .versions
+        .entry(api_version.to_owned())
+        .or_default()
+        .tenant = tenant;
+}
+
+/// Drops the cached responses of `api_version`.
+pub fn invalidate(api_version: &str) {
+    if api_version == "__chiselstrike" {
+        return invalidate_all();
+    }
+    let mut cache = CACHE.lock().unwrap();
+    let version = cache.versions.entry(api_version.to_owned()).or_default();
+    version.generation += 1;
+    let removed: usize = version
+        .entries
+        .drain()
+        .map(|(_, entries)| entries.len())
+        .sum();
+    cache.len -= removed;
+}
+
+/// Drops all cached responses.
+pub fn invalidate_all() {
+    let mut cache = CACHE.lock().unwrap();
+    for version in cache.versions.values_mut() {
+        version.generation += 1;
+        version.entries.clear();
+    }
+    cache.len = 0;
+}
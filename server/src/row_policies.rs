@@ -0,0 +1,375 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Row-level security.
+//!
+//! The `rows` section of the policies of a version restricts the rows of an
+//! entity that requests can read, depending on the user logged in:
+//!
+//! ```yaml
+//! rows:
+//!   - match: Comment
+//!     read: author == @user.id
+//!   - match: Post
+//!     read: published == true || author == @user.id
+//! ```
+//!
+//! A condition compares fields of the entity, or of the entities it refers
+//! to like `post.author`, with `@user.id`, the id of the user logged in, or
+//! with string, number and boolean literals. Comparisons are joined with
+//! `&&` and `||`, and grouped with parentheses.
+//!
+//! Conditions are compiled into the WHERE clause of every query reading the
+//! entity, be it directly, nested in another entity, or to delete its rows,
+//! so rows a user can't read never leave the database. All the rules of an
+//! entity must hold. Without a logged-in user, `@user.id` matches no row.
+
+use crate::datastore::expr::{BinaryExpr, BinaryOp, Expr, PropertyAccess, Value};
+use anyhow::{anyhow, Result};
+use yaml_rust::Yaml;
+
+/// A rule restricting the rows of an entity that can be read.
+#[derive(Clone, Debug)]
+pub struct RowPolicy {
+    /// Name of the entity the rule applies to.
+    pub entity: String,
+    /// Condition that the rows read must satisfy.
+    read: Condition,
+}
+
+#[derive(Clone, Debug)]
+enum Condition {
+    Compare(Operand, BinaryOp, Operand),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    /// A field, through the entities referred to on the way.
+    Property(Vec<String>),
+    /// `@user.id`
+    UserId,
+    Literal(Value),
+}
+
+impl RowPolicy {
+    pub fn from_yaml(yaml: &Yaml) -> Result<Self> {
+        let entity = yaml["match"]
+            .as_str()
+            .ok_or_else(|| anyhow!("row policy without an entity to match: {:?}", yaml))?;
+        let read = yaml["read"]
+            .as_str()
+            .ok_or_else(|| anyhow!("row policy of {} without a read condition", entity))?;
+        let read = Condition::parse(read)
+            .map_err(|e| anyhow!("invalid read condition of {} `{}`: {}", entity, read, e))?;
+        Ok(Self {
+            entity: entity.to_owned(),
+            read,
+        })
+    }
+
+    /// The filter keeping the rows of `object`, an entity or a property
+    /// chain leading to one, that user `user_id` can read.
+    pub fn read_filter(&self, object: &Expr, user_id: Option<&str>) -> Expr {
+        self.read.to_expr(object, user_id)
+    }
+}
+
+impl Condition {
+    fn parse(source: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+        };
+        let condition = parser.disjunction()?;
+        match parser.next() {
+            None => Ok(condition),
+            Some(token) => anyhow::bail!("unexpected {}", token),
+        }
+    }
+
+    fn to_expr(&self, object: &Expr, user_id: Option<&str>) -> Expr {
+        match self {
+            Condition::Compare(left, op, right) => BinaryExpr::new(
+                op.clone(),
+                left.to_expr(object, user_id),
+                right.to_expr(object, user_id),
+            )
+            .into(),
+            Condition::And(left, right) => BinaryExpr::and(
+                left.to_expr(object, user_id),
+                right.to_expr(object, user_id),
+            ),
+            Condition::Or(left, right) => BinaryExpr::or(
+                left.to_expr(object, user_id),
+                right.to_expr(object, user_id),
+            ),
+        }
+    }
+}
+
+impl Operand {
+    fn to_expr(&self, object: &Expr, user_id: Option<&str>) -> Expr {
+        match self {
+            Operand::Property(path) => path.iter().fold(object.clone(), |object, property| {
+                PropertyAccess {
+                    property: property.clone(),
+                    object: object.into(),
+                }
+                .into()
+            }),
+            // NULL compares as neither equal nor unequal to anything, so
+            // anonymous requests match no row.
+            Operand::UserId => Value::from(user_id.map(Value::from)).into(),
+            Operand::Literal(value) => value.clone().into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Token {
+    Path(Vec<String>),
+    UserId,
+    Literal(Value),
+    Op(BinaryOp),
+    And,
+    Or,
+    LParen,
+    RParen,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Path(path) => write!(f, "`{}`", path.join(".")),
+            Token::UserId => write!(f, "`@user.id`"),
+            Token::Literal(value) => {
+                let value = serde_json::to_string(value).map_err(|_| std::fmt::Error)?;
+                write!(f, "`{}`", value)
+            }
+            Token::Op(op) => write!(f, "`{}`", op.to_sql_string()),
+            Token::And => write!(f, "`&&`"),
+            Token::Or => write!(f, "`||`"),
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let rest: String = chars[i..].iter().take(2).collect();
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let (token, len) = match (c, rest.as_str()) {
+            (_, "==") => (Token::Op(BinaryOp::Eq), 2),
+            (_, "!=") => (Token::Op(BinaryOp::NotEq), 2),
+            (_, "<=") => (Token::Op(BinaryOp::LtEq), 2),
+            (_, ">=") => (Token::Op(BinaryOp::GtEq), 2),
+            (_, "&&") => (Token::And, 2),
+            (_, "||") => (Token::Or, 2),
+            ('<', _) => (Token::Op(BinaryOp::Lt), 1),
+            ('>', _) => (Token::Op(BinaryOp::Gt), 1),
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            ('"' | '\'', _) => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&other| other == c)
+                    .ok_or_else(|| anyhow!("unterminated string"))?;
+                let s: String = chars[i + 1..i + 1 + end].iter().collect();
+                (Token::Literal(Value::String(s)), end + 2)
+            }
+            _ => {
+                let len = chars[i..]
+                    .iter()
+                    .position(|&ch| !is_word_char(ch))
+                    .unwrap_or(chars.len() - i);
+                if len == 0 {
+                    anyhow::bail!("unexpected `{}`", c);
+                }
+                let word: String = chars[i..i + len].iter().collect();
+                (word_token(&word)?, len)
+            }
+        };
+        tokens.push(token);
+        i += len;
+    }
+    Ok(tokens)
+}
+
+/// Whether `c` can be part of a field, a variable or a number.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | '@' | '-')
+}
+
+fn word_token(word: &str) -> Result<Token> {
+    let token = match word {
+        "@user.id" => Token::UserId,
+        "true" => Token::Literal(Value::Bool(true)),
+        "false" => Token::Literal(Value::Bool(false)),
+        _ if word.starts_with('@') => {
+            anyhow::bail!("unknown variable `{}`, expected `@user.id`", word)
+        }
+        _ if word.starts_with(|c: char| c.is_ascii_digit() || c == '-') => {
+            if let Ok(n) = word.parse::<i64>() {
+                Token::Literal(Value::I64(n))
+            } else if let Ok(n) = word.parse::<f64>() {
+                Token::Literal(Value::F64(n))
+            } else {
+                anyhow::bail!("invalid number `{}`", word)
+            }
+        }
+        _ => {
+            let path: Vec<String> = word.split('.').map(str::to_owned).collect();
+            let valid = |name: &String| {
+                name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                    && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+            };
+            anyhow::ensure!(path.iter().all(valid), "invalid field `{}`", word);
+            Token::Path(path)
+        }
+    };
+    Ok(token)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn disjunction(&mut self) -> Result<Condition> {
+        let mut condition = self.conjunction()?;
+        while matches!(self.peek(), Some(Token::Or)) {
+            self.pos += 1;
+            condition = Condition::Or(condition.into(), self.conjunction()?.into());
+        }
+        Ok(condition)
+    }
+
+    fn conjunction(&mut self) -> Result<Condition> {
+        let mut condition = self.comparison()?;
+        while matches!(self.peek(), Some(Token::And)) {
+            self.pos += 1;
+            condition = Condition::And(condition.into(), self.comparison()?.into());
+        }
+        Ok(condition)
+    }
+
+    fn comparison(&mut self) -> Result<Condition> {
+        if matches!(self.peek(), Some(Token::LParen)) {
+            self.pos += 1;
+            let condition = self.disjunction()?;
+            return match self.next() {
+                Some(Token::RParen) => Ok(condition),
+                Some(token) => anyhow::bail!("expected `)`, got {}", token),
+                None => anyhow::bail!("expected `)`"),
+            };
+        }
+        let left = self.operand()?;
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            Some(token) => anyhow::bail!("expected a comparison, got {}", token),
+            None => anyhow::bail!("expected a comparison"),
+        };
+        let right = self.operand()?;
+        anyhow::ensure!(
+            matches!(left, Operand::Property(_)) || matches!(right, Operand::Property(_)),
+            "comparisons must involve a field"
+        );
+        Ok(Condition::Compare(left, op, right))
+    }
+
+    fn operand(&mut self) -> Result<Operand> {
+        match self.next() {
+            Some(Token::Path(path)) => Ok(Operand::Property(path)),
+            Some(Token::UserId) => Ok(Operand::UserId),
+            Some(Token::Literal(value)) => Ok(Operand::Literal(value)),
+            Some(token) => anyhow::bail!("expected a field or a value, got {}", token),
+            None => anyhow::bail!("expected a field or a value"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(condition: &str, user_id: Option<&str>) -> Expr {
+        Condition::parse(condition)
+            .unwrap()
+            .to_expr(&Expr::Parameter { position: 0 }, user_id)
+    }
+
+    fn property(path: &[&str]) -> Expr {
+        path.iter()
+            .fold(Expr::Parameter { position: 0 }, |object, property| {
+                PropertyAccess {
+                    property: property.to_string(),
+                    object: object.into(),
+                }
+                .into()
+            })
+    }
+
+    #[test]
+    fn compiles_conditions() {
+        assert_eq!(
+            filter("author == @user.id", Some("u1")),
+            BinaryExpr::eq(property(&["author"]), Value::from("u1").into())
+        );
+        assert_eq!(
+            filter("author == @user.id", None),
+            BinaryExpr::eq(property(&["author"]), Value::Null.into())
+        );
+        assert_eq!(
+            filter(
+                "post.author != 'x' && (score >= -2 || public == true)",
+                None
+            ),
+            BinaryExpr::and(
+                BinaryExpr::not_eq(property(&["post", "author"]), Value::from("x").into()),
+                BinaryExpr::or(
+                    BinaryExpr::gt_eq(property(&["score"]), Value::I64(-2).into()),
+                    BinaryExpr::eq(property(&["public"]), Value::Bool(true).into()),
+                ),
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_conditions() {
+        for (condition, error) in [
+            ("author = @user.id", "unexpected `=`"),
+            (
+                "author == @user.name",
+                "unknown variable `@user.name`, expected `@user.id`",
+            ),
+            ("author ==", "expected a field or a value"),
+            ("(author == @user.id", "expected `)`"),
+            ("author == 'x", "unterminated string"),
+            ("1 == 2", "comparisons must involve a field"),
+            ("author == @user.id author", "unexpected `author`"),
+        ] {
+            let e = Condition::parse(condition).unwrap_err();
+            assert_eq!(e.to_string(), error, "{}", condition);
+        }
+    }
+}
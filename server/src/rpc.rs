@@ -2,25 +2,56 @@
 
 use crate::api::{ApiInfo, RequestPath};
 use crate::apply::{self, ApplyResult};
+use crate::assets;
+use crate::auth;
+use crate::auth_provider::ApiKey;
+use crate::datastore::explain;
+use crate::datastore::policy_impact::entity_policy_impact;
+use crate::datastore::stats::entity_stats;
+use crate::datastore::verify::entity_checksum;
 use crate::datastore::{MetaService, QueryEngine};
 use crate::deno;
 use crate::deno::endpoint_path_from_source_path;
 use crate::deno::mutate_policies;
 use crate::deno::remove_type_version;
 use crate::deno::set_type_system;
+use crate::faults::{self, FaultKind};
+use crate::inflight;
 use crate::internal::mark_ready;
-use crate::policies::Policies;
+use crate::introspect::{self, OpenApiEndpoint};
+use crate::jobs;
+use crate::memory;
+use crate::migration_gate;
+use crate::notifications::{notify, EventKind};
+use crate::policies::{Policies, VersionPolicy};
 use crate::prefix_map::PrefixMap;
+use crate::privacy;
+use crate::profiling;
 use crate::proto::chisel_rpc_server::{ChiselRpc, ChiselRpcServer};
 use crate::proto::{
-    self, ChiselApplyRequest, ChiselApplyResponse, ChiselDeleteRequest, ChiselDeleteResponse,
-    DescribeRequest, DescribeResponse, PopulateRequest, PopulateResponse, RestartRequest,
-    RestartResponse, StatusRequest, StatusResponse,
+    self, ApplyPlan, ApproveMigrationRequest, ApproveMigrationResponse, ChiselApplyRequest,
+    ChiselApplyResponse, ChiselDeleteRequest, ChiselDeleteResponse, ClearFaultsRequest,
+    ClearFaultsResponse, CreateApiKeyRequest, CreateApiKeyResponse, DescribeRequest,
+    DescribeResponse, ExplainRequest, ExplainResponse, ExplainedStatement, HandshakeRequest,
+    HandshakeResponse, InFlightRequest, InjectFaultRequest, InjectFaultResponse, KillRequest,
+    KillResponse, ListApiKeysRequest, ListApiKeysResponse, ListFaultsRequest, ListFaultsResponse,
+    ListMigrationsRequest, ListMigrationsResponse, ListProfilesRequest, ListProfilesResponse,
+    ListReadOnlyRequest, ListReadOnlyResponse, PolicyImpactRequest, PolicyImpactResponse,
+    PopulateRequest, PopulateResponse, PrivacyEraseRequest, PrivacyEraseResponse,
+    PrivacyExportRequest, PrivacyExportResponse, ProfileSpan, PsRequest, PsResponse,
+    ReadOnlyWindow, RejectMigrationRequest, RejectMigrationResponse, RequestProfile,
+    RestartRequest, RestartResponse, RevokeApiKeyRequest, RevokeApiKeyResponse, StagedMigration,
+    StartReadOnlyRequest, StartReadOnlyResponse, StatsRequest, StatsResponse, StatusRequest,
+    StatusResponse, StopReadOnlyRequest, StopReadOnlyResponse, UnitTestRequest, UnitTestResponse,
+    VerifyRequest, VerifyResponse,
 };
+use crate::read_only;
+use crate::response_cache;
 use crate::runtime;
 use crate::server::CommandTrait;
 use crate::server::CoordinatorChannel;
-use crate::types::{Entity, TypeSystem};
+use crate::tasks;
+use crate::types::{datetime, Entity, PopulateChanges, PopulateIds, TypeSystem};
 use anyhow::{Context, Result};
 use async_lock::Mutex;
 use deno_core::futures;
@@ -29,6 +60,7 @@ use futures::FutureExt;
 use std::collections::{BTreeSet, HashMap};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tonic::{transport::Server, Request, Response, Status};
 use utils::without_extension;
 use uuid::Uuid;
@@ -155,6 +187,15 @@ impl RpcService {
 
         meta.delete_policy_version(&mut transaction, &api_version)
             .await?;
+        meta.delete_populate_marks(&mut transaction, &api_version)
+            .await?;
+        meta.delete_cache_hints(&mut transaction, &api_version)
+            .await?;
+        meta.delete_tasks(&mut transaction, &api_version).await?;
+        meta.delete_endpoint_schemas(&mut transaction, &api_version)
+            .await?;
+        meta.delete_static_files(&mut transaction, &api_version)
+            .await?;
 
         for ty in to_remove.iter() {
             meta.remove_type(&mut transaction, ty).await?;
@@ -189,6 +230,15 @@ impl RpcService {
             Ok(())
         });
         state.send_command(cmd).await?;
+        jobs::retain(&format!("/{}/", api_version), &[]);
+        tasks::retain(&format!("/{}/", api_version), &[]);
+        response_cache::invalidate(&api_version);
+        response_cache::set_hints(&api_version, Default::default());
+        response_cache::set_tenant(&api_version, None);
+        introspect::set_schemas(&api_version, Default::default());
+        assets::set_files(&api_version, Default::default());
+        read_only::stop(&api_version);
+        memory::forget_version(&api_version);
 
         Ok(Response::new(ChiselDeleteResponse {
             result: format!("deleted {}", api_version),
@@ -203,13 +253,50 @@ impl RpcService {
 
         let to = request.to_version.clone();
         let from = request.from_version.clone();
+        let ids = match proto::IdMode::from_i32(request.id_mode) {
+            Some(proto::IdMode::Preserve) => PopulateIds::Preserve,
+            Some(proto::IdMode::Remap) => {
+                let namespace = if request.id_namespace.is_empty() {
+                    format!("{}:{}", from, to)
+                } else {
+                    request.id_namespace
+                };
+                PopulateIds::Remap { namespace }
+            }
+            Some(proto::IdMode::Regenerate) => PopulateIds::Regenerate,
+            None => anyhow::bail!("unknown ID mode {}", request.id_mode),
+        };
 
         let state = self.state.lock().await;
 
+        let mut changes = if request.changed_field.is_empty() {
+            None
+        } else {
+            anyhow::ensure!(
+                !matches!(ids, PopulateIds::Regenerate),
+                "a differential populate can't regenerate IDs, as it copies some objects again"
+            );
+            let field = request.changed_field;
+            let marks = state.meta.load_populate_marks(&from, &to, &field).await?;
+            Some(PopulateChanges { field, marks })
+        };
         state
             .type_system
-            .populate_types(state.query_engine.clone(), &to, &from)
+            .populate_types(
+                state.query_engine.clone(),
+                &to,
+                &from,
+                &ids,
+                changes.as_mut(),
+            )
             .await?;
+        if let Some(changes) = changes {
+            state
+                .meta
+                .persist_populate_marks(&from, &to, &changes.field, &changes.marks)
+                .await?;
+        }
+        response_cache::invalidate(&to);
 
         let response = proto::PopulateResponse {
             msg: "OK".to_string(),
@@ -217,6 +304,486 @@ impl RpcService {
 
         Ok(Response::new(response))
     }
+    async fn privacy_export_aux(
+        &self,
+        request: Request<PrivacyExportRequest>,
+    ) -> Result<Response<PrivacyExportResponse>> {
+        let request = request.into_inner();
+        let state = self.state.lock().await;
+
+        let data = privacy::export_user_data(
+            state.query_engine.clone(),
+            &state.type_system,
+            &request.user_id,
+        )
+        .await?;
+
+        Ok(Response::new(PrivacyExportResponse {
+            data: serde_json::to_string_pretty(&data)?,
+        }))
+    }
+
+    async fn privacy_erase_aux(
+        &self,
+        request: Request<PrivacyEraseRequest>,
+    ) -> Result<Response<PrivacyEraseResponse>> {
+        let request = request.into_inner();
+        let state = self.state.lock().await;
+
+        let deleted_rows = privacy::erase_user_data(
+            state.query_engine.clone(),
+            &state.type_system,
+            &request.user_id,
+            request.anonymize,
+        )
+        .await?;
+        response_cache::invalidate_all();
+
+        Ok(Response::new(PrivacyEraseResponse { deleted_rows }))
+    }
+
+    fn ps_aux(&self, _request: Request<PsRequest>) -> Result<Response<PsResponse>> {
+        let requests = inflight::list()
+            .into_iter()
+            .map(|request| InFlightRequest {
+                id: request.id,
+                version: request.api_version,
+                path: request.path,
+                method: request.method,
+                age_ms: request.age.as_millis() as u64,
+                sql: request.sql,
+            })
+            .collect();
+        Ok(Response::new(PsResponse { requests }))
+    }
+
+    fn kill_aux(&self, request: Request<KillRequest>) -> Result<Response<KillResponse>> {
+        let id = request.into_inner().id;
+        anyhow::ensure!(inflight::kill(id), "no request with id {}", id);
+        Ok(Response::new(KillResponse {}))
+    }
+
+    fn list_profiles_aux(
+        &self,
+        request: Request<ListProfilesRequest>,
+    ) -> Result<Response<ListProfilesResponse>> {
+        let request = request.into_inner();
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let profiles =
+            profiling::exemplars(request.version.as_deref(), request.endpoint.as_deref())
+                .into_iter()
+                .map(|profile| RequestProfile {
+                    version: profile.api_version,
+                    path: profile.path,
+                    method: profile.method,
+                    age_ms: profile.recorded.elapsed().unwrap_or_default().as_millis() as u64,
+                    total_ms: millis(profile.total),
+                    js_ms: millis(profile.js),
+                    spans: profile
+                        .spans
+                        .into_iter()
+                        .map(|span| ProfileSpan {
+                            kind: span.kind.name().to_owned(),
+                            label: span.label,
+                            count: span.count,
+                            duration_ms: millis(span.duration),
+                        })
+                        .collect(),
+                })
+                .collect();
+        Ok(Response::new(ListProfilesResponse { profiles }))
+    }
+
+    /// Explains the statements of the latest profiled request to an
+    /// endpoint.
+    async fn explain_aux(
+        &self,
+        request: Request<ExplainRequest>,
+    ) -> Result<Response<ExplainResponse>> {
+        let request = request.into_inner();
+        let profile = profiling::exemplars(request.version.as_deref(), Some(&request.endpoint))
+            .into_iter()
+            .max_by_key(|profile| profile.recorded)
+            .with_context(|| {
+                format!(
+                    "no request to {} was profiled, send one with the {} header first",
+                    request.endpoint,
+                    profiling::PROFILE_HEADER
+                )
+            })?;
+        let query_engine = self.state.lock().await.query_engine.clone();
+        let mut statements = vec![];
+        for sql in profile.statements() {
+            let plan = if !explain::is_explainable(sql) {
+                vec!["(only queries and deletes are explained)".to_owned()]
+            } else {
+                match explain::explain(&query_engine, sql).await {
+                    Ok(plan) => plan,
+                    Err(e) => vec![format!("(could not be explained: {:#})", e)],
+                }
+            };
+            statements.push(ExplainedStatement {
+                sql: sql.to_owned(),
+                plan,
+            });
+        }
+        Ok(Response::new(ExplainResponse {
+            version: profile.api_version.clone(),
+            path: profile.path.clone(),
+            method: profile.method.clone(),
+            age_ms: profile.recorded.elapsed().unwrap_or_default().as_millis() as u64,
+            statements,
+        }))
+    }
+
+    async fn start_read_only_aux(
+        &self,
+        request: Request<StartReadOnlyRequest>,
+    ) -> Result<Response<StartReadOnlyResponse>> {
+        let request = request.into_inner();
+        let state = self.state.lock().await;
+
+        anyhow::ensure!(
+            state.versions.contains(&request.version),
+            "unknown version {}",
+            request.version
+        );
+        for entity in &request.entities {
+            state
+                .type_system
+                .lookup_custom_type(entity, &request.version)
+                .with_context(|| format!("unknown entity {}", entity))?;
+        }
+        let duration = request.duration_secs.map(Duration::from_secs);
+        read_only::start(&request.version, request.entities, duration);
+        Ok(Response::new(StartReadOnlyResponse {}))
+    }
+
+    fn stop_read_only_aux(
+        &self,
+        request: Request<StopReadOnlyRequest>,
+    ) -> Result<Response<StopReadOnlyResponse>> {
+        let version = request.into_inner().version;
+        anyhow::ensure!(
+            read_only::stop(&version),
+            "version {} is not read-only",
+            version
+        );
+        Ok(Response::new(StopReadOnlyResponse {}))
+    }
+
+    fn list_read_only_aux(
+        &self,
+        _request: Request<ListReadOnlyRequest>,
+    ) -> Result<Response<ListReadOnlyResponse>> {
+        let windows = read_only::list()
+            .into_iter()
+            .map(|window| ReadOnlyWindow {
+                version: window.api_version,
+                entities: window.entities,
+                remaining_secs: window.remaining.map(|remaining| remaining.as_secs()),
+            })
+            .collect();
+        Ok(Response::new(ListReadOnlyResponse { windows }))
+    }
+
+    async fn create_api_key_aux(
+        &self,
+        request: Request<CreateApiKeyRequest>,
+    ) -> Result<Response<CreateApiKeyResponse>> {
+        let request = request.into_inner();
+        anyhow::ensure!(!request.name.is_empty(), "API keys must have a name");
+        let user_id = request.user_id.unwrap_or_else(|| request.name.clone());
+        let key = ApiKey::generate();
+        self.state
+            .lock()
+            .await
+            .meta
+            .create_api_key(&ApiKey::new(request.name, user_id), &ApiKey::hash(&key))
+            .await?;
+        Ok(Response::new(CreateApiKeyResponse { key }))
+    }
+
+    async fn revoke_api_key_aux(
+        &self,
+        request: Request<RevokeApiKeyRequest>,
+    ) -> Result<Response<RevokeApiKeyResponse>> {
+        let name = request.into_inner().name;
+        let revoked = self.state.lock().await.meta.revoke_api_key(&name).await?;
+        anyhow::ensure!(revoked, "there is no API key named {}", name);
+        Ok(Response::new(RevokeApiKeyResponse {}))
+    }
+
+    async fn list_api_keys_aux(
+        &self,
+        _request: Request<ListApiKeysRequest>,
+    ) -> Result<Response<ListApiKeysResponse>> {
+        let keys = self
+            .state
+            .lock()
+            .await
+            .meta
+            .load_api_keys()
+            .await?
+            .into_iter()
+            .map(|key| proto::ApiKey {
+                name: key.name,
+                user_id: key.user_id,
+                created_at: key.created_at,
+            })
+            .collect();
+        Ok(Response::new(ListApiKeysResponse { keys }))
+    }
+
+    async fn run_unit_tests_aux(
+        &self,
+        request: Request<UnitTestRequest>,
+    ) -> Result<Response<UnitTestResponse>> {
+        let UnitTestRequest {
+            sources,
+            tests,
+            coverage,
+            cassettes,
+            record,
+        } = request.into_inner();
+        let state = self.state.lock().await;
+
+        let outcome = Arc::new(std::sync::Mutex::new(None));
+        let cmd = {
+            let outcome = outcome.clone();
+            send_command!({
+                let run = deno::run_unit_tests(sources, tests, coverage, cassettes, record).await?;
+                *outcome.lock().unwrap() = Some(run);
+                Ok(())
+            })
+        };
+        // The tests don't use the database, so a single executor runs them.
+        state.commands[0]
+            .send(cmd)
+            .await
+            .context("Could not run the unit tests")?;
+
+        let run = outcome
+            .lock()
+            .unwrap()
+            .take()
+            .context("The unit tests didn't run")?;
+        let results = run
+            .results
+            .into_iter()
+            .map(|result| proto::UnitTestResult {
+                file: result.file,
+                name: result.name,
+                error: result.error,
+                duration_ms: result.duration_ms,
+            })
+            .collect();
+        let coverage = run
+            .coverage
+            .unwrap_or_default()
+            .into_iter()
+            .map(|script| proto::ScriptCoverage {
+                path: script.path,
+                functions: script.functions.to_string(),
+            })
+            .collect();
+        Ok(Response::new(UnitTestResponse {
+            results,
+            coverage,
+            cassettes: run.cassettes,
+        }))
+    }
+
+    async fn inject_fault_aux(
+        &self,
+        request: Request<InjectFaultRequest>,
+    ) -> Result<Response<InjectFaultResponse>> {
+        let rule = request.into_inner().rule.context("missing fault rule")?;
+        let state = self.state.lock().await;
+
+        anyhow::ensure!(
+            state.versions.contains(&rule.version),
+            "unknown version {}",
+            rule.version
+        );
+        let kind = match proto::FaultKind::from_i32(rule.kind) {
+            Some(proto::FaultKind::Database) => FaultKind::Database,
+            Some(proto::FaultKind::SlowQuery) => {
+                FaultKind::SlowQuery(Duration::from_millis(rule.delay_ms))
+            }
+            Some(proto::FaultKind::Fetch) => FaultKind::Fetch,
+            Some(proto::FaultKind::OutOfMemory) => FaultKind::OutOfMemory,
+            None => anyhow::bail!("unknown fault kind {}", rule.kind),
+        };
+        let id = faults::inject(faults::Rule {
+            id: 0,
+            api_version: rule.version,
+            endpoint: rule.endpoint,
+            kind,
+            percent: rule.percent,
+        })?;
+        Ok(Response::new(InjectFaultResponse { id }))
+    }
+
+    fn clear_faults_aux(
+        &self,
+        request: Request<ClearFaultsRequest>,
+    ) -> Result<Response<ClearFaultsResponse>> {
+        let id = request.into_inner().id;
+        if let Some(id) = id {
+            anyhow::ensure!(faults::clear(Some(id)), "no fault rule with id {}", id);
+        } else {
+            faults::clear(None);
+        }
+        Ok(Response::new(ClearFaultsResponse {}))
+    }
+
+    fn list_faults_aux(
+        &self,
+        _request: Request<ListFaultsRequest>,
+    ) -> Result<Response<ListFaultsResponse>> {
+        let rules = faults::list()
+            .into_iter()
+            .map(|rule| {
+                let (kind, delay) = match rule.kind {
+                    FaultKind::Database => (proto::FaultKind::Database, Duration::ZERO),
+                    FaultKind::SlowQuery(delay) => (proto::FaultKind::SlowQuery, delay),
+                    FaultKind::Fetch => (proto::FaultKind::Fetch, Duration::ZERO),
+                    FaultKind::OutOfMemory => (proto::FaultKind::OutOfMemory, Duration::ZERO),
+                };
+                proto::FaultRule {
+                    id: rule.id,
+                    version: rule.api_version,
+                    endpoint: rule.endpoint,
+                    kind: kind as i32,
+                    percent: rule.percent,
+                    delay_ms: delay.as_millis() as u64,
+                }
+            })
+            .collect();
+        Ok(Response::new(ListFaultsResponse { rules }))
+    }
+
+    async fn stats_aux(&self, request: Request<StatsRequest>) -> Result<Response<StatsResponse>> {
+        let request = request.into_inner();
+        let state = self.state.lock().await;
+
+        let mut entities = vec![];
+        for api_version in state.versions.iter() {
+            if !request.version.is_empty() && &request.version != api_version {
+                continue;
+            }
+            if let Some(version_types) = state.type_system.versions.get(api_version) {
+                use itertools::Itertools;
+                for ty in version_types
+                    .custom_types
+                    .values()
+                    .sorted_by(|x, y| x.name().cmp(y.name()))
+                {
+                    let stats = entity_stats(&state.query_engine, ty, request.columns).await?;
+                    let columns = stats
+                        .columns
+                        .into_iter()
+                        .map(|column| proto::ColumnStats {
+                            name: column.name,
+                            distinct_count: column.distinct_count,
+                            null_count: column.null_count,
+                            most_common: column
+                                .most_common
+                                .into_iter()
+                                .map(|(value, count)| proto::ValueFrequency { value, count })
+                                .collect(),
+                        })
+                        .collect();
+                    entities.push(proto::EntityStats {
+                        version: api_version.to_string(),
+                        entity_name: ty.name().to_string(),
+                        row_count: stats.row_count,
+                        columns,
+                    });
+                }
+            }
+        }
+
+        Ok(Response::new(StatsResponse { entities }))
+    }
+
+    async fn verify_aux(
+        &self,
+        request: Request<VerifyRequest>,
+    ) -> Result<Response<VerifyResponse>> {
+        let request = request.into_inner();
+        let state = self.state.lock().await;
+
+        let version_types = state
+            .type_system
+            .versions
+            .get(&request.version)
+            .with_context(|| format!("unknown version {}", request.version))?;
+        use itertools::Itertools;
+        let mut entities = vec![];
+        for ty in version_types
+            .custom_types
+            .values()
+            .sorted_by(|x, y| x.name().cmp(y.name()))
+        {
+            let checksum =
+                entity_checksum(state.query_engine.clone(), ty, request.ignore_ids).await?;
+            entities.push(proto::EntityChecksum {
+                entity_name: ty.name().to_string(),
+                row_count: checksum.row_count,
+                checksum: checksum.checksum,
+            });
+        }
+        Ok(Response::new(VerifyResponse { entities }))
+    }
+
+    async fn policy_impact_aux(
+        &self,
+        request: Request<PolicyImpactRequest>,
+    ) -> Result<Response<PolicyImpactResponse>> {
+        let request = request.into_inner();
+        let proposed = VersionPolicy::from_yaml(&request.policy_config)?;
+        let state = self.state.lock().await;
+
+        let version_types = state
+            .type_system
+            .versions
+            .get(&request.version)
+            .with_context(|| format!("unknown version {}", request.version))?;
+        let current = state.policies.versions.get(&request.version);
+        use itertools::Itertools;
+        let mut entities = vec![];
+        for ty in version_types
+            .custom_types
+            .values()
+            .sorted_by(|x, y| x.name().cmp(y.name()))
+        {
+            let impact =
+                entity_policy_impact(&state.query_engine, ty, current, &proposed, request.sample)
+                    .await?;
+            if let Some(impact) = impact {
+                entities.push(proto::EntityPolicyImpact {
+                    entity_name: ty.name().to_string(),
+                    total_rows: impact.total_rows,
+                    sampled_rows: impact.sampled_rows,
+                    newly_filtered_rows: impact.newly_filtered_rows,
+                    fields: impact
+                        .fields
+                        .into_iter()
+                        .map(|field| proto::FieldPolicyImpact {
+                            field_name: field.field_name,
+                            before: field.before,
+                            after: field.after,
+                            affected_rows: field.affected_rows,
+                        })
+                        .collect(),
+                });
+            }
+        }
+        Ok(Response::new(PolicyImpactResponse { entities }))
+    }
+
     /// Apply a new version of ChiselStrike
     async fn apply_aux(
         &self,
@@ -232,8 +799,19 @@ impl RpcService {
         let mut state = self.state.lock().await;
         let api_info = ApiInfo::new(app_name, api_version_tag);
 
-        let mut endpoint_paths = vec![];
-        let mut event_handler_paths = vec![];
+        if migration_gate::enabled() && !apply_request.dry_run {
+            let plan = plan_apply(&state, &apply_request, &api_version, &api_info).await?;
+            let changes = migration_gate::destructive_changes(&plan);
+            if !migration_gate::check(&api_version, &changes) {
+                let id = migration_gate::stage(apply_request, changes);
+                return Ok(Response::new(ChiselApplyResponse {
+                    plan: Some(plan),
+                    staged_migration: Some(id),
+                    ..Default::default()
+                }));
+            }
+        }
+
         let mut sources = HashMap::new();
         for (path, code) in apply_request.sources.drain() {
             if Url::parse(&path).is_ok() {
@@ -242,21 +820,47 @@ impl RpcService {
             }
 
             sources.insert(format!("/{}/{}", api_version, path), code.clone());
-            let path = without_extension(&path);
-            if let Some(path) = path
-                .strip_prefix("routes/")
-                .or_else(|| path.strip_prefix("endpoints/"))
-            {
-                let path = format!("/{}/{}", api_version, path);
-                endpoint_paths.push(path);
-            }
-            if let Some(path) = path.strip_prefix("events/") {
-                let path = format!("/{}/{}", api_version, path);
-                event_handler_paths.push(path);
+        }
+        add_crud_routes(&apply_request, &api_version, &mut sources)?;
+        add_login_route(&apply_request, &api_version, &mut sources)?;
+        let (endpoint_paths, event_handler_paths) =
+            handler_paths(&api_version, sources.keys().map(String::as_str));
+        let (old_endpoint_paths, old_event_handler_paths) =
+            handler_paths(&api_version, state.sources.iter().map(|(path, _)| path));
+        let job_paths = module_paths(&api_version, "jobs", sources.keys().map(String::as_str));
+        let task_paths = module_paths(&api_version, "tasks", sources.keys().map(String::as_str));
+        let plan_handlers = |plan: &mut ApplyPlan| {
+            (plan.endpoints_added, plan.endpoints_removed) =
+                added_and_removed(&old_endpoint_paths, &endpoint_paths);
+            (plan.event_handlers_added, plan.event_handlers_removed) =
+                added_and_removed(&old_event_handler_paths, &event_handler_paths);
+        };
+
+        anyhow::ensure!(
+            "__chiselstrike" != &api_version,
+            "__chiselstrike is a reserved version name"
+        );
+        if !apply_request.static_files.is_empty() {
+            let static_prefix = format!("/{}/static", api_version);
+            if let Some(path) = endpoint_paths.iter().find(|path| {
+                **path == static_prefix || path.starts_with(&format!("{}/", static_prefix))
+            }) {
+                anyhow::bail!(
+                    "route {} would shadow the static files served under {}/",
+                    path,
+                    static_prefix
+                );
             }
         }
-        endpoint_paths.sort_unstable();
-        event_handler_paths.sort_unstable();
+
+        if apply_request.dry_run {
+            let mut plan = plan_apply(&state, &apply_request, &api_version, &api_info).await?;
+            plan_handlers(&mut plan);
+            return Ok(Response::new(ChiselApplyResponse {
+                plan: Some(plan),
+                ..Default::default()
+            }));
+        }
 
         // Do this before any permanent changes to any of the databases. Otherwise
         // we end up with bad code commited to the meta database and will fail to load
@@ -271,11 +875,6 @@ impl RpcService {
             .await
             .context("Could not apply the provided code")?;
 
-        anyhow::ensure!(
-            "__chiselstrike" != &api_version,
-            "__chiselstrike is a reserved version name"
-        );
-
         // so that an empty apply removes the version.
         // We'll add it back as soon as we notice this is not empty
         state.versions.remove(&api_version);
@@ -284,6 +883,9 @@ impl RpcService {
             type_names_user_order,
             labels,
             version_policy,
+            cache_hints,
+            endpoint_schemas,
+            mut plan,
         } = {
             // help the borrow checker figure out that the borrows below are safe
             let state: &mut GlobalRpcState = &mut state;
@@ -299,6 +901,13 @@ impl RpcService {
             .await?
         };
 
+        plan_handlers(&mut plan);
+        assets::set_files(
+            &api_version,
+            std::mem::take(&mut apply_request.static_files),
+        );
+        let has_static_files = assets::has_files(&api_version);
+
         let prefix = format!("/{}/", api_version);
         state.sources.remove_prefix(&prefix);
 
@@ -310,12 +919,20 @@ impl RpcService {
 
         let types_global = state.type_system.clone();
 
-        if !endpoint_paths.is_empty() || types_global.get_version(&api_version).is_ok() {
+        if !endpoint_paths.is_empty()
+            || has_static_files
+            || !job_paths.is_empty()
+            || !task_paths.is_empty()
+            || types_global.get_version(&api_version).is_ok()
+        {
             state.versions.insert(api_version.clone());
         }
 
         let endpoints_for_cmd = endpoint_paths.clone();
         let event_handlers_for_cmd = event_handler_paths.clone();
+        let jobs_for_cmd = job_paths.clone();
+        let tasks_for_cmd = task_paths.clone();
+        let tenant_source = version_policy.tenant.clone();
         let cmd = send_command!({
             {
                 set_type_system(types_global.clone()).await;
@@ -328,6 +945,9 @@ impl RpcService {
                 let runtime = runtime::get();
                 runtime.api.remove_routes(&prefix);
 
+                if has_static_files {
+                    assets::add_route(&runtime.api, &api_version);
+                }
                 for path in &endpoints_for_cmd {
                     let func = Arc::new({
                         let path = path.clone();
@@ -352,10 +972,22 @@ impl RpcService {
             for path in event_handlers_for_cmd {
                 deno::activate_event_handler(&path).await?;
             }
+            for path in jobs_for_cmd {
+                deno::activate_job(&path).await?;
+            }
+            for path in tasks_for_cmd {
+                deno::activate_task(&path).await?;
+            }
             Ok(())
         });
         // FIXME: activate_event_handlers()
         state.send_command(cmd).await?;
+        jobs::retain(&prefix, &job_paths);
+        tasks::retain(&prefix, &task_paths);
+        response_cache::set_hints(&api_version, cache_hints);
+        response_cache::set_tenant(&api_version, tenant_source);
+        response_cache::invalidate(&api_version);
+        introspect::set_schemas(&api_version, endpoint_schemas);
 
         // FIXME: return number of effective changes? Probably depends on how we implement
         // terraform-like workflow (x added, y removed, z modified)
@@ -364,25 +996,287 @@ impl RpcService {
             endpoints: endpoint_paths,
             labels,
             event_handlers: event_handler_paths,
+            plan: Some(plan),
+            staged_migration: None,
+            jobs: job_paths,
+            tasks: task_paths,
         }))
     }
+
+    async fn list_migrations_aux(
+        &self,
+        _request: Request<ListMigrationsRequest>,
+    ) -> Result<Response<ListMigrationsResponse>> {
+        let migrations = migration_gate::list()
+            .into_iter()
+            .map(|staged| StagedMigration {
+                id: staged.id,
+                version: staged.request.version,
+                changes: staged.changes,
+            })
+            .collect();
+        Ok(Response::new(ListMigrationsResponse { migrations }))
+    }
+
+    async fn approve_migration_aux(
+        &self,
+        request: Request<ApproveMigrationRequest>,
+    ) -> Result<Response<ApproveMigrationResponse>> {
+        let id = request.into_inner().id;
+        let staged = migration_gate::approve(id)
+            .with_context(|| format!("there is no staged migration {}", id))?;
+        Ok(Response::new(ApproveMigrationResponse {
+            request: Some(staged.request),
+        }))
+    }
+
+    async fn reject_migration_aux(
+        &self,
+        request: Request<RejectMigrationRequest>,
+    ) -> Result<Response<RejectMigrationResponse>> {
+        let id = request.into_inner().id;
+        anyhow::ensure!(
+            migration_gate::reject(id),
+            "there is no staged migration {}",
+            id
+        );
+        Ok(Response::new(RejectMigrationResponse {}))
+    }
+}
+
+/// Plans `apply_request` without changing anything.
+async fn plan_apply(
+    state: &GlobalRpcState,
+    apply_request: &ChiselApplyRequest,
+    api_version: &str,
+    api_info: &ApiInfo,
+) -> Result<ApplyPlan> {
+    let apply_request = ChiselApplyRequest {
+        dry_run: true,
+        ..apply_request.clone()
+    };
+    // Plan against copies, which apply() may touch before it returns.
+    let ApplyResult { plan, .. } = apply::apply(
+        &state.query_engine,
+        &state.meta,
+        &mut state.type_system.clone(),
+        &mut state.policies.clone(),
+        &apply_request,
+        api_version.to_owned(),
+        api_info,
+    )
+    .await?;
+    Ok(plan)
+}
+
+/// Adds to `sources` the routes that `@autoCrud` generates for the types of
+/// `apply_request`. They serve their entity like `ChiselEntity.crud()`,
+/// whose methods run in the server on top of the query engine, so that the
+/// policies and the transaction of the request apply to them. The routes
+/// declare an empty class of the entity, as they can't import the model; the
+/// CLI refuses `@autoCrud` on classes with hooks, which they would miss.
+fn add_crud_routes(
+    apply_request: &ChiselApplyRequest,
+    api_version: &str,
+    sources: &mut HashMap<String, String>,
+) -> Result<()> {
+    let valid_path = regex::Regex::new(r"^[-_[[:alnum:]]]+(/[-_[[:alnum:]]]+)*$").unwrap();
+    for ty in &apply_request.types {
+        if ty.crud_path.is_empty() {
+            continue;
+        }
+        anyhow::ensure!(
+            valid_path.is_match(&ty.crud_path),
+            "the @autoCrud path of {} must be letters, digits, - and _, separated by /, got `{}`",
+            ty.name,
+            ty.crud_path
+        );
+        for dir in ["routes", "endpoints"] {
+            let route = format!("/{}/{}/{}", api_version, dir, ty.crud_path);
+            if let Some(path) = sources.keys().find(|path| without_extension(path) == route) {
+                anyhow::bail!(
+                    "route /{}/{} of @autoCrud on {} is also defined by {}",
+                    api_version,
+                    ty.crud_path,
+                    ty.name,
+                    path
+                );
+            }
+        }
+        let config = match ty.crud_pagination.as_str() {
+            "" => String::new(),
+            pagination => format!(", {{ pagination: {} }}", serde_json::to_string(pagination)?),
+        };
+        let code = format!(
+            "import {{ ChiselEntity, crud }} from \"@chiselstrike/api\";\n\
+             class {name} extends ChiselEntity {{}}\n\
+             export default crud({name}, \"\"{config});\n",
+            name = ty.name,
+            config = config
+        );
+        sources.insert(format!("/{}/routes/{}.js", api_version, ty.crud_path), code);
+    }
+    Ok(())
+}
+
+/// Adds to `sources` the route under which users log in with the providers
+/// that the `login` key of the manifest lists. It runs the OAuth code flow
+/// in the server, and is the only route of a version that writes the auth
+/// entities, so a project can't define a route at its path.
+fn add_login_route(
+    apply_request: &ChiselApplyRequest,
+    api_version: &str,
+    sources: &mut HashMap<String, String>,
+) -> Result<()> {
+    for dir in ["routes", "endpoints"] {
+        let route = format!("/{}/{}{}", api_version, dir, auth::LOGIN_ROUTE);
+        if let Some(path) = sources.keys().find(|path| without_extension(path) == route) {
+            anyhow::bail!(
+                "route /{}{} is reserved for login, but is defined by {}",
+                api_version,
+                auth::LOGIN_ROUTE,
+                path
+            );
+        }
+    }
+    if apply_request.login_providers.is_empty() {
+        return Ok(());
+    }
+    let valid_name = regex::Regex::new(r"^[-_[[:alnum:]]]+$").unwrap();
+    for name in &apply_request.login_providers {
+        anyhow::ensure!(
+            valid_name.is_match(name),
+            "login provider names must be letters, digits, - and _, got `{}`",
+            name
+        );
+    }
+    let code = format!(
+        "import {{ loginRoutes }} from \"@chiselstrike/api\";\n\
+         export default loginRoutes({});\n",
+        serde_json::to_string(&apply_request.login_providers)?
+    );
+    sources.insert(
+        format!("/{}/routes{}.js", api_version, auth::LOGIN_ROUTE),
+        code,
+    );
+    Ok(())
+}
+
+/// Splits the paths of the sources of `api_version` into the paths of the
+/// endpoints and of the event handlers they define, both sorted.
+fn handler_paths<'a>(
+    api_version: &str,
+    sources: impl Iterator<Item = &'a str>,
+) -> (Vec<String>, Vec<String>) {
+    let prefix = format!("/{}/", api_version);
+    let mut endpoint_paths = vec![];
+    let mut event_handler_paths = vec![];
+    for path in sources {
+        let path = match path.strip_prefix(&prefix) {
+            Some(path) => without_extension(path),
+            None => continue,
+        };
+        if let Some(path) = path
+            .strip_prefix("routes/")
+            .or_else(|| path.strip_prefix("endpoints/"))
+        {
+            endpoint_paths.push(format!("{}{}", prefix, path));
+        }
+        if let Some(path) = path.strip_prefix("events/") {
+            event_handler_paths.push(format!("{}{}", prefix, path));
+        }
+    }
+    endpoint_paths.sort_unstable();
+    event_handler_paths.sort_unstable();
+    (endpoint_paths, event_handler_paths)
+}
+
+/// The paths of the modules of `api_version` in directory `dir` of
+/// `sources`, like its jobs.
+fn module_paths<'a>(
+    api_version: &str,
+    dir: &str,
+    sources: impl Iterator<Item = &'a str>,
+) -> Vec<String> {
+    let prefix = format!("/{}/", api_version);
+    let dir = format!("{}/", dir);
+    let mut paths: Vec<String> = sources
+        .filter_map(|path| path.strip_prefix(&prefix))
+        .filter_map(|path| without_extension(path).strip_prefix(&dir))
+        .map(|path| format!("{}{}", prefix, path))
+        .collect();
+    paths.sort_unstable();
+    paths
+}
+
+/// Returns the paths in `new` but not in `old`, and those in `old` but not
+/// in `new`.
+fn added_and_removed(old: &[String], new: &[String]) -> (Vec<String>, Vec<String>) {
+    let added = new.iter().filter(|p| !old.contains(p)).cloned().collect();
+    let removed = old.iter().filter(|p| !new.contains(p)).cloned().collect();
+    (added, removed)
 }
 
 #[tonic::async_trait]
 impl ChiselRpc for RpcService {
+    /// Tell chisel which of its versions work with this server.
+    async fn handshake(
+        &self,
+        request: Request<HandshakeRequest>,
+    ) -> Result<Response<HandshakeResponse>, Status> {
+        let server_version = env!("CARGO_PKG_VERSION");
+        let (min_client_version, max_client_version) =
+            utils::compatible_versions(server_version)
+                .map_err(|e| Status::internal(e.to_string()))?;
+        let client_version = request.into_inner().client_version;
+        if !utils::version_in_range(&client_version, &min_client_version, &max_client_version)
+            .unwrap_or(false)
+        {
+            warn!(
+                "chisel {} connected, but chiseld {} only works with chisel >= {} and < {}",
+                client_version, server_version, min_client_version, max_client_version
+            );
+        }
+        Ok(Response::new(HandshakeResponse {
+            server_version: server_version.to_owned(),
+            min_client_version,
+            max_client_version,
+        }))
+    }
+
     /// Get Chisel server status.
     async fn get_status(
         &self,
-        _request: Request<StatusRequest>,
+        request: Request<StatusRequest>,
     ) -> Result<Response<StatusResponse>, Status> {
         let server_id = {
             let state = self.state.lock().await;
             state.id.to_string()
         };
-        let response = proto::StatusResponse {
+        let mut response = proto::StatusResponse {
             server_id,
             message: "OK".to_string(),
+            ..Default::default()
         };
+        if request.into_inner().verbose {
+            response.isolates = memory::heaps()
+                .into_iter()
+                .map(|(executor, usage)| proto::IsolateMemory {
+                    executor: executor as u64,
+                    heap_used: usage.used,
+                    heap_total: usage.total,
+                    heap_limit: usage.limit,
+                    external: usage.external,
+                })
+                .collect();
+            response.versions = memory::modules()
+                .into_iter()
+                .map(|(version, module_bytes)| proto::VersionMemory {
+                    version,
+                    module_bytes,
+                })
+                .collect();
+        }
         Ok(Response::new(response))
     }
 
@@ -391,9 +1285,15 @@ impl ChiselRpc for RpcService {
         &self,
         request: Request<ChiselApplyRequest>,
     ) -> Result<Response<ChiselApplyResponse>, Status> {
-        self.apply_aux(request)
-            .await
-            .map_err(|e| Status::internal(format!("{:?}", e)))
+        let api_version = request.get_ref().version.clone();
+        self.apply_aux(request).await.map_err(|e| {
+            notify(
+                EventKind::ApplyFailed,
+                &api_version,
+                format!("apply of version {} failed: {:#}", api_version, e),
+            );
+            Status::internal(format!("{:?}", e))
+        })
     }
 
     /// Delete a version of ChiselStrike
@@ -415,13 +1315,166 @@ impl ChiselRpc for RpcService {
             .map_err(|e| Status::internal(format!("{:?}", e)))
     }
 
+    /// Export all data of a user
+    async fn privacy_export(
+        &self,
+        request: Request<PrivacyExportRequest>,
+    ) -> Result<Response<PrivacyExportResponse>, Status> {
+        self.privacy_export_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    /// Erase or anonymize all data of a user
+    async fn privacy_erase(
+        &self,
+        request: Request<PrivacyEraseRequest>,
+    ) -> Result<Response<PrivacyEraseResponse>, Status> {
+        self.privacy_erase_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    /// Get row and column statistics of entities
+    async fn stats(
+        &self,
+        request: Request<StatsRequest>,
+    ) -> Result<Response<StatsResponse>, Status> {
+        self.stats_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn verify(
+        &self,
+        request: Request<VerifyRequest>,
+    ) -> Result<Response<VerifyResponse>, Status> {
+        self.verify_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn ps(&self, request: Request<PsRequest>) -> Result<Response<PsResponse>, Status> {
+        self.ps_aux(request)
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn kill(&self, request: Request<KillRequest>) -> Result<Response<KillResponse>, Status> {
+        self.kill_aux(request)
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn list_profiles(
+        &self,
+        request: Request<ListProfilesRequest>,
+    ) -> Result<Response<ListProfilesResponse>, Status> {
+        self.list_profiles_aux(request)
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn explain(
+        &self,
+        request: Request<ExplainRequest>,
+    ) -> Result<Response<ExplainResponse>, Status> {
+        self.explain_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn start_read_only(
+        &self,
+        request: Request<StartReadOnlyRequest>,
+    ) -> Result<Response<StartReadOnlyResponse>, Status> {
+        self.start_read_only_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn stop_read_only(
+        &self,
+        request: Request<StopReadOnlyRequest>,
+    ) -> Result<Response<StopReadOnlyResponse>, Status> {
+        self.stop_read_only_aux(request)
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn list_read_only(
+        &self,
+        request: Request<ListReadOnlyRequest>,
+    ) -> Result<Response<ListReadOnlyResponse>, Status> {
+        self.list_read_only_aux(request)
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn create_api_key(
+        &self,
+        request: Request<CreateApiKeyRequest>,
+    ) -> Result<Response<CreateApiKeyResponse>, Status> {
+        self.create_api_key_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn revoke_api_key(
+        &self,
+        request: Request<RevokeApiKeyRequest>,
+    ) -> Result<Response<RevokeApiKeyResponse>, Status> {
+        self.revoke_api_key_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn list_api_keys(
+        &self,
+        request: Request<ListApiKeysRequest>,
+    ) -> Result<Response<ListApiKeysResponse>, Status> {
+        self.list_api_keys_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn run_unit_tests(
+        &self,
+        request: Request<UnitTestRequest>,
+    ) -> Result<Response<UnitTestResponse>, Status> {
+        self.run_unit_tests_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn inject_fault(
+        &self,
+        request: Request<InjectFaultRequest>,
+    ) -> Result<Response<InjectFaultResponse>, Status> {
+        self.inject_fault_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn clear_faults(
+        &self,
+        request: Request<ClearFaultsRequest>,
+    ) -> Result<Response<ClearFaultsResponse>, Status> {
+        self.clear_faults_aux(request)
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn list_faults(
+        &self,
+        request: Request<ListFaultsRequest>,
+    ) -> Result<Response<ListFaultsResponse>, Status> {
+        self.list_faults_aux(request)
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
     async fn describe(
         &self,
-        _request: tonic::Request<DescribeRequest>,
+        request: tonic::Request<DescribeRequest>,
     ) -> Result<tonic::Response<DescribeResponse>, tonic::Status> {
         let state = self.state.lock().await;
 
         let mut version_defs = vec![];
+        let mut openapi_endpoints = vec![];
         for api_version in state.versions.iter() {
             let mut type_defs = vec![];
             if let Some(version_types) = state.type_system.versions.get(api_version) {
@@ -441,49 +1494,163 @@ impl ChiselRpc for RpcService {
                             default_value: field.user_provided_default().clone(),
                             is_optional: field.is_optional,
                             is_unique: field.is_unique,
+                            description: field.description.clone(),
+                            column: field.column.clone(),
                         });
                     }
+                    let indexes = ty
+                        .indexes()
+                        .iter()
+                        .map(|index| proto::IndexDefinition {
+                            fields: index.fields.clone(),
+                            search: index.search,
+                            counted: index.counted,
+                        })
+                        .collect();
                     let type_def = proto::TypeDefinition {
                         name: ty.name().to_string(),
                         field_defs,
+                        indexes,
+                        partition: ty.partition().map(Into::into),
+                        archive: ty.archive().map(Into::into),
+                        connector: ty.connector().map(Into::into),
+                        description: ty.description().map(str::to_owned),
                     };
                     type_defs.push(type_def);
                 }
             }
             let mut endpoint_defs = vec![];
             let version_path_str = format!("/{}/", api_version);
-            for (path, _) in state.sources.iter() {
+            for (path, code) in state.sources.iter() {
                 let dir_name = path.split('/').nth(2);
-                if dir_name != Some("routes") || dir_name != Some("endpoints") {
+                if dir_name != Some("routes") && dir_name != Some("endpoints") {
                     continue;
                 }
                 let path = endpoint_path_from_source_path(path);
                 if path.starts_with(&version_path_str) {
+                    let crud = state
+                        .type_system
+                        .versions
+                        .get(api_version)
+                        .and_then(|version_types| introspect::crud_entity(code, version_types));
+                    let pagination = match crud {
+                        Some(_) => introspect::crud_pagination(code),
+                        None => String::new(),
+                    };
+                    let schema = introspect::endpoint_schema(api_version, &path);
                     endpoint_defs.push(proto::EndpointDefinition {
                         path: path.to_string(),
+                        crud_entity: crud.as_ref().map(|ty| ty.name().to_owned()),
+                        request: schema.as_ref().and_then(|s| s.request.clone()),
+                        response: schema.as_ref().and_then(|s| s.response.clone()),
+                        crud_pagination: pagination.clone(),
+                    });
+                    openapi_endpoints.push(OpenApiEndpoint {
+                        path,
+                        crud,
+                        pagination,
+                        schema,
                     });
                 }
             }
             let mut label_policy_defs = vec![];
+            let mut webhook_defs = vec![];
             if let Some(policies) = state.policies.versions.get(api_version) {
                 for label in policies.labels.keys() {
                     label_policy_defs.push(proto::LabelPolicyDefinition {
                         label: label.clone(),
                     });
                 }
+                for webhook in &policies.webhooks {
+                    webhook_defs.push(proto::WebhookDefinition {
+                        url: webhook.url.clone(),
+                        entities: webhook.entities.clone(),
+                        events: webhook
+                            .events
+                            .iter()
+                            .map(|e| e.as_str().to_owned())
+                            .collect(),
+                        signed: webhook.secret_name.is_some(),
+                    });
+                }
             }
+            let job_defs = jobs::list(api_version)
+                .into_iter()
+                .map(|job| proto::JobDefinition {
+                    path: job.path,
+                    schedule: job.schedule,
+                    last_run: job
+                        .last_run
+                        .as_ref()
+                        .map(|run| datetime::format(run.started)),
+                    last_duration_ms: job
+                        .last_run
+                        .as_ref()
+                        .map(|run| run.duration.as_millis() as u64),
+                    last_error: job.last_run.and_then(|run| run.error),
+                })
+                .collect();
             version_defs.push(proto::VersionDefinition {
                 version: api_version.to_string(),
                 type_defs,
                 endpoint_defs,
                 label_policy_defs,
+                job_defs,
+                webhook_defs,
             });
         }
 
-        let response = proto::DescribeResponse { version_defs };
+        let openapi = if request.into_inner().openapi {
+            let versions = state.versions.iter().cloned().collect::<Vec<_>>();
+            let document =
+                introspect::openapi_document(&state.type_system, &versions, &openapi_endpoints);
+            serde_json::to_string_pretty(&document).unwrap()
+        } else {
+            String::new()
+        };
+        let response = proto::DescribeResponse {
+            version_defs,
+            openapi,
+        };
         Ok(Response::new(response))
     }
 
+    async fn list_migrations(
+        &self,
+        request: Request<ListMigrationsRequest>,
+    ) -> Result<Response<ListMigrationsResponse>, Status> {
+        self.list_migrations_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn approve_migration(
+        &self,
+        request: Request<ApproveMigrationRequest>,
+    ) -> Result<Response<ApproveMigrationResponse>, Status> {
+        self.approve_migration_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn reject_migration(
+        &self,
+        request: Request<RejectMigrationRequest>,
+    ) -> Result<Response<RejectMigrationResponse>, Status> {
+        self.reject_migration_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
+    async fn policy_impact(
+        &self,
+        request: Request<PolicyImpactRequest>,
+    ) -> Result<Response<PolicyImpactResponse>, Status> {
+        self.policy_impact_aux(request)
+            .await
+            .map_err(|e| Status::internal(format!("{:?}", e)))
+    }
+
     async fn restart(
         &self,
         _request: tonic::Request<RestartRequest>,
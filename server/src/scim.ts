@@ -0,0 +1,251 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+// SCIM 2.0 provisioning of AuthUser, served at
+// /__chiselstrike/auth/scim/v2/Users. Every change is recorded as an
+// AuthAuditEvent.
+
+import { ChiselEntity, ChiselRequest } from "@chiselstrike/api";
+
+class AuthUser extends ChiselEntity {
+    name?: string;
+    email?: string;
+}
+
+class AuthSession extends ChiselEntity {
+    userId = "";
+}
+
+class AuthScimUser extends ChiselEntity {
+    userId = "";
+    externalId?: string;
+    active = true;
+}
+
+class AuthAuditEvent extends ChiselEntity {
+    time = "";
+    actor = "";
+    action = "";
+    userId = "";
+    details = "";
+}
+
+const USER_SCHEMA = "urn:ietf:params:scim:schemas:core:2.0:User";
+const LIST_SCHEMA = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+const ERROR_SCHEMA = "urn:ietf:params:scim:api:messages:2.0:Error";
+const PATCH_SCHEMA = "urn:ietf:params:scim:api:messages:2.0:PatchOp";
+
+type ScimUser = {
+    userName?: string;
+    displayName?: string;
+    externalId?: string;
+    active?: boolean;
+    name?: { formatted?: string };
+    emails?: { value: string; primary?: boolean }[];
+};
+
+function scimResponse(body: unknown, status = 200): Response {
+    return new Response(JSON.stringify(body), {
+        status,
+        headers: { "content-type": "application/scim+json" },
+    });
+}
+
+function scimError(status: number, detail: string): Response {
+    return scimResponse(
+        { schemas: [ERROR_SCHEMA], status: String(status), detail },
+        status,
+    );
+}
+
+async function audit(action: string, userId: string, details: unknown) {
+    await AuthAuditEvent.create({
+        time: new Date().toISOString(),
+        actor: "scim",
+        action,
+        userId,
+        details: JSON.stringify(details),
+    });
+}
+
+function toScim(user: AuthUser, scim: AuthScimUser | undefined) {
+    return {
+        schemas: [USER_SCHEMA],
+        id: user.id,
+        externalId: scim?.externalId,
+        userName: user.email,
+        displayName: user.name,
+        name: user.name === undefined ? undefined : { formatted: user.name },
+        emails: user.email === undefined
+            ? []
+            : [{ value: user.email, primary: true }],
+        active: scim?.active ?? true,
+        meta: { resourceType: "User" },
+    };
+}
+
+async function scimUser(userId: string): Promise<AuthScimUser | undefined> {
+    return await AuthScimUser.findOne({ userId });
+}
+
+// Applies the attributes of `body` to `user` and its SCIM state.
+async function apply(user: AuthUser, body: ScimUser): Promise<void> {
+    const email = body.userName ??
+        body.emails?.find((e) => e.primary)?.value ??
+        body.emails?.[0]?.value;
+    if (email !== undefined) {
+        user.email = email;
+    }
+    const name = body.displayName ?? body.name?.formatted;
+    if (name !== undefined) {
+        user.name = name;
+    }
+    await user.save();
+
+    const scim = await scimUser(user.id!) ??
+        AuthScimUser.build({ userId: user.id });
+    if (body.externalId !== undefined) {
+        scim.externalId = body.externalId;
+    }
+    if (body.active !== undefined) {
+        scim.active = body.active;
+    }
+    await scim.save();
+    if (!scim.active) {
+        // Deactivated users are logged out.
+        await AuthSession.delete({ userId: user.id });
+    }
+}
+
+async function list(req: ChiselRequest): Promise<Response> {
+    let users = await AuthUser.findAll();
+    const filter = req.query.get("filter");
+    if (filter !== undefined) {
+        const match = filter.match(/^\s*userName\s+eq\s+"([^"]*)"\s*$/i);
+        if (match === null) {
+            return scimError(400, "only `userName eq` filters are supported");
+        }
+        users = users.filter((user) => user.email === match[1]);
+    }
+    const startIndex = Math.max(req.query.getNumber("startIndex") ?? 1, 1);
+    const count = req.query.getNumber("count") ?? users.length;
+    const page = users.slice(startIndex - 1, startIndex - 1 + count);
+    const resources = [];
+    for (const user of page) {
+        resources.push(toScim(user, await scimUser(user.id!)));
+    }
+    return scimResponse({
+        schemas: [LIST_SCHEMA],
+        totalResults: users.length,
+        startIndex,
+        itemsPerPage: resources.length,
+        Resources: resources,
+    });
+}
+
+async function create(req: ChiselRequest): Promise<Response> {
+    const body = await req.json() as ScimUser;
+    if (body.userName === undefined) {
+        return scimError(400, "userName is required");
+    }
+    if (await AuthUser.findOne({ email: body.userName }) !== undefined) {
+        return scimError(409, `user ${body.userName} already exists`);
+    }
+    const user = AuthUser.build({});
+    await apply(user, body);
+    await audit("create", user.id!, body);
+    return scimResponse(toScim(user, await scimUser(user.id!)), 201);
+}
+
+// Turns a PATCH request into the attributes it sets.
+function patchAttributes(body: Record<string, unknown>): ScimUser | string {
+    const schemas = body.schemas as string[] | undefined;
+    if (!schemas?.includes(PATCH_SCHEMA)) {
+        return "not a PatchOp request";
+    }
+    const attributes: Record<string, unknown> = {};
+    const operations = (body.Operations ?? []) as {
+        op: string;
+        path?: string;
+        value?: unknown;
+    }[];
+    for (const operation of operations) {
+        const op = operation.op.toLowerCase();
+        if (op !== "replace" && op !== "add") {
+            return `unsupported PATCH operation ${operation.op}`;
+        }
+        if (operation.path !== undefined) {
+            attributes[operation.path] = operation.value;
+        } else {
+            Object.assign(attributes, operation.value);
+        }
+    }
+    // Some providers send booleans as strings.
+    if (typeof attributes.active === "string") {
+        attributes.active = attributes.active.toLowerCase() === "true";
+    }
+    return attributes as ScimUser;
+}
+
+async function update(req: ChiselRequest, user: AuthUser): Promise<Response> {
+    let body: ScimUser;
+    if (req.method === "PATCH") {
+        const attributes = patchAttributes(await req.json());
+        if (typeof attributes === "string") {
+            return scimError(400, attributes);
+        }
+        body = attributes;
+    } else {
+        body = await req.json() as ScimUser;
+    }
+    const wasActive = (await scimUser(user.id!))?.active ?? true;
+    await apply(user, body);
+    const scim = await scimUser(user.id!);
+    let action = "update";
+    if (wasActive && !scim?.active) {
+        action = "deactivate";
+    } else if (!wasActive && scim?.active) {
+        action = "reactivate";
+    }
+    await audit(action, user.id!, body);
+    return scimResponse(toScim(user, scim));
+}
+
+async function remove(user: AuthUser): Promise<Response> {
+    await AuthSession.delete({ userId: user.id });
+    await AuthScimUser.delete({ userId: user.id });
+    await AuthUser.delete({ id: user.id });
+    await audit("delete", user.id!, { userName: user.email });
+    return new Response(null, { status: 204 });
+}
+
+export default async function (req: ChiselRequest): Promise<Response> {
+    const [id, ...rest] = req.pathComponents();
+    if (rest.length > 0) {
+        return scimError(404, "not found");
+    }
+    if (id === undefined) {
+        switch (req.method) {
+            case "GET":
+                return await list(req);
+            case "POST":
+                return await create(req);
+            default:
+                return scimError(405, `${req.method} is not supported`);
+        }
+    }
+    const user = await AuthUser.findOne({ id });
+    if (user === undefined) {
+        return scimError(404, `user ${id} not found`);
+    }
+    switch (req.method) {
+        case "GET":
+            return scimResponse(toScim(user, await scimUser(id)));
+        case "PUT":
+        case "PATCH":
+            return await update(req, user);
+        case "DELETE":
+            return await remove(user);
+        default:
+            return scimError(405, `${req.method} is not supported`);
+    }
+}
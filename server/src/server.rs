@@ -1,24 +1,29 @@
 // SPDX-FileCopyrightText: © 2021 ChiselStrike <info@chiselstrike.com>
 
-use crate::api::ApiService;
+use crate::api::{ApiService, HttpConfig};
 use crate::datastore::{DbConnection, MetaService, QueryEngine};
 use crate::deno;
 use crate::deno::init_deno;
 use crate::deno::set_meta;
 use crate::deno::set_policies;
+use crate::deno::set_query_budget;
 use crate::deno::set_query_engine;
 use crate::deno::set_type_system;
 use crate::deno::update_secrets;
-use crate::deno::{activate_endpoint, activate_event_handler, compile_endpoints};
+use crate::deno::QueryBudget;
+use crate::deno::{
+    activate_endpoint, activate_event_handler, activate_job, activate_task, compile_endpoints,
+};
 use crate::internal::mark_not_ready;
 use crate::kafka;
+use crate::response_cache;
 use crate::rpc::InitState;
 use crate::rpc::{GlobalRpcState, RpcService};
 use crate::runtime;
 use crate::runtime::Runtime;
 use crate::secrets::get_secrets;
 use crate::JsonObject;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_lock::Mutex;
 use deno_core::futures;
 use enclose::enclose;
@@ -59,21 +64,81 @@ pub struct Opt {
     /// Database URI.
     #[structopt(long, default_value = "sqlite://.chiseld.db?mode=rwc")]
     db_uri: String,
+    /// Accept HTTP/2 connections on the API server, in addition to HTTP/1.1.
+    #[structopt(long)]
+    api_http2: bool,
+    /// Close API connections after each HTTP/1.1 request instead of keeping them alive.
+    #[structopt(long)]
+    api_disable_keep_alive: bool,
+    /// Seconds between keep-alive probes on idle API connections (TCP keep-alive and HTTP/2
+    /// pings). An HTTP/2 connection is closed if a ping isn't answered within this time.
+    #[structopt(long)]
+    api_keep_alive_timeout: Option<u64>,
+    /// Maximum number of concurrent HTTP/2 streams per API connection.
+    #[structopt(long)]
+    api_max_concurrent_streams: Option<u32>,
+    /// Maximum length of the queue of pending API connections.
+    #[structopt(long, default_value = "1024")]
+    api_tcp_backlog: i32,
+    /// PEM certificate chain to serve the API over TLS. Requires --api-tls-key.
+    #[structopt(long)]
+    api_tls_cert: Option<PathBuf>,
+    /// PEM private key to serve the API over TLS. Requires --api-tls-cert.
+    #[structopt(long)]
+    api_tls_key: Option<PathBuf>,
     /// Kafka connection.
     #[structopt(long)]
     kafka_connection: Option<String>,
     /// Kafka topics to subscribe to.
     #[structopt(long)]
     kafka_topics: Vec<String>,
+    /// How many times a failing event handler is retried before the event is dropped.
+    #[structopt(long, default_value = "3")]
+    event_handler_retries: u32,
+    /// Versions whose endpoints are loaded on their first request instead of at startup,
+    /// trading first-request latency for memory. `*` makes every version lazy.
+    #[structopt(long)]
+    lazy_versions: Vec<String>,
+    /// Versions whose endpoints are always loaded at startup, even if matched by
+    /// --lazy-versions.
+    #[structopt(long)]
+    eager_versions: Vec<String>,
     /// Activate inspector and let a debugger attach at any time.
     #[structopt(long)]
     inspect: bool,
     /// Activate inspector, but pause the runtime at startup to wait for a debugger to attach.
     #[structopt(long)]
     inspect_brk: bool,
+    /// Activate the inspector on a local port, so that `chisel test --coverage` can collect
+    /// code coverage.
+    #[structopt(long)]
+    coverage: bool,
     /// Activate debug mode, it will show runtime exceptions in HTTP responses.
     #[structopt(long)]
     debug: bool,
+    /// Stage applies that would drop models or fields instead of running them, until
+    /// `chisel migrate approve` approves them.
+    #[structopt(long)]
+    require_migration_approval: bool,
+    /// Warn about requests issuing more than this many SQL statements, which
+    /// usually means an N+1 query pattern.
+    #[structopt(long)]
+    query_budget: Option<usize>,
+    /// Fail requests going over --query-budget instead of only warning. Meant for development.
+    #[structopt(long)]
+    enforce_query_budget: bool,
+    /// Percentage of API requests to profile, for `chisel profile` to show. In debug mode,
+    /// requests with a `ChiselProfile` header are profiled as well.
+    #[structopt(long, default_value = "0")]
+    profile_sample_rate: f64,
+    /// How many transactions to spread the table changes of an apply over on Postgres. The
+    /// tables of different models are then changed concurrently.
+    #[structopt(long, default_value = "4")]
+    ddl_parallelism: usize,
+    /// Largest number of rows of a query that endpoints read at once. Batches start smaller
+    /// and grow up to this size as the rows are read.
+    #[structopt(long, default_value = "256")]
+    query_batch_size: usize,
     /// size of database connection pool.
     #[structopt(short, long, default_value = "10")]
     nr_connections: usize,
@@ -83,6 +148,29 @@ pub struct Opt {
     /// V8 flags.
     #[structopt(long)]
     v8_flags: Vec<String>,
+    /// Log a warning when the heap of an executor is over this percentage of its limit, which
+    /// `--v8-flags=--max-old-space-size` sets.
+    #[structopt(long, default_value = "90")]
+    heap_warn_percent: u64,
+    /// URLs to notify of failed applies, executor heaps near their limit, tenants over their
+    /// quota, requests over --query-budget and dropped events. Slack and Discord incoming
+    /// webhooks get a message, other URLs a JSON object with the event.
+    #[structopt(long)]
+    notify_url: Vec<String>,
+    /// Where the content of files is stored: a local directory, or an S3-compatible bucket like
+    /// `s3://bucket/prefix?region=eu-west-1`, with the credentials in AWS_ACCESS_KEY_ID and
+    /// AWS_SECRET_ACCESS_KEY.
+    #[structopt(long, default_value = ".chiseld-blobs")]
+    blob_store: String,
+    /// Directory to write a crash report to when chiseld panics or exits with an error.
+    #[structopt(long, default_value = ".chiseld-crashes")]
+    pub crash_dir: PathBuf,
+    /// URL to also POST crash reports to, as JSON.
+    #[structopt(long)]
+    pub crash_report_url: Option<String>,
+    /// Default log filter, in env_logger syntax. RUST_LOG takes precedence.
+    #[structopt(long, default_value = "info")]
+    pub log_filter: String,
     /// Read default configuration from this toml configuration file
     #[structopt(long, short)]
     #[serde(skip)]
@@ -101,15 +189,93 @@ pub struct Opt {
     pub show_config: bool,
 }
 
+/// Prefix of the environment variables overriding configuration options:
+/// `CHISELD_API_LISTEN_ADDR` overrides `api_listen_addr`, and so on.
+const ENV_PREFIX: &str = "CHISELD_";
+
 impl Opt {
     pub async fn from_file(path: &Path) -> Result<Self> {
         let content = tokio::fs::read(path).await?;
         let content = std::str::from_utf8(&content)?;
 
-        Self::from_args_with_toml(content).map_err(|e| anyhow::anyhow!(e.to_string()))
+        Self::from_toml(content)
+    }
+
+    /// Builds the configuration from, in order of precedence, the command
+    /// line, `CHISELD_*` environment variables and the TOML `content`.
+    pub fn from_toml(content: &str) -> Result<Self> {
+        let mut table: toml::value::Table = toml::from_str(content)?;
+        let defaults = serde_json::to_value(Self::from_iter(["chiseld"]))?;
+        for (key, default) in defaults.as_object().unwrap() {
+            // deprecated options
+            if key.starts_with('_') {
+                continue;
+            }
+            let var = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+            if let Ok(value) = std::env::var(&var) {
+                let value = env_to_toml(&value, default)
+                    .with_context(|| format!("invalid value of {}", var))?;
+                table.insert(key.clone(), value);
+            }
+        }
+        let content = toml::to_string(&table)?;
+
+        Self::from_args_with_toml(&content).map_err(|e| anyhow::anyhow!(e.to_string()))
+    }
+
+    fn http_config(&self) -> Result<HttpConfig> {
+        let mut config = HttpConfig {
+            http2: self.api_http2,
+            keep_alive: !self.api_disable_keep_alive,
+            keep_alive_timeout: self.api_keep_alive_timeout.map(Duration::from_secs),
+            max_concurrent_streams: self.api_max_concurrent_streams,
+            tcp_backlog: self.api_tcp_backlog,
+            tls: None,
+        };
+        match (&self.api_tls_cert, &self.api_tls_key) {
+            (Some(cert), Some(key)) => config.load_tls(cert, key)?,
+            (None, None) => {}
+            _ => anyhow::bail!("--api-tls-cert and --api-tls-key must be given together"),
+        }
+        Ok(config)
+    }
+
+    /// Whether the endpoints of `api_version` are loaded on first request.
+    fn is_lazy_version(&self, api_version: &str) -> bool {
+        !self.eager_versions.iter().any(|v| v == api_version)
+            && self
+                .lazy_versions
+                .iter()
+                .any(|v| v == api_version || v == "*")
     }
 }
 
+/// Converts the value of an environment variable to the TOML type of the
+/// option it overrides, going by the option's default value.
+fn env_to_toml(value: &str, default: &serde_json::Value) -> Result<toml::Value> {
+    use serde_json::Value as Json;
+    use toml::Value as Toml;
+
+    let value = match default {
+        Json::Bool(_) => Toml::Boolean(value.parse()?),
+        Json::Number(_) => Toml::Integer(value.parse()?),
+        Json::String(_) => Toml::String(value.to_owned()),
+        Json::Array(_) => Toml::Array(
+            value
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| Toml::String(s.to_owned()))
+                .collect(),
+        ),
+        // Optional options have no default to go by.
+        _ => value
+            .parse()
+            .map(Toml::Integer)
+            .unwrap_or_else(|_| Toml::String(value.to_owned())),
+    };
+    Ok(value)
+}
+
 /// Whether an action should be repeated.
 pub enum DoRepeat {
     Yes,
@@ -181,6 +347,12 @@ pub async fn add_endpoints(
                 }
             });
             api_service.add_event_handler(path, func);
+        } else if path.contains("/jobs/") {
+            let path = deno::endpoint_path_from_source_path(path);
+            activate_job(&path).await?;
+        } else if path.contains("/tasks/") {
+            let path = deno::endpoint_path_from_source_path(path);
+            activate_task(&path).await?;
         } else {
             println!("warning: unrecognized source: {}", path);
         }
@@ -188,6 +360,74 @@ pub async fn add_endpoints(
     Ok(())
 }
 
+/// Registers the endpoints and event handlers of a lazily loaded version
+/// without compiling them. The first call to any of them compiles and
+/// activates the whole version on this thread, which replaces these
+/// placeholders with the regular handlers.
+fn add_lazy_endpoints(
+    api_version: String,
+    sources: HashMap<String, String>,
+    api_service: &ApiService,
+) {
+    let loaded = Arc::new(Mutex::new(false));
+    let load = Arc::new({
+        let sources = Arc::new(sources.clone());
+        move || {
+            let loaded = loaded.clone();
+            let sources = sources.clone();
+            let api_version = api_version.clone();
+            async move {
+                let mut loaded = loaded.lock().await;
+                if !*loaded {
+                    info!("Loading version {} on first request", api_version);
+                    let api = runtime::get().api.clone();
+                    add_endpoints((*sources).clone(), &api)
+                        .await
+                        .with_context(|| format!("failed to load version {}", api_version))?;
+                    *loaded = true;
+                }
+                Ok::<_, anyhow::Error>(())
+            }
+        }
+    });
+
+    for path in sources.keys() {
+        if path.contains("/routes/") || path.contains("/endpoints/") {
+            let path = deno::endpoint_path_from_source_path(path);
+            let func = Arc::new({
+                let path = path.clone();
+                let load = load.clone();
+                move |req| {
+                    let path = path.clone();
+                    let load = load.clone();
+                    async move {
+                        load().await?;
+                        deno::run_js(path, req).await
+                    }
+                    .boxed_local()
+                }
+            });
+            api_service.add_route(path, func);
+        } else if path.contains("/events/") {
+            let path = deno::endpoint_path_from_source_path(path);
+            let func = Arc::new({
+                let path = path.clone();
+                let load = load.clone();
+                move |key: Option<Vec<u8>>, value: Option<Vec<u8>>| {
+                    let path = path.clone();
+                    let load = load.clone();
+                    async move {
+                        load().await?;
+                        deno::run_js_event(path, key, value).await
+                    }
+                    .boxed_local()
+                }
+            });
+            api_service.add_event_handler(path, func);
+        }
+    }
+}
+
 async fn read_secrets(opt: &Opt) -> Result<JsonObject> {
     static LAST_TRY_WAS_FAILURE: Mutex<bool> = Mutex::new(false);
     let secrets = get_secrets(opt).await;
@@ -212,7 +452,12 @@ async fn read_secrets(opt: &Opt) -> Result<JsonObject> {
     }
 }
 
-async fn run(state: SharedState, init: InitState, mut cmd: ExecutorChannel) -> Result<()> {
+async fn run(
+    state: SharedState,
+    init: InitState,
+    mut cmd: ExecutorChannel,
+    id: usize,
+) -> Result<()> {
     let InitState {
         sources,
         policies,
@@ -222,6 +467,7 @@ async fn run(state: SharedState, init: InitState, mut cmd: ExecutorChannel) -> R
         state.opt.v8_flags.clone(),
         state.opt.inspect,
         state.opt.inspect_brk,
+        state.opt.coverage,
     )
     .await?;
 
@@ -253,6 +499,7 @@ async fn run(state: SharedState, init: InitState, mut cmd: ExecutorChannel) -> R
     for v in versions {
         crate::introspect::add_introspection(&api_service, v);
     }
+    crate::assets::add_routes(&api_service);
 
     let rt = Runtime::new(api_service.clone());
     runtime::set(rt);
@@ -260,16 +507,69 @@ async fn run(state: SharedState, init: InitState, mut cmd: ExecutorChannel) -> R
     set_query_engine(query_engine).await;
     set_policies(policies).await;
     set_meta(meta).await;
-
-    // add_endpoints expects a HashMap, not a PrefixMap
-    let hashmap = sources
-        .iter()
-        .map(|(k, v)| {
-            let path = k.to_string();
-            (path, v.clone())
+    if let Some(limit) = state.opt.query_budget {
+        set_query_budget(QueryBudget {
+            limit,
+            enforce: state.opt.enforce_query_budget,
         })
-        .collect();
-    add_endpoints(hashmap, &api_service).await?;
+        .await;
+    }
+
+    // add_endpoints expects a HashMap, not a PrefixMap. Sources of lazy
+    // versions are set aside, grouped by version.
+    let mut eager_sources = HashMap::new();
+    let mut lazy_sources: HashMap<String, HashMap<String, String>> = HashMap::new();
+    for (k, v) in sources.iter() {
+        let path = k.to_string();
+        // External modules have no version and are always loaded.
+        let api_version = path.split('/').nth(1).filter(|_| path.starts_with('/'));
+        match api_version {
+            Some(api_version) if state.opt.is_lazy_version(api_version) => {
+                lazy_sources
+                    .entry(api_version.to_owned())
+                    .or_default()
+                    .insert(path, v.clone());
+            }
+            _ => {
+                eager_sources.insert(path, v.clone());
+            }
+        }
+    }
+    // Versions with jobs or tasks are loaded anyway, so that their jobs are
+    // scheduled and their queued tasks run.
+    lazy_sources.retain(|_, sources| {
+        let runs_in_background = sources
+            .keys()
+            .any(|path| path.contains("/jobs/") || path.contains("/tasks/"));
+        if runs_in_background {
+            eager_sources.extend(sources.drain());
+        }
+        !runs_in_background
+    });
+    add_endpoints(eager_sources, &api_service).await?;
+    for (api_version, sources) in lazy_sources {
+        add_lazy_endpoints(api_version, sources, &api_service);
+    }
+
+    let memory_task = tokio::task::spawn_local(async move {
+        let mut interval = tokio::time::interval(crate::memory::SAMPLE_INTERVAL);
+        loop {
+            interval.tick().await;
+            crate::memory::record_heap(id, deno::heap_usage());
+        }
+    });
+
+    // Jobs run once per minute, whatever the number of executors.
+    let jobs_task = if id == 0 {
+        Some(crate::jobs::spawn(
+            state.db.clone(),
+            state.signal_rx.clone(),
+        ))
+    } else {
+        None
+    };
+
+    let tasks_task = crate::tasks::spawn(state.db.clone(), state.signal_rx.clone());
 
     let command_task = tokio::task::spawn_local(async move {
         while let Some(item) = cmd.rx.next().await {
@@ -281,8 +581,12 @@ async fn run(state: SharedState, init: InitState, mut cmd: ExecutorChannel) -> R
     let kafka_tasks = if let Some(kafka_connection) = state.opt.kafka_connection {
         kafka::spawn(
             api_service.clone(),
+            state.db.clone(),
+            id,
+            state.opt.executor_threads,
             kafka_connection,
             state.opt.kafka_topics,
+            state.opt.event_handler_retries,
             state.signal_rx.clone(),
         )
         .await?
@@ -290,16 +594,23 @@ async fn run(state: SharedState, init: InitState, mut cmd: ExecutorChannel) -> R
         vec![]
     };
 
+    let http_config = state.opt.http_config()?;
     let api_tasks = crate::api::spawn(
         api_service,
         state.opt.api_listen_addr.clone(),
+        &http_config,
         state.signal_rx.clone(),
     )?;
     state.readiness_tx.send(()).await?;
 
+    let scheme = if http_config.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
     info!(
-        "ChiselStrike server is ready 🚀 - URL: http://{} ",
-        state.opt.api_listen_addr
+        "ChiselStrike server is ready 🚀 - URL: {}://{} ",
+        scheme, state.opt.api_listen_addr
     );
 
     for kafka_task in kafka_tasks {
@@ -308,7 +619,12 @@ async fn run(state: SharedState, init: InitState, mut cmd: ExecutorChannel) -> R
     for api_task in api_tasks {
         api_task.await??;
     }
+    if let Some(jobs_task) = jobs_task {
+        jobs_task.await??;
+    }
+    tasks_task.await??;
     command_task.await?;
+    memory_task.abort();
     kafka::shutdown();
     deno::shutdown();
     Ok(())
@@ -383,6 +699,18 @@ async fn run_shared_state(
     let sources = meta.load_sources().await?;
     let policies = meta.load_policies().await?;
     let type_system = meta.load_type_system().await?;
+    for (api_version, hints) in meta.load_cache_hints().await? {
+        response_cache::set_hints(&api_version, hints);
+    }
+    for (api_version, version_policy) in &policies.versions {
+        response_cache::set_tenant(api_version, version_policy.tenant.clone());
+    }
+    for (api_version, schemas) in meta.load_endpoint_schemas().await? {
+        crate::introspect::set_schemas(&api_version, schemas);
+    }
+    for (api_version, files) in meta.load_static_files().await? {
+        crate::assets::set_files(&api_version, files);
+    }
     let init = InitState {
         sources,
         policies,
@@ -393,6 +721,18 @@ async fn run_shared_state(
     ));
 
     let rpc = RpcService::new(state);
+    if opt.debug {
+        crate::faults::enable();
+    }
+    crate::profiling::configure(opt.debug, opt.profile_sample_rate)?;
+    crate::memory::configure(opt.heap_warn_percent)?;
+    crate::notifications::configure(&opt.notify_url)?;
+    crate::apply::configure(opt.ddl_parallelism)?;
+    crate::datastore::prefetch::configure(opt.query_batch_size)?;
+    crate::blobs::configure(&opt.blob_store)?;
+    if opt.require_migration_approval {
+        crate::migration_gate::enable();
+    }
 
     let (signal_tx, signal_rx) = utils::make_signal_channel();
 
@@ -444,6 +784,25 @@ async fn run_shared_state(
         }
     });
 
+    // Deliver messages enqueued in the outbox by committed transactions.
+    let _outbox_delivery = crate::outbox::spawn(
+        db_conn.clone(),
+        opt.kafka_connection.clone(),
+        signal_rx.clone(),
+    );
+
+    // Deliver the payloads of webhooks on committed writes.
+    let _webhook_delivery = crate::webhooks::spawn(db_conn.clone(), signal_rx.clone());
+
+    // Create and drop the partitions of tables partitioned by time.
+    let _partition_maintenance = crate::partitions::spawn(db_conn.clone(), signal_rx.clone());
+
+    // Move the old rows of archived entities to their archive tables.
+    let _archiving = crate::archive::spawn(db_conn.clone(), signal_rx.clone());
+
+    // Send the notifications of operational events.
+    let _notifications = crate::notifications::spawn(signal_rx.clone());
+
     // rpc server should start listening only when all threads start
     let (readiness_tx, readiness_rx) = async_channel::bounded(opt.executor_threads);
 
@@ -482,9 +841,10 @@ async fn run_on_new_localset(
     state: SharedState,
     init: InitState,
     command: ExecutorChannel,
+    id: usize,
 ) -> Result<()> {
     let local = tokio::task::LocalSet::new();
-    local.run_until(run(state, init, command)).await
+    local.run_until(run(state, init, command, id)).await
 }
 
 pub async fn run_all(opt: Opt) -> Result<DoRepeat> {
@@ -500,7 +860,7 @@ pub async fn run_all(opt: Opt) -> Result<DoRepeat> {
                 .build()
                 .unwrap()
                 .block_on(async {
-                    run_on_new_localset(shared, init, cmd).await
+                    run_on_new_localset(shared, init, cmd, id).await
                 }).unwrap();
         }}));
     }
@@ -0,0 +1,238 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Background tasks.
+//!
+//! Modules in the `tasks/` directory of a project export a default function
+//! that takes a payload, and are named after their path, like `send-email`.
+//! Endpoints enqueue tasks with `Chisel.enqueue()` in the same transaction
+//! as their writes, so that a task runs if and only if the request
+//! committed, and the request doesn't wait for it.
+//!
+//! Each executor runs a worker that claims due tasks from the `tasks` table
+//! and runs them, each in a transaction of its own. A failed task is retried
+//! with exponential backoff, and left in the table for inspection once it
+//! ran out of attempts. A claim expires, so that the tasks of a chiseld that
+//! died are run again: tasks run at least once. Attempts are counted when a
+//! task is claimed, so that a task that keeps crashing or timing out its
+//! worker runs out of them too.
+
+use crate::datastore::DbConnection;
+use crate::deno;
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use sqlx::any::Any;
+use sqlx::{Executor, Row, Transaction};
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+/// How often a worker looks for due tasks when it found none.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Maximum number of due tasks fetched at once.
+const BATCH: i64 = 10;
+/// Tasks that failed this many times are no longer retried.
+const MAX_ATTEMPTS: i32 = 10;
+/// Delay before the first retry, doubled for each further one.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+/// How long a worker may run a task before others can claim it again.
+const CLAIM_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// The paths of the active tasks, like `/dev/send-email`.
+static TASKS: Lazy<Mutex<BTreeSet<String>>> = Lazy::new(Default::default);
+
+pub(crate) fn register(path: &str) {
+    TASKS.lock().unwrap().insert(path.to_owned());
+}
+
+/// Forgets the tasks under `prefix` that are not in `paths`.
+pub(crate) fn retain(prefix: &str, paths: &[String]) {
+    TASKS
+        .lock()
+        .unwrap()
+        .retain(|path| !path.starts_with(prefix) || paths.contains(path));
+}
+
+pub(crate) fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+/// How long to wait before running a task that failed `attempts` times.
+pub(crate) fn backoff(attempts: i32) -> Duration {
+    let exponent = (attempts.max(1) - 1).min(20) as u32;
+    (FIRST_BACKOFF * 2u32.pow(exponent)).min(MAX_BACKOFF)
+}
+
+/// Enqueues task `name` of `api_version` as part of `transaction`.
+pub(crate) async fn enqueue(
+    transaction: &mut Transaction<'_, Any>,
+    api_version: &str,
+    name: &str,
+    payload: &str,
+) -> Result<()> {
+    let path = format!("/{}/{}", api_version, name.trim_start_matches('/'));
+    anyhow::ensure!(
+        TASKS.lock().unwrap().contains(&path),
+        "there is no task {} in version {}",
+        name,
+        api_version
+    );
+    let query = sqlx::query(
+        r#"
+        INSERT INTO tasks (api_version, path, payload, attempts, run_at)
+        VALUES ($1, $2, $3, 0, $4)"#,
+    )
+    .bind(api_version.to_owned())
+    .bind(path)
+    .bind(payload.to_owned())
+    .bind(now_millis());
+    transaction
+        .execute(query)
+        .await
+        .context("failed to enqueue task")?;
+    Ok(())
+}
+
+struct DueTask {
+    id: i32,
+    path: String,
+    payload: String,
+    attempts: i32,
+}
+
+async fn due_tasks(db: &DbConnection, now: i64) -> Result<Vec<DueTask>> {
+    let query = sqlx::query(
+        r#"
+        SELECT task_id, path, payload, attempts
+        FROM tasks
+        WHERE attempts < $1 AND run_at <= $2
+        ORDER BY run_at
+        LIMIT $3"#,
+    )
+    .bind(MAX_ATTEMPTS)
+    .bind(now)
+    .bind(BATCH);
+    let rows = query.fetch_all(&db.pool).await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| DueTask {
+            id: row.get("task_id"),
+            path: row.get("path"),
+            payload: row.get("payload"),
+            attempts: row.get("attempts"),
+        })
+        .collect())
+}
+
+/// Claims `task` for this worker by pushing its next run past the time it
+/// may take and counting the attempt, unless another worker claimed it
+/// first.
+async fn claim(db: &DbConnection, task: &mut DueTask, now: i64) -> Result<bool> {
+    let query = sqlx::query(
+        r#"
+        UPDATE tasks
+        SET run_at = $1, attempts = attempts + 1
+        WHERE task_id = $2 AND run_at <= $3 AND attempts < $4"#,
+    )
+    .bind(now + CLAIM_TIMEOUT.as_millis() as i64)
+    .bind(task.id)
+    .bind(now)
+    .bind(MAX_ATTEMPTS);
+    if db.pool.execute(query).await?.rows_affected() != 1 {
+        return Ok(false);
+    }
+    task.attempts += 1;
+    Ok(true)
+}
+
+async fn run(db: &DbConnection, task: DueTask) -> Result<()> {
+    let result = if TASKS.lock().unwrap().contains(&task.path) {
+        deno::run_js_task(task.path.clone(), task.payload).await
+    } else {
+        Err(anyhow::anyhow!("the task no longer exists"))
+    };
+    match result {
+        Ok(()) => {
+            let query = sqlx::query("DELETE FROM tasks WHERE task_id = $1").bind(task.id);
+            db.pool.execute(query).await?;
+        }
+        Err(e) => {
+            let attempts = task.attempts;
+            warn!(
+                "Task {} failed on attempt {}/{}: {:?}",
+                task.path, attempts, MAX_ATTEMPTS, e
+            );
+            let query = sqlx::query(
+                r#"
+                UPDATE tasks
+                SET run_at = $1, last_error = $2
+                WHERE task_id = $3"#,
+            )
+            .bind(now_millis() + backoff(attempts).as_millis() as i64)
+            .bind(format!("{:?}", e))
+            .bind(task.id);
+            db.pool.execute(query).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs the due tasks this worker could claim, and returns how many.
+async fn run_due(db: &DbConnection) -> Result<usize> {
+    let mut ran = 0;
+    for mut task in due_tasks(db, now_millis()).await? {
+        if claim(db, &mut task, now_millis()).await? {
+            run(db, task).await?;
+            ran += 1;
+        }
+    }
+    Ok(ran)
+}
+
+/// Spawns the worker of the current executor, which runs tasks until
+/// `shutdown` fires.
+pub(crate) fn spawn(
+    db: DbConnection,
+    shutdown: async_channel::Receiver<()>,
+) -> JoinHandle<Result<()>> {
+    tokio::task::spawn_local(async move {
+        let mut idle = false;
+        loop {
+            if idle {
+                tokio::select! {
+                    _ = sleep(POLL_INTERVAL) => {},
+                    _ = shutdown.recv() => break,
+                };
+            } else if shutdown.is_closed() {
+                break;
+            }
+            idle = match run_due(&db).await {
+                Ok(ran) => ran == 0,
+                Err(e) => {
+                    warn!("Running tasks failed: {:?}", e);
+                    true
+                }
+            };
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles() {
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(2), Duration::from_secs(2));
+        assert_eq!(backoff(5), Duration::from_secs(16));
+        assert_eq!(backoff(MAX_ATTEMPTS), Duration::from_secs(512));
+        assert_eq!(backoff(100), MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,57 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Archiving of the old rows of entities, declared with `@archive`.
+//!
+//! Rows are moved from the backing table to an archive table with the same
+//! columns once they are old enough. Queries only read the archive table
+//! when they opt in, so the backing table only holds the rows in use.
+
+use super::datetime;
+use serde_derive::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+/// Which rows of an entity are archived. It is stored as JSON in the meta
+/// database.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Archiving {
+    /// The `Date` field that gives the age of the rows.
+    pub field: String,
+    /// How many days old the rows are archived at.
+    #[serde(rename = "afterDays")]
+    pub after_days: u32,
+}
+
+impl Archiving {
+    /// Describes the archiving for the plan of `chisel apply`.
+    pub fn describe(&self) -> String {
+        format!(
+            "archive rows {} days after their {}",
+            self.after_days, self.field
+        )
+    }
+
+    /// The stored value of `field` that the rows archived at `now` are older
+    /// than.
+    pub fn cutoff(&self, now: OffsetDateTime) -> String {
+        datetime::format(now - Duration::days(self.after_days.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cutoff() {
+        let archiving = Archiving {
+            field: "createdAt".to_owned(),
+            after_days: 30,
+        };
+        let now = OffsetDateTime::from_unix_timestamp(1_671_235_200).unwrap();
+        assert_eq!(archiving.cutoff(now), "2022-11-17T00:00:00.000Z");
+        assert_eq!(
+            serde_json::to_string(&archiving).unwrap(),
+            r#"{"field":"createdAt","afterDays":30}"#
+        );
+    }
+}
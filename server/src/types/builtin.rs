@@ -1,7 +1,10 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
 use super::{Entity, Field, InternalObject, ObjectType, Type, TypeId};
-use crate::auth::{AUTH_ACCOUNT_NAME, AUTH_SESSION_NAME, AUTH_TOKEN_NAME, AUTH_USER_NAME};
+use crate::auth::{
+    AUTH_ACCOUNT_NAME, AUTH_AUDIT_EVENT_NAME, AUTH_SCIM_USER_NAME, AUTH_SESSION_NAME,
+    AUTH_TOKEN_NAME, AUTH_USER_NAME,
+};
 use crate::datastore::QueryEngine;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -17,6 +20,9 @@ impl BuiltinTypes {
         types.insert("string".into(), Type::String);
         types.insert("number".into(), Type::Float);
         types.insert("boolean".into(), Type::Boolean);
+        types.insert("Date".into(), Type::DateTime);
+        types.insert("JSONValue".into(), Type::Json);
+        types.insert("ChiselFile".into(), Type::File);
         add_auth_entity(
             &mut types,
             AUTH_USER_NAME,
@@ -68,6 +74,28 @@ impl BuiltinTypes {
             ],
             "auth_account",
         );
+        add_auth_entity(
+            &mut types,
+            AUTH_SCIM_USER_NAME,
+            vec![
+                string_field("userId"),
+                optional_string_field("externalId"),
+                boolean_field("active"),
+            ],
+            "auth_scim_user",
+        );
+        add_auth_entity(
+            &mut types,
+            AUTH_AUDIT_EVENT_NAME,
+            vec![
+                string_field("time"),
+                string_field("actor"),
+                string_field("action"),
+                string_field("userId"),
+                string_field("details"),
+            ],
+            "auth_audit_event",
+        );
 
         Self { types }
     }
@@ -110,6 +138,8 @@ fn string_field(name: &str) -> Field {
         is_optional: false,
         api_version: "__chiselstrike".into(),
         is_unique: false,
+        description: None,
+        column: None,
     }
 }
 
@@ -119,6 +149,12 @@ fn optional_string_field(name: &str) -> Field {
     f
 }
 
+fn boolean_field(name: &str) -> Field {
+    let mut f = string_field(name);
+    f.type_id = TypeId::Boolean;
+    f
+}
+
 fn optional_number_field(name: &str) -> Field {
     Field {
         id: None,
@@ -130,5 +166,7 @@ fn optional_number_field(name: &str) -> Field {
         is_optional: true,
         api_version: "__chiselstrike".into(),
         is_unique: false,
+        description: None,
+        column: None,
     }
 }
@@ -0,0 +1,127 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Entities whose rows are fetched from an external REST API, declared with
+//! `@connector`.
+//!
+//! The fetched rows are cached in the backing table of the entity, so they
+//! are queried, filtered and joined with local entities like any other rows.
+
+use super::ObjectType;
+use crate::JsonObject;
+use anyhow::{Context, Result};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+
+/// Where the rows of an entity come from. It is stored as JSON in the meta
+/// database.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Connector {
+    /// The URL that a GET request fetches the rows from.
+    pub url: String,
+    /// The dot-separated path of the array of rows in the response, or empty
+    /// if the response is the array itself.
+    #[serde(default)]
+    pub items: String,
+    /// The dot-separated path of the value of a field in each row, for the
+    /// fields named differently than in the response.
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    /// For how many seconds the fetched rows are served before they are
+    /// fetched again.
+    pub ttl: u64,
+    /// At most how many times a minute the rows are fetched.
+    #[serde(rename = "rateLimit")]
+    pub rate_limit: u32,
+}
+
+impl Connector {
+    /// Describes the connector for the plan of `chisel apply`.
+    pub fn describe(&self) -> String {
+        format!(
+            "fetch rows from {} at most every {}s and {} times a minute",
+            self.url, self.ttl, self.rate_limit
+        )
+    }
+
+    /// The rows of `ty` in the fetched `response`. Fields missing from a
+    /// row get their default value, and rows without an id are given one.
+    pub fn rows(&self, ty: &ObjectType, response: &JsonValue) -> Result<Vec<JsonObject>> {
+        let items = lookup(response, &self.items)
+            .and_then(JsonValue::as_array)
+            .with_context(|| match self.items.as_str() {
+                "" => format!("response of {} is not an array", self.url),
+                items => format!("response of {} has no array at `{}`", self.url, items),
+            })?;
+        let mut rows = vec![];
+        for item in items {
+            let mut row = JsonObject::new();
+            for field in ty.column_fields() {
+                let path = self.fields.get(&field.name).unwrap_or(&field.name);
+                let value = match lookup(item, path) {
+                    None | Some(JsonValue::Null) => continue,
+                    // Numeric ids are common, but ids are strings.
+                    Some(JsonValue::Number(id)) if field.name == "id" => {
+                        JsonValue::String(id.to_string())
+                    }
+                    Some(value) => value.clone(),
+                };
+                row.insert(field.name.clone(), value);
+            }
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+}
+
+/// The value at the dot-separated `path` in `value`.
+fn lookup<'a>(value: &'a JsonValue, path: &str) -> Option<&'a JsonValue> {
+    if path.is_empty() {
+        return Some(value);
+    }
+    path.split('.').try_fold(value, |value, key| match value {
+        JsonValue::Array(items) => items.get(key.parse::<usize>().ok()?),
+        value => value.get(key),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::query::tests::{make_entity, make_field};
+    use crate::types::Type;
+    use serde_json::json;
+
+    #[test]
+    fn rows_of_response() {
+        let ty = make_entity(
+            "Repo",
+            vec![
+                make_field("name", Type::String),
+                make_field("stars", Type::Float),
+            ],
+        );
+        let connector = Connector {
+            url: "https://api.example.com/repos".to_owned(),
+            items: "data.items".to_owned(),
+            fields: BTreeMap::from([("stars".to_owned(), "counts.stars".to_owned())]),
+            ttl: 60,
+            rate_limit: 10,
+        };
+        let response = json!({"data": {"items": [
+            {"id": 7, "name": "chisel", "counts": {"stars": 3}},
+            {"name": "nameless", "counts": {}},
+        ]}});
+        let rows = connector.rows(&ty, &response).unwrap();
+        assert_eq!(
+            JsonValue::Array(rows.into_iter().map(JsonValue::Object).collect()),
+            json!([{"id": "7", "name": "chisel", "stars": 3}, {"name": "nameless"}])
+        );
+
+        let error = connector.rows(&ty, &json!([])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "response of https://api.example.com/repos has no array at `data.items`"
+        );
+    }
+}
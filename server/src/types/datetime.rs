@@ -0,0 +1,94 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Values of `Date` fields. They are stored as ISO-8601 strings in UTC with
+//! millisecond precision, like those of JavaScript's `Date.toISOString()`.
+//! All of them have the same length, so they compare and sort like the
+//! times they represent.
+
+use anyhow::{Context, Result};
+use serde_json::Value as JsonValue;
+use time::format_description::well_known::Rfc3339;
+use time::{OffsetDateTime, UtcOffset};
+
+/// Formats `time` as stored.
+pub fn format(time: OffsetDateTime) -> String {
+    let time = time.to_offset(UtcOffset::UTC);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+        time.millisecond()
+    )
+}
+
+/// Converts the JSON value of a `Date`, an ISO-8601 string or a number of
+/// milliseconds since the Unix epoch, to the value stored.
+pub fn from_json(value: &JsonValue) -> Result<String> {
+    match value {
+        JsonValue::String(s) => from_str(s),
+        JsonValue::Number(n) => {
+            let millis = n.as_f64().context("invalid number of milliseconds")?;
+            from_millis(millis)
+        }
+        _ => anyhow::bail!(
+            "a date must be an ISO-8601 string or a number of milliseconds, got {value}"
+        ),
+    }
+}
+
+/// Like `from_json()` for a string, which may hold a number of milliseconds.
+pub fn from_str(s: &str) -> Result<String> {
+    if let Ok(millis) = s.parse::<f64>() {
+        return from_millis(millis);
+    }
+    let time = OffsetDateTime::parse(s, &Rfc3339)
+        .with_context(|| format!("`{s}` is not an ISO-8601 date and time"))?;
+    checked(time)
+}
+
+fn from_millis(millis: f64) -> Result<String> {
+    // Dates have millisecond precision.
+    let nanos = millis.round() as i128 * 1_000_000;
+    let time = OffsetDateTime::from_unix_timestamp_nanos(nanos)
+        .with_context(|| format!("{millis} milliseconds since the epoch is out of range"))?;
+    checked(time)
+}
+
+/// Ensures the year of `time` has 4 digits, so its stored value has the
+/// same length as all others.
+fn checked(time: OffsetDateTime) -> Result<String> {
+    let year = time.to_offset(UtcOffset::UTC).year();
+    anyhow::ensure!(
+        (0..=9999).contains(&year),
+        "dates must be between the years 0 and 9999, got {year}"
+    );
+    Ok(format(time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn converts_to_stored_values() {
+        let stored = "2022-03-04T05:06:07.089Z";
+        assert_eq!(from_json(&json!(stored)).unwrap(), stored);
+        assert_eq!(
+            from_json(&json!("2022-03-04T07:06:07.089+02:00")).unwrap(),
+            stored
+        );
+        assert_eq!(from_json(&json!(1646370367089u64)).unwrap(), stored);
+        assert_eq!(from_str("1646370367089").unwrap(), stored);
+        assert_eq!(
+            from_json(&json!("2022-03-04T05:06:07Z")).unwrap(),
+            "2022-03-04T05:06:07.000Z"
+        );
+        assert!(from_json(&json!("yesterday")).is_err());
+        assert!(from_json(&json!(true)).is_err());
+    }
+}
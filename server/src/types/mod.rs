@@ -1,23 +1,53 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
+pub use self::archive::Archiving;
 pub use self::builtin::BuiltinTypes;
-pub use self::type_system::{TypeSystem, TypeSystemError};
+pub use self::connector::Connector;
+pub use self::naming::NamingStrategy;
+pub use self::partition::{PartitionInterval, PartitionScheme, Partitioning};
+pub use self::type_system::{
+    PopulateChanges, PopulateIds, TypeSystem, TypeSystemError, VersionTypes,
+};
 use crate::datastore::query::truncate_identifier;
 use crate::policies::EntityPolicy;
+use anyhow::Context;
+use deno_core::url::Url;
 use std::collections::BTreeMap;
 use std::ops::Deref;
 use std::sync::Arc;
 use uuid::Uuid;
 
+pub mod archive;
 mod builtin;
+pub mod connector;
+pub mod datetime;
+pub mod naming;
+pub mod partition;
 mod type_system;
 
+/// The property of the objects of a polymorphic relation that names their
+/// entity, like `"__type": "Post"`.
+pub const TYPE_KEY: &str = "__type";
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Type {
     String,
     Float,
     Boolean,
+    /// A point in time, a `Date` in TypeScript.
+    DateTime,
+    /// Any JSON value, a `JSONValue` in TypeScript.
+    Json,
+    /// A file in the blob store, a `ChiselFile` in TypeScript. Its metadata
+    /// is stored in the column, and its content in the blob store.
+    File,
+    /// One of the given strings, a union of string literals in TypeScript.
+    Enum(Vec<String>),
     Entity(Entity),
+    /// One of the given entities, a union of entity classes in TypeScript.
+    /// The id of the object is stored in the column, and the name of its
+    /// entity in the discriminator column next to it.
+    Union(Vec<Entity>),
     Array(Box<Type>),
 }
 
@@ -27,10 +57,62 @@ impl Type {
             Type::Float => "number".to_string(),
             Type::String => "string".to_string(),
             Type::Boolean => "boolean".to_string(),
+            Type::DateTime => "Date".to_string(),
+            Type::Json => "JSONValue".to_string(),
+            Type::File => "ChiselFile".to_string(),
+            Type::Enum(variants) => enum_name(variants),
             Type::Entity(ty) => ty.name.to_string(),
+            Type::Union(entities) => entities
+                .iter()
+                .map(|ty| ty.name())
+                .collect::<Vec<_>>()
+                .join(" | "),
             Type::Array(ty) => format!("Array<{}>", ty.name()),
         }
     }
+
+    /// Whether all values of type `other` are values of this type, which
+    /// stores them the same way.
+    pub fn includes(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Enum(variants), Type::Enum(others)) => {
+                others.iter().all(|other| variants.contains(other))
+            }
+            (Type::String, Type::Enum(_)) => true,
+            (Type::Union(entities), Type::Union(others)) => others
+                .iter()
+                .all(|other| entities.iter().any(|ty| ty.name() == other.name())),
+            _ => self == other,
+        }
+    }
+}
+
+/// Name of the enum type of `variants`, in the syntax of TypeScript, like
+/// `"draft" | "published"`.
+fn enum_name(variants: &[String]) -> String {
+    variants
+        .iter()
+        .map(|variant| serde_json::to_string(variant).unwrap())
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// Parses the variants of an enum type from its name.
+pub fn parse_enum_name(name: &str) -> Option<Vec<String>> {
+    if !name.starts_with('"') {
+        return None;
+    }
+    let mut variants = vec![];
+    let mut rest = name;
+    loop {
+        let mut stream = serde_json::Deserializer::from_str(rest).into_iter::<String>();
+        variants.push(stream.next()?.ok()?);
+        rest = rest[stream.byte_offset()..].trim_start();
+        if rest.is_empty() {
+            return Some(variants);
+        }
+        rest = rest.strip_prefix('|')?.trim_start();
+    }
 }
 
 impl From<Entity> for Type {
@@ -198,6 +280,16 @@ impl<'a> NewObject<'a> {
             backing_table,
         }
     }
+
+    /// A new object stored in `backing_table`, which may already exist,
+    /// rather than in a table of its own.
+    pub fn with_backing_table(name: &'a str, api_version: &'a str, backing_table: String) -> Self {
+        Self {
+            name,
+            api_version,
+            backing_table,
+        }
+    }
 }
 
 impl<'a> ObjectDescriptor for NewObject<'a> {
@@ -223,8 +315,19 @@ pub enum TypeId {
     String,
     Float,
     Boolean,
+    DateTime,
+    Json,
+    File,
+    Enum(Vec<String>),
     Id,
-    Entity { name: String, api_version: String },
+    Entity {
+        name: String,
+        api_version: String,
+    },
+    Union {
+        names: Vec<String>,
+        api_version: String,
+    },
     Array(Box<TypeId>),
 }
 
@@ -234,10 +337,22 @@ impl TypeId {
             TypeId::Id | TypeId::String => "string".to_string(),
             TypeId::Float => "number".to_string(),
             TypeId::Boolean => "boolean".to_string(),
+            TypeId::DateTime => "Date".to_string(),
+            TypeId::Json => "JSONValue".to_string(),
+            TypeId::File => "ChiselFile".to_string(),
+            TypeId::Enum(variants) => enum_name(variants),
             TypeId::Entity { ref name, .. } => name.to_string(),
+            TypeId::Union { names, .. } => names.join(" | "),
             TypeId::Array(elem_type) => format!("Array<{}>", elem_type.name()),
         }
     }
+
+    /// Whether a field of this type is a many-to-many relation, like
+    /// `authors: Author[]`. Those are stored in a join table instead of a
+    /// column of the backing table.
+    pub fn is_many_to_many(&self) -> bool {
+        matches!(self, TypeId::Array(elem_type) if matches!(**elem_type, TypeId::Entity { .. }))
+    }
 }
 
 impl From<Type> for TypeId {
@@ -246,10 +361,21 @@ impl From<Type> for TypeId {
             Type::String => Self::String,
             Type::Float => Self::Float,
             Type::Boolean => Self::Boolean,
+            Type::DateTime => Self::DateTime,
+            Type::Json => Self::Json,
+            Type::File => Self::File,
+            Type::Enum(variants) => Self::Enum(variants),
             Type::Entity(e) => Self::Entity {
                 name: e.name().to_string(),
                 api_version: e.api_version.clone(),
             },
+            Type::Union(entities) => Self::Union {
+                api_version: entities
+                    .first()
+                    .map(|e| e.api_version.clone())
+                    .unwrap_or_default(),
+                names: entities.iter().map(|e| e.name().to_owned()).collect(),
+            },
             Type::Array(elem_type) => {
                 let element_type_id: Self = (*elem_type).into();
                 Self::Array(Box::new(element_type_id))
@@ -260,7 +386,7 @@ impl From<Type> for TypeId {
 
 impl From<&dyn FieldDescriptor> for TypeId {
     fn from(other: &dyn FieldDescriptor) -> Self {
-        other.ty().into()
+        other.type_id()
     }
 }
 
@@ -278,6 +404,14 @@ pub struct ObjectType {
     chisel_id: Field,
     /// Name of the backing table for this type.
     backing_table: String,
+    /// How the backing table is partitioned, from `@partition`.
+    partition: Option<Partitioning>,
+    /// Which rows are moved to the archive table, from `@archive`.
+    archive: Option<Archiving>,
+    /// The external API the rows are fetched from, from `@connector`.
+    connector: Option<Connector>,
+    /// The doc comment of the class.
+    description: Option<String>,
 
     pub api_version: String,
 }
@@ -291,27 +425,73 @@ impl ObjectType {
         let backing_table = desc.backing_table();
         let api_version = desc.api_version();
 
-        for field in fields.iter() {
+        for (i, field) in fields.iter().enumerate() {
             anyhow::ensure!(
                 api_version == field.api_version,
                 "API version of fields don't match: Got {} and {}",
                 api_version,
                 field.api_version
             );
+            let column = field.column_name();
+            let other = fields[..i].iter().find(|f| f.column_name() == column);
+            anyhow::ensure!(
+                column != "id" && other.is_none(),
+                "field '{}' of type '{}' is stored in column '{}', which is taken by {}",
+                field.name,
+                desc.name(),
+                column,
+                other.map_or("the id".to_owned(), |f| format!("field '{}'", f.name))
+            );
         }
         for index in &indexes {
             for field_name in &index.fields {
-                if field_name == "id" {
+                if field_name == "id" && !index.search && !index.counted {
                     continue;
                 }
+                let field = fields.iter().find(|f| &f.name == field_name);
                 anyhow::ensure!(
-                    fields.iter().any(|f| &f.name == field_name),
+                    field.is_some(),
                     "trying to create an index over field '{}' which is not present on type '{}'",
                     field_name,
                     desc.name()
                 );
+                anyhow::ensure!(
+                    !index.search || field.unwrap().type_id == TypeId::String,
+                    "field '{}' of type '{}' is searchable, but isn't a string",
+                    field_name,
+                    desc.name()
+                );
+                if index.counted {
+                    let field = field.unwrap();
+                    anyhow::ensure!(
+                        !field.is_optional,
+                        "type '{}' is counted by optional field '{}'",
+                        desc.name(),
+                        field_name
+                    );
+                    anyhow::ensure!(
+                        matches!(
+                            field.type_id,
+                            TypeId::String | TypeId::Float | TypeId::Boolean | TypeId::Enum(_)
+                        ),
+                        "type '{}' can't be counted by field '{}' of type {}",
+                        desc.name(),
+                        field_name,
+                        field.type_id.name()
+                    );
+                }
             }
+            anyhow::ensure!(
+                !index.counted || index.fields.len() <= 1,
+                "a counter of type '{}' can only group by one field",
+                desc.name()
+            );
         }
+        anyhow::ensure!(
+            indexes.iter().filter(|index| index.search).count() <= 1,
+            "type '{}' declares @searchable more than once",
+            desc.name()
+        );
         let chisel_id = Field {
             id: None,
             name: "id".to_string(),
@@ -322,6 +502,8 @@ impl ObjectType {
             is_optional: false,
             api_version: "__chiselstrike".into(),
             is_unique: true,
+            description: None,
+            column: None,
         };
 
         Ok(Self {
@@ -332,9 +514,144 @@ impl ObjectType {
             fields,
             indexes,
             chisel_id,
+            partition: None,
+            archive: None,
+            connector: None,
+            description: None,
         })
     }
 
+    /// Partitions the backing table as `partition` says, after checking that
+    /// it can be.
+    pub fn with_partition(mut self, partition: Option<Partitioning>) -> anyhow::Result<Self> {
+        if let Some(partition) = &partition {
+            let field = self.get_field(&partition.field).with_context(|| {
+                format!(
+                    "entity '{}' is partitioned by field '{}', which it doesn't have",
+                    self.name, partition.field
+                )
+            })?;
+            anyhow::ensure!(
+                !field.is_optional,
+                "entity '{}' is partitioned by optional field '{}'",
+                self.name,
+                field.name
+            );
+            match &partition.scheme {
+                PartitionScheme::Time { .. } => anyhow::ensure!(
+                    field.type_id == TypeId::DateTime,
+                    "entity '{}' is partitioned by time, but field '{}' isn't a Date",
+                    self.name,
+                    field.name
+                ),
+                PartitionScheme::Hash { partitions } => {
+                    anyhow::ensure!(
+                        *partitions > 0,
+                        "entity '{}' must be partitioned in at least one partition",
+                        self.name
+                    );
+                    anyhow::ensure!(
+                        !matches!(
+                            field.type_id,
+                            TypeId::Array(_) | TypeId::Json | TypeId::File
+                        ),
+                        "entity '{}' can't be partitioned by field '{}' of type {}",
+                        self.name,
+                        field.name,
+                        field.type_id.name()
+                    );
+                }
+            }
+            // Postgres only enforces uniqueness within each partition.
+            if let Some(unique) = self.fields.iter().find(|f| f.is_unique) {
+                anyhow::bail!(
+                    "entity '{}' is partitioned, so its field '{}' can't be @unique",
+                    self.name,
+                    unique.name
+                );
+            }
+        }
+        self.partition = partition;
+        Ok(self)
+    }
+
+    /// Archives the rows as `archive` says, after checking that they can be.
+    /// It must be called after `with_partition()`.
+    pub fn with_archive(mut self, archive: Option<Archiving>) -> anyhow::Result<Self> {
+        if let Some(archive) = &archive {
+            let field = self.get_field(&archive.field).with_context(|| {
+                format!(
+                    "entity '{}' is archived by field '{}', which it doesn't have",
+                    self.name, archive.field
+                )
+            })?;
+            anyhow::ensure!(
+                field.type_id == TypeId::DateTime,
+                "entity '{}' is archived by field '{}', which isn't a Date",
+                self.name,
+                field.name
+            );
+            anyhow::ensure!(
+                archive.after_days > 0,
+                "entity '{}' must be archived after at least one day",
+                self.name
+            );
+            // Partitions by time already drop the old rows.
+            anyhow::ensure!(
+                self.partition.is_none(),
+                "entity '{}' can't be both partitioned and archived",
+                self.name
+            );
+        }
+        self.archive = archive;
+        Ok(self)
+    }
+
+    /// Fetches the rows from the API of `connector`, after checking that it
+    /// maps the fields of this type.
+    pub fn with_connector(mut self, connector: Option<Connector>) -> anyhow::Result<Self> {
+        if let Some(connector) = &connector {
+            let url = Url::parse(&connector.url).with_context(|| {
+                format!(
+                    "entity '{}' has a connector with invalid URL '{}'",
+                    self.name, connector.url
+                )
+            })?;
+            anyhow::ensure!(
+                matches!(url.scheme(), "http" | "https"),
+                "entity '{}' has a connector with URL '{}', which isn't HTTP",
+                self.name,
+                connector.url
+            );
+            for field in connector.fields.keys() {
+                anyhow::ensure!(
+                    self.has_field(field),
+                    "the connector of entity '{}' maps field '{}', which it doesn't have",
+                    self.name,
+                    field
+                );
+            }
+            anyhow::ensure!(
+                connector.rate_limit > 0,
+                "the connector of entity '{}' must allow at least one fetch a minute",
+                self.name
+            );
+            // The rows are replaced whenever they are fetched.
+            anyhow::ensure!(
+                self.partition.is_none() && self.archive.is_none(),
+                "entity '{}' has a connector, so it can't be partitioned or archived",
+                self.name
+            );
+        }
+        self.connector = connector;
+        Ok(self)
+    }
+
+    pub fn with_description(mut self, description: Option<String>) -> Self {
+        self.description = description;
+        self
+    }
+
     pub fn user_fields(&self) -> impl Iterator<Item = &Field> {
         self.fields.iter()
     }
@@ -351,10 +668,44 @@ impl ObjectType {
         self.all_fields().find(|f| f.name == field_name)
     }
 
+    /// Fields stored in columns of the backing table, which are all but the
+    /// many-to-many relations.
+    pub fn column_fields(&self) -> impl Iterator<Item = &Field> {
+        self.all_fields()
+            .filter(|field| !field.type_id.is_many_to_many())
+    }
+
+    /// Fields that are many-to-many relations, stored in join tables.
+    pub fn many_to_many_fields(&self) -> impl Iterator<Item = &Field> {
+        self.fields
+            .iter()
+            .filter(|field| field.type_id.is_many_to_many())
+    }
+
     pub fn backing_table(&self) -> &str {
         &self.backing_table
     }
 
+    /// The column of the backing table that stores the field named `name`,
+    /// which is `name` itself unless the field is mapped to another column.
+    pub fn column<'a>(&'a self, name: &'a str) -> &'a str {
+        self.fields
+            .iter()
+            .find(|field| field.name == name)
+            .map_or(name, |field| field.column_name())
+    }
+
+    /// Name of the join table of the many-to-many relation `field`. Its rows
+    /// link the object with id "owner" to the related object with id "item",
+    /// which is at "position" in the relation.
+    pub fn join_table(&self, field: &Field) -> anyhow::Result<String> {
+        let field_id = field
+            .id
+            .with_context(|| format!("field {} of {} has no id", field.name, self.name))?;
+        let name = format!("join_{}_{}_{}", field_id, self.name, field.name);
+        Ok(truncate_identifier(&name).to_owned())
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -372,6 +723,54 @@ impl ObjectType {
     pub fn indexes(&self) -> &Vec<DbIndex> {
         &self.indexes
     }
+
+    /// How the backing table is partitioned, if it is.
+    pub fn partition(&self) -> Option<&Partitioning> {
+        self.partition.as_ref()
+    }
+
+    /// Which rows are archived, if any are.
+    pub fn archive(&self) -> Option<&Archiving> {
+        self.archive.as_ref()
+    }
+
+    /// The external API the rows are fetched from, if they are.
+    pub fn connector(&self) -> Option<&Connector> {
+        self.connector.as_ref()
+    }
+
+    /// What the type is, as its doc comment says.
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Name of the table the archived rows are moved to.
+    pub fn archive_table(&self) -> String {
+        truncate_identifier(&format!("archive_{}", self.backing_table)).to_owned()
+    }
+
+    /// The full-text search index of this type, if it has searchable fields.
+    pub fn search_index(&self) -> Option<&DbIndex> {
+        self.indexes.iter().find(|index| index.search)
+    }
+
+    /// The counter of the rows of this type with each value of `group_by`,
+    /// or of all the rows if `group_by` is None. All the rows can also be
+    /// counted by adding up the counts of any counter.
+    pub fn counter(&self, group_by: Option<&str>) -> Option<&DbIndex> {
+        let mut counters = self.indexes.iter().filter(|index| index.counted);
+        match group_by {
+            Some(field) => counters.find(|index| index.fields == [field]),
+            None => {
+                let counters: Vec<_> = counters.collect();
+                counters
+                    .iter()
+                    .find(|index| index.fields.is_empty())
+                    .or_else(|| counters.first())
+                    .copied()
+            }
+        }
+    }
 }
 
 impl PartialEq for ObjectType {
@@ -387,14 +786,29 @@ pub struct DbIndex {
     /// Name of the index in database. Before it's creation, it will be None.
     backing_table: Option<String>,
     pub fields: Vec<String>,
+    /// Whether this is a full-text search index over the fields, from
+    /// `@searchable`, rather than a regular one.
+    pub search: bool,
+    /// Whether this is a table counting the rows, from `@counted`, rather
+    /// than an index. It counts the rows with each value of its field, or
+    /// all of them if it has none.
+    pub counted: bool,
 }
 
 impl DbIndex {
-    pub fn new(meta_id: i32, backing_table: String, fields: Vec<String>) -> Self {
+    pub fn new(
+        meta_id: i32,
+        backing_table: String,
+        fields: Vec<String>,
+        search: bool,
+        counted: bool,
+    ) -> Self {
         Self {
             meta_id: Some(meta_id),
             backing_table: Some(backing_table),
             fields,
+            search,
+            counted,
         }
     }
 
@@ -403,6 +817,22 @@ impl DbIndex {
             meta_id: None,
             backing_table: None,
             fields,
+            search: false,
+            counted: false,
+        }
+    }
+
+    pub fn new_search(fields: Vec<String>) -> Self {
+        Self {
+            search: true,
+            ..Self::new_from_fields(fields)
+        }
+    }
+
+    pub fn new_counted(fields: Vec<String>) -> Self {
+        Self {
+            counted: true,
+            ..Self::new_from_fields(fields)
         }
     }
 
@@ -468,22 +898,22 @@ impl<'a> FieldMap<'a> {
 pub trait FieldDescriptor {
     fn name(&self) -> String;
     fn id(&self) -> Option<i32>;
-    fn ty(&self) -> Type;
+    fn type_id(&self) -> TypeId;
     fn api_version(&self) -> String;
 }
 
 pub struct ExistingField {
     name: String,
-    ty_: Type,
+    type_id: TypeId,
     id: i32,
     version: String,
 }
 
 impl ExistingField {
-    pub fn new(name: &str, ty_: Type, id: i32, version: &str) -> Self {
+    pub fn new(name: &str, ty_: impl Into<TypeId>, id: i32, version: &str) -> Self {
         Self {
             name: name.to_owned(),
-            ty_,
+            type_id: ty_.into(),
             id,
             version: version.to_owned(),
         }
@@ -499,8 +929,8 @@ impl FieldDescriptor for ExistingField {
         Some(self.id)
     }
 
-    fn ty(&self) -> Type {
-        self.ty_.clone()
+    fn type_id(&self) -> TypeId {
+        self.type_id.clone()
     }
 
     fn api_version(&self) -> String {
@@ -510,13 +940,19 @@ impl FieldDescriptor for ExistingField {
 
 pub struct NewField<'a> {
     name: &'a str,
-    ty_: Type,
+    type_id: TypeId,
     version: &'a str,
 }
 
 impl<'a> NewField<'a> {
-    pub fn new(name: &'a str, ty_: Type, version: &'a str) -> anyhow::Result<Self> {
-        Ok(Self { name, ty_, version })
+    /// A field of type `ty_`, which is given by its id for a model that refers
+    /// to itself, as it isn't in the type system yet.
+    pub fn new(name: &'a str, ty_: impl Into<TypeId>, version: &'a str) -> anyhow::Result<Self> {
+        Ok(Self {
+            name,
+            type_id: ty_.into(),
+            version,
+        })
     }
 }
 
@@ -529,8 +965,8 @@ impl<'a> FieldDescriptor for NewField<'a> {
         None
     }
 
-    fn ty(&self) -> Type {
-        self.ty_.clone()
+    fn type_id(&self) -> TypeId {
+        self.type_id.clone()
     }
 
     fn api_version(&self) -> String {
@@ -546,6 +982,11 @@ pub struct Field {
     pub labels: Vec<String>,
     pub is_optional: bool,
     pub is_unique: bool,
+    /// The doc comment of the field.
+    pub description: Option<String>,
+    /// The column of the backing table storing the field, from `@column` or
+    /// the naming strategy, if it isn't named like the field.
+    pub column: Option<String>,
     // We want to keep the default the user gave us so we can
     // return it in `chisel describe`. That's the default that is
     // valid in typescriptland.
@@ -567,7 +1008,7 @@ impl Field {
         is_optional: bool,
         is_unique: bool,
     ) -> Self {
-        let effective_default = if let Type::Boolean = &desc.ty() {
+        let effective_default = if let TypeId::Boolean = desc.type_id() {
             default
                 .clone()
                 .map(|x| if x == "false" { "false" } else { "true" })
@@ -586,9 +1027,26 @@ impl Field {
             effective_default,
             is_optional,
             is_unique,
+            description: None,
+            column: None,
         }
     }
 
+    pub fn with_description(mut self, description: Option<String>) -> Self {
+        self.description = description;
+        self
+    }
+
+    pub fn with_column(mut self, column: Option<String>) -> Self {
+        self.column = column.filter(|column| *column != self.name);
+        self
+    }
+
+    /// The column of the backing table storing the field.
+    pub fn column_name(&self) -> &str {
+        self.column.as_deref().unwrap_or(&self.name)
+    }
+
     pub fn user_provided_default(&self) -> &Option<String> {
         &self.default
     }
@@ -604,6 +1062,15 @@ impl Field {
         }
     }
 
+    /// The column storing the name of the entity of the object that the
+    /// field refers to, if the field is a polymorphic relation.
+    pub fn discriminator_column(&self) -> Option<String> {
+        match self.type_id {
+            TypeId::Union { .. } => Some(format!("{}__type", self.column_name())),
+            _ => None,
+        }
+    }
+
     pub fn persisted_name(&self, parent_type_name: &ObjectType) -> String {
         format!(
             "{}.{}.{}",
@@ -627,6 +1094,8 @@ pub struct FieldDelta {
     pub id: i32,
     pub attrs: Option<FieldAttrDelta>,
     pub labels: Option<Vec<String>>,
+    /// The new description, if it changed.
+    pub description: Option<Option<String>>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -636,4 +1105,29 @@ pub struct ObjectDelta {
     pub updated_fields: Vec<FieldDelta>,
     pub added_indexes: Vec<DbIndex>,
     pub removed_indexes: Vec<DbIndex>,
+    /// The new partitioning, when only its retention changed.
+    pub partition: Option<Partitioning>,
+    /// The new archiving, if `@archive` was added, changed or removed, in
+    /// which case it is `Some(None)`.
+    pub archive: Option<Option<Archiving>>,
+    /// The new connector, if `@connector` was added, changed or removed, in
+    /// which case it is `Some(None)`.
+    pub connector: Option<Option<Connector>>,
+    /// The new description, if it changed.
+    pub description: Option<Option<String>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enum_names_round_trip() {
+        let variants = vec!["draft".to_owned(), "a | \"b\"".to_owned(), "".to_owned()];
+        let name = enum_name(&variants);
+        assert_eq!(name, r#""draft" | "a | \"b\"" | """#);
+        assert_eq!(parse_enum_name(&name), Some(variants));
+        assert_eq!(parse_enum_name("string"), None);
+        assert_eq!(parse_enum_name(r#""a" "b""#), None);
+    }
 }
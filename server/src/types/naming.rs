@@ -0,0 +1,103 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! How the backing tables and columns of entities are named, from the
+//! `[naming]` table of the manifest.
+//!
+//! By default, each entity gets a table of its own named after it and a
+//! UUID, and each field a column named like it. Projects sitting on top of
+//! existing schemas can instead name them in snake_case, prefix the tables,
+//! or name them explicitly with `@table` and `@column`. Tables and columns
+//! are only named once, when they are created: changing the strategy later
+//! doesn't rename those that already exist.
+
+/// How the tables and columns of new entities and fields are named.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NamingStrategy {
+    /// Prepended to the names of the tables.
+    pub table_prefix: String,
+    /// Whether tables and columns are named in snake_case, rather than like
+    /// the entities and fields.
+    pub snake_case: bool,
+}
+
+impl NamingStrategy {
+    /// The table of new entity `name`, if the strategy names it rather than
+    /// leaving it to the default.
+    pub fn table(&self, name: &str) -> Option<String> {
+        if self.table_prefix.is_empty() && !self.snake_case {
+            return None;
+        }
+        Some(format!("{}{}", self.table_prefix, self.case(name)))
+    }
+
+    /// The column of new field `name`, if it isn't named like the field.
+    pub fn column(&self, name: &str) -> Option<String> {
+        Some(self.case(name)).filter(|column| column != name)
+    }
+
+    fn case(&self, name: &str) -> String {
+        match self.snake_case {
+            true => snake_case(name),
+            false => name.to_owned(),
+        }
+    }
+}
+
+/// `name`, like `createdAt` or `HTTPRequest`, in snake_case, like
+/// `created_at` or `http_request`.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).map_or(false, |next| next.is_lowercase());
+            if prev.is_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_uppercase() && next_is_lower)
+            {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snake_case_names() {
+        assert_eq!(snake_case("title"), "title");
+        assert_eq!(snake_case("createdAt"), "created_at");
+        assert_eq!(snake_case("BlogPost"), "blog_post");
+        assert_eq!(snake_case("HTTPRequest"), "http_request");
+        assert_eq!(snake_case("userID"), "user_id");
+        assert_eq!(snake_case("line2Total"), "line2_total");
+        assert_eq!(snake_case("already_snake"), "already_snake");
+    }
+
+    #[test]
+    fn tables_and_columns() {
+        let default = NamingStrategy::default();
+        assert_eq!(default.table("BlogPost"), None);
+        assert_eq!(default.column("createdAt"), None);
+
+        let naming = NamingStrategy {
+            table_prefix: "app_".to_owned(),
+            snake_case: true,
+        };
+        assert_eq!(naming.table("BlogPost").as_deref(), Some("app_blog_post"));
+        assert_eq!(naming.column("createdAt").as_deref(), Some("created_at"));
+        assert_eq!(naming.column("title"), None);
+
+        let prefixed = NamingStrategy {
+            table_prefix: "app_".to_owned(),
+            snake_case: false,
+        };
+        assert_eq!(prefixed.table("BlogPost").as_deref(), Some("app_BlogPost"));
+        assert_eq!(prefixed.column("createdAt"), None);
+    }
+}
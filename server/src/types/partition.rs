@@ -0,0 +1,176 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Partitioning of the backing tables of entities, declared with
+//! `@partition`.
+//!
+//! Only Postgres partitions tables. On SQLite, the table of an entity
+//! partitioned by time is a single partition, from which rows are deleted
+//! once they are past retention.
+
+use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+use time::{Date, Duration, Month};
+
+/// How the rows of an entity are partitioned. It is stored as JSON in the
+/// meta database.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Partitioning {
+    /// The field the rows are partitioned by.
+    pub field: String,
+    #[serde(flatten)]
+    pub scheme: PartitionScheme,
+}
+
+impl Partitioning {
+    /// Describes the partitioning for the plan of `chisel apply`.
+    pub fn describe(&self) -> String {
+        match &self.scheme {
+            PartitionScheme::Time {
+                interval,
+                retention: 0,
+            } => format!("partition by {} of {}", interval.name(), self.field),
+            PartitionScheme::Time {
+                interval,
+                retention,
+            } => format!(
+                "partition by {} of {}, keeping {} before the current one",
+                interval.name(),
+                self.field,
+                retention
+            ),
+            PartitionScheme::Hash { partitions } => {
+                format!("partition by hash of {} into {}", self.field, partitions)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "by", rename_all = "lowercase")]
+pub enum PartitionScheme {
+    /// A partition per `interval` of the times in a `Date` field. Rows more
+    /// than `retention` intervals older than the current one are dropped,
+    /// unless `retention` is 0.
+    Time {
+        interval: PartitionInterval,
+        retention: u32,
+    },
+    /// A fixed number of partitions, by the hash of the field.
+    Hash { partitions: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionInterval {
+    Day,
+    Month,
+    Year,
+}
+
+impl PartitionInterval {
+    pub fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "day" => Self::Day,
+            "month" => Self::Month,
+            "year" => Self::Year,
+            _ => anyhow::bail!(
+                "unknown partition interval '{}', expected day, month or year",
+                name
+            ),
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Month => "month",
+            Self::Year => "year",
+        }
+    }
+
+    /// The start of the interval `date` is in.
+    pub fn start(self, date: Date) -> Date {
+        match self {
+            Self::Day => date,
+            Self::Month => date.replace_day(1).unwrap(),
+            Self::Year => Date::from_calendar_date(date.year(), Month::January, 1).unwrap(),
+        }
+    }
+
+    /// The start of the interval `n` intervals after the one that starts at
+    /// `start`, or before it if `n` is negative.
+    pub fn advance(self, start: Date, n: i32) -> Date {
+        match self {
+            Self::Day => start + Duration::days(n.into()),
+            Self::Month => {
+                let months = start.year() * 12 + start.month() as i32 - 1 + n;
+                let month = Month::try_from((months.rem_euclid(12) + 1) as u8).unwrap();
+                Date::from_calendar_date(months.div_euclid(12), month, 1).unwrap()
+            }
+            Self::Year => Date::from_calendar_date(start.year() + n, Month::January, 1).unwrap(),
+        }
+    }
+
+    /// Suffix of the name of the partition of the interval that starts at
+    /// `start`. The suffixes of an interval sort like the times they're for.
+    pub fn suffix(self, start: Date) -> String {
+        match self {
+            Self::Day => format!(
+                "{:04}{:02}{:02}",
+                start.year(),
+                start.month() as u8,
+                start.day()
+            ),
+            Self::Month => format!("{:04}{:02}", start.year(), start.month() as u8),
+            Self::Year => format!("{:04}", start.year()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u8, day: u8) -> Date {
+        Date::from_calendar_date(year, Month::try_from(month).unwrap(), day).unwrap()
+    }
+
+    #[test]
+    fn intervals() {
+        let day = date(2022, 12, 17);
+        let month = PartitionInterval::Month;
+        assert_eq!(month.start(day), date(2022, 12, 1));
+        assert_eq!(month.advance(date(2022, 12, 1), 1), date(2023, 1, 1));
+        assert_eq!(month.advance(date(2022, 1, 1), -13), date(2020, 12, 1));
+        assert_eq!(month.suffix(date(2022, 3, 1)), "202203");
+
+        let year = PartitionInterval::Year;
+        assert_eq!(year.start(day), date(2022, 1, 1));
+        assert_eq!(year.advance(date(2022, 1, 1), -2), date(2020, 1, 1));
+        assert_eq!(year.suffix(date(2022, 1, 1)), "2022");
+
+        let days = PartitionInterval::Day;
+        assert_eq!(days.advance(day, 15), date(2023, 1, 1));
+        assert_eq!(days.suffix(day), "20221217");
+    }
+
+    #[test]
+    fn stored_as_json() {
+        let partitioning = Partitioning {
+            field: "createdAt".to_owned(),
+            scheme: PartitionScheme::Time {
+                interval: PartitionInterval::Month,
+                retention: 12,
+            },
+        };
+        let json = serde_json::to_string(&partitioning).unwrap();
+        assert_eq!(
+            json,
+            r#"{"field":"createdAt","by":"time","interval":"month","retention":12}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Partitioning>(&json).unwrap(),
+            partitioning
+        );
+    }
+}
@@ -1,17 +1,26 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
 use super::{
-    BuiltinTypes, DbIndex, Entity, FieldAttrDelta, FieldDelta, FieldMap, ObjectDelta, ObjectType,
-    Type, TypeId,
+    parse_enum_name, BuiltinTypes, DbIndex, Entity, FieldAttrDelta, FieldDelta, FieldMap,
+    ObjectDelta, ObjectType, PartitionScheme, Partitioning, Type, TypeId,
 };
+use crate::auth::is_auth_entity_name;
+use crate::datastore::expr::{BinaryExpr, Expr, PropertyAccess, Value as ExprValue};
 use crate::datastore::query::QueryPlan;
 use crate::datastore::QueryEngine;
+use crate::JsonObject;
 use anyhow::Context;
 use deno_core::futures;
 use derive_new::new;
 use futures::StreamExt;
+use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::sync::Arc;
+use uuid::Uuid;
+
+/// Number of rows that population copies to the new version at once.
+const POPULATE_BATCH: usize = 5000;
 
 #[derive(thiserror::Error, Debug)]
 pub enum TypeSystemError {
@@ -126,6 +135,8 @@ impl TypeSystem {
             ));
         }
 
+        let partition = Self::partition_delta(old_type, &new_type)?;
+
         let mut old_fields = FieldMap::from(old_type);
         let new_fields = FieldMap::from(new_type.as_ref());
 
@@ -148,7 +159,40 @@ impl TypeSystem {
                     let field_ty = ts.get(&field.type_id)?;
 
                     let old_ty = ts.get(&old.type_id)?;
-                    if !allow_unsafe_replacement && field_ty != old_ty {
+                    if field.type_id.is_many_to_many() != old.type_id.is_many_to_many() {
+                        return Err(TypeSystemError::UnsafeReplacement(
+                            new_type.name.clone(),
+                            format!(
+                                "changing field {} to or from a many-to-many relation. Incompatible change",
+                                field.name
+                            ),
+                        ));
+                    }
+                    // Polymorphic relations have a discriminator column too.
+                    if field.discriminator_column().is_some()
+                        != old.discriminator_column().is_some()
+                    {
+                        return Err(TypeSystemError::UnsafeReplacement(
+                            new_type.name.clone(),
+                            format!(
+                                "changing field {} to or from a polymorphic relation. Incompatible change",
+                                field.name
+                            ),
+                        ));
+                    }
+                    // Columns aren't renamed, they would have to be copied over.
+                    if field.column_name() != old.column_name() {
+                        return Err(TypeSystemError::UnsafeReplacement(
+                            new_type.name.clone(),
+                            format!(
+                                "changing the column of field {} from {} to {}. Incompatible change",
+                                field.name,
+                                old.column_name(),
+                                field.column_name()
+                            ),
+                        ));
+                    }
+                    if !allow_unsafe_replacement && !field_ty.includes(&old_ty) {
                         // FIXME: it should be almost always possible to evolve things into
                         // strings.
                         return Err(TypeSystemError::UnsafeReplacement(
@@ -162,6 +206,19 @@ impl TypeSystem {
                         ));
                     }
 
+                    let partitioned_by_field = new_type
+                        .partition()
+                        .map_or(false, |partition| partition.field == field.name);
+                    if partitioned_by_field && field_ty != old_ty {
+                        return Err(TypeSystemError::UnsafeReplacement(
+                            new_type.name.clone(),
+                            format!(
+                                "changing the type of field {}, which the table is partitioned by. Incompatible change",
+                                field.name
+                            ),
+                        ));
+                    }
+
                     if !allow_unsafe_replacement && field.is_unique && !old.is_unique {
                         // FIXME: it should be possible to do it by issuing a select count() and
                         // then a select count distinct and comparing both results. But to do this
@@ -208,7 +265,14 @@ impl TypeSystem {
                             "logical error! updating field without id".to_string(),
                         )
                     })?;
-                    updated_fields.push(FieldDelta { id, attrs, labels });
+                    let description =
+                        (field.description != old.description).then(|| field.description.clone());
+                    updated_fields.push(FieldDelta {
+                        id,
+                        attrs,
+                        labels,
+                        description,
+                    });
                 }
             }
         }
@@ -233,9 +297,50 @@ impl TypeSystem {
             updated_fields,
             added_indexes: Self::find_added_indexes(old_type, &new_type),
             removed_indexes: Self::find_removed_indexes(old_type, &new_type),
+            partition,
+            archive: (old_type.archive() != new_type.archive())
+                .then(|| new_type.archive().cloned()),
+            connector: (old_type.connector() != new_type.connector())
+                .then(|| new_type.connector().cloned()),
+            description: (old_type.description() != new_type.description())
+                .then(|| new_type.description().map(str::to_owned)),
         })
     }
 
+    /// The new partitioning of a type, if it changed. Only the retention of
+    /// partitions by time can change, as anything else would need the whole
+    /// table to be rewritten.
+    fn partition_delta(
+        old_type: &ObjectType,
+        new_type: &ObjectType,
+    ) -> Result<Option<Partitioning>, TypeSystemError> {
+        let (old, new) = (old_type.partition(), new_type.partition());
+        if old == new {
+            return Ok(None);
+        }
+        if let (Some(old), Some(new)) = (old, new) {
+            if let (
+                PartitionScheme::Time { interval, .. },
+                PartitionScheme::Time {
+                    interval: new_interval,
+                    ..
+                },
+            ) = (&old.scheme, &new.scheme)
+            {
+                if old.field == new.field && interval == new_interval {
+                    return Ok(Some(new.clone()));
+                }
+            }
+        }
+        Err(TypeSystemError::UnsafeReplacement(
+            new_type.name.clone(),
+            format!(
+                "changing how {} is partitioned. Incompatible change",
+                new_type.name
+            ),
+        ))
+    }
+
     fn find_added_indexes(old_type: &ObjectType, new_type: &ObjectType) -> Vec<DbIndex> {
         Self::index_diff(new_type.indexes(), old_type.indexes())
     }
@@ -248,7 +353,13 @@ impl TypeSystem {
     /// all indexes that are contained in `lhs` but not in `rhs` (`lhs` - `rhs`).
     fn index_diff(lhs: &[DbIndex], rhs: &[DbIndex]) -> Vec<DbIndex> {
         lhs.iter()
-            .filter(|lhs_idx| !rhs.iter().any(|rhs_idx| lhs_idx.fields == rhs_idx.fields))
+            .filter(|lhs_idx| {
+                !rhs.iter().any(|rhs_idx| {
+                    lhs_idx.fields == rhs_idx.fields
+                        && lhs_idx.search == rhs_idx.search
+                        && lhs_idx.counted == rhs_idx.counted
+                })
+            })
             .cloned()
             .collect()
     }
@@ -274,6 +385,9 @@ impl TypeSystem {
 
     /// Looks up a builtin type with name `type_name`.
     pub fn lookup_builtin_type(&self, type_name: &str) -> Result<Type, TypeSystemError> {
+        if let Some(variants) = parse_enum_name(type_name) {
+            return Ok(Type::Enum(variants));
+        }
         if let Some(element_type_str) = type_name.strip_prefix("Array<") {
             if let Some(element_type_str) = element_type_str.strip_suffix('>') {
                 let element_type = self.lookup_builtin_type(element_type_str)?;
@@ -295,6 +409,18 @@ impl TypeSystem {
     pub fn lookup_type(&self, type_name: &str, api_version: &str) -> Result<Type, TypeSystemError> {
         if let Ok(ty) = self.lookup_builtin_type(type_name) {
             Ok(ty)
+        } else if let Some(element_type_str) = type_name
+            .strip_prefix("Array<")
+            .and_then(|s| s.strip_suffix('>'))
+        {
+            let element_type = self.lookup_type(element_type_str, api_version)?;
+            Ok(Type::Array(Box::new(element_type)))
+        } else if type_name.contains(" | ") {
+            let entities = type_name
+                .split(" | ")
+                .map(|name| self.lookup_custom_type(name, api_version))
+                .collect::<Result<_, _>>()?;
+            Ok(Type::Union(entities))
         } else {
             let version = self.get_version(api_version)?;
             if let Ok(ty) = version.lookup_custom_type(type_name) {
@@ -331,6 +457,8 @@ impl TypeSystem {
         engine: Arc<QueryEngine>,
         api_version_to: T,
         api_version_from: F,
+        ids: &PopulateIds,
+        mut changes: Option<&mut PopulateChanges>,
     ) -> anyhow::Result<()> {
         let to = match self.versions.get(api_version_to.as_ref()) {
             Some(x) => Ok(x),
@@ -346,6 +474,10 @@ impl TypeSystem {
             )),
         }?;
 
+        let mut id_mapper = IdMapper {
+            ids,
+            regenerated: HashMap::new(),
+        };
         for (ty_name, ty_obj) in from.custom_types.iter() {
             if let Some(ty_obj_to) = to.custom_types.get(ty_name) {
                 // Either the TO type is a safe replacement of FROM, of we need to have a lens
@@ -361,15 +493,47 @@ impl TypeSystem {
                     })?;
 
                 let tr = engine.clone().begin_transaction_static().await?;
-                let query_plan = QueryPlan::from_type(ty_obj);
+                let mut query_plan = QueryPlan::from_type(ty_obj);
+                let mut tracked = changes.as_deref_mut().and_then(|changes| {
+                    let field = changes.changed_field(ty_obj)?.to_owned();
+                    let mark = changes
+                        .marks
+                        .entry(ty_name.to_owned())
+                        .or_insert(JsonValue::Null);
+                    Some((field, mark))
+                });
+                if let Some((field, mark)) = &tracked {
+                    if let Some(value) = mark_to_value(mark) {
+                        let property = PropertyAccess {
+                            property: field.clone(),
+                            object: Box::new(Expr::Parameter { position: 0 }),
+                        };
+                        query_plan =
+                            query_plan.filter(BinaryExpr::gt_eq(property.into(), value.into()));
+                    }
+                }
                 let mut row_streams = engine.query(tr.clone(), query_plan)?;
 
+                let mut batch = vec![];
                 while let Some(row) = row_streams.next().await {
                     // FIXME: basic rate limit?
-                    let row = row
+                    let mut row = row
                         .with_context(|| format!("population can't proceed as reading from the underlying database for type {} failed", ty_obj_to.name))?;
-                    engine.add_row_shallow(ty_obj_to, &row).await?;
+                    if let Some((field, mark)) = &mut tracked {
+                        if let Some(value) = row.get(field.as_str()) {
+                            if is_later(value, mark) {
+                                **mark = value.clone();
+                            }
+                        }
+                    }
+                    id_mapper.map_row(ty_obj_to, &mut row);
+                    batch.push(row);
+                    if batch.len() == POPULATE_BATCH {
+                        engine.load_rows(ty_obj_to, &batch).await?;
+                        batch.clear();
+                    }
                 }
+                engine.load_rows(ty_obj_to, &batch).await?;
                 drop(row_streams);
                 QueryEngine::commit_transaction_static(tr).await?;
             }
@@ -379,12 +543,142 @@ impl TypeSystem {
 
     pub fn get(&self, ty: &TypeId) -> Result<Type, TypeSystemError> {
         match ty {
-            TypeId::String | TypeId::Float | TypeId::Boolean | TypeId::Id | TypeId::Array(_) => {
-                self.lookup_builtin_type(&ty.name())
-            }
+            TypeId::String
+            | TypeId::Float
+            | TypeId::Boolean
+            | TypeId::DateTime
+            | TypeId::Json
+            | TypeId::File
+            | TypeId::Id => self.lookup_builtin_type(&ty.name()),
             TypeId::Entity { name, api_version } => {
                 self.lookup_entity(name, api_version).map(Type::Entity)
             }
+            TypeId::Union { names, api_version } => {
+                let entities = names
+                    .iter()
+                    .map(|name| self.lookup_custom_type(name, api_version))
+                    .collect::<Result<_, _>>()?;
+                Ok(Type::Union(entities))
+            }
+            TypeId::Enum(variants) => Ok(Type::Enum(variants.clone())),
+            TypeId::Array(elem_type) => Ok(Type::Array(Box::new(self.get(elem_type)?))),
+        }
+    }
+}
+
+/// State of a differential populate, which only copies the objects whose
+/// `field` changed since the last run. `field` must be a number or a string
+/// that grows whenever an object is written, such as a timestamp; objects of
+/// entities without it are all copied.
+pub struct PopulateChanges {
+    pub field: String,
+    /// The largest value of `field` copied so far, by entity name.
+    pub marks: HashMap<String, JsonValue>,
+}
+
+impl PopulateChanges {
+    fn changed_field(&self, ty: &ObjectType) -> Option<&str> {
+        ty.user_fields()
+            .find(|field| field.name == self.field)
+            .filter(|field| {
+                matches!(
+                    field.type_id,
+                    TypeId::Float | TypeId::String | TypeId::DateTime
+                )
+            })
+            .map(|_| self.field.as_str())
+    }
+}
+
+/// The filter value of a high-water mark. Objects whose changed field
+/// equals the mark are copied again, since more of them may have been
+/// written after the last run read them.
+fn mark_to_value(mark: &JsonValue) -> Option<ExprValue> {
+    match mark {
+        JsonValue::Number(n) => n.as_f64().map(ExprValue::F64),
+        JsonValue::String(s) => Some(ExprValue::String(s.clone())),
+        _ => None,
+    }
+}
+
+/// Whether `value` of a changed field is past high-water `mark`.
+fn is_later(value: &JsonValue, mark: &JsonValue) -> bool {
+    match (value, mark) {
+        (JsonValue::Number(value), JsonValue::Number(mark)) => value.as_f64() > mark.as_f64(),
+        (JsonValue::String(value), JsonValue::String(mark)) => value > mark,
+        (JsonValue::Number(_) | JsonValue::String(_), _) => true,
+        _ => false,
+    }
+}
+
+/// How `populate_types` assigns IDs to the objects it copies.
+pub enum PopulateIds {
+    /// Keep the original IDs, so populating again updates the copies.
+    Preserve,
+    /// Derive the IDs from the original ones and `namespace`, so populating
+    /// again with the same namespace updates the copies.
+    Remap { namespace: String },
+    /// Give the copies new random IDs. Populating again makes new copies.
+    Regenerate,
+}
+
+/// Maps the IDs of copied objects, and the references to them, according to
+/// `PopulateIds`. The same original ID always maps to the same new one, so
+/// relations between the copies are kept whatever order they are made in.
+struct IdMapper<'a> {
+    ids: &'a PopulateIds,
+    regenerated: HashMap<String, String>,
+}
+
+impl IdMapper<'_> {
+    fn map(&mut self, id: &str) -> String {
+        match self.ids {
+            PopulateIds::Preserve => id.to_owned(),
+            PopulateIds::Remap { namespace } => remap_id(namespace, id),
+            PopulateIds::Regenerate => self
+                .regenerated
+                .entry(id.to_owned())
+                .or_insert_with(|| Uuid::new_v4().to_string())
+                .clone(),
+        }
+    }
+
+    /// Maps the ID of `row`, an object of `ty`, and its references to other
+    /// objects. Auth entities are shared by all versions and never copied, so
+    /// references to them are kept.
+    fn map_row(&mut self, ty: &ObjectType, row: &mut JsonObject) {
+        if let PopulateIds::Preserve = self.ids {
+            return;
+        }
+        for field in ty.all_fields() {
+            let is_id = match &field.type_id {
+                TypeId::Id => true,
+                TypeId::Entity { name, .. } => !is_auth_entity_name(name),
+                TypeId::Union { .. } => true,
+                _ => false,
+            };
+            if !is_id {
+                continue;
+            }
+            if let Some(JsonValue::String(id)) = row.get_mut(&field.name) {
+                *id = self.map(id);
+            }
         }
     }
 }
+
+/// Derives a UUID from `id` and `namespace`, in the manner of name-based
+/// UUIDs but hashing with SHA-256.
+fn remap_id(namespace: &str, id: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(namespace.as_bytes());
+    hasher.update([0]);
+    hasher.update(id.as_bytes());
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(&hasher.finalize()[..16]);
+    uuid::Builder::from_bytes(bytes)
+        .set_variant(uuid::Variant::RFC4122)
+        .set_version(uuid::Version::Sha1)
+        .build()
+        .to_string()
+}
@@ -0,0 +1,416 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! Outgoing webhooks.
+//!
+//! The `webhooks` section of the policies of a version subscribes URLs to
+//! the writes of its entities:
+//!
+//! ```yaml
+//! webhooks:
+//!   - url: https://example.com/hooks/orders
+//!     secret_value_ref: ORDERS_WEBHOOK_SECRET
+//!     entities: [Order]
+//!     events: [create, delete]
+//! ```
+//!
+//! Without `entities`, a webhook gets the writes of all entities, and without
+//! `events`, all of create, update and delete.
+//!
+//! Writes enqueue their deliveries in their own transaction, so a webhook
+//! hears of a write if and only if it was committed. A worker then POSTs the
+//! JSON payloads, retrying failed deliveries with exponential backoff. The
+//! deliveries that ran out of attempts are dead letters: they stay in the
+//! `webhook_deliveries` table, with their last error, for inspection.
+//!
+//! Payloads of webhooks with a secret are signed: the
+//! `ChiselStrike-Signature` header is `t=<timestamp>,v1=<signature>`, where
+//! the signature is the hex HMAC-SHA256 of `<timestamp>.<body>` keyed with
+//! the secret, and the timestamp is when the write happened, in seconds
+//! since the epoch.
+
+use crate::auth_provider::{hex, hmac_sha256};
+use crate::datastore::DbConnection;
+use crate::tasks::{backoff, now_millis};
+use crate::JsonObject;
+use anyhow::{Context, Result};
+use reqwest::Url;
+use serde_json::{json, Value};
+use sqlx::any::Any;
+use sqlx::{Executor, Row, Transaction};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use yaml_rust::Yaml;
+
+/// How often the worker looks for due deliveries when it found none.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Maximum number of due deliveries fetched at once.
+const BATCH: i64 = 100;
+/// Deliveries that failed this many times are dead letters.
+const MAX_ATTEMPTS: i32 = 10;
+/// How long a webhook may take to answer.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a worker may take to deliver before others can claim it again.
+const CLAIM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The writes that webhooks are told about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WebhookEvent {
+    Create,
+    Update,
+    Delete,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 3] = [
+        WebhookEvent::Create,
+        WebhookEvent::Update,
+        WebhookEvent::Delete,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::Create => "create",
+            WebhookEvent::Update => "update",
+            WebhookEvent::Delete => "delete",
+        }
+    }
+
+    fn parse(name: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|event| event.as_str() == name)
+            .with_context(|| {
+                format!(
+                    "unknown webhook event `{}`, expected create, update or delete",
+                    name
+                )
+            })
+    }
+}
+
+/// A URL subscribed to the writes of the entities of a version.
+#[derive(Clone, Debug)]
+pub struct Webhook {
+    pub url: String,
+    /// Names a secret (see secrets.rs) to sign the payloads with.
+    pub secret_name: Option<String>,
+    /// The entities whose writes are delivered, all of them if empty.
+    pub entities: Vec<String>,
+    pub events: Vec<WebhookEvent>,
+}
+
+/// The strings of `yaml`, either one string or a list of them.
+fn strings(yaml: &Yaml, key: &str) -> Result<Vec<String>> {
+    match yaml {
+        Yaml::BadValue => Ok(vec![]),
+        Yaml::String(s) => Ok(vec![s.clone()]),
+        Yaml::Array(a) => a
+            .iter()
+            .map(|v| {
+                v.as_str()
+                    .map(str::to_owned)
+                    .with_context(|| format!("webhook {} must be strings, got {:?}", key, v))
+            })
+            .collect(),
+        v => anyhow::bail!("webhook {} must be a list of strings, got {:?}", key, v),
+    }
+}
+
+impl Webhook {
+    pub fn from_yaml(yaml: &Yaml) -> Result<Self> {
+        let url = yaml["url"]
+            .as_str()
+            .with_context(|| format!("webhook must have a string url, got {:?}", yaml))?;
+        let parsed = Url::parse(url).with_context(|| format!("invalid webhook url `{}`", url))?;
+        anyhow::ensure!(
+            matches!(parsed.scheme(), "http" | "https"),
+            "webhook url `{}` must be http or https",
+            url
+        );
+        let secret_name = match &yaml["secret_value_ref"] {
+            Yaml::BadValue => None,
+            Yaml::String(name) => Some(name.clone()),
+            v => anyhow::bail!("webhook secret_value_ref must be a string, got {:?}", v),
+        };
+        let mut events = strings(&yaml["events"], "events")?
+            .iter()
+            .map(|name| WebhookEvent::parse(name))
+            .collect::<Result<Vec<_>>>()?;
+        if events.is_empty() {
+            events = WebhookEvent::ALL.to_vec();
+        }
+        Ok(Webhook {
+            url: url.to_owned(),
+            secret_name,
+            entities: strings(&yaml["entities"], "entities")?,
+            events,
+        })
+    }
+
+    pub fn matches(&self, entity: &str, event: WebhookEvent) -> bool {
+        self.events.contains(&event)
+            && (self.entities.is_empty() || self.entities.iter().any(|e| e == entity))
+    }
+}
+
+/// A payload ready to be delivered to a webhook.
+pub(crate) struct Delivery {
+    url: String,
+    payload: String,
+    signature: Option<String>,
+}
+
+/// The `ChiselStrike-Signature` header of `payload`, sent at `timestamp`.
+fn signature(secret: &str, timestamp: i64, payload: &str) -> String {
+    let signed = format!("{}.{}", timestamp, payload);
+    let mac = hmac_sha256(secret.as_bytes(), signed.as_bytes());
+    format!("t={},v1={}", timestamp, hex(&mac))
+}
+
+/// The deliveries of `event` on `objects` of `entity` to the `webhooks` of
+/// `api_version` subscribed to it. Each object must have an `id`; the other
+/// fields are the ones the write set.
+pub(crate) fn deliveries(
+    webhooks: &[Webhook],
+    secrets: &JsonObject,
+    api_version: &str,
+    entity: &str,
+    event: WebhookEvent,
+    objects: &[JsonObject],
+) -> Result<Vec<Delivery>> {
+    let timestamp = now_millis() / 1000;
+    let mut deliveries = vec![];
+    for webhook in webhooks.iter().filter(|w| w.matches(entity, event)) {
+        let secret = match &webhook.secret_name {
+            None => None,
+            Some(name) => match secrets.get(name) {
+                Some(Value::String(secret)) => Some(secret),
+                Some(_) => {
+                    anyhow::bail!("secret {} of webhook {} isn't a string", name, webhook.url)
+                }
+                None => anyhow::bail!("secret {} of webhook {} is not set", name, webhook.url),
+            },
+        };
+        for object in objects {
+            let mut payload = json!({
+                "event": event.as_str(),
+                "entity": entity,
+                "version": api_version,
+                "id": object.get("id"),
+                "timestamp": timestamp,
+            });
+            if event != WebhookEvent::Delete {
+                payload["object"] = Value::Object(object.clone());
+            }
+            let payload = payload.to_string();
+            deliveries.push(Delivery {
+                url: webhook.url.clone(),
+                signature: secret.map(|s| signature(s, timestamp, &payload)),
+                payload,
+            });
+        }
+    }
+    Ok(deliveries)
+}
+
+/// Enqueues `deliveries` as part of `transaction`.
+pub(crate) async fn enqueue(
+    transaction: &mut Transaction<'_, Any>,
+    api_version: &str,
+    deliveries: Vec<Delivery>,
+) -> Result<()> {
+    for delivery in deliveries {
+        let query = sqlx::query(
+            r#"
+            INSERT INTO webhook_deliveries (api_version, url, payload, signature, attempts, run_at)
+            VALUES ($1, $2, $3, $4, 0, $5)"#,
+        )
+        .bind(api_version.to_owned())
+        .bind(delivery.url)
+        .bind(delivery.payload)
+        .bind(delivery.signature)
+        .bind(now_millis());
+        transaction
+            .execute(query)
+            .await
+            .context("failed to enqueue webhook delivery")?;
+    }
+    Ok(())
+}
+
+struct DueDelivery {
+    id: i32,
+    url: String,
+    payload: String,
+    signature: Option<String>,
+    attempts: i32,
+}
+
+async fn due_deliveries(db: &DbConnection, now: i64) -> Result<Vec<DueDelivery>> {
+    let query = sqlx::query(
+        r#"
+        SELECT delivery_id, url, payload, signature, attempts
+        FROM webhook_deliveries
+        WHERE attempts < $1 AND run_at <= $2
+        ORDER BY run_at, delivery_id
+        LIMIT $3"#,
+    )
+    .bind(MAX_ATTEMPTS)
+    .bind(now)
+    .bind(BATCH);
+    let rows = query.fetch_all(&db.pool).await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| DueDelivery {
+            id: row.get("delivery_id"),
+            url: row.get("url"),
+            payload: row.get("payload"),
+            signature: row.get("signature"),
+            attempts: row.get("attempts"),
+        })
+        .collect())
+}
+
+/// Claims `delivery` for this worker by pushing its next attempt past the
+/// time it may take, unless another worker claimed it first.
+async fn claim(db: &DbConnection, delivery: &DueDelivery, now: i64) -> Result<bool> {
+    let query = sqlx::query(
+        "UPDATE webhook_deliveries SET run_at = $1 WHERE delivery_id = $2 AND run_at <= $3",
+    )
+    .bind(now + CLAIM_TIMEOUT.as_millis() as i64)
+    .bind(delivery.id)
+    .bind(now);
+    Ok(db.pool.execute(query).await?.rows_affected() == 1)
+}
+
+async fn post(http: &reqwest::Client, delivery: &DueDelivery) -> Result<()> {
+    let mut request = http
+        .post(&delivery.url)
+        .timeout(DELIVERY_TIMEOUT)
+        .header("content-type", "application/json")
+        .header("ChiselStrike-Webhook-Delivery-Id", delivery.id.to_string())
+        .body(delivery.payload.clone());
+    if let Some(signature) = &delivery.signature {
+        request = request.header("ChiselStrike-Signature", signature);
+    }
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+async fn deliver(db: &DbConnection, http: &reqwest::Client, delivery: DueDelivery) -> Result<()> {
+    match post(http, &delivery).await {
+        Ok(()) => {
+            let query = sqlx::query("DELETE FROM webhook_deliveries WHERE delivery_id = $1")
+                .bind(delivery.id);
+            db.pool.execute(query).await?;
+        }
+        Err(e) => {
+            let attempts = delivery.attempts + 1;
+            if attempts < MAX_ATTEMPTS {
+                warn!(
+                    "Webhook delivery {} to {} failed on attempt {}/{}: {}",
+                    delivery.id, delivery.url, attempts, MAX_ATTEMPTS, e
+                );
+            } else {
+                warn!(
+                    "Giving up on webhook delivery {} to {}, left in webhook_deliveries: {}",
+                    delivery.id, delivery.url, e
+                );
+            }
+            let query = sqlx::query(
+                r#"
+                UPDATE webhook_deliveries
+                SET attempts = $1, run_at = $2, last_error = $3
+                WHERE delivery_id = $4"#,
+            )
+            .bind(attempts)
+            .bind(now_millis() + backoff(attempts).as_millis() as i64)
+            .bind(e.to_string())
+            .bind(delivery.id);
+            db.pool.execute(query).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Makes the due deliveries this worker could claim, and returns how many.
+async fn deliver_due(db: &DbConnection, http: &reqwest::Client) -> Result<usize> {
+    let mut delivered = 0;
+    for delivery in due_deliveries(db, now_millis()).await? {
+        if claim(db, &delivery, now_millis()).await? {
+            deliver(db, http, delivery).await?;
+            delivered += 1;
+        }
+    }
+    Ok(delivered)
+}
+
+/// Spawns the worker that delivers webhook payloads until `shutdown` fires.
+pub(crate) fn spawn(
+    db: DbConnection,
+    shutdown: async_channel::Receiver<()>,
+) -> JoinHandle<Result<()>> {
+    tokio::task::spawn(async move {
+        let http = reqwest::Client::new();
+        let mut idle = false;
+        loop {
+            if idle {
+                tokio::select! {
+                    _ = sleep(POLL_INTERVAL) => {},
+                    _ = shutdown.recv() => break,
+                };
+            } else if shutdown.is_closed() {
+                break;
+            }
+            idle = match deliver_due(&db, &http).await {
+                Ok(delivered) => delivered == 0,
+                Err(e) => {
+                    warn!("Delivering webhooks failed: {:?}", e);
+                    true
+                }
+            };
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webhook(yaml: &str) -> Result<Webhook> {
+        Webhook::from_yaml(&yaml_rust::YamlLoader::load_from_str(yaml).unwrap()[0])
+    }
+
+    #[test]
+    fn parse() {
+        let w = webhook("url: http://localhost/hook").unwrap();
+        assert_eq!(w.events, WebhookEvent::ALL);
+        assert!(w.matches("Order", WebhookEvent::Delete));
+
+        let w = webhook("url: http://localhost/hook\nentities: Order\nevents: [update]").unwrap();
+        assert!(w.matches("Order", WebhookEvent::Update));
+        assert!(!w.matches("Order", WebhookEvent::Create));
+        assert!(!w.matches("Person", WebhookEvent::Update));
+
+        let err = webhook("url: http://localhost/hook\nevents: [upsert]").unwrap_err();
+        assert!(err.to_string().contains("unknown webhook event `upsert`"));
+        let err = webhook("url: ftp://localhost/hook").unwrap_err();
+        assert!(err.to_string().contains("must be http or https"));
+    }
+
+    #[test]
+    fn sign() {
+        // From RFC 4231, test case 2.
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(
+            hex(&mac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            signature("Jefe", 1, "{}"),
+            format!("t=1,v1={}", hex(&hmac_sha256(b"Jefe", b"1.{}")))
+        );
+    }
+}
@@ -0,0 +1,157 @@
+// SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
+
+//! WebSocket connections to routes exporting a `websocket` handler.
+//!
+//! The upgrade request goes through the route like any other request, so
+//! that its policies and authentication apply, and the `open` function of
+//! the handler runs in its transaction. If the route accepts the socket, by
+//! answering with `101 Switching Protocols`, the connection is upgraded and
+//! served by a task on the executor that accepted it. Each message the
+//! client sends runs the `message` function in a transaction of its own,
+//! like event handlers, and `close` runs once the connection is closed.
+//!
+//! Messages to the client can be sent from any request, not only those of
+//! the socket, since sockets are looked up by id across executors.
+
+use crate::deno::{self, SocketEvent};
+use anyhow::{Context, Result};
+use deno_core::futures::{SinkExt, StreamExt};
+use hyper::header::{HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
+use hyper::upgrade::OnUpgrade;
+use hyper::Request;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+pub(crate) use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// The channels of the messages to send to the clients of the open sockets,
+/// by id.
+static SOCKETS: Lazy<Mutex<HashMap<u64, async_channel::Sender<Message>>>> =
+    Lazy::new(Default::default);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Id of the socket a request would open, kept in its extensions.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SocketId(pub u64);
+
+/// Registration of a socket, which is dropped once it is closed or its
+/// route doesn't accept it.
+struct Registration(u64);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        SOCKETS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// A socket that the route of an upgrade request may accept. It is
+/// forgotten when dropped without being accepted.
+pub(crate) struct PendingSocket {
+    registration: Registration,
+    accept_key: String,
+    on_upgrade: OnUpgrade,
+    outgoing: async_channel::Receiver<Message>,
+}
+
+/// If `req` asks for a WebSocket, registers the socket it would open.
+pub(crate) fn pending(req: &mut Request<hyper::Body>) -> Option<PendingSocket> {
+    let is_websocket = req
+        .headers()
+        .get(UPGRADE)
+        .and_then(|upgrade| upgrade.to_str().ok())
+        .map_or(false, |upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+    if !is_websocket {
+        return None;
+    }
+    let accept_key = derive_accept_key(req.headers().get(SEC_WEBSOCKET_KEY)?.as_bytes());
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (tx, outgoing) = async_channel::unbounded();
+    SOCKETS.lock().unwrap().insert(id, tx);
+    req.extensions_mut().insert(SocketId(id));
+    Some(PendingSocket {
+        registration: Registration(id),
+        accept_key,
+        on_upgrade: hyper::upgrade::on(req),
+        outgoing,
+    })
+}
+
+impl PendingSocket {
+    /// Adds the headers that accept the socket to the `101` response to its
+    /// upgrade request, and serves the socket once the connection is
+    /// upgraded.
+    pub(crate) fn accept(self, path: String, headers: &mut hyper::HeaderMap) {
+        headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+        headers.insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+        headers.insert(
+            SEC_WEBSOCKET_ACCEPT,
+            HeaderValue::from_str(&self.accept_key).unwrap(),
+        );
+        tokio::task::spawn_local(async move {
+            let id = self.registration.0;
+            if let Err(e) = serve(&path, id, self.on_upgrade, &self.outgoing).await {
+                warn!("WebSocket {} of {} failed: {:?}", id, path, e);
+            }
+            drop(self.registration);
+            if let Err(e) = deno::run_js_socket(path.clone(), id, SocketEvent::Close).await {
+                warn!("WebSocket handler of {} failed on close: {:?}", path, e);
+            }
+        });
+    }
+}
+
+async fn serve(
+    path: &str,
+    id: u64,
+    on_upgrade: OnUpgrade,
+    outgoing: &async_channel::Receiver<Message>,
+) -> Result<()> {
+    let upgraded = on_upgrade.await?;
+    let mut ws = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+    loop {
+        tokio::select! {
+            received = ws.next() => {
+                let event = match received {
+                    None => break,
+                    Some(received) => match received? {
+                        Message::Text(text) => SocketEvent::Text(text),
+                        Message::Binary(data) => SocketEvent::Binary(data),
+                        Message::Close(_) => break,
+                        // Pings are answered by tungstenite.
+                        _ => continue,
+                    },
+                };
+                // A failing handler only loses its message, like event
+                // handlers out of retries.
+                if let Err(e) = deno::run_js_socket(path.to_owned(), id, event).await {
+                    warn!("WebSocket handler of {} failed: {:?}", path, e);
+                }
+            }
+            outgoing = outgoing.recv() => match outgoing {
+                Ok(Message::Close(frame)) => {
+                    ws.close(frame).await?;
+                    break;
+                }
+                Ok(message) => ws.send(message).await?,
+                Err(_) => break,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sends `message` to the client of socket `id`.
+pub(crate) fn send(id: &str, message: Message) -> Result<()> {
+    let id: u64 = id.parse().context("invalid socket id")?;
+    let sockets = SOCKETS.lock().unwrap();
+    let tx = sockets
+        .get(&id)
+        .with_context(|| format!("socket {} is closed", id))?;
+    // Unbounded, so this only fails once the socket is closed.
+    tx.try_send(message)
+        .map_err(|_| anyhow::anyhow!("socket {} is closed", id))
+}
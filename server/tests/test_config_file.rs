@@ -2,7 +2,7 @@
 
 use std::fs::create_dir_all;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use serde_json::Value;
 use tempfile::{NamedTempFile, TempDir};
@@ -15,8 +15,19 @@ fn chiseld() -> PathBuf {
 }
 
 fn chiseld_check_config(args: &[&str], env: &[(&str, &str)]) -> serde_json::Value {
+    chiseld_check_config_in(None, args, env)
+}
+
+fn chiseld_check_config_in(
+    dir: Option<&Path>,
+    args: &[&str],
+    env: &[(&str, &str)],
+) -> serde_json::Value {
     let mut cmd = std::process::Command::new(chiseld());
 
+    if let Some(dir) = dir {
+        cmd.current_dir(dir);
+    }
     cmd.args(args);
     cmd.envs(env.iter().cloned());
     cmd.arg("--show-config");
@@ -68,14 +79,27 @@ executor_threads = 21
         "_metadata_db_uri": "sqlite://chiseld.db?mode=rwc",
         "_data_db_uri": "sqlite://chiseld-data.db?mode=rwc",
         "db_uri": "sqlite://.chiseld.db?mode=rwc",
+        "api_http2": false,
+        "api_disable_keep_alive": false,
+        "api_keep_alive_timeout": Value::Null,
+        "api_max_concurrent_streams": Value::Null,
+        "api_tcp_backlog": 1024,
+        "api_tls_cert": Value::Null,
+        "api_tls_key": Value::Null,
         "kafka_connection": Value::Null,
         "kafka_topics": Value::Array(vec![]),
+        "event_handler_retries": 3,
+        "lazy_versions": Value::Array(vec![]),
+        "eager_versions": Value::Array(vec![]),
         "v8_flags": Value::Array(vec![]),
         "inspect": false,
         "inspect_brk": false,
         "debug": false,
+        "query_budget": Value::Null,
+        "enforce_query_budget": false,
         "nr_connections": 10,
         "executor_threads": 21,
+        "log_filter": "info",
         "chisel_secret_location": Value::Null,
         "chisel_secret_key_location": Value::Null,
     });
@@ -108,14 +132,27 @@ executor_threads = 21
         "_metadata_db_uri": "sqlite://chiseld.db?mode=rwc",
         "_data_db_uri": "sqlite://chiseld-data.db?mode=rwc",
         "db_uri": "sqlite://.chiseld.db?mode=rwc",
+        "api_http2": false,
+        "api_disable_keep_alive": false,
+        "api_keep_alive_timeout": Value::Null,
+        "api_max_concurrent_streams": Value::Null,
+        "api_tcp_backlog": 1024,
+        "api_tls_cert": Value::Null,
+        "api_tls_key": Value::Null,
         "kafka_connection": Value::Null,
         "kafka_topics": Value::Array(vec![]),
+        "event_handler_retries": 3,
+        "lazy_versions": Value::Array(vec![]),
+        "eager_versions": Value::Array(vec![]),
         "v8_flags": Value::Array(vec![]),
         "inspect": false,
         "inspect_brk": false,
         "debug": false,
+        "query_budget": Value::Null,
+        "enforce_query_budget": false,
         "nr_connections": 10,
         "executor_threads": 21,
+        "log_filter": "info",
         "chisel_secret_location": Value::Null,
         "chisel_secret_key_location": Value::Null,
     });
@@ -146,14 +183,27 @@ executor_threads = 21
         "_metadata_db_uri": "sqlite://chiseld.db?mode=rwc",
         "_data_db_uri": "sqlite://chiseld-data.db?mode=rwc",
         "db_uri": "sqlite://.chiseld.db?mode=rwc",
+        "api_http2": false,
+        "api_disable_keep_alive": false,
+        "api_keep_alive_timeout": Value::Null,
+        "api_max_concurrent_streams": Value::Null,
+        "api_tcp_backlog": 1024,
+        "api_tls_cert": Value::Null,
+        "api_tls_key": Value::Null,
         "kafka_connection": Value::Null,
         "kafka_topics": Value::Array(vec![]),
+        "event_handler_retries": 3,
+        "lazy_versions": Value::Array(vec![]),
+        "eager_versions": Value::Array(vec![]),
         "v8_flags": Value::Array(vec![]),
         "inspect": false,
         "inspect_brk": false,
         "debug": false,
+        "query_budget": Value::Null,
+        "enforce_query_budget": false,
         "nr_connections": 10,
         "executor_threads": 21,
+        "log_filter": "info",
         "chisel_secret_location": Value::Null,
         "chisel_secret_key_location": Value::Null,
     });
@@ -194,17 +244,81 @@ executor_threads = 21
         "_metadata_db_uri":"sqlite://chiseld.db?mode=rwc",
         "_data_db_uri":"sqlite://chiseld-data.db?mode=rwc",
         "db_uri":"sqlite://.chiseld.db?mode=rwc",
+        "api_http2": false,
+        "api_disable_keep_alive": false,
+        "api_keep_alive_timeout": Value::Null,
+        "api_max_concurrent_streams": Value::Null,
+        "api_tcp_backlog": 1024,
+        "api_tls_cert": Value::Null,
+        "api_tls_key": Value::Null,
         "kafka_connection": Value::Null,
         "kafka_topics": Value::Array(vec![]),
+        "event_handler_retries": 3,
+        "lazy_versions": Value::Array(vec![]),
+        "eager_versions": Value::Array(vec![]),
         "v8_flags": Value::Array(vec![]),
         "inspect": false,
         "inspect_brk":false,
         "debug": false,
+        "query_budget": Value::Null,
+        "enforce_query_budget": false,
         "nr_connections":10,
         "executor_threads":21,
+        "log_filter": "info",
         "chisel_secret_location": Value::Null,
         "chisel_secret_key_location": Value::Null,
     });
 
     assert_eq!(out, expected);
 }
+
+#[test]
+fn test_env_overrides_config_file() {
+    let conf = write_config(
+        r#"
+api_listen_addr = "localhost:12345"
+executor_threads = 21
+api_http2 = false
+    "#,
+    );
+    let out = chiseld_check_config(
+        &[
+            "-c",
+            &conf.path().display().to_string(),
+            "--nr-connections",
+            "3",
+        ],
+        &[
+            ("CHISELD_EXECUTOR_THREADS", "7"),
+            ("CHISELD_API_HTTP2", "true"),
+            ("CHISELD_KAFKA_TOPICS", "a,b"),
+            ("CHISELD_API_KEEP_ALIVE_TIMEOUT", "30"),
+            ("CHISELD_NR_CONNECTIONS", "5"),
+        ],
+    );
+
+    assert_eq!(out["api_listen_addr"], "localhost:12345");
+    assert_eq!(out["executor_threads"], 7);
+    assert_eq!(out["api_http2"], true);
+    assert_eq!(out["kafka_topics"], serde_json::json!(["a", "b"]));
+    assert_eq!(out["api_keep_alive_timeout"], 30);
+    // command line flags take precedence over the environment
+    assert_eq!(out["nr_connections"], 3);
+}
+
+#[test]
+fn test_project_config_file() {
+    let project_dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        project_dir.path().join("chiseld.toml"),
+        r#"
+api_listen_addr = "localhost:12347"
+log_filter = "debug"
+    "#,
+    )
+    .unwrap();
+
+    let out = chiseld_check_config_in(Some(project_dir.path()), &[], &[]);
+    assert_eq!(out["api_listen_addr"], "localhost:12347");
+    assert_eq!(out["log_filter"], "debug");
+}
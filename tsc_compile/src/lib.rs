@@ -215,6 +215,8 @@ pub struct CompileOptions<'a> {
     pub extra_libs: HashMap<String, String>,
     pub emit_declarations: bool,
     pub is_worker: bool,
+    /// Append inline source maps to the emitted JavaScript.
+    pub source_maps: bool,
 }
 
 struct ModuleLoader {
@@ -407,13 +409,14 @@ impl Compiler {
                 get_member(global_proxy, scope, "compile").unwrap();
             let emit_declarations = v8::Boolean::new(scope, opts.emit_declarations).into();
             let is_worker = v8::Boolean::new(scope, opts.is_worker).into();
+            let source_maps = v8::Boolean::new(scope, opts.source_maps).into();
 
             let root = v8::String::new(scope, ROOT_URL).unwrap().into();
             compile
                 .call(
                     scope,
                     global_proxy.into(),
-                    &[root, is_worker, lib, emit_declarations],
+                    &[root, is_worker, lib, emit_declarations, source_maps],
                 )
                 .unwrap();
         }
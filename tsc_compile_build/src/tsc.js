@@ -94,7 +94,7 @@
     };
 
     const readCache = {};
-    function compileAux(root, isWorker, lib, emitDeclarations) {
+    function compileAux(root, isWorker, lib, emitDeclarations, sourceMaps) {
         const defaultLibs = [
             "lib.deno.unstable.d.ts",
             "lib.deno_core.d.ts",
@@ -114,6 +114,8 @@
             declaration: emitDeclarations,
             emitDecoratorMetadata: false,
             experimentalDecorators: true,
+            inlineSourceMap: sourceMaps,
+            inlineSources: sourceMaps,
             isolatedModules: true,
             lib: defaultLibs,
             module: ts.ModuleKind.ESNext,
@@ -149,9 +151,15 @@
         }
     }
 
-    function compile(root, isWorker, lib, emitDeclarations) {
+    function compile(root, isWorker, lib, emitDeclarations, sourceMaps) {
         try {
-            return compileAux(root, isWorker, lib, emitDeclarations);
+            return compileAux(
+                root,
+                isWorker,
+                lib,
+                emitDeclarations,
+                sourceMaps,
+            );
         } catch (e) {
             Deno.core.opSync("diagnostic", e.stack + "\n");
             return false;
@@ -1,6 +1,6 @@
 // SPDX-FileCopyrightText: © 2022 ChiselStrike <info@chiselstrike.com>
 
-use anyhow::{ensure, Result};
+use anyhow::{anyhow, ensure, Result};
 use reqwest::{Response, Url};
 use std::panic;
 
@@ -22,6 +22,36 @@ pub async fn get_ok(url: Url) -> Result<Response> {
     Ok(res)
 }
 
+/// Parses the major, minor and patch numbers of `version`, ignoring its
+/// pre-release and build parts.
+fn parse_version(version: &str) -> Result<(u64, u64, u64)> {
+    let core = version.split(|c| c == '-' || c == '+').next().unwrap();
+    let numbers: Vec<_> = core.split('.').map(|n| n.parse::<u64>()).collect();
+    match numbers.as_slice() {
+        [Ok(major), Ok(minor), Ok(patch)] => Ok((*major, *minor, *patch)),
+        _ => Err(anyhow!("invalid version {}", version)),
+    }
+}
+
+/// The versions of chisel and chiseld that work with `version` of either, as
+/// the range from `min` up to, but excluding, `max`: the releases of the
+/// same minor version while the major version is 0, and of the same major
+/// version from 1 on.
+pub fn compatible_versions(version: &str) -> Result<(String, String)> {
+    let (major, minor, _) = parse_version(version)?;
+    Ok(if major == 0 {
+        (format!("0.{}.0", minor), format!("0.{}.0", minor + 1))
+    } else {
+        (format!("{}.0.0", major), format!("{}.0.0", major + 1))
+    })
+}
+
+/// Whether `version` is in the range from `min` up to, but excluding, `max`.
+pub fn version_in_range(version: &str, min: &str, max: &str) -> Result<bool> {
+    let version = parse_version(version)?;
+    Ok(parse_version(min)? <= version && version < parse_version(max)?)
+}
+
 pub fn make_signal_channel() -> (async_channel::Sender<()>, async_channel::Receiver<()>) {
     let default_hook = panic::take_hook();
     panic::set_hook(Box::new(move |info| {
@@ -30,3 +60,21 @@ pub fn make_signal_channel() -> (async_channel::Sender<()>, async_channel::Recei
     }));
     async_channel::bounded(1)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compatible() {
+        let (min, max) = compatible_versions("0.13.0-dev.0").unwrap();
+        assert_eq!((min.as_str(), max.as_str()), ("0.13.0", "0.14.0"));
+        assert!(version_in_range("0.13.2", &min, &max).unwrap());
+        assert!(!version_in_range("0.12.5", &min, &max).unwrap());
+        assert!(!version_in_range("0.14.0-dev.0", &min, &max).unwrap());
+
+        let (min, max) = compatible_versions("1.2.3").unwrap();
+        assert_eq!((min.as_str(), max.as_str()), ("1.0.0", "2.0.0"));
+        assert!(compatible_versions("1.2").is_err());
+    }
+}